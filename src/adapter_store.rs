@@ -0,0 +1,379 @@
+//! --rga-adapter-add and --rga-adapter-list: custom adapters written by others, downloaded into the
+//! adapters directory next to the config file. A definition is a JSON object like an entry of
+//! `custom_adapters`, and it is only installed if its SHA-256 checksum is the one in the adapter index
+//! (--rga-adapter-index) or in the `#sha256=` fragment of its URL. The adapters in the directory are
+//! added to the custom adapters of the config file, as long as they still have that checksum.
+
+use crate::adapters::custom::CustomAdapterConfig;
+use crate::adapters::get_all_adapters;
+use crate::config::{user_config_path, RgaConfig};
+use crate::install::run;
+use anyhow::{bail, Context, Result};
+use log::*;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// the URL and checksum of the installed adapters, by name
+const INSTALLED: &str = "installed.json";
+
+/// where an adapter was downloaded from, also the format of the entries of an adapter index
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq)]
+struct Source {
+    url: String,
+    sha256: String,
+}
+
+/// the directory of the adapters installed with --rga-adapter-add, next to the config file
+pub fn adapters_dir() -> Result<PathBuf> {
+    Ok(user_config_path()?
+        .parent()
+        .context("no config directory")?
+        .join("adapters"))
+}
+
+fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// the names become file names, so only the characters custom adapter names may have are allowed
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_')
+}
+
+/// the URL of an adapter with the checksum of its `#sha256=` fragment, if it has one
+fn parse_url(source: &str) -> (String, Option<String>) {
+    match source.split_once("#sha256=") {
+        Some((url, sha256)) => (url.to_owned(), Some(sha256.trim().to_ascii_lowercase())),
+        None => (source.to_owned(), None),
+    }
+}
+
+/// git repositories, which are not downloaded with curl
+fn is_git_url(url: &str) -> bool {
+    url.starts_with("git@")
+        || url.starts_with("git://")
+        || url.starts_with("git+")
+        || url.trim_end_matches('/').ends_with(".git")
+}
+
+/// the URL and checksum of `source`, a URL or the name of an adapter in the index
+async fn resolve(config: &RgaConfig, source: &str) -> Result<(String, Option<String>)> {
+    let (url, _) = parse_url(source);
+    if is_git_url(&url) {
+        bail!(
+            "{} is a git repository, which can't be cloned. Use the URL of the raw adapter file in it",
+            url
+        );
+    }
+    if source.contains("://") {
+        return Ok(parse_url(source));
+    }
+    let index_url = match &config.adapter_index {
+        Some(url) => url,
+        None => bail!(
+            "{} is not a URL, and there is no adapter index to look it up in (--rga-adapter-index)",
+            source
+        ),
+    };
+    let index = run(config, "curl", &["-sSfL", index_url]).await?;
+    let mut index: BTreeMap<String, Source> = serde_json::from_slice(&index)
+        .with_context(|| format!("reading the adapter index {index_url}"))?;
+    match index.remove(source) {
+        Some(entry) => Ok((entry.url, Some(entry.sha256.to_ascii_lowercase()))),
+        None => bail!(
+            "no adapter {} in {}, available: {}",
+            source,
+            index_url,
+            index.keys().cloned().collect::<Vec<_>>().join(", ")
+        ),
+    }
+}
+
+fn read_installed(dir: &Path) -> Result<BTreeMap<String, Source>> {
+    let path = dir.join(INSTALLED);
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    serde_json::from_slice(&std::fs::read(&path)?)
+        .with_context(|| format!("reading {}", path.display()))
+}
+
+/// check the downloaded definition and write it to `dir`
+fn install(
+    dir: &Path,
+    url: &str,
+    expected: Option<&str>,
+    data: &[u8],
+) -> Result<CustomAdapterConfig> {
+    let sha256 = sha256_hex(data);
+    match expected {
+        Some(expected) if expected == sha256 => {}
+        Some(expected) => bail!("the checksum of {} is {}, not {}", url, sha256, expected),
+        // the user has to look at the file before trusting it, it runs a program on their files
+        None => bail!(
+            "{} has no checksum. Check the adapter, then install it with {}#sha256={}",
+            url,
+            url,
+            sha256
+        ),
+    }
+    let adapter: CustomAdapterConfig = serde_json::from_slice(data)
+        .with_context(|| format!("{url} is not a custom adapter definition"))?;
+    if !is_valid_name(&adapter.name) {
+        bail!("invalid adapter name {:?}", adapter.name);
+    }
+    let (enabled, disabled) = get_all_adapters(None);
+    if enabled
+        .iter()
+        .chain(disabled.iter())
+        .any(|a| a.metadata().name == adapter.name)
+    {
+        bail!("{} is the name of a built-in adapter", adapter.name);
+    }
+    std::fs::create_dir_all(dir)?;
+    let path = dir.join(format!("{}.json", adapter.name));
+    std::fs::write(&path, data).with_context(|| format!("writing {}", path.display()))?;
+    let mut installed = read_installed(dir)?;
+    installed.insert(
+        adapter.name.clone(),
+        Source {
+            url: url.to_owned(),
+            sha256,
+        },
+    );
+    std::fs::write(
+        dir.join(INSTALLED),
+        serde_json::to_string_pretty(&installed)? + "\n",
+    )?;
+    Ok(adapter)
+}
+
+/// --rga-adapter-add: download an adapter definition and install it
+pub async fn add(config: &RgaConfig, source: &str) -> Result<()> {
+    if config.policy.no_network {
+        bail!("--rga-adapter-add downloads the adapter, which --rga-policy-no-network forbids");
+    }
+    let (url, sha256) = resolve(config, source).await?;
+    if url.ends_with(".wasm") {
+        bail!(
+            "{} is a WebAssembly plugin, rga only runs custom adapters that call a program",
+            url
+        );
+    }
+    let data = run(config, "curl", &["-sSfL", &url]).await?;
+    let dir = adapters_dir()?;
+    let adapter = install(&dir, &url, sha256.as_deref(), &data)?;
+    println!(
+        "installed adapter {} (version {}) to {}, it runs: {} {}",
+        adapter.name,
+        adapter.version,
+        dir.display(),
+        adapter.binary,
+        adapter.args.join(" ")
+    );
+    Ok(())
+}
+
+/// --rga-adapter-list: the installed adapters with where they came from, and whether they were changed
+/// since
+pub fn list() -> Result<()> {
+    let dir = adapters_dir()?;
+    let installed = read_installed(&dir)?;
+    if installed.is_empty() {
+        println!("no adapters installed in {}", dir.display());
+        return Ok(());
+    }
+    for (name, source) in installed {
+        let status = match std::fs::read(dir.join(format!("{name}.json"))) {
+            Ok(data) if sha256_hex(&data) == source.sha256 => "",
+            Ok(_) => " (changed, not used)",
+            Err(_) => " (removed)",
+        };
+        println!("{:<20} {}{}", name, source.url, status);
+    }
+    Ok(())
+}
+
+/// the definition in `path`, if it is the one that was installed
+fn installed_adapter(path: &Path, installed: &BTreeMap<String, Source>) -> Result<Value> {
+    let data = std::fs::read(path)?;
+    let name = path
+        .file_stem()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match installed.get(&name) {
+        Some(source) if source.sha256 == sha256_hex(&data) => {}
+        Some(source) => bail!(
+            "it was changed since it was installed from {}, check it and install it again",
+            source.url
+        ),
+        None => bail!("it was not installed with --rga-adapter-add"),
+    }
+    let adapter: CustomAdapterConfig =
+        serde_json::from_slice(&data).context("not a custom adapter definition")?;
+    if adapter.name != name {
+        bail!("it defines the adapter {:?}", adapter.name);
+    }
+    Ok(serde_json::from_slice(&data)?)
+}
+
+/// the definitions of the installed adapters, in the order of their names. The ones that were changed
+/// since, or are not valid, are skipped with a warning: they would run a program nobody checked, or
+/// make every search fail
+fn installed_adapters(dir: &Path) -> Result<Vec<Value>> {
+    let installed = match read_installed(dir) {
+        Ok(installed) => installed,
+        Err(e) => {
+            warn!("skipping the installed adapters: {:#}", e);
+            return Ok(vec![]);
+        }
+    };
+    let mut paths = std::fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.sort();
+    let mut adapters = vec![];
+    for path in paths
+        .iter()
+        .filter(|p| p.extension().map_or(false, |e| e == "json"))
+        .filter(|p| p.file_name().map_or(false, |n| n != INSTALLED))
+    {
+        match installed_adapter(path, &installed) {
+            Ok(adapter) => adapters.push(adapter),
+            Err(e) => warn!("skipping adapter {}: {:#}", path.display(), e),
+        }
+    }
+    Ok(adapters)
+}
+
+/// add `adapters` to the custom adapters of a config, except the ones it already has an adapter of
+/// the same name for
+fn merge_adapters(config: &mut Value, adapters: Vec<Value>) {
+    if adapters.is_empty() {
+        return;
+    }
+    let custom = &mut config["custom_adapters"];
+    if !custom.is_array() {
+        *custom = Value::Array(vec![]);
+    }
+    let custom = custom.as_array_mut().expect("is an array");
+    for adapter in adapters {
+        if !custom.iter().any(|a| a["name"] == adapter["name"]) {
+            custom.push(adapter);
+        }
+    }
+}
+
+/// add the installed adapters to the options of the config files
+pub(crate) fn merge_installed(config: &mut Value) -> Result<()> {
+    let dir = adapters_dir()?;
+    if dir.exists() {
+        merge_adapters(config, installed_adapters(&dir)?);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    const ADAPTER: &str = r#"{"name": "gpg", "description": "decrypts gpg files", "version": 1,
+        "extensions": ["gpg"], "binary": "gpg", "args": ["--decrypt"]}"#;
+
+    #[test]
+    fn checksums() -> Result<()> {
+        assert_eq!(
+            sha256_hex(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            parse_url("https://example.org/gpg.json#sha256=ABC"),
+            (
+                "https://example.org/gpg.json".to_owned(),
+                Some("abc".to_owned())
+            )
+        );
+        assert!(is_git_url("git@github.com:someone/rga-adapters.git"));
+        assert!(is_git_url("https://github.com/someone/rga-adapters.git"));
+        assert!(!is_git_url(
+            "https://raw.githubusercontent.com/someone/rga-adapters/main/gpg.json"
+        ));
+        let dir = tempfile::tempdir()?;
+        let url = "https://example.org/gpg.json";
+        let sha256 = sha256_hex(ADAPTER.as_bytes());
+        assert!(install(dir.path(), url, None, ADAPTER.as_bytes()).is_err());
+        assert!(install(dir.path(), url, Some("00"), ADAPTER.as_bytes()).is_err());
+        assert_eq!(
+            install(dir.path(), url, Some(&sha256), ADAPTER.as_bytes())?.name,
+            "gpg"
+        );
+        assert_eq!(
+            read_installed(dir.path())?.remove("gpg"),
+            Some(Source {
+                url: url.to_owned(),
+                sha256
+            })
+        );
+        let builtin = ADAPTER.replace("\"gpg\",", "\"zip\",");
+        let sha256 = sha256_hex(builtin.as_bytes());
+        assert!(install(dir.path(), url, Some(&sha256), builtin.as_bytes()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn merge() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let url = "https://example.org/gpg.json";
+        install(
+            dir.path(),
+            url,
+            Some(&sha256_hex(ADAPTER.as_bytes())),
+            ADAPTER.as_bytes(),
+        )?;
+        // not installed with --rga-adapter-add, and installed but invalid
+        std::fs::write(dir.path().join("other.json"), ADAPTER)?;
+        std::fs::write(dir.path().join("broken.json"), "{")?;
+        let mut installed = read_installed(dir.path())?;
+        installed.insert(
+            "broken".to_owned(),
+            Source {
+                url: url.to_owned(),
+                sha256: sha256_hex(b"{"),
+            },
+        );
+        std::fs::write(
+            dir.path().join(INSTALLED),
+            serde_json::to_string(&installed)?,
+        )?;
+        let adapters = installed_adapters(dir.path())?;
+        assert_eq!(adapters.len(), 1);
+        let mut config = json!({"accurate": true});
+        merge_adapters(&mut config, adapters.clone());
+        assert_eq!(config["custom_adapters"][0]["name"], "gpg");
+        // the adapters of the config file are kept
+        let mut config = json!({"custom_adapters": [{"name": "gpg", "binary": "gpg2"}]});
+        merge_adapters(&mut config, adapters);
+        assert_eq!(
+            config["custom_adapters"],
+            json!([{"name": "gpg", "binary": "gpg2"}])
+        );
+        // changed after it was installed
+        std::fs::write(
+            dir.path().join("gpg.json"),
+            ADAPTER.replace("gpg\"]", "sh\"]"),
+        )?;
+        assert!(installed_adapters(dir.path())?.is_empty());
+        std::fs::write(dir.path().join(INSTALLED), "[")?;
+        assert!(installed_adapters(dir.path())?.is_empty());
+        Ok(())
+    }
+}
@@ -1,11 +1,57 @@
+mod binary;
+pub mod cad;
+pub mod calibre;
+mod cfb;
+pub mod cloudformation;
+pub mod comic;
+pub mod crashdump;
 pub mod custom;
 pub mod decompress;
+pub mod dotnet;
+pub mod ebook;
+pub mod epub;
+pub mod etcd;
+pub mod exif;
+#[cfg(feature = "media")]
 pub mod ffmpeg;
+pub mod fonts;
+pub mod fsimage;
+pub mod gamearchive;
+pub mod iso;
+pub mod java;
+pub mod localization;
+pub mod mail;
+pub mod msg;
+#[cfg(feature = "ocr")]
+pub mod ocr;
+#[cfg(feature = "office-native")]
+pub mod ooxml;
+#[cfg(feature = "office-native")]
+pub mod opendocument;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+#[cfg(any(feature = "pdf-poppler", feature = "pdf-native"))]
+pub mod pdf;
 pub mod postproc;
+pub mod pst;
+pub mod python;
+pub mod rar;
+pub mod rtf;
+pub mod sevenzip;
+pub mod sfx;
+pub mod sphinx;
 use std::sync::Arc;
 pub mod sqlite;
+pub mod squashfs;
+pub mod strings;
+pub mod tags;
 pub mod tar;
+pub mod terraform;
+pub mod vmdisk;
+#[cfg(feature = "media")]
+pub mod whisper;
 pub mod writing;
+pub(crate) mod xml;
 pub mod zip;
 use crate::{adapted_iter::AdaptedFilesIterBox, config::RgaConfig, matching::*};
 use anyhow::{format_err, Context, Result};
@@ -25,6 +71,20 @@ use std::pin::Pin;
 use self::postproc::PostprocPageBreaks;
 
 pub type ReadBox = Pin<Box<dyn AsyncRead + Send>>;
+
+/// the form of input an adapter needs. rga_preproc provides it before calling the adapter,
+/// so adapters don't have to handle files that are members of archives themselves
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputKind {
+    /// `ai.inp` is read once from start to end. Works for files on disk and archive members
+    Stream,
+    /// the adapter seeks in the input. Archive members are spooled to a temporary file first
+    Seekable,
+    /// the adapter (or a program it runs) opens `ai.filepath_hint` itself. Archive members are spooled
+    /// to a temporary file with the same file name, and `filepath_hint` points to it
+    Path,
+}
+
 pub struct AdapterMeta {
     /// unique short name of this adapter (a-z0-9 only)
     pub name: String,
@@ -44,6 +104,10 @@ pub struct AdapterMeta {
     pub keep_fast_matchers_if_accurate: bool,
     // if true, adapter is only used when user lists it in `--rga-adapters`
     pub disabled_by_default: bool,
+    /// if true, the output contains secrets (e.g. decrypted files) and is never cached unless `--rga-cache-sensitive` is given
+    pub sensitive: bool,
+    /// the form of input the adapter needs, see [InputKind]
+    pub input: InputKind,
 }
 impl AdapterMeta {
     // todo: this is pretty ugly
@@ -83,6 +147,9 @@ pub trait FileAdapter: GetMetadata + Send + Sync {
     /// adapt a file.
     ///
     /// detection_reason is the Matcher that was used to identify this file. Unless --rga-accurate was given, it is always a FastMatcher
+    ///
+    /// The output does not need to be clean text: before it is searched, it is made valid UTF-8 without NUL bytes
+    /// and lines longer than [postproc::MAX_LINE_LEN] are split, see [postproc::postproc_lines]
     async fn adapt(
         &self,
         a: AdaptInfo,
@@ -108,6 +175,22 @@ pub struct AdaptInfo {
 /// (enabledAdapters, disabledAdapters)
 type AdaptersTuple = (Vec<Arc<dyn FileAdapter>>, Vec<Arc<dyn FileAdapter>>);
 
+/// the optional adapters and backends (cargo features) rga was built with, shown by --version
+pub static FEATURES: &[&str] = &[
+    #[cfg(feature = "pdf-poppler")]
+    "pdf-poppler",
+    #[cfg(feature = "pdf-native")]
+    "pdf-native",
+    #[cfg(feature = "ocr")]
+    "ocr",
+    #[cfg(feature = "media")]
+    "media",
+    #[cfg(feature = "office-native")]
+    "office-native",
+    #[cfg(feature = "parquet")]
+    "parquet",
+];
+
 pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> AdaptersTuple {
     // order in descending priority
     let mut adapters: Vec<Arc<dyn FileAdapter>> = vec![];
@@ -119,11 +202,60 @@ pub fn get_all_adapters(custom_adapters: Option<Vec<CustomAdapterConfig>>) -> Ad
 
     let internal_adapters: Vec<Arc<dyn FileAdapter>> = vec![
         Arc::new(PostprocPageBreaks::default()),
+        #[cfg(feature = "media")]
         Arc::new(ffmpeg::FFmpegAdapter::new()),
+        // for m4a files, and for the other music files in builds without ffmpeg
+        Arc::new(tags::TagsAdapter::new()),
+        // photos only have their metadata extracted unless ocr is enabled with +ocr, which puts it first
+        Arc::new(exif::ExifAdapter::new()),
+        // before zip, since office documents, e-books and comic books are zip files
+        #[cfg(feature = "office-native")]
+        Arc::new(ooxml::OoxmlAdapter::new()),
+        #[cfg(feature = "office-native")]
+        Arc::new(opendocument::OpenDocumentAdapter::new()),
+        Arc::new(epub::EpubAdapter::new()),
+        Arc::new(comic::ComicAdapter::new()),
         Arc::new(zip::ZipAdapter::new()),
         Arc::new(decompress::DecompressAdapter::new()),
         Arc::new(tar::TarAdapter::new()),
+        Arc::new(sevenzip::SevenZipAdapter::new()),
+        Arc::new(rar::RarAdapter::new()),
+        Arc::new(iso::IsoAdapter::new()),
+        Arc::new(squashfs::SquashfsAdapter::new()),
+        Arc::new(fsimage::FsImageAdapter::new()),
+        Arc::new(vmdisk::VmDiskAdapter::new()),
+        Arc::new(mail::MboxAdapter::new()),
+        Arc::new(mail::MaildirAdapter::new()),
+        Arc::new(mail::EmlAdapter::new()),
+        Arc::new(msg::MsgAdapter::new()),
+        Arc::new(pst::PstAdapter::new()),
         Arc::new(sqlite::SqliteAdapter::new()),
+        #[cfg(feature = "parquet")]
+        Arc::new(parquet::ParquetAdapter::new()),
+        #[cfg(any(feature = "pdf-poppler", feature = "pdf-native"))]
+        Arc::new(pdf::PdfAdapter::new()),
+        Arc::new(crashdump::CrashdumpAdapter::new()),
+        Arc::new(java::JavaAdapter::new()),
+        Arc::new(dotnet::DotnetAdapter::new()),
+        Arc::new(python::PycAdapter::new()),
+        Arc::new(python::PickleAdapter::new()),
+        Arc::new(terraform::TerraformAdapter::new()),
+        Arc::new(cloudformation::CloudformationAdapter::new()),
+        Arc::new(etcd::EtcdAdapter::new()),
+        Arc::new(sphinx::SphinxAdapter::new()),
+        Arc::new(localization::LocalizationAdapter::new()),
+        Arc::new(ebook::EbookAdapter::new()),
+        Arc::new(rtf::RtfAdapter::new()),
+        Arc::new(fonts::FontsAdapter::new()),
+        Arc::new(cad::CadAdapter::new()),
+        Arc::new(gamearchive::GameArchiveAdapter::new()),
+        Arc::new(sfx::SfxAdapter::new()),
+        Arc::new(strings::StringsAdapter::new()),
+        Arc::new(calibre::CalibreAdapter::new()),
+        #[cfg(feature = "ocr")]
+        Arc::new(ocr::OcrAdapter::new()),
+        #[cfg(feature = "media")]
+        Arc::new(whisper::WhisperAdapter::new()),
     ];
     adapters.extend(
         BUILTIN_SPAWNING_ADAPTERS
@@ -202,3 +334,14 @@ pub fn get_adapters_filtered<T: AsRef<str>>(
     );
     Ok(adapters)
 }
+
+/// the names of the adapters `config` chooses, without the ones in `excluded`
+pub fn adapters_without(config: &RgaConfig, excluded: &[String]) -> Result<Vec<String>> {
+    Ok(
+        get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?
+            .iter()
+            .map(|a| a.metadata().name.clone())
+            .filter(|name| !excluded.contains(name))
+            .collect(),
+    )
+}
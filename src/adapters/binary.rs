@@ -0,0 +1,207 @@
+//! Helpers for adapters that parse binary formats from an in-memory buffer.
+//!
+//! All reads are bounds checked and return an error instead of panicking,
+//! since the input is usually some random file from disk.
+
+use anyhow::{format_err, Result};
+
+/// Reads integers and strings at absolute offsets of a byte slice
+#[derive(Clone, Copy)]
+pub struct BinReader<'a> {
+    pub data: &'a [u8],
+    pub big_endian: bool,
+}
+
+impl<'a> BinReader<'a> {
+    pub fn le(data: &'a [u8]) -> BinReader<'a> {
+        BinReader {
+            data,
+            big_endian: false,
+        }
+    }
+    pub fn be(data: &'a [u8]) -> BinReader<'a> {
+        BinReader {
+            data,
+            big_endian: true,
+        }
+    }
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+    pub fn slice(&self, off: usize, len: usize) -> Result<&'a [u8]> {
+        off.checked_add(len)
+            .and_then(|end| self.data.get(off..end))
+            .ok_or_else(|| {
+                format_err!(
+                    "read of {} bytes at offset {} out of bounds (len {})",
+                    len,
+                    off,
+                    self.data.len()
+                )
+            })
+    }
+    fn array<const N: usize>(&self, off: usize) -> Result<[u8; N]> {
+        let mut a = [0u8; N];
+        a.copy_from_slice(self.slice(off, N)?);
+        if self.big_endian {
+            a.reverse();
+        }
+        Ok(a)
+    }
+    pub fn u8(&self, off: usize) -> Result<u8> {
+        Ok(self.slice(off, 1)?[0])
+    }
+    pub fn u16(&self, off: usize) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array(off)?))
+    }
+    pub fn u32(&self, off: usize) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array(off)?))
+    }
+    pub fn u64(&self, off: usize) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.array(off)?))
+    }
+    /// read a u32 or u64 depending on the word size of the format (e.g. ELF32 vs ELF64)
+    pub fn word(&self, off: usize, is_64: bool) -> Result<u64> {
+        if is_64 {
+            self.u64(off)
+        } else {
+            self.u32(off).map(u64::from)
+        }
+    }
+    /// read an unsigned LEB128 varint, returning the value and its encoded length
+    pub fn uleb128(&self, off: usize) -> Result<(u64, usize)> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let b = self.u8(off + i)?;
+            value |= u64::from(b & 0x7f) << (7 * i);
+            if b & 0x80 == 0 {
+                return Ok((value, i + 1));
+            }
+        }
+        Err(format_err!("LEB128 value at offset {} too long", off))
+    }
+    /// read a NUL-terminated string starting at off. If there is no NUL, the rest of the buffer is returned
+    pub fn cstr(&self, off: usize) -> Result<&'a [u8]> {
+        let rest = self
+            .data
+            .get(off..)
+            .ok_or_else(|| format_err!("string offset {} out of bounds", off))?;
+        Ok(match memchr::memchr(0, rest) {
+            Some(end) => &rest[..end],
+            None => rest,
+        })
+    }
+}
+
+/// u32 offsets in file formats need to be converted to usize for indexing
+pub fn usize_from(v: impl TryInto<usize>) -> Result<usize> {
+    v.try_into()
+        .map_err(|_| format_err!("offset does not fit into usize"))
+}
+
+/// the result of checked_add/checked_mul on offsets and sizes read from a file, an error if it overflowed
+pub fn checked<T>(v: Option<T>) -> Result<T> {
+    v.ok_or_else(|| format_err!("offset or size out of range"))
+}
+
+/// decode UTF-16LE, replacing invalid surrogates
+pub fn utf16le_lossy(data: &[u8]) -> String {
+    let units: Vec<u16> = data
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// decode a byte string lossily and remove control characters so the result fits on one line
+pub fn one_line(data: &[u8]) -> String {
+    String::from_utf8_lossy(data)
+        .chars()
+        .map(|c| if c.is_control() { ' ' } else { c })
+        .collect()
+}
+
+/// Shannon entropy in bits per byte, from 0 (constant) to 8 (uniformly random).
+/// Compressed and encrypted data is close to 8, machine code and text are well below 7
+pub fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &b in data {
+        counts[usize::from(b)] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// a protobuf field value, without knowing the schema
+pub enum Proto<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// split a protobuf message into (field number, value) pairs
+pub fn proto_fields(data: &[u8]) -> Result<Vec<(u64, Proto<'_>)>> {
+    let r = BinReader::le(data);
+    let mut pos = 0;
+    let mut fields = vec![];
+    while pos < data.len() {
+        let (key, n) = r.uleb128(pos)?;
+        pos += n;
+        let value = match key & 7 {
+            0 => {
+                let (v, n) = r.uleb128(pos)?;
+                pos += n;
+                Proto::Varint(v)
+            }
+            1 | 5 => {
+                let len = if key & 7 == 1 { 8 } else { 4 };
+                r.slice(pos, len)?;
+                pos += len;
+                Proto::Fixed
+            }
+            2 => {
+                let (len, n) = r.uleb128(pos)?;
+                let len = usize_from(len)?;
+                let bytes = r.slice(pos + n, len)?;
+                pos += n + len;
+                Proto::Bytes(bytes)
+            }
+            t => return Err(format_err!("unsupported protobuf wire type {}", t)),
+        };
+        fields.push((key >> 3, value));
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads() -> Result<()> {
+        let data = [1u8, 2, 3, 4, b'h', b'i', 0, b'x'];
+        let le = BinReader::le(&data);
+        let be = BinReader::be(&data);
+        assert_eq!(le.u16(0)?, 0x0201);
+        assert_eq!(be.u16(0)?, 0x0102);
+        assert_eq!(le.u32(0)?, 0x04030201);
+        assert_eq!(be.u32(0)?, 0x01020304);
+        assert_eq!(le.cstr(4)?, b"hi");
+        assert_eq!(le.cstr(7)?, b"x");
+        assert!(le.u64(4).is_err());
+        assert!(le.slice(usize::MAX, 2).is_err());
+        assert!(checked(u64::MAX.checked_mul(2)).is_err());
+        assert_eq!(BinReader::le(&[0xe5, 0x8e, 0x26]).uleb128(0)?, (624485, 3));
+        Ok(())
+    }
+}
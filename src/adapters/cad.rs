@@ -0,0 +1,423 @@
+use super::binary::{one_line, usize_from, BinReader};
+use super::terraform::flatten_json;
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["step", "stp", "p21", "stl", "gltf", "glb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cad".to_owned(),
+        version: 1,
+        description:
+            "Extracts header metadata and part / product names of STEP files, names and extras of glTF models and the header of STL files"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            ["model/step", "model/stl", "model/gltf+json", "model/gltf-binary"]
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CadAdapter;
+
+impl CadAdapter {
+    pub fn new() -> CadAdapter {
+        CadAdapter
+    }
+}
+impl GetMetadata for CadAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// decode the control directives of ISO 10303-21 strings (\X2\...\X0\ etc.)
+fn step_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(i) = rest.find('\\') {
+        out.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let hex_run = |r: &str, width: usize| -> Option<(String, usize)> {
+            let end = r.find("\\X0\\")?;
+            let digits = &r[..end];
+            let units = (0..digits.len() / width)
+                .map(|j| u32::from_str_radix(digits.get(j * width..(j + 1) * width)?, 16).ok())
+                .collect::<Option<Vec<_>>>()?;
+            let text = if width == 4 {
+                let units: Vec<u16> = units.iter().map(|u| *u as u16).collect();
+                String::from_utf16_lossy(&units)
+            } else {
+                units.into_iter().filter_map(char::from_u32).collect()
+            };
+            Some((text, end + 4))
+        };
+        let decoded = if let Some(r) = rest.strip_prefix("\\X2\\") {
+            hex_run(r, 4).map(|(t, len)| (t, len + 4))
+        } else if let Some(r) = rest.strip_prefix("\\X4\\") {
+            hex_run(r, 8).map(|(t, len)| (t, len + 4))
+        } else if let Some(r) = rest.strip_prefix("\\X\\") {
+            r.get(..2)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .map(|b| (char::from(b).to_string(), 5))
+        } else if let Some(r) = rest.strip_prefix("\\S\\") {
+            r.chars()
+                .next()
+                .and_then(|c| char::from_u32(c as u32 + 128))
+                .map(|c| (c.to_string(), 4))
+        } else if rest.starts_with("\\\\") {
+            Some(("\\".to_owned(), 2))
+        } else {
+            None
+        };
+        match decoded {
+            Some((text, len)) => {
+                out.push_str(&text);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('\\');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// the keyword and all string literals (with the index of the top level parameter they are in) of one statement
+fn step_statement(stmt: &str) -> Option<(&str, Vec<(usize, String)>)> {
+    let body = match stmt.split_once('=') {
+        Some((id, body)) if id.trim_start().starts_with('#') => body,
+        _ => stmt,
+    };
+    let body = body.trim_start();
+    // complex entity instances like (LENGTH_UNIT() NAMED_UNIT(*)) have no single keyword
+    let open = body.find('(')?;
+    let keyword = body[..open].trim();
+    if keyword.is_empty() {
+        return None;
+    }
+    let mut strings = vec![];
+    let (mut depth, mut param) = (0, 0);
+    let mut chars = body[open..].chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 1 => param += 1,
+            '\'' => {
+                let mut s = String::new();
+                while let Some(c) = chars.next() {
+                    if c == '\'' {
+                        if chars.peek() == Some(&'\'') {
+                            chars.next();
+                        } else {
+                            break;
+                        }
+                    }
+                    s.push(c);
+                }
+                strings.push((param, step_unescape(&s)));
+            }
+            _ => {}
+        }
+    }
+    Some((keyword, strings))
+}
+
+/// split the exchange structure into statements, ignoring ; in strings and comments
+fn step_statements(text: &str) -> Vec<&str> {
+    let mut statements = vec![];
+    let (mut start, mut in_string, mut in_comment) = (0, false, false);
+    let bytes = text.as_bytes();
+    for (i, &b) in bytes.iter().enumerate() {
+        match b {
+            b'\'' if !in_comment => in_string = !in_string,
+            b'/' if !in_string && !in_comment && bytes.get(i + 1) == Some(&b'*') => {
+                in_comment = true
+            }
+            b'/' if in_comment && i > 0 && bytes[i - 1] == b'*' => {
+                in_comment = false;
+                start = i + 1;
+            }
+            b';' if !in_string && !in_comment => {
+                statements.push(text[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    statements
+}
+
+const FILE_NAME_FIELDS: &[&str] = &[
+    "name",
+    "time stamp",
+    "author",
+    "organization",
+    "preprocessor",
+    "originating system",
+    "authorization",
+];
+
+fn dump_step(text: &str, s: &mut impl Write) -> Result<()> {
+    let mut seen = HashSet::new();
+    for stmt in step_statements(text) {
+        let (keyword, strings) = match step_statement(stmt) {
+            Some(k) => k,
+            None => continue,
+        };
+        let strings = strings
+            .into_iter()
+            .filter(|(_, v)| !v.is_empty() && v != "NONE" && v != "$");
+        match keyword {
+            "FILE_DESCRIPTION" => {
+                for (param, v) in strings {
+                    if param == 0 {
+                        writeln!(s, "description: {}", one_line(v.as_bytes()))?;
+                    }
+                }
+            }
+            "FILE_NAME" => {
+                for (param, v) in strings {
+                    let field = FILE_NAME_FIELDS.get(param).unwrap_or(&"file name");
+                    writeln!(s, "{field}: {}", one_line(v.as_bytes()))?;
+                }
+            }
+            "FILE_SCHEMA" => {
+                for (_, v) in strings {
+                    writeln!(s, "schema: {v}")?;
+                }
+            }
+            _ => {
+                let values: Vec<String> = strings.map(|(_, v)| one_line(v.as_bytes())).collect();
+                if values.is_empty() {
+                    continue;
+                }
+                // geometry often repeats the same names thousands of times
+                let line = format!("{}: {}", keyword.to_lowercase(), values.join(" | "));
+                if seen.insert(line.clone()) {
+                    writeln!(s, "{line}")?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_stl(data: &[u8], s: &mut impl Write) -> Result<()> {
+    // binary STL files can also start with "solid", so check for the ascii keywords too
+    let is_ascii = data.starts_with(b"solid")
+        && memchr::memmem::find(&data[..data.len().min(1024)], b"facet").is_some();
+    if is_ascii {
+        let text = String::from_utf8_lossy(data);
+        let mut triangles = 0;
+        for line in text.lines() {
+            let line = line.trim();
+            if let Some(name) = line.strip_prefix("solid") {
+                writeln!(s, "solid: {}", name.trim())?;
+            } else if line.starts_with("facet") {
+                triangles += 1;
+            }
+        }
+        writeln!(s, "triangles: {triangles}")?;
+    } else {
+        let r = BinReader::le(data);
+        let header = one_line(
+            r.slice(0, 80)?
+                .split(|b| *b == 0)
+                .next()
+                .unwrap_or_default(),
+        );
+        if !header.trim().is_empty() {
+            writeln!(s, "header: {}", header.trim())?;
+        }
+        writeln!(s, "triangles: {}", r.u32(80)?)?;
+    }
+    Ok(())
+}
+
+/// top level glTF arrays whose elements have user-facing names
+const GLTF_NAMED: &[&str] = &[
+    "scenes",
+    "nodes",
+    "meshes",
+    "materials",
+    "textures",
+    "images",
+    "animations",
+    "skins",
+    "cameras",
+];
+
+fn dump_gltf(json: &[u8], s: &mut impl Write) -> Result<()> {
+    let v: Value = serde_json::from_slice(json)?;
+    for key in ["asset", "extensionsUsed", "extras"] {
+        if let Some(value) = v.get(key) {
+            flatten_json(key, value, s)?;
+        }
+    }
+    for key in GLTF_NAMED {
+        for (i, item) in v
+            .get(key)
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            if let Some(name) = item.get("name").and_then(Value::as_str) {
+                writeln!(s, "{key}[{i}].name = {name}")?;
+            }
+            // embedded data: uris are base64 blobs
+            if let Some(uri) = item.get("uri").and_then(Value::as_str) {
+                if !uri.starts_with("data:") {
+                    writeln!(s, "{key}[{i}].uri = {uri}")?;
+                }
+            }
+            if let Some(extras) = item.get("extras") {
+                flatten_json(&format!("{key}[{i}].extras"), extras, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// binary glTF: a header and chunks, the first of which is the JSON document
+fn dump_glb(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let r = BinReader::le(data);
+    let len = usize_from(r.u32(12)?)?;
+    if r.slice(16, 4)? != b"JSON" {
+        return Err(format_err!("first glb chunk is not JSON"));
+    }
+    dump_gltf(r.slice(20, len)?, s)
+}
+
+fn dump_cad(data: &[u8], s: &mut impl Write) -> Result<()> {
+    if data.starts_with(b"glTF") {
+        dump_glb(data, s)
+    } else if data.starts_with(b"ISO-10303-21") {
+        dump_step(&String::from_utf8_lossy(data), s)
+    } else if data.trim_ascii_start().starts_with(b"{") {
+        dump_gltf(data, s)
+    } else {
+        dump_stl(data, s)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for CadAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_cad(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dump(data: &[u8]) -> Result<String> {
+        let mut out = Vec::new();
+        dump_cad(data, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn step() -> Result<()> {
+        let step = r"ISO-10303-21;
+HEADER;
+FILE_DESCRIPTION(('Bracket assembly'),'2;1');
+FILE_NAME('bracket.stp','2023-05-01T10:00:00',('J. Doe'),('ACME'),'ST-DEVELOPER','CAD 2023','');
+FILE_SCHEMA(('AUTOMOTIVE_DESIGN'));
+ENDSEC;
+DATA;
+/* comment; with semicolon */
+#1=PRODUCT('B-100','Bracket','M\X2\00FC\X0\nster''s part',(#2));
+#2=PRODUCT_CONTEXT('',#3,'mechanical');
+#5=(LENGTH_UNIT()NAMED_UNIT(*)SI_UNIT(.MILLI.,.METRE.));
+#6=CARTESIAN_POINT('NONE',(0.,0.,0.));
+#7=ADVANCED_FACE('',(#8),#9,.T.);
+ENDSEC;
+END-ISO-10303-21;
+";
+        assert_eq!(
+            dump(step.as_bytes())?,
+            "description: Bracket assembly
+name: bracket.stp
+time stamp: 2023-05-01T10:00:00
+author: J. Doe
+organization: ACME
+preprocessor: ST-DEVELOPER
+originating system: CAD 2023
+schema: AUTOMOTIVE_DESIGN
+product: B-100 | Bracket | Münster's part
+product_context: mechanical
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn stl_and_gltf() -> Result<()> {
+        let ascii =
+            b"solid gear\nfacet normal 0 0 1\nouter loop\nendloop\nendfacet\nendsolid gear\n";
+        assert_eq!(dump(ascii)?, "solid: gear\ntriangles: 1\n");
+        let mut binary = b"Exported from Modeler".to_vec();
+        binary.resize(80, 0);
+        binary.extend(12u32.to_le_bytes());
+        assert_eq!(
+            dump(&binary)?,
+            "header: Exported from Modeler\ntriangles: 12\n"
+        );
+
+        let json = br#"{"asset": {"version": "2.0", "generator": "Blender"},
+            "nodes": [{"name": "Wheel", "extras": {"part_no": "W-7"}}, {"mesh": 0}],
+            "images": [{"uri": "data:image/png;base64,AAAA"}, {"uri": "wood.png"}]}"#;
+        let mut glb = b"glTF".to_vec();
+        glb.extend(2u32.to_le_bytes());
+        glb.extend(0u32.to_le_bytes());
+        glb.extend((json.len() as u32).to_le_bytes());
+        glb.extend(b"JSON");
+        glb.extend(json);
+        assert_eq!(
+            dump(&glb)?,
+            "asset.generator = Blender
+asset.version = 2.0
+nodes[0].name = Wheel
+nodes[0].extras.part_no = W-7
+images[1].uri = wood.png
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,99 @@
+use super::custom::map_exe_error;
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::policy;
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Stdio;
+
+static EXTENSIONS: &[&str] = &[
+    "mobi", "azw", "azw3", "azw4", "kfx", "lit", "fb2", "fbz", "pdb", "prc", "lrf", "snb", "tcr",
+    "pml", "rb",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "calibre".to_owned(),
+        version: 1,
+        description:
+            "Uses ebook-convert (from calibre) to convert e-book formats other tools can't read, like MOBI, AZW3 and LIT, to plain text. DRM-protected books fail"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-mobipocket-ebook".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true,
+        sensitive: false,
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CalibreAdapter;
+
+impl CalibreAdapter {
+    pub fn new() -> CalibreAdapter {
+        CalibreAdapter
+    }
+}
+impl GetMetadata for CalibreAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for CalibreAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        // ebook-convert chooses the formats by the file extensions and can't write to stdout
+        let dir = tempfile::tempdir().context("creating temporary directory")?;
+        let txt = dir.path().join("book.txt");
+        let out = policy::command(&config.policy, "ebook-convert", &filepath_hint)?
+            .arg(&filepath_hint)
+            .arg(&txt)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "ebook-convert", "Install calibre."))?;
+        if !out.status.success() {
+            // the error is at the end of a python traceback
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            bail!(
+                "ebook-convert failed ({}): {}",
+                out.status,
+                stderr.trim().lines().last().unwrap_or_default()
+            );
+        }
+        let text = tokio::fs::read(&txt)
+            .await
+            .context("reading output of ebook-convert")?;
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text)),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
@@ -0,0 +1,425 @@
+//! A reader for Compound File Binary files (OLE2 structured storage), the container of Outlook .msg
+//! files and the old Office formats. The file is a small FAT file system: a tree of storages
+//! (directories) and streams (files) stored in chains of sectors.
+
+use super::binary::{usize_from, utf16le_lossy, BinReader};
+use anyhow::{bail, format_err, Result};
+
+const SIGNATURE: &[u8] = b"\xd0\xcf\x11\xe0\xa1\xb1\x1a\xe1";
+/// FAT entries >= this mark the end of a chain or special sectors
+const MAX_SECTOR: u32 = 0xfffffffa;
+const NO_ENTRY: u32 = 0xffffffff;
+const ENTRY_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    Empty,
+    Storage,
+    Stream,
+    Root,
+}
+
+#[derive(Debug, Clone)]
+pub struct Entry {
+    pub name: String,
+    pub kind: EntryKind,
+    left: u32,
+    right: u32,
+    child: u32,
+    start: u32,
+    pub size: u64,
+}
+
+pub struct CompoundFile<'a> {
+    r: BinReader<'a>,
+    sector_len: usize,
+    mini_sector_len: usize,
+    mini_cutoff: u64,
+    fat: Vec<u32>,
+    mini_fat: Vec<u32>,
+    mini_stream: Vec<u8>,
+    entries: Vec<Entry>,
+}
+
+pub fn is_compound_file(data: &[u8]) -> bool {
+    data.starts_with(SIGNATURE)
+}
+
+impl<'a> CompoundFile<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<CompoundFile<'a>> {
+        if !is_compound_file(data) {
+            bail!("not a compound file");
+        }
+        let r = BinReader::le(data);
+        let sector_shift = r.u16(0x1e)?;
+        let mini_sector_shift = r.u16(0x20)?;
+        if !(7..=16).contains(&sector_shift) || mini_sector_shift >= sector_shift {
+            bail!("invalid sector size 2^{sector_shift}");
+        }
+        let mut cf = CompoundFile {
+            r,
+            sector_len: 1 << sector_shift,
+            mini_sector_len: 1 << mini_sector_shift,
+            mini_cutoff: u64::from(r.u32(0x38)?),
+            fat: Vec::new(),
+            mini_fat: Vec::new(),
+            mini_stream: Vec::new(),
+            entries: Vec::new(),
+        };
+        // the sectors of the FAT: 109 in the header, the rest in a chain of DIFAT sectors
+        let fat_sectors = usize_from(r.u32(0x2c)?)?;
+        let mut difat = Vec::new();
+        for i in 0..109 {
+            difat.push(r.u32(0x4c + i * 4)?);
+        }
+        let mut next = r.u32(0x44)?;
+        let per_sector = cf.sector_len / 4 - 1;
+        while next < MAX_SECTOR && difat.len() < fat_sectors {
+            let sector = cf.sector(next)?;
+            let s = BinReader::le(sector);
+            for i in 0..per_sector {
+                difat.push(s.u32(i * 4)?);
+            }
+            next = s.u32(per_sector * 4)?;
+        }
+        for &sector in difat.iter().take(fat_sectors) {
+            let s = BinReader::le(cf.sector(sector)?);
+            for i in 0..cf.sector_len / 4 {
+                cf.fat.push(s.u32(i * 4)?);
+            }
+        }
+        let dir = cf.chain(r.u32(0x30)?, None)?;
+        for e in dir.chunks_exact(ENTRY_LEN) {
+            let e = BinReader::le(e);
+            let kind = match e.u8(66)? {
+                1 => EntryKind::Storage,
+                2 => EntryKind::Stream,
+                5 => EntryKind::Root,
+                // unused entries keep their index, so the tree links stay valid
+                _ => EntryKind::Empty,
+            };
+            let name_len = usize::from(e.u16(64)?).min(64);
+            cf.entries.push(Entry {
+                name: utf16le_lossy(e.slice(0, name_len.saturating_sub(2))?),
+                kind,
+                left: e.u32(68)?,
+                right: e.u32(72)?,
+                child: e.u32(76)?,
+                start: e.u32(116)?,
+                // the high half is garbage in version 3 files
+                size: if sector_shift == 9 {
+                    u64::from(e.u32(120)?)
+                } else {
+                    e.u64(120)?
+                },
+            });
+        }
+        let root = match cf.entries.first() {
+            Some(root) if root.kind == EntryKind::Root => root.clone(),
+            _ => bail!("compound file without root entry"),
+        };
+        let mini_fat = cf.chain(r.u32(0x3c)?, None)?;
+        cf.mini_fat = mini_fat
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        cf.mini_stream = cf.chain(root.start, Some(root.size))?;
+        Ok(cf)
+    }
+
+    fn sector(&self, sector: u32) -> Result<&'a [u8]> {
+        let offset = (usize_from(sector)? + 1)
+            .checked_mul(self.sector_len)
+            .ok_or_else(|| format_err!("sector {} out of bounds", sector))?;
+        self.r.slice(offset, self.sector_len)
+    }
+
+    /// the contents of the chain of sectors starting at `start`, cut to `size`
+    fn chain(&self, start: u32, size: Option<u64>) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut sector = start;
+        while sector < MAX_SECTOR {
+            if out.len() > self.fat.len() * self.sector_len {
+                bail!("loop in sector chain");
+            }
+            out.extend_from_slice(self.sector(sector)?);
+            if size.map_or(false, |size| out.len() as u64 >= size) {
+                break;
+            }
+            sector = *self
+                .fat
+                .get(usize_from(sector)?)
+                .ok_or_else(|| format_err!("sector {} not in FAT", sector))?;
+        }
+        if let Some(size) = size {
+            out.truncate(usize_from(size)?);
+        }
+        Ok(out)
+    }
+
+    /// the contents of the chain of mini sectors (in the mini stream) starting at `start`
+    fn mini_chain(&self, start: u32, size: u64) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        let mut sector = start;
+        while sector < MAX_SECTOR && (out.len() as u64) < size {
+            if out.len() > self.mini_fat.len() * self.mini_sector_len {
+                bail!("loop in mini sector chain");
+            }
+            let offset = usize_from(sector)? * self.mini_sector_len;
+            out.extend_from_slice(
+                self.mini_stream
+                    .get(offset..offset + self.mini_sector_len)
+                    .ok_or_else(|| format_err!("mini sector {} out of bounds", sector))?,
+            );
+            sector = *self
+                .mini_fat
+                .get(usize_from(sector)?)
+                .ok_or_else(|| format_err!("mini sector {} not in mini FAT", sector))?;
+        }
+        out.truncate(usize_from(size)?);
+        Ok(out)
+    }
+
+    pub fn root(&self) -> usize {
+        0
+    }
+
+    pub fn entry(&self, index: usize) -> &Entry {
+        &self.entries[index]
+    }
+
+    /// the entries in the storage `storage`, which are stored as a binary tree
+    pub fn children(&self, storage: usize) -> Vec<usize> {
+        let mut out = Vec::new();
+        let mut todo = vec![self.entries[storage].child];
+        while let Some(i) = todo.pop() {
+            let i = i as usize;
+            // a broken file could link entries in a loop
+            if i >= self.entries.len() || out.contains(&i) {
+                continue;
+            }
+            let e = &self.entries[i];
+            out.push(i);
+            if e.left != NO_ENTRY {
+                todo.push(e.left);
+            }
+            if e.right != NO_ENTRY {
+                todo.push(e.right);
+            }
+        }
+        out.sort_by(|a, b| self.entries[*a].name.cmp(&self.entries[*b].name));
+        out
+    }
+
+    /// the entry named `name` (case insensitive) in the storage `storage`
+    pub fn child(&self, storage: usize, name: &str) -> Option<usize> {
+        self.children(storage)
+            .into_iter()
+            .find(|&i| self.entries[i].name.eq_ignore_ascii_case(name))
+    }
+
+    /// the contents of the stream `stream`
+    pub fn read(&self, stream: usize) -> Result<Vec<u8>> {
+        let e = &self.entries[stream];
+        if e.kind != EntryKind::Stream {
+            bail!("{} is not a stream", e.name);
+        }
+        if e.size < self.mini_cutoff {
+            self.mini_chain(e.start, e.size)
+        } else {
+            self.chain(e.start, Some(e.size))
+        }
+    }
+}
+
+/// builds a compound file (version 3, 512 byte sectors) with the given streams, with storages
+/// separated by `/` in their paths
+#[cfg(test)]
+pub fn build_compound_file(streams: &[(&str, &[u8])]) -> Vec<u8> {
+    struct Node {
+        name: String,
+        kind: u8,
+        children: Vec<usize>,
+        data: Vec<u8>,
+        start: u32,
+    }
+    const END: u32 = 0xfffffffe;
+    let mut nodes = vec![Node {
+        name: "Root Entry".to_owned(),
+        kind: 5,
+        children: vec![],
+        data: vec![],
+        start: END,
+    }];
+    for (path, data) in streams {
+        let mut parent = 0;
+        let parts = path.split('/').collect::<Vec<_>>();
+        for (i, part) in parts.iter().enumerate() {
+            let existing = nodes[parent]
+                .children
+                .iter()
+                .copied()
+                .find(|&c| nodes[c].name == *part);
+            parent = match existing {
+                Some(c) => c,
+                None => {
+                    let is_stream = i == parts.len() - 1;
+                    nodes.push(Node {
+                        name: part.to_string(),
+                        kind: if is_stream { 2 } else { 1 },
+                        children: vec![],
+                        data: if is_stream { data.to_vec() } else { vec![] },
+                        start: END,
+                    });
+                    let c = nodes.len() - 1;
+                    nodes[parent].children.push(c);
+                    c
+                }
+            };
+        }
+    }
+    // small streams go into the mini stream
+    let mut mini_stream = Vec::new();
+    let mut mini_fat = Vec::new();
+    for node in nodes
+        .iter_mut()
+        .filter(|n| n.kind == 2 && n.data.len() < 4096)
+    {
+        if node.data.is_empty() {
+            continue;
+        }
+        node.start = mini_fat.len() as u32;
+        let count = node.data.len().div_ceil(64);
+        for i in 0..count {
+            let n = mini_fat.len() as u32;
+            mini_fat.push(if i + 1 == count { END } else { n + 1 });
+        }
+        mini_stream.extend_from_slice(&node.data);
+        mini_stream.resize(mini_fat.len() * 64, 0);
+    }
+    nodes[0].data = mini_stream;
+    // sector 0 is the FAT, which is big enough for the files of the tests
+    let mut sectors = vec![vec![0u8; 512]];
+    let mut fat = vec![0xfffffffd];
+    let mut add_chain = |data: &[u8], sectors: &mut Vec<Vec<u8>>| -> u32 {
+        if data.is_empty() {
+            return END;
+        }
+        let start = sectors.len() as u32;
+        let chunks = data.chunks(512).collect::<Vec<_>>();
+        for (i, chunk) in chunks.iter().enumerate() {
+            let mut sector = chunk.to_vec();
+            sector.resize(512, 0);
+            sectors.push(sector);
+            let n = sectors.len() as u32;
+            fat.push(if i + 1 == chunks.len() { END } else { n });
+        }
+        start
+    };
+    for i in 0..nodes.len() {
+        if nodes[i].kind == 5 || nodes[i].data.len() >= 4096 {
+            let data = std::mem::take(&mut nodes[i].data);
+            nodes[i].start = add_chain(&data, &mut sectors);
+            nodes[i].data = data;
+        }
+    }
+    let mini_fat_bytes = mini_fat
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect::<Vec<_>>();
+    let mini_fat_start = add_chain(&mini_fat_bytes, &mut sectors);
+    let mut dir = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        let mut e = vec![0u8; ENTRY_LEN];
+        let name = node
+            .name
+            .encode_utf16()
+            .chain([0])
+            .flat_map(|u| u.to_le_bytes())
+            .collect::<Vec<_>>();
+        e[..name.len()].copy_from_slice(&name);
+        e[64..66].copy_from_slice(&(name.len() as u16).to_le_bytes());
+        e[66] = node.kind;
+        e[67] = 1;
+        // the children of a storage as a list of right siblings
+        let sibling = nodes
+            .iter()
+            .find(|n| n.children.contains(&i))
+            .and_then(|p| {
+                let pos = p.children.iter().position(|&c| c == i)?;
+                p.children.get(pos + 1).copied()
+            })
+            .map_or(NO_ENTRY, |s| s as u32);
+        e[68..72].copy_from_slice(&NO_ENTRY.to_le_bytes());
+        e[72..76].copy_from_slice(&sibling.to_le_bytes());
+        let child = node.children.first().map_or(NO_ENTRY, |&c| c as u32);
+        e[76..80].copy_from_slice(&child.to_le_bytes());
+        e[116..120].copy_from_slice(&node.start.to_le_bytes());
+        e[120..124].copy_from_slice(&(node.data.len() as u32).to_le_bytes());
+        dir.extend_from_slice(&e);
+    }
+    let dir_start = add_chain(&dir, &mut sectors);
+    assert!(fat.len() <= 128, "FAT of the test file too large");
+    let mut fat_sector = fat.iter().flat_map(|n| n.to_le_bytes()).collect::<Vec<_>>();
+    fat_sector.resize(512, 0xff);
+    sectors[0] = fat_sector;
+
+    let mut header = vec![0u8; 512];
+    header[..8].copy_from_slice(SIGNATURE);
+    header[0x18..0x1a].copy_from_slice(&0x3eu16.to_le_bytes());
+    header[0x1a..0x1c].copy_from_slice(&3u16.to_le_bytes());
+    header[0x1c..0x1e].copy_from_slice(&0xfffeu16.to_le_bytes());
+    header[0x1e..0x20].copy_from_slice(&9u16.to_le_bytes());
+    header[0x20..0x22].copy_from_slice(&6u16.to_le_bytes());
+    header[0x2c..0x30].copy_from_slice(&1u32.to_le_bytes());
+    header[0x30..0x34].copy_from_slice(&dir_start.to_le_bytes());
+    header[0x38..0x3c].copy_from_slice(&4096u32.to_le_bytes());
+    header[0x3c..0x40].copy_from_slice(&mini_fat_start.to_le_bytes());
+    header[0x40..0x44].copy_from_slice(&(mini_fat_bytes.len().div_ceil(512) as u32).to_le_bytes());
+    header[0x44..0x48].copy_from_slice(&END.to_le_bytes());
+    header[0x4c..0x50].copy_from_slice(&0u32.to_le_bytes());
+    for i in 1..109 {
+        header[0x4c + i * 4..0x50 + i * 4].copy_from_slice(&NO_ENTRY.to_le_bytes());
+    }
+    header.extend(sectors.concat());
+    header
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn compound_file() -> Result<()> {
+        let big = (0..5000u32).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let data = build_compound_file(&[
+            ("a", b"first stream"),
+            ("dir/b", &[7; 100]),
+            ("dir/big", &big),
+            ("c", b""),
+        ]);
+        let cf = CompoundFile::parse(&data)?;
+        let names = |storage| {
+            cf.children(storage)
+                .into_iter()
+                .map(|i| cf.entry(i).name.clone())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(cf.root()), vec!["a", "c", "dir"]);
+        let dir = cf.child(cf.root(), "DIR").expect("dir");
+        assert_eq!(cf.entry(dir).kind, EntryKind::Storage);
+        assert_eq!(names(dir), vec!["b", "big"]);
+        assert_eq!(
+            cf.read(cf.child(cf.root(), "a").expect("a"))?,
+            b"first stream"
+        );
+        assert_eq!(cf.read(cf.child(dir, "b").expect("b"))?, vec![7; 100]);
+        assert_eq!(cf.read(cf.child(dir, "big").expect("big"))?, big);
+        assert_eq!(cf.read(cf.child(cf.root(), "c").expect("c"))?, b"");
+        assert!(cf.read(dir).is_err());
+        assert!(CompoundFile::parse(&data[..600]).is_err());
+        assert!(CompoundFile::parse(b"not a compound file").is_err());
+        Ok(())
+    }
+}
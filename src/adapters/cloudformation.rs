@@ -0,0 +1,176 @@
+use super::terraform::flatten_json;
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["cfn.json", "template.json"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "cloudformation".to_owned(),
+        version: 1,
+        description:
+            "Flattens CloudFormation templates and stack descriptions / exports from the AWS CLI into `type.name.attr = value` lines"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CloudformationAdapter;
+
+impl CloudformationAdapter {
+    pub fn new() -> CloudformationAdapter {
+        CloudformationAdapter
+    }
+}
+impl GetMetadata for CloudformationAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn str_field<'a>(v: &'a Value, key: &str) -> &'a str {
+    v.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+fn array<'a>(v: &'a Value, key: &str) -> impl Iterator<Item = &'a Value> {
+    v.get(key).and_then(Value::as_array).into_iter().flatten()
+}
+
+fn dump_cfn(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let v: Value = serde_json::from_slice(data)?;
+    // template
+    if let Some(resources) = v.get("Resources").and_then(Value::as_object) {
+        for (logical_id, resource) in resources {
+            let typ = str_field(resource, "Type");
+            writeln!(s, "{typ}.{logical_id}")?;
+            if let Some(props) = resource.get("Properties") {
+                flatten_json(&format!("{typ}.{logical_id}"), props, s)?;
+            }
+        }
+    }
+    if let Some(params) = v.get("Parameters").and_then(Value::as_object) {
+        for (name, param) in params {
+            flatten_json(&format!("param.{name}"), param, s)?;
+        }
+    }
+    if let Some(outputs) = v.get("Outputs").and_then(Value::as_object) {
+        for (name, output) in outputs {
+            flatten_json(&format!("output.{name}"), output, s)?;
+        }
+    }
+    // aws cloudformation list-exports
+    for export in array(&v, "Exports") {
+        writeln!(
+            s,
+            "export.{} = {}",
+            str_field(export, "Name"),
+            str_field(export, "Value")
+        )?;
+    }
+    // aws cloudformation describe-stack-resources / list-stack-resources
+    for resource in array(&v, "StackResources").chain(array(&v, "StackResourceSummaries")) {
+        let prefix = format!(
+            "{}.{}",
+            str_field(resource, "ResourceType"),
+            str_field(resource, "LogicalResourceId")
+        );
+        for key in ["PhysicalResourceId", "ResourceStatus", "StackName"] {
+            if let Some(value) = resource.get(key).and_then(Value::as_str) {
+                writeln!(s, "{prefix}.{key} = {value}")?;
+            }
+        }
+    }
+    // aws cloudformation describe-stacks
+    for stack in array(&v, "Stacks") {
+        let name = str_field(stack, "StackName");
+        writeln!(
+            s,
+            "stack.{name}.StackStatus = {}",
+            str_field(stack, "StackStatus")
+        )?;
+        for p in array(stack, "Parameters") {
+            writeln!(
+                s,
+                "stack.{name}.param.{} = {}",
+                str_field(p, "ParameterKey"),
+                str_field(p, "ParameterValue")
+            )?;
+        }
+        for o in array(stack, "Outputs") {
+            writeln!(
+                s,
+                "stack.{name}.output.{} = {}",
+                str_field(o, "OutputKey"),
+                str_field(o, "OutputValue")
+            )?;
+            if let Some(export) = o.get("ExportName").and_then(Value::as_str) {
+                writeln!(s, "export.{export} = {}", str_field(o, "OutputValue"))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for CloudformationAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_cfn(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn template_and_exports() -> Result<()> {
+        let template = br#"{
+            "Resources": {
+                "Logs": {"Type": "AWS::S3::Bucket", "Properties": {"BucketName": "my-logs", "Tags": [{"Key": "team", "Value": "infra"}]}}
+            },
+            "Outputs": {"Arn": {"Value": {"Fn::GetAtt": ["Logs", "Arn"]}, "Export": {"Name": "logs-arn"}}},
+            "Exports": [{"ExportingStackId": "arn:x", "Name": "vpc-id", "Value": "vpc-123"}]
+        }"#;
+        let mut out = Vec::new();
+        dump_cfn(template, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "AWS::S3::Bucket.Logs
+AWS::S3::Bucket.Logs.BucketName = my-logs
+AWS::S3::Bucket.Logs.Tags[0].Key = team
+AWS::S3::Bucket.Logs.Tags[0].Value = infra
+output.Arn.Export.Name = logs-arn
+output.Arn.Value.Fn::GetAtt[0] = Logs
+output.Arn.Value.Fn::GetAtt[1] = Arn
+export.vpc-id = vpc-123
+"
+        );
+        Ok(())
+    }
+}
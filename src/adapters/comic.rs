@@ -0,0 +1,191 @@
+use super::custom::pipe_output;
+use super::tar::TarAdapter;
+use super::zip::ZipAdapter;
+use super::*;
+use crate::config::RgaConfig;
+use crate::policy;
+use crate::vpath::page_prefix;
+use anyhow::Result;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use std::path::Path;
+
+static EXTENSIONS: &[&str] = &["cbz", "cbr", "cbt"];
+
+/// pages of comic books, other members (like ComicInfo.xml) are adapted normally
+static IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "comic".to_owned(),
+        version: 1,
+        description:
+            "Reads comic book archives (.cbz, .cbr, .cbt). The images are numbered as pages (#page=N) and only adapted if an adapter for images like ocr is enabled. .cbr files are converted with bsdtar (from libarchive)"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.comicbook+zip".to_owned()),
+            FileMatcher::MimeType("application/vnd.comicbook-rar".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ComicAdapter;
+
+impl ComicAdapter {
+    pub fn new() -> ComicAdapter {
+        ComicAdapter
+    }
+}
+impl GetMetadata for ComicAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default()
+}
+
+/// whether an enabled adapter reads images, otherwise the pages would only be binary data
+fn ocr_enabled(config: &RgaConfig) -> bool {
+    let adapters = match get_adapters_filtered(config.custom_adapters.clone(), &config.adapters) {
+        Ok(adapters) => adapters,
+        Err(_) => return false,
+    };
+    adapters.iter().any(|a| {
+        a.metadata().fast_matchers.iter().any(|m| match m {
+            FastFileMatcher::FileExtension(e) => IMAGE_EXTENSIONS.contains(&e.as_str()),
+            FastFileMatcher::Directory(_) => false,
+        })
+    })
+}
+
+#[async_trait]
+impl FileAdapter for ComicAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let line_prefix = ai.line_prefix.clone();
+        let ocr = ocr_enabled(&ai.config);
+        let members = match extension(&ai.filepath_hint).as_str() {
+            "cbr" => {
+                // libarchive reads rar (v4 and most of v5), and writes the members as a tar stream
+                let mut cmd = policy::command(&ai.config.policy, "bsdtar", &ai.filepath_hint)?;
+                cmd.args(["-cf", "-", "@-"]);
+                let tar = pipe_output(
+                    &line_prefix,
+                    cmd,
+                    ai.inp,
+                    "bsdtar",
+                    "Install libarchive-tools.",
+                )?;
+                let ai = AdaptInfo {
+                    inp: tar,
+                    is_real_file: false,
+                    ..ai
+                };
+                TarAdapter::new().adapt(ai, detection_reason).await?
+            }
+            "cbt" => TarAdapter::new().adapt(ai, detection_reason).await?,
+            _ => ZipAdapter::new().adapt(ai, detection_reason).await?,
+        };
+        let s = stream! {
+            let mut page = 0;
+            for await member in members {
+                let mut member = member?;
+                if !IMAGE_EXTENSIONS.contains(&extension(&member.filepath_hint).as_str()) {
+                    yield Ok(member);
+                    continue;
+                }
+                if !ocr {
+                    // the archive readers need the member to be read before going to the next one
+                    tokio::io::copy(&mut member.inp, &mut tokio::io::sink()).await?;
+                    continue;
+                }
+                page += 1;
+                member.line_prefix = page_prefix(&line_prefix, page);
+                yield Ok(member);
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::custom::CustomAdapterConfig;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use async_zip::{write::ZipFileWriter, Compression, ZipEntryBuilder};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    async fn create_cbz() -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        for (name, content) in [
+            ("ComicInfo.xml", "<Series>Example</Series>"),
+            ("page01.png", "first page"),
+            ("page02.png", "second page"),
+        ] {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Stored);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    #[tokio::test]
+    async fn without_ocr() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("test.cbz"),
+            Box::pin(Cursor::new(create_cbz().await?)),
+        );
+        let o = adapted_to_vec(loop_adapt(&ComicAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:ComicInfo.xml: <Series>Example</Series>\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pages() -> Result<()> {
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("test.cbz"),
+            Box::pin(Cursor::new(create_cbz().await?)),
+        );
+        // stands in for an OCR adapter
+        a.config.custom_adapters = Some(vec![CustomAdapterConfig {
+            name: "fakeocr".to_owned(),
+            binary: "cat".to_owned(),
+            extensions: vec!["png".to_owned()],
+            ..Default::default()
+        }]);
+        let o = adapted_to_vec(loop_adapt(&ComicAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:ComicInfo.xml: <Series>Example</Series>
+PREFIX:#page=1: first page
+PREFIX:#page=2: second page
+"
+        );
+        Ok(())
+    }
+}
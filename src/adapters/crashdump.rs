@@ -0,0 +1,691 @@
+use super::binary::{checked, one_line, usize_from, utf16le_lossy, BinReader};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["dmp", "mdmp", "core"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "crashdump".to_owned(),
+        version: 1,
+        description:
+            "Lists modules, threads (with module+offset stack references), exception info and environment strings of Windows/Breakpad minidumps and ELF core dumps"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-core".to_owned()),
+            FileMatcher::MimeType("application/x-dmp".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        // the environment strings often contain tokens and passwords
+        sensitive: true,
+        // cores are as large as the memory of the process, only the notes and stacks are read
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct CrashdumpAdapter;
+
+impl CrashdumpAdapter {
+    pub fn new() -> CrashdumpAdapter {
+        CrashdumpAdapter
+    }
+}
+impl GetMetadata for CrashdumpAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// max number of stack words that are resolved to module+offset per thread
+const MAX_STACK_REFS: usize = 32;
+/// how much of each thread stack (from the stack pointer upwards) is scanned
+const MAX_STACK_SCAN: usize = 64 * 1024;
+
+struct Module {
+    name: String,
+    start: u64,
+    end: u64,
+    /// offset of `start` relative to the module base
+    offset: u64,
+}
+
+fn basename(path: &str) -> &str {
+    path.rsplit(['/', '\\']).next().unwrap_or(path)
+}
+
+fn symbolize(modules: &[Module], addr: u64) -> Option<String> {
+    modules
+        .iter()
+        .find(|m| m.start <= addr && addr < m.end)
+        .and_then(|m| {
+            let offset = (addr - m.start).checked_add(m.offset)?;
+            Some(format!("{}+0x{:x}", basename(&m.name), offset))
+        })
+}
+
+/// Scan raw stack memory for words that point into a known module.
+/// These are likely return addresses, so this gives a rough stack trace without needing unwind info.
+fn stack_refs(stack: &[u8], ptr_size: usize, big_endian: bool, modules: &[Module]) -> Vec<String> {
+    let mut refs: Vec<String> = Vec::new();
+    for word in stack.chunks_exact(ptr_size) {
+        let mut bytes = [0u8; 8];
+        if big_endian {
+            bytes[8 - ptr_size..].copy_from_slice(word);
+        } else {
+            bytes[..ptr_size].copy_from_slice(word);
+        }
+        let addr = if big_endian {
+            u64::from_be_bytes(bytes)
+        } else {
+            u64::from_le_bytes(bytes)
+        };
+        if let Some(sym) = symbolize(modules, addr) {
+            if refs.last() != Some(&sym) {
+                refs.push(sym);
+            }
+            if refs.len() >= MAX_STACK_REFS {
+                break;
+            }
+        }
+    }
+    refs
+}
+
+const MINIDUMP_SIGNATURE: &[u8] = b"MDMP";
+/// headers, notes and streams larger than this are damaged. The memory of the process isn't read in one piece
+const MAX_READ: u64 = 64 * 1024 * 1024;
+
+fn read_at<R: Read + Seek>(r: &mut R, pos: u64, len: u64) -> Result<Vec<u8>> {
+    if len > MAX_READ {
+        bail!("block of {} bytes at offset {} is too large", len, pos);
+    }
+    r.seek(SeekFrom::Start(pos))?;
+    let mut data = Vec::new();
+    r.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        bail!("the dump ends before {}", checked(pos.checked_add(len))?);
+    }
+    Ok(data)
+}
+
+fn minidump_string<R: Read + Seek>(r: &mut R, rva: u64) -> Result<String> {
+    let len = BinReader::le(&read_at(r, rva, 4)?).u32(0)?;
+    Ok(utf16le_lossy(&read_at(
+        r,
+        checked(rva.checked_add(4))?,
+        len.into(),
+    )?))
+}
+
+/// the data of the first stream of the given kind in the directory of (kind, size, rva)
+fn minidump_stream<R: Read + Seek>(
+    r: &mut R,
+    streams: &[(u32, u32, u32)],
+    kind: u32,
+) -> Result<Option<Vec<u8>>> {
+    match streams.iter().find(|e| e.0 == kind) {
+        Some((_, size, rva)) => Ok(Some(read_at(r, (*rva).into(), (*size).into())?)),
+        None => Ok(None),
+    }
+}
+
+/// program counter offset within the thread CONTEXT structure, by processor architecture
+fn minidump_pc(context: &[u8], arch: u16) -> Option<u64> {
+    let r = BinReader::le(context);
+    match arch {
+        0 => r.u32(0xb8).ok().map(u64::from), // x86 Eip
+        5 => r.u32(64).ok().map(u64::from),   // arm pc (iregs[15])
+        9 => r.u64(0xf8).ok(),                // amd64 Rip
+        12 => r.u64(264).ok(),                // arm64 pc
+        _ => None,
+    }
+}
+
+fn dump_minidump<R: Read + Seek>(r: &mut R, s: &mut impl Write) -> Result<()> {
+    let header = read_at(r, 0, 16)?;
+    let header = BinReader::le(&header);
+    let stream_count = u64::from(header.u32(8)?);
+    let directory = read_at(r, header.u32(12)?.into(), stream_count * 12)?;
+    let directory = BinReader::le(&directory);
+    // (kind, size, rva), the streams themselves are only read if they are used
+    let mut streams = Vec::new();
+    for i in 0..usize_from(stream_count)? {
+        let entry = i * 12;
+        streams.push((
+            directory.u32(entry)?,
+            directory.u32(entry + 4)?,
+            directory.u32(entry + 8)?,
+        ));
+    }
+
+    // SystemInfoStream
+    let arch = match minidump_stream(r, &streams, 7)? {
+        Some(data) => BinReader::le(&data).u16(0)?,
+        None => 0xffff,
+    };
+    let ptr_size = if arch == 0 || arch == 5 { 4 } else { 8 };
+
+    let mut modules = Vec::new();
+    // ModuleListStream
+    if let Some(data) = minidump_stream(r, &streams, 4)? {
+        let list = BinReader::le(&data);
+        for i in 0..usize_from(list.u32(0)?)? {
+            let entry = checked((i * 108).checked_add(4))?;
+            let base = list.u64(entry)?;
+            let size = list.u32(entry + 8)?;
+            let name = minidump_string(r, list.u32(entry + 20)?.into())?;
+            writeln!(s, "module: {name} base=0x{base:x} size=0x{size:x}")?;
+            modules.push(Module {
+                name,
+                start: base,
+                end: checked(base.checked_add(size.into()))?,
+                offset: 0,
+            });
+        }
+    }
+
+    // ThreadNamesStream
+    let mut thread_names = HashMap::new();
+    if let Some(data) = minidump_stream(r, &streams, 24)? {
+        let list = BinReader::le(&data);
+        for i in 0..usize_from(list.u32(0)?)? {
+            let entry = checked((i * 12).checked_add(4))?;
+            let thread_id = list.u32(entry)?;
+            thread_names.insert(thread_id, minidump_string(r, list.u64(entry + 4)?)?);
+        }
+    }
+
+    // ThreadListStream
+    if let Some(data) = minidump_stream(r, &streams, 3)? {
+        let list = BinReader::le(&data);
+        for i in 0..usize_from(list.u32(0)?)? {
+            let entry = checked((i * 48).checked_add(4))?;
+            let thread_id = list.u32(entry)?;
+            let stack_start = list.u64(entry + 24)?;
+            let stack_len = u64::from(list.u32(entry + 32)?);
+            let stack_rva = list.u32(entry + 36)?;
+            let context_len = list.u32(entry + 40)?;
+            let context_rva = list.u32(entry + 44)?;
+            let name = thread_names
+                .get(&thread_id)
+                .map(|n| format!(" ({n})"))
+                .unwrap_or_default();
+            writeln!(
+                s,
+                "thread {thread_id}{name}: stack 0x{stack_start:x}-0x{:x}",
+                checked(stack_start.checked_add(stack_len))?
+            )?;
+            if let Some(pc) = read_at(r, context_rva.into(), context_len.into())
+                .ok()
+                .and_then(|c| minidump_pc(&c, arch))
+            {
+                let sym = symbolize(&modules, pc).unwrap_or_else(|| format!("0x{pc:x}"));
+                writeln!(s, "thread {thread_id}{name}: pc {sym}")?;
+            }
+            let stack = read_at(r, stack_rva.into(), stack_len.min(MAX_STACK_SCAN as u64))?;
+            for sym in stack_refs(&stack, ptr_size, false, &modules) {
+                writeln!(s, "thread {thread_id}{name}: stack ref {sym}")?;
+            }
+        }
+    }
+
+    // ExceptionStream
+    if let Some(data) = minidump_stream(r, &streams, 6)? {
+        let e = BinReader::le(&data);
+        let thread_id = e.u32(0)?;
+        let code = e.u32(8)?;
+        let addr = e.u64(24)?;
+        let sym = symbolize(&modules, addr).unwrap_or_else(|| format!("0x{addr:x}"));
+        writeln!(
+            s,
+            "exception: code 0x{code:08x} at {sym} in thread {thread_id}"
+        )?;
+    }
+
+    // Breakpad linux extension streams
+    if let Some(data) = minidump_stream(r, &streams, 0x4767_0006)? {
+        let args: Vec<String> = data
+            .split(|b| *b == 0)
+            .filter(|a| !a.is_empty())
+            .map(one_line)
+            .collect();
+        writeln!(s, "cmdline: {}", args.join(" "))?;
+    }
+    if let Some(data) = minidump_stream(r, &streams, 0x4767_0007)? {
+        for var in data.split(|b| *b == 0).filter(|a| !a.is_empty()) {
+            writeln!(s, "env: {}", one_line(var))?;
+        }
+    }
+    Ok(())
+}
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const ET_CORE: u16 = 4;
+const PT_LOAD: u32 = 1;
+const PT_NOTE: u32 = 4;
+const NT_PRSTATUS: u32 = 1;
+const NT_PRPSINFO: u32 = 3;
+const NT_AUXV: u32 = 6;
+const NT_FILE: u32 = 0x4649_4c45;
+const AT_EXECFN: u64 = 31;
+
+/// a PT_LOAD segment, the memory of the process at `vaddr` is in the file at `offset`
+struct Segment {
+    vaddr: u64,
+    offset: u64,
+    len: u64,
+}
+
+/// the file offset and length of up to max_len bytes of process memory starting at addr
+fn find_memory(segments: &[Segment], addr: u64, max_len: u64) -> Option<(u64, u64)> {
+    segments.iter().find_map(|seg| {
+        let off = addr.checked_sub(seg.vaddr).filter(|off| *off < seg.len)?;
+        Some((seg.offset + off, (seg.len - off).min(max_len)))
+    })
+}
+
+fn is_env_var(s: &[u8]) -> bool {
+    match s.iter().position(|b| *b == b'=') {
+        Some(eq) if eq > 0 => {
+            (s[0].is_ascii_alphabetic() || s[0] == b'_')
+                && s[..eq]
+                    .iter()
+                    .all(|b| b.is_ascii_alphanumeric() || *b == b'_')
+        }
+        _ => false,
+    }
+}
+
+/// names and descriptors of ELF notes are padded to 4 bytes
+fn align4(len: usize) -> Result<usize> {
+    Ok(checked(len.checked_add(3))? & !3)
+}
+
+fn dump_elf_core<R: Read + Seek>(f: &mut R, s: &mut impl Write) -> Result<()> {
+    let file_len = f.seek(SeekFrom::End(0))?;
+    let header = read_at(f, 0, file_len.min(64))?;
+    let is_64 = match header.get(4) {
+        Some(1) => false,
+        Some(2) => true,
+        _ => return Err(format_err!("unknown ELF class")),
+    };
+    let big_endian = header.get(5) == Some(&2);
+    let r = BinReader {
+        data: &header,
+        big_endian,
+    };
+    if r.u16(16)? != ET_CORE {
+        writeln!(s, "[rga: ELF file is not a core dump]")?;
+        return Ok(());
+    }
+    let machine = r.u16(18)?;
+    let (phoff, phentsize, phnum) = if is_64 {
+        (r.u64(32)?, r.u16(54)?, r.u16(56)?)
+    } else {
+        (r.u32(28)?.into(), r.u16(42)?, r.u16(44)?)
+    };
+    let program_headers = read_at(f, phoff, u64::from(phentsize) * u64::from(phnum))?;
+    let ph = BinReader {
+        data: &program_headers,
+        big_endian,
+    };
+    let mut notes = Vec::new();
+    let mut segments = Vec::new();
+    for i in 0..usize::from(phnum) {
+        let entry = i * usize::from(phentsize);
+        let kind = ph.u32(entry)?;
+        let (offset, vaddr, filesz) = if is_64 {
+            (ph.u64(entry + 8)?, ph.u64(entry + 16)?, ph.u64(entry + 32)?)
+        } else {
+            (
+                ph.u32(entry + 4)?.into(),
+                ph.u32(entry + 8)?.into(),
+                ph.u32(entry + 16)?.into(),
+            )
+        };
+        // truncated cores are common, so clamp segments to the file size
+        let offset = offset.min(file_len);
+        let len = filesz.min(file_len - offset);
+        match kind {
+            PT_NOTE => notes.push(read_at(f, offset, len)?),
+            PT_LOAD => segments.push(Segment { vaddr, offset, len }),
+            _ => {}
+        }
+    }
+
+    let word_size = if is_64 { 8 } else { 4 };
+    let mut modules: Vec<Module> = Vec::new();
+    let mut threads = Vec::new();
+    let mut execfn = None;
+    for note in &notes {
+        let n = BinReader {
+            data: note,
+            big_endian,
+        };
+        let mut pos = 0;
+        while pos + 12 <= n.len() {
+            let namesz = usize_from(n.u32(pos)?)?;
+            let descsz = usize_from(n.u32(pos + 4)?)?;
+            let kind = n.u32(pos + 8)?;
+            let desc_off = checked((pos + 12).checked_add(align4(namesz)?))?;
+            let desc = BinReader {
+                data: n.slice(desc_off, descsz)?,
+                big_endian,
+            };
+            pos = checked(desc_off.checked_add(align4(descsz)?))?;
+            match kind {
+                NT_PRPSINFO => {
+                    let (fname, psargs) = if is_64 { (40, 56) } else { (28, 44) };
+                    let fname = desc.slice(fname, 16)?;
+                    let psargs = desc.slice(psargs, 80)?;
+                    let trim = |b: &[u8]| one_line(b.split(|c| *c == 0).next().unwrap_or(b));
+                    writeln!(s, "process: {}", trim(fname))?;
+                    writeln!(s, "cmdline: {}", trim(psargs).trim_end())?;
+                }
+                NT_PRSTATUS => threads.push(desc),
+                NT_AUXV => {
+                    for pair in 0..desc.len() / (2 * word_size) {
+                        let off = pair * 2 * word_size;
+                        if desc.word(off, is_64)? == AT_EXECFN {
+                            execfn = Some(desc.word(off + word_size, is_64)?);
+                        }
+                    }
+                }
+                NT_FILE => {
+                    let count = usize_from(desc.word(0, is_64)?)?;
+                    let page_size = desc.word(word_size, is_64)?;
+                    let entry_len = 3 * word_size;
+                    let mut name_off = checked(
+                        count
+                            .checked_mul(entry_len)
+                            .and_then(|l| l.checked_add(2 * word_size)),
+                    )?;
+                    for i in 0..count {
+                        let entry = 2 * word_size + i * entry_len;
+                        let name = desc.cstr(name_off)?;
+                        name_off += name.len() + 1;
+                        modules.push(Module {
+                            name: String::from_utf8_lossy(name).into_owned(),
+                            start: desc.word(entry, is_64)?,
+                            end: desc.word(entry + word_size, is_64)?,
+                            offset: checked(
+                                desc.word(entry + 2 * word_size, is_64)?
+                                    .checked_mul(page_size),
+                            )?,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let mut listed = std::collections::HashSet::new();
+    for m in modules.iter() {
+        if listed.insert(&m.name) {
+            writeln!(s, "module: {} base=0x{:x}", m.name, m.start)?;
+        }
+    }
+
+    // offsets into elf_prstatus; registers only for the common architectures
+    let (pid_off, reg_off) = if is_64 { (32, 112) } else { (24, 72) };
+    let (pc_reg, sp_reg) = match machine {
+        62 => (Some(16), Some(19)),  // x86_64 rip, rsp
+        183 => (Some(32), Some(31)), // aarch64 pc, sp
+        3 => (Some(12), Some(15)),   // i386 eip, esp
+        _ => (None, None),
+    };
+    for thread in threads {
+        let pid = thread.u32(pid_off)?;
+        let signal = thread.u32(0)?;
+        writeln!(s, "thread {pid}: signal {signal}")?;
+        let reg = |i: usize| thread.word(reg_off + i * word_size, is_64).ok();
+        if let Some(pc) = pc_reg.and_then(reg) {
+            let sym = symbolize(&modules, pc).unwrap_or_else(|| format!("0x{pc:x}"));
+            writeln!(s, "thread {pid}: pc {sym}")?;
+        }
+        if let Some((offset, len)) = sp_reg
+            .and_then(reg)
+            .and_then(|sp| find_memory(&segments, sp, MAX_STACK_SCAN as u64))
+        {
+            let stack = read_at(f, offset, len)?;
+            for sym in stack_refs(&stack, word_size, big_endian, &modules) {
+                writeln!(s, "thread {pid}: stack ref {sym}")?;
+            }
+        }
+    }
+
+    // the environment strings are stored at the top of the main thread stack, right before the executable name
+    if let Some(execfn) = execfn {
+        if let Some(seg) = segments
+            .iter()
+            .find(|seg| seg.vaddr <= execfn && execfn - seg.vaddr < seg.len)
+        {
+            let end = execfn - seg.vaddr;
+            let start = end.saturating_sub(MAX_STACK_SCAN as u64);
+            let env = read_at(f, seg.offset + start, end - start)?;
+            for var in env.split(|b| *b == 0).filter(|v| is_env_var(v)) {
+                writeln!(s, "env: {}", one_line(var))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_crash<R: Read + Seek>(r: &mut R, s: &mut impl Write) -> Result<()> {
+    let mut magic = Vec::new();
+    r.by_ref().take(4).read_to_end(&mut magic)?;
+    if magic == MINIDUMP_SIGNATURE {
+        dump_minidump(r, s)
+    } else if magic == ELF_MAGIC {
+        dump_elf_core(r, s)
+    } else {
+        writeln!(s, "[rga: unknown crash dump format]")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for CrashdumpAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let path = ai.filepath_hint;
+        let buf = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let mut file =
+                File::open(&path).with_context(|| format!("opening {}", path.display()))?;
+            let mut buf = Vec::new();
+            dump_crash(&mut file, &mut buf)?;
+            Ok(buf)
+        })
+        .await??;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn minidump_fixture() -> Vec<u8> {
+        let mut d = vec![0u8; 32];
+        d[0..4].copy_from_slice(MINIDUMP_SIGNATURE);
+        d[8..12].copy_from_slice(&3u32.to_le_bytes()); // stream count
+        d[12..16].copy_from_slice(&32u32.to_le_bytes()); // directory rva
+        d.resize(32 + 3 * 12, 0);
+        let push_stream = |d: &mut Vec<u8>, i: usize, kind: u32, data: &[u8]| {
+            let rva = d.len() as u32;
+            d.extend_from_slice(data);
+            let e = 32 + i * 12;
+            d[e..e + 4].copy_from_slice(&kind.to_le_bytes());
+            d[e + 4..e + 8].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            d[e + 8..e + 12].copy_from_slice(&rva.to_le_bytes());
+        };
+        // module name string
+        let name_rva = d.len() as u32 + 4 + 108;
+        let mut modules = 1u32.to_le_bytes().to_vec();
+        let mut module = vec![0u8; 108];
+        module[0..8].copy_from_slice(&0x1000u64.to_le_bytes());
+        module[8..12].copy_from_slice(&0x1000u32.to_le_bytes());
+        module[20..24].copy_from_slice(&name_rva.to_le_bytes());
+        modules.extend(module);
+        let name: Vec<u8> = "C:\\app\\crashy.dll"
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        modules.extend((name.len() as u32).to_le_bytes());
+        modules.extend(name);
+        push_stream(&mut d, 0, 4, &modules);
+        let mut exception = vec![0u8; 32];
+        exception[0..4].copy_from_slice(&7u32.to_le_bytes());
+        exception[8..12].copy_from_slice(&0xc0000005u32.to_le_bytes());
+        exception[24..32].copy_from_slice(&0x1234u64.to_le_bytes());
+        push_stream(&mut d, 1, 6, &exception);
+        push_stream(&mut d, 2, 0x4767_0007, b"HOME=/root\0SECRET_FLAG=1\0");
+        d
+    }
+
+    #[test]
+    fn minidump() -> Result<()> {
+        let mut out = Vec::new();
+        dump_crash(&mut Cursor::new(minidump_fixture()), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "module: C:\\app\\crashy.dll base=0x1000 size=0x1000
+exception: code 0xc0000005 at crashy.dll+0x234 in thread 7
+env: HOME=/root
+env: SECRET_FLAG=1
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn truncated() {
+        let d = minidump_fixture();
+        assert!(dump_crash(&mut Cursor::new(&d[..60]), &mut Vec::new()).is_err());
+    }
+
+    fn note(kind: u32, desc: &[u8]) -> Vec<u8> {
+        let mut n = 5u32.to_le_bytes().to_vec();
+        n.extend((desc.len() as u32).to_le_bytes());
+        n.extend(kind.to_le_bytes());
+        n.extend(b"CORE\0\0\0\0");
+        n.extend(desc);
+        n.resize((n.len() + 3) & !3, 0);
+        n
+    }
+
+    /// an x86_64 core with the mapped executable, one thread and its stack
+    fn core_fixture(page_size: u64) -> Vec<u8> {
+        let words = |w: &[u64]| -> Vec<u8> { w.iter().flat_map(|w| w.to_le_bytes()).collect() };
+        let mut file = words(&[1, page_size, 0x400000, 0x401000, 2]);
+        file.extend(b"/usr/bin/app\0");
+        let mut prstatus = vec![0u8; 336];
+        prstatus[0..4].copy_from_slice(&11u32.to_le_bytes());
+        prstatus[32..36].copy_from_slice(&42u32.to_le_bytes());
+        prstatus[240..248].copy_from_slice(&0x400123u64.to_le_bytes()); // rip
+        prstatus[264..272].copy_from_slice(&0x7ff000u64.to_le_bytes()); // rsp
+        let mut notes = note(NT_FILE, &file);
+        notes.extend(note(NT_PRSTATUS, &prstatus));
+        notes.extend(note(NT_AUXV, &words(&[AT_EXECFN, 0x7ff020, 0, 0])));
+        let mut stack = words(&[0x400456]);
+        stack.extend(b"HOME=/root\0");
+        stack.resize(0x20, 0);
+        stack.extend(b"/usr/bin/app\0");
+
+        let mut d = vec![0u8; 64];
+        d[0..4].copy_from_slice(ELF_MAGIC);
+        d[4] = 2; // 64 bit
+        d[5] = 1; // little endian
+        d[16..18].copy_from_slice(&ET_CORE.to_le_bytes());
+        d[18..20].copy_from_slice(&62u16.to_le_bytes());
+        d[32..40].copy_from_slice(&64u64.to_le_bytes());
+        d[54..56].copy_from_slice(&56u16.to_le_bytes());
+        d[56..58].copy_from_slice(&2u16.to_le_bytes());
+        let notes_off = 64 + 2 * 56;
+        let stack_off = notes_off + notes.len();
+        for (kind, offset, vaddr, len) in [
+            (PT_NOTE, notes_off, 0, notes.len()),
+            (PT_LOAD, stack_off, 0x7ff000, stack.len()),
+        ] {
+            let mut ph = vec![0u8; 56];
+            ph[0..4].copy_from_slice(&kind.to_le_bytes());
+            ph[8..16].copy_from_slice(&(offset as u64).to_le_bytes());
+            ph[16..24].copy_from_slice(&(vaddr as u64).to_le_bytes());
+            ph[32..40].copy_from_slice(&(len as u64).to_le_bytes());
+            d.extend(ph);
+        }
+        d.extend(notes);
+        d.extend(stack);
+        d
+    }
+
+    #[test]
+    fn elf_core() -> Result<()> {
+        let mut out = Vec::new();
+        dump_crash(&mut Cursor::new(core_fixture(0x1000)), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "module: /usr/bin/app base=0x400000
+thread 42: signal 11
+thread 42: pc app+0x2123
+thread 42: stack ref app+0x2456
+env: HOME=/root
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn overflow() {
+        // the file offset of the mapping is 2 * page_size
+        let d = core_fixture(u64::MAX);
+        assert!(dump_crash(&mut Cursor::new(d), &mut Vec::new()).is_err());
+        let mut d = minidump_fixture();
+        // a stream directory larger than any dump
+        d[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(dump_crash(&mut Cursor::new(d), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn env_vars() {
+        assert!(is_env_var(b"PATH=/bin"));
+        assert!(is_env_var(b"_X="));
+        assert!(!is_env_var(b"=foo"));
+        assert!(!is_env_var(b"/usr/bin/foo"));
+    }
+
+    #[tokio::test]
+    async fn adapt() -> Result<()> {
+        let adapter = CrashdumpAdapter::new();
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("crash.dmp");
+        std::fs::write(&fname, minidump_fixture())?;
+        let (a, d) = simple_fs_adapt_info(&fname).await?;
+        let r = adapter.adapt(a, &d).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert!(o.contains("exception: code 0xc0000005 at crashy.dll+0x234"));
+        // the environment strings are not cached
+        assert!(adapter.metadata().sensitive);
+        Ok(())
+    }
+}
@@ -4,8 +4,11 @@ use crate::adapted_iter::one_file;
 
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
+    config::RgaConfig,
     expand::expand_str_ez,
+    install::MissingProgram,
     matching::{FastFileMatcher, FileMatcher},
+    policy,
 };
 use crate::{join_handle_to_stream, to_io_err};
 use anyhow::Result;
@@ -16,10 +19,10 @@ use log::debug;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
-use std::process::Stdio;
-use tokio::io::AsyncReadExt;
-use tokio::process::Child;
+use std::process::{ExitStatus, Stdio};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::process::Command;
+use tokio::process::{Child, ChildStderr};
 
 use tokio_util::io::StreamReader;
 // mostly the same as AdapterMeta + SpawningFileAdapter
@@ -31,7 +34,8 @@ pub struct CustomAdapterConfig {
     pub description: String,
     /// if true, the adapter will be disabled by default
     pub disabled_by_default: Option<bool>,
-    /// version identifier. used to key cache entries, change if the configuration or program changes
+    /// version identifier. used to key cache entries, change if the program changes. Changes to the
+    /// configuration are picked up without it
     pub version: i32,
     /// the file extensions this adapter supports. For example ["epub", "mobi"]
     pub extensions: Vec<String>,
@@ -45,7 +49,16 @@ pub struct CustomAdapterConfig {
     /// - $input_file_extension: the file extension (without dot). e.g. foo.tar.gz -> gz
     /// - $input_file_stem, the file name without the last extension. e.g. foo.tar.gz -> foo.tar
     /// - $input_virtual_path: the full input file path. Note that this path may not actually exist on disk because it is the result of another adapter
-    /// stdin of the program will be connected to the input file, and stdout is assumed to be the converted file
+    /// - $input_file_name: the file name without the directories. e.g. a/foo.tar.gz -> foo.tar.gz
+    /// - $input_path: the path of the file on disk, only with "input": "path" or "tempfile"
+    /// - $tmpdir: an empty temporary directory, deleted once the output was read
+    /// - $ocr_languages: the languages of --rga-ocr-languages joined with +, the way tesseract takes them. e.g. eng+deu
+    ///
+    /// ${name:+text} is text if the placeholder is not empty, ${name:-text} is text if it is empty. text can contain
+    /// placeholders without braces, e.g. "${ocr_languages:+-l $ocr_languages}". An argument that is only such a conditional
+    /// is left out if it is empty
+    ///
+    /// stdin of the program will be connected to the input file (see `.input`), and stdout is assumed to be the converted file
     pub args: Vec<String>,
     /// The output path hint. The placeholders are the same as for `.args`
     ///
@@ -53,8 +66,65 @@ pub struct CustomAdapterConfig {
     ///
     /// Setting this is useful if the output format is not plain text (.txt) but instead some other format that should be passed to another adapter
     pub output_path_hint: Option<String>,
+    /// if true, the output contains secrets (e.g. the program decrypts the file).
+    /// The output is marked as decrypted and never cached unless --rga-cache-sensitive is given
+    pub sensitive: Option<bool>,
+    /// if true, the program uses the network, e.g. to send the file to a server. Forbidden by --rga-policy-no-network
+    pub network: Option<bool>,
+    /// what to do with the messages the program writes to stderr. If not set, they are shown in the terminal
+    pub stderr: Option<StderrMode>,
+    /// what exit codes other than 0 mean. Other exit codes are errors
+    pub exit_codes: Option<ExitCodes>,
+    /// how the program gets the file. If not set, it is written to stdin
+    pub input: Option<InputMode>,
+}
+
+/// how the program of a custom adapter gets the file. Members of archives are written to a temporary
+/// file for tempfile and path, files on disk are used as they are
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum InputMode {
+    /// the file is written to stdin
+    #[default]
+    Stdin,
+    /// stdin is the file itself, for programs that seek in their input
+    Tempfile,
+    /// the program opens $input_path itself, stdin is empty
+    Path,
+}
+
+/// the meaning of exit codes of the program of a custom adapter, e.g. {"ok": [1]} for a program that
+/// exits with 1 after warnings. With empty or fallback, the output is only used once the program has
+/// exited, and with fallback the file is also kept in memory
+#[derive(Debug, Deserialize, Serialize, JsonSchema, Default, PartialEq, Eq, Clone)]
+pub struct ExitCodes {
+    /// the program succeeded, like with 0
+    #[serde(default)]
+    pub ok: Vec<i32>,
+    /// the file has no text, the output of the program is left out
+    #[serde(default)]
+    pub empty: Vec<i32>,
+    /// the program can't convert the file, it is searched as it is instead
+    #[serde(default)]
+    pub fallback: Vec<i32>,
 }
 
+/// what happens to the stderr of the program of a custom adapter. Of log and inline, only the first
+/// 64 KiB are kept, the rest is read and dropped
+#[derive(Debug, Deserialize, Serialize, JsonSchema, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum StderrMode {
+    /// drop the messages
+    Discard,
+    /// write the messages to the debug log (--debug)
+    Log,
+    /// add the messages to the end of the output, so they are searched like it
+    Inline,
+}
+
+/// the stderr of a program that is kept for --debug or the output, per file
+const STDERR_MAX_LEN: u64 = 64 * 1024;
+
 fn strs(arr: &[&str]) -> Vec<String> {
     arr.iter().map(ToString::to_string).collect()
 }
@@ -99,8 +169,9 @@ lazy_static! {
         CustomAdapterConfig {
             name: "pandoc".to_string(),
             description: "Uses pandoc to convert binary/unreadable text documents to plain markdown-like text".to_string(),
-            version: 3,
-            extensions: strs(&["epub", "odt", "docx", "fb2", "ipynb"]),
+            version: 5,
+            // docx, odt and epub are read by the ooxml, opendocument and epub adapters, which fall back to pandoc, and fb2 by the ebook adapter
+            extensions: strs(&["ipynb"]),
             binary: "pandoc".to_string(),
             mimetypes: None,
             // simpler markown (with more information loss but plainer text)
@@ -113,22 +184,111 @@ lazy_static! {
             ]),
             disabled_by_default: None,
             match_only_by_mime: None,
-            output_path_hint: None
+            output_path_hint: None,
+            sensitive: None,
+            network: None,
+            stderr: None,
+            exit_codes: None,
+            input: None
         },
         CustomAdapterConfig {
-            name: "poppler".to_owned(),
+            name: "ansiblevault".to_owned(),
             version: 1,
-            description: "Uses pdftotext (from poppler-utils) to extract plain text from PDF files"
+            description: "Uses ansible-vault to decrypt Ansible Vault files. The password is read as configured for ansible, e.g. from ANSIBLE_VAULT_PASSWORD_FILE"
                 .to_owned(),
-
-            extensions: strs(&["pdf"]),
-            mimetypes: Some(strs(&["application/pdf"])),
-
-            binary: "pdftotext".to_string(),
-            args: strs(&["-", "-"]),
-            disabled_by_default: None,
+            extensions: strs(&["vault", "vault.yml", "vault.yaml"]),
+            mimetypes: None,
+            binary: "ansible-vault".to_string(),
+            args: strs(&["decrypt", "--output", "-", "-"]),
+            disabled_by_default: Some(true),
             match_only_by_mime: None,
-            output_path_hint: Some("${input_virtual_path}.txt.asciipagebreaks".into())
+            output_path_hint: None,
+            sensitive: Some(true),
+            network: None,
+            stderr: None,
+            exit_codes: None,
+            input: None
+        },
+        CustomAdapterConfig {
+            name: "sops".to_owned(),
+            version: 1,
+            description: "Uses sops to decrypt SOPS-encrypted YAML files. Keys are found as configured for sops, e.g. SOPS_AGE_KEY_FILE, gpg-agent or cloud KMS credentials"
+                .to_owned(),
+            extensions: strs(&["sops.yaml", "sops.yml", "enc.yaml", "enc.yml"]),
+            mimetypes: None,
+            binary: "sops".to_string(),
+            args: strs(&[
+                "--decrypt",
+                "--input-type",
+                "yaml",
+                "--output-type",
+                "yaml",
+                "/dev/stdin"
+            ]),
+            disabled_by_default: Some(true),
+            match_only_by_mime: None,
+            output_path_hint: None,
+            sensitive: Some(true),
+            network: None,
+            stderr: None,
+            exit_codes: None,
+            input: None
+        },
+        CustomAdapterConfig {
+            name: "tika".to_owned(),
+            version: 1,
+            description: "Uploads documents to an Apache Tika server on localhost:9998 with curl and returns the extracted text, for the long tail of formats no other adapter handles. Start the server with e.g. `docker run -p 9998:9998 apache/tika`"
+                .to_owned(),
+            extensions: strs(&[
+                "doc", "dot", "xls", "xlsx", "xlsb", "ppt", "pps", "rtf", "odp", "ods", "pages",
+                "key", "numbers", "vsd", "vsdx", "pub", "msg", "wpd", "wps", "xps", "chm"
+            ]),
+            mimetypes: None,
+            binary: "curl".to_string(),
+            // the file name helps tika detect the type. -T - streams stdin as the request body
+            args: strs(&[
+                "--silent",
+                "--show-error",
+                "--fail",
+                "-T",
+                "-",
+                "-H",
+                "Accept: text/plain",
+                "-H",
+                "Content-Disposition: attachment; filename=${input_file_stem}.${input_file_extension}",
+                "http://localhost:9998/tika"
+            ]),
+            disabled_by_default: Some(true),
+            match_only_by_mime: None,
+            output_path_hint: None,
+            sensitive: None,
+            network: Some(true),
+            stderr: None,
+            exit_codes: None,
+            input: None
+        },
+        CustomAdapterConfig {
+            name: "libreoffice".to_owned(),
+            version: 1,
+            description: "Converts office documents to PDF with unoconvert, which uses the LibreOffice instance of a running unoserver instead of starting soffice for every file. Start it with `unoserver`"
+                .to_owned(),
+            extensions: strs(&[
+                "doc", "dot", "rtf", "wpd", "wps", "xls", "xlsx", "xlsb", "ppt", "pps", "odp",
+                "ods", "odg", "vsd", "vsdx", "pub", "sxw", "sxc", "sxi"
+            ]),
+            mimetypes: None,
+            binary: "unoconvert".to_string(),
+            // LibreOffice can write pdf for all kinds of documents, which the pdf adapter then reads
+            args: strs(&["--convert-to", "pdf", "-", "-"]),
+            disabled_by_default: Some(true),
+            match_only_by_mime: None,
+            output_path_hint: Some("${input_virtual_path}.pdf".to_owned()),
+            sensitive: None,
+            // unoconvert talks to unoserver over XML-RPC
+            network: Some(true),
+            stderr: None,
+            exit_codes: None,
+            input: None
         }
     ];
 }
@@ -138,15 +298,22 @@ lazy_static! {
 pub fn map_exe_error(err: std::io::Error, exe_name: &str, help: &str) -> anyhow::Error {
     use std::io::ErrorKind::*;
     match err.kind() {
-        NotFound => format_err!("Could not find executable \"{}\". {}", exe_name, help),
+        NotFound => anyhow::Error::new(MissingProgram {
+            program: exe_name.to_owned(),
+            help: help.to_owned(),
+        }),
         _ => anyhow::Error::from(err),
     }
 }
 
-fn proc_wait(mut child: Child, context: impl FnOnce() -> String) -> impl AsyncRead {
+fn is_ok(status: ExitStatus, ok: &[i32]) -> bool {
+    status.success() || status.code().map_or(false, |code| ok.contains(&code))
+}
+
+fn proc_wait(mut child: Child, ok: Vec<i32>, context: impl FnOnce() -> String) -> impl AsyncRead {
     let s = stream! {
         let res = child.wait().await?;
-        if res.success() {
+        if is_ok(res, &ok) {
             yield std::io::Result::Ok(Bytes::new());
         } else {
             Err(format_err!("{:?}", res)).with_context(context).map_err(to_io_err)?;
@@ -156,29 +323,106 @@ fn proc_wait(mut child: Child, context: impl FnOnce() -> String) -> impl AsyncRe
 }
 
 pub fn pipe_output(
+    line_prefix: &str,
+    cmd: Command,
+    inp: ReadBox,
+    exe_name: &str,
+    help: &str,
+) -> Result<ReadBox> {
+    pipe_output_stderr(line_prefix, cmd, Some(inp), exe_name, help, None, vec![])
+}
+
+/// the first STDERR_MAX_LEN bytes of stderr. The rest is read too, the program would block on a full pipe
+async fn read_stderr(mut stderr: ChildStderr) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    (&mut stderr)
+        .take(STDERR_MAX_LEN)
+        .read_to_end(&mut data)
+        .await?;
+    if tokio::io::copy(&mut stderr, &mut tokio::io::sink()).await? > 0 {
+        data.extend_from_slice(b"\n[rga: stderr truncated]\n");
+    }
+    Ok(data)
+}
+
+/// the messages of a program: logged, or returned to be added to the output
+fn stderr_output(exe_name: &str, data: &[u8], stderr: Option<StderrMode>) -> Option<Bytes> {
+    let text = String::from_utf8_lossy(data);
+    if stderr == Some(StderrMode::Inline) {
+        if !text.trim().is_empty() {
+            return Some(Bytes::from(format!(
+                "[rga: stderr of {exe_name}]\n{}\n",
+                text.trim_end()
+            )));
+        }
+    } else {
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            debug!("{}: {}", exe_name, line);
+        }
+    }
+    None
+}
+
+fn set_stderr(cmd: &mut Command, stderr: Option<StderrMode>) {
+    match stderr {
+        None => {}
+        Some(StderrMode::Discard) => {
+            cmd.stderr(Stdio::null());
+        }
+        Some(StderrMode::Log | StderrMode::Inline) => {
+            cmd.stderr(Stdio::piped());
+        }
+    }
+}
+
+/// like pipe_output, with stderr handled by `stderr`, or shown in the terminal, and the exit codes
+/// `ok` accepted like 0. `inp` is None if the stdin of `cmd` is set already
+fn pipe_output_stderr(
     _line_prefix: &str,
     mut cmd: Command,
-    inp: ReadBox,
+    inp: Option<ReadBox>,
     exe_name: &str,
     help: &str,
+    stderr: Option<StderrMode>,
+    ok: Vec<i32>,
 ) -> Result<ReadBox> {
     let cmd_log = format!("{:?}", cmd); // todo: perf
+    set_stderr(&mut cmd, stderr);
+    if inp.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
     let mut cmd = cmd
-        .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .spawn()
         .map_err(|e| map_exe_error(e, exe_name, help))?;
-    let mut stdi = cmd.stdin.take().expect("is piped");
+    let stdi = cmd.stdin.take();
     let stdo = cmd.stdout.take().expect("is piped");
+    let stde = cmd.stderr.take().map(read_stderr).map(tokio::spawn);
 
     let join = tokio::spawn(async move {
-        let mut z = inp;
-        tokio::io::copy(&mut z, &mut stdi).await?;
+        if let (Some(mut z), Some(mut stdi)) = (inp, stdi) {
+            tokio::io::copy(&mut z, &mut stdi).await?;
+        }
         std::io::Result::Ok(())
     });
-    Ok(Box::pin(stdo.chain(
-        proc_wait(cmd, move || format!("subprocess: {cmd_log}")).chain(join_handle_to_stream(join)),
-    )))
+    let exe_name = exe_name.to_owned();
+    let s = stream! {
+        if let Some(stde) = stde {
+            let data = stde
+                .await
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))??;
+            if let Some(text) = stderr_output(&exe_name, &data, stderr) {
+                yield std::io::Result::Ok(text);
+            }
+        }
+    };
+    Ok(Box::pin(
+        stdo.chain(
+            proc_wait(cmd, ok, move || format!("subprocess: {cmd_log}"))
+                .chain(join_handle_to_stream(join)),
+        )
+        .chain(StreamReader::new(s)),
+    ))
 }
 
 pub struct CustomSpawningFileAdapter {
@@ -186,38 +430,169 @@ pub struct CustomSpawningFileAdapter {
     args: Vec<String>,
     meta: AdapterMeta,
     output_path_hint: Option<String>,
+    network: bool,
+    stderr: Option<StderrMode>,
+    exit_codes: ExitCodes,
+    input: InputMode,
 }
 impl GetMetadata for CustomSpawningFileAdapter {
     fn metadata(&self) -> &AdapterMeta {
         &self.meta
     }
 }
-fn arg_replacer(arg: &str, filepath_hint: &Path) -> Result<String> {
-    expand_str_ez(arg, |s| match s {
-        "input_virtual_path" => Ok(filepath_hint.to_string_lossy()),
-        "input_file_stem" => Ok(filepath_hint
+/// the values of the placeholders of the args, see [`CustomAdapterConfig::args`]
+struct Placeholders<'a> {
+    filepath_hint: &'a Path,
+    config: &'a RgaConfig,
+    tmpdir: Option<&'a Path>,
+    /// the file on disk, if the program is given one
+    input_path: Option<&'a Path>,
+}
+fn placeholder<'a>(name: &str, values: &Placeholders<'a>) -> Result<Cow<'a, str>> {
+    let filepath_hint = values.filepath_hint;
+    Ok(match name {
+        "input_virtual_path" => filepath_hint.to_string_lossy(),
+        "input_file_stem" => filepath_hint
             .file_stem()
             .unwrap_or_default()
-            .to_string_lossy()),
-        "input_file_extension" => Ok(filepath_hint
+            .to_string_lossy(),
+        "input_file_extension" => filepath_hint
             .extension()
             .unwrap_or_default()
-            .to_string_lossy()),
-        e => Err(anyhow::format_err!("unknown replacer ${{{e}}}")),
+            .to_string_lossy(),
+        "input_file_name" => filepath_hint
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy(),
+        "tmpdir" => match values.tmpdir {
+            Some(tmpdir) => tmpdir.to_string_lossy(),
+            None => Err(anyhow::format_err!("$tmpdir can only be used in args"))?,
+        },
+        "input_path" => match values.input_path {
+            Some(path) => path.to_string_lossy(),
+            None => Err(anyhow::format_err!(
+                "$input_path needs \"input\": \"path\" or \"tempfile\""
+            ))?,
+        },
+        "ocr_languages" => Cow::Owned(values.config.ocr_languages.join("+")),
+        e => Err(anyhow::format_err!("unknown replacer ${{{e}}}"))?,
+    })
+}
+fn arg_replacer(arg: &str, values: &Placeholders) -> Result<String> {
+    expand_str_ez(arg, |s| {
+        if let Some((name, text)) = s.split_once(":+") {
+            return Ok(if placeholder(name, values)?.is_empty() {
+                Cow::Borrowed("")
+            } else {
+                Cow::Owned(arg_replacer(text, values)?)
+            });
+        }
+        if let Some((name, text)) = s.split_once(":-") {
+            let value = placeholder(name, values)?;
+            return Ok(if value.is_empty() {
+                Cow::Owned(arg_replacer(text, values)?)
+            } else {
+                value
+            });
+        }
+        placeholder(s, values)
+    })
+}
+/// an argument like ${name:+text}, left out if it is empty
+fn is_conditional(arg: &str) -> bool {
+    arg.starts_with("${")
+        && arg.ends_with('}')
+        && arg[2..].find('}') == Some(arg.len() - 3)
+        && (arg.contains(":+") || arg.contains(":-"))
+}
+/// the result of a program that was run to the end
+struct Finished {
+    status: ExitStatus,
+    output: Vec<u8>,
+    stderr: Vec<u8>,
+    /// the input, if it was kept
+    input: Option<Vec<u8>>,
+}
+
+/// run the program to the end, for exit codes that decide what happens with the output. `inp` is
+/// None if the stdin of `cmd` is set already
+async fn run_to_end(
+    mut cmd: Command,
+    inp: Option<ReadBox>,
+    exe_name: &str,
+    stderr: Option<StderrMode>,
+    keep_input: bool,
+) -> Result<Finished> {
+    set_stderr(&mut cmd, stderr);
+    if inp.is_some() {
+        cmd.stdin(Stdio::piped());
+    }
+    let mut child = cmd
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| map_exe_error(e, exe_name, ""))?;
+    let stdin = child.stdin.take();
+    let mut stdout = child.stdout.take().expect("is piped");
+    let stde = child.stderr.take();
+    let write = async move {
+        let (mut inp, mut stdin) = match (inp, stdin) {
+            (Some(inp), Some(stdin)) => (inp, stdin),
+            _ => return std::io::Result::Ok(None),
+        };
+        let mut input = Vec::new();
+        let res = if keep_input {
+            inp.read_to_end(&mut input).await?;
+            stdin.write_all(&input).await
+        } else {
+            tokio::io::copy(&mut inp, &mut stdin).await.map(|_| ())
+        };
+        match res {
+            Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => Err(e),
+            // or the program exited without reading all of the input
+            _ => std::io::Result::Ok(keep_input.then_some(input)),
+        }
+    };
+    let read = async move {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).await?;
+        std::io::Result::Ok(output)
+    };
+    let errors = async move {
+        match stde {
+            Some(stde) => read_stderr(stde).await,
+            None => Ok(Vec::new()),
+        }
+    };
+    let (input, output, stderr) = tokio::try_join!(write, read, errors)?;
+    Ok(Finished {
+        status: child.wait().await?,
+        output,
+        stderr,
+        input,
     })
 }
+
+/// an empty reader that deletes the temporary directory of the program once its output was read
+fn remove_on_end(dir: tempfile::TempDir) -> impl AsyncRead {
+    let s = stream! {
+        drop(dir);
+        yield std::io::Result::Ok(Bytes::new());
+    };
+    StreamReader::new(s)
+}
 impl CustomSpawningFileAdapter {
     fn command(
         &self,
-        filepath_hint: &std::path::Path,
+        values: &Placeholders,
         mut command: tokio::process::Command,
     ) -> Result<tokio::process::Command> {
-        command.args(
-            self.args
-                .iter()
-                .map(|arg| arg_replacer(arg, filepath_hint))
-                .collect::<Result<Vec<_>>>()?,
-        );
+        for arg in &self.args {
+            let value = arg_replacer(arg, values)?;
+            if value.is_empty() && is_conditional(arg) {
+                continue;
+            }
+            command.arg(value);
+        }
         log::debug!("running command {:?}", command);
         Ok(command)
     }
@@ -239,19 +614,122 @@ impl FileAdapter for CustomSpawningFileAdapter {
             ..
         } = ai;
 
-        let cmd = Command::new(&self.binary);
-        let cmd = self
-            .command(&filepath_hint, cmd)
+        if self.network {
+            policy::check_network(&config.policy, &self.meta.name)?;
+        }
+        let tmpdir = if self.args.iter().any(|arg| arg.contains("tmpdir")) {
+            Some(tempfile::tempdir().context("creating temporary directory")?)
+        } else {
+            None
+        };
+        let values = Placeholders {
+            filepath_hint: &filepath_hint,
+            config: &config,
+            tmpdir: tmpdir.as_ref().map(|dir| dir.path()),
+            // provide_input spooled members of archives to a file
+            input_path: (self.input != InputMode::Stdin).then_some(filepath_hint.as_path()),
+        };
+        let cmd = policy::command(&config.policy, &self.binary, &filepath_hint)?;
+        let mut cmd = self
+            .command(&values, cmd)
             .with_context(|| format!("Could not set cmd arguments for {}", self.binary))?;
+        let output_path = arg_replacer(
+            self.output_path_hint
+                .as_deref()
+                .unwrap_or("${input_virtual_path}.txt"),
+            &Placeholders {
+                tmpdir: None,
+                ..values
+            },
+        )?;
         debug!("executing {:?}", cmd);
-        let output = pipe_output(&line_prefix, cmd, inp, &self.binary, "")?;
+        let inp = match self.input {
+            InputMode::Stdin => Some(inp),
+            InputMode::Tempfile => {
+                let file = std::fs::File::open(&filepath_hint)
+                    .with_context(|| format!("opening {}", filepath_hint.display()))?;
+                cmd.stdin(file);
+                None
+            }
+            InputMode::Path => {
+                cmd.stdin(Stdio::null());
+                None
+            }
+        };
+        let codes = &self.exit_codes;
+        let output: ReadBox = if codes.empty.is_empty() && codes.fallback.is_empty() {
+            pipe_output_stderr(
+                &line_prefix,
+                cmd,
+                inp,
+                &self.binary,
+                "",
+                self.stderr,
+                codes.ok.clone(),
+            )?
+        } else {
+            let keep_input = !codes.fallback.is_empty();
+            let finished = run_to_end(cmd, inp, &self.binary, self.stderr, keep_input).await?;
+            let code = finished.status.code().unwrap_or(-1);
+            if is_ok(finished.status, &codes.ok) {
+                let stderr = stderr_output(&self.binary, &finished.stderr, self.stderr);
+                Box::pin(
+                    std::io::Cursor::new(finished.output)
+                        .chain(std::io::Cursor::new(stderr.unwrap_or_default())),
+                )
+            } else if codes.empty.contains(&code) {
+                debug!(
+                    "{}: {} exited with {}, no text",
+                    filepath_hint.display(),
+                    self.binary,
+                    code
+                );
+                Box::pin(tokio::io::empty())
+            } else if codes.fallback.contains(&code) {
+                debug!(
+                    "{}: {} exited with {}, searching the file as it is",
+                    filepath_hint.display(),
+                    self.binary,
+                    code
+                );
+                // as text, so it isn't given to this adapter again
+                return Ok(one_file(AdaptInfo {
+                    filepath_hint: PathBuf::from(format!(
+                        "{}.txt",
+                        filepath_hint.to_string_lossy()
+                    )),
+                    inp: match finished.input {
+                        Some(input) => Box::pin(std::io::Cursor::new(input)),
+                        None => Box::pin(tokio::fs::File::open(&filepath_hint).await?),
+                    },
+                    line_prefix,
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config,
+                }));
+            } else {
+                let stderr = String::from_utf8_lossy(&finished.stderr);
+                return Err(format_err!(
+                    "{} failed ({}): {}",
+                    self.binary,
+                    finished.status,
+                    stderr.trim().lines().last().unwrap_or_default()
+                ));
+            }
+        };
+        let output: ReadBox = match tmpdir {
+            Some(dir) => Box::pin(output.chain(remove_on_end(dir))),
+            None => output,
+        };
+        let output: ReadBox = if self.meta.sensitive {
+            let marker = format!("[rga: decrypted with {}]\n", self.binary);
+            Box::pin(std::io::Cursor::new(marker.into_bytes()).chain(output))
+        } else {
+            output
+        };
         Ok(one_file(AdaptInfo {
-            filepath_hint: PathBuf::from(arg_replacer(
-                self.output_path_hint
-                    .as_deref()
-                    .unwrap_or("${input_virtual_path}.txt"),
-                &filepath_hint,
-            )?),
+            filepath_hint: PathBuf::from(output_path),
             inp: output,
             line_prefix,
             is_real_file: false,
@@ -267,6 +745,10 @@ impl CustomAdapterConfig {
             binary: self.binary.clone(),
             args: self.args.clone(),
             output_path_hint: self.output_path_hint.clone(),
+            network: self.network.unwrap_or(false),
+            stderr: self.stderr,
+            exit_codes: self.exit_codes.clone().unwrap_or_default(),
+            input: self.input.unwrap_or_default(),
             meta: AdapterMeta {
                 name: self.name.clone(),
                 version: self.version,
@@ -290,6 +772,12 @@ impl CustomAdapterConfig {
                 }),
                 keep_fast_matchers_if_accurate: !self.match_only_by_mime.unwrap_or(false),
                 disabled_by_default: self.disabled_by_default.unwrap_or(false),
+                sensitive: self.sensitive.unwrap_or(false),
+                input: match self.input.unwrap_or_default() {
+                    InputMode::Stdin => InputKind::Stream,
+                    InputMode::Tempfile => InputKind::Seekable,
+                    InputMode::Path => InputKind::Path,
+                },
             },
         }
     }
@@ -299,37 +787,13 @@ impl CustomAdapterConfig {
 mod test {
     use super::super::FileAdapter;
     use super::*;
-    use crate::preproc::loop_adapt;
     use crate::test_utils::*;
     use anyhow::Result;
     use pretty_assertions::assert_eq;
-    use tokio::fs::File;
-
-    #[tokio::test]
-    async fn poppler() -> Result<()> {
-        let adapter = poppler_adapter();
-
-        let filepath = test_data_dir().join("short.pdf");
-
-        let (a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
-        // let r = adapter.adapt(a, &d)?;
-        let r = loop_adapt(&adapter, d, a).await?;
-        let o = adapted_to_vec(r).await?;
-        assert_eq!(
-            String::from_utf8(o)?,
-            "PREFIX:Page 1: hello world
-PREFIX:Page 1: this is just a test.
-PREFIX:Page 1: 
-PREFIX:Page 1: 1
-PREFIX:Page 1: 
-PREFIX:Page 1: 
-"
-        );
-        Ok(())
-    }
 
     use crate::{
         adapters::custom::CustomAdapterConfig,
+        preproc::{keep_spool, provide_input},
         test_utils::{adapted_to_vec, simple_adapt_info},
     };
     use std::io::Cursor;
@@ -348,6 +812,11 @@ PREFIX:Page 1:
             binary: "sed".to_string(),
             args: vec!["s/e/u/g".to_string()],
             output_path_hint: None,
+            sensitive: None,
+            network: None,
+            stderr: None,
+            exit_codes: None,
+            input: None,
         };
 
         let adapter = adapter.to_adapter();
@@ -373,4 +842,179 @@ PREFIX:Page 1:
         println!("output: {}", String::from_utf8_lossy(&oup));
         Ok(())
     }
+    #[test]
+    fn placeholders() -> Result<()> {
+        let config = RgaConfig::default();
+        let values = Placeholders {
+            filepath_hint: Path::new("scans/page 1.png"),
+            config: &config,
+            tmpdir: Some(Path::new("/tmp/x")),
+            input_path: None,
+        };
+        let expand = |arg: &str| arg_replacer(arg, &values).unwrap();
+        assert_eq!(expand("$input_file_name"), "page 1.png");
+        assert_eq!(expand("${tmpdir}/out.txt"), "/tmp/x/out.txt");
+        assert_eq!(expand("${ocr_languages:+-l $ocr_languages}"), "");
+        assert_eq!(expand("--lang=${ocr_languages:-eng}"), "--lang=eng");
+        assert!(arg_replacer("$unknown", &values).is_err());
+        assert!(arg_replacer("$input_path", &values).is_err());
+        let config = RgaConfig {
+            ocr_languages: vec!["eng".to_owned(), "deu".to_owned()],
+            ..Default::default()
+        };
+        let values = Placeholders {
+            config: &config,
+            ..values
+        };
+        assert_eq!(
+            arg_replacer("${ocr_languages:+-l $ocr_languages}", &values)?,
+            "-l eng+deu"
+        );
+        assert!(is_conditional("${ocr_languages:+-l}"));
+        assert!(!is_conditional("--lang=${ocr_languages:-eng}"));
+        assert!(!is_conditional("${a:+x}${b}"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stderr() -> anyhow::Result<()> {
+        let adapter = |stderr| {
+            CustomAdapterConfig {
+                name: "noisy".to_owned(),
+                binary: "sh".to_owned(),
+                args: strs(&["-c", "cat; echo warning >&2"]),
+                stderr,
+                ..Default::default()
+            }
+            .to_adapter()
+        };
+        for (stderr, expected) in [
+            (StderrMode::Discard, "text\n"),
+            (StderrMode::Log, "text\n"),
+            (StderrMode::Inline, "text\n[rga: stderr of sh]\nwarning\n"),
+        ] {
+            let (a, d) = simple_adapt_info(
+                Path::new("foo.txt"),
+                Box::pin(Cursor::new(Vec::from("text\n"))),
+            );
+            let output = adapter(Some(stderr)).adapt(a, &d).await?;
+            assert_eq!(String::from_utf8(adapted_to_vec(output).await?)?, expected);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn exit_codes() -> anyhow::Result<()> {
+        let run = |script: &str| {
+            let adapter = CustomAdapterConfig {
+                name: "picky".to_owned(),
+                binary: "sh".to_owned(),
+                args: strs(&["-c", script]),
+                exit_codes: Some(ExitCodes {
+                    ok: vec![1],
+                    empty: vec![2],
+                    fallback: vec![3],
+                }),
+                ..Default::default()
+            }
+            .to_adapter();
+            async move {
+                let (a, d) = simple_adapt_info(
+                    Path::new("foo.txt"),
+                    Box::pin(Cursor::new(Vec::from("text\n"))),
+                );
+                let output = adapter.adapt(a, &d).await?;
+                String::from_utf8(adapted_to_vec(output).await?).map_err(anyhow::Error::from)
+            }
+        };
+        assert_eq!(run("sed s/t/T/g; exit 1").await?, "TexT\n");
+        assert_eq!(run("sed s/t/T/g; exit 2").await?, "");
+        assert_eq!(run("echo converted; exit 3").await?, "text\n");
+        assert!(run("sed s/t/T/g; exit 4").await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn input_modes() -> anyhow::Result<()> {
+        // a member of an archive, which is spooled to a temporary file for tempfile and path
+        let run = |input: InputMode, args: &[&str]| {
+            let adapter = CustomAdapterConfig {
+                name: "reader".to_owned(),
+                binary: "sh".to_owned(),
+                args: strs(args),
+                input: Some(input),
+                ..Default::default()
+            }
+            .to_adapter();
+            async move {
+                let (a, d) = simple_adapt_info_full(
+                    Path::new("dir/notes.txt"),
+                    Box::pin(Cursor::new(Vec::from("some notes\n"))),
+                    false,
+                );
+                let (a, spool) = provide_input(&adapter, a).await?;
+                let output = keep_spool(adapter.adapt(a, &d).await?, spool);
+                String::from_utf8(adapted_to_vec(output).await?).map_err(anyhow::Error::from)
+            }
+        };
+        assert_eq!(
+            run(InputMode::Stdin, &["-c", "tr a-z A-Z"]).await?,
+            "SOME NOTES\n"
+        );
+        // stdin is a file that can be seeked in
+        assert_eq!(
+            run(
+                InputMode::Tempfile,
+                &["-c", "test -f /dev/stdin && tail -c 6"]
+            )
+            .await?,
+            "notes\n"
+        );
+        assert_eq!(
+            run(
+                InputMode::Path,
+                &["-c", "basename \"$0\"; cat \"$0\"", "$input_path"]
+            )
+            .await?,
+            "notes.txt\nsome notes\n"
+        );
+        assert!(run(InputMode::Stdin, &["-c", "cat", "$input_path"])
+            .await
+            .is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sensitive_marker() -> anyhow::Result<()> {
+        let adapter = CustomAdapterConfig {
+            name: "fakedecrypt".to_string(),
+            description: "".to_string(),
+            disabled_by_default: None,
+            version: 1,
+            extensions: vec!["secret".to_string()],
+            mimetypes: None,
+            match_only_by_mime: None,
+            binary: "cat".to_string(),
+            args: vec![],
+            output_path_hint: None,
+            sensitive: Some(true),
+            network: None,
+            stderr: None,
+            exit_codes: None,
+            input: None,
+        };
+        let adapter = adapter.to_adapter();
+        assert!(adapter.metadata().sensitive);
+        let (a, d) = simple_adapt_info(
+            Path::new("foo.secret"),
+            Box::pin(Cursor::new(Vec::from("password: hunter2\n"))),
+        );
+        let output = adapter.adapt(a, &d).await?;
+        let oup = adapted_to_vec(output).await?;
+        assert_eq!(
+            String::from_utf8(oup)?,
+            "[rga: decrypted with cat]\npassword: hunter2\n"
+        );
+        Ok(())
+    }
 }
@@ -5,22 +5,30 @@ use super::*;
 use anyhow::Result;
 use lazy_static::lazy_static;
 use tokio::io::BufReader;
+use tokio_util::io::SyncIoBridge;
 
+use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 
-static EXTENSIONS: &[&str] = &["tgz", "tbz", "tbz2", "gz", "bz2", "xz", "zst"];
+static EXTENSIONS: &[&str] = &[
+    "tgz", "tbz", "tbz2", "txz", "tzst", "gz", "bz2", "xz", "zst", "lz4", "br",
+];
 static MIME_TYPES: &[&str] = &[
     "application/gzip",
     "application/x-bzip",
     "application/x-xz",
     "application/zstd",
+    "application/x-lz4",
 ];
+
+/// the buffer between the lz4 decoder and the adapter of the contents
+const PIPE_LEN: usize = 64 * 1024;
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "decompress".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Reads compressed file as a stream and runs a different extractor on the contents."
+            "Reads compressed file (gzip, bzip2, xz, zstd, lz4 or brotli) as a stream and runs a different extractor on the contents."
                 .to_owned(),
         recurses: true,
         fast_matchers: EXTENSIONS
@@ -34,6 +42,8 @@ lazy_static! {
                 .collect()
         ),
         disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream,
         keep_fast_matchers_if_accurate: true
     };
 }
@@ -51,6 +61,24 @@ impl GetMetadata for DecompressAdapter {
     }
 }
 
+/// lz4 frames, decoded on a blocking thread since there is no async decoder
+fn lz4(inp: ReadBox) -> ReadBox {
+    let (out, decoded) = tokio::io::duplex(PIPE_LEN);
+    let inp = SyncIoBridge::new(inp);
+    let mut out = SyncIoBridge::new(out);
+    tokio::task::spawn_blocking(move || {
+        let mut decoder = lz4_flex::frame::FrameDecoder::new(inp);
+        match std::io::copy(&mut decoder, &mut out) {
+            Ok(_) => {}
+            // the contents were not read to the end
+            Err(e) if e.kind() == ErrorKind::BrokenPipe => {}
+            // the reader gets an early end of the contents
+            Err(e) => log::warn!("decoding lz4: {}", e),
+        }
+    });
+    Box::pin(decoded)
+}
+
 fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
     use async_compression::tokio::bufread;
     use FastFileMatcher::*;
@@ -59,20 +87,25 @@ fn decompress_any(reason: &FileMatcher, inp: ReadBox) -> Result<ReadBox> {
     let bz2 = |inp: ReadBox| Box::pin(bufread::BzDecoder::new(BufReader::new(inp)));
     let xz = |inp: ReadBox| Box::pin(bufread::XzDecoder::new(BufReader::new(inp)));
     let zst = |inp: ReadBox| Box::pin(bufread::ZstdDecoder::new(BufReader::new(inp)));
+    let br = |inp: ReadBox| Box::pin(bufread::BrotliDecoder::new(BufReader::new(inp)));
 
     Ok(match reason {
         Fast(FileExtension(ext)) => match ext.as_ref() {
             "tgz" | "gz" => gz(inp),
             "tbz" | "tbz2" | "bz2" => bz2(inp),
-            "xz" => xz(inp),
-            "zst" => zst(inp),
+            "txz" | "xz" => xz(inp),
+            "tzst" | "zst" => zst(inp),
+            "lz4" => lz4(inp),
+            "br" => br(inp),
             ext => Err(format_err!("don't know how to decompress {}", ext))?,
         },
+        Fast(Directory(dir)) => Err(format_err!("don't know how to decompress {}/", dir))?,
         MimeType(mime) => match mime.as_ref() {
             "application/gzip" => gz(inp),
             "application/x-bzip" => bz2(inp),
             "application/x-xz" => xz(inp),
             "application/zstd" => zst(inp),
+            "application/x-lz4" => lz4(inp),
             mime => Err(format_err!("don't know how to decompress mime {}", mime))?,
         },
     })
@@ -82,12 +115,9 @@ fn get_inner_filename(filename: &Path) -> PathBuf {
         .extension()
         .map(|e| e.to_string_lossy())
         .unwrap_or(Cow::Borrowed(""));
-    let stem = filename
-        .file_stem()
-        .expect("no filename given?")
-        .to_string_lossy();
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
     let new_extension = match extension.as_ref() {
-        "tgz" | "tbz" | "tbz2" => ".tar",
+        "tgz" | "tbz" | "tbz2" | "txz" | "tzst" => ".tar",
         _other => "",
     };
     filename.with_file_name(format!("{}{}", stem, new_extension))
@@ -130,6 +160,9 @@ mod tests {
             ("hi/test.tbz", "hi/test.tar"),
             ("hi/test.hi.bz2", "hi/test.hi"),
             ("hello.tar.gz", "hello.tar"),
+            ("hi/test.txz", "hi/test.tar"),
+            ("logs/log.json.zst", "logs/log.json"),
+            ("hi/test.html.br", "hi/test.html"),
         ] {
             assert_eq!(get_inner_filename(&PathBuf::from(a)), PathBuf::from(*b));
         }
@@ -148,6 +181,20 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn other_formats() -> Result<()> {
+        let adapter = DecompressAdapter;
+
+        for name in ["hello.xz", "hello.zst", "hello.lz4", "hello.br"] {
+            let filepath = test_data_dir().join(name);
+            let (a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+            let r = adapter.adapt(a, &d).await?;
+            let o = adapted_to_vec(r).await?;
+            assert_eq!(String::from_utf8(o)?, "hello\n", "{name}");
+        }
+        Ok(())
+    }
+
     #[tokio::test]
     async fn pdf_gz() -> Result<()> {
         let adapter = DecompressAdapter;
@@ -159,12 +206,12 @@ mod tests {
         let o = adapted_to_vec(r).await?;
         assert_eq!(
             String::from_utf8(o)?,
-            "PREFIX:Page 1: hello world
-PREFIX:Page 1: this is just a test.
-PREFIX:Page 1: 
-PREFIX:Page 1: 1
-PREFIX:Page 1: 
-PREFIX:Page 1: 
+            "PREFIX:#page=1: hello world
+PREFIX:#page=1: this is just a test.
+PREFIX:#page=1: 
+PREFIX:#page=1: 1
+PREFIX:#page=1: 
+PREFIX:#page=1: 
 "
         );
         Ok(())
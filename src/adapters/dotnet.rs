@@ -0,0 +1,521 @@
+use super::binary::{one_line, usize_from, utf16le_lossy, BinReader};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::BTreeSet;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["dll", "exe", "winmd"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "dotnet".to_owned(),
+        version: 1,
+        description:
+            "Lists namespaces, types, members, assembly references and user strings of .NET (CLI) assemblies.\nDisabled by default since .dll and .exe files are usually native code."
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct DotnetAdapter;
+
+impl DotnetAdapter {
+    pub fn new() -> DotnetAdapter {
+        DotnetAdapter
+    }
+}
+impl GetMetadata for DotnetAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// maps a relative virtual address to a file offset using the PE section table
+fn rva_to_offset(r: &BinReader, sections: usize, section_count: u16, rva: u32) -> Result<usize> {
+    for i in 0..usize::from(section_count) {
+        let sec = sections + 40 * i;
+        let virtual_size = r.u32(sec + 8)?;
+        let virtual_address = r.u32(sec + 12)?;
+        let raw_size = r.u32(sec + 16)?;
+        let raw_pointer = r.u32(sec + 20)?;
+        if virtual_address <= rva && rva - virtual_address < virtual_size.max(raw_size) {
+            return usize_from(rva - virtual_address + raw_pointer);
+        }
+    }
+    Err(format_err!("rva 0x{:x} not in any section", rva))
+}
+
+/// returns the metadata root of a .NET assembly, or None if the file is a native PE file
+fn find_metadata<'a>(r: &BinReader<'a>) -> Result<Option<&'a [u8]>> {
+    let pe = usize_from(r.u32(0x3c)?)?;
+    if r.slice(pe, 4)? != b"PE\0\0" {
+        return Err(format_err!("PE signature missing"));
+    }
+    let section_count = r.u16(pe + 6)?;
+    let optional_size = usize::from(r.u16(pe + 20)?);
+    let optional = pe + 24;
+    let directories = match r.u16(optional)? {
+        0x10b => optional + 96,
+        0x20b => optional + 112,
+        magic => {
+            return Err(format_err!(
+                "unknown PE optional header magic 0x{:x}",
+                magic
+            ))
+        }
+    };
+    let sections = optional + optional_size;
+    // data directory 14 is the CLI header
+    let cli_rva = match r.u32(directories + 14 * 8) {
+        Ok(0) | Err(_) => return Ok(None),
+        Ok(rva) => rva,
+    };
+    let cli = rva_to_offset(r, sections, section_count, cli_rva)?;
+    let metadata_rva = r.u32(cli + 8)?;
+    let metadata_size = usize_from(r.u32(cli + 12)?)?;
+    let metadata = rva_to_offset(r, sections, section_count, metadata_rva)?;
+    Ok(Some(r.slice(metadata, metadata_size)?))
+}
+
+// metadata table numbers, ECMA-335 II.22
+const MODULE: usize = 0x00;
+const TYPE_REF: usize = 0x01;
+const TYPE_DEF: usize = 0x02;
+const FIELD: usize = 0x04;
+const METHOD_DEF: usize = 0x06;
+const PARAM: usize = 0x08;
+const INTERFACE_IMPL: usize = 0x09;
+const MEMBER_REF: usize = 0x0a;
+const DECL_SECURITY: usize = 0x0e;
+const STAND_ALONE_SIG: usize = 0x11;
+const EVENT: usize = 0x14;
+const PROPERTY: usize = 0x17;
+const MODULE_REF: usize = 0x1a;
+const TYPE_SPEC: usize = 0x1b;
+const ASSEMBLY: usize = 0x20;
+const ASSEMBLY_REF: usize = 0x23;
+const FILE: usize = 0x26;
+const EXPORTED_TYPE: usize = 0x27;
+const MANIFEST_RESOURCE: usize = 0x28;
+const GENERIC_PARAM: usize = 0x2a;
+const METHOD_SPEC: usize = 0x2b;
+const GENERIC_PARAM_CONSTRAINT: usize = 0x2c;
+const TABLE_COUNT: usize = 0x2d;
+
+#[derive(Clone, Copy)]
+enum Col {
+    Fixed(usize),
+    Str,
+    Guid,
+    Blob,
+    Index(usize),
+    Coded(&'static [usize], u32),
+}
+use Col::*;
+
+const TYPE_DEF_OR_REF: Col = Coded(&[TYPE_DEF, TYPE_REF, TYPE_SPEC], 2);
+const HAS_CONSTANT: Col = Coded(&[FIELD, PARAM, PROPERTY], 2);
+const HAS_CUSTOM_ATTRIBUTE: Col = Coded(
+    &[
+        METHOD_DEF,
+        FIELD,
+        TYPE_REF,
+        TYPE_DEF,
+        PARAM,
+        INTERFACE_IMPL,
+        MEMBER_REF,
+        MODULE,
+        DECL_SECURITY,
+        PROPERTY,
+        EVENT,
+        STAND_ALONE_SIG,
+        MODULE_REF,
+        TYPE_SPEC,
+        ASSEMBLY,
+        ASSEMBLY_REF,
+        FILE,
+        EXPORTED_TYPE,
+        MANIFEST_RESOURCE,
+        GENERIC_PARAM,
+        GENERIC_PARAM_CONSTRAINT,
+        METHOD_SPEC,
+    ],
+    5,
+);
+const HAS_FIELD_MARSHAL: Col = Coded(&[FIELD, PARAM], 1);
+const HAS_DECL_SECURITY: Col = Coded(&[TYPE_DEF, METHOD_DEF, ASSEMBLY], 2);
+const MEMBER_REF_PARENT: Col = Coded(&[TYPE_DEF, TYPE_REF, MODULE_REF, METHOD_DEF, TYPE_SPEC], 3);
+const HAS_SEMANTICS: Col = Coded(&[EVENT, PROPERTY], 1);
+const METHOD_DEF_OR_REF: Col = Coded(&[METHOD_DEF, MEMBER_REF], 1);
+const MEMBER_FORWARDED: Col = Coded(&[FIELD, METHOD_DEF], 1);
+const IMPLEMENTATION: Col = Coded(&[FILE, ASSEMBLY_REF, EXPORTED_TYPE], 2);
+const CUSTOM_ATTRIBUTE_TYPE: Col = Coded(&[METHOD_DEF, MEMBER_REF], 3);
+const RESOLUTION_SCOPE: Col = Coded(&[MODULE, MODULE_REF, ASSEMBLY_REF, TYPE_REF], 2);
+const TYPE_OR_METHOD_DEF: Col = Coded(&[TYPE_DEF, METHOD_DEF], 1);
+
+/// column layout of every metadata table
+fn schema(table: usize) -> &'static [Col] {
+    match table {
+        0x00 => &[Fixed(2), Str, Guid, Guid, Guid],
+        0x01 => &[RESOLUTION_SCOPE, Str, Str],
+        0x02 => &[
+            Fixed(4),
+            Str,
+            Str,
+            TYPE_DEF_OR_REF,
+            Index(FIELD),
+            Index(METHOD_DEF),
+        ],
+        0x03 => &[Index(FIELD)],
+        0x04 => &[Fixed(2), Str, Blob],
+        0x05 => &[Index(METHOD_DEF)],
+        0x06 => &[Fixed(4), Fixed(2), Fixed(2), Str, Blob, Index(PARAM)],
+        0x07 => &[Index(PARAM)],
+        0x08 => &[Fixed(2), Fixed(2), Str],
+        0x09 => &[Index(TYPE_DEF), TYPE_DEF_OR_REF],
+        0x0a => &[MEMBER_REF_PARENT, Str, Blob],
+        0x0b => &[Fixed(2), HAS_CONSTANT, Blob],
+        0x0c => &[HAS_CUSTOM_ATTRIBUTE, CUSTOM_ATTRIBUTE_TYPE, Blob],
+        0x0d => &[HAS_FIELD_MARSHAL, Blob],
+        0x0e => &[Fixed(2), HAS_DECL_SECURITY, Blob],
+        0x0f => &[Fixed(2), Fixed(4), Index(TYPE_DEF)],
+        0x10 => &[Fixed(4), Index(FIELD)],
+        0x11 => &[Blob],
+        0x12 => &[Index(TYPE_DEF), Index(EVENT)],
+        0x13 => &[Index(EVENT)],
+        0x14 => &[Fixed(2), Str, TYPE_DEF_OR_REF],
+        0x15 => &[Index(TYPE_DEF), Index(PROPERTY)],
+        0x16 => &[Index(PROPERTY)],
+        0x17 => &[Fixed(2), Str, Blob],
+        0x18 => &[Fixed(2), Index(METHOD_DEF), HAS_SEMANTICS],
+        0x19 => &[Index(TYPE_DEF), METHOD_DEF_OR_REF, METHOD_DEF_OR_REF],
+        0x1a => &[Str],
+        0x1b => &[Blob],
+        0x1c => &[Fixed(2), MEMBER_FORWARDED, Str, Index(MODULE_REF)],
+        0x1d => &[Fixed(4), Index(FIELD)],
+        0x1e => &[Fixed(4), Fixed(4)],
+        0x1f => &[Fixed(4)],
+        0x20 => &[
+            Fixed(4),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(4),
+            Blob,
+            Str,
+            Str,
+        ],
+        0x21 => &[Fixed(4)],
+        0x22 => &[Fixed(4), Fixed(4), Fixed(4)],
+        0x23 => &[
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(4),
+            Blob,
+            Str,
+            Str,
+            Blob,
+        ],
+        0x24 => &[Fixed(4), Index(ASSEMBLY_REF)],
+        0x25 => &[Fixed(4), Fixed(4), Fixed(4), Index(ASSEMBLY_REF)],
+        0x26 => &[Fixed(4), Str, Blob],
+        0x27 => &[Fixed(4), Fixed(4), Str, Str, IMPLEMENTATION],
+        0x28 => &[Fixed(4), Fixed(4), Str, IMPLEMENTATION],
+        0x29 => &[Index(TYPE_DEF), Index(TYPE_DEF)],
+        0x2a => &[Fixed(2), Fixed(2), TYPE_OR_METHOD_DEF, Str],
+        0x2b => &[METHOD_DEF_OR_REF, Blob],
+        0x2c => &[Index(GENERIC_PARAM), TYPE_DEF_OR_REF],
+        _ => &[],
+    }
+}
+
+struct Tables<'a> {
+    r: BinReader<'a>,
+    strings: &'a [u8],
+    rows: [usize; TABLE_COUNT],
+    offsets: [usize; TABLE_COUNT],
+    heap_sizes: u8,
+}
+
+impl<'a> Tables<'a> {
+    fn col_size(&self, col: Col) -> usize {
+        let wide = |flag: u8| if self.heap_sizes & flag != 0 { 4 } else { 2 };
+        match col {
+            Fixed(n) => n,
+            Str => wide(0x01),
+            Guid => wide(0x02),
+            Blob => wide(0x04),
+            Index(t) => {
+                if self.rows[t] > 0xffff {
+                    4
+                } else {
+                    2
+                }
+            }
+            Coded(tables, bits) => {
+                let max = tables.iter().map(|t| self.rows[*t]).max().unwrap_or(0);
+                if max < 1 << (16 - bits) {
+                    2
+                } else {
+                    4
+                }
+            }
+        }
+    }
+    fn row_size(&self, table: usize) -> usize {
+        schema(table).iter().map(|c| self.col_size(*c)).sum()
+    }
+    /// read column `col` of 1-based row `row`
+    fn get(&self, table: usize, row: usize, col: usize) -> Result<u32> {
+        let columns = schema(table);
+        let off = self.offsets[table]
+            + (row - 1) * self.row_size(table)
+            + columns[..col]
+                .iter()
+                .map(|c| self.col_size(*c))
+                .sum::<usize>();
+        match self.col_size(columns[col]) {
+            2 => self.r.u16(off).map(u32::from),
+            4 => self.r.u32(off),
+            _ => Err(format_err!(
+                "column {} of table {} is not an index",
+                col,
+                table
+            )),
+        }
+    }
+    fn string(&self, table: usize, row: usize, col: usize) -> Result<String> {
+        let idx = usize_from(self.get(table, row, col)?)?;
+        Ok(one_line(BinReader::le(self.strings).cstr(idx)?))
+    }
+    fn type_name(&self, table: usize, row: usize) -> Result<String> {
+        let name = self.string(table, row, 1)?;
+        let namespace = self.string(table, row, 2)?;
+        Ok(if namespace.is_empty() {
+            name
+        } else {
+            format!("{namespace}.{name}")
+        })
+    }
+    /// the rows of `list_table` owned by `row`, for the FieldList/MethodList columns of TypeDef
+    fn list_range(
+        &self,
+        row: usize,
+        col: usize,
+        list_table: usize,
+    ) -> Result<std::ops::Range<usize>> {
+        let start = usize_from(self.get(TYPE_DEF, row, col)?)?;
+        let end = if row < self.rows[TYPE_DEF] {
+            usize_from(self.get(TYPE_DEF, row + 1, col)?)?
+        } else {
+            self.rows[list_table] + 1
+        };
+        Ok(start.max(1)..end.max(start.max(1)))
+    }
+}
+
+fn version(t: &Tables, table: usize, row: usize, first_col: usize) -> Result<String> {
+    Ok(format!(
+        "{}.{}.{}.{}",
+        t.get(table, row, first_col)?,
+        t.get(table, row, first_col + 1)?,
+        t.get(table, row, first_col + 2)?,
+        t.get(table, row, first_col + 3)?
+    ))
+}
+
+/// iterate the entries of the #US heap: each is a compressed length followed by UTF-16LE and a trailing flag byte
+fn user_strings(heap: &[u8]) -> Vec<String> {
+    let r = BinReader::le(heap);
+    let mut out = vec![];
+    let mut pos = 1;
+    while let Ok(b0) = r.u8(pos) {
+        let (len, header) = if b0 & 0x80 == 0 {
+            (usize::from(b0), 1)
+        } else if b0 & 0xc0 == 0x80 {
+            match r.u8(pos + 1) {
+                Ok(b1) => (usize::from(b0 & 0x3f) << 8 | usize::from(b1), 2),
+                Err(_) => break,
+            }
+        } else {
+            match r.u32(pos).map(u32::swap_bytes) {
+                Ok(v) => ((v & 0x1fff_ffff) as usize, 4),
+                Err(_) => break,
+            }
+        };
+        if len == 0 {
+            // heap padding
+            pos += header;
+            continue;
+        }
+        match r.slice(pos + header, len - 1) {
+            Ok(chars) => out.push(utf16le_lossy(chars)),
+            Err(_) => break,
+        }
+        pos += header + len;
+    }
+    out
+}
+
+fn dump_dotnet(data: &[u8], s: &mut impl Write) -> Result<()> {
+    if !data.starts_with(b"MZ") {
+        writeln!(s, "[rga: not a PE file]")?;
+        return Ok(());
+    }
+    let meta = match find_metadata(&BinReader::le(data))? {
+        Some(meta) => BinReader::le(meta),
+        // native dll, nothing to show
+        None => return Ok(()),
+    };
+    if meta.u32(0)? != 0x424a_5342 {
+        return Err(format_err!("metadata signature missing"));
+    }
+    let version_len = usize_from(meta.u32(12)?)?;
+    let stream_count = meta.u16(16 + version_len + 2)?;
+    let mut pos = 16 + version_len + 4;
+    let mut heaps = HashMap::new();
+    for _ in 0..stream_count {
+        let offset = usize_from(meta.u32(pos)?)?;
+        let size = usize_from(meta.u32(pos + 4)?)?;
+        let name = meta.cstr(pos + 8)?;
+        heaps.insert(name, meta.slice(offset, size)?);
+        pos += 8 + ((name.len() + 4) & !3);
+    }
+    let heap = |name: &[u8]| heaps.get(name).copied().unwrap_or_default();
+    let tables_heap = heaps
+        .get(&b"#~"[..])
+        .or_else(|| heaps.get(&b"#-"[..]))
+        .ok_or_else(|| format_err!("no metadata tables stream"))?;
+
+    let r = BinReader::le(tables_heap);
+    let heap_sizes = r.u8(6)?;
+    let valid = r.u64(8)?;
+    let mut rows = [0usize; TABLE_COUNT];
+    let mut pos = 24;
+    for (table, count) in rows.iter_mut().enumerate() {
+        if valid & (1 << table) != 0 {
+            *count = usize_from(r.u32(pos)?)?;
+            pos += 4;
+        }
+    }
+    if valid >> TABLE_COUNT != 0 {
+        return Err(format_err!("unsupported metadata tables present"));
+    }
+    let mut t = Tables {
+        r,
+        strings: heap(b"#Strings"),
+        rows,
+        offsets: [0; TABLE_COUNT],
+        heap_sizes,
+    };
+    for table in 0..TABLE_COUNT {
+        t.offsets[table] = pos;
+        pos += t.rows[table] * t.row_size(table);
+    }
+    r.slice(0, pos)
+        .map_err(|_| format_err!("metadata tables truncated"))?;
+
+    if t.rows[ASSEMBLY] > 0 {
+        writeln!(
+            s,
+            "assembly: {} {}",
+            t.string(ASSEMBLY, 1, 7)?,
+            version(&t, ASSEMBLY, 1, 1)?
+        )?;
+    }
+    for row in 1..=t.rows[ASSEMBLY_REF] {
+        writeln!(
+            s,
+            "reference: {} {}",
+            t.string(ASSEMBLY_REF, row, 6)?,
+            version(&t, ASSEMBLY_REF, row, 0)?
+        )?;
+    }
+    let namespaces: BTreeSet<String> = (1..=t.rows[TYPE_DEF])
+        .map(|row| t.string(TYPE_DEF, row, 2))
+        .collect::<Result<_>>()?;
+    for namespace in namespaces.iter().filter(|n| !n.is_empty()) {
+        writeln!(s, "namespace: {namespace}")?;
+    }
+    for row in 1..=t.rows[TYPE_DEF] {
+        let name = t.type_name(TYPE_DEF, row)?;
+        if name != "<Module>" {
+            let extends = t.get(TYPE_DEF, row, 3)?;
+            let (tag, idx) = (extends & 3, usize_from(extends >> 2)?);
+            let base = match tag {
+                0 if idx > 0 => Some(t.type_name(TYPE_DEF, idx)?),
+                1 if idx > 0 => Some(t.type_name(TYPE_REF, idx)?),
+                _ => None,
+            };
+            match base {
+                Some(base) => writeln!(s, "type: {name} extends {base}")?,
+                None => writeln!(s, "type: {name}")?,
+            }
+        }
+        for field in t.list_range(row, 4, FIELD)? {
+            writeln!(s, "field: {}.{}", name, t.string(FIELD, field, 1)?)?;
+        }
+        for method in t.list_range(row, 5, METHOD_DEF)? {
+            writeln!(s, "method: {}.{}", name, t.string(METHOD_DEF, method, 3)?)?;
+        }
+    }
+    for str in user_strings(heap(b"#US")) {
+        writeln!(s, "string: {}", one_line(str.as_bytes()))?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for DotnetAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_dotnet(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn us_heap() {
+        // empty entry, "hi" (2 chars + flag byte), padding
+        let heap = [0u8, 5, b'h', 0, b'i', 0, 0, 0];
+        assert_eq!(user_strings(&heap), vec!["hi".to_string()]);
+    }
+
+    #[test]
+    fn not_pe() -> Result<()> {
+        let mut out = Vec::new();
+        dump_dotnet(b"hello", &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "[rga: not a PE file]\n");
+        assert!(dump_dotnet(b"MZ", &mut Vec::new()).is_err());
+        Ok(())
+    }
+}
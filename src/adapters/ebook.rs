@@ -0,0 +1,550 @@
+use super::binary::{usize_from, BinReader};
+use super::xml::{xml_events, Xml};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::{bail, format_err, Result};
+use async_trait::async_trait;
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use lazy_static::lazy_static;
+use std::borrow::Cow;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["fb2", "mobi", "prc", "azw", "azw3"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ebook".to_owned(),
+        version: 2,
+        description:
+            "Extracts the text of FictionBook (.fb2) and Kindle / MOBI / PalmDoc (.mobi, .prc, .azw, .azw3) e-books. DRM-protected books are not supported"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-fictionbook+xml".to_owned()),
+            FileMatcher::MimeType("application/x-mobipocket-ebook".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EbookAdapter;
+
+impl EbookAdapter {
+    pub fn new() -> EbookAdapter {
+        EbookAdapter
+    }
+}
+impl GetMetadata for EbookAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// writes the text collected for one block element, with whitespace collapsed like in HTML
+fn flush(line: &mut String, s: &mut impl Write) -> Result<()> {
+    let text = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        writeln!(s, "{text}")?;
+    }
+    line.clear();
+    Ok(())
+}
+
+/// decode an XML document in the encoding given in its declaration (FB2 files are often windows-1251)
+fn decode_xml(data: &[u8]) -> String {
+    let head = String::from_utf8_lossy(&data[..data.len().min(200)]);
+    let encoding = head
+        .find("encoding=")
+        .and_then(|i| {
+            let value = &head[i + "encoding=".len()..];
+            let quote = value.chars().next()?;
+            let end = value[1..].find(quote)?;
+            Encoding::for_label(value[1..=end].as_bytes())
+        })
+        .unwrap_or(UTF_8);
+    encoding.decode(data).0.into_owned()
+}
+
+/// elements of FB2 that are part of a line of text. All others end the line
+static FB2_INLINE: &[&str] = &[
+    "strong",
+    "emphasis",
+    "style",
+    "a",
+    "strikethrough",
+    "sub",
+    "sup",
+    "code",
+    "image",
+];
+
+/// FictionBook 2: the title info (title, authors, annotation) and the paragraphs of the bodies
+fn dump_fb2(doc: &str, s: &mut impl Write) -> Result<()> {
+    // depth inside elements that are not part of the book's text, like base64 images
+    let mut skip = 0usize;
+    let mut line = String::new();
+    for event in xml_events(doc) {
+        match event {
+            Xml::Start(
+                "binary" | "document-info" | "publish-info" | "custom-info" | "stylesheet",
+                _,
+            ) => skip += 1,
+            Xml::End(
+                "binary" | "document-info" | "publish-info" | "custom-info" | "stylesheet",
+            ) => skip = skip.saturating_sub(1),
+            _ if skip > 0 => {}
+            Xml::Text(t) => line.push_str(&t),
+            Xml::End("first-name" | "middle-name" | "last-name" | "nickname") => line.push(' '),
+            Xml::End(tag) if !FB2_INLINE.contains(&tag) => flush(&mut line, s)?,
+            Xml::Start("empty-line", _) => flush(&mut line, s)?,
+            _ => {}
+        }
+    }
+    flush(&mut line, s)
+}
+
+/// html elements that start or end a line
+pub(super) fn is_html_block(tag: &str) -> bool {
+    matches!(
+        tag.to_ascii_lowercase().as_str(),
+        "p" | "br"
+            | "div"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "li"
+            | "tr"
+            | "blockquote"
+            | "pagebreak"
+    )
+}
+
+/// the text of the (not necessarily well-formed) HTML of a MOBI book, one line per paragraph
+fn dump_html(html: &str, s: &mut impl Write) -> Result<()> {
+    let mut line = String::new();
+    for event in xml_events(html) {
+        match event {
+            Xml::Text(t) => line.push_str(&t.replace("&nbsp;", " ")),
+            Xml::Start(tag, _) | Xml::End(tag) if is_html_block(tag) => flush(&mut line, s)?,
+            _ => {}
+        }
+    }
+    flush(&mut line, s)
+}
+
+/// PalmDoc LZ77 decompression of one text record
+fn palmdoc_decompress(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let start = out.len();
+    let mut i = 0;
+    while i < data.len() {
+        let c = data[i];
+        i += 1;
+        match c {
+            // the next 1-8 bytes are literals
+            1..=8 => {
+                let n = usize::from(c);
+                let literal = data
+                    .get(i..i + n)
+                    .ok_or_else(|| format_err!("literal run out of bounds"))?;
+                out.extend_from_slice(literal);
+                i += n;
+            }
+            // 11 bits distance, 3 bits length - 3
+            0x80..=0xbf => {
+                let next = *data
+                    .get(i)
+                    .ok_or_else(|| format_err!("truncated back reference"))?;
+                i += 1;
+                let pair = (usize::from(c) << 8) | usize::from(next);
+                let dist = (pair >> 3) & 0x7ff;
+                let len = (pair & 7) + 3;
+                if dist == 0 || dist > out.len() - start {
+                    bail!("back reference before the start of the record");
+                }
+                for _ in 0..len {
+                    out.push(out[out.len() - dist]);
+                }
+            }
+            // space + character
+            0xc0..=0xff => {
+                out.push(b' ');
+                out.push(c ^ 0x80);
+            }
+            _ => out.push(c),
+        }
+    }
+    Ok(())
+}
+
+/// the HUFF/CDIC compression of MOBI books: a Huffman code whose symbols are phrases, which can
+/// themselves be compressed. The HUFF record holds the code tables, the CDIC records the phrases
+struct HuffCdic<'a> {
+    /// code length, whether the code is complete at that length and the largest code, by the first byte
+    dict1: Vec<(usize, bool, u64)>,
+    mincode: [u64; 33],
+    maxcode: [u64; 33],
+    /// the phrases, and whether they are already decompressed. None while being decompressed
+    phrases: Vec<Option<(Cow<'a, [u8]>, bool)>>,
+}
+
+impl<'a> HuffCdic<'a> {
+    fn new(huff: &[u8], cdics: &[&'a [u8]]) -> Result<HuffCdic<'a>> {
+        let r = BinReader::be(huff);
+        if r.slice(0, 8)? != b"HUFF\x00\x00\x00\x18" {
+            bail!("invalid HUFF record");
+        }
+        let (off1, off2) = (usize_from(r.u32(8)?)?, usize_from(r.u32(12)?)?);
+        let dict1 = (0..256)
+            .map(|i| {
+                let v = r.u32(off1 + 4 * i)?;
+                let codelen = (v & 0x1f) as usize;
+                if codelen == 0 {
+                    bail!("invalid code length");
+                }
+                let maxcode = ((u64::from(v >> 8) + 1) << (32 - codelen)) - 1;
+                Ok((codelen, v & 0x80 != 0, maxcode))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let (mut mincode, mut maxcode) = ([0; 33], [u64::from(u32::MAX); 33]);
+        for codelen in 1..=32 {
+            let i = off2 + 8 * (codelen - 1);
+            mincode[codelen] = u64::from(r.u32(i)?) << (32 - codelen);
+            maxcode[codelen] = ((u64::from(r.u32(i + 4)?) + 1) << (32 - codelen)) - 1;
+        }
+        let mut phrases = vec![];
+        for cdic in cdics {
+            let r = BinReader::be(cdic);
+            if r.slice(0, 8)? != b"CDIC\x00\x00\x00\x10" {
+                bail!("invalid CDIC record");
+            }
+            let (count, bits) = (usize_from(r.u32(8)?)?, r.u32(12)?);
+            let n = (1usize << bits.min(16)).min(count.saturating_sub(phrases.len()));
+            for i in 0..n {
+                let off = 16 + usize::from(r.u16(16 + 2 * i)?);
+                let len = r.u16(off)?;
+                let phrase = r.slice(off + 2, usize::from(len & 0x7fff))?;
+                phrases.push(Some((Cow::Borrowed(phrase), len & 0x8000 != 0)));
+            }
+        }
+        Ok(HuffCdic {
+            dict1,
+            mincode,
+            maxcode,
+            phrases,
+        })
+    }
+
+    fn decompress(&mut self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        let mut bits_left = data.len() as i64 * 8;
+        // a window of 64 bits, of which the 32 starting at bit n from the right are decoded next
+        let word = |pos: usize| {
+            let mut bytes = [0u8; 8];
+            for (i, b) in bytes.iter_mut().enumerate() {
+                *b = data.get(pos + i).copied().unwrap_or(0);
+            }
+            u64::from_be_bytes(bytes)
+        };
+        let (mut pos, mut n) = (0, 32i32);
+        let mut x = word(pos);
+        loop {
+            if n <= 0 {
+                pos += 4;
+                x = word(pos);
+                n += 32;
+            }
+            let code = (x >> n) & 0xffff_ffff;
+            let (mut codelen, term, mut maxcode) = self.dict1[(code >> 24) as usize];
+            if !term {
+                while code < self.mincode[codelen] {
+                    codelen += 1;
+                    if codelen > 32 {
+                        bail!("invalid huffman code");
+                    }
+                }
+                maxcode = self.maxcode[codelen];
+            }
+            n -= codelen as i32;
+            bits_left -= codelen as i64;
+            if bits_left < 0 {
+                return Ok(());
+            }
+            let index = (maxcode.wrapping_sub(code) >> (32 - codelen)) as usize;
+            let (phrase, done) = self
+                .phrases
+                .get_mut(index)
+                .ok_or_else(|| format_err!("phrase {} out of bounds", index))?
+                .take()
+                .ok_or_else(|| format_err!("phrase {} refers to itself", index))?;
+            let phrase = if done {
+                phrase
+            } else {
+                let mut decompressed = Vec::new();
+                self.decompress(&phrase, &mut decompressed)?;
+                Cow::Owned(decompressed)
+            };
+            out.extend_from_slice(&phrase);
+            self.phrases[index] = Some((phrase, true));
+        }
+    }
+}
+
+/// size of the extra data at the end of a MOBI text record, as announced by the extra data flags
+fn trailing_size(rec: &[u8], flags: u16) -> usize {
+    let mut size = 0;
+    for bit in 1..16 {
+        if flags & (1 << bit) == 0 {
+            continue;
+        }
+        // a varint read backwards from the end, which includes its own length
+        let mut pos = rec.len().saturating_sub(size);
+        let (mut n, mut shift) = (0usize, 0);
+        while pos > 0 && shift < 28 {
+            pos -= 1;
+            n |= usize::from(rec[pos] & 0x7f) << shift;
+            shift += 7;
+            if rec[pos] & 0x80 != 0 {
+                break;
+            }
+        }
+        size += n;
+    }
+    // multibyte character overlap
+    if flags & 1 != 0 {
+        if let Some(&b) = rec.len().checked_sub(size + 1).and_then(|i| rec.get(i)) {
+            size += usize::from(b & 3) + 1;
+        }
+    }
+    size
+}
+
+/// MOBI (BOOKMOBI) and plain PalmDoc (TEXtREAd) books: a Palm database whose first record is a header,
+/// followed by the (usually compressed) text records
+fn dump_mobi(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let r = BinReader::be(data);
+    let num_records = usize::from(r.u16(76)?);
+    let record = |i: usize| -> Result<&[u8]> {
+        if i >= num_records {
+            bail!("record {} out of bounds ({} records)", i, num_records);
+        }
+        let off = usize_from(r.u32(78 + 8 * i)?)?;
+        let end = if i + 1 < num_records {
+            usize_from(r.u32(78 + 8 * (i + 1))?)?
+        } else {
+            data.len()
+        };
+        r.slice(off, end.saturating_sub(off))
+    };
+    let header = BinReader::be(record(0)?);
+    let compression = header.u16(0)?;
+    let text_records = usize::from(header.u16(8)?);
+    let (mut utf8, mut extra_flags) = (false, 0);
+    let mut huff = None;
+    if header.slice(16, 4).ok() == Some(&b"MOBI"[..]) {
+        if header.u16(12)? != 0 {
+            bail!("the book is encrypted (DRM)");
+        }
+        let header_len = usize_from(header.u32(20)?)?;
+        utf8 = header.u32(28)? == 65001;
+        if header_len >= 0xe4 {
+            extra_flags = header.u16(0xf2)?;
+        }
+        if compression == 17480 {
+            let first = usize_from(header.u32(0x70)?)?;
+            let count = usize_from(header.u32(0x74)?)?;
+            let cdics = (first + 1..first + count)
+                .map(record)
+                .collect::<Result<Vec<_>>>()?;
+            huff = Some(HuffCdic::new(record(first)?, &cdics)?);
+        }
+        let title_off = usize_from(header.u32(0x54)?)?;
+        let title_len = usize_from(header.u32(0x58)?)?;
+        if let Ok(title) = header.slice(title_off, title_len) {
+            let title = if utf8 {
+                String::from_utf8_lossy(title)
+            } else {
+                WINDOWS_1252.decode_without_bom_handling(title).0
+            };
+            writeln!(s, "title: {title}")?;
+        }
+    }
+    let mut text = Vec::new();
+    for i in 1..=text_records {
+        let rec = record(i)?;
+        let rec = &rec[..rec.len() - trailing_size(rec, extra_flags).min(rec.len())];
+        match compression {
+            1 => text.extend_from_slice(rec),
+            2 => palmdoc_decompress(rec, &mut text)?,
+            17480 => match &mut huff {
+                Some(huff) => huff.decompress(rec, &mut text)?,
+                None => bail!("HUFF/CDIC compression without MOBI header"),
+            },
+            c => bail!("unknown compression {}", c),
+        }
+    }
+    let html = if utf8 {
+        String::from_utf8_lossy(&text)
+    } else {
+        WINDOWS_1252.decode_without_bom_handling(&text).0
+    };
+    dump_html(&html, s)
+}
+
+fn dump_ebook(data: &[u8], s: &mut impl Write) -> Result<()> {
+    match data.get(60..68) {
+        Some(b"BOOKMOBI") | Some(b"TEXtREAd") => dump_mobi(data, s),
+        _ => dump_fb2(&decode_xml(data), s),
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for EbookAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_ebook(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dump(data: &[u8]) -> Result<String> {
+        let mut out = Vec::new();
+        dump_ebook(data, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn fb2() -> Result<()> {
+        let mut doc = br#"<?xml version="1.0" encoding="windows-1251"?>
+<FictionBook xmlns="http://www.gribuser.ru/xml/fictionbook/2.0" xmlns:l="http://www.w3.org/1999/xlink">
+<description><title-info><genre>prose</genre><author><first-name>Leo</first-name><last-name>Tolstoy</last-name></author>
+<book-title>"#
+            .to_vec();
+        // "Война и мир" in windows-1251
+        doc.extend_from_slice(b"\xc2\xee\xe9\xed\xe0 \xe8 \xec\xe8\xf0");
+        doc.extend_from_slice(
+            br#"</book-title></title-info><document-info><program-used>FB Editor</program-used></document-info></description>
+<body><section><title><p>Chapter 1</p></title>
+<p>Well, <emphasis>Prince</emphasis>, so
+   Genoa and Lucca</p><empty-line/><p>are now just family estates.</p></section></body>
+<binary id="cover.jpg" content-type="image/jpeg">/9j/4AAQSkZJRgABAQ</binary>
+</FictionBook>"#,
+        );
+        assert_eq!(
+            dump(&doc)?,
+            "prose
+Leo Tolstoy
+Война и мир
+Chapter 1
+Well, Prince, so Genoa and Lucca
+are now just family estates.
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn palmdoc() -> Result<()> {
+        let mut out = Vec::new();
+        // literals, " h" and a back reference to "ello" (distance 6, length 4)
+        palmdoc_decompress(b"<p>hello\xe8\x80\x31</p>", &mut out)?;
+        assert_eq!(out, b"<p>hello hello</p>");
+        assert!(palmdoc_decompress(b"\x80\x31", &mut Vec::new()).is_err());
+        assert!(palmdoc_decompress(b"\x05ab", &mut Vec::new()).is_err());
+        // multibyte overlap (1 byte) and one trailing entry of 3 bytes
+        assert_eq!(trailing_size(b"text\x00xy\x83", 3), 4);
+        Ok(())
+    }
+
+    /// a Huffman code of 8 bit codes, where byte b is phrase 255 - b, and a CDIC record with the phrases
+    fn huff_cdic(phrases: &[(&[u8], bool)]) -> (Vec<u8>, Vec<u8>) {
+        let mut huff = b"HUFF\x00\x00\x00\x18".to_vec();
+        huff.extend_from_slice(&24u32.to_be_bytes());
+        huff.extend_from_slice(&(24u32 + 1024).to_be_bytes());
+        huff.extend_from_slice(&[0; 8]);
+        for _ in 0..256 {
+            huff.extend_from_slice(&(8u32 | 0x80 | (255 << 8)).to_be_bytes());
+        }
+        huff.extend_from_slice(&[0; 256]);
+        let mut cdic = b"CDIC\x00\x00\x00\x10".to_vec();
+        cdic.extend_from_slice(&(phrases.len() as u32).to_be_bytes());
+        cdic.extend_from_slice(&8u32.to_be_bytes());
+        let mut data = vec![];
+        for (phrase, done) in phrases {
+            let off = (2 * phrases.len() + data.len()) as u16;
+            cdic.extend_from_slice(&off.to_be_bytes());
+            let flag = if *done { 0x8000 } else { 0 };
+            data.extend_from_slice(&(phrase.len() as u16 | flag).to_be_bytes());
+            data.extend_from_slice(phrase);
+        }
+        cdic.extend_from_slice(&data);
+        (huff, cdic)
+    }
+
+    #[test]
+    fn huffcdic() -> Result<()> {
+        // the third phrase is compressed itself, and made of the first two
+        let (huff, cdic) = huff_cdic(&[(b"<p>hi", true), (b"</p>", true), (b"\xff\xfe", false)]);
+        let mut huff = HuffCdic::new(&huff, &[&cdic])?;
+        let mut out = Vec::new();
+        huff.decompress(b"\xfd\xfd\xff", &mut out)?;
+        assert_eq!(out, b"<p>hi</p><p>hi</p><p>hi");
+        assert!(huff.decompress(b"\x00", &mut Vec::new()).is_err());
+
+        let (huff, cdic) = huff_cdic(&[(b"\xff", false)]);
+        let mut huff = HuffCdic::new(&huff, &[&cdic])?;
+        assert!(huff.decompress(b"\xff", &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn mobi() -> Result<()> {
+        let mut header = vec![0u8; 0xf8];
+        header[0..2].copy_from_slice(&2u16.to_be_bytes());
+        header[8..10].copy_from_slice(&1u16.to_be_bytes());
+        header[16..20].copy_from_slice(b"MOBI");
+        header[20..24].copy_from_slice(&0xe8u32.to_be_bytes());
+        header[28..32].copy_from_slice(&65001u32.to_be_bytes());
+        header[0x54..0x58].copy_from_slice(&0xf8u32.to_be_bytes());
+        header[0x58..0x5c].copy_from_slice(&6u32.to_be_bytes());
+        header[0xf2..0xf4].copy_from_slice(&3u16.to_be_bytes());
+        header.extend_from_slice("Títle".as_bytes());
+        let text = b"<p>hello\xe8\x80\x31</p><mbp:pagebreak/><p>second &amp; last</p>\x00xy\x83";
+
+        let mut book = vec![0u8; 96];
+        book[60..68].copy_from_slice(b"BOOKMOBI");
+        book[76..78].copy_from_slice(&2u16.to_be_bytes());
+        book[78..82].copy_from_slice(&96u32.to_be_bytes());
+        book[86..90].copy_from_slice(&(96 + header.len() as u32).to_be_bytes());
+        book.extend_from_slice(&header);
+        book.extend_from_slice(text);
+        assert_eq!(dump(&book)?, "title: Títle\nhello hello\nsecond & last\n");
+
+        // encrypted
+        book[96 + 12] = 2;
+        assert!(dump(&book).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,435 @@
+use super::custom::BUILTIN_SPAWNING_ADAPTERS;
+use super::ebook::is_html_block;
+use super::xml::{attr, xml_events, Xml};
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::{format_err, Result};
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["epub"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "epub".to_owned(),
+        version: 1,
+        description:
+            "Extracts the text of EPUB e-books chapter by chapter in reading order, without external programs. Each line is prefixed with the title of its chapter from the table of contents. Falls back to pandoc for books it can't read"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/epub+zip".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EpubAdapter;
+
+impl EpubAdapter {
+    pub fn new() -> EpubAdapter {
+        EpubAdapter
+    }
+}
+impl GetMetadata for EpubAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// read the XML and XHTML files of the book (a zip file), by name. Images and fonts are skipped
+async fn read_files(data: &[u8]) -> Result<BTreeMap<String, String>> {
+    use async_zip::read::stream::ZipFileReader;
+    let mut zip = ZipFileReader::new(Cursor::new(data));
+    let mut files = BTreeMap::new();
+    while let Some(mut entry) = zip.next_entry().await? {
+        let name = entry.entry().filename().to_owned();
+        let is_text = [".xml", ".opf", ".ncx", ".xhtml", ".html", ".htm"]
+            .iter()
+            .any(|ext| name.to_ascii_lowercase().ends_with(ext));
+        if !is_text {
+            zip = entry.skip().await?;
+            continue;
+        }
+        let mut buf = Vec::new();
+        let reader = entry.reader();
+        tokio::pin!(reader);
+        reader.read_to_end(&mut buf).await?;
+        files.insert(name, String::from_utf8_lossy(&buf).into_owned());
+        zip = entry.done().await?;
+    }
+    Ok(files)
+}
+
+/// the path in the zip file of `href`, relative to the file `base`. Fragments are removed
+fn resolve(base: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let mut parts: Vec<&str> = base.split('/').collect();
+    parts.pop();
+    for part in href.split('/') {
+        match part {
+            "." | "" => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    percent_decode(&parts.join("/"))
+}
+
+/// hrefs are URLs, so a space in a file name is %20
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(b)) => {
+                out.push(b);
+                i += 3;
+            }
+            (b, _) => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// the chapter files in reading order (the spine of the package document), and the table of contents file
+fn spine(files: &BTreeMap<String, String>) -> Result<(Vec<String>, Option<String>)> {
+    let container = files
+        .get("META-INF/container.xml")
+        .ok_or_else(|| format_err!("no META-INF/container.xml, not an EPUB"))?;
+    let opf_path = xml_events(container)
+        .into_iter()
+        .find_map(|e| match e {
+            Xml::Start("rootfile", attrs) => attr(&attrs, "full-path").map(str::to_owned),
+            _ => None,
+        })
+        .ok_or_else(|| format_err!("no rootfile in META-INF/container.xml"))?;
+    let opf = files
+        .get(&opf_path)
+        .ok_or_else(|| format_err!("package document {} not found", opf_path))?;
+    let mut manifest = HashMap::new();
+    let (mut nav, mut ncx, mut spine_toc) = (None, None, None);
+    let mut itemrefs = vec![];
+    for event in xml_events(opf) {
+        match event {
+            Xml::Start("item", attrs) => {
+                if let (Some(id), Some(href)) = (attr(&attrs, "id"), attr(&attrs, "href")) {
+                    let path = resolve(&opf_path, href);
+                    let properties = attr(&attrs, "properties").unwrap_or_default();
+                    if properties.split_whitespace().any(|p| p == "nav") {
+                        nav = Some(path.clone());
+                    }
+                    if attr(&attrs, "media-type") == Some("application/x-dtbncx+xml") {
+                        ncx = Some(path.clone());
+                    }
+                    manifest.insert(id.to_owned(), path);
+                }
+            }
+            Xml::Start("spine", attrs) => spine_toc = attr(&attrs, "toc").map(str::to_owned),
+            Xml::Start("itemref", attrs) => {
+                if let Some(idref) = attr(&attrs, "idref") {
+                    itemrefs.push(idref.to_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    let chapters = itemrefs
+        .iter()
+        .filter_map(|id| manifest.get(id).cloned())
+        .collect::<Vec<_>>();
+    if chapters.is_empty() {
+        return Err(format_err!("empty spine in {}", opf_path));
+    }
+    // EPUB 3 has a navigation document, EPUB 2 an NCX file
+    let toc = nav
+        .or_else(|| spine_toc.and_then(|id| manifest.get(&id).cloned()))
+        .or(ncx);
+    Ok((chapters, toc))
+}
+
+/// the titles of the chapter files from the table of contents. The first entry of a file wins
+fn toc_titles(toc_path: &str, toc: &str) -> HashMap<String, String> {
+    let mut titles = HashMap::new();
+    // in an NCX file the label comes before the link, in a navigation document the link contains it
+    let (mut label, mut href) = (String::new(), None);
+    let (mut in_label, mut in_link) = (false, false);
+    let mut add = |label: &mut String, href: &mut Option<String>| {
+        let title = label.split_whitespace().collect::<Vec<_>>().join(" ");
+        if let Some(href) = href.take() {
+            if !title.is_empty() {
+                titles.entry(resolve(toc_path, &href)).or_insert(title);
+            }
+        }
+        label.clear();
+    };
+    for event in xml_events(toc) {
+        match event {
+            Xml::Start("navLabel", _) => {
+                add(&mut label, &mut href);
+                in_label = true;
+            }
+            Xml::End("navLabel") => in_label = false,
+            Xml::Start("content", attrs) => {
+                href = attr(&attrs, "src").map(str::to_owned);
+                add(&mut label, &mut href);
+            }
+            Xml::Start("a", attrs) => {
+                href = attr(&attrs, "href").map(str::to_owned);
+                in_link = true;
+            }
+            Xml::End("a") => {
+                add(&mut label, &mut href);
+                in_link = false;
+            }
+            Xml::Text(t) if in_label || in_link => label.push_str(&t),
+            _ => {}
+        }
+    }
+    titles
+}
+
+/// the lines of text of a chapter, and its first heading
+fn chapter_text(xhtml: &str) -> (Vec<String>, Option<String>) {
+    let (mut lines, mut heading) = (vec![], None);
+    let mut line = String::new();
+    // depth inside elements without text, like <head> and <script>
+    let mut skip = 0usize;
+    let mut in_heading = false;
+    let mut flush = |line: &mut String, in_heading: bool, heading: &mut Option<String>| {
+        let text = line.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            if in_heading && heading.is_none() {
+                *heading = Some(text.clone());
+            }
+            lines.push(text);
+        }
+        line.clear();
+    };
+    for event in xml_events(xhtml) {
+        match event {
+            Xml::Start("head" | "script" | "style", _) => skip += 1,
+            Xml::End("head" | "script" | "style") => skip = skip.saturating_sub(1),
+            _ if skip > 0 => {}
+            Xml::Text(t) => line.push_str(&t.replace("&nbsp;", " ")),
+            Xml::Start(tag, _) if is_html_block(tag) => {
+                flush(&mut line, in_heading, &mut heading);
+                in_heading = matches!(tag, "h1" | "h2" | "h3");
+            }
+            Xml::End(tag) if is_html_block(tag) => {
+                flush(&mut line, in_heading, &mut heading);
+                in_heading = false;
+            }
+            _ => {}
+        }
+    }
+    flush(&mut line, in_heading, &mut heading);
+    (lines, heading)
+}
+
+/// the text of the chapters in reading order, each line prefixed with the title of its chapter
+fn extract_text(files: &BTreeMap<String, String>) -> Result<String> {
+    let (chapters, toc) = spine(files)?;
+    let titles = toc
+        .and_then(|toc| Some(toc_titles(&toc, files.get(&toc)?)))
+        .unwrap_or_default();
+    let mut out = String::new();
+    let mut title: Option<String> = None;
+    for chapter in chapters {
+        let xhtml = match files.get(&chapter) {
+            Some(xhtml) => xhtml,
+            None => {
+                debug!("chapter {} not found", chapter);
+                continue;
+            }
+        };
+        let (lines, heading) = chapter_text(xhtml);
+        // files without an entry in the table of contents usually continue the previous chapter
+        title = titles
+            .get(&chapter)
+            .cloned()
+            .or_else(|| if titles.is_empty() { heading } else { None })
+            .or(title);
+        for line in lines {
+            if let Some(title) = &title {
+                out.push_str(title);
+                out.push_str(": ");
+            }
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    // the line prefix is added after each newline, and postprocessing ends the output with one
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl FileAdapter for EpubAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let text = match read_files(&data)
+            .await
+            .and_then(|files| extract_text(&files))
+        {
+            Ok(text) => text,
+            Err(e) => {
+                debug!(
+                    "{}: {:#}, falling back to pandoc",
+                    filepath_hint.display(),
+                    e
+                );
+                let pandoc = BUILTIN_SPAWNING_ADAPTERS
+                    .iter()
+                    .find(|a| a.name == "pandoc")
+                    .expect("pandoc is a builtin adapter")
+                    .to_adapter();
+                let ai = AdaptInfo {
+                    inp: Box::pin(Cursor::new(data)),
+                    filepath_hint,
+                    line_prefix,
+                    archive_recursion_depth,
+                    postprocess,
+                    config,
+                    is_real_file: false,
+                };
+                return pandoc.adapt(ai, detection_reason).await;
+            }
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use async_zip::{write::ZipFileWriter, Compression, ZipEntryBuilder};
+    use pretty_assertions::assert_eq;
+
+    async fn create_epub(files: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        for (name, content) in files {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    const CONTAINER: &str = r#"<?xml version="1.0"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+<rootfiles><rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/></rootfiles>
+</container>"#;
+
+    #[tokio::test]
+    async fn chapters() -> Result<()> {
+        let opf = r#"<package xmlns="http://www.idpf.org/2007/opf" version="3.0"><manifest>
+<item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+<item id="c2" href="text/chapter%202.xhtml" media-type="application/xhtml+xml"/>
+<item id="c1" href="text/c1.xhtml" media-type="application/xhtml+xml"/>
+<item id="c1b" href="text/c1b.xhtml" media-type="application/xhtml+xml"/>
+<item id="cover" href="images/cover.jpg" media-type="image/jpeg"/>
+</manifest><spine><itemref idref="c1"/><itemref idref="c1b"/><itemref idref="c2"/></spine></package>"#;
+        let nav = r#"<html xmlns:epub="http://www.idpf.org/2007/ops"><body><nav epub:type="toc"><ol>
+<li><a href="text/c1.xhtml">Loomings</a></li>
+<li><a href="text/chapter%202.xhtml#start">The  Carpet-Bag</a></li>
+</ol></nav></body></html>"#;
+        let c1 = r#"<html><head><title>ignored</title><style>p { margin: 0 }</style></head><body>
+<h1>Chapter 1</h1><p>Call me <i>Ishmael</i>.</p><p>Some years ago&#8212;never mind how long</p></body></html>"#;
+        let c1b = "<html><body><p>continued</p></body></html>";
+        let c2 = "<html><body><p>I stuffed a shirt or two</p></body></html>";
+        let epub = create_epub(&[
+            ("mimetype", "application/epub+zip"),
+            ("META-INF/container.xml", CONTAINER),
+            ("OEBPS/content.opf", opf),
+            ("OEBPS/nav.xhtml", nav),
+            ("OEBPS/text/c1.xhtml", c1),
+            ("OEBPS/text/c1b.xhtml", c1b),
+            ("OEBPS/text/chapter 2.xhtml", c2),
+        ])
+        .await?;
+        let (a, d) = simple_adapt_info(&PathBuf::from("moby.epub"), Box::pin(Cursor::new(epub)));
+        let o = adapted_to_vec(loop_adapt(&EpubAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:Loomings: Chapter 1
+PREFIX:Loomings: Call me Ishmael.
+PREFIX:Loomings: Some years ago\u{2014}never mind how long
+PREFIX:Loomings: continued
+PREFIX:The Carpet-Bag: I stuffed a shirt or two
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn ncx() {
+        let ncx = r#"<ncx><navMap>
+<navPoint id="n1"><navLabel><text>Part One</text></navLabel><content src="part1.html"/>
+<navPoint id="n2"><navLabel><text>A subchapter</text></navLabel><content src="part1.html#sub"/></navPoint>
+</navPoint>
+<navPoint id="n3"><navLabel><text>Part Two</text></navLabel><content src="../part2.html"/></navPoint>
+</navMap></ncx>"#;
+        let titles = toc_titles("OEBPS/toc.ncx", ncx);
+        assert_eq!(titles["OEBPS/part1.html"], "Part One");
+        assert_eq!(titles["part2.html"], "Part Two");
+        assert_eq!(titles.len(), 2);
+    }
+
+    #[test]
+    fn headings_without_toc() {
+        let (lines, heading) =
+            chapter_text("<html><body><h2>Prologue</h2><p>It was<br/>dark</p></body></html>");
+        assert_eq!(lines, vec!["Prologue", "It was", "dark"]);
+        assert_eq!(heading.as_deref(), Some("Prologue"));
+    }
+}
@@ -0,0 +1,428 @@
+use super::binary::{one_line, proto_fields, usize_from, BinReader, Proto};
+use super::terraform::flatten_json;
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+// etcd snapshots are usually called snapshot.db, but "db" is already taken by sqlite
+static EXTENSIONS: &[&str] = &["etcd", "bolt", "boltdb"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "etcd".to_owned(),
+        version: 1,
+        description:
+            "Lists keys and values of etcd snapshots (including Kubernetes objects) and other bbolt databases"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct EtcdAdapter;
+
+impl EtcdAdapter {
+    pub fn new() -> EtcdAdapter {
+        EtcdAdapter
+    }
+}
+impl GetMetadata for EtcdAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const BOLT_MAGIC: u32 = 0xED0CDAED;
+const PAGE_HEADER_LEN: usize = 16;
+const ELEMENT_LEN: usize = 16;
+const BRANCH_PAGE: u16 = 0x01;
+const LEAF_PAGE: u16 = 0x02;
+const BUCKET_LEAF: u32 = 0x01;
+const MAX_DEPTH: usize = 64;
+
+struct Bolt<'a> {
+    r: BinReader<'a>,
+    page_size: usize,
+}
+
+struct Entry<'a> {
+    key: &'a [u8],
+    value: &'a [u8],
+    is_bucket: bool,
+}
+
+impl<'a> Bolt<'a> {
+    /// read the newer of the two meta pages, returning the db and the root bucket page
+    fn open(data: &'a [u8]) -> Result<(Bolt<'a>, u64)> {
+        let r = BinReader::le(data);
+        let mut best: Option<(u64, usize, u64)> = None;
+        let first_page_size = usize_from(r.u32(PAGE_HEADER_LEN + 8)?)?;
+        for page_start in [0, first_page_size] {
+            let meta = page_start + PAGE_HEADER_LEN;
+            if r.u32(meta).ok() != Some(BOLT_MAGIC) {
+                continue;
+            }
+            let page_size = usize_from(r.u32(meta + 8)?)?;
+            let root = r.u64(meta + 16)?;
+            let txid = r.u64(meta + 48)?;
+            if !matches!(best, Some((best_txid, ..)) if best_txid >= txid) {
+                best = Some((txid, page_size, root));
+            }
+        }
+        let (_, page_size, root) = best.ok_or_else(|| format_err!("not a bolt database"))?;
+        if page_size < PAGE_HEADER_LEN + ELEMENT_LEN {
+            return Err(format_err!("invalid bolt page size {}", page_size));
+        }
+        Ok((Bolt { r, page_size }, root))
+    }
+
+    fn page(&self, id: u64) -> Result<&'a [u8]> {
+        let start = usize_from(id)?
+            .checked_mul(self.page_size)
+            .ok_or_else(|| format_err!("page id {} out of range", id))?;
+        let overflow = usize_from(self.r.u32(start + 12)?)?;
+        let len = overflow
+            .checked_add(1)
+            .and_then(|n| n.checked_mul(self.page_size))
+            .ok_or_else(|| format_err!("page overflow {} out of range", overflow))?;
+        // the last page of the file may be shorter than its overflow claims in truncated files
+        let len = len.min(self.r.len() - start);
+        self.r.slice(start, len)
+    }
+
+    /// get the root page of a bucket stored as a leaf value. Small buckets are stored inline in the value
+    fn bucket_page(&self, value: &'a [u8]) -> Result<&'a [u8]> {
+        let root = BinReader::le(value).u64(0)?;
+        if root == 0 {
+            Ok(value.get(16..).unwrap_or_default())
+        } else {
+            self.page(root)
+        }
+    }
+
+    /// call f for every leaf element below the given page, in key order
+    fn walk(
+        &self,
+        page: &'a [u8],
+        depth: usize,
+        f: &mut dyn FnMut(Entry<'a>) -> Result<()>,
+    ) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Err(format_err!("bolt tree nested too deeply"));
+        }
+        let p = BinReader::le(page);
+        let flags = p.u16(8)?;
+        let count = usize::from(p.u16(10)?);
+        for i in 0..count {
+            let e = PAGE_HEADER_LEN + i * ELEMENT_LEN;
+            if flags & BRANCH_PAGE != 0 {
+                let child = p.u64(e + 8)?;
+                self.walk(self.page(child)?, depth + 1, f)?;
+            } else if flags & LEAF_PAGE != 0 {
+                let elem_flags = p.u32(e)?;
+                let key_start = e + usize_from(p.u32(e + 4)?)?;
+                let key_len = usize_from(p.u32(e + 8)?)?;
+                let value_len = usize_from(p.u32(e + 12)?)?;
+                f(Entry {
+                    key: p.slice(key_start, key_len)?,
+                    value: p.slice(key_start + key_len, value_len)?,
+                    is_bucket: elem_flags & BUCKET_LEAF != 0,
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// print all keys and values of a generic bolt db
+    fn dump_bucket(
+        &self,
+        page: &'a [u8],
+        path: &str,
+        depth: usize,
+        s: &mut impl Write,
+    ) -> Result<()> {
+        let mut entries = vec![];
+        self.walk(page, 0, &mut |e| {
+            entries.push(e);
+            Ok(())
+        })?;
+        for e in entries {
+            let key = one_line(e.key);
+            if e.is_bucket {
+                if depth > MAX_DEPTH {
+                    return Err(format_err!("bolt buckets nested too deeply"));
+                }
+                let child = self.bucket_page(e.value)?;
+                self.dump_bucket(child, &format!("{path}{key}/"), depth + 1, s)?;
+            } else {
+                writeln!(s, "{path}{key} = {}", one_line(e.value))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// print the strings in a protobuf message without knowing its schema, e.g. kubernetes objects.
+/// Messages with exactly a string key and value are printed as `key = value`, since that is how maps are encoded
+fn dump_proto_strings(data: &[u8], prefix: &str, depth: usize, s: &mut impl Write) -> Result<()> {
+    let printable = |b: &[u8]| {
+        std::str::from_utf8(b)
+            .map(|str| {
+                !str.chars()
+                    .any(|c| c.is_control() && c != '\n' && c != '\t')
+            })
+            .unwrap_or(false)
+    };
+    let fields = match proto_fields(data) {
+        Ok(fields) => fields,
+        Err(_) => return Ok(()),
+    };
+    if let [(1, Proto::Bytes(k)), (2, Proto::Bytes(v))] = fields.as_slice() {
+        if !k.is_empty() && printable(k) && printable(v) {
+            writeln!(s, "{prefix}: {} = {}", one_line(k), one_line(v))?;
+            return Ok(());
+        }
+    }
+    for (_, v) in fields {
+        if let Proto::Bytes(b) = v {
+            if b.is_empty() {
+                continue;
+            }
+            if printable(b) {
+                writeln!(s, "{prefix}: {}", one_line(b))?;
+            } else if depth < MAX_DEPTH {
+                dump_proto_strings(b, prefix, depth + 1, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// print one value stored in etcd
+fn dump_etcd_value(key: &str, value: &[u8], s: &mut impl Write) -> Result<()> {
+    if let Some(raw) = value.strip_prefix(b"k8s\x00") {
+        // runtime.Unknown: TypeMeta, raw object
+        for (field, v) in proto_fields(raw)? {
+            match (field, v) {
+                (1, Proto::Bytes(type_meta)) => {
+                    let parts: Vec<String> = proto_fields(type_meta)?
+                        .into_iter()
+                        .filter_map(|(_, v)| match v {
+                            Proto::Bytes(b) => Some(one_line(b)),
+                            _ => None,
+                        })
+                        .collect();
+                    writeln!(s, "{key}: {}", parts.join(" "))?;
+                }
+                (2, Proto::Bytes(object)) => dump_proto_strings(object, key, 0, s)?,
+                _ => {}
+            }
+        }
+    } else if value.starts_with(b"k8s:enc:") {
+        let provider = value.split(|b| *b == b':').nth(2).unwrap_or_default();
+        writeln!(s, "{key}: [rga: encrypted with {}]", one_line(provider))?;
+    } else if let Ok(json) = serde_json::from_slice::<serde_json::Value>(value) {
+        flatten_json(key, &json, s)?;
+    } else {
+        for line in String::from_utf8_lossy(value).lines() {
+            writeln!(s, "{key}: {}", one_line(line.as_bytes()))?;
+        }
+    }
+    Ok(())
+}
+
+fn dump_bolt(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let (db, root) = Bolt::open(data)?;
+    let root_page = db.page(root)?;
+    let mut key_bucket = None;
+    db.walk(root_page, 0, &mut |e| {
+        if e.is_bucket && e.key == b"key" {
+            key_bucket = Some(e.value);
+        }
+        Ok(())
+    })?;
+    let key_bucket = match key_bucket {
+        Some(b) => db.bucket_page(b)?,
+        // not etcd, just list everything
+        None => return db.dump_bucket(root_page, "", 0, s),
+    };
+    // etcd mvcc store: revision -> mvccpb.KeyValue, with the full history of every key
+    let mut entries = vec![];
+    db.walk(key_bucket, 0, &mut |e| {
+        entries.push(e);
+        Ok(())
+    })?;
+    for e in entries {
+        let r = BinReader::be(e.key);
+        let revision = r.u64(0).unwrap_or_default();
+        let deleted = e.key.get(17) == Some(&b't');
+        let mut key = String::new();
+        let mut value: &[u8] = &[];
+        for (field, v) in proto_fields(e.value)? {
+            match (field, v) {
+                (1, Proto::Bytes(k)) => key = one_line(k),
+                (5, Proto::Bytes(v)) => value = v,
+                _ => {}
+            }
+        }
+        if deleted {
+            writeln!(s, "{key} @ {revision} (deleted)")?;
+        } else {
+            writeln!(s, "{key} @ {revision}")?;
+            dump_etcd_value(&key, value, s)?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for EtcdAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_bolt(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    const PAGE_SIZE: usize = 1024;
+
+    fn leaf(elements: &[(&[u8], &[u8], bool)]) -> Vec<u8> {
+        let mut page = vec![0u8; PAGE_HEADER_LEN];
+        page[8..10].copy_from_slice(&LEAF_PAGE.to_le_bytes());
+        page[10..12].copy_from_slice(&(elements.len() as u16).to_le_bytes());
+        let mut data = vec![];
+        for (i, (k, v, bucket)) in elements.iter().enumerate() {
+            let pos = (elements.len() - i) * ELEMENT_LEN + data.len();
+            page.extend((*bucket as u32).to_le_bytes());
+            page.extend((pos as u32).to_le_bytes());
+            page.extend((k.len() as u32).to_le_bytes());
+            page.extend((v.len() as u32).to_le_bytes());
+            data.extend_from_slice(k);
+            data.extend_from_slice(v);
+        }
+        page.extend(data);
+        page
+    }
+
+    fn inline_bucket(elements: &[(&[u8], &[u8], bool)]) -> Vec<u8> {
+        [vec![0u8; 16], leaf(elements)].concat()
+    }
+
+    fn db(root: Vec<u8>) -> Vec<u8> {
+        let mut data = vec![0u8; PAGE_SIZE * 3];
+        for (page, txid) in [(0, 1u64), (1, 2)] {
+            let meta = page * PAGE_SIZE + PAGE_HEADER_LEN;
+            data[meta..meta + 4].copy_from_slice(&BOLT_MAGIC.to_le_bytes());
+            data[meta + 8..meta + 12].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+            data[meta + 16..meta + 24].copy_from_slice(&2u64.to_le_bytes());
+            data[meta + 48..meta + 56].copy_from_slice(&txid.to_le_bytes());
+        }
+        data[2 * PAGE_SIZE..2 * PAGE_SIZE + root.len()].copy_from_slice(&root);
+        data
+    }
+
+    fn kv(key: &[u8], value: &[u8]) -> Vec<u8> {
+        [
+            &[0x0a, key.len() as u8][..],
+            key,
+            &[0x2a, value.len() as u8],
+            value,
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn etcd() -> Result<()> {
+        let rev = |n: u64, t: bool| {
+            let mut r = [
+                n.to_be_bytes().to_vec(),
+                b"_".to_vec(),
+                0u64.to_be_bytes().to_vec(),
+            ]
+            .concat();
+            if t {
+                r.push(b't');
+            }
+            r
+        };
+        let cm = kv(
+            b"/registry/configmaps/default/app",
+            br#"{"kind":"ConfigMap","data":{"password":"hunter2"}}"#,
+        );
+        let deleted = kv(b"/registry/secrets/default/old", b"");
+        let keys = inline_bucket(&[
+            (&rev(5, false), &cm, false),
+            (&rev(6, true), &deleted, false),
+        ]);
+        let meta = inline_bucket(&[]);
+        let root = leaf(&[(b"key", &keys, true), (b"meta", &meta, true)]);
+        let mut out = Vec::new();
+        dump_bolt(&db(root), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "/registry/configmaps/default/app @ 5
+/registry/configmaps/default/app.data.password = hunter2
+/registry/configmaps/default/app.kind = ConfigMap
+/registry/secrets/default/old @ 6 (deleted)
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn generic_bolt() -> Result<()> {
+        let inner = inline_bucket(&[(b"name", b"value", false)]);
+        let root = leaf(&[(b"settings", &inner, true)]);
+        let mut out = Vec::new();
+        dump_bolt(&db(root), &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "settings/name = value\n");
+        assert!(dump_bolt(b"not a database at all, really not", &mut Vec::new()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn k8s_protobuf() -> Result<()> {
+        // runtime.Unknown{TypeMeta{"v1", "ConfigMap"}, raw: {data entry {"key", "value"}}}
+        let type_meta = b"\x0a\x02v1\x12\x09ConfigMap";
+        let object = b"\x12\x0c\x0a\x03key\x12\x05value";
+        let unknown = [
+            &[0x0a, type_meta.len() as u8][..],
+            type_meta,
+            &[0x12, object.len() as u8],
+            object,
+        ]
+        .concat();
+        let mut out = Vec::new();
+        dump_etcd_value("/cm", &[&b"k8s\x00"[..], &unknown].concat(), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "/cm: v1 ConfigMap\n/cm: key = value\n"
+        );
+        Ok(())
+    }
+}
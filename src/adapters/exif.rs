@@ -0,0 +1,907 @@
+use super::tags::mp4_atoms;
+use super::xml::{local_name, xml_events, Xml};
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::meta::{push, Field};
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+static EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "tif", "tiff", "heic", "heif", "avif", "dng",
+];
+
+/// metadata blocks and TIFF files larger than this are skipped instead of read into memory
+const MAX_LEN: u64 = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "exif".to_owned(),
+        version: 1,
+        description:
+            "Outputs the metadata of photos as lines like \"Model: value\": the camera, lens and exposure and the GPS position from EXIF, and the title, description, keywords, creator and location from XMP and IPTC. Reads jpg, png, tiff, dng and heic files without external programs, and only the parts of them with the metadata"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            ["image/jpeg", "image/png", "image/tiff", "image/heic", "image/heif"]
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // the metadata is spread over the file, and the image data between it is skipped
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ExifAdapter;
+
+impl ExifAdapter {
+    pub fn new() -> ExifAdapter {
+        ExifAdapter
+    }
+}
+impl GetMetadata for ExifAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the XMP properties, by their name without namespace. Descriptions, keywords and creators have the
+/// same names as in EXIF and IPTC, so the ones that are in both are output once
+static XMP_PROPERTIES: &[(&str, &str)] = &[
+    ("title", "Title"),
+    ("description", "Description"),
+    ("subject", "Keywords"),
+    ("creator", "Creator"),
+    ("rights", "Copyright"),
+    ("Headline", "Headline"),
+    ("Location", "Location"),
+    ("City", "City"),
+    ("State", "State"),
+    ("Country", "Country"),
+    ("Label", "Label"),
+];
+
+/// the datasets of the IPTC application record (2:x)
+static IPTC_DATASETS: &[(u8, &str)] = &[
+    (5, "Title"),
+    (25, "Keywords"),
+    (80, "Creator"),
+    (90, "City"),
+    (92, "Location"),
+    (95, "State"),
+    (101, "Country"),
+    (105, "Headline"),
+    (116, "Copyright"),
+    (120, "Description"),
+];
+
+fn read_at(file: &mut File, offset: u64, len: u64) -> Result<Vec<u8>> {
+    if len > MAX_LEN {
+        bail!("metadata block of {} bytes is too large", len);
+    }
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buf = Vec::new();
+    file.by_ref().take(len).read_to_end(&mut buf)?;
+    Ok(buf)
+}
+
+/// text that is usually UTF-8, but Latin-1 in older files
+fn text(data: &[u8]) -> String {
+    let data = data.split(|&b| b == 0).next().unwrap_or_default();
+    match std::str::from_utf8(data) {
+        Ok(s) => s.to_owned(),
+        Err(_) => data.iter().map(|&b| char::from(b)).collect(),
+    }
+}
+
+/// a number with at most two decimals, without trailing zeros
+fn decimal(x: f64) -> String {
+    let s = format!("{x:.2}");
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+/// a TIFF structure, as in EXIF blocks and TIFF files
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+/// a tag of an IFD: its id, type, count and value
+type Entry<'a> = (u16, u16, u32, &'a [u8]);
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Tiff<'a>> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Tiff {
+            data,
+            little_endian,
+        })
+    }
+
+    fn u16(&self, bytes: &[u8]) -> Option<u16> {
+        let bytes = bytes.get(..2)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, bytes: &[u8]) -> Option<u32> {
+        let bytes = bytes.get(..4)?.try_into().ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// the entries of the IFD at `offset`
+    fn ifd(&self, offset: u32) -> Vec<Entry<'a>> {
+        let mut entries = vec![];
+        let offset = offset as usize;
+        let count = match self.data.get(offset..).and_then(|d| self.u16(d)) {
+            Some(count) => count as usize,
+            None => return entries,
+        };
+        for i in 0..count {
+            let entry = match self.data.get(offset + 2 + i * 12..offset + 14 + i * 12) {
+                Some(entry) => entry,
+                None => break,
+            };
+            let (tag, ty, count) = match (
+                self.u16(entry),
+                self.u16(&entry[2..]),
+                self.u32(&entry[4..]),
+            ) {
+                (Some(tag), Some(ty), Some(count)) => (tag, ty, count),
+                _ => break,
+            };
+            let size = match ty {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            } * count as usize;
+            // values of up to 4 bytes are in the entry, longer ones at an offset
+            let value = if size <= 4 {
+                Some(&entry[8..8 + size])
+            } else {
+                self.u32(&entry[8..])
+                    .and_then(|o| self.data.get(o as usize..o as usize + size))
+            };
+            if let Some(value) = value {
+                entries.push((tag, ty, count, value));
+            }
+        }
+        entries
+    }
+
+    /// the first value of a SHORT or LONG tag
+    fn number(&self, (_, ty, _, value): &Entry) -> Option<u32> {
+        match ty {
+            1 | 7 => value.first().map(|&b| u32::from(b)),
+            3 => self.u16(value).map(u32::from),
+            4 => self.u32(value),
+            _ => None,
+        }
+    }
+
+    /// the i-th value of a RATIONAL tag
+    fn rational(&self, (_, ty, _, value): &Entry, i: usize) -> Option<f64> {
+        if *ty != 5 {
+            return None;
+        }
+        let n = self.u32(value.get(i * 8..)?)?;
+        let d = self.u32(value.get(i * 8 + 4..)?)?;
+        if d == 0 {
+            return None;
+        }
+        Some(f64::from(n) / f64::from(d))
+    }
+
+    /// the UTF-16 text of the tags Windows uses for titles and keywords, in little endian
+    fn utf16(value: &[u8]) -> String {
+        let units = value
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0)
+            .collect::<Vec<_>>();
+        String::from_utf16_lossy(&units)
+    }
+
+    /// the EXIF UserComment, with the character set in its first 8 bytes
+    fn user_comment(&self, value: &[u8]) -> String {
+        match value.split_at(value.len().min(8)) {
+            (b"UNICODE\0", rest) => {
+                let units = rest
+                    .chunks_exact(2)
+                    .filter_map(|c| self.u16(c))
+                    .take_while(|&u| u != 0)
+                    .collect::<Vec<_>>();
+                String::from_utf16_lossy(&units)
+            }
+            (_, rest) => text(rest),
+        }
+    }
+}
+
+/// the fields of an EXIF block or TIFF file. TIFF files also have their XMP and IPTC in tags
+fn tiff_fields(data: &[u8], fields: &mut Vec<Field>) {
+    let tiff = match Tiff::new(data) {
+        Some(tiff) => tiff,
+        None => return,
+    };
+    let ifd0 = match tiff.u32(&data[4..]) {
+        Some(offset) => tiff.ifd(offset),
+        None => return,
+    };
+    let mut sub_ifds = vec![];
+    for entry in &ifd0 {
+        let (tag, _, _, value) = *entry;
+        match tag {
+            0x010F => push(fields, "Make", &text(value)),
+            0x0110 => push(fields, "Model", &text(value)),
+            0x010E => push(fields, "Description", &text(value)),
+            0x0131 => push(fields, "Software", &text(value)),
+            0x013B => push(fields, "Creator", &text(value)),
+            // the photographer's and the editor's copyright, separated by NUL
+            0x8298 => {
+                for copyright in value.split(|&b| b == 0) {
+                    push(fields, "Copyright", &text(copyright));
+                }
+            }
+            0x9C9B => push(fields, "Title", &Tiff::utf16(value)),
+            0x9C9C => push(fields, "Comment", &Tiff::utf16(value)),
+            0x9C9D => push(fields, "Creator", &Tiff::utf16(value)),
+            0x9C9E => {
+                for keyword in Tiff::utf16(value).split(';') {
+                    push(fields, "Keywords", keyword);
+                }
+            }
+            0x9C9F => push(fields, "Description", &Tiff::utf16(value)),
+            700 => xmp_fields(&String::from_utf8_lossy(value), fields),
+            33723 => iptc_fields(value, fields),
+            0x8769 | 0x8825 => {
+                if let Some(offset) = tiff.number(entry) {
+                    sub_ifds.push((tag, tiff.ifd(offset)));
+                }
+            }
+            _ => {}
+        }
+    }
+    for (tag, ifd) in sub_ifds {
+        if tag == 0x8769 {
+            exif_ifd_fields(&tiff, &ifd, fields);
+        } else {
+            gps_fields(&tiff, &ifd, fields);
+        }
+    }
+}
+
+/// the camera settings in the EXIF IFD
+fn exif_ifd_fields(tiff: &Tiff, ifd: &[Entry], fields: &mut Vec<Field>) {
+    for entry in ifd {
+        let (tag, _, _, value) = *entry;
+        match tag {
+            0xA433 => push(fields, "LensMake", &text(value)),
+            0xA434 => push(fields, "LensModel", &text(value)),
+            0x9003 => push(fields, "DateTimeOriginal", &text(value)),
+            0x829A => {
+                if let Some(t) = tiff.rational(entry, 0).filter(|&t| t > 0.0) {
+                    let exposure = if t < 1.0 {
+                        format!("1/{}", (1.0 / t).round())
+                    } else {
+                        decimal(t)
+                    };
+                    push(fields, "ExposureTime", &exposure);
+                }
+            }
+            0x829D => {
+                if let Some(f) = tiff.rational(entry, 0) {
+                    push(fields, "FNumber", &format!("f/{}", decimal(f)));
+                }
+            }
+            0x8827 => {
+                if let Some(iso) = tiff.number(entry) {
+                    push(fields, "ISO", &iso.to_string());
+                }
+            }
+            0x920A => {
+                if let Some(mm) = tiff.rational(entry, 0) {
+                    push(fields, "FocalLength", &format!("{} mm", decimal(mm)));
+                }
+            }
+            0x9286 => push(fields, "Comment", &tiff.user_comment(value)),
+            _ => {}
+        }
+    }
+}
+
+/// the position in the GPS IFD, in decimal degrees
+fn gps_fields(tiff: &Tiff, ifd: &[Entry], fields: &mut Vec<Field>) {
+    let find = |tag: u16| ifd.iter().find(|e| e.0 == tag);
+    let degrees = |reference: u16, tag: u16, negative: u8| -> Option<f64> {
+        let entry = find(tag)?;
+        let d = tiff.rational(entry, 0)?
+            + tiff.rational(entry, 1)? / 60.0
+            + tiff.rational(entry, 2)? / 3600.0;
+        match find(reference).and_then(|r| r.3.first()) {
+            Some(&r) if r == negative => Some(-d),
+            _ => Some(d),
+        }
+    };
+    if let (Some(latitude), Some(longitude)) = (degrees(1, 2, b'S'), degrees(3, 4, b'W')) {
+        push(
+            fields,
+            "GPSPosition",
+            &format!("{latitude:.6}, {longitude:.6}"),
+        );
+    }
+    if let Some(altitude) = find(6).and_then(|e| tiff.rational(e, 0)) {
+        // the reference is 1 below sea level
+        let below = find(5).and_then(|r| r.3.first()) == Some(&1);
+        let altitude = if below { -altitude } else { altitude };
+        push(fields, "GPSAltitude", &format!("{} m", decimal(altitude)));
+    }
+}
+
+/// the properties of an XMP packet, as elements or as attributes of rdf:Description
+fn xmp_fields(xmp: &str, fields: &mut Vec<Field>) {
+    let property = |name: &str| {
+        XMP_PROPERTIES
+            .iter()
+            .find(|(p, _)| *p == name)
+            .map(|(_, field)| *field)
+    };
+    let mut current: Option<(&str, &'static str)> = None;
+    for event in xml_events(xmp) {
+        match event {
+            Xml::Start("Description", attrs) => {
+                for (key, value) in &attrs {
+                    if let Some(field) = property(local_name(key)) {
+                        push(fields, field, value);
+                    }
+                }
+            }
+            Xml::Start(element, _) => {
+                if current.is_none() {
+                    current = property(element).map(|field| (element, field));
+                }
+            }
+            Xml::Text(text) => {
+                if let Some((_, field)) = current {
+                    push(fields, field, &text);
+                }
+            }
+            Xml::End(element) => {
+                if current.map_or(false, |(e, _)| e == element) {
+                    current = None;
+                }
+            }
+        }
+    }
+}
+
+/// the datasets of IPTC-NAA records, as in the IPTC block of Photoshop and in TIFF files
+fn iptc_fields(data: &[u8], fields: &mut Vec<Field>) {
+    let mut pos = 0;
+    while let Some(&[0x1C, record, dataset, len_hi, len_lo]) = data.get(pos..pos + 5) {
+        // longer datasets have the length of their length here, they are not text
+        if len_hi & 0x80 != 0 {
+            break;
+        }
+        let len = usize::from(u16::from_be_bytes([len_hi, len_lo]));
+        let value = match data.get(pos + 5..pos + 5 + len) {
+            Some(value) => value,
+            None => break,
+        };
+        if record == 2 {
+            if let Some((_, name)) = IPTC_DATASETS.iter().find(|(d, _)| *d == dataset) {
+                push(fields, name, &text(value));
+            }
+        }
+        pos += 5 + len;
+    }
+}
+
+/// the IPTC block in the image resources of Photoshop, which JPEG files have in an APP13 segment
+fn photoshop_fields(data: &[u8], fields: &mut Vec<Field>) {
+    let mut pos = 0;
+    while data.get(pos..pos + 4) == Some(b"8BIM") {
+        let id = match data.get(pos + 4..pos + 6) {
+            Some(id) => u16::from_be_bytes([id[0], id[1]]),
+            None => break,
+        };
+        // a Pascal string padded to an even length
+        let name_len = match data.get(pos + 6) {
+            Some(&len) => (usize::from(len) + 2) & !1,
+            None => break,
+        };
+        let size_pos = pos + 6 + name_len;
+        let size = match data.get(size_pos..size_pos + 4) {
+            Some(size) => u32::from_be_bytes(size.try_into().unwrap()) as usize,
+            None => break,
+        };
+        let value = match data.get(size_pos + 4..size_pos + 4 + size) {
+            Some(value) => value,
+            None => break,
+        };
+        if id == 0x0404 {
+            iptc_fields(value, fields);
+        }
+        pos = size_pos + 4 + ((size + 1) & !1);
+    }
+}
+
+/// the EXIF, XMP and IPTC blocks in the APP1 and APP13 segments of JPEG files
+fn jpeg_fields(file: &mut File, fields: &mut Vec<Field>) -> Result<()> {
+    let mut pos = 2;
+    loop {
+        let header = read_at(file, pos, 4)?;
+        if header.len() < 4 || header[0] != 0xFF {
+            return Ok(());
+        }
+        match header[1] {
+            // padding
+            0xFF => {
+                pos += 1;
+                continue;
+            }
+            // the image data starts, the metadata is before it
+            0xDA | 0xD9 => return Ok(()),
+            0x01 | 0xD0..=0xD7 => {
+                pos += 2;
+                continue;
+            }
+            _ => {}
+        }
+        let len = u64::from(u16::from_be_bytes([header[2], header[3]]));
+        if len < 2 {
+            return Ok(());
+        }
+        if header[1] == 0xE1 || header[1] == 0xED {
+            let data = read_at(file, pos + 4, len - 2)?;
+            if let Some(tiff) = data.strip_prefix(b"Exif\0\0") {
+                tiff_fields(tiff, fields);
+            } else if let Some(xmp) = data.strip_prefix(b"http://ns.adobe.com/xap/1.0/\0") {
+                xmp_fields(&String::from_utf8_lossy(xmp), fields);
+            } else if let Some(resources) = data.strip_prefix(b"Photoshop 3.0\0") {
+                photoshop_fields(resources, fields);
+            }
+        }
+        pos += 2 + len;
+    }
+}
+
+/// the eXIf chunk and the text chunks of PNG files, which have XMP in an iTXt chunk
+fn png_fields(file: &mut File, fields: &mut Vec<Field>) -> Result<()> {
+    let text_field = |keyword: &str| match keyword {
+        "Title" => Some("Title"),
+        "Author" => Some("Creator"),
+        "Description" => Some("Description"),
+        "Copyright" => Some("Copyright"),
+        "Comment" => Some("Comment"),
+        "Software" => Some("Software"),
+        _ => None,
+    };
+    let mut pos = 8;
+    loop {
+        let header = read_at(file, pos, 8)?;
+        if header.len() < 8 {
+            return Ok(());
+        }
+        let len = u64::from(u32::from_be_bytes(header[..4].try_into().unwrap()));
+        match &header[4..] {
+            b"eXIf" => tiff_fields(&read_at(file, pos + 8, len)?, fields),
+            b"tEXt" => {
+                let data = read_at(file, pos + 8, len)?;
+                if let Some(nul) = data.iter().position(|&b| b == 0) {
+                    let keyword = text(&data[..nul]);
+                    if let Some(field) = text_field(&keyword) {
+                        let value = data[nul + 1..].iter().map(|&b| char::from(b));
+                        push(fields, field, &value.collect::<String>());
+                    }
+                }
+            }
+            b"iTXt" => {
+                // keyword, compression flag and method, language, translated keyword, text
+                let data = read_at(file, pos + 8, len)?;
+                let mut parts = data.splitn(2, |&b| b == 0);
+                let keyword = text(parts.next().unwrap_or_default());
+                let rest = parts.next().unwrap_or_default();
+                if rest.first() == Some(&0) {
+                    let mut parts = rest.get(2..).unwrap_or_default().splitn(3, |&b| b == 0);
+                    let value = String::from_utf8_lossy(parts.nth(2).unwrap_or_default());
+                    if keyword == "XML:com.adobe.xmp" {
+                        xmp_fields(&value, fields);
+                    } else if let Some(field) = text_field(&keyword) {
+                        push(fields, field, &value);
+                    }
+                }
+            }
+            b"IEND" => return Ok(()),
+            _ => {}
+        }
+        pos += 12 + len;
+    }
+}
+
+/// the items of a HEIF meta box (iinf) as their id, type and content type
+fn heif_items(iinf: &[u8]) -> Vec<(u32, &[u8], String)> {
+    let mut items = vec![];
+    let entries = match iinf.first() {
+        Some(0) => iinf.get(6..),
+        Some(_) => iinf.get(8..),
+        None => None,
+    };
+    for (ty, infe) in mp4_atoms(entries.unwrap_or_default()) {
+        let (id, rest) = match (ty, infe.first()) {
+            (b"infe", Some(2)) => match infe.get(4..6) {
+                Some(id) => (u32::from(u16::from_be_bytes([id[0], id[1]])), &infe[6..]),
+                None => continue,
+            },
+            (b"infe", Some(3)) => match infe.get(4..8) {
+                Some(id) => (u32::from_be_bytes(id.try_into().unwrap()), &infe[8..]),
+                None => continue,
+            },
+            _ => continue,
+        };
+        // the protection index, the type and the name of the item, then the content type of mime items
+        let item_type = match rest.get(2..6) {
+            Some(item_type) => item_type,
+            None => continue,
+        };
+        let content_type = rest[6..]
+            .splitn(3, |&b| b == 0)
+            .nth(1)
+            .map(text)
+            .unwrap_or_default();
+        items.push((id, item_type, content_type));
+    }
+    items
+}
+
+/// the extents of the items in a HEIF meta box (iloc) in the file, as offset and length
+fn heif_locations(iloc: &[u8]) -> Option<Vec<(u32, Vec<(u64, u64)>)>> {
+    let mut pos = 0;
+    let mut uint = |size: u8| -> Option<u64> {
+        let bytes = iloc.get(pos..pos + usize::from(size))?;
+        pos += usize::from(size);
+        Some(bytes.iter().fold(0, |n, &b| n << 8 | u64::from(b)))
+    };
+    let version = uint(1)?;
+    uint(3)?;
+    let sizes = uint(1)?;
+    let (offset_size, length_size) = ((sizes >> 4) as u8, (sizes & 15) as u8);
+    let sizes = uint(1)?;
+    let base_offset_size = (sizes >> 4) as u8;
+    let index_size = if version >= 1 { (sizes & 15) as u8 } else { 0 };
+    let id_size = if version < 2 { 2 } else { 4 };
+    let mut locations = vec![];
+    for _ in 0..uint(id_size)? {
+        let id = uint(id_size)? as u32;
+        // only items in the file itself, not in other items or files
+        let construction_method = if version >= 1 { uint(2)? & 15 } else { 0 };
+        uint(2)?;
+        let base_offset = uint(base_offset_size)?;
+        let mut extents = vec![];
+        for _ in 0..uint(2)? {
+            uint(index_size)?;
+            let offset = uint(offset_size)?;
+            extents.push((base_offset + offset, uint(length_size)?));
+        }
+        if construction_method == 0 {
+            locations.push((id, extents));
+        }
+    }
+    Some(locations)
+}
+
+/// the Exif and XMP items of HEIF files (heic, avif)
+fn heif_fields(file: &mut File, file_len: u64, fields: &mut Vec<Field>) -> Result<()> {
+    let mut pos = 0;
+    let meta = loop {
+        let header = read_at(file, pos, 16)?;
+        if header.len() < 8 {
+            return Ok(());
+        }
+        let (header_len, size) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (8, file_len - pos),
+            1 if header.len() == 16 => (16, u64::from_be_bytes(header[8..].try_into().unwrap())),
+            size => (8, u64::from(size)),
+        };
+        if size < header_len {
+            return Ok(());
+        }
+        if &header[4..8] == b"meta" {
+            break read_at(file, pos + header_len, size - header_len)?;
+        }
+        pos += size;
+    };
+    // meta has a version and flags before its boxes
+    let boxes = mp4_atoms(meta.get(4..).unwrap_or_default());
+    let find = |ty: &[u8]| boxes.iter().find(|(t, _)| *t == ty).map(|(_, b)| *b);
+    let (iinf, iloc) = match (find(b"iinf"), find(b"iloc")) {
+        (Some(iinf), Some(iloc)) => (iinf, iloc),
+        _ => return Ok(()),
+    };
+    let locations = heif_locations(iloc).unwrap_or_default();
+    for (id, item_type, content_type) in heif_items(iinf) {
+        let is_xmp = item_type == b"mime" && content_type == "application/rdf+xml";
+        if item_type != b"Exif" && !is_xmp {
+            continue;
+        }
+        let extents = match locations.iter().find(|(i, _)| *i == id) {
+            Some((_, extents)) => extents,
+            None => continue,
+        };
+        let mut data = vec![];
+        for &(offset, len) in extents {
+            data.extend(read_at(file, offset, len)?);
+        }
+        if is_xmp {
+            xmp_fields(&String::from_utf8_lossy(&data), fields);
+        } else if let Some(offset) = data.get(..4) {
+            // the offset of the TIFF header comes first
+            let offset = u32::from_be_bytes(offset.try_into().unwrap()) as usize;
+            tiff_fields(data.get(4 + offset..).unwrap_or_default(), fields);
+        }
+    }
+    Ok(())
+}
+
+/// the metadata of an image file, recognized by its content
+fn image_meta(path: &Path) -> Result<Vec<Field>> {
+    let mut file = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let len = file.metadata()?.len();
+    let head = read_at(&mut file, 0, 12)?;
+    let mut fields = vec![];
+    if head.starts_with(&[0xFF, 0xD8]) {
+        jpeg_fields(&mut file, &mut fields)?;
+    } else if head.starts_with(b"\x89PNG\r\n\x1a\n") {
+        png_fields(&mut file, &mut fields)?;
+    } else if Tiff::new(&head).is_some() {
+        // the tags of TIFF files can be anywhere in them
+        if len <= MAX_LEN {
+            tiff_fields(&read_at(&mut file, 0, len)?, &mut fields);
+        }
+    } else if head.get(4..8) == Some(b"ftyp") {
+        heif_fields(&mut file, len, &mut fields)?;
+    }
+    Ok(fields)
+}
+
+#[async_trait]
+impl FileAdapter for ExifAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let fields = {
+            let path = filepath_hint.clone();
+            tokio::task::spawn_blocking(move || image_meta(&path)).await??
+        };
+        let text = fields
+            .iter()
+            .map(|(name, value)| format!("{name}: {value}\n"))
+            .collect::<String>();
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn owned(fields: &[(&'static str, &str)]) -> Vec<Field> {
+        fields.iter().map(|(n, v)| (*n, v.to_string())).collect()
+    }
+
+    /// a little endian TIFF with IFD0, an EXIF IFD and a GPS IFD
+    fn exif_block() -> Vec<u8> {
+        // IFD0 at 8: Make, Model, ExifIFD, GPSIFD. The values follow the IFDs
+        let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+        let mut values = vec![];
+        let ifd0_len = 2 + 4 * 12 + 4;
+        let exif_len = 2 + 2 * 12 + 4;
+        let gps_len = 2 + 4 * 12 + 4;
+        let values_start = 8 + ifd0_len + exif_len + gps_len;
+        let mut entry = |ifd: &mut Vec<u8>, tag: u16, ty: u16, count: u32, value: &[u8]| {
+            ifd.extend_from_slice(&tag.to_le_bytes());
+            ifd.extend_from_slice(&ty.to_le_bytes());
+            ifd.extend_from_slice(&count.to_le_bytes());
+            if value.len() <= 4 {
+                let mut inline = value.to_vec();
+                inline.resize(4, 0);
+                ifd.extend_from_slice(&inline);
+            } else {
+                ifd.extend_from_slice(&((values_start + values.len()) as u32).to_le_bytes());
+                values.extend_from_slice(value);
+            }
+        };
+        let rationals = |r: &[(u32, u32)]| {
+            r.iter()
+                .flat_map(|(n, d)| [n.to_le_bytes(), d.to_le_bytes()].concat())
+                .collect::<Vec<u8>>()
+        };
+        let mut ifd0 = 4u16.to_le_bytes().to_vec();
+        entry(&mut ifd0, 0x010F, 2, 6, b"Canon\0");
+        entry(&mut ifd0, 0x0110, 2, 13, b"Canon EOS R5\0");
+        entry(
+            &mut ifd0,
+            0x8769,
+            4,
+            1,
+            &((8 + ifd0_len) as u32).to_le_bytes(),
+        );
+        entry(
+            &mut ifd0,
+            0x8825,
+            4,
+            1,
+            &((8 + ifd0_len + exif_len) as u32).to_le_bytes(),
+        );
+        ifd0.extend_from_slice(&[0; 4]);
+        let mut exif = 2u16.to_le_bytes().to_vec();
+        entry(&mut exif, 0x829A, 5, 1, &rationals(&[(1, 250)]));
+        entry(&mut exif, 0x8827, 3, 1, &400u16.to_le_bytes());
+        exif.extend_from_slice(&[0; 4]);
+        let mut gps = 4u16.to_le_bytes().to_vec();
+        entry(&mut gps, 1, 2, 2, b"N\0");
+        entry(
+            &mut gps,
+            2,
+            5,
+            3,
+            &rationals(&[(48, 1), (51, 1), (2964, 100)]),
+        );
+        entry(&mut gps, 3, 2, 2, b"W\0");
+        entry(
+            &mut gps,
+            4,
+            5,
+            3,
+            &rationals(&[(2, 1), (17, 1), (4020, 100)]),
+        );
+        gps.extend_from_slice(&[0; 4]);
+        tiff.extend_from_slice(&ifd0);
+        tiff.extend_from_slice(&exif);
+        tiff.extend_from_slice(&gps);
+        tiff.extend_from_slice(&values);
+        tiff
+    }
+
+    fn exif_fields() -> Vec<Field> {
+        owned(&[
+            ("Make", "Canon"),
+            ("Model", "Canon EOS R5"),
+            ("ExposureTime", "1/250"),
+            ("ISO", "400"),
+            ("GPSPosition", "48.858233, -2.294500"),
+        ])
+    }
+
+    static XMP: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/"><rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+<rdf:Description rdf:about="" xmlns:photoshop="http://ns.adobe.com/photoshop/1.0/" photoshop:City="Paris">
+<dc:description><rdf:Alt><rdf:li xml:lang="x-default">Sunset over the river</rdf:li></rdf:Alt></dc:description>
+<dc:subject><rdf:Bag><rdf:li>sunset</rdf:li><rdf:li>river</rdf:li></rdf:Bag></dc:subject>
+</rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    fn xmp() {
+        let mut fields = vec![];
+        xmp_fields(XMP, &mut fields);
+        assert_eq!(
+            fields,
+            owned(&[
+                ("City", "Paris"),
+                ("Description", "Sunset over the river"),
+                ("Keywords", "sunset, river"),
+            ])
+        );
+    }
+
+    #[test]
+    fn jpeg() -> Result<()> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        let mut segment = |marker: u8, data: &[u8]| {
+            jpeg.extend_from_slice(&[0xFF, marker]);
+            jpeg.extend_from_slice(&((data.len() + 2) as u16).to_be_bytes());
+            jpeg.extend_from_slice(data);
+        };
+        segment(0xE1, &[&b"Exif\0\0"[..], &exif_block()[..]].concat());
+        segment(
+            0xE1,
+            &[&b"http://ns.adobe.com/xap/1.0/\0"[..], XMP.as_bytes()].concat(),
+        );
+        let iptc = [
+            &b"\x1c\x02\x19\x00\x05river"[..],
+            b"\x1c\x02\x19\x00\x06bridge",
+            b"\x1c\x02\x78\x00\x15Sunset over the river",
+        ]
+        .concat();
+        let mut photoshop = b"Photoshop 3.0\08BIM\x04\x04\0\0".to_vec();
+        photoshop.extend_from_slice(&(iptc.len() as u32).to_be_bytes());
+        photoshop.extend_from_slice(&iptc);
+        segment(0xED, &photoshop);
+        segment(0xDA, b"\0\0");
+        jpeg.extend_from_slice(&[0xFF, 0xE1, 0xFF, 0xFF]);
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("photo.jpg");
+        std::fs::write(&path, jpeg)?;
+        let mut expected = exif_fields();
+        expected.extend(owned(&[
+            ("City", "Paris"),
+            ("Description", "Sunset over the river"),
+            ("Keywords", "sunset, river, bridge"),
+        ]));
+        assert_eq!(image_meta(&path)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn heic() -> Result<()> {
+        let atom = |ty: &[u8], content: &[u8]| {
+            [&((content.len() + 8) as u32).to_be_bytes()[..], ty, content].concat()
+        };
+        let ftyp = atom(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let exif = [&[0, 0, 0, 0][..], &exif_block()[..]].concat();
+        let infe = atom(b"infe", b"\x02\0\0\0\0\x01\0\0Exif\0");
+        let iinf = atom(b"iinf", &[&[0, 0, 0, 0, 0, 1][..], &infe[..]].concat());
+        let iloc_len = 8 + 4 + 2 + 2 + 2 + 2 + 2 + 4 + 4;
+        let meta_len = 8 + 4 + iinf.len() + iloc_len;
+        let mdat_start = ftyp.len() + meta_len + 8;
+        let iloc = atom(
+            b"iloc",
+            &[
+                &[0, 0, 0, 0, 0x44, 0x00, 0, 1, 0, 1, 0, 0, 0, 1][..],
+                &(mdat_start as u32).to_be_bytes(),
+                &(exif.len() as u32).to_be_bytes(),
+            ]
+            .concat(),
+        );
+        let meta = atom(b"meta", &[&[0, 0, 0, 0][..], &iinf[..], &iloc[..]].concat());
+        assert_eq!(meta.len(), meta_len);
+        let heic = [ftyp, meta, atom(b"mdat", &exif)].concat();
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("photo.heic");
+        std::fs::write(&path, heic)?;
+        assert_eq!(image_meta(&path)?, exif_fields());
+        Ok(())
+    }
+}
@@ -1,14 +1,17 @@
 use super::*;
 use super::{custom::map_exe_error, writing::async_writeln};
+use crate::config::RgaConfig;
+use crate::policy;
 use anyhow::*;
 use async_trait::async_trait;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
 use std::process::Stdio;
 use tokio::io::AsyncWrite;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
 use writing::WritingFileAdapter;
 // todo:
 // maybe todo: read list of extensions from
@@ -19,9 +22,9 @@ static EXTENSIONS: &[&str] = &["mkv", "mp4", "avi", "mp3", "ogg", "flac", "webm"
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "ffmpeg".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Uses ffmpeg to extract video metadata/chapters, subtitles, lyrics, and other metadata"
+            "Uses ffmpeg to extract video metadata/chapters, subtitles, lyrics, and other metadata. Chapters and subtitles start with their time, like [00:01:23]. Image subtitles (Blu-ray and DVD) are recognized with tesseract if --rga-ffmpeg-ocr-subtitles is set"
                 .to_owned(),
         recurses: false,
         fast_matchers: EXTENSIONS
@@ -30,10 +33,17 @@ lazy_static! {
             .collect(),
         slow_matchers: None,
         disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Path,
         keep_fast_matchers_if_accurate: true
     };
+    /// a cue in WebVTT, like `09:55.195 --> 09:56.730`. The hours are left out in short videos
+    static ref CUE: Regex = Regex::new(r"^((?:\d+:)?\d+:\d+)(?:\.\d+)?\s+-->").unwrap();
 }
 
+/// the subtitle codecs that are images, which ffmpeg can't convert to text
+static BITMAP_CODECS: &[&str] = &["hdmv_pgs_subtitle", "dvd_subtitle", "dvb_subtitle", "xsub"];
+
 #[derive(Default, Clone)]
 pub struct FFmpegAdapter;
 
@@ -50,11 +60,124 @@ impl GetMetadata for FFmpegAdapter {
 
 #[derive(Serialize, Deserialize)]
 struct FFprobeOutput {
+    #[serde(default)]
     streams: Vec<FFprobeStream>,
+    #[serde(default)]
+    chapters: Vec<FFprobeChapter>,
 }
 #[derive(Serialize, Deserialize)]
 struct FFprobeStream {
     index: i32, // stream index
+    #[serde(default)]
+    codec_name: Option<String>,
+}
+#[derive(Serialize, Deserialize)]
+struct FFprobeChapter {
+    start_time: String, // in seconds
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// the subtitle OCR options for the cache key, since they change the output. None for the defaults
+pub fn options_key(config: &RgaConfig) -> Option<String> {
+    if !config.ffmpeg_ocr_subtitles {
+        return None;
+    }
+    Some(format!(
+        "ffmpeg:ocr-subtitles {}",
+        config.ocr_languages.join("+")
+    ))
+}
+
+/// a time in seconds as the prefix of a line, like `[00:01:23]`
+fn timestamp(seconds: u64) -> String {
+    format!(
+        "[{:02}:{:02}:{:02}]",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// the start of a WebVTT cue as the prefix of its lines
+fn cue_start(line: &str) -> Option<String> {
+    let c = CUE.captures(line.trim())?;
+    let seconds = c[1]
+        .split(':')
+        .fold(0, |acc, n| acc * 60 + n.parse::<u64>().unwrap_or(0));
+    Some(timestamp(seconds))
+}
+
+/// recognizes the text of an image subtitle stream: ffmpeg renders it to one image per second in
+/// which it changed, named by the second, and tesseract reads each of them
+async fn ocr_subtitles(
+    config: &RgaConfig,
+    inp_fname: &Path,
+    index: i32,
+    oup: &mut Pin<Box<dyn AsyncWrite + Send>>,
+) -> Result<()> {
+    let dir = tempfile::tempdir().context("creating temporary directory")?;
+    let out = policy::command(&config.policy, "ffmpeg", inp_fname)?
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(inp_fname)
+        .arg("-filter_complex")
+        .arg(format!("[0:{index}]fps=1,mpdecimate[v]"))
+        .args(["-map", "[v]", "-vsync", "0", "-frame_pts", "1"])
+        .arg(dir.path().join("%d.png"))
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed."))?;
+    if !out.status.success() {
+        return Err(format_err!(
+            "ffmpeg failed to render subtitles: {:?}\n{}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr)
+        ));
+    }
+    let mut frames = vec![];
+    for entry in std::fs::read_dir(dir.path())? {
+        let path = entry?.path();
+        if let Some(second) = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .and_then(|s| s.parse::<u64>().ok())
+        {
+            frames.push((second, path));
+        }
+    }
+    frames.sort();
+    let mut previous = String::new();
+    for (second, frame) in frames {
+        let mut cmd = policy::command(&config.policy, "tesseract", inp_fname)?;
+        cmd.arg(&frame).arg("stdout");
+        if !config.ocr_languages.is_empty() {
+            cmd.arg("-l").arg(config.ocr_languages.join("+"));
+        }
+        let out = cmd
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "tesseract", "Install tesseract-ocr."))?;
+        if !out.status.success() {
+            return Err(format_err!(
+                "tesseract failed: {:?}\n{}",
+                out.status,
+                String::from_utf8_lossy(&out.stderr)
+            ));
+        }
+        let text = String::from_utf8_lossy(&out.stdout).trim().to_owned();
+        // the blank frames between subtitles, and subtitles that only moved
+        if text.is_empty() || text == previous {
+            continue;
+        }
+        let time = timestamp(second);
+        for line in text.lines().filter(|l| !l.trim().is_empty()) {
+            async_writeln!(oup, "{time} {line}")?;
+        }
+        previous = text;
+    }
+    Ok(())
 }
 
 #[async_trait]
@@ -64,24 +187,20 @@ impl WritingFileAdapter for FFmpegAdapter {
         _detection_reason: &FileMatcher,
         mut oup: Pin<Box<dyn AsyncWrite + Send>>,
     ) -> Result<()> {
+        // ffprobe and ffmpeg need to seek, so members of archives are spooled to a temporary file first (InputKind::Path)
         let AdaptInfo {
-            is_real_file,
             filepath_hint,
-            line_prefix,
+            config,
             ..
         } = ai;
-        if !is_real_file {
-            // we *could* probably adapt this to also work based on streams,
-            // it would require using a BufReader to read at least part of the file to memory
-            // but really when would you want to search for videos within archives?
-            // So instead, we only run this adapter if the file is a actual file on disk for now
-            async_writeln!(oup, "{line_prefix}[rga: skipping video in archive]\n")?;
-            return Ok(());
-        }
         let inp_fname = filepath_hint;
+        let command = |program| policy::command(&config.policy, program, &inp_fname);
         let spawn_fail = |e| map_exe_error(e, "ffprobe", "Make sure you have ffmpeg installed.");
-        let subtitle_streams = {
-            let probe = Command::new("ffprobe")
+        let FFprobeOutput {
+            streams: subtitle_streams,
+            chapters,
+        } = {
+            let probe = command("ffprobe")?
                 .args(vec![
                     "-v",
                     "error", // show all errors
@@ -90,7 +209,8 @@ impl WritingFileAdapter for FFmpegAdapter {
                     "-of",
                     "json", // use json as output format
                     "-show_entries",
-                    "stream=index", // show index of subtitle streams
+                    "stream=index,codec_name", // show index and codec of subtitle streams
+                    "-show_chapters",
                 ])
                 .arg("-i")
                 .arg(&inp_fname)
@@ -104,12 +224,11 @@ impl WritingFileAdapter for FFmpegAdapter {
                     String::from_utf8_lossy(&probe.stderr)
                 ));
             }
-            let p: FFprobeOutput = serde_json::from_slice(&probe.stdout)?;
-            p.streams
+            serde_json::from_slice(&probe.stdout)?
         };
         {
-            // extract file metadata (especially chapter names in a greppable format)
-            let mut probe = Command::new("ffprobe")
+            // extract file metadata in a greppable format
+            let mut probe = command("ffprobe")?
                 .args(vec![
                     "-v",
                     "error",
@@ -120,7 +239,6 @@ impl WritingFileAdapter for FFmpegAdapter {
                     // "-show_data",
                     "-show_error",
                     "-show_programs",
-                    "-show_chapters",
                     // "-count_frames",
                     //"-count_packets",
                 ])
@@ -138,38 +256,80 @@ impl WritingFileAdapter for FFmpegAdapter {
                 return Err(format_err!("ffprobe failed: {:?}", exit));
             }
         }
-        if !subtitle_streams.is_empty() {
-            for probe_stream in subtitle_streams.iter() {
-                // extract subtitles
-                let mut cmd = Command::new("ffmpeg");
-                cmd.arg("-hide_banner")
-                    .arg("-loglevel")
-                    .arg("panic")
-                    .arg("-i")
-                    .arg(&inp_fname)
-                    .arg("-map")
-                    .arg(format!("0:{}", probe_stream.index)) // 0 for first input
-                    .arg("-f")
-                    .arg("webvtt")
-                    .arg("-");
-                let mut cmd = cmd.stdout(Stdio::piped()).spawn().map_err(spawn_fail)?;
-                let stdo = cmd.stdout.as_mut().expect("is piped");
-                let time_re = Regex::new(r".*\d.*-->.*\d.*").unwrap();
-                let mut time: String = "".to_owned();
-                // rewrite subtitle times so they are shown as a prefix in every line
-                let mut lines = BufReader::new(stdo).lines();
-                while let Some(line) = lines.next_line().await? {
-                    // 09:55.195 --> 09:56.730
-                    if time_re.is_match(&line) {
-                        time = line.to_owned();
-                    } else if line.is_empty() {
-                        async_writeln!(oup)?;
-                    } else {
-                        async_writeln!(oup, "{time}: {line}")?;
-                    }
+        for chapter in &chapters {
+            let start = chapter.start_time.parse::<f64>().unwrap_or(0.0);
+            let title = chapter.tags.get("title").map(|t| t.as_str()).unwrap_or("");
+            async_writeln!(oup, "{} chapter: {title}", timestamp(start as u64))?;
+        }
+        for probe_stream in subtitle_streams.iter() {
+            let codec = probe_stream.codec_name.as_deref().unwrap_or("");
+            if BITMAP_CODECS.contains(&codec) {
+                if config.ffmpeg_ocr_subtitles {
+                    ocr_subtitles(&config, &inp_fname, probe_stream.index, &mut oup).await?;
+                }
+                continue;
+            }
+            // extract subtitles
+            let mut cmd = command("ffmpeg")?;
+            cmd.arg("-hide_banner")
+                .arg("-loglevel")
+                .arg("panic")
+                .arg("-i")
+                .arg(&inp_fname)
+                .arg("-map")
+                .arg(format!("0:{}", probe_stream.index)) // 0 for first input
+                .arg("-f")
+                .arg("webvtt")
+                .arg("-");
+            let mut cmd = cmd.stdout(Stdio::piped()).spawn().map_err(spawn_fail)?;
+            let stdo = cmd.stdout.as_mut().expect("is piped");
+            let mut time: String = "".to_owned();
+            // rewrite subtitle times so the start is shown as a prefix in every line
+            let mut lines = BufReader::new(stdo).lines();
+            while let Some(line) = lines.next_line().await? {
+                // 09:55.195 --> 09:56.730
+                if let Some(start) = cue_start(&line) {
+                    time = start;
+                } else if line.is_empty() {
+                    async_writeln!(oup)?;
+                } else {
+                    async_writeln!(oup, "{time} {line}")?;
                 }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn times() {
+        assert_eq!(timestamp(3723), "[01:02:03]");
+        assert_eq!(
+            cue_start("09:55.195 --> 09:56.730").as_deref(),
+            Some("[00:09:55]")
+        );
+        assert_eq!(
+            cue_start("01:00:02.000 --> 01:00:04.500 align:start").as_deref(),
+            Some("[01:00:02]")
+        );
+        assert_eq!(cue_start("WEBVTT"), None);
+        assert_eq!(cue_start("the --> arrow"), None);
+    }
+
+    #[test]
+    fn options() {
+        let mut config = RgaConfig::default();
+        assert_eq!(options_key(&config), None);
+        config.ffmpeg_ocr_subtitles = true;
+        config.ocr_languages = vec!["eng".to_owned(), "deu".to_owned()];
+        assert_eq!(
+            options_key(&config).as_deref(),
+            Some("ffmpeg:ocr-subtitles eng+deu")
+        );
+    }
+}
@@ -0,0 +1,441 @@
+use super::binary::{one_line, usize_from, BinReader};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_compression::tokio::bufread::{BrotliDecoder, ZlibDecoder};
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["ttf", "otf", "ttc", "otc", "woff", "woff2"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "fonts".to_owned(),
+        version: 1,
+        description:
+            "Lists the name table (family, designer, license, ...), scripts and OpenType features of TrueType / OpenType / WOFF fonts"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(
+            ["font/ttf", "font/otf", "font/collection", "font/woff", "font/woff2"]
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FontsAdapter;
+
+impl FontsAdapter {
+    pub fn new() -> FontsAdapter {
+        FontsAdapter
+    }
+}
+impl GetMetadata for FontsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+type Tag = [u8; 4];
+
+fn tag_at(r: &BinReader, off: usize) -> Result<Tag> {
+    Ok(r.slice(off, 4)?.try_into()?)
+}
+
+/// the table directory of a TrueType / OpenType font starting at `offset` (non-zero in collections)
+fn sfnt_tables(data: &[u8], offset: usize) -> Result<Vec<(Tag, &[u8])>> {
+    let r = BinReader::be(data);
+    let count = usize::from(r.u16(offset + 4)?);
+    (0..count)
+        .map(|i| {
+            let record = offset + 12 + i * 16;
+            let table = r.slice(
+                usize_from(r.u32(record + 8)?)?,
+                usize_from(r.u32(record + 12)?)?,
+            )?;
+            Ok((tag_at(&r, record)?, table))
+        })
+        .collect()
+}
+
+/// the tables of all fonts in a .ttc collection
+fn ttc_tables(data: &[u8]) -> Result<Vec<(Tag, &[u8])>> {
+    let r = BinReader::be(data);
+    let mut tables = vec![];
+    for i in 0..usize_from(r.u32(8)?)? {
+        tables.extend(sfnt_tables(data, usize_from(r.u32(12 + i * 4)?)?)?);
+    }
+    Ok(tables)
+}
+
+const NAME_IDS: &[&str] = &[
+    "copyright",
+    "family",
+    "subfamily",
+    "unique id",
+    "full name",
+    "version",
+    "postscript name",
+    "trademark",
+    "manufacturer",
+    "designer",
+    "description",
+    "vendor url",
+    "designer url",
+    "license",
+    "license url",
+    "reserved",
+    "typographic family",
+    "typographic subfamily",
+    "compatible full name",
+    "sample text",
+    "postscript cid name",
+    "wws family",
+    "wws subfamily",
+    "light background palette",
+    "dark background palette",
+    "variations postscript prefix",
+];
+
+fn dump_name_table(
+    table: &[u8],
+    seen: &mut HashSet<(u16, String)>,
+    s: &mut impl Write,
+) -> Result<()> {
+    let r = BinReader::be(table);
+    let count = usize::from(r.u16(2)?);
+    let strings = usize::from(r.u16(4)?);
+    for i in 0..count {
+        let record = 6 + i * 12;
+        let platform = r.u16(record)?;
+        let encoding = r.u16(record + 2)?;
+        let name_id = r.u16(record + 6)?;
+        let bytes = r.slice(
+            strings + usize::from(r.u16(record + 10)?),
+            usize::from(r.u16(record + 8)?),
+        )?;
+        let value = match (platform, encoding) {
+            // unicode and windows
+            (0, _) | (3, _) => {
+                let units: Vec<u16> = bytes
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            // mac roman, close enough to latin1 for names
+            (1, 0) => bytes.iter().map(|&c| char::from(c)).collect(),
+            _ => continue,
+        };
+        let value = one_line(value.as_bytes());
+        if value.is_empty() || !seen.insert((name_id, value.clone())) {
+            continue;
+        }
+        match NAME_IDS.get(usize::from(name_id)) {
+            Some(label) => writeln!(s, "{label}: {value}")?,
+            None => writeln!(s, "name {name_id}: {value}")?,
+        }
+    }
+    Ok(())
+}
+
+/// script and feature tags of a GSUB or GPOS table
+fn layout_tags(table: &[u8], scripts: &mut Vec<String>, features: &mut Vec<String>) -> Result<()> {
+    let r = BinReader::be(table);
+    for (list, out) in [(r.u16(4)?, scripts), (r.u16(6)?, features)] {
+        let list = usize::from(list);
+        for i in 0..usize::from(r.u16(list)?) {
+            let tag = String::from_utf8_lossy(r.slice(list + 2 + i * 6, 4)?)
+                .trim_end()
+                .to_owned();
+            if !out.contains(&tag) {
+                out.push(tag);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_font(tables: &[(Tag, &[u8])], s: &mut impl Write) -> Result<()> {
+    let mut seen = HashSet::new();
+    let (mut scripts, mut features) = (vec![], vec![]);
+    for (tag, table) in tables {
+        match tag {
+            b"name" => dump_name_table(table, &mut seen, s)?,
+            b"GSUB" | b"GPOS" => layout_tags(table, &mut scripts, &mut features)?,
+            _ => {}
+        }
+    }
+    if !scripts.is_empty() {
+        writeln!(s, "scripts: {}", scripts.join(" "))?;
+    }
+    if !features.is_empty() {
+        writeln!(s, "features: {}", features.join(" "))?;
+    }
+    Ok(())
+}
+
+/// the tables of a WOFF 1 file, decompressing only the ones that are printed
+async fn woff_tables(data: &[u8]) -> Result<Vec<(Tag, Vec<u8>)>> {
+    let r = BinReader::be(data);
+    let mut tables = vec![];
+    for i in 0..usize::from(r.u16(12)?) {
+        let record = 44 + i * 20;
+        let tag = tag_at(&r, record)?;
+        if !matches!(&tag, b"name" | b"GSUB" | b"GPOS") {
+            continue;
+        }
+        let compressed = r.slice(
+            usize_from(r.u32(record + 4)?)?,
+            usize_from(r.u32(record + 8)?)?,
+        )?;
+        let orig_len = usize_from(r.u32(record + 12)?)?;
+        if compressed.len() < orig_len {
+            let mut table = Vec::with_capacity(orig_len);
+            ZlibDecoder::new(compressed).read_to_end(&mut table).await?;
+            tables.push((tag, table));
+        } else {
+            tables.push((tag, compressed.to_vec()));
+        }
+    }
+    Ok(tables)
+}
+
+const WOFF2_TAGS: &[&[u8; 4]] = &[
+    b"cmap", b"head", b"hhea", b"hmtx", b"maxp", b"name", b"OS/2", b"post", b"cvt ", b"fpgm",
+    b"glyf", b"loca", b"prep", b"CFF ", b"VORG", b"EBDT", b"EBLC", b"gasp", b"hdmx", b"kern",
+    b"LTSH", b"PCLT", b"VDMX", b"vhea", b"vmtx", b"BASE", b"GDEF", b"GPOS", b"GSUB", b"EBSC",
+    b"JSTF", b"MATH", b"CBDT", b"CBLC", b"COLR", b"CPAL", b"SVG ", b"sbix", b"acnt", b"avar",
+    b"bdat", b"bloc", b"bsln", b"cvar", b"fdsc", b"feat", b"fmtx", b"fvar", b"gvar", b"hsty",
+    b"just", b"lcar", b"mort", b"morx", b"opbd", b"prop", b"trak", b"Zapf", b"Silf", b"Glat",
+    b"Gloc", b"Feat", b"Sill",
+];
+
+/// WOFF 2 UIntBase128: big-endian groups of 7 bits
+fn uint_base128(r: &BinReader, pos: &mut usize) -> Result<u32> {
+    let mut value = 0u32;
+    for _ in 0..5 {
+        let b = r.u8(*pos)?;
+        *pos += 1;
+        value = value
+            .checked_mul(128)
+            .ok_or_else(|| format_err!("UIntBase128 overflow"))?
+            | u32::from(b & 0x7f);
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(format_err!("UIntBase128 too long"))
+}
+
+fn uint_255(r: &BinReader, pos: &mut usize) -> Result<u16> {
+    let code = r.u8(*pos)?;
+    *pos += 1;
+    let value = match code {
+        253 => {
+            *pos += 2;
+            r.u16(*pos - 2)?
+        }
+        254 => u16::from(r.u8(*pos)?) + 253 * 2,
+        255 => u16::from(r.u8(*pos)?) + 253,
+        v => return Ok(u16::from(v)),
+    };
+    if code != 253 {
+        *pos += 1;
+    }
+    Ok(value)
+}
+
+/// the tables of a WOFF 2 file. All tables are in one brotli stream, the
+/// ones printed here (name, GSUB, GPOS) are never transformed
+async fn woff2_tables(data: &[u8]) -> Result<Vec<(Tag, Vec<u8>)>> {
+    let r = BinReader::be(data);
+    let is_collection = r.slice(4, 4)? == b"ttcf";
+    let compressed_size = usize_from(r.u32(20)?)?;
+    let mut pos = 48;
+    let mut directory = vec![];
+    for _ in 0..r.u16(12)? {
+        let flags = r.u8(pos)?;
+        pos += 1;
+        let tag = match flags & 0x3f {
+            63 => {
+                pos += 4;
+                tag_at(&r, pos - 4)?
+            }
+            i => **WOFF2_TAGS
+                .get(usize::from(i))
+                .ok_or_else(|| format_err!("unknown woff2 table index {}", i))?,
+        };
+        let mut len = uint_base128(&r, &mut pos)?;
+        let transform = flags >> 6;
+        let transformed = match &tag {
+            b"glyf" | b"loca" => transform != 3,
+            _ => transform != 0,
+        };
+        if transformed {
+            len = uint_base128(&r, &mut pos)?;
+        }
+        directory.push((tag, usize_from(len)?));
+    }
+    if is_collection {
+        pos += 4;
+        for _ in 0..uint_255(&r, &mut pos)? {
+            let tables = uint_255(&r, &mut pos)?;
+            pos += 4;
+            for _ in 0..tables {
+                uint_255(&r, &mut pos)?;
+            }
+        }
+    }
+    let mut stream = Vec::new();
+    BrotliDecoder::new(r.slice(pos, compressed_size)?)
+        .read_to_end(&mut stream)
+        .await?;
+    let stream = BinReader::be(&stream);
+    let mut offset = 0;
+    let mut tables = vec![];
+    for (tag, len) in directory {
+        if matches!(&tag, b"name" | b"GSUB" | b"GPOS") {
+            tables.push((tag, stream.slice(offset, len)?.to_vec()));
+        }
+        offset += len;
+    }
+    Ok(tables)
+}
+
+/// extended metadata of WOFF files, an XML document with license, credits etc.
+async fn woff_metadata(data: &[u8], brotli: bool) -> Result<Option<String>> {
+    let r = BinReader::be(data);
+    let (offset, len) = if brotli { (28, 32) } else { (24, 28) };
+    let meta = r.slice(usize_from(r.u32(offset)?)?, usize_from(r.u32(len)?)?)?;
+    if meta.is_empty() {
+        return Ok(None);
+    }
+    let mut xml = Vec::new();
+    if brotli {
+        BrotliDecoder::new(meta).read_to_end(&mut xml).await?;
+    } else {
+        ZlibDecoder::new(meta).read_to_end(&mut xml).await?;
+    }
+    Ok(Some(String::from_utf8_lossy(&xml).into_owned()))
+}
+
+#[async_trait]
+impl WritingFileAdapter for FontsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        match data.get(..4) {
+            Some(b"wOFF") | Some(b"wOF2") => {
+                let brotli = data.starts_with(b"wOF2");
+                let tables = if brotli {
+                    woff2_tables(&data).await?
+                } else {
+                    woff_tables(&data).await?
+                };
+                let tables: Vec<(Tag, &[u8])> =
+                    tables.iter().map(|(tag, t)| (*tag, t.as_slice())).collect();
+                dump_font(&tables, &mut buf)?;
+                if let Some(xml) = woff_metadata(&data, brotli).await? {
+                    writeln!(buf, "metadata:\n{xml}")?;
+                }
+            }
+            Some(b"ttcf") => dump_font(&ttc_tables(&data)?, &mut buf)?,
+            _ => dump_font(&sfnt_tables(&data, 0)?, &mut buf)?,
+        }
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn name_table(records: &[(u16, u16, u16, u16, &[u8])]) -> Vec<u8> {
+        let mut table = vec![];
+        let mut strings = vec![];
+        let be = |t: &mut Vec<u8>, v: u16| t.extend(v.to_be_bytes());
+        be(&mut table, 0);
+        be(&mut table, records.len() as u16);
+        be(&mut table, 6 + 12 * records.len() as u16);
+        for (platform, encoding, language, name_id, value) in records {
+            for v in [*platform, *encoding, *language, *name_id] {
+                be(&mut table, v);
+            }
+            be(&mut table, value.len() as u16);
+            be(&mut table, strings.len() as u16);
+            strings.extend_from_slice(value);
+        }
+        table.extend(strings);
+        table
+    }
+
+    #[test]
+    fn sfnt() -> Result<()> {
+        let utf16 = |s: &str| {
+            s.encode_utf16()
+                .flat_map(u16::to_be_bytes)
+                .collect::<Vec<u8>>()
+        };
+        let family = utf16("Fira Sans");
+        let license = utf16("SIL Open Font License");
+        let name = name_table(&[
+            (1, 0, 0, 1, b"Fira Sans"),
+            (3, 1, 0x409, 1, &family),
+            (3, 1, 0x409, 13, &license),
+            (3, 1, 0x409, 256, &utf16("Alternate a")),
+        ]);
+        // GSUB with one script (latn) and two features (liga, smcp)
+        let mut gsub = vec![0, 1, 0, 0, 0, 10, 0, 18, 0, 0];
+        gsub.extend([0, 1, b'l', b'a', b't', b'n', 0, 0]);
+        gsub.extend([
+            0, 2, b'l', b'i', b'g', b'a', 0, 0, b's', b'm', b'c', b'p', 0, 0,
+        ]);
+
+        let mut font = vec![0, 1, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0];
+        let mut offset = 12 + 2 * 16;
+        for (tag, table) in [(b"GSUB", &gsub), (b"name", &name)] {
+            font.extend(tag);
+            font.extend([0; 4]);
+            font.extend((offset as u32).to_be_bytes());
+            font.extend((table.len() as u32).to_be_bytes());
+            offset += table.len();
+        }
+        font.extend(&gsub);
+        font.extend(&name);
+
+        let mut out = Vec::new();
+        dump_font(&sfnt_tables(&font, 0)?, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "family: Fira Sans
+license: SIL Open Font License
+name 256: Alternate a
+scripts: latn
+features: liga smcp
+"
+        );
+        Ok(())
+    }
+}
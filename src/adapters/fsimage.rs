@@ -0,0 +1,1023 @@
+use super::binary::{usize_from, BinReader};
+use super::iso::IsoAdapter;
+use super::squashfs::SquashfsAdapter;
+use super::*;
+use crate::vpath::member_prefix;
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
+use bytes::Bytes;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::StreamReader;
+
+static EXTENSIONS: &[&str] = &["img", "dd", "ext2", "ext3", "ext4"];
+
+const SECTOR_LEN: u64 = 512;
+/// directories and FATs longer than this are damaged
+const MAX_TABLE_LEN: u64 = 256 * 1024 * 1024;
+const MAX_DEPTH: usize = 64;
+/// the extent trees of ext4 have at most 5 levels
+const MAX_EXTENT_DEPTH: usize = 5;
+/// the chunks the files are read in
+const CHUNK_LEN: u64 = 64 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "fsimage".to_owned(),
+        version: 1,
+        description:
+            "Reads the files of ext2/3/4 and FAT filesystem images (like dd images of disks and partitions) without mounting them, and recurses into them. Disk images with an MBR or GPT partition table are searched partition by partition, .img files that are SquashFS or ISO images are passed on to those adapters. Deleted files are not recovered"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-raw-disk-image".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // only the filesystem structures and the files are read
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct FsImageAdapter;
+
+impl FsImageAdapter {
+    pub fn new() -> FsImageAdapter {
+        FsImageAdapter
+    }
+}
+impl GetMetadata for FsImageAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a part of the contents of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Run {
+    /// bytes of the image
+    Data { offset: u64, len: u64 },
+    /// a hole of a sparse file
+    Zeros(u64),
+}
+
+impl Run {
+    fn len(&self) -> u64 {
+        match *self {
+            Run::Data { len, .. } | Run::Zeros(len) => len,
+        }
+    }
+}
+
+/// a file in the image
+#[derive(Debug, PartialEq, Eq)]
+struct Member {
+    name: String,
+    runs: Vec<Run>,
+}
+
+/// add a run, merged with the last one if it continues it
+fn push_run(runs: &mut Vec<Run>, run: Run) {
+    match (runs.last_mut(), run) {
+        (_, run) if run.len() == 0 => {}
+        (Some(Run::Data { offset, len }), Run::Data { offset: o, len: l })
+            if *offset + *len == o =>
+        {
+            *len += l
+        }
+        (Some(Run::Zeros(len)), Run::Zeros(l)) => *len += l,
+        _ => runs.push(run),
+    }
+}
+
+/// the first `size` bytes of the runs, with zeros after them if they are shorter
+fn truncate_runs(runs: Vec<Run>, size: u64) -> Vec<Run> {
+    let mut out = Vec::new();
+    let mut left = size;
+    for run in runs {
+        let run = match run {
+            Run::Data { offset, len } => Run::Data {
+                offset,
+                len: len.min(left),
+            },
+            Run::Zeros(len) => Run::Zeros(len.min(left)),
+        };
+        left -= run.len();
+        push_run(&mut out, run);
+    }
+    push_run(&mut out, Run::Zeros(left));
+    out
+}
+
+struct Image<R> {
+    r: R,
+}
+
+impl<R: Read + Seek> Image<R> {
+    fn read_at(&mut self, pos: u64, len: u64) -> Result<Vec<u8>> {
+        self.r.seek(SeekFrom::Start(pos))?;
+        let mut data = Vec::new();
+        (&mut self.r).take(len).read_to_end(&mut data)?;
+        if (data.len() as u64) < len {
+            bail!("the image ends before {}", pos + len);
+        }
+        Ok(data)
+    }
+
+    fn read_runs(&mut self, runs: &[Run]) -> Result<Vec<u8>> {
+        if runs.iter().map(Run::len).sum::<u64>() > MAX_TABLE_LEN {
+            bail!("directory too long");
+        }
+        let mut data = Vec::new();
+        for run in runs {
+            match *run {
+                Run::Data { offset, len } => data.extend(self.read_at(offset, len)?),
+                Run::Zeros(len) => data.resize(data.len() + usize_from(len)?, 0),
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// where the files of the image come from, with the members of a directory added to `out`
+trait Filesystem<R> {
+    fn files(&self, img: &mut Image<R>, prefix: &str, out: &mut Vec<Member>) -> Result<()>;
+}
+
+/// FAT12, FAT16 and FAT32
+struct Fat {
+    /// where the filesystem starts in the image
+    offset: u64,
+    cluster_len: u64,
+    /// where cluster 2 starts, from the start of the filesystem
+    data_start: u64,
+    bits: u32,
+    clusters: u64,
+    table: Vec<u8>,
+    root: Vec<Run>,
+}
+
+impl Fat {
+    fn open<R: Read + Seek>(img: &mut Image<R>, offset: u64) -> Result<Fat> {
+        let boot = img.read_at(offset, SECTOR_LEN)?;
+        let r = BinReader::le(&boot);
+        if &boot[3..11] == b"EXFAT   " {
+            bail!("exFAT is not supported");
+        }
+        let bytes_per_sector = u64::from(r.u16(11)?);
+        let sectors_per_cluster = u64::from(r.u8(13)?);
+        let reserved = u64::from(r.u16(14)?);
+        let fats = u64::from(r.u8(16)?);
+        let root_entries = u64::from(r.u16(17)?);
+        let is_fat = r.u16(510)? == 0xaa55
+            && matches!(boot[0], 0xeb | 0xe9)
+            && matches!(bytes_per_sector, 512 | 1024 | 2048 | 4096)
+            && sectors_per_cluster.is_power_of_two()
+            && reserved > 0
+            && fats > 0;
+        if !is_fat {
+            bail!("no FAT boot sector");
+        }
+        let fat_len = match r.u16(22)? {
+            0 => u64::from(r.u32(36)?),
+            len => u64::from(len),
+        };
+        let sectors = match r.u16(19)? {
+            0 => u64::from(r.u32(32)?),
+            sectors => u64::from(sectors),
+        };
+        let root_start = reserved + fats * fat_len;
+        let data_start = root_start + (root_entries * 32).div_ceil(bytes_per_sector);
+        if sectors <= data_start {
+            bail!("invalid FAT boot sector");
+        }
+        let clusters = (sectors - data_start) / sectors_per_cluster;
+        let bits = match clusters {
+            0..=4084 => 12,
+            4085..=65524 => 16,
+            _ => 32,
+        };
+        if fat_len * bytes_per_sector > MAX_TABLE_LEN {
+            bail!("FAT too long");
+        }
+        let table = img.read_at(
+            offset + reserved * bytes_per_sector,
+            fat_len * bytes_per_sector,
+        )?;
+        let mut fat = Fat {
+            offset,
+            cluster_len: sectors_per_cluster * bytes_per_sector,
+            data_start: data_start * bytes_per_sector,
+            bits,
+            clusters,
+            table,
+            root: vec![Run::Data {
+                offset: offset + root_start * bytes_per_sector,
+                len: root_entries * 32,
+            }],
+        };
+        if bits == 32 {
+            fat.root = fat.chain(r.u32(44)?);
+        }
+        Ok(fat)
+    }
+
+    /// the cluster after `cluster`, None at the end of the chain
+    fn next(&self, cluster: u32) -> Option<u32> {
+        let r = BinReader::le(&self.table);
+        let c = cluster as usize;
+        let next = match self.bits {
+            12 => {
+                let v = u32::from(r.u16(c + c / 2).ok()?);
+                if c % 2 == 1 {
+                    v >> 4
+                } else {
+                    v & 0xfff
+                }
+            }
+            16 => u32::from(r.u16(c * 2).ok()?),
+            _ => r.u32(c * 4).ok()? & 0x0fff_ffff,
+        };
+        // the end of chain marks and bad clusters are above the last cluster
+        (next >= 2 && u64::from(next) < self.clusters + 2).then_some(next)
+    }
+
+    fn chain(&self, start: u32) -> Vec<Run> {
+        let mut runs = Vec::new();
+        let mut cluster = start;
+        // a loop in a damaged FAT ends after as many clusters as there are
+        for _ in 0..self.clusters {
+            if cluster < 2 || u64::from(cluster) >= self.clusters + 2 {
+                break;
+            }
+            push_run(
+                &mut runs,
+                Run::Data {
+                    offset: self.offset
+                        + self.data_start
+                        + u64::from(cluster - 2) * self.cluster_len,
+                    len: self.cluster_len,
+                },
+            );
+            cluster = match self.next(cluster) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+        runs
+    }
+
+    fn walk<R: Read + Seek>(
+        &self,
+        img: &mut Image<R>,
+        runs: &[Run],
+        dir: &str,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        out: &mut Vec<Member>,
+    ) -> Result<()> {
+        let data = img.read_runs(runs)?;
+        // the long name parts are before the entry, the last part first
+        let mut long_name: Vec<u16> = Vec::new();
+        for entry in data.chunks_exact(32) {
+            match entry[0] {
+                0 => break,
+                // deleted
+                0xe5 => {
+                    long_name.clear();
+                    continue;
+                }
+                _ => {}
+            }
+            let r = BinReader::le(entry);
+            let attributes = entry[11];
+            if attributes & 0x3f == 0x0f {
+                let mut part = [&entry[1..11], &entry[14..26], &entry[28..32]]
+                    .concat()
+                    .chunks_exact(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .take_while(|&c| c != 0)
+                    .collect::<Vec<_>>();
+                if entry[0] & 0x40 == 0 {
+                    part.extend_from_slice(&long_name);
+                }
+                long_name = part;
+                continue;
+            }
+            let long_name = std::mem::take(&mut long_name);
+            // the volume label
+            if attributes & 0x08 != 0 {
+                continue;
+            }
+            let name = if long_name.is_empty() {
+                short_name(entry)
+            } else {
+                String::from_utf16_lossy(&long_name)
+            };
+            if name == "." || name == ".." {
+                continue;
+            }
+            let path = join(dir, &name);
+            let cluster = u32::from(r.u16(20)?) << 16 | u32::from(r.u16(26)?);
+            if attributes & 0x10 != 0 {
+                if depth >= MAX_DEPTH || !visited.insert(cluster) {
+                    warn!("skipping the directory {} of the image", path);
+                    continue;
+                }
+                self.walk(img, &self.chain(cluster), &path, depth + 1, visited, out)?;
+            } else {
+                let size = u64::from(r.u32(28)?);
+                out.push(Member {
+                    name: path,
+                    runs: truncate_runs(self.chain(cluster), size),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Filesystem<R> for Fat {
+    fn files(&self, img: &mut Image<R>, prefix: &str, out: &mut Vec<Member>) -> Result<()> {
+        self.walk(img, &self.root, prefix, 0, &mut HashSet::new(), out)
+    }
+}
+
+/// the 8.3 name of a directory entry, with the lower case flags of Windows NT
+fn short_name(entry: &[u8]) -> String {
+    let part = |bytes: &[u8], lower: bool| {
+        let s = bytes
+            .iter()
+            .map(|&b| b as char)
+            .collect::<String>()
+            .trim_end()
+            .to_owned();
+        if lower {
+            s.to_lowercase()
+        } else {
+            s
+        }
+    };
+    let mut base = entry[..8].to_vec();
+    // 0xe5 is the first byte of deleted entries, names that start with it have 0x05
+    if base[0] == 0x05 {
+        base[0] = 0xe5;
+    }
+    let base = part(&base, entry[12] & 0x08 != 0);
+    let extension = part(&entry[8..11], entry[12] & 0x10 != 0);
+    if extension.is_empty() {
+        base
+    } else {
+        format!("{base}.{extension}")
+    }
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }
+}
+
+const EXT_MAGIC: u16 = 0xef53;
+const INCOMPAT_FILETYPE: u32 = 0x2;
+const INCOMPAT_META_BG: u32 = 0x10;
+const INCOMPAT_64BIT: u32 = 0x80;
+const INODE_EXTENTS: u32 = 0x80000;
+const INODE_INLINE_DATA: u32 = 0x1000_0000;
+const ROOT_INODE: u32 = 2;
+
+/// ext2, ext3 and ext4
+struct Ext {
+    offset: u64,
+    block_len: u64,
+    inodes: u32,
+    inodes_per_group: u32,
+    inode_len: u64,
+    desc_len: usize,
+    incompat: u32,
+    /// the group descriptors
+    groups: Vec<u8>,
+}
+
+/// a range of blocks of a file: the first block in the file, the first block in the filesystem
+/// (None for unwritten blocks) and the number of blocks
+type Extent = (u64, Option<u64>, u64);
+
+impl Ext {
+    fn open<R: Read + Seek>(img: &mut Image<R>, offset: u64) -> Result<Ext> {
+        let sb = img.read_at(offset + 1024, 1024)?;
+        let r = BinReader::le(&sb);
+        if r.u16(56)? != EXT_MAGIC {
+            bail!("no ext2/3/4 superblock");
+        }
+        let log_block_len = r.u32(24)?;
+        if log_block_len > 6 {
+            bail!("invalid block size");
+        }
+        let block_len = 1024 << log_block_len;
+        let incompat = r.u32(96)?;
+        if incompat & INCOMPAT_META_BG != 0 {
+            bail!("ext4 filesystems with meta_bg are not supported");
+        }
+        let is_64bit = incompat & INCOMPAT_64BIT != 0;
+        let blocks = u64::from(r.u32(4)?)
+            | if is_64bit {
+                u64::from(r.u32(0x150)?) << 32
+            } else {
+                0
+            };
+        let first_data_block = u64::from(r.u32(20)?);
+        let blocks_per_group = u64::from(r.u32(32)?);
+        let inodes_per_group = r.u32(40)?;
+        if blocks_per_group == 0 || inodes_per_group == 0 || blocks <= first_data_block {
+            bail!("invalid ext2/3/4 superblock");
+        }
+        let inode_len = match r.u32(76)? {
+            0 => 128,
+            _ => u64::from(r.u16(88)?),
+        };
+        let desc_len = if is_64bit {
+            usize::from(r.u16(0xfe)?).max(32)
+        } else {
+            32
+        };
+        let groups = (blocks - first_data_block).div_ceil(blocks_per_group);
+        let groups_len = groups * desc_len as u64;
+        if groups_len > MAX_TABLE_LEN || inode_len < 128 {
+            bail!("invalid ext2/3/4 superblock");
+        }
+        let groups = img.read_at(offset + (first_data_block + 1) * block_len, groups_len)?;
+        Ok(Ext {
+            offset,
+            block_len,
+            inodes: r.u32(0)?,
+            inodes_per_group,
+            inode_len,
+            desc_len,
+            incompat,
+            groups,
+        })
+    }
+
+    /// the position of an inode in the image and its data
+    fn inode<R: Read + Seek>(&self, img: &mut Image<R>, inode: u32) -> Result<(u64, Vec<u8>)> {
+        if inode == 0 || inode > self.inodes {
+            bail!("invalid inode {}", inode);
+        }
+        let group = usize_from((inode - 1) / self.inodes_per_group)?;
+        let index = u64::from((inode - 1) % self.inodes_per_group);
+        let r = BinReader::le(&self.groups);
+        let desc = group * self.desc_len;
+        let mut table = u64::from(r.u32(desc + 8)?);
+        if self.desc_len >= 64 {
+            table |= u64::from(r.u32(desc + 0x28)?) << 32;
+        }
+        let pos = self.offset + table * self.block_len + index * self.inode_len;
+        Ok((pos, img.read_at(pos, self.inode_len)?))
+    }
+
+    fn block<R: Read + Seek>(&self, img: &mut Image<R>, block: u64) -> Result<Vec<u8>> {
+        img.read_at(self.offset + block * self.block_len, self.block_len)
+    }
+
+    fn extents<R: Read + Seek>(
+        &self,
+        img: &mut Image<R>,
+        node: &[u8],
+        depth: usize,
+        out: &mut Vec<Extent>,
+    ) -> Result<()> {
+        let r = BinReader::le(node);
+        if r.u16(0)? != 0xf30a || depth > MAX_EXTENT_DEPTH {
+            bail!("invalid extent tree");
+        }
+        let is_leaf = r.u16(6)? == 0;
+        for i in 0..usize::from(r.u16(2)?) {
+            let entry = 12 + 12 * i;
+            if is_leaf {
+                let mut len = u64::from(r.u16(entry + 4)?);
+                // unwritten extents read as zeros
+                let is_unwritten = len > 32768;
+                if is_unwritten {
+                    len -= 32768;
+                }
+                let start = u64::from(r.u16(entry + 6)?) << 32 | u64::from(r.u32(entry + 8)?);
+                out.push((
+                    u64::from(r.u32(entry)?),
+                    (!is_unwritten).then_some(start),
+                    len,
+                ));
+            } else {
+                let leaf = u64::from(r.u16(entry + 8)?) << 32 | u64::from(r.u32(entry + 4)?);
+                let node = self.block(img, leaf)?;
+                self.extents(img, &node, depth + 1, out)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// the blocks of an indirect block of the given level, from block `*next` of the file on
+    fn indirect<R: Read + Seek>(
+        &self,
+        img: &mut Image<R>,
+        block: u32,
+        level: u32,
+        next: &mut u64,
+        blocks: u64,
+        out: &mut Vec<Extent>,
+    ) -> Result<()> {
+        let span = (self.block_len / 4).pow(level);
+        if *next >= blocks {
+            return Ok(());
+        }
+        if block == 0 {
+            *next += span;
+            return Ok(());
+        }
+        let data = self.block(img, u64::from(block))?;
+        for pointer in data.chunks_exact(4) {
+            let pointer = u32::from_le_bytes(pointer.try_into()?);
+            if level == 1 {
+                if pointer != 0 {
+                    out.push((*next, Some(u64::from(pointer)), 1));
+                }
+                *next += 1;
+            } else {
+                self.indirect(img, pointer, level - 1, next, blocks, out)?;
+            }
+            if *next >= blocks {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// the contents of a file or directory
+    fn runs<R: Read + Seek>(&self, img: &mut Image<R>, pos: u64, inode: &[u8]) -> Result<Vec<Run>> {
+        let r = BinReader::le(inode);
+        let size = u64::from(r.u32(4)?) | u64::from(r.u32(108)?) << 32;
+        let flags = r.u32(32)?;
+        let i_block = r.slice(40, 60)?;
+        if flags & INODE_INLINE_DATA != 0 {
+            // the first 60 bytes are in the inode, the rest in an extended attribute
+            return Ok(truncate_runs(
+                vec![Run::Data {
+                    offset: pos + 40,
+                    len: size.min(60),
+                }],
+                size,
+            ));
+        }
+        let mut extents = Vec::new();
+        if flags & INODE_EXTENTS != 0 {
+            self.extents(img, i_block, 0, &mut extents)?;
+        } else {
+            let blocks = size.div_ceil(self.block_len);
+            let pointers = BinReader::le(i_block);
+            for i in 0..12 {
+                let pointer = pointers.u32(i * 4)?;
+                if pointer != 0 && (i as u64) < blocks {
+                    extents.push((i as u64, Some(u64::from(pointer)), 1));
+                }
+            }
+            let mut next = 12;
+            for (level, i) in [(1, 12), (2, 13), (3, 14)] {
+                self.indirect(
+                    img,
+                    pointers.u32(i * 4)?,
+                    level,
+                    &mut next,
+                    blocks,
+                    &mut extents,
+                )?;
+            }
+        }
+        extents.sort_by_key(|e| e.0);
+        let mut runs = Vec::new();
+        let mut len = 0;
+        for (first, start, count) in extents {
+            let from = first * self.block_len;
+            // overlapping extents of a damaged filesystem
+            if from < len || from >= size {
+                continue;
+            }
+            push_run(&mut runs, Run::Zeros(from - len));
+            let run_len = (count * self.block_len).min(size - from);
+            push_run(
+                &mut runs,
+                match start {
+                    Some(start) => Run::Data {
+                        offset: self.offset + start * self.block_len,
+                        len: run_len,
+                    },
+                    None => Run::Zeros(run_len),
+                },
+            );
+            len = from + run_len;
+        }
+        push_run(&mut runs, Run::Zeros(size - len));
+        Ok(runs)
+    }
+
+    fn walk<R: Read + Seek>(
+        &self,
+        img: &mut Image<R>,
+        inode: u32,
+        dir: &str,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        out: &mut Vec<Member>,
+    ) -> Result<()> {
+        let (pos, inode) = self.inode(img, inode)?;
+        let is_inline = BinReader::le(&inode).u32(32)? & INODE_INLINE_DATA != 0;
+        let runs = self.runs(img, pos, &inode)?;
+        let data = img.read_runs(&runs)?;
+        let r = BinReader::le(&data);
+        // inline directories start with the inode of the parent
+        let mut entry = if is_inline { 4 } else { 0 };
+        while entry + 8 <= data.len() {
+            let child = r.u32(entry)?;
+            let rec_len = usize::from(r.u16(entry + 4)?);
+            let name_len = if self.incompat & INCOMPAT_FILETYPE != 0 {
+                usize::from(r.u8(entry + 6)?)
+            } else {
+                usize::from(r.u16(entry + 6)?)
+            };
+            if rec_len < 8 {
+                break;
+            }
+            let name = r.slice(entry + 8, name_len)?;
+            entry += rec_len;
+            if child == 0 || name == b"." || name == b".." {
+                continue;
+            }
+            let path = join(dir, &String::from_utf8_lossy(name));
+            let (pos, child_inode) = match self.inode(img, child) {
+                Ok(inode) => inode,
+                Err(e) => {
+                    warn!("skipping {}: {:#}", path, e);
+                    continue;
+                }
+            };
+            match BinReader::le(&child_inode).u16(0)? & 0xf000 {
+                0x4000 => {
+                    if depth >= MAX_DEPTH || !visited.insert(child) {
+                        warn!("skipping the directory {} of the image", path);
+                        continue;
+                    }
+                    if let Err(e) = self.walk(img, child, &path, depth + 1, visited, out) {
+                        warn!("skipping the directory {}: {:#}", path, e);
+                    }
+                }
+                0x8000 => match self.runs(img, pos, &child_inode) {
+                    Ok(runs) => out.push(Member { name: path, runs }),
+                    Err(e) => warn!("skipping {}: {:#}", path, e),
+                },
+                // links, devices, sockets and pipes
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Filesystem<R> for Ext {
+    fn files(&self, img: &mut Image<R>, prefix: &str, out: &mut Vec<Member>) -> Result<()> {
+        let mut visited = HashSet::from([ROOT_INODE]);
+        self.walk(img, ROOT_INODE, prefix, 0, &mut visited, out)
+    }
+}
+
+/// the filesystem that starts at `offset`, if it is one rga can read
+fn filesystem<R: Read + Seek>(
+    img: &mut Image<R>,
+    offset: u64,
+) -> Result<Option<Box<dyn Filesystem<R>>>> {
+    let superblock = img.read_at(offset + 1024, 1024).ok();
+    if superblock.map_or(false, |sb| sb[56..58] == EXT_MAGIC.to_le_bytes()) {
+        return Ok(Some(Box::new(Ext::open(img, offset)?)));
+    }
+    match Fat::open(img, offset) {
+        Ok(fat) => Ok(Some(Box::new(fat))),
+        Err(e) => {
+            debug!("{:#x}: {:#}", offset, e);
+            Ok(None)
+        }
+    }
+}
+
+/// the start of the partitions of a GPT or an MBR
+fn partitions<R: Read + Seek>(img: &mut Image<R>) -> Result<Vec<u64>> {
+    let header = img.read_at(SECTOR_LEN, SECTOR_LEN)?;
+    if header.starts_with(b"EFI PART") {
+        let r = BinReader::le(&header);
+        let table = r.u64(72)?;
+        let entries = u64::from(r.u32(80)?).min(256);
+        let entry_len = u64::from(r.u32(84)?);
+        if entry_len < 128 {
+            bail!("invalid GPT header");
+        }
+        let data = img.read_at(table * SECTOR_LEN, entries * entry_len)?;
+        return data
+            .chunks_exact(usize_from(entry_len)?)
+            // unused entries have no type
+            .filter(|entry| entry[..16].iter().any(|&b| b != 0))
+            .map(|entry| Ok(BinReader::le(entry).u64(32)? * SECTOR_LEN))
+            .collect();
+    }
+    let mbr = img.read_at(0, SECTOR_LEN)?;
+    if mbr[510..512] != [0x55, 0xaa] {
+        return Ok(vec![]);
+    }
+    mbr[446..510]
+        .chunks_exact(16)
+        // extended partitions are not read
+        .filter(|entry| !matches!(entry[4], 0x00 | 0x05 | 0x0f | 0x85 | 0xee))
+        .map(|entry| Ok(u64::from(BinReader::le(entry).u32(8)?) * SECTOR_LEN))
+        .collect()
+}
+
+/// the files of a filesystem image, or of the partitions of a disk image as p1/..., p2/...
+fn image_members<R: Read + Seek>(r: R) -> Result<Vec<Member>> {
+    let mut img = Image { r };
+    let mut out = Vec::new();
+    if let Some(fs) = filesystem(&mut img, 0)? {
+        fs.files(&mut img, "", &mut out)?;
+        return Ok(out);
+    }
+    let mut found = false;
+    for (i, start) in partitions(&mut img)?.into_iter().enumerate() {
+        let prefix = format!("p{}", i + 1);
+        match filesystem(&mut img, start) {
+            Ok(Some(fs)) => {
+                found = true;
+                if let Err(e) = fs.files(&mut img, &prefix, &mut out) {
+                    warn!("skipping partition {}: {:#}", i + 1, e);
+                }
+            }
+            Ok(None) => debug!("partition {}: no ext2/3/4 or FAT filesystem", i + 1),
+            Err(e) => warn!("skipping partition {}: {:#}", i + 1, e),
+        }
+    }
+    if !found {
+        bail!("no ext2/3/4 or FAT filesystem found");
+    }
+    Ok(out)
+}
+
+/// the contents of a file in the image
+fn open_runs(path: PathBuf, runs: Vec<Run>) -> ReadBox {
+    let s = stream! {
+        let mut file = tokio::fs::File::open(&path).await?;
+        for run in runs {
+            let mut left = run.len();
+            if let Run::Data { offset, .. } = run {
+                file.seek(SeekFrom::Start(offset)).await?;
+            }
+            while left > 0 {
+                let mut buf = vec![0; left.min(CHUNK_LEN) as usize];
+                if let Run::Data { .. } = run {
+                    file.read_exact(&mut buf).await?;
+                }
+                left -= buf.len() as u64;
+                yield std::io::Result::Ok(Bytes::from(buf));
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+/// images with .img names that are handled by other adapters
+async fn other_format(path: &Path) -> Result<Option<Box<dyn FileAdapter>>> {
+    let mut head = Vec::new();
+    tokio::fs::File::open(path)
+        .await?
+        .take(0x8006)
+        .read_to_end(&mut head)
+        .await?;
+    if head.starts_with(b"hsqs") {
+        return Ok(Some(Box::new(SquashfsAdapter::new())));
+    }
+    if head.get(0x8001..0x8006) == Some(&b"CD001"[..]) {
+        return Ok(Some(Box::new(IsoAdapter::new())));
+    }
+    Ok(None)
+}
+
+#[async_trait]
+impl FileAdapter for FsImageAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        if let Some(adapter) = other_format(&ai.filepath_hint).await? {
+            debug!(
+                "{}: handled by the {} adapter",
+                ai.filepath_hint.display(),
+                adapter.metadata().name
+            );
+            return adapter.adapt(ai, detection_reason).await;
+        }
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let members = {
+            let path = filepath_hint.clone();
+            tokio::task::spawn_blocking(move || {
+                image_members(
+                    File::open(&path).with_context(|| format!("opening {}", path.display()))?,
+                )
+            })
+            .await??
+        };
+        let s = stream! {
+            for member in members {
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&member.name),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: open_runs(filepath_hint.clone(), member.runs),
+                    line_prefix: member_prefix(&line_prefix, &member.name),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    /// a directory entry with an 8.3 name
+    fn fat_entry(name: &[u8; 11], attributes: u8, cluster: u16, size: u32) -> Vec<u8> {
+        let mut entry = name.to_vec();
+        entry.resize(32, 0);
+        entry[11] = attributes;
+        entry[26..28].copy_from_slice(&cluster.to_le_bytes());
+        entry[28..32].copy_from_slice(&size.to_le_bytes());
+        entry
+    }
+
+    /// a FAT12 image with one sector per cluster: HELLO.TXT in clusters 2 and 3, the directory
+    /// DOCS in cluster 4 with "Read me.txt" in cluster 5
+    fn fat12() -> Vec<u8> {
+        let mut img = vec![0; 64 * 512];
+        img[..3].copy_from_slice(&[0xeb, 0x3c, 0x90]);
+        // 512 bytes per sector, 1 sector per cluster, 1 reserved sector, 1 FAT of 1 sector,
+        // 16 root entries, 64 sectors
+        img[11..24].copy_from_slice(&[0, 2, 1, 1, 0, 1, 16, 0, 64, 0, 0xf8, 1, 0]);
+        img[510..512].copy_from_slice(&[0x55, 0xaa]);
+        // 2 -> 3, the end of the chains for 3, 4 and 5
+        img[512..521].copy_from_slice(&[0xf8, 0xff, 0xff, 0x03, 0xf0, 0xff, 0xff, 0xff, 0xff]);
+        let mut label = fat_entry(b"DISK       ", 0x08, 0, 0);
+        label.extend(fat_entry(b"HELLO   TXT", 0x20, 2, 600));
+        label.extend(fat_entry(b"DOCS       ", 0x10, 4, 0));
+        img[1024..1024 + label.len()].copy_from_slice(&label);
+        img[1536..1542].copy_from_slice(b"hello\n");
+        let mut docs = fat_entry(b".          ", 0x10, 4, 0);
+        let mut long_name = vec![0x41];
+        let units = "Read me.txt\0\u{ffff}"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .collect::<Vec<_>>();
+        long_name.extend_from_slice(&units[..10]);
+        long_name.extend_from_slice(&[0x0f, 0, 0]);
+        long_name.extend_from_slice(&units[10..22]);
+        long_name.extend_from_slice(&[0, 0]);
+        long_name.extend_from_slice(&units[22..26]);
+        docs.extend(long_name);
+        docs.extend(fat_entry(b"README~1TXT", 0x20, 5, 6));
+        img[2560..2560 + docs.len()].copy_from_slice(&docs);
+        img[3072..3078].copy_from_slice(b"notes\n");
+        img
+    }
+
+    #[test]
+    fn fat() -> Result<()> {
+        let members = image_members(Cursor::new(fat12()))?;
+        assert_eq!(
+            members,
+            [
+                Member {
+                    name: "HELLO.TXT".to_owned(),
+                    runs: vec![Run::Data {
+                        offset: 1536,
+                        len: 600
+                    }]
+                },
+                Member {
+                    name: "DOCS/Read me.txt".to_owned(),
+                    runs: vec![Run::Data {
+                        offset: 3072,
+                        len: 6
+                    }]
+                },
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn partitions() -> Result<()> {
+        // an MBR with the FAT image as the second partition, after an empty one
+        let mut img = vec![0; 512];
+        img[446 + 4] = 0x83;
+        img[446 + 8] = 0x80;
+        img[462 + 4] = 0x01;
+        img[462 + 8] = 2;
+        img[510..512].copy_from_slice(&[0x55, 0xaa]);
+        img.resize(1024, 0);
+        img.extend(fat12());
+        let members = image_members(Cursor::new(img))?;
+        let names = members.iter().map(|m| m.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["p2/HELLO.TXT", "p2/DOCS/Read me.txt"]);
+        assert_eq!(
+            members[1].runs,
+            [Run::Data {
+                offset: 4096,
+                len: 6
+            }]
+        );
+        assert!(image_members(Cursor::new(vec![0; 4096])).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn runs() {
+        let runs = vec![
+            Run::Data {
+                offset: 0,
+                len: 512,
+            },
+            Run::Data {
+                offset: 1024,
+                len: 512,
+            },
+        ];
+        assert_eq!(
+            truncate_runs(runs.clone(), 600),
+            [
+                Run::Data {
+                    offset: 0,
+                    len: 512
+                },
+                Run::Data {
+                    offset: 1024,
+                    len: 88
+                }
+            ]
+        );
+        assert_eq!(
+            truncate_runs(runs, 1100),
+            [
+                Run::Data {
+                    offset: 0,
+                    len: 512
+                },
+                Run::Data {
+                    offset: 1024,
+                    len: 512
+                },
+                Run::Zeros(76)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ext4() -> Result<()> {
+        let filepath = test_data_dir().join("hello.img");
+        let (a, d) =
+            simple_adapt_info(&filepath, Box::pin(tokio::fs::File::open(&filepath).await?));
+        let o = adapted_to_vec(loop_adapt(&FsImageAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:docs/notes.txt: Hello from ext4\nPREFIX:hello.txt: hello\n"
+        );
+        Ok(())
+    }
+}
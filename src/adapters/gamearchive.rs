@@ -0,0 +1,478 @@
+use super::binary::{usize_from, BinReader};
+use super::*;
+use crate::print_bytes;
+use crate::vpath::member_prefix;
+use anyhow::*;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+static EXTENSIONS: &[&str] = &["pak", "vpk", "unity3d", "assetbundle"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "gamearchive".to_owned(),
+        version: 1,
+        description:
+            "Lists the members of Quake-style PAK, Valve VPK and UnityFS asset bundles and recurses into them"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct GameArchiveAdapter;
+
+impl GameArchiveAdapter {
+    pub fn new() -> GameArchiveAdapter {
+        GameArchiveAdapter
+    }
+}
+impl GetMetadata for GameArchiveAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+struct Member {
+    name: String,
+    /// the contents, or for VPK entries stored in a separate archive file only the preloaded start
+    data: Vec<u8>,
+    /// (archive index, offset, length) of the rest of the contents in pak01_000.vpk etc.
+    external: Option<(u16, u64, u64)>,
+}
+
+const VPK_MAGIC: u32 = 0x55aa1234;
+/// archive index of VPK entries stored in the _dir.vpk file itself
+const VPK_DIR_INDEX: u16 = 0x7fff;
+
+fn next_str(r: &BinReader, pos: &mut usize) -> Result<String> {
+    let s = r.cstr(*pos)?;
+    *pos += s.len() + 1;
+    Ok(String::from_utf8_lossy(s).into_owned())
+}
+
+/// Quake / Half-Life PAK: a flat directory of 64 byte entries
+fn pak_members(data: &[u8]) -> Result<Vec<Member>> {
+    let r = BinReader::le(data);
+    let dir = usize_from(r.u32(4)?)?;
+    let dir_len = usize_from(r.u32(8)?)?;
+    (0..dir_len / 64)
+        .map(|i| {
+            let entry = dir + i * 64;
+            let name = r.slice(entry, 56)?;
+            let name = &name[..memchr::memchr(0, name).unwrap_or(name.len())];
+            let data = r.slice(
+                usize_from(r.u32(entry + 56)?)?,
+                usize_from(r.u32(entry + 60)?)?,
+            )?;
+            Ok(Member {
+                name: String::from_utf8_lossy(name).into_owned(),
+                data: data.to_vec(),
+                external: None,
+            })
+        })
+        .collect()
+}
+
+/// Valve VPK version 1 and 2: a tree of extension -> directory -> file name
+fn vpk_members(data: &[u8]) -> Result<Vec<Member>> {
+    let r = BinReader::le(data);
+    let header_len = match r.u32(4)? {
+        1 => 12,
+        2 => 28,
+        v => bail!("unknown vpk version {}", v),
+    };
+    let data_start = header_len + usize_from(r.u32(8)?)?;
+    let mut members = vec![];
+    let mut pos = header_len;
+    loop {
+        let ext = next_str(&r, &mut pos)?;
+        if ext.is_empty() {
+            break;
+        }
+        loop {
+            let dir = next_str(&r, &mut pos)?;
+            if dir.is_empty() {
+                break;
+            }
+            loop {
+                let file = next_str(&r, &mut pos)?;
+                if file.is_empty() {
+                    break;
+                }
+                // " " stands for the root directory and files without extension
+                let mut name = match dir.as_str() {
+                    " " => file,
+                    _ => format!("{dir}/{file}"),
+                };
+                if ext != " " {
+                    name = format!("{name}.{ext}");
+                }
+                // crc, preload length, archive index, offset, length, terminator
+                let preload_len = usize::from(r.u16(pos + 4)?);
+                let archive = r.u16(pos + 6)?;
+                let offset = u64::from(r.u32(pos + 8)?);
+                let len = u64::from(r.u32(pos + 12)?);
+                let mut data = r.slice(pos + 18, preload_len)?.to_vec();
+                pos += 18 + preload_len;
+                let mut external = None;
+                if archive == VPK_DIR_INDEX {
+                    data.extend_from_slice(
+                        r.slice(data_start + usize_from(offset)?, usize_from(len)?)?,
+                    );
+                } else if len > 0 {
+                    external = Some((archive, offset, len));
+                }
+                members.push(Member {
+                    name,
+                    data,
+                    external,
+                });
+            }
+        }
+    }
+    Ok(members)
+}
+
+/// the rest of a VPK entry, stored in pak01_003.vpk next to pak01_dir.vpk
+async fn read_vpk_archive(dir_file: &Path, index: u16, offset: u64, len: u64) -> Result<Vec<u8>> {
+    let base = dir_file
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|n| n.strip_suffix("_dir.vpk"))
+        .ok_or_else(|| format_err!("{} is not a _dir.vpk file", dir_file.display()))?;
+    let mut file =
+        tokio::fs::File::open(dir_file.with_file_name(format!("{base}_{index:03}.vpk"))).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut data = Vec::new();
+    file.take(len).read_to_end(&mut data).await?;
+    Ok(data)
+}
+
+/// decompress an LZ4 block (without frame header), as used by Unity
+fn lz4_block(src: &[u8], len: usize) -> Result<Vec<u8>> {
+    let mut out = Vec::with_capacity(len);
+    let mut i = 0;
+    let byte = |i: usize| {
+        src.get(i)
+            .copied()
+            .ok_or_else(|| format_err!("truncated lz4 block"))
+    };
+    let length = |i: &mut usize, mut n: usize| -> Result<usize> {
+        if n == 15 {
+            loop {
+                let b = byte(*i)?;
+                *i += 1;
+                n += usize::from(b);
+                if b != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(n)
+    };
+    while i < src.len() {
+        let token = byte(i)?;
+        i += 1;
+        let literals = length(&mut i, usize::from(token >> 4))?;
+        out.extend_from_slice(
+            src.get(i..i + literals)
+                .ok_or_else(|| format_err!("truncated lz4 block"))?,
+        );
+        i += literals;
+        // the last sequence only has literals
+        if i >= src.len() {
+            break;
+        }
+        let offset = usize::from(u16::from_le_bytes([byte(i)?, byte(i + 1)?]));
+        i += 2;
+        let match_len = length(&mut i, usize::from(token & 15))? + 4;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            bail!("invalid lz4 match");
+        }
+        // matches may overlap the output they produce, so copy byte by byte
+        let start = out.len() - offset;
+        for k in 0..match_len {
+            out.push(out[start + k]);
+        }
+    }
+    Ok(out)
+}
+
+async fn unity_decompress(data: &[u8], len: u32, compression: u32) -> Result<Vec<u8>> {
+    Ok(match compression {
+        0 => data.to_vec(),
+        1 => {
+            // raw lzma with properties but no size header, turn it into the .lzma format
+            let props = data
+                .get(..5)
+                .ok_or_else(|| format_err!("truncated lzma block"))?;
+            let mut alone = props.to_vec();
+            alone.extend(u64::from(len).to_le_bytes());
+            alone.extend_from_slice(&data[5..]);
+            let mut out = Vec::new();
+            async_compression::tokio::bufread::LzmaDecoder::new(&alone[..])
+                .read_to_end(&mut out)
+                .await?;
+            out
+        }
+        2 | 3 => lz4_block(data, usize_from(len)?)?,
+        c => bail!("unsupported unity compression {}", c),
+    })
+}
+
+/// UnityFS asset bundles: a block list and a list of nodes (serialized files, resources)
+/// addressing the concatenation of the decompressed blocks
+async fn unity_members(data: &[u8]) -> Result<Vec<Member>> {
+    let r = BinReader::be(data);
+    let align = |pos: usize| (pos + 15) & !15;
+    let mut pos = 0;
+    next_str(&r, &mut pos)?;
+    let format = r.u32(pos)?;
+    pos += 4;
+    let unity_version = next_str(&r, &mut pos)?;
+    next_str(&r, &mut pos)?;
+    debug!("unity bundle format {format}, unity {unity_version}");
+    let info_compressed = usize_from(r.u32(pos + 8)?)?;
+    let info_len = r.u32(pos + 12)?;
+    let flags = r.u32(pos + 16)?;
+    pos += 20;
+    if format >= 7 {
+        pos = align(pos);
+    }
+    let info_raw = if flags & 0x80 != 0 {
+        // block info at the end of the file
+        r.slice(data.len().saturating_sub(info_compressed), info_compressed)?
+    } else {
+        pos += info_compressed;
+        r.slice(pos - info_compressed, info_compressed)?
+    };
+    if flags & 0x200 != 0 {
+        pos = align(pos);
+    }
+    let info = unity_decompress(info_raw, info_len, flags & 0x3f).await?;
+    let info = BinReader::be(&info);
+    // after a 16 byte hash
+    let mut p = 20;
+    let mut blocks = Vec::new();
+    for _ in 0..info.u32(16)? {
+        let len = info.u32(p)?;
+        let compressed = usize_from(info.u32(p + 4)?)?;
+        let compression = u32::from(info.u16(p + 8)? & 0x3f);
+        p += 10;
+        blocks.extend(unity_decompress(r.slice(pos, compressed)?, len, compression).await?);
+        pos += compressed;
+    }
+    let node_count = info.u32(p)?;
+    p += 4;
+    let blocks = BinReader::be(&blocks);
+    let mut members = vec![];
+    for _ in 0..node_count {
+        let offset = usize_from(info.u64(p)?)?;
+        let len = usize_from(info.u64(p + 8)?)?;
+        p += 20;
+        members.push(Member {
+            name: next_str(&info, &mut p)?,
+            data: blocks.slice(offset, len)?.to_vec(),
+            external: None,
+        });
+    }
+    Ok(members)
+}
+
+#[async_trait]
+impl FileAdapter for GameArchiveAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let members = if data.starts_with(b"PACK") {
+            pak_members(&data)?
+        } else if data.starts_with(&VPK_MAGIC.to_le_bytes()) {
+            vpk_members(&data)?
+        } else if data.starts_with(b"UnityFS\0") {
+            unity_members(&data).await?
+        } else {
+            bail!("unknown game archive format")
+        };
+        drop(data);
+        let listing: String = members.iter().map(|m| format!("{}\n", m.name)).collect();
+        let s = stream! {
+            // the member names themselves, so they can be searched like the contents
+            yield Ok(AdaptInfo {
+                filepath_hint: PathBuf::from("members"),
+                is_real_file: false,
+                inp: Box::pin(Cursor::new(listing.into_bytes())),
+                line_prefix: line_prefix.clone(),
+                archive_recursion_depth: archive_recursion_depth + 1,
+                postprocess,
+                config: config.clone(),
+            });
+            for member in members {
+                let mut data = member.data;
+                if let Some((index, offset, len)) = member.external {
+                    if is_real_file {
+                        match read_vpk_archive(&filepath_hint, index, offset, len).await {
+                            Ok(rest) => data.extend(rest),
+                            Err(e) => debug!("{}|{}: {:#}", filepath_hint.display(), member.name, e),
+                        }
+                    }
+                }
+                debug!(
+                    "{}|{}: {}",
+                    filepath_hint.display(),
+                    member.name,
+                    print_bytes(data.len() as f64)
+                );
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&member.name),
+                    is_real_file: false,
+                    inp: Box::pin(Cursor::new(data)),
+                    line_prefix: member_prefix(&line_prefix, &member.name),
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    postprocess,
+                    config: config.clone(),
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    fn pak(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut data = b"PACK".to_vec();
+        let contents_len: usize = files.iter().map(|(_, c)| c.len()).sum();
+        data.extend((12 + contents_len as u32).to_le_bytes());
+        data.extend((64 * files.len() as u32).to_le_bytes());
+        let mut dir = vec![];
+        for (name, contents) in files {
+            let mut entry = name.as_bytes().to_vec();
+            entry.resize(56, 0);
+            entry.extend((data.len() as u32).to_le_bytes());
+            entry.extend((contents.len() as u32).to_le_bytes());
+            dir.extend(entry);
+            data.extend_from_slice(contents);
+        }
+        data.extend(dir);
+        data
+    }
+
+    #[test]
+    fn vpk() -> Result<()> {
+        let mut tree = vec![];
+        tree.extend(b"txt\0scripts\0readme\0");
+        // crc, preload 3 bytes, stored in the dir file at offset 0 with 4 more bytes
+        tree.extend([0, 0, 0, 0, 3, 0, 0xff, 0x7f]);
+        tree.extend([0, 0, 0, 0, 4, 0, 0, 0, 0xff, 0xff]);
+        tree.extend(b"hi ");
+        tree.extend(b"\0\0");
+        tree.extend(b"vtf\0 \0logo\0");
+        // stored in pak01_002.vpk
+        tree.extend([0, 0, 0, 0, 0, 0, 2, 0, 16, 0, 0, 0, 32, 0, 0, 0, 0xff, 0xff]);
+        tree.extend(b"\0\0\0");
+        let mut data = VPK_MAGIC.to_le_bytes().to_vec();
+        data.extend(1u32.to_le_bytes());
+        data.extend((tree.len() as u32).to_le_bytes());
+        data.extend(tree);
+        data.extend(b"all\n");
+        let members = vpk_members(&data)?;
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].name, "scripts/readme.txt");
+        assert_eq!(members[0].data, b"hi all\n");
+        assert_eq!(members[1].name, "logo.vtf");
+        assert_eq!(members[1].external, Some((2, 16, 32)));
+        Ok(())
+    }
+
+    #[test]
+    fn lz4() -> Result<()> {
+        // literals "abc", then a match of length 6 at offset 3, then literal "!"
+        let block = [0x32, b'a', b'b', b'c', 3, 0, 0x10, b'!'];
+        assert_eq!(lz4_block(&block, 10)?, b"abcabcabc!");
+        assert!(lz4_block(&[0x12, b'a', 9, 0], 10).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn unity_bundle() -> Result<()> {
+        let contents = b"serialized file contents";
+        let mut block = vec![0xf0, (contents.len() - 15) as u8];
+        block.extend(contents);
+        let mut info = vec![0; 16];
+        info.extend(1u32.to_be_bytes());
+        info.extend((contents.len() as u32).to_be_bytes());
+        info.extend((block.len() as u32).to_be_bytes());
+        info.extend(2u16.to_be_bytes());
+        info.extend(1u32.to_be_bytes());
+        info.extend(0u64.to_be_bytes());
+        info.extend((contents.len() as u64).to_be_bytes());
+        info.extend(4u32.to_be_bytes());
+        info.extend(b"CAB-0123\0");
+        let mut data = b"UnityFS\0".to_vec();
+        data.extend(6u32.to_be_bytes());
+        data.extend(b"5.x.x\x002019.4.1f1\0");
+        data.extend(0u64.to_be_bytes());
+        data.extend((info.len() as u32).to_be_bytes());
+        data.extend((info.len() as u32).to_be_bytes());
+        data.extend(0u32.to_be_bytes());
+        data.extend(info);
+        data.extend(block);
+        let members = unity_members(&data).await?;
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].name, "CAB-0123");
+        assert_eq!(members[0].data, contents);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pak_recurses() -> Result<()> {
+        let data = pak(&[
+            ("readme.txt", b"hello\n"),
+            ("scripts/weapons.cfg", b"damage 10\n"),
+        ]);
+        let (a, d) = simple_adapt_info(&PathBuf::from("pak0.pak"), Box::pin(Cursor::new(data)));
+        let adapter = GameArchiveAdapter::new();
+        let r = loop_adapt(&adapter, d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:readme.txt
+PREFIX:scripts/weapons.cfg
+PREFIX:readme.txt: hello
+PREFIX:scripts/weapons.cfg: damage 10
+"
+        );
+        Ok(())
+    }
+}
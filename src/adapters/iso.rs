@@ -0,0 +1,478 @@
+use super::*;
+use crate::vpath::member_prefix;
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+static EXTENSIONS: &[&str] = &["iso"];
+
+/// the volume descriptors start after the system area of 16 sectors
+const FIRST_DESCRIPTOR: u64 = 16;
+const SECTOR_LEN: usize = 2048;
+/// images have a few descriptors, a longer list is a damaged image
+const MAX_DESCRIPTORS: u64 = 64;
+/// directories longer than this are damaged, real ones have a few KB per thousand files
+const MAX_DIR_LEN: u64 = 16 * 1024 * 1024;
+/// ISO 9660 allows 8 levels of directories, Rock Ridge images can have more
+const MAX_DEPTH: usize = 64;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "iso".to_owned(),
+        version: 1,
+        description:
+            "Recurses into ISO 9660 disc images without mounting them. Files have their Rock Ridge or Joliet names if the image has them. Of images with UDF, only the ISO 9660 part is read, which most discs have for compatibility"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-iso9660-image".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // only the directories and the files are read, the image can be many GB
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct IsoAdapter;
+
+impl IsoAdapter {
+    pub fn new() -> IsoAdapter {
+        IsoAdapter
+    }
+}
+impl GetMetadata for IsoAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a file in the image, with the byte ranges of its contents. Files larger than 4 GB are split into
+/// several extents
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    name: String,
+    extents: Vec<(u64, u64)>,
+}
+
+/// the tree the names of the files are read from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Names {
+    /// 8.3 upper case names
+    Plain,
+    /// UCS-2 names of the supplementary tree, up to 64 characters
+    Joliet,
+    /// POSIX names in the system use area of the records of the primary tree
+    RockRidge { skip: usize },
+}
+
+/// a directory record
+struct Record<'a> {
+    /// the first block of the contents
+    extent: u64,
+    len: u64,
+    flags: u8,
+    name: &'a [u8],
+    system_use: &'a [u8],
+}
+
+const FLAG_ASSOCIATED: u8 = 0x04;
+const FLAG_DIRECTORY: u8 = 0x02;
+const FLAG_MULTI_EXTENT: u8 = 0x80;
+
+fn u32_le(data: &[u8], pos: usize) -> u64 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 bytes")) as u64
+}
+
+fn parse_record(data: &[u8]) -> Option<Record<'_>> {
+    let name_len = *data.get(32)? as usize;
+    let name = data.get(33..33 + name_len)?;
+    // names of even length are padded to an even offset of the system use area
+    let system_use = data
+        .get(33 + name_len + (1 - name_len % 2)..)
+        .unwrap_or_default();
+    Some(Record {
+        // the extended attribute record is before the contents
+        extent: u32_le(data, 2) + data[1] as u64,
+        len: u32_le(data, 10),
+        flags: data[25],
+        name,
+        system_use,
+    })
+}
+
+/// the entries of the system use sharing protocol, like NM with a Rock Ridge name
+fn susp_entries(system_use: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    let mut rest = system_use;
+    std::iter::from_fn(move || {
+        if rest.len() < 4 || &rest[..2] == b"ST" {
+            return None;
+        }
+        let len = rest[2] as usize;
+        if len < 4 || len > rest.len() {
+            return None;
+        }
+        let (entry, next) = rest.split_at(len);
+        rest = next;
+        Some((&entry[..2], &entry[4..]))
+    })
+}
+
+fn strip_version(name: &str) -> &str {
+    let name = name.split_once(';').map_or(name, |(name, _)| name);
+    // files without an extension are written as NAME.;1
+    name.strip_suffix('.').unwrap_or(name)
+}
+
+/// the name of a record in the tree, None for a name the tree doesn't have
+fn record_name(record: &Record, names: Names) -> Option<String> {
+    match names {
+        Names::Plain => Some(strip_version(&String::from_utf8_lossy(record.name)).to_owned()),
+        Names::Joliet => {
+            let name = char::decode_utf16(
+                record
+                    .name
+                    .chunks_exact(2)
+                    .map(|c| u16::from_be_bytes([c[0], c[1]])),
+            )
+            .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+            .collect::<String>();
+            Some(strip_version(&name).to_owned())
+        }
+        Names::RockRidge { skip } => {
+            let mut name = Vec::new();
+            for (signature, body) in susp_entries(record.system_use.get(skip..)?) {
+                if signature != b"NM" || body.is_empty() {
+                    continue;
+                }
+                name.extend_from_slice(&body[1..]);
+                // the rest of the name is in the next NM entry
+                if body[0] & 1 == 0 {
+                    return Some(String::from_utf8_lossy(&name).into_owned());
+                }
+            }
+            None
+        }
+    }
+}
+
+fn read_at<R: Read + Seek>(r: &mut R, pos: u64, len: u64) -> Result<Vec<u8>> {
+    r.seek(SeekFrom::Start(pos))?;
+    let mut data = Vec::new();
+    r.take(len).read_to_end(&mut data)?;
+    if (data.len() as u64) < len {
+        bail!("the image ends before {}", pos + len);
+    }
+    Ok(data)
+}
+
+struct Walker<'a, R> {
+    r: &'a mut R,
+    block_len: u64,
+    names: Names,
+    visited: HashSet<u64>,
+    files: Vec<Entry>,
+}
+
+impl<R: Read + Seek> Walker<'_, R> {
+    /// add the files of a directory, and the ones of its subdirectories
+    fn walk(&mut self, extent: u64, len: u64, dir: &str, depth: usize) -> Result<()> {
+        if depth > MAX_DEPTH || len > MAX_DIR_LEN || !self.visited.insert(extent) {
+            warn!("skipping the directory {} of the image", dir);
+            return Ok(());
+        }
+        let data = read_at(self.r, extent * self.block_len, len)?;
+        let mut pos = 0;
+        // the previous record has the first extent of the same file
+        let mut continued = false;
+        while pos < data.len() {
+            let record_len = data[pos] as usize;
+            if record_len == 0 {
+                // records don't cross sectors, the rest of this one is empty
+                pos = (pos / SECTOR_LEN + 1) * SECTOR_LEN;
+                continue;
+            }
+            let record = match data
+                .get(pos..pos + record_len)
+                .filter(|_| record_len >= 34)
+                .and_then(parse_record)
+            {
+                Some(record) => record,
+                None => bail!("invalid directory record in {}", dir),
+            };
+            pos += record_len;
+            // . and ..
+            if record.name == [0] || record.name == [1] {
+                continue;
+            }
+            let mut is_dir = record.flags & FLAG_DIRECTORY != 0;
+            let mut extent = record.extent;
+            if let Names::RockRidge { skip } = self.names {
+                let system_use = record.system_use.get(skip..).unwrap_or_default();
+                // directories moved to keep the depth below 8 are read where CL points to them
+                if susp_entries(system_use).any(|(signature, _)| signature == b"RE") {
+                    continue;
+                }
+                if let Some((_, body)) = susp_entries(system_use)
+                    .find(|(signature, body)| *signature == b"CL" && body.len() >= 4)
+                {
+                    is_dir = true;
+                    extent = u32_le(body, 0);
+                }
+            }
+            let name = match record_name(&record, self.names) {
+                Some(name) => name,
+                // Rock Ridge records without a name
+                None => strip_version(&String::from_utf8_lossy(record.name)).to_owned(),
+            };
+            let path = if dir.is_empty() {
+                name
+            } else {
+                format!("{dir}/{name}")
+            };
+            if is_dir {
+                let len = if extent == record.extent {
+                    record.len
+                } else {
+                    // a relocated directory, its length is in its own . record
+                    let first = read_at(self.r, extent * self.block_len, 34)?;
+                    u32_le(&first, 10)
+                };
+                self.walk(extent, len, &path, depth + 1)?;
+                continued = false;
+                continue;
+            }
+            if record.flags & FLAG_ASSOCIATED != 0 {
+                continue;
+            }
+            let range = (record.extent * self.block_len, record.len);
+            match self.files.last_mut() {
+                Some(last) if continued && last.name == path => last.extents.push(range),
+                _ => self.files.push(Entry {
+                    name: path,
+                    extents: vec![range],
+                }),
+            }
+            continued = record.flags & FLAG_MULTI_EXTENT != 0;
+        }
+        Ok(())
+    }
+}
+
+/// the files of an image in the order of the directories, with the best names it has
+fn image_files<R: Read + Seek>(r: &mut R) -> Result<Vec<Entry>> {
+    let mut primary = None;
+    let mut joliet = None;
+    for sector in FIRST_DESCRIPTOR..FIRST_DESCRIPTOR + MAX_DESCRIPTORS {
+        let descriptor = read_at(r, sector * SECTOR_LEN as u64, SECTOR_LEN as u64)
+            .context("reading the volume descriptors")?;
+        if &descriptor[1..6] != b"CD001" {
+            if sector == FIRST_DESCRIPTOR {
+                bail!("not an ISO 9660 image, images with only UDF are not supported");
+            }
+            break;
+        }
+        let block_len = u16::from_le_bytes([descriptor[128], descriptor[129]]) as u64;
+        let root = descriptor[156..190].to_vec();
+        match descriptor[0] {
+            1 if primary.is_none() => primary = Some((root, block_len)),
+            // the escape sequences of UCS-2 levels 1 to 3
+            2 if matches!(&descriptor[88..91], b"%/@" | b"%/C" | b"%/E") => {
+                joliet = Some((root, block_len))
+            }
+            255 => break,
+            _ => {}
+        }
+    }
+    let (root, block_len) = primary.context("the image has no primary volume descriptor")?;
+    if block_len == 0 {
+        bail!("invalid block size 0");
+    }
+    let root = parse_record(&root).context("invalid root directory")?;
+    // Rock Ridge images start the system use area of the first record of the root with SP
+    let first = read_at(r, root.extent * block_len, root.len.min(SECTOR_LEN as u64))?;
+    let first_len = match first.first().copied() {
+        Some(len) if len >= 34 => len as usize,
+        _ => bail!("invalid root directory"),
+    };
+    let rock_ridge = parse_record(&first[..first_len.min(first.len())]).and_then(|dot| {
+        let (signature, body) = susp_entries(dot.system_use).next()?;
+        (signature == b"SP" && body.len() >= 3 && body[..2] == [0xbe, 0xef])
+            .then(|| body[2] as usize)
+    });
+    let (extent, len, block_len, names) = match (rock_ridge, joliet) {
+        (Some(skip), _) => (root.extent, root.len, block_len, Names::RockRidge { skip }),
+        (None, Some((root, block_len))) => {
+            let root = parse_record(&root).context("invalid Joliet root directory")?;
+            (root.extent, root.len, block_len, Names::Joliet)
+        }
+        (None, None) => (root.extent, root.len, block_len, Names::Plain),
+    };
+    debug!("reading the {:?} names", names);
+    let mut walker = Walker {
+        r,
+        block_len,
+        names,
+        visited: HashSet::new(),
+        files: Vec::new(),
+    };
+    walker.walk(extent, len, "", 0)?;
+    Ok(walker.files)
+}
+
+/// the contents of a file in the image, read from its extents
+async fn open_file(path: &Path, extents: &[(u64, u64)]) -> Result<ReadBox> {
+    let mut inp: ReadBox = Box::pin(tokio::io::empty());
+    for &(offset, len) in extents {
+        let mut file = tokio::fs::File::open(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        inp = Box::pin(inp.chain(file.take(len)));
+    }
+    Ok(inp)
+}
+
+#[async_trait]
+impl FileAdapter for IsoAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let files = {
+            let path = filepath_hint.clone();
+            tokio::task::spawn_blocking(move || image_files(&mut File::open(path)?)).await??
+        };
+        let s = stream! {
+            for entry in files {
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&entry.name),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: open_file(&filepath_hint, &entry.extents).await?,
+                    line_prefix: member_prefix(&line_prefix, &entry.name),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn record(extent: u32, len: u32, flags: u8, name: &[u8], system_use: &[u8]) -> Vec<u8> {
+        let mut record = vec![0; 33];
+        record[2..6].copy_from_slice(&extent.to_le_bytes());
+        record[10..14].copy_from_slice(&len.to_le_bytes());
+        record[25] = flags;
+        record[32] = name.len() as u8;
+        record.extend_from_slice(name);
+        if name.len() % 2 == 0 {
+            record.push(0);
+        }
+        record.extend_from_slice(system_use);
+        if record.len() % 2 == 1 {
+            record.push(0);
+        }
+        record[0] = record.len() as u8;
+        record
+    }
+
+    fn nm(name: &str) -> Vec<u8> {
+        [&b"NM"[..], &[name.len() as u8 + 5, 1, 0], name.as_bytes()].concat()
+    }
+
+    /// an image with hello.txt and dir/ReadMe, with Rock Ridge names or without
+    fn image(rock_ridge: bool) -> Vec<u8> {
+        let mut image = vec![0; 22 * SECTOR_LEN];
+        let mut write = |sector: usize, data: &[u8]| {
+            image[sector * SECTOR_LEN..sector * SECTOR_LEN + data.len()].copy_from_slice(data)
+        };
+        let nm = |name| if rock_ridge { nm(name) } else { vec![] };
+        let mut primary = vec![1];
+        primary.extend_from_slice(b"CD001");
+        primary.resize(128, 0);
+        primary.extend_from_slice(&2048u16.to_le_bytes());
+        primary.resize(156, 0);
+        primary.extend(record(18, 2048, FLAG_DIRECTORY, &[0], &[]));
+        write(16, &primary);
+        write(17, b"\xffCD001");
+        let sp = if rock_ridge {
+            vec![b'S', b'P', 7, 1, 0xbe, 0xef, 0]
+        } else {
+            vec![]
+        };
+        let root = [
+            record(18, 2048, FLAG_DIRECTORY, &[0], &sp),
+            record(18, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(20, 10, 0, b"HELLO.TXT;1", &nm("hello.txt")),
+            record(19, 2048, FLAG_DIRECTORY, b"DIR", &nm("dir")),
+        ];
+        write(18, &root.concat());
+        let dir = [
+            record(19, 2048, FLAG_DIRECTORY, &[0], &[]),
+            record(18, 2048, FLAG_DIRECTORY, &[1], &[]),
+            record(21, 6, 0, b"README.;1", &nm("ReadMe")),
+        ];
+        write(19, &dir.concat());
+        write(20, b"hello iso\n");
+        write(21, b"noext\n");
+        image
+    }
+
+    #[test]
+    fn files() -> Result<()> {
+        let expected = |hello: &str, readme: &str| {
+            vec![
+                Entry {
+                    name: hello.to_owned(),
+                    extents: vec![(20 * 2048, 10)],
+                },
+                Entry {
+                    name: readme.to_owned(),
+                    extents: vec![(21 * 2048, 6)],
+                },
+            ]
+        };
+        assert_eq!(
+            image_files(&mut Cursor::new(image(true)))?,
+            expected("hello.txt", "dir/ReadMe")
+        );
+        assert_eq!(
+            image_files(&mut Cursor::new(image(false)))?,
+            expected("HELLO.TXT", "DIR/README")
+        );
+        assert!(image_files(&mut Cursor::new(vec![0; 40 * SECTOR_LEN])).is_err());
+        // a root directory of length 0
+        let mut empty_root = image(false);
+        empty_root[16 * SECTOR_LEN + 166..16 * SECTOR_LEN + 170].fill(0);
+        let err = image_files(&mut Cursor::new(empty_root)).unwrap_err();
+        assert_eq!(err.to_string(), "invalid root directory");
+        Ok(())
+    }
+}
@@ -0,0 +1,465 @@
+use super::binary::{one_line, usize_from, BinReader};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::collections::HashSet;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["class", "dex"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "java".to_owned(),
+        version: 1,
+        description:
+            "Lists class, field and method names and string constants of Java .class and Android .dex files"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-java".to_owned()),
+            FileMatcher::MimeType("application/x-java-vm".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct JavaAdapter;
+
+impl JavaAdapter {
+    pub fn new() -> JavaAdapter {
+        JavaAdapter
+    }
+}
+impl GetMetadata for JavaAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// Parses one field descriptor (e.g. `[Ljava/lang/String;`) at the start of `desc`.
+/// Returns the java type name and the length of the descriptor
+fn parse_descriptor(desc: &str) -> Option<(String, usize)> {
+    let dims = desc.bytes().take_while(|b| *b == b'[').count();
+    let rest = &desc[dims..];
+    let (name, len) = match rest.as_bytes().first()? {
+        b'L' => {
+            let end = rest.find(';')?;
+            (rest[1..end].replace('/', "."), end + 1)
+        }
+        b'B' => ("byte".to_owned(), 1),
+        b'C' => ("char".to_owned(), 1),
+        b'D' => ("double".to_owned(), 1),
+        b'F' => ("float".to_owned(), 1),
+        b'I' => ("int".to_owned(), 1),
+        b'J' => ("long".to_owned(), 1),
+        b'S' => ("short".to_owned(), 1),
+        b'Z' => ("boolean".to_owned(), 1),
+        b'V' => ("void".to_owned(), 1),
+        _ => return None,
+    };
+    Some((format!("{}{}", name, "[]".repeat(dims)), dims + len))
+}
+
+fn type_name(desc: &str) -> String {
+    parse_descriptor(desc)
+        .map(|e| e.0)
+        .unwrap_or_else(|| desc.to_owned())
+}
+
+/// `(Ljava/lang/String;I)V` + `foo` -> `void foo(java.lang.String, int)`
+fn method_signature(name: &str, desc: &str) -> String {
+    let parsed = (|| {
+        let mut rest = desc.strip_prefix('(')?;
+        let mut params = vec![];
+        while !rest.starts_with(')') {
+            let (param, len) = parse_descriptor(rest)?;
+            params.push(param);
+            rest = &rest[len..];
+        }
+        let (ret, _) = parse_descriptor(&rest[1..])?;
+        Some(format!("{} {}({})", ret, name, params.join(", ")))
+    })();
+    parsed.unwrap_or_else(|| format!("{name}{desc}"))
+}
+
+const CLASS_MAGIC: &[u8] = b"\xca\xfe\xba\xbe";
+
+enum Constant {
+    Utf8(String),
+    Class(u16),
+    String(u16),
+    Other,
+}
+
+fn dump_class(r: BinReader, s: &mut impl Write) -> Result<()> {
+    let cp_count = r.u16(8)?;
+    let mut pool = vec![Constant::Other];
+    let mut pos = 10;
+    while pool.len() < usize::from(cp_count) {
+        let tag = r.u8(pos)?;
+        let (constant, len, slots) = match tag {
+            1 => {
+                let len = usize::from(r.u16(pos + 1)?);
+                // modified UTF-8 is close enough to UTF-8 for searching
+                let str = String::from_utf8_lossy(r.slice(pos + 3, len)?).into_owned();
+                (Constant::Utf8(str), 3 + len, 1)
+            }
+            7 => (Constant::Class(r.u16(pos + 1)?), 3, 1),
+            8 => (Constant::String(r.u16(pos + 1)?), 3, 1),
+            3 | 4 | 9 | 10 | 11 | 12 | 17 | 18 => (Constant::Other, 5, 1),
+            5 | 6 => (Constant::Other, 9, 2),
+            15 => (Constant::Other, 4, 1),
+            16 | 19 | 20 => (Constant::Other, 3, 1),
+            tag => return Err(format_err!("unknown constant pool tag {}", tag)),
+        };
+        pool.push(constant);
+        if slots == 2 {
+            pool.push(Constant::Other);
+        }
+        pos += len;
+    }
+    let utf8 = |i: u16| match pool.get(usize::from(i)) {
+        Some(Constant::Utf8(s)) => Ok(s.as_str()),
+        _ => Err(format_err!("constant {} is not a utf8 constant", i)),
+    };
+    let class = |i: u16| match pool.get(usize::from(i)) {
+        Some(Constant::Class(name)) => utf8(*name).map(|n| n.replace('/', ".")),
+        _ => Err(format_err!("constant {} is not a class constant", i)),
+    };
+
+    let this_class = r.u16(pos + 2)?;
+    let super_class = r.u16(pos + 4)?;
+    let interface_count = usize::from(r.u16(pos + 6)?);
+    let mut line = format!("class: {}", class(this_class)?);
+    if super_class != 0 {
+        line += &format!(" extends {}", class(super_class)?);
+    }
+    let interfaces = (0..interface_count)
+        .map(|i| class(r.u16(pos + 8 + 2 * i)?))
+        .collect::<Result<Vec<_>>>()?;
+    if !interfaces.is_empty() {
+        line += &format!(" implements {}", interfaces.join(", "));
+    }
+    writeln!(s, "{line}")?;
+    pos += 8 + 2 * interface_count;
+
+    for kind in ["field", "method"] {
+        let count = r.u16(pos)?;
+        pos += 2;
+        for _ in 0..count {
+            let name = utf8(r.u16(pos + 2)?)?;
+            let desc = utf8(r.u16(pos + 4)?)?;
+            let attribute_count = r.u16(pos + 6)?;
+            pos += 8;
+            for _ in 0..attribute_count {
+                pos += 6 + usize_from(r.u32(pos + 2)?)?;
+            }
+            if kind == "field" {
+                writeln!(s, "field: {} {}", type_name(desc), name)?;
+            } else {
+                writeln!(s, "method: {}", method_signature(name, desc))?;
+            }
+        }
+    }
+
+    for (i, constant) in pool.iter().enumerate() {
+        match constant {
+            Constant::Class(_) if i != usize::from(this_class) && i != usize::from(super_class) => {
+                // array classes are stored as descriptors
+                let name = class(i as u16)?;
+                if name.starts_with('[') {
+                    writeln!(s, "uses: {}", type_name(&name))?
+                } else {
+                    writeln!(s, "uses: {name}")?
+                }
+            }
+            Constant::String(str) => writeln!(s, "string: {}", one_line(utf8(*str)?.as_bytes()))?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+const DEX_MAGIC: &[u8] = b"dex\n";
+
+fn dump_dex(r: BinReader, s: &mut impl Write) -> Result<()> {
+    let table = |off: usize| -> Result<(usize, usize)> {
+        Ok((usize_from(r.u32(off)?)?, usize_from(r.u32(off + 4)?)?))
+    };
+    let (string_count, string_off) = table(56)?;
+    let (type_count, type_off) = table(64)?;
+    let (proto_count, proto_off) = table(72)?;
+    let (field_count, field_off) = table(80)?;
+    let (method_count, method_off) = table(88)?;
+    let (class_count, class_off) = table(96)?;
+
+    let string = |i: usize| -> Result<String> {
+        if i >= string_count {
+            return Err(format_err!("string index {} out of range", i));
+        }
+        let data = usize_from(r.u32(string_off + 4 * i)?)?;
+        let (_utf16_len, len) = r.uleb128(data)?;
+        Ok(String::from_utf8_lossy(r.cstr(data + len)?).into_owned())
+    };
+    // strings that are identifiers, so they are not listed as string constants
+    let mut identifiers = HashSet::new();
+    let type_string = |i: usize| -> Result<usize> {
+        if i >= type_count {
+            return Err(format_err!("type index {} out of range", i));
+        }
+        usize_from(r.u32(type_off + 4 * i)?)
+    };
+    for i in 0..type_count {
+        identifiers.insert(type_string(i)?);
+    }
+    let type_name_at = |i: usize| -> Result<String> { Ok(type_name(&string(type_string(i)?)?)) };
+    let proto = |i: usize| -> Result<(String, Vec<String>)> {
+        if i >= proto_count {
+            return Err(format_err!("proto index {} out of range", i));
+        }
+        let entry = proto_off + 12 * i;
+        let ret = type_name_at(usize_from(r.u32(entry + 4)?)?)?;
+        let params_off = usize_from(r.u32(entry + 8)?)?;
+        let mut params = vec![];
+        if params_off != 0 {
+            for p in 0..usize_from(r.u32(params_off)?)? {
+                params.push(type_name_at(usize::from(r.u16(params_off + 4 + 2 * p)?))?);
+            }
+        }
+        Ok((ret, params))
+    };
+    for i in 0..proto_count {
+        identifiers.insert(usize_from(r.u32(proto_off + 12 * i)?)?);
+    }
+    for i in 0..field_count {
+        identifiers.insert(usize_from(r.u32(field_off + 8 * i + 4)?)?);
+    }
+
+    let mut defined_classes = HashSet::new();
+    for i in 0..class_count {
+        let entry = class_off + 32 * i;
+        let class_idx = usize_from(r.u32(entry)?)?;
+        defined_classes.insert(class_idx);
+        let mut line = format!("class: {}", type_name_at(class_idx)?);
+        let superclass = r.u32(entry + 8)?;
+        if superclass != u32::MAX {
+            line += &format!(" extends {}", type_name_at(usize_from(superclass)?)?);
+        }
+        writeln!(s, "{line}")?;
+    }
+
+    for i in 0..method_count {
+        let entry = method_off + 8 * i;
+        let class_idx = usize::from(r.u16(entry)?);
+        let name_idx = usize_from(r.u32(entry + 4)?)?;
+        identifiers.insert(name_idx);
+        let class = type_name_at(class_idx)?;
+        if defined_classes.contains(&class_idx) {
+            let (ret, params) = proto(usize::from(r.u16(entry + 2)?))?;
+            writeln!(
+                s,
+                "method: {} {}.{}({})",
+                ret,
+                class,
+                string(name_idx)?,
+                params.join(", ")
+            )?;
+        } else {
+            writeln!(s, "uses: {}.{}", class, string(name_idx)?)?;
+        }
+    }
+
+    for i in (0..string_count).filter(|i| !identifiers.contains(i)) {
+        writeln!(s, "string: {}", one_line(string(i)?.as_bytes()))?;
+    }
+    Ok(())
+}
+
+fn dump_java(data: &[u8], s: &mut impl Write) -> Result<()> {
+    if data.starts_with(CLASS_MAGIC) {
+        dump_class(BinReader::be(data), s)
+    } else if data.starts_with(DEX_MAGIC) {
+        dump_dex(BinReader::le(data), s)
+    } else {
+        writeln!(s, "[rga: unknown java bytecode format]")?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for JavaAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_java(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn signatures() {
+        assert_eq!(
+            method_signature("main", "([Ljava/lang/String;)V"),
+            "void main(java.lang.String[])"
+        );
+        assert_eq!(method_signature("f", "(IJ)[[Z"), "boolean[][] f(int, long)");
+        assert_eq!(method_signature("broken", "(Q)V"), "broken(Q)V");
+    }
+
+    /// class Hello extends Object { static final String greeting = "hello world"; void run() {} }
+    fn class_fixture() -> Vec<u8> {
+        let mut d = CLASS_MAGIC.to_vec();
+        d.extend([0, 0, 0, 52]);
+        let utf8 = |s: &str| {
+            let mut v = vec![1u8];
+            v.extend((s.len() as u16).to_be_bytes());
+            v.extend(s.as_bytes());
+            v
+        };
+        let pool: Vec<Vec<u8>> = vec![
+            utf8("Hello"),                   // 1
+            vec![7, 0, 1],                   // 2 class Hello
+            utf8("java/lang/Object"),        // 3
+            vec![7, 0, 3],                   // 4 class Object
+            utf8("hello world"),             // 5
+            vec![8, 0, 5],                   // 6 string
+            utf8("greeting"),                // 7
+            utf8("Ljava/lang/String;"),      // 8
+            utf8("run"),                     // 9
+            utf8("()V"),                     // 10
+            vec![5, 0, 0, 0, 0, 0, 0, 0, 1], // 11+12 long
+        ];
+        d.extend(13u16.to_be_bytes());
+        for c in pool {
+            d.extend(c);
+        }
+        d.extend([0, 0x21, 0, 2, 0, 4, 0, 0]); // flags, this, super, no interfaces
+        d.extend([0, 1, 0, 0x18, 0, 7, 0, 8, 0, 0]); // one field
+        d.extend([0, 1, 0, 0, 0, 9, 0, 10, 0, 1, 0, 9, 0, 0, 0, 2, 0xab, 0xcd]); // one method with an attribute
+        d.extend([0, 0]); // no attributes
+        d
+    }
+
+    #[test]
+    fn class() -> Result<()> {
+        let mut out = Vec::new();
+        dump_java(&class_fixture(), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "class: Hello extends java.lang.Object
+field: java.lang.String greeting
+method: void run()
+string: hello world
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn truncated_class() {
+        let d = class_fixture();
+        assert!(dump_java(&d[..40], &mut Vec::new()).is_err());
+    }
+
+    fn put(d: &mut [u8], off: usize, values: &[u32]) {
+        for (i, v) in values.iter().enumerate() {
+            d[off + 4 * i..off + 4 * i + 4].copy_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    /// class Hello extends Object { String greeting; void run(String s) { System.out.println("hello world"); } }
+    fn dex_fixture() -> Vec<u8> {
+        let strings = [
+            "LHello;",
+            "Ljava/lang/Object;",
+            "V",
+            "run",
+            "hello world",
+            "Ljava/io/PrintStream;",
+            "println",
+            "Ljava/lang/String;",
+            "greeting",
+        ];
+        let mut d = vec![0u8; 0x100];
+        d[..8].copy_from_slice(b"dex\n035\0");
+        // count and offset of the string, type, proto, field, method and class tables
+        put(
+            &mut d,
+            56,
+            &[9, 0x70, 5, 0x94, 2, 0xa8, 1, 0xc0, 2, 0xc8, 1, 0xd8],
+        );
+        put(&mut d, 0x94, &[0, 1, 2, 5, 7]);
+        // ()V and (String)V, the parameters are a type list
+        put(&mut d, 0xa8, &[2, 2, 0, 2, 2, 0xf8]);
+        put(&mut d, 0xf8, &[1, 4]);
+        // the field Hello.greeting: class and type as u16, then the name
+        put(&mut d, 0xc0, &[4 << 16, 8]);
+        // Hello.run(String) and PrintStream.println(): class and proto as u16, then the name
+        put(&mut d, 0xc8, &[1 << 16, 3, 3, 6]);
+        // class Hello, public, extends Object
+        put(&mut d, 0xd8, &[0, 1, 1]);
+        for (i, s) in strings.iter().enumerate() {
+            let off = d.len() as u32;
+            put(&mut d, 0x70 + 4 * i, &[off]);
+            d.push(s.len() as u8); // uleb128 length in UTF-16 units
+            d.extend(s.as_bytes());
+            d.push(0);
+        }
+        d
+    }
+
+    #[test]
+    fn dex() -> Result<()> {
+        let mut out = Vec::new();
+        dump_java(&dex_fixture(), &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "class: Hello extends java.lang.Object
+method: void Hello.run(java.lang.String)
+uses: java.io.PrintStream.println
+string: hello world
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn broken_dex() {
+        let d = dex_fixture();
+        assert!(dump_java(&d[..0x110], &mut Vec::new()).is_err());
+        assert!(dump_java(&d[..0x80], &mut Vec::new()).is_err());
+        // a method name that is not a string
+        let mut bad_index = d.clone();
+        put(&mut bad_index, 0xcc, &[99]);
+        assert!(dump_java(&bad_index, &mut Vec::new()).is_err());
+        // more strings and parameters than the file has room for
+        let mut bad_count = d.clone();
+        put(&mut bad_count, 56, &[u32::MAX]);
+        assert!(dump_java(&bad_count, &mut Vec::new()).is_err());
+        let mut bad_params = d;
+        put(&mut bad_params, 0xf8, &[u32::MAX]);
+        assert!(dump_java(&bad_params, &mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use super::binary::{one_line, usize_from, BinReader};
+use super::xml::{attr, xml_events, Xml};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["mo", "gmo", "qm", "resx", "resw", "xliff", "xlf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "localization".to_owned(),
+        version: 1,
+        description:
+            "Lists source => translation pairs of gettext (.mo), Qt (.qm), .NET (.resx) and XLIFF localization files"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-gettext-translation".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct LocalizationAdapter;
+
+impl LocalizationAdapter {
+    pub fn new() -> LocalizationAdapter {
+        LocalizationAdapter
+    }
+}
+impl GetMetadata for LocalizationAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// gettext message catalog
+fn dump_mo(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let r = match BinReader::le(data).u32(0)? {
+        0x950412de => BinReader::le(data),
+        0xde120495 => BinReader::be(data),
+        _ => return Err(format_err!("not a gettext .mo file")),
+    };
+    let count = usize_from(r.u32(8)?)?;
+    let originals = usize_from(r.u32(12)?)?;
+    let translations = usize_from(r.u32(16)?)?;
+    let string = |table: usize, i: usize| -> Result<&[u8]> {
+        let len = usize_from(r.u32(table + i * 8)?)?;
+        let off = usize_from(r.u32(table + i * 8 + 4)?)?;
+        r.slice(off, len)
+    };
+    for i in 0..count {
+        let original = string(originals, i)?;
+        let translation = string(translations, i)?;
+        if original.is_empty() {
+            // the header, with metadata like the language and project
+            for line in String::from_utf8_lossy(translation).lines() {
+                writeln!(s, "{line}")?;
+            }
+            continue;
+        }
+        // context\x04msgid\0plural
+        let (context, original) = match memchr::memchr(4, original) {
+            Some(i) => (Some(&original[..i]), &original[i + 1..]),
+            None => (None, original),
+        };
+        let join = |b: &[u8]| {
+            b.split(|c| *c == 0)
+                .map(one_line)
+                .collect::<Vec<_>>()
+                .join(" | ")
+        };
+        if let Some(context) = context {
+            write!(s, "[{}] ", one_line(context))?;
+        }
+        writeln!(s, "{} => {}", join(original), join(translation))?;
+    }
+    Ok(())
+}
+
+const QM_MAGIC: [u8; 16] = [
+    0x3c, 0xb8, 0x64, 0x18, 0xca, 0xef, 0x9c, 0x95, 0xcd, 0x21, 0x1c, 0xbf, 0x60, 0xa1, 0xbd, 0xdd,
+];
+
+/// compiled Qt translations
+fn dump_qm(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let r = BinReader::be(data);
+    let mut pos = QM_MAGIC.len();
+    while pos < data.len() {
+        let tag = r.u8(pos)?;
+        let len = usize_from(r.u32(pos + 1)?)?;
+        let block = r.slice(pos + 5, len)?;
+        pos += 5 + len;
+        if tag != 0x69 {
+            // only the messages block has text, the others are hashes, plural rules etc.
+            continue;
+        }
+        let b = BinReader::be(block);
+        let mut p = 0;
+        let mut translations = vec![];
+        let (mut source, mut context, mut comment) = (String::new(), String::new(), String::new());
+        while p < block.len() {
+            let item = b.u8(p)?;
+            p += 1;
+            match item {
+                // end of message
+                1 => {
+                    if !context.is_empty() {
+                        write!(s, "[{context}] ")?;
+                    }
+                    if source.is_empty() {
+                        writeln!(s, "{}", translations.join(" | "))?;
+                    } else {
+                        writeln!(s, "{source} => {}", translations.join(" | "))?;
+                    }
+                    if !comment.is_empty() {
+                        writeln!(s, "  # {comment}")?;
+                    }
+                    translations.clear();
+                    source.clear();
+                    context.clear();
+                    comment.clear();
+                }
+                // translation (UTF-16), source text, context, comment
+                3 | 6 | 7 | 8 => {
+                    let len = b.u32(p)?;
+                    p += 4;
+                    if len == u32::MAX {
+                        continue;
+                    }
+                    let bytes = b.slice(p, usize_from(len)?)?;
+                    p += bytes.len();
+                    match item {
+                        3 => {
+                            let units: Vec<u16> = bytes
+                                .chunks_exact(2)
+                                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                                .collect();
+                            let text = String::from_utf16_lossy(&units);
+                            translations.push(one_line(text.as_bytes()));
+                        }
+                        6 => source = one_line(bytes),
+                        7 => context = one_line(bytes),
+                        _ => comment = one_line(bytes),
+                    }
+                }
+                // obsolete: hash
+                5 => p += 4,
+                other => return Err(format_err!("unknown qm message tag {}", other)),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// .NET resources: <data name="..."><value>...</value><comment>...</comment></data>
+fn dump_resx(doc: &str, s: &mut impl Write) -> Result<()> {
+    let mut name = None;
+    let mut current: Option<&str> = None;
+    let (mut value, mut comment) = (String::new(), String::new());
+    for event in xml_events(doc) {
+        match event {
+            // resources with a mimetype are serialized objects like images
+            Xml::Start("data", attrs) if attr(&attrs, "mimetype").is_none() => {
+                name = attr(&attrs, "name").map(str::to_owned);
+            }
+            Xml::Start(tag @ ("value" | "comment"), _) if name.is_some() => current = Some(tag),
+            Xml::Text(t) => match current {
+                Some("value") => value.push_str(&t),
+                Some(_) => comment.push_str(&t),
+                None => {}
+            },
+            Xml::End("value" | "comment") => current = None,
+            Xml::End("data") => {
+                if let Some(name) = name.take() {
+                    writeln!(s, "{name} = {}", one_line(value.as_bytes()))?;
+                    if !comment.trim().is_empty() {
+                        writeln!(s, "  # {}", one_line(comment.trim().as_bytes()))?;
+                    }
+                }
+                value.clear();
+                comment.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// XLIFF 1.2 (<trans-unit>) and 2.x (<unit><segment>)
+fn dump_xliff(doc: &str, s: &mut impl Write) -> Result<()> {
+    let mut id = String::new();
+    let mut current: Option<&str> = None;
+    let (mut source, mut target, mut note) = (String::new(), String::new(), String::new());
+    for event in xml_events(doc) {
+        match event {
+            Xml::Start("file", attrs) => {
+                if let Some(original) = attr(&attrs, "original") {
+                    writeln!(s, "file: {original}")?;
+                }
+            }
+            Xml::Start("trans-unit" | "unit", attrs) => {
+                id = attr(&attrs, "id").unwrap_or_default().to_owned();
+            }
+            Xml::Start(tag @ ("source" | "target" | "note"), _) => current = Some(tag),
+            Xml::Text(t) => match current {
+                Some("source") => source.push_str(&t),
+                Some("target") => target.push_str(&t),
+                Some(_) => note.push_str(&t),
+                None => {}
+            },
+            Xml::End("source" | "target" | "note") => current = None,
+            Xml::End("trans-unit" | "segment" | "unit") => {
+                if !source.is_empty() {
+                    let text = |t: &str| one_line(t.trim().as_bytes());
+                    writeln!(s, "{}: {} => {}", id, text(&source), text(&target))?;
+                }
+                if !note.trim().is_empty() {
+                    writeln!(s, "  # {}", one_line(note.trim().as_bytes()))?;
+                }
+                source.clear();
+                target.clear();
+                note.clear();
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn dump_localization(data: &[u8], s: &mut impl Write) -> Result<()> {
+    if data.starts_with(&QM_MAGIC) {
+        return dump_qm(data, s);
+    }
+    match data.get(..4) {
+        Some([0xde, 0x12, 0x04, 0x95]) | Some([0x95, 0x04, 0x12, 0xde]) => return dump_mo(data, s),
+        _ => {}
+    }
+    let doc = String::from_utf8_lossy(data);
+    let doc = doc.trim_start_matches('\u{feff}');
+    if doc.contains("<xliff") {
+        dump_xliff(doc, s)
+    } else {
+        dump_resx(doc, s)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for LocalizationAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_localization(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dump(data: &[u8]) -> Result<String> {
+        let mut out = Vec::new();
+        dump_localization(data, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn mo() -> Result<()> {
+        let strings: [(&[u8], &[u8]); 3] = [
+            (b"", b"Language: de\n"),
+            (b"Open file", b"Datei \xc3\xb6ffnen"),
+            (b"menu\x04%d file\0%d files", b"%d Datei\0%d Dateien"),
+        ];
+        let mut data = vec![];
+        let header = |data: &mut Vec<u8>, v: u32| data.extend(v.to_le_bytes());
+        header(&mut data, 0x950412de);
+        header(&mut data, 0);
+        header(&mut data, 3);
+        header(&mut data, 28);
+        header(&mut data, 28 + 24);
+        header(&mut data, 0);
+        header(&mut data, 0);
+        let mut off = 28 + 48;
+        let mut blob = vec![];
+        for column in 0..2 {
+            for pair in &strings {
+                let str = if column == 0 { pair.0 } else { pair.1 };
+                header(&mut data, str.len() as u32);
+                header(&mut data, off as u32);
+                blob.extend_from_slice(str);
+                blob.push(0);
+                off += str.len() + 1;
+            }
+        }
+        data.extend(blob);
+        assert_eq!(
+            dump(&data)?,
+            "Language: de
+Open file => Datei öffnen
+[menu] %d file | %d files => %d Datei | %d Dateien
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn qm() -> Result<()> {
+        let mut messages = vec![3u8, 0, 0, 0, 4, 0, b'J', 0, b'a'];
+        messages.extend([6, 0, 0, 0, 3, b'Y', b'e', b's']);
+        messages.extend([7, 0, 0, 0, 6, b'D', b'i', b'a', b'l', b'o', b'g']);
+        messages.push(1);
+        let mut data = QM_MAGIC.to_vec();
+        data.push(0x42);
+        data.extend([0, 0, 0, 0]);
+        data.push(0x69);
+        data.extend((messages.len() as u32).to_be_bytes());
+        data.extend(messages);
+        assert_eq!(dump(&data)?, "[Dialog] Yes => Ja\n");
+        Ok(())
+    }
+
+    #[test]
+    fn xml() -> Result<()> {
+        let resx = r#"<?xml version="1.0" encoding="utf-8"?>
+<root>
+  <!-- <data name="commented"> -->
+  <data name="Greeting" xml:space="preserve">
+    <value>Hello &amp; welcome</value>
+    <comment>shown on start</comment>
+  </data>
+  <data name="Logo" type="System.Drawing.Bitmap" mimetype="application/x-microsoft.net.object.bytearray.base64">
+    <value>iVBORw0KGgo=</value>
+  </data>
+</root>"#;
+        assert_eq!(
+            dump(resx.as_bytes())?,
+            "Greeting = Hello & welcome\n  # shown on start\n"
+        );
+        let xliff = r#"<xliff version="1.2"><file original="app.ts" source-language="en" target-language="fr"><body>
+<trans-unit id="save"><source>Save <g id="1">now</g></source><target><![CDATA[Enregistrer]]> maintenant</target></trans-unit>
+</body></file></xliff>"#;
+        assert_eq!(
+            dump(xliff.as_bytes())?,
+            "file: app.ts\nsave: Save now => Enregistrer maintenant\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,933 @@
+use super::ebook::is_html_block;
+use super::xml::{xml_events, Xml};
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::vpath::{member_prefix, MEMBER_SEPARATOR};
+use anyhow::Result;
+use async_stream::stream;
+use async_trait::async_trait;
+use encoding_rs::{Encoding, UTF_8};
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, BufReader};
+
+static MBOX_EXTENSIONS: &[&str] = &["mbox", "mbx"];
+static EML_EXTENSIONS: &[&str] = &["eml"];
+static MAILDIR_DIRECTORIES: &[&str] = &["cur", "new"];
+
+lazy_static! {
+    static ref MBOX_METADATA: AdapterMeta = AdapterMeta {
+        name: "mbox".to_owned(),
+        version: 1,
+        description:
+            "Splits mbox mailboxes into their messages and recurses into the attachments. The lines of a message are prefixed with its date and subject, like 2001-07-03 10:20 Re: budget, and start with its From, To, Cc, Subject and Date headers. Quoted-printable and base64 parts are decoded, HTML parts without a plain text alternative are converted to text"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: MBOX_EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/mbox".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+    static ref EML_METADATA: AdapterMeta = AdapterMeta {
+        name: "eml".to_owned(),
+        version: 1,
+        description:
+            "Extracts the From, To, Cc, Subject and Date headers and the text of email messages and recurses into the attachments, which are prefixed with their file name like archive members. Quoted-printable and base64 parts are decoded, HTML parts without a plain text alternative are converted to text"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EML_EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType("message/rfc822".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+    static ref MAILDIR_METADATA: AdapterMeta = AdapterMeta {
+        name: "maildir".to_owned(),
+        version: 1,
+        description:
+            "Reads the messages of Maildir folders (the files in cur/ and new/ directories) like the eml adapter. The lines of the text of a message are prefixed with its sender and subject, like Alice: Re: budget. Files in these directories that are not messages are searched as they are"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: MAILDIR_DIRECTORIES
+            .iter()
+            .map(|s| FastFileMatcher::Directory(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MboxAdapter;
+
+impl MboxAdapter {
+    pub fn new() -> MboxAdapter {
+        MboxAdapter
+    }
+}
+impl GetMetadata for MboxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &MBOX_METADATA
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct EmlAdapter;
+
+impl EmlAdapter {
+    pub fn new() -> EmlAdapter {
+        EmlAdapter
+    }
+}
+impl GetMetadata for EmlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &EML_METADATA
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct MaildirAdapter;
+
+impl MaildirAdapter {
+    pub fn new() -> MaildirAdapter {
+        MaildirAdapter
+    }
+}
+impl GetMetadata for MaildirAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &MAILDIR_METADATA
+    }
+}
+
+/// the headers of a message that are output before its text
+static SHOWN_HEADERS: &[&str] = &["From", "To", "Cc", "Subject", "Date"];
+/// multiparts nested deeper than this are left out
+const MAX_DEPTH: usize = 20;
+
+fn decode_base64(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() / 4 * 3);
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    for &c in data {
+        let v = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            b'=' => break,
+            // line breaks
+            _ => continue,
+        };
+        acc = (acc << 6) | u32::from(v);
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    out
+}
+
+/// quoted-printable, in headers (`header`) `_` is a space
+fn decode_quoted_printable(data: &[u8], header: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut i = 0;
+    while i < data.len() {
+        match data[i] {
+            b'=' => {
+                let rest = &data[i + 1..];
+                // soft line break
+                if rest.starts_with(b"\r\n") {
+                    i += 3;
+                    continue;
+                }
+                if rest.starts_with(b"\n") {
+                    i += 2;
+                    continue;
+                }
+                match rest.get(..2) {
+                    Some(hex) if hex.iter().all(u8::is_ascii_hexdigit) => {
+                        let hex = std::str::from_utf8(hex).expect("ascii");
+                        out.push(u8::from_str_radix(hex, 16).expect("hex digits"));
+                        i += 3;
+                        continue;
+                    }
+                    _ => out.push(b'='),
+                }
+            }
+            b'_' if header => out.push(b' '),
+            c => out.push(c),
+        }
+        i += 1;
+    }
+    out
+}
+
+/// text in a charset of the Content-Type, UTF-8 if it is unknown
+fn decode_charset(data: &[u8], charset: Option<&str>) -> String {
+    let encoding = charset
+        .and_then(|c| Encoding::for_label(c.trim().as_bytes()))
+        .unwrap_or(UTF_8);
+    encoding.decode_without_bom_handling(data).0.into_owned()
+}
+
+/// an RFC 2047 encoded word at the start of `s` (`=?charset?B?...?=`), and its length
+fn encoded_word(s: &str) -> Option<(String, usize)> {
+    let inner = s.strip_prefix("=?")?;
+    let (charset, rest) = inner.split_once('?')?;
+    let (encoding, rest) = rest.split_once('?')?;
+    let end = rest.find("?=")?;
+    let text = &rest[..end];
+    if text.contains(' ') {
+        return None;
+    }
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(text.as_bytes()),
+        "Q" | "q" => decode_quoted_printable(text.as_bytes(), true),
+        _ => return None,
+    };
+    // charset*language (RFC 2231)
+    let charset = charset.split('*').next();
+    let len = s.len() - rest.len() + end + 2;
+    Some((decode_charset(&bytes, charset), len))
+}
+
+/// a header value with its encoded words decoded. Whitespace between encoded words is left out
+pub(crate) fn decode_header(value: &str) -> String {
+    let mut out = String::new();
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, word) = rest.split_at(start);
+        match encoded_word(word) {
+            Some((decoded, len)) => {
+                if !(after_word && before.trim().is_empty()) {
+                    out.push_str(before);
+                }
+                out.push_str(&decoded);
+                rest = &word[len..];
+                after_word = true;
+            }
+            None => {
+                out.push_str(before);
+                out.push_str("=?");
+                rest = &word[2..];
+                after_word = false;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// the value of a parameter of a header like Content-Type, e.g. `boundary` of
+/// `multipart/mixed; boundary="abc"`. Extended values (`filename*=utf-8''%C3%A4.pdf`) are decoded
+fn header_param(value: &str, name: &str) -> Option<String> {
+    for param in value.split(';').skip(1) {
+        let (key, v) = match param.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+        let key = key.trim();
+        let v = v.trim().trim_matches('"');
+        if key.eq_ignore_ascii_case(name) {
+            return Some(v.to_owned());
+        }
+        if key
+            .strip_suffix('*')
+            .map_or(false, |k| k.eq_ignore_ascii_case(name))
+        {
+            let (charset, v) = match v.split_once('\'') {
+                Some((charset, v)) => (Some(charset), v.split_once('\'').map_or(v, |(_, v)| v)),
+                None => (None, v),
+            };
+            let mut bytes = Vec::with_capacity(v.len());
+            let mut i = 0;
+            let v = v.as_bytes();
+            while i < v.len() {
+                match v.get(i + 1..i + 3) {
+                    Some(hex) if v[i] == b'%' && hex.iter().all(u8::is_ascii_hexdigit) => {
+                        let hex = std::str::from_utf8(hex).expect("ascii");
+                        bytes.push(u8::from_str_radix(hex, 16).expect("hex digits"));
+                        i += 3;
+                    }
+                    _ => {
+                        bytes.push(v[i]);
+                        i += 1;
+                    }
+                }
+            }
+            return Some(decode_charset(&bytes, charset.filter(|c| !c.is_empty())));
+        }
+    }
+    None
+}
+
+/// a message or a part of a multipart
+struct Part<'a> {
+    /// unfolded, names in lower case
+    headers: Vec<(String, String)>,
+    body: &'a [u8],
+}
+
+impl Part<'_> {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn mime_type(&self) -> String {
+        self.header("content-type")
+            .and_then(|ct| ct.split(';').next())
+            .unwrap_or("text/plain")
+            .trim()
+            .to_ascii_lowercase()
+    }
+
+    /// the body without its Content-Transfer-Encoding
+    fn decoded_body(&self) -> Vec<u8> {
+        let encoding = self
+            .header("content-transfer-encoding")
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        match encoding.as_str() {
+            "base64" => decode_base64(self.body),
+            "quoted-printable" => decode_quoted_printable(self.body, false),
+            _ => self.body.to_vec(),
+        }
+    }
+}
+
+fn parse_part(data: &[u8]) -> Part<'_> {
+    let mut headers: Vec<(String, String)> = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let end = data[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(data.len(), |i| pos + i + 1);
+        let line = String::from_utf8_lossy(&data[pos..end]);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            pos = end;
+            break;
+        }
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+        } else if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_ascii_lowercase(), value.trim().to_owned()));
+        } else {
+            // not a header, the part has no header section
+            break;
+        }
+        pos = end;
+    }
+    Part {
+        headers,
+        body: &data[pos..],
+    }
+}
+
+/// the parts of a multipart body between the `--boundary` lines
+fn split_multipart<'a>(body: &'a [u8], boundary: &str) -> Vec<&'a [u8]> {
+    let delimiter = format!("--{boundary}");
+    let mut parts = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut pos = 0;
+    while pos < body.len() {
+        let end = body[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(body.len(), |i| pos + i + 1);
+        if let Some(rest) = body[pos..end].strip_prefix(delimiter.as_bytes()) {
+            let close = rest.starts_with(b"--");
+            if close || rest.iter().all(u8::is_ascii_whitespace) {
+                if let Some(start) = start {
+                    // the line break before the delimiter belongs to it
+                    let part = &body[start..pos];
+                    let part = part.strip_suffix(b"\n").unwrap_or(part);
+                    parts.push(part.strip_suffix(b"\r").unwrap_or(part));
+                }
+                if close {
+                    return parts;
+                }
+                start = Some(end);
+            }
+        }
+        pos = end;
+    }
+    if let Some(start) = start {
+        parts.push(&body[start..]);
+    }
+    parts
+}
+
+/// adds the text collected for one block element to `out`, with whitespace collapsed like in HTML
+fn flush(line: &mut String, out: &mut String) {
+    let text = line.split_whitespace().collect::<Vec<_>>().join(" ");
+    if !text.is_empty() {
+        out.push_str(&text);
+        out.push('\n');
+    }
+    line.clear();
+}
+
+/// the text of an HTML part, one line per block element, without styles and scripts
+pub(super) fn html_text(html: &str) -> String {
+    let mut out = String::new();
+    let mut line = String::new();
+    let mut hidden = 0usize;
+    for event in xml_events(html) {
+        match event {
+            Xml::Start(tag, _) if is_hidden_html(tag) => hidden += 1,
+            Xml::End(tag) if is_hidden_html(tag) => hidden = hidden.saturating_sub(1),
+            _ if hidden > 0 => {}
+            Xml::Text(t) => line.push_str(&t.replace("&nbsp;", " ")),
+            Xml::Start(tag, _) | Xml::End(tag) if is_html_block(tag) => flush(&mut line, &mut out),
+            _ => {}
+        }
+    }
+    flush(&mut line, &mut out);
+    out
+}
+
+fn is_hidden_html(tag: &str) -> bool {
+    matches!(
+        tag.to_ascii_lowercase().as_str(),
+        "style" | "script" | "head" | "title"
+    )
+}
+
+/// the contents of a message: the text of its text parts and its attachments (name and data)
+#[derive(Default, Debug, PartialEq)]
+pub(super) struct Contents {
+    pub(super) text: String,
+    pub(super) attachments: Vec<(String, Vec<u8>)>,
+}
+
+fn shown_headers(part: &Part, out: &mut String) {
+    for name in SHOWN_HEADERS {
+        if let Some(value) = part.header(&name.to_ascii_lowercase()) {
+            out.push_str(&format!("{name}: {}\n", decode_header(value)));
+        }
+    }
+}
+
+fn collect_contents(part: &Part, depth: usize, contents: &mut Contents) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let mime_type = part.mime_type();
+    let content_type = part.header("content-type").unwrap_or_default();
+    if mime_type.starts_with("multipart/") {
+        if let Some(boundary) = header_param(content_type, "boundary") {
+            let parts = split_multipart(part.body, &boundary)
+                .into_iter()
+                .map(parse_part)
+                .collect::<Vec<_>>();
+            if mime_type == "multipart/alternative" {
+                // the plain text, else the last (richest) alternative
+                if let Some(part) = parts
+                    .iter()
+                    .find(|p| p.mime_type() == "text/plain")
+                    .or_else(|| parts.last())
+                {
+                    collect_contents(part, depth + 1, contents);
+                }
+            } else {
+                for part in &parts {
+                    collect_contents(part, depth + 1, contents);
+                }
+            }
+            return;
+        }
+    }
+    let disposition = part.header("content-disposition").unwrap_or_default();
+    let filename = header_param(disposition, "filename")
+        .or_else(|| header_param(content_type, "name"))
+        .map(|name| decode_header(&name));
+    let attachment = disposition
+        .trim_start()
+        .to_ascii_lowercase()
+        .starts_with("attachment");
+    if mime_type == "message/rfc822" && !attachment {
+        // a forwarded message
+        let body = part.decoded_body();
+        let message = parse_part(&body);
+        shown_headers(&message, &mut contents.text);
+        collect_contents(&message, depth + 1, contents);
+    } else if mime_type.starts_with("text/") && !attachment {
+        let text = decode_charset(
+            &part.decoded_body(),
+            header_param(content_type, "charset").as_deref(),
+        );
+        if mime_type == "text/html" {
+            contents.text.push_str(&html_text(&text));
+        } else {
+            contents.text.push_str(&text);
+            if !text.ends_with('\n') {
+                contents.text.push('\n');
+            }
+        }
+    } else {
+        let name = filename.unwrap_or_else(|| "attachment".to_owned());
+        contents.attachments.push((name, part.decoded_body()));
+    }
+}
+
+/// "Tue, 3 Jul 2001 10:20:30 +0200" as "2001-07-03 10:20"
+fn short_date(date: &str) -> Option<String> {
+    static MONTHS: &[&str] = &[
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let mut tokens = date
+        .split_whitespace()
+        // the day of the week
+        .skip_while(|t| t.chars().next().map_or(false, |c| c.is_alphabetic()));
+    let day: u32 = tokens.next()?.parse().ok()?;
+    let month = tokens.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? + 1;
+    let year: u32 = tokens.next()?.parse().ok()?;
+    let year = match year {
+        0..=49 => year + 2000,
+        50..=999 => year + 1900,
+        _ => year,
+    };
+    let time = tokens.next()?.get(..5)?;
+    if !time.chars().all(|c| c.is_ascii_digit() || c == ':') {
+        return None;
+    }
+    Some(format!("{year:04}-{month:02}-{day:02} {time}"))
+}
+
+/// the name of a message for the line prefixes: its date and subject
+fn message_name(message: &Part, number: usize) -> String {
+    let date = message
+        .header("date")
+        .map(|d| short_date(d).unwrap_or_else(|| d.to_owned()));
+    let subject = message.header("subject").map(decode_header);
+    let name = [date, subject]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>()
+        .join(" ");
+    let name = name
+        .replace(['\r', '\n', '\t'], " ")
+        .replace(MEMBER_SEPARATOR, "! ");
+    if name.trim().is_empty() {
+        format!("message {number}")
+    } else {
+        name.trim().to_owned()
+    }
+}
+
+/// the sender and subject of a message for the line prefixes, like `Alice: Re: budget`
+fn sender_subject(message: &Part) -> String {
+    let sender = message.header("from").map(|from| {
+        let from = decode_header(from);
+        // the display name, or else the address
+        let name = from
+            .split('<')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .trim_matches('"')
+            .trim();
+        if name.is_empty() {
+            from.trim().trim_matches(['<', '>']).to_owned()
+        } else {
+            name.to_owned()
+        }
+    });
+    let subject = message.header("subject").map(decode_header);
+    [sender, subject]
+        .into_iter()
+        .flatten()
+        .filter(|s| !s.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join(": ")
+        .replace(['\r', '\n', '\t'], " ")
+        .trim()
+        .to_owned()
+}
+
+/// the text (with the shown headers) and the attachments of a message
+fn read_message(data: &[u8]) -> (Part<'_>, Contents) {
+    let message = parse_part(data);
+    let mut contents = Contents::default();
+    shown_headers(&message, &mut contents.text);
+    collect_contents(&message, 0, &mut contents);
+    // in mailboxes, the empty line before the next "From " line is not part of the message
+    contents.text.truncate(contents.text.trim_end().len());
+    contents.text.push('\n');
+    (message, contents)
+}
+
+/// the file name of the text of the message in `filepath_hint`. Without its directory, which could
+/// be the cur/ or new/ of a Maildir
+pub(super) fn text_hint(filepath_hint: &Path) -> PathBuf {
+    let name = filepath_hint.file_name().unwrap_or_default();
+    PathBuf::from(format!("{}.txt", name.to_string_lossy()))
+}
+
+/// the text of a message as a file with the line prefix `prefix`, followed by its attachments as
+/// members of it
+pub(super) fn message_files(
+    contents: Contents,
+    text_hint: PathBuf,
+    prefix: &str,
+    archive_recursion_depth: i32,
+    config: &RgaConfig,
+    postprocess: bool,
+) -> Vec<AdaptInfo> {
+    let mut files = vec![AdaptInfo {
+        filepath_hint: text_hint,
+        is_real_file: false,
+        archive_recursion_depth: archive_recursion_depth + 1,
+        inp: Box::pin(Cursor::new(contents.text.into_bytes())),
+        line_prefix: prefix.to_owned(),
+        config: config.clone(),
+        postprocess,
+    }];
+    for (filename, attachment) in contents.attachments {
+        files.push(AdaptInfo {
+            line_prefix: member_prefix(prefix, &filename),
+            filepath_hint: PathBuf::from(filename),
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            inp: Box::pin(Cursor::new(attachment)),
+            config: config.clone(),
+            postprocess,
+        });
+    }
+    files
+}
+
+#[async_trait]
+impl FileAdapter for MboxAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+            ..
+        } = ai;
+        let s = stream! {
+            let mut inp = BufReader::new(inp);
+            let mut data = Vec::new();
+            let mut line = Vec::new();
+            let mut previous_blank = true;
+            let mut number = 0;
+            loop {
+                line.clear();
+                let len = inp.read_until(b'\n', &mut line).await?;
+                // a "From " line after an empty line starts the next message
+                if len == 0 || (previous_blank && line.starts_with(b"From ")) {
+                    if data.iter().any(|b: &u8| !b.is_ascii_whitespace()) {
+                        number += 1;
+                        let (message, contents) = read_message(&data);
+                        let name = message_name(&message, number);
+                        let files = message_files(
+                            contents,
+                            PathBuf::from(format!("{name}.txt")),
+                            &member_prefix(&line_prefix, &name),
+                            archive_recursion_depth,
+                            &config,
+                            postprocess,
+                        );
+                        for file in files {
+                            yield Ok(file);
+                        }
+                    }
+                    data.clear();
+                    if len == 0 {
+                        break;
+                    }
+                } else {
+                    // mboxrd escapes "From " at the start of lines with >
+                    let unescaped = match line.iter().position(|&b| b != b'>') {
+                        Some(i) if i > 0 && line[i..].starts_with(b"From ") => &line[1..],
+                        _ => &line[..],
+                    };
+                    data.extend_from_slice(unescaped);
+                }
+                previous_blank = line == b"\n" || line == b"\r\n";
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[async_trait]
+impl FileAdapter for EmlAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let (_, contents) = read_message(&data);
+        let files = message_files(
+            contents,
+            text_hint(&filepath_hint),
+            &line_prefix,
+            archive_recursion_depth,
+            &config,
+            postprocess,
+        );
+        Ok(Box::pin(tokio_stream::iter(files.into_iter().map(Ok))))
+    }
+}
+
+#[async_trait]
+impl FileAdapter for MaildirAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let text_hint = text_hint(&filepath_hint);
+        let (message, contents) = read_message(&data);
+        if message.header("from").is_none() && message.header("date").is_none() {
+            // e.g. the files of other programs in a directory called new
+            return Ok(one_file(AdaptInfo {
+                filepath_hint: text_hint,
+                is_real_file: false,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                inp: Box::pin(Cursor::new(data)),
+                line_prefix,
+                config,
+                postprocess,
+            }));
+        }
+        let name = sender_subject(&message);
+        let mut files = message_files(
+            contents,
+            text_hint,
+            &line_prefix,
+            archive_recursion_depth,
+            &config,
+            postprocess,
+        );
+        // the attachments keep the virtual paths of members
+        if !name.is_empty() {
+            files[0].line_prefix = format!("{line_prefix}{name}: ");
+        }
+        Ok(Box::pin(tokio_stream::iter(files.into_iter().map(Ok))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    static MBOX: &[u8] = b"From alice@example.com Tue Jul  3 10:20:30 2001
+From: Alice <alice@example.com>
+To: bob@example.com
+Subject: =?utf-8?Q?Caf=C3=A9?= budget
+Date: Tue, 3 Jul 2001 10:20:30 +0200
+Content-Type: text/plain; charset=iso-8859-1
+Content-Transfer-Encoding: quoted-printable
+
+The budget for the caf=E9 is =
+ready.
+>From now on we meet on Mondays.
+
+From bob@example.com Wed Jul  4 08:00:00 2001
+From: bob@example.com
+Subject: Re: budget
+Date: 4 Jul 2001 08:00 -0000
+MIME-Version: 1.0
+Content-Type: multipart/mixed; boundary=\"outer\"
+
+--outer
+Content-Type: multipart/alternative; boundary=\"inner\"
+
+--inner
+Content-Type: text/html
+
+<html><head><style>p { color: red }</style></head><body><p>Looks good</p></body></html>
+--inner--
+--outer
+Content-Type: text/plain; name=\"notes.txt\"
+Content-Disposition: attachment; filename*=utf-8''n%C3%B6tes.txt
+Content-Transfer-Encoding: base64
+
+YXR0YWNoZWQgbm90ZXMK
+--outer--
+";
+
+    static EML: &[u8] = b"From: Carol <carol@example.com>
+To: dave@example.com
+Subject: Quarterly report
+Content-Type: multipart/mixed; boundary=\"b\"
+
+--b
+Content-Type: text/html; charset=utf-8
+
+<p>See the attached report.</p><p>Regards</p>
+--b
+Content-Type: text/plain
+Content-Disposition: attachment; filename=\"report.txt\"
+Content-Transfer-Encoding: base64
+
+UmV2ZW51ZSB1cCAxMCUK
+--b--
+";
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            decode_header("=?utf-8?B?w6Rw?= =?iso-8859-1?q?f=E9l?= and =?x?"),
+            "äpfél and =?x?"
+        );
+        assert_eq!(
+            short_date("Tue, 3 Jul 2001 10:20:30 +0200").as_deref(),
+            Some("2001-07-03 10:20")
+        );
+        assert_eq!(short_date("yesterday"), None);
+        assert_eq!(
+            header_param("text/plain; charset=\"utf-8\"; format=flowed", "charset").as_deref(),
+            Some("utf-8")
+        );
+    }
+
+    #[tokio::test]
+    async fn mbox() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("mail.mbox"),
+            Box::pin(Cursor::new(MBOX.to_vec())),
+        );
+        let r = loop_adapt(&MboxAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:2001-07-03 10:20 Café budget: From: Alice <alice@example.com>
+PREFIX:2001-07-03 10:20 Café budget: To: bob@example.com
+PREFIX:2001-07-03 10:20 Café budget: Subject: Café budget
+PREFIX:2001-07-03 10:20 Café budget: Date: Tue, 3 Jul 2001 10:20:30 +0200
+PREFIX:2001-07-03 10:20 Café budget: The budget for the café is ready.
+PREFIX:2001-07-03 10:20 Café budget: From now on we meet on Mondays.
+PREFIX:2001-07-03 10:20 Café budget: 
+PREFIX:2001-07-04 08:00 Re: budget: From: bob@example.com
+PREFIX:2001-07-04 08:00 Re: budget: Subject: Re: budget
+PREFIX:2001-07-04 08:00 Re: budget: Date: 4 Jul 2001 08:00 -0000
+PREFIX:2001-07-04 08:00 Re: budget: Looks good
+PREFIX:2001-07-04 08:00 Re: budget: 
+PREFIX:2001-07-04 08:00 Re: budget!/nötes.txt: attached notes
+PREFIX:2001-07-04 08:00 Re: budget!/nötes.txt: 
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn eml() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("report.eml"),
+            Box::pin(Cursor::new(EML.to_vec())),
+        );
+        let r = loop_adapt(&EmlAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:From: Carol <carol@example.com>
+PREFIX:To: dave@example.com
+PREFIX:Subject: Quarterly report
+PREFIX:See the attached report.
+PREFIX:Regards
+PREFIX:
+PREFIX:report.txt: Revenue up 10%
+PREFIX:report.txt: 
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn maildir() -> Result<()> {
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("Maildir/cur/1700000000.M1P2.host:2,S"),
+            Box::pin(Cursor::new(EML.to_vec())),
+        );
+        let r = loop_adapt(&MaildirAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:Carol: Quarterly report: From: Carol <carol@example.com>
+PREFIX:Carol: Quarterly report: To: dave@example.com
+PREFIX:Carol: Quarterly report: Subject: Quarterly report
+PREFIX:Carol: Quarterly report: See the attached report.
+PREFIX:Carol: Quarterly report: Regards
+PREFIX:Carol: Quarterly report: 
+PREFIX:report.txt: Revenue up 10%
+PREFIX:report.txt: 
+"
+        );
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("project/new/todo"),
+            Box::pin(Cursor::new(b"buy milk\nNote: call Bob\n".to_vec())),
+        );
+        let r = loop_adapt(&MaildirAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(o, "PREFIX:buy milk\nPREFIX:Note: call Bob\nPREFIX:\n");
+
+        let message =
+            parse_part(b"From: \"Doe, Jane\" <jane@example.com>\nSubject: Re:\n budget\n\n");
+        assert_eq!(sender_subject(&message), "Doe, Jane: Re: budget");
+        let message = parse_part(b"From: <jane@example.com>\n\n");
+        assert_eq!(sender_subject(&message), "jane@example.com");
+        Ok(())
+    }
+}
@@ -0,0 +1,446 @@
+use super::binary::{usize_from, utf16le_lossy, BinReader};
+use super::cfb::{CompoundFile, EntryKind};
+use super::mail::{html_text, message_files, text_hint, Contents};
+use super::rtf::{codepage_encoding, rtf_to_text};
+use super::*;
+use crate::dates::civil_from_days;
+use anyhow::*;
+use async_trait::async_trait;
+use encoding_rs::{Encoding, WINDOWS_1252};
+use lazy_static::lazy_static;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["msg"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "msg".to_owned(),
+        version: 1,
+        description:
+            "Extracts the sender, recipients, subject, date and body of Outlook .msg files and recurses into the attachments, which are prefixed with their file name like archive members. Attached messages are searched as part of the message"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.ms-outlook".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct MsgAdapter;
+
+impl MsgAdapter {
+    pub fn new() -> MsgAdapter {
+        MsgAdapter
+    }
+}
+impl GetMetadata for MsgAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// attached messages nested deeper than this are left out
+const MAX_DEPTH: usize = 20;
+
+// the MAPI properties we read
+const SUBJECT: u16 = 0x0037;
+const CLIENT_SUBMIT_TIME: u16 = 0x0039;
+const SENDER_NAME: u16 = 0x0c1a;
+const SENDER_EMAIL: u16 = 0x0c1f;
+const DISPLAY_CC: u16 = 0x0e03;
+const DISPLAY_TO: u16 = 0x0e04;
+const BODY: u16 = 0x1000;
+const RTF_COMPRESSED: u16 = 0x1009;
+const BODY_HTML: u16 = 0x1013;
+const DISPLAY_NAME: u16 = 0x3001;
+const ATTACH_DATA: u16 = 0x3701;
+const ATTACH_FILENAME: u16 = 0x3704;
+const ATTACH_LONG_FILENAME: u16 = 0x3707;
+const INTERNET_CPID: u16 = 0x3fde;
+const MESSAGE_CODEPAGE: u16 = 0x3ffd;
+
+// the types of the properties
+const PT_STRING8: u16 = 0x001e;
+const PT_UNICODE: u16 = 0x001f;
+const PT_BINARY: u16 = 0x0102;
+const PT_OBJECT: u16 = 0x000d;
+
+/// the length of the header of the property stream of the message, an attached message and an
+/// attachment
+const MESSAGE_HEADER_LEN: usize = 32;
+const EMBEDDED_HEADER_LEN: usize = 24;
+const ATTACHMENT_HEADER_LEN: usize = 8;
+
+/// a storage of a .msg file (the message, an attachment or an attached message) and its properties
+struct Properties<'a, 'b> {
+    cf: &'b CompoundFile<'a>,
+    storage: usize,
+    header_len: usize,
+    /// the encoding of the 8-bit strings
+    encoding: &'static Encoding,
+}
+
+impl<'a, 'b> Properties<'a, 'b> {
+    fn new(
+        cf: &'b CompoundFile<'a>,
+        storage: usize,
+        header_len: usize,
+        encoding: &'static Encoding,
+    ) -> Properties<'a, 'b> {
+        let mut p = Properties {
+            cf,
+            storage,
+            header_len,
+            encoding,
+        };
+        if let Some(encoding) = p
+            .fixed(MESSAGE_CODEPAGE)
+            .or_else(|| p.fixed(INTERNET_CPID))
+            .and_then(|codepage| codepage_encoding(codepage as i64))
+        {
+            p.encoding = encoding;
+        }
+        p
+    }
+
+    /// the stream of a property that is too long for the property stream
+    fn stream(&self, id: u16, typ: u16) -> Option<Vec<u8>> {
+        let name = format!("__substg1.0_{id:04X}{typ:04X}");
+        let stream = self.cf.child(self.storage, &name)?;
+        self.cf.read(stream).ok()
+    }
+
+    fn string(&self, id: u16) -> Option<String> {
+        let s = match self.stream(id, PT_UNICODE) {
+            Some(data) => utf16le_lossy(&data),
+            None => self
+                .encoding
+                .decode(&self.stream(id, PT_STRING8)?)
+                .0
+                .into_owned(),
+        };
+        let s = s.trim_end_matches('\0').trim();
+        if s.is_empty() {
+            None
+        } else {
+            Some(s.to_owned())
+        }
+    }
+
+    /// the value of a property with a fixed size, which are all in the property stream
+    fn fixed(&self, id: u16) -> Option<u64> {
+        let stream = self.cf.child(self.storage, "__properties_version1.0")?;
+        let data = self.cf.read(stream).ok()?;
+        let r = BinReader::le(&data);
+        (self.header_len..data.len())
+            .step_by(16)
+            .find(|&off| r.u16(off + 2).ok() == Some(id))
+            .and_then(|off| r.u64(off + 8).ok())
+    }
+}
+
+/// a FILETIME (100 ns since 1601) as "2001-07-03 08:20:30 UTC"
+fn filetime(filetime: u64) -> Option<String> {
+    let secs = (filetime / 10_000_000).checked_sub(11_644_473_600)?;
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    Some(format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    ))
+}
+
+/// the dictionary that compressed RTF starts with
+static RTF_PREBUF: &[u8] = b"{\\rtf1\\ansi\\mac\\deff0\\deftab720{\\fonttbl;}{\\f0\\fnil \\froman \\fswiss \\fmodern \\fscript \\fdecor MS Sans SerifSymbolArialTimes New RomanCourier{\\colortbl\\red0\\green0\\blue0\r\n\\par \\pard\\plain\\f0\\fs20\\b\\i\\u\\tab\\tx";
+
+/// decompress the RTF body of a message (MS-OXRTFCP)
+fn decompress_rtf(data: &[u8]) -> Result<Vec<u8>> {
+    let r = BinReader::le(data);
+    let compressed_len = usize_from(r.u32(0)?)?;
+    let raw_len = usize_from(r.u32(4)?)?;
+    let body = r.slice(
+        16,
+        compressed_len
+            .saturating_sub(12)
+            .min(data.len().saturating_sub(16)),
+    )?;
+    match r.u32(8)? {
+        // "MELA", not compressed
+        0x414c454d => return Ok(body[..raw_len.min(body.len())].to_vec()),
+        // "LZFu"
+        0x75465a4c => {}
+        _ => bail!("unknown compressed RTF format"),
+    }
+    let mut dict = [0u8; 4096];
+    dict[..RTF_PREBUF.len()].copy_from_slice(RTF_PREBUF);
+    let mut write = RTF_PREBUF.len();
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < body.len() {
+        let control = body[pos];
+        pos += 1;
+        for bit in 0..8 {
+            if control & (1 << bit) == 0 {
+                let b = match body.get(pos) {
+                    Some(&b) => b,
+                    None => return Ok(out),
+                };
+                pos += 1;
+                out.push(b);
+                dict[write] = b;
+                write = (write + 1) % dict.len();
+            } else {
+                // a reference to a run of bytes in the dictionary
+                let reference = match body.get(pos..pos + 2) {
+                    Some(r) => u16::from_be_bytes([r[0], r[1]]),
+                    None => return Ok(out),
+                };
+                pos += 2;
+                let offset = usize::from(reference >> 4);
+                if offset == write {
+                    return Ok(out);
+                }
+                for i in 0..usize::from(reference & 0xf) + 2 {
+                    let b = dict[(offset + i) % dict.len()];
+                    out.push(b);
+                    dict[write] = b;
+                    write = (write + 1) % dict.len();
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// the body of a message: the plain text, else the HTML or the RTF version
+fn body_text(p: &Properties) -> String {
+    if let Some(body) = p.string(BODY) {
+        return body.replace("\r\n", "\n");
+    }
+    let html = p
+        .stream(BODY_HTML, PT_BINARY)
+        .map(|html| p.encoding.decode(&html).0.into_owned())
+        .or_else(|| p.string(BODY_HTML));
+    if let Some(html) = html {
+        return html_text(&html);
+    }
+    let rtf = p
+        .stream(RTF_COMPRESSED, PT_BINARY)
+        .and_then(|rtf| decompress_rtf(&rtf).ok())
+        .and_then(|rtf| rtf_to_text(&rtf).ok());
+    match rtf {
+        Some(text) => text
+            .lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty())
+            .map(|l| format!("{l}\n"))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn collect_contents(p: &Properties, depth: usize, contents: &mut Contents) {
+    if depth > MAX_DEPTH {
+        return;
+    }
+    let from = match (p.string(SENDER_NAME), p.string(SENDER_EMAIL)) {
+        (Some(name), Some(email)) if name != email => Some(format!("{name} <{email}>")),
+        (name, email) => name.or(email),
+    };
+    let headers = [
+        ("From", from),
+        ("To", p.string(DISPLAY_TO)),
+        ("Cc", p.string(DISPLAY_CC)),
+        ("Subject", p.string(SUBJECT)),
+        ("Date", p.fixed(CLIENT_SUBMIT_TIME).and_then(filetime)),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            contents.text.push_str(&format!("{name}: {value}\n"));
+        }
+    }
+    let body = body_text(p);
+    let body = body.trim();
+    if !body.is_empty() {
+        contents.text.push_str(body);
+        contents.text.push('\n');
+    }
+    for child in p.cf.children(p.storage) {
+        let entry = p.cf.entry(child);
+        if entry.kind != EntryKind::Storage || !entry.name.starts_with("__attach_version1.0_") {
+            continue;
+        }
+        let attachment = Properties::new(p.cf, child, ATTACHMENT_HEADER_LEN, p.encoding);
+        let embedded =
+            p.cf.child(
+                child,
+                &format!("__substg1.0_{ATTACH_DATA:04X}{PT_OBJECT:04X}"),
+            )
+            .filter(|&e| p.cf.entry(e).kind == EntryKind::Storage);
+        if let Some(embedded) = embedded {
+            // an attached message
+            let message = Properties::new(p.cf, embedded, EMBEDDED_HEADER_LEN, p.encoding);
+            collect_contents(&message, depth + 1, contents);
+        } else if let Some(data) = attachment.stream(ATTACH_DATA, PT_BINARY) {
+            let name = attachment
+                .string(ATTACH_LONG_FILENAME)
+                .or_else(|| attachment.string(ATTACH_FILENAME))
+                .or_else(|| attachment.string(DISPLAY_NAME))
+                .unwrap_or_else(|| "attachment".to_owned());
+            contents.attachments.push((name, data));
+        }
+    }
+}
+
+fn read_msg(data: &[u8]) -> Result<Contents> {
+    let cf = CompoundFile::parse(data)?;
+    let mut contents = Contents::default();
+    let message = Properties::new(&cf, cf.root(), MESSAGE_HEADER_LEN, WINDOWS_1252);
+    collect_contents(&message, 0, &mut contents);
+    Ok(contents)
+}
+
+#[async_trait]
+impl FileAdapter for MsgAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let contents = read_msg(&data)?;
+        let files = message_files(
+            contents,
+            text_hint(&filepath_hint),
+            &line_prefix,
+            archive_recursion_depth,
+            &config,
+            postprocess,
+        );
+        Ok(Box::pin(tokio_stream::iter(files.into_iter().map(Ok))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::cfb::build_compound_file;
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+
+    fn utf16(s: &str) -> Vec<u8> {
+        s.encode_utf16().flat_map(|u| u.to_le_bytes()).collect()
+    }
+
+    fn properties(header_len: usize, props: &[(u16, u16, u64)]) -> Vec<u8> {
+        let mut out = vec![0; header_len];
+        for &(id, typ, value) in props {
+            out.extend(((u32::from(id) << 16) | u32::from(typ)).to_le_bytes());
+            out.extend(6u32.to_le_bytes());
+            out.extend(value.to_le_bytes());
+        }
+        out
+    }
+
+    /// `{\rtf1\ansi\ansicpg1252\pard hello world}`, the example of MS-OXRTFCP
+    static COMPRESSED_RTF: &[u8] = b"\x2d\x00\x00\x00\x2b\x00\x00\x00\x4c\x5a\x46\x75\xf1\xc5\xc7\xa7\x03\x00\x0a\x00\x72\x63\x70\x67\x31\x32\x35\x42\x32\x0a\xf3\x20\x68\x65\x6c\x09\x00\x20\x62\x77\x05\xb0\x6c\x64\x7d\x0a\x80\x0f\xa0";
+
+    fn msg() -> Vec<u8> {
+        let props = properties(
+            MESSAGE_HEADER_LEN,
+            &[(CLIENT_SUBMIT_TIME, 0x0040, 126386220300000000)],
+        );
+        let embedded_props = properties(EMBEDDED_HEADER_LEN, &[(MESSAGE_CODEPAGE, 0x0003, 1252)]);
+        build_compound_file(&[
+            ("__properties_version1.0", &props),
+            ("__substg1.0_0037001F", &utf16("Quarterly report")),
+            ("__substg1.0_0C1A001F", &utf16("Carol")),
+            ("__substg1.0_0C1F001F", &utf16("carol@example.com")),
+            ("__substg1.0_0E04001F", &utf16("Dave\0")),
+            ("__substg1.0_10090102", COMPRESSED_RTF),
+            (
+                "__attach_version1.0_#00000000/__properties_version1.0",
+                &properties(ATTACHMENT_HEADER_LEN, &[]),
+            ),
+            (
+                "__attach_version1.0_#00000000/__substg1.0_3707001F",
+                &utf16("report.txt"),
+            ),
+            (
+                "__attach_version1.0_#00000000/__substg1.0_37010102",
+                b"Revenue up 10%\n",
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3701000D/__properties_version1.0",
+                &embedded_props,
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3701000D/__substg1.0_0037001E",
+                b"Caf\xe9",
+            ),
+            (
+                "__attach_version1.0_#00000001/__substg1.0_3701000D/__substg1.0_1000001E",
+                b"See you at the caf\xe9\r\nat noon\r\n",
+            ),
+        ])
+    }
+
+    #[test]
+    fn helpers() -> Result<()> {
+        assert_eq!(
+            decompress_rtf(COMPRESSED_RTF)?,
+            b"{\\rtf1\\ansi\\ansicpg1252\\pard hello world}\r\n"
+        );
+        assert_eq!(
+            filetime(126386220300000000).as_deref(),
+            Some("2001-07-03 08:20:30 UTC")
+        );
+        assert_eq!(filetime(0), None);
+        assert!(read_msg(b"not a msg").is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn msg_attachments() -> Result<()> {
+        let (a, d) = simple_adapt_info(&PathBuf::from("report.msg"), Box::pin(Cursor::new(msg())));
+        let r = loop_adapt(&MsgAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert_eq!(
+            o,
+            "PREFIX:From: Carol <carol@example.com>
+PREFIX:To: Dave
+PREFIX:Subject: Quarterly report
+PREFIX:Date: 2001-07-03 08:20:30 UTC
+PREFIX:hello world
+PREFIX:Subject: Café
+PREFIX:See you at the café
+PREFIX:at noon
+PREFIX:
+PREFIX:report.txt: Revenue up 10%
+PREFIX:report.txt: 
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,235 @@
+use super::custom::{map_exe_error, pipe_output};
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::config::RgaConfig;
+use crate::policy;
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+static EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff"];
+
+/// the OCR engines, selected with --rga-ocr-backend. The first one is the default
+static BACKENDS: &[&str] = &["tesseract", "kraken"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ocr".to_owned(),
+        version: 1,
+        description:
+            "Recognizes the text in images (OCR) with tesseract, or with kraken for handwriting (--rga-ocr-backend=kraken). The languages and the page segmentation for tesseract are set with --rga-ocr-languages and --rga-ocr-psm. Slow, so it is disabled by default"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        // screenshots and scans are often saved without the right extension
+        slow_matchers: Some(
+            ["image/jpeg", "image/png", "image/gif", "image/webp", "image/bmp", "image/tiff"]
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OcrAdapter;
+
+impl OcrAdapter {
+    pub fn new() -> OcrAdapter {
+        OcrAdapter
+    }
+}
+impl GetMetadata for OcrAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn backend(config: &RgaConfig) -> Result<&str> {
+    match config.ocr_backend.as_deref() {
+        None => Ok(BACKENDS[0]),
+        Some(name) => match BACKENDS.iter().find(|b| **b == name) {
+            Some(b) => Ok(*b),
+            None => bail!(
+                "unknown OCR backend {}, available: {}",
+                name,
+                BACKENDS.join(", ")
+            ),
+        },
+    }
+}
+
+/// the OCR options from the config as tesseract arguments
+fn options(config: &RgaConfig) -> Vec<String> {
+    let mut args = vec![];
+    if !config.ocr_languages.is_empty() {
+        args.push("-l".to_owned());
+        args.push(config.ocr_languages.join("+"));
+    }
+    if let Some(psm) = config.ocr_psm {
+        args.push("--psm".to_owned());
+        args.push(psm.to_string());
+    }
+    args
+}
+
+/// the kraken pipeline: baseline segmentation (needed for handwriting), then recognition with the model
+fn kraken_options(config: &RgaConfig) -> Vec<String> {
+    let mut args = vec!["segment".to_owned(), "-bl".to_owned(), "ocr".to_owned()];
+    if let Some(model) = &config.ocr_model {
+        args.push("-m".to_owned());
+        args.push(model.clone());
+    }
+    args
+}
+
+/// the OCR backend and options for the cache key, since they change the output. None for the defaults
+pub fn options_key(config: &RgaConfig) -> Option<String> {
+    let mut options = options(config);
+    if let Some(backend) = config.ocr_backend.as_deref().filter(|b| *b != BACKENDS[0]) {
+        options.insert(0, backend.to_owned());
+    }
+    if let Some(model) = &config.ocr_model {
+        options.push("-m".to_owned());
+        options.push(model.clone());
+    }
+    if options.is_empty() {
+        return None;
+    }
+    Some(format!("ocr:{}", options.join(" ")))
+}
+
+async fn tesseract(
+    config: &RgaConfig,
+    filepath_hint: &Path,
+    line_prefix: &str,
+    inp: ReadBox,
+) -> Result<ReadBox> {
+    if let Some(psm) = config.ocr_psm {
+        // 0 only detects the orientation, 2 only the layout, without any text
+        if psm > 13 || psm == 0 || psm == 2 {
+            bail!("--rga-ocr-psm={} is not a mode that recognizes text", psm);
+        }
+    }
+    let mut cmd = policy::command(&config.policy, "tesseract", filepath_hint)?;
+    cmd.args(["stdin", "stdout"]).args(options(config));
+    pipe_output(line_prefix, cmd, inp, "tesseract", "Install tesseract-ocr.")
+}
+
+async fn kraken(config: &RgaConfig, filepath_hint: &Path, mut inp: ReadBox) -> Result<ReadBox> {
+    // kraken only reads and writes files, and prints its progress to stdout
+    let dir = tempfile::tempdir().context("creating temporary directory")?;
+    let image = dir.path().join(
+        filepath_hint
+            .file_name()
+            .unwrap_or_else(|| std::ffi::OsStr::new("image")),
+    );
+    let mut file = tokio::fs::File::create(&image)
+        .await
+        .context("creating temporary file")?;
+    tokio::io::copy(&mut inp, &mut file).await?;
+    drop(file);
+    let txt = dir.path().join("out.txt");
+    let out = policy::command(&config.policy, "kraken", filepath_hint)?
+        .arg("-i")
+        .arg(&image)
+        .arg(&txt)
+        .args(kraken_options(config))
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "kraken", "Install kraken (pip install kraken)."))?;
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        bail!(
+            "kraken failed ({}): {}",
+            out.status,
+            stderr.trim().lines().last().unwrap_or_default()
+        );
+    }
+    let text = tokio::fs::read(&txt)
+        .await
+        .context("reading output of kraken")?;
+    Ok(Box::pin(Cursor::new(text)))
+}
+
+#[async_trait]
+impl FileAdapter for OcrAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let output = match backend(&config)? {
+            "kraken" => kraken(&config, &filepath_hint, inp).await?,
+            _ => tesseract(&config, &filepath_hint, &line_prefix, inp).await?,
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: output,
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ocr_options() {
+        let mut config = RgaConfig::default();
+        assert_eq!(options_key(&config), None);
+        config.ocr_languages = vec!["eng".to_owned(), "deu".to_owned()];
+        config.ocr_psm = Some(4);
+        assert_eq!(options(&config), vec!["-l", "eng+deu", "--psm", "4"]);
+        assert_eq!(
+            options_key(&config).as_deref(),
+            Some("ocr:-l eng+deu --psm 4")
+        );
+    }
+
+    #[test]
+    fn backends() {
+        let mut config = RgaConfig::default();
+        assert_eq!(backend(&config).unwrap(), "tesseract");
+        config.ocr_backend = Some("tesseract".to_owned());
+        assert_eq!(options_key(&config), None);
+        config.ocr_backend = Some("kraken".to_owned());
+        config.ocr_model = Some("htr.mlmodel".to_owned());
+        assert_eq!(backend(&config).unwrap(), "kraken");
+        assert_eq!(
+            kraken_options(&config),
+            vec!["segment", "-bl", "ocr", "-m", "htr.mlmodel"]
+        );
+        assert_eq!(
+            options_key(&config).as_deref(),
+            Some("ocr:kraken -m htr.mlmodel")
+        );
+        config.ocr_backend = Some("trocr".to_owned());
+        assert!(backend(&config).is_err());
+    }
+}
@@ -0,0 +1,667 @@
+use super::custom::BUILTIN_SPAWNING_ADAPTERS;
+use super::xml::{attr, xml_events, Xml};
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &[
+    "docx", "docm", "dotx", "xlsx", "xlsm", "xltx", "pptx", "pptm", "ppsx",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "ooxml".to_owned(),
+        version: 3,
+        description:
+            "Extracts the text of Word (.docx), Excel (.xlsx) and PowerPoint (.pptx) documents, including footnotes, comments, headers, text boxes and speaker notes, without external programs. Each table row is a line with the cells separated by tabs, spreadsheet rows are prefixed with the sheet and cell, like Sheet1!A5, and the lines of slides and their notes with the slide number, like slide 12:. Falls back to pandoc for .docx files it can't read"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType(
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.document"
+                    .to_owned()
+            ),
+            FileMatcher::MimeType(
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet".to_owned()
+            ),
+            FileMatcher::MimeType(
+                "application/vnd.openxmlformats-officedocument.presentationml.presentation"
+                    .to_owned()
+            )
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OoxmlAdapter;
+
+impl OoxmlAdapter {
+    pub fn new() -> OoxmlAdapter {
+        OoxmlAdapter
+    }
+}
+impl GetMetadata for OoxmlAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// `ppt/slides/slide12.xml` + `ppt/slides/slide` -> 12
+fn part_number(name: &str, prefix: &str) -> Option<u32> {
+    name.strip_prefix(prefix)?
+        .strip_suffix(".xml")?
+        .parse()
+        .ok()
+}
+
+/// the parts of the package that contain text
+fn is_text_part(name: &str) -> bool {
+    matches!(
+        name,
+        "word/document.xml"
+            | "word/footnotes.xml"
+            | "word/endnotes.xml"
+            | "word/comments.xml"
+            | "xl/workbook.xml"
+            | "xl/_rels/workbook.xml.rels"
+            | "xl/sharedStrings.xml"
+            | "ppt/presentation.xml"
+            | "ppt/_rels/presentation.xml.rels"
+    ) || ["word/header", "word/footer"]
+        .iter()
+        .any(|p| name.starts_with(p) && name.ends_with(".xml"))
+        || part_number(name, "xl/worksheets/sheet").is_some()
+        || part_number(name, "ppt/slides/slide").is_some()
+        || name
+            .strip_prefix("ppt/slides/_rels/")
+            .and_then(|n| n.strip_suffix(".rels"))
+            .map_or(false, |n| part_number(n, "slide").is_some())
+        || part_number(name, "ppt/notesSlides/notesSlide").is_some()
+}
+
+/// read the text parts of the package (a zip file), by name
+async fn read_parts(data: &[u8]) -> Result<BTreeMap<String, String>> {
+    use async_zip::read::stream::ZipFileReader;
+    let mut zip = ZipFileReader::new(Cursor::new(data));
+    let mut parts = BTreeMap::new();
+    while let Some(mut entry) = zip.next_entry().await? {
+        let name = entry.entry().filename().to_owned();
+        if !is_text_part(&name) {
+            zip = entry.skip().await?;
+            continue;
+        }
+        let mut buf = Vec::new();
+        let reader = entry.reader();
+        tokio::pin!(reader);
+        reader.read_to_end(&mut buf).await?;
+        parts.insert(name, String::from_utf8_lossy(&buf).into_owned());
+        zip = entry.done().await?;
+    }
+    Ok(parts)
+}
+
+/// appends the paragraphs (`<w:p>`, `<a:p>`) of a WordprocessingML or DrawingML part, one per line.
+/// Text is in `<w:t>` / `<a:t>` elements, everything else (formatting, deleted text, field codes) is skipped.
+/// A table row is a single line, with a tab after each cell
+fn paragraphs(xml: &str, out: &mut String) {
+    let (mut in_run, mut in_text) = (false, false);
+    // depth of the table rows and of the skipped <mc:Fallback> elements
+    let (mut rows, mut fallback) = (0u32, 0u32);
+    let mut line = String::new();
+    let mut flush = |line: &mut String| {
+        if !line.trim().is_empty() {
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        line.clear();
+    };
+    for event in xml_events(xml) {
+        match event {
+            // text boxes and other newer content are stored twice, the fallback for older readers is skipped
+            Xml::Start("Fallback", _) => fallback += 1,
+            Xml::End("Fallback") => fallback = fallback.saturating_sub(1),
+            _ if fallback > 0 => {}
+            Xml::Start("r", _) => in_run = true,
+            Xml::End("r") => in_run = false,
+            Xml::Start("t", _) => in_text = true,
+            Xml::End("t") => in_text = false,
+            Xml::Text(t) if in_text => line.push_str(&t),
+            // <w:tab/> in a paragraph's properties is a tab stop, not a tab
+            Xml::Start("tab", _) if in_run => line.push('\t'),
+            Xml::Start("br" | "cr", _) => line.push('\n'),
+            Xml::Start("noBreakHyphen", _) if in_run => line.push('-'),
+            Xml::Start("tr", _) => rows += 1,
+            Xml::End("tr") if rows > 0 => {
+                flush(&mut line);
+                rows -= 1;
+            }
+            Xml::End("p") if rows > 0 => {
+                if !line.is_empty() && !line.ends_with('\t') {
+                    line.push(' ');
+                }
+            }
+            Xml::End("tc") if rows > 0 => {
+                line.truncate(line.trim_end_matches(' ').len());
+                line.push('\t');
+            }
+            Xml::End("p") => flush(&mut line),
+            _ => {}
+        }
+    }
+    flush(&mut line);
+}
+
+/// `AB12` -> 28
+fn column_number(cell: &str) -> u32 {
+    cell.chars()
+        .take_while(|c| c.is_ascii_alphabetic())
+        .fold(0, |n, c| {
+            n * 26 + (c.to_ascii_uppercase() as u32 - 'A' as u32 + 1)
+        })
+}
+
+/// 28 -> `AB`
+pub(super) fn column_name(mut n: u32) -> String {
+    let mut name = Vec::new();
+    while n > 0 {
+        name.push(b'A' + ((n - 1) % 26) as u8);
+        n = (n - 1) / 26;
+    }
+    name.reverse();
+    String::from_utf8(name).expect("ascii")
+}
+
+/// the sheet name as written in a formula, quoted if it contains spaces or punctuation
+pub(super) fn quote_sheet(name: &str) -> String {
+    if name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        name.to_owned()
+    } else {
+        format!("'{}'", name.replace('\'', "''"))
+    }
+}
+
+/// the text of the `<si>` items of sharedStrings.xml. Phonetic readings (`<rPh>`) are skipped
+fn shared_strings(xml: &str) -> Vec<String> {
+    let mut strings = Vec::new();
+    let (mut current, mut in_text, mut in_phonetic) = (String::new(), false, false);
+    for event in xml_events(xml) {
+        match event {
+            Xml::Start("si", _) => current.clear(),
+            Xml::End("si") => strings.push(std::mem::take(&mut current)),
+            Xml::Start("rPh", _) => in_phonetic = true,
+            Xml::End("rPh") => in_phonetic = false,
+            Xml::Start("t", _) => in_text = !in_phonetic,
+            Xml::End("t") => in_text = false,
+            Xml::Text(t) if in_text => current.push_str(&t),
+            _ => {}
+        }
+    }
+    strings
+}
+
+/// the relationships of a part, from `dir/_rels/name.rels`: id -> (type, target part). Targets are
+/// relative to the directory of the part
+fn relationships(
+    parts: &BTreeMap<String, String>,
+    part: &str,
+) -> BTreeMap<String, (String, String)> {
+    let (dir, name) = part.rsplit_once('/').unwrap_or(("", part));
+    let mut targets = BTreeMap::new();
+    let rels = match parts.get(&format!("{dir}/_rels/{name}.rels")) {
+        Some(rels) => rels,
+        None => return targets,
+    };
+    for event in xml_events(rels) {
+        if let Xml::Start("Relationship", attrs) = event {
+            if let (Some(id), Some(target)) = (attr(&attrs, "Id"), attr(&attrs, "Target")) {
+                let mut path: Vec<&str> = match target.strip_prefix('/') {
+                    Some(_) => vec![],
+                    None => dir.split('/').filter(|s| !s.is_empty()).collect(),
+                };
+                for segment in target.split('/') {
+                    match segment {
+                        "" | "." => {}
+                        ".." => {
+                            path.pop();
+                        }
+                        s => path.push(s),
+                    }
+                }
+                let kind = attr(&attrs, "Type").unwrap_or_default();
+                targets.insert(id.to_owned(), (kind.to_owned(), path.join("/")));
+            }
+        }
+    }
+    targets
+}
+
+/// the `r:id` attribute, which links to a relationship. Not `id`, which is something else
+fn relationship_id<'a>(attrs: &'a [(&str, String)]) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| k.ends_with(":id"))
+        .map(|(_, v)| v.as_str())
+}
+
+/// the names and parts of the worksheets, in the order of the workbook
+fn sheets(parts: &BTreeMap<String, String>) -> Vec<(String, String)> {
+    let targets = relationships(parts, "xl/workbook.xml");
+    let workbook = parts.get("xl/workbook.xml").map_or("", |w| w.as_str());
+    let mut sheets = Vec::new();
+    for event in xml_events(workbook) {
+        if let Xml::Start("sheet", attrs) = event {
+            let part = relationship_id(&attrs).and_then(|id| targets.get(id));
+            if let (Some(name), Some((_, part))) = (attr(&attrs, "name"), part) {
+                sheets.push((name.to_owned(), part.clone()));
+            }
+        }
+    }
+    if sheets.is_empty() {
+        // without the relationships, sheetN usually is the Nth sheet
+        let mut parts: Vec<(u32, &String)> = parts
+            .keys()
+            .filter_map(|k| Some((part_number(k, "xl/worksheets/sheet")?, k)))
+            .collect();
+        parts.sort_by_key(|(n, _)| *n);
+        sheets = parts
+            .into_iter()
+            .map(|(n, k)| (format!("Sheet{n}"), k.clone()))
+            .collect();
+    }
+    sheets
+}
+
+/// appends the rows of a worksheet, one per line with the cells separated by tabs, prefixed with the
+/// sheet and the first cell of the row. Formulas are skipped, their cached values are used
+fn sheet_rows(sheet: &str, xml: &str, strings: &[String], out: &mut String) {
+    let sheet = quote_sheet(sheet);
+    let (mut row, mut column) = (0u32, 0u32);
+    let mut cells: Vec<String> = Vec::new();
+    let mut first: Option<String> = None;
+    let (mut cell, mut kind, mut value) = (String::new(), String::new(), String::new());
+    let (mut in_value, mut in_phonetic) = (false, false);
+    for event in xml_events(xml) {
+        match event {
+            Xml::Start("row", attrs) => {
+                row = attr(&attrs, "r")
+                    .and_then(|r| r.parse().ok())
+                    .unwrap_or(row + 1);
+                column = 0;
+                cells.clear();
+                first = None;
+            }
+            Xml::Start("c", attrs) => {
+                cell = match attr(&attrs, "r") {
+                    Some(r) => r.to_owned(),
+                    None => format!("{}{}", column_name(column + 1), row),
+                };
+                column = column_number(&cell);
+                kind = attr(&attrs, "t").unwrap_or("n").to_owned();
+                value.clear();
+            }
+            Xml::Start("rPh", _) => in_phonetic = true,
+            Xml::End("rPh") => in_phonetic = false,
+            // <v> is the value, <is><t> an inline string
+            Xml::Start("v" | "t", _) => in_value = !in_phonetic,
+            Xml::End("v" | "t") => in_value = false,
+            Xml::Text(t) if in_value => value.push_str(&t),
+            Xml::End("c") => {
+                let text = match kind.as_str() {
+                    "s" => value
+                        .trim()
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|i| strings.get(i))
+                        .cloned()
+                        .unwrap_or_default(),
+                    "b" if value.trim() == "1" => "TRUE".to_owned(),
+                    "b" => "FALSE".to_owned(),
+                    _ => std::mem::take(&mut value),
+                };
+                // a cell is a single field of the line
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    first.get_or_insert_with(|| cell.clone());
+                    cells.push(text);
+                }
+            }
+            Xml::End("row") => {
+                if let Some(first) = first.take() {
+                    out.push_str(&format!("{}!{}: {}\n", sheet, first, cells.join("\t")));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// the parts of the slides, in the order of the presentation
+fn slides(parts: &BTreeMap<String, String>) -> Vec<String> {
+    let targets = relationships(parts, "ppt/presentation.xml");
+    let presentation = parts.get("ppt/presentation.xml").map_or("", |p| p.as_str());
+    let slides: Vec<String> = xml_events(presentation)
+        .into_iter()
+        .filter_map(|event| match event {
+            Xml::Start("sldId", attrs) => Some(targets.get(relationship_id(&attrs)?)?.1.clone()),
+            _ => None,
+        })
+        .collect();
+    if !slides.is_empty() {
+        return slides;
+    }
+    // without the relationships, slideN usually is the Nth slide
+    let mut slides: Vec<(u32, &String)> = parts
+        .keys()
+        .filter_map(|k| Some((part_number(k, "ppt/slides/slide")?, k)))
+        .collect();
+    slides.sort_by_key(|(n, _)| *n);
+    slides.into_iter().map(|(_, k)| k.clone()).collect()
+}
+
+/// the notes of a slide, linked in its relationships
+fn notes_of<'a>(parts: &'a BTreeMap<String, String>, slide: &str) -> Option<&'a String> {
+    let rels = relationships(parts, slide);
+    if rels.is_empty() {
+        // notesSlideN usually belongs to slideN
+        let n = part_number(slide, "ppt/slides/slide")?;
+        return parts.get(&format!("ppt/notesSlides/notesSlide{n}.xml"));
+    }
+    rels.values()
+        .find(|(kind, _)| kind.ends_with("/notesSlide"))
+        .and_then(|(_, notes)| parts.get(notes))
+}
+
+/// the text of a docx, xlsx or pptx
+fn extract_text(parts: &BTreeMap<String, String>) -> Result<String> {
+    let mut out = String::new();
+    if let Some(document) = parts.get("word/document.xml") {
+        paragraphs(document, &mut out);
+        // footnotes, endnotes, comments, then headers and footers
+        let mut rest: Vec<&String> = parts.keys().filter(|k| *k != "word/document.xml").collect();
+        rest.sort_by_key(|k| (k.contains("/header") || k.contains("/footer"), *k));
+        for name in rest {
+            paragraphs(&parts[name], &mut out);
+        }
+        // the line prefix is added after each newline, and postprocessing ends the output with one
+        if out.ends_with('\n') {
+            out.pop();
+        }
+        return Ok(out);
+    }
+    if parts.contains_key("xl/workbook.xml") {
+        let strings = parts
+            .get("xl/sharedStrings.xml")
+            .map(|s| shared_strings(s))
+            .unwrap_or_default();
+        for (name, part) in sheets(parts) {
+            if let Some(sheet) = parts.get(&part) {
+                sheet_rows(&name, sheet, &strings, &mut out);
+            }
+        }
+        if out.ends_with('\n') {
+            out.pop();
+        }
+        return Ok(out);
+    }
+    let slides = slides(parts);
+    if slides.is_empty() {
+        bail!("neither a word document, a spreadsheet nor a presentation");
+    }
+    for (i, slide) in slides.iter().enumerate() {
+        let mut text = String::new();
+        if let Some(xml) = parts.get(slide) {
+            paragraphs(xml, &mut text);
+        }
+        if let Some(notes) = notes_of(parts, slide) {
+            paragraphs(notes, &mut text);
+        }
+        for line in text.lines() {
+            out.push_str(&format!("slide {}: {}\n", i + 1, line));
+        }
+    }
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl FileAdapter for OoxmlAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let res = read_parts(&data)
+            .await
+            .and_then(|parts| extract_text(&parts));
+        let text = match res {
+            Ok(res) => res,
+            Err(e) if filepath_hint.extension().map_or(false, |e| e == "docx") => {
+                debug!(
+                    "{}: {:#}, falling back to pandoc",
+                    filepath_hint.display(),
+                    e
+                );
+                let pandoc = BUILTIN_SPAWNING_ADAPTERS
+                    .iter()
+                    .find(|a| a.name == "pandoc")
+                    .expect("pandoc is a builtin adapter")
+                    .to_adapter();
+                let ai = AdaptInfo {
+                    inp: Box::pin(Cursor::new(data)),
+                    filepath_hint,
+                    line_prefix,
+                    archive_recursion_depth,
+                    postprocess,
+                    config,
+                    is_real_file: false,
+                };
+                return pandoc.adapt(ai, detection_reason).await;
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use async_zip::{write::ZipFileWriter, Compression, ZipEntryBuilder};
+    use pretty_assertions::assert_eq;
+
+    async fn create_package(parts: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        for (name, content) in parts {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    async fn adapt(name: &str, data: Vec<u8>) -> Result<String> {
+        let (a, d) = simple_adapt_info(&PathBuf::from(name), Box::pin(Cursor::new(data)));
+        let o = adapted_to_vec(loop_adapt(&OoxmlAdapter::new(), d, a).await?).await?;
+        Ok(String::from_utf8(o)?)
+    }
+
+    #[tokio::test]
+    async fn docx() -> Result<()> {
+        let document = r#"<?xml version="1.0" encoding="UTF-8"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:body>
+<w:p><w:pPr><w:tabs><w:tab w:val="left" w:pos="720"/></w:tabs></w:pPr><w:r><w:t>The Waste</w:t></w:r><w:r><w:t xml:space="preserve"> Land</w:t></w:r></w:p>
+<w:p></w:p>
+<w:p><w:r><w:t>April</w:t><w:tab/><w:t>is the cruellest month &amp; so on</w:t></w:r><w:del><w:r><w:delText>gone</w:delText></w:r></w:del></w:p>
+<w:tbl><w:tr><w:tc><w:p><w:r><w:t>Part</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>Title</w:t></w:r></w:p></w:tc></w:tr>
+<w:tr><w:tc><w:p><w:r><w:t>I</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>The Burial</w:t></w:r></w:p><w:p><w:r><w:t>of the Dead</w:t></w:r></w:p></w:tc></w:tr></w:tbl>
+<w:p><w:r><mc:AlternateContent><mc:Choice Requires="wps"><w:drawing><wps:txbx><w:txbxContent><w:p><w:r><w:t>text box</w:t></w:r></w:p></w:txbxContent></wps:txbx></w:drawing></mc:Choice><mc:Fallback><w:pict><v:textbox><w:txbxContent><w:p><w:r><w:t>text box</w:t></w:r></w:p></w:txbxContent></v:textbox></w:pict></mc:Fallback></mc:AlternateContent></w:r><w:r><w:t>T. S.</w:t><w:noBreakHyphen/><w:t>Eliot</w:t></w:r></w:p>
+</w:body></w:document>"#;
+        let footnotes = r#"<w:footnotes xmlns:w="x"><w:footnote w:id="1"><w:p><w:r><w:t>a footnote</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+        let header =
+            r#"<w:hdr xmlns:w="x"><w:p><w:r><w:t>running header</w:t></w:r></w:p></w:hdr>"#;
+        let docx = create_package(&[
+            ("[Content_Types].xml", "<Types/>"),
+            ("word/header1.xml", header),
+            ("word/document.xml", document),
+            ("word/footnotes.xml", footnotes),
+            ("word/styles.xml", "<w:t>not text</w:t>"),
+        ])
+        .await?;
+        assert_eq!(
+            adapt("test.docx", docx).await?,
+            "PREFIX:The Waste Land
+PREFIX:April\tis the cruellest month & so on
+PREFIX:Part\tTitle
+PREFIX:I\tThe Burial of the Dead
+PREFIX:text box
+PREFIX:T. S.-Eliot
+PREFIX:a footnote
+PREFIX:running header
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn xlsx() -> Result<()> {
+        let workbook = r#"<workbook xmlns="x" xmlns:r="y"><sheets><sheet name="Q1 Sales" sheetId="1" r:id="rId2"/><sheet name="Notes" sheetId="2" r:id="rId1"/></sheets></workbook>"#;
+        let rels = r#"<Relationships xmlns="x"><Relationship Id="rId1" Type="worksheet" Target="worksheets/sheet1.xml"/><Relationship Id="rId2" Type="worksheet" Target="/xl/worksheets/sheet2.xml"/></Relationships>"#;
+        let strings = r#"<sst xmlns="x"><si><t>Region</t></si><si><r><t>Total</t></r><r><t xml:space="preserve"> sold</t></r></si><si><t>North</t><rPh><t>ノース</t></rPh></si></sst>"#;
+        let sales = r#"<worksheet xmlns="x"><sheetData>
+<row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>
+<row r="2"><c r="A2" t="s"><v>2</v></c><c r="B2"><f>SUM(C2:D2)</f><v>1250.5</v></c><c r="C2" t="b"><v>1</v></c></row>
+<row r="4"><c r="A4"/><c r="B4" t="inlineStr"><is><t>two
+lines</t></is></c></row>
+</sheetData></worksheet>"#;
+        let notes = r#"<worksheet xmlns="x"><sheetData><row><c t="str"><v>checked</v></c></row></sheetData></worksheet>"#;
+        let xlsx = create_package(&[
+            ("[Content_Types].xml", "<Types/>"),
+            ("xl/workbook.xml", workbook),
+            ("xl/_rels/workbook.xml.rels", rels),
+            ("xl/sharedStrings.xml", strings),
+            ("xl/worksheets/sheet1.xml", notes),
+            ("xl/worksheets/sheet2.xml", sales),
+            ("xl/styles.xml", "<t>not text</t>"),
+        ])
+        .await?;
+        assert_eq!(
+            adapt("test.xlsx", xlsx).await?,
+            "PREFIX:'Q1 Sales'!A1: Region\tTotal sold
+PREFIX:'Q1 Sales'!A2: North\t1250.5\tTRUE
+PREFIX:'Q1 Sales'!B4: two lines
+PREFIX:Notes!A1: checked
+"
+        );
+        assert_eq!(column_name(28), "AB");
+        assert_eq!(column_number("AB12"), 28);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pptx() -> Result<()> {
+        let slide = |text: &str| {
+            format!(
+                r#"<p:sld xmlns:p="x" xmlns:a="y"><p:cSld><p:spTree><p:sp><p:txBody><a:p><a:r><a:t>{text}</a:t></a:r><a:br/><a:r><a:t>second line</a:t></a:r></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>"#
+            )
+        };
+        let (s1, s2, s10) = (slide("first"), slide("second"), slide("tenth"));
+        let notes = r#"<p:notes xmlns:p="x" xmlns:a="y"><a:p><a:r><a:t>speaker notes</a:t></a:r></a:p></p:notes>"#;
+        let pptx = create_package(&[
+            ("ppt/slides/slide10.xml", &s10),
+            ("ppt/slides/slide2.xml", &s2),
+            ("ppt/slides/slide1.xml", &s1),
+            ("ppt/notesSlides/notesSlide2.xml", notes),
+        ])
+        .await?;
+        assert_eq!(
+            adapt("test.pptx", pptx).await?,
+            "PREFIX:slide 1: first
+PREFIX:slide 1: second line
+PREFIX:slide 2: second
+PREFIX:slide 2: second line
+PREFIX:slide 2: speaker notes
+PREFIX:slide 3: tenth
+PREFIX:slide 3: second line
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn pptx_relationships() -> Result<()> {
+        let slide = |text: &str| {
+            format!(
+                r#"<p:sld xmlns:p="x" xmlns:a="y"><p:cSld><p:spTree><p:sp><p:txBody><a:p><a:r><a:t>{text}</a:t></a:r></a:p></p:txBody></p:sp></p:spTree></p:cSld></p:sld>"#
+            )
+        };
+        let (s1, s2) = (slide("added last"), slide("moved to the front"));
+        let presentation = r#"<p:presentation xmlns:p="x" xmlns:r="y"><p:sldIdLst><p:sldId id="257" r:id="rId3"/><p:sldId id="256" r:id="rId2"/></p:sldIdLst></p:presentation>"#;
+        let rels = r#"<Relationships xmlns="x"><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/slide" Target="slides/slide1.xml"/><Relationship Id="rId3" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/slide" Target="slides/slide2.xml"/></Relationships>"#;
+        let slide_rels = r#"<Relationships xmlns="x"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/notesSlide" Target="../notesSlides/notesSlide1.xml"/></Relationships>"#;
+        let layout_rels = r#"<Relationships xmlns="x"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/slideLayout" Target="../slideLayouts/slideLayout1.xml"/></Relationships>"#;
+        let notes = r#"<p:notes xmlns:p="x" xmlns:a="y"><a:p><a:r><a:t>speaker notes</a:t></a:r></a:p></p:notes>"#;
+        let pptx = create_package(&[
+            ("ppt/presentation.xml", presentation),
+            ("ppt/_rels/presentation.xml.rels", rels),
+            ("ppt/slides/slide1.xml", &s1),
+            ("ppt/slides/_rels/slide1.xml.rels", layout_rels),
+            ("ppt/slides/slide2.xml", &s2),
+            ("ppt/slides/_rels/slide2.xml.rels", slide_rels),
+            ("ppt/notesSlides/notesSlide1.xml", notes),
+        ])
+        .await?;
+        assert_eq!(
+            adapt("test.pptx", pptx).await?,
+            "PREFIX:slide 1: moved to the front
+PREFIX:slide 1: speaker notes
+PREFIX:slide 2: added last
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_a_package() -> Result<()> {
+        let zip = create_package(&[("hello.txt", "hello")]).await?;
+        assert!(adapt("test.pptx", zip).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,462 @@
+use super::custom::BUILTIN_SPAWNING_ADAPTERS;
+use super::ooxml::{column_name, quote_sheet};
+use super::xml::{attr, xml_events, Xml};
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &[
+    "odt", "ott", "fodt", "ods", "ots", "fods", "odp", "otp", "fodp",
+];
+
+/// repeated rows and cells with content are output at most this often, a formatted column or a
+/// filled range can repeat a cell for the whole sheet
+const MAX_REPEAT: u32 = 1000;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "opendocument".to_owned(),
+        version: 1,
+        description:
+            "Extracts the text of OpenDocument text documents (.odt), spreadsheets (.ods) and presentations (.odp), including flat XML ones, without external programs. Spreadsheet rows are prefixed with the sheet and cell, like Sheet1!A5, and the lines of slides with the slide number, like slide 3:. Falls back to pandoc for .odt files it can't read"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.oasis.opendocument.text".to_owned()),
+            FileMatcher::MimeType("application/vnd.oasis.opendocument.spreadsheet".to_owned()),
+            FileMatcher::MimeType("application/vnd.oasis.opendocument.presentation".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct OpenDocumentAdapter;
+
+impl OpenDocumentAdapter {
+    pub fn new() -> OpenDocumentAdapter {
+        OpenDocumentAdapter
+    }
+}
+impl GetMetadata for OpenDocumentAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// content.xml and styles.xml of the package (a zip file). A flat XML document (.fodt) is both
+async fn read_parts(data: &[u8]) -> Result<(String, String)> {
+    use async_zip::read::stream::ZipFileReader;
+    if data.iter().find(|b| !b.is_ascii_whitespace()) == Some(&b'<') {
+        let flat = String::from_utf8_lossy(data).into_owned();
+        return Ok((flat.clone(), flat));
+    }
+    let mut zip = ZipFileReader::new(Cursor::new(data));
+    let (mut content, mut styles) = (None, None);
+    while let Some(mut entry) = zip.next_entry().await? {
+        let name = entry.entry().filename().to_owned();
+        if name != "content.xml" && name != "styles.xml" {
+            zip = entry.skip().await?;
+            continue;
+        }
+        let mut buf = Vec::new();
+        let reader = entry.reader();
+        tokio::pin!(reader);
+        reader.read_to_end(&mut buf).await?;
+        let text = String::from_utf8_lossy(&buf).into_owned();
+        if name == "content.xml" {
+            content = Some(text);
+        } else {
+            styles = Some(text);
+        }
+        zip = entry.done().await?;
+    }
+    match content {
+        Some(content) => Ok((content, styles.unwrap_or_default())),
+        None => bail!("no content.xml, not an OpenDocument package"),
+    }
+}
+
+/// the number in a `number-rows-repeated` or `number-columns-repeated` attribute
+fn repeated(attrs: &[(&str, String)], name: &str) -> u32 {
+    attr(attrs, name)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// appends the text of an event inside a paragraph, spaces and tabs are elements
+fn inline(event: &Xml, line: &mut String) {
+    match event {
+        Xml::Text(t) => line.push_str(t),
+        // <text:s text:c="3"/> are three spaces
+        Xml::Start("s", attrs) => {
+            let count = attr(attrs, "c").and_then(|c| c.parse().ok()).unwrap_or(1);
+            line.push_str(&" ".repeat(count));
+        }
+        Xml::Start("tab", _) => line.push('\t'),
+        Xml::Start("line-break", _) => line.push('\n'),
+        _ => {}
+    }
+}
+
+/// the lines of a text document or presentation: paragraphs and headings, with each table row on
+/// one line with a tab after each cell. `prefix` returns the prefix of the lines, e.g. the slide
+fn paragraphs<'a>(
+    events: impl IntoIterator<Item = Xml<'a>>,
+    prefix: impl Fn(u32) -> String,
+) -> String {
+    let mut out = String::new();
+    // the open paragraphs, notes and text boxes are paragraphs in paragraphs
+    let mut open: Vec<String> = Vec::new();
+    let mut row = String::new();
+    let (mut rows, mut skip, mut page) = (0u32, 0u32, 0u32);
+    let mut flush = |line: &str, page: u32| {
+        for line in line.lines().filter(|l| !l.trim().is_empty()) {
+            out.push_str(&prefix(page));
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+    };
+    for event in events {
+        match event {
+            // deleted text, and the number of a footnote in the text
+            Xml::Start("tracked-changes" | "note-citation", _) => skip += 1,
+            Xml::End("tracked-changes" | "note-citation") => skip = skip.saturating_sub(1),
+            _ if skip > 0 => {}
+            Xml::Start("page", _) => page += 1,
+            Xml::Start("p" | "h", _) => open.push(String::new()),
+            Xml::End("p" | "h") => {
+                let line = open.pop().unwrap_or_default();
+                if rows > 0 && open.is_empty() {
+                    if !row.is_empty() && !row.ends_with('\t') {
+                        row.push(' ');
+                    }
+                    row.push_str(line.trim());
+                } else {
+                    flush(&line, page);
+                }
+            }
+            Xml::Start("table-row", _) => rows += 1,
+            Xml::End("table-row") if rows > 0 => {
+                flush(&row, page);
+                row.clear();
+                rows -= 1;
+            }
+            Xml::End("table-cell" | "covered-table-cell") if rows > 0 => row.push('\t'),
+            event => {
+                if let Some(line) = open.last_mut() {
+                    inline(&event, line);
+                }
+            }
+        }
+    }
+    out
+}
+
+/// the headers and footers of the master pages in styles.xml
+fn headers_footers(styles: &str) -> Vec<Xml<'_>> {
+    let mut depth = 0u32;
+    xml_events(styles)
+        .into_iter()
+        .filter(|event| {
+            let is_header = |name: &str| name.starts_with("header") || name.starts_with("footer");
+            match event {
+                Xml::Start(name, _) if is_header(name) => depth += 1,
+                Xml::End(name) if is_header(name) => {
+                    depth = depth.saturating_sub(1);
+                    return true;
+                }
+                _ => {}
+            }
+            depth > 0
+        })
+        .collect()
+}
+
+/// the rows of the sheets, one per line with the cells separated by tabs, prefixed with the sheet and
+/// the first cell of the row
+fn spreadsheet<'a>(events: impl IntoIterator<Item = Xml<'a>>) -> String {
+    let mut out = String::new();
+    let mut sheet = String::new();
+    let (mut row, mut column) = (0u32, 0u32);
+    let (mut row_repeat, mut cell_repeat) = (1, 1);
+    // the first column with content and the texts of the cells
+    let mut first: Option<u32> = None;
+    let mut cells: Vec<String> = Vec::new();
+    let (mut cell, mut value) = (String::new(), None);
+    let (mut in_cell, mut in_paragraph) = (false, false);
+    for event in events {
+        match event {
+            Xml::Start("table", attrs) => {
+                sheet = quote_sheet(attr(&attrs, "name").unwrap_or("Sheet"));
+                row = 0;
+            }
+            Xml::Start("table-row", attrs) => {
+                row_repeat = repeated(&attrs, "number-rows-repeated");
+                column = 0;
+                first = None;
+                cells.clear();
+            }
+            Xml::Start("table-cell" | "covered-table-cell", attrs) => {
+                cell_repeat = repeated(&attrs, "number-columns-repeated");
+                cell.clear();
+                // the text is the formatted value, this is used if there is none
+                value = [
+                    "value",
+                    "date-value",
+                    "time-value",
+                    "boolean-value",
+                    "string-value",
+                ]
+                .iter()
+                .find_map(|a| attr(&attrs, a).map(str::to_owned));
+                in_cell = true;
+            }
+            Xml::End("table-cell" | "covered-table-cell") => {
+                let text = match cell.trim() {
+                    "" => value.take().unwrap_or_default(),
+                    _ => std::mem::take(&mut cell),
+                };
+                // a cell is a single field of the line
+                let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+                if !text.is_empty() {
+                    first.get_or_insert(column + 1);
+                    for _ in 0..cell_repeat.min(MAX_REPEAT) {
+                        cells.push(text.clone());
+                    }
+                }
+                column = column.saturating_add(cell_repeat);
+                in_cell = false;
+            }
+            Xml::Start("p", _) if in_cell => {
+                if !cell.is_empty() {
+                    cell.push(' ');
+                }
+                in_paragraph = true;
+            }
+            Xml::End("p") => in_paragraph = false,
+            Xml::End("table-row") => {
+                if let Some(first) = first {
+                    for r in 0..row_repeat.min(MAX_REPEAT) {
+                        out.push_str(&format!(
+                            "{}!{}{}: {}\n",
+                            sheet,
+                            column_name(first),
+                            row + r + 1,
+                            cells.join("\t")
+                        ));
+                    }
+                }
+                row = row.saturating_add(row_repeat);
+            }
+            event if in_paragraph => {
+                inline(&event, &mut cell);
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// the text of a text document, spreadsheet or presentation
+fn extract_text(content: &str, styles: &str) -> Result<String> {
+    let events = xml_events(content);
+    // the kind of document is the element in <office:body>
+    let kind = events
+        .iter()
+        .skip_while(|e| !matches!(e, Xml::Start("body", _)))
+        .find_map(|e| match e {
+            Xml::Start(name, _) if *name != "body" => Some(*name),
+            _ => None,
+        });
+    let mut out = match kind {
+        Some("text") => {
+            let mut out = paragraphs(events, |_| String::new());
+            out.push_str(&paragraphs(headers_footers(styles), |_| String::new()));
+            out
+        }
+        Some("spreadsheet") => spreadsheet(events),
+        Some("presentation") => paragraphs(events, |page| format!("slide {page}: ")),
+        Some(other) => bail!("unsupported OpenDocument {}", other),
+        None => bail!("no office:body, not an OpenDocument document"),
+    };
+    // the line prefix is added after each newline, and postprocessing ends the output with one
+    if out.ends_with('\n') {
+        out.pop();
+    }
+    Ok(out)
+}
+
+#[async_trait]
+impl FileAdapter for OpenDocumentAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            mut inp,
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let res = read_parts(&data)
+            .await
+            .and_then(|(content, styles)| extract_text(&content, &styles));
+        let text = match res {
+            Ok(text) => text,
+            Err(e) if filepath_hint.extension().map_or(false, |e| e == "odt") => {
+                debug!(
+                    "{}: {:#}, falling back to pandoc",
+                    filepath_hint.display(),
+                    e
+                );
+                let pandoc = BUILTIN_SPAWNING_ADAPTERS
+                    .iter()
+                    .find(|a| a.name == "pandoc")
+                    .expect("pandoc is a builtin adapter")
+                    .to_adapter();
+                let ai = AdaptInfo {
+                    inp: Box::pin(Cursor::new(data)),
+                    filepath_hint,
+                    line_prefix,
+                    archive_recursion_depth,
+                    postprocess,
+                    config,
+                    is_real_file: false,
+                };
+                return pandoc.adapt(ai, detection_reason).await;
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use async_zip::{write::ZipFileWriter, Compression, ZipEntryBuilder};
+    use pretty_assertions::assert_eq;
+
+    async fn create_package(parts: &[(&str, &str)]) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        for (name, content) in parts {
+            let options = ZipEntryBuilder::new(name.to_string(), Compression::Deflate);
+            zip.write_entry_whole(options, content.as_bytes()).await?;
+        }
+        zip.close().await?;
+        Ok(cursor.into_inner())
+    }
+
+    async fn adapt(name: &str, data: Vec<u8>) -> Result<String> {
+        let (a, d) = simple_adapt_info(&PathBuf::from(name), Box::pin(Cursor::new(data)));
+        let o = adapted_to_vec(loop_adapt(&OpenDocumentAdapter::new(), d, a).await?).await?;
+        Ok(String::from_utf8(o)?)
+    }
+
+    #[tokio::test]
+    async fn odt() -> Result<()> {
+        let content = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document-content xmlns:office="o" xmlns:text="t" xmlns:table="ta"><office:body><office:text>
+<text:tracked-changes><text:changed-region><text:deletion><text:p>deleted</text:p></text:deletion></text:changed-region></text:tracked-changes>
+<text:h text:outline-level="1">The Waste Land</text:h>
+<text:p>April<text:tab/>is the<text:s text:c="2"/>cruellest month<text:note><text:note-citation>1</text:note-citation><text:note-body><text:p>a footnote</text:p></text:note-body></text:note></text:p>
+<text:p/>
+<table:table table:name="Table1"><table:table-row><table:table-cell><text:p>Part</text:p></table:table-cell><table:table-cell><text:p>The Burial</text:p><text:p>of the Dead</text:p></table:table-cell></table:table-row></table:table>
+</office:text></office:body></office:document-content>"#;
+        let styles = r#"<office:document-styles xmlns:office="o" xmlns:style="s" xmlns:text="t"><office:styles><text:p>not text</text:p></office:styles><office:master-styles><style:master-page><style:header><text:p>running header</text:p></style:header></style:master-page></office:master-styles></office:document-styles>"#;
+        let odt = create_package(&[
+            ("mimetype", "application/vnd.oasis.opendocument.text"),
+            ("content.xml", content),
+            ("styles.xml", styles),
+        ])
+        .await?;
+        assert_eq!(
+            adapt("test.odt", odt).await?,
+            "PREFIX:The Waste Land
+PREFIX:a footnote
+PREFIX:April\tis the  cruellest month
+PREFIX:Part\tThe Burial of the Dead
+PREFIX:running header
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ods() -> Result<()> {
+        let content = r#"<office:document-content xmlns:office="o" xmlns:text="t" xmlns:table="ta"><office:body><office:spreadsheet>
+<table:table table:name="Q1 Sales">
+<table:table-row><table:table-cell office:value-type="string"><text:p>Region</text:p></table:table-cell><table:table-cell><text:p>Total</text:p></table:table-cell></table:table-row>
+<table:table-row table:number-rows-repeated="2"><table:table-cell table:number-columns-repeated="3"/></table:table-row>
+<table:table-row><table:table-cell/><table:table-cell><text:p>North</text:p></table:table-cell><table:table-cell office:value-type="float" office:value="1250.5"><text:p>1,250.50</text:p></table:table-cell><table:table-cell office:value-type="float" office:value="3"/></table:table-row>
+<table:table-row table:number-rows-repeated="1048570"><table:table-cell table:number-columns-repeated="1024"/></table:table-row>
+</table:table>
+<table:table table:name="Notes"><table:table-row><table:table-cell><text:p>checked</text:p></table:table-cell></table:table-row></table:table>
+</office:spreadsheet></office:body></office:document-content>"#;
+        let ods = create_package(&[("content.xml", content)]).await?;
+        assert_eq!(
+            adapt("test.ods", ods).await?,
+            "PREFIX:'Q1 Sales'!A1: Region\tTotal
+PREFIX:'Q1 Sales'!B4: North\t1,250.50\t3
+PREFIX:Notes!A1: checked
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flat_odp() -> Result<()> {
+        let fodp = r#"<?xml version="1.0" encoding="UTF-8"?>
+<office:document xmlns:office="o" xmlns:draw="d" xmlns:text="t" xmlns:presentation="p"><office:body><office:presentation>
+<draw:page draw:name="page1"><draw:frame><draw:text-box><text:p>first slide</text:p></draw:text-box></draw:frame></draw:page>
+<draw:page draw:name="page2"><draw:frame><draw:text-box><text:p>second<text:line-break/>slide</text:p></draw:text-box></draw:frame><presentation:notes><draw:frame><draw:text-box><text:p>speaker notes</text:p></draw:text-box></draw:frame></presentation:notes></draw:page>
+</office:presentation></office:body></office:document>"#;
+        assert_eq!(
+            adapt("test.fodp", fodp.as_bytes().to_vec()).await?,
+            "PREFIX:slide 1: first slide
+PREFIX:slide 2: second
+PREFIX:slide 2: slide
+PREFIX:slide 2: speaker notes
+"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn not_a_document() -> Result<()> {
+        let zip = create_package(&[("hello.txt", "hello")]).await?;
+        assert!(adapt("test.ods", zip).await.is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,236 @@
+use super::{writing::WritingFileAdapter, *};
+use crate::config::RgaConfig;
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use log::*;
+use parquet::basic::Type as PhysicalType;
+use parquet::file::reader::{FileReader, SerializedFileReader};
+use parquet::record::Field;
+use parquet::schema::types::Type;
+use std::{fs::File, io::Write, sync::Arc};
+use tokio::io::AsyncWrite;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["parquet"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "parquet".to_owned(),
+        version: 1,
+        description:
+            "Uses the parquet crate to convert Apache Parquet files into a simple plain text format, one line per row prefixed with the row number. With --rga-parquet-strings-only only the text columns are read"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/vnd.apache.parquet".to_owned()
+        )]),
+        // the mime type isn't known to tree_magic
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // the footer with the schema is at the end of the file
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct ParquetAdapter;
+
+impl ParquetAdapter {
+    pub fn new() -> ParquetAdapter {
+        ParquetAdapter
+    }
+}
+impl GetMetadata for ParquetAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the options for the cache key, since they change the output. None for the defaults
+pub fn options_key(config: &RgaConfig) -> Option<String> {
+    config
+        .parquet_strings_only
+        .then(|| "parquet:strings-only".to_string())
+}
+
+fn format_field(f: &Field) -> String {
+    match f {
+        // binary columns are often text without the UTF8 annotation
+        Field::Bytes(b) => match std::str::from_utf8(b.data()) {
+            Ok(s) => format!("{s:?}"),
+            Err(_) => format!(
+                "[blob {}B]",
+                size_format::SizeFormatterSI::new(b.data().len() as u64)
+            ),
+        },
+        f => f.to_string(),
+    }
+}
+
+/// the schema with only the top level columns that hold strings, or None if there are none
+fn strings_projection(schema: &Type) -> Result<Option<Type>> {
+    let fields: Vec<_> = schema
+        .get_fields()
+        .iter()
+        .filter(|f| f.is_primitive() && f.get_physical_type() == PhysicalType::BYTE_ARRAY)
+        .map(Arc::clone)
+        .collect();
+    if fields.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        Type::group_type_builder(schema.name())
+            .with_fields(fields)
+            .build()?,
+    ))
+}
+
+fn synchronous_dump_parquet(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
+    let AdaptInfo {
+        filepath_hint,
+        line_prefix,
+        config,
+        ..
+    } = ai;
+    let reader = SerializedFileReader::new(
+        File::open(&filepath_hint)
+            .with_context(|| format!("opening {}", filepath_hint.display()))?,
+    )
+    .with_context(|| format!("reading parquet footer of {}", filepath_hint.display()))?;
+    let file_meta = reader.metadata().file_metadata();
+    debug!(
+        "parquet file has {} rows in {} row groups",
+        file_meta.num_rows(),
+        reader.num_row_groups()
+    );
+    let projection = if config.parquet_strings_only {
+        match strings_projection(file_meta.schema())? {
+            Some(p) => Some(p),
+            None => {
+                debug!("no string columns, skipping");
+                return Ok(());
+            }
+        }
+    } else {
+        None
+    };
+    for (i, row) in reader.get_row_iter(projection)?.enumerate() {
+        let row_str = row?
+            .get_column_iter()
+            .map(|(name, field)| format!("{}={}", name, format_field(field)))
+            .collect::<Vec<_>>()
+            .join(" ");
+        writeln!(s, "{line_prefix}{}: {row_str}", i + 1)?;
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for ParquetAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let oup_sync = SyncIoBridge::new(oup);
+        tokio::task::spawn_blocking(|| synchronous_dump_parquet(ai, oup_sync))
+            .await?
+            .context("in synchronous parquet task")?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::*;
+    use parquet::data_type::{ByteArray, ByteArrayType, DoubleType, Int64Type};
+    use parquet::file::{properties::WriterProperties, writer::SerializedFileWriter};
+    use parquet::schema::parser::parse_message_type;
+    use pretty_assertions::assert_eq;
+    use std::path::Path;
+
+    fn write_example(path: &Path) -> Result<()> {
+        let schema = Arc::new(parse_message_type(
+            "message schema {
+                REQUIRED INT64 id;
+                REQUIRED BYTE_ARRAY name (UTF8);
+                OPTIONAL DOUBLE score;
+                REQUIRED BYTE_ARRAY raw;
+            }",
+        )?);
+        let props = Arc::new(WriterProperties::builder().build());
+        let mut writer = SerializedFileWriter::new(File::create(path)?, schema, props)?;
+        let mut row_group = writer.next_row_group()?;
+        let mut col = row_group.next_column()?.unwrap();
+        col.typed::<Int64Type>().write_batch(&[1, 2], None, None)?;
+        col.close()?;
+        let mut col = row_group.next_column()?.unwrap();
+        col.typed::<ByteArrayType>().write_batch(
+            &[ByteArray::from("alice"), ByteArray::from("bob")],
+            None,
+            None,
+        )?;
+        col.close()?;
+        let mut col = row_group.next_column()?.unwrap();
+        col.typed::<DoubleType>()
+            .write_batch(&[0.5], Some(&[1, 0]), None)?;
+        col.close()?;
+        let mut col = row_group.next_column()?.unwrap();
+        col.typed::<ByteArrayType>().write_batch(
+            &[ByteArray::from("x-1234"), ByteArray::from(vec![0xff, 0xfe])],
+            None,
+            None,
+        )?;
+        col.close()?;
+        row_group.close()?;
+        writer.close()?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rows() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("people.parquet");
+        write_example(&fname)?;
+        let (a, d) = simple_fs_adapt_info(&fname).await?;
+        let res = ParquetAdapter::default().adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:1: id=1 name=\"alice\" score=0.5 raw=\"x-1234\"\nPREFIX:2: id=2 name=\"bob\" score=null raw=[blob 2B]\n",
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn strings_only() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("people.parquet");
+        write_example(&fname)?;
+        let (mut a, d) = simple_fs_adapt_info(&fname).await?;
+        a.config.parquet_strings_only = true;
+        let res = ParquetAdapter::default().adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:1: name=\"alice\" raw=\"x-1234\"\nPREFIX:2: name=\"bob\" raw=[blob 2B]\n",
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn options() {
+        let mut config = RgaConfig::default();
+        assert_eq!(options_key(&config), None);
+        config.parquet_strings_only = true;
+        assert_eq!(
+            options_key(&config).as_deref(),
+            Some("parquet:strings-only")
+        );
+    }
+}
@@ -0,0 +1,294 @@
+#[cfg(feature = "pdf-poppler")]
+use super::custom::{map_exe_error, pipe_output};
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::config::RgaConfig;
+#[cfg(feature = "pdf-poppler")]
+use crate::{config::PolicyConfig, policy};
+use anyhow::{bail, format_err, Result};
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+#[cfg(feature = "pdf-poppler")]
+use std::process::Stdio;
+#[cfg(feature = "pdf-poppler")]
+use tokio::process::Command;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pdf".to_owned(),
+        version: 2,
+        description:
+            "Extracts plain text from PDF files with pdftotext (from poppler-utils) or mutool (from mupdf), or without external programs if neither is installed. The backends are tried in the order of --rga-pdf-backends until one succeeds, since each of them fails on different broken PDFs"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: vec![FastFileMatcher::FileExtension("pdf".to_owned())],
+        slow_matchers: Some(vec![FileMatcher::MimeType("application/pdf".to_owned())]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Path
+    };
+}
+
+pub struct Backend {
+    /// the name used in --rga-pdf-backends
+    pub name: &'static str,
+    extractor: Extractor,
+}
+
+enum Extractor {
+    /// a program that writes the text of the given file to stdout, pages separated by form feeds
+    #[cfg(feature = "pdf-poppler")]
+    Program {
+        binary: &'static str,
+        help: &'static str,
+        args: fn(&Path) -> Vec<String>,
+    },
+    /// pdf-extract, in process. Works without any programs installed, but the text is worse
+    #[cfg(feature = "pdf-native")]
+    Native,
+}
+
+pub static BACKENDS: &[Backend] = &[
+    #[cfg(feature = "pdf-poppler")]
+    Backend {
+        name: "poppler",
+        extractor: Extractor::Program {
+            binary: "pdftotext",
+            help: "Install poppler-utils.",
+            args: |path| vec![path.to_string_lossy().into_owned(), "-".to_owned()],
+        },
+    },
+    #[cfg(feature = "pdf-poppler")]
+    Backend {
+        name: "mupdf",
+        extractor: Extractor::Program {
+            binary: "mutool",
+            help: "Install mupdf-tools.",
+            args: |path| {
+                ["draw", "-q", "-F", "txt", "-o", "-"]
+                    .iter()
+                    .map(ToString::to_string)
+                    .chain(std::iter::once(path.to_string_lossy().into_owned()))
+                    .collect()
+            },
+        },
+    },
+    #[cfg(feature = "pdf-native")]
+    Backend {
+        name: "native",
+        extractor: Extractor::Native,
+    },
+];
+
+/// the backends to try, in order
+fn backends(config: &RgaConfig) -> Result<Vec<&'static Backend>> {
+    if config.pdf_backends.is_empty() {
+        return Ok(BACKENDS.iter().collect());
+    }
+    config
+        .pdf_backends
+        .iter()
+        .map(|name| {
+            BACKENDS
+                .iter()
+                .find(|b| b.name == name.as_str())
+                .ok_or_else(|| format_err!("unknown pdf backend {}", name))
+        })
+        .collect()
+}
+
+#[cfg(feature = "pdf-poppler")]
+fn command(
+    policy: &PolicyConfig,
+    binary: &str,
+    args: fn(&Path) -> Vec<String>,
+    path: &Path,
+) -> Result<Command> {
+    let mut cmd = policy::command(policy, binary, path)?;
+    cmd.args(args(path));
+    Ok(cmd)
+}
+
+/// run the program to completion, so a failure doesn't leave half of the output behind
+#[cfg(feature = "pdf-poppler")]
+async fn run_program(mut cmd: Command, binary: &str, help: &str) -> Result<Vec<u8>> {
+    let out = cmd
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, binary, help))?;
+    if !out.status.success() {
+        bail!(
+            "{}, {}",
+            out.status,
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(out.stdout)
+}
+
+#[cfg(feature = "pdf-native")]
+async fn extract_native(path: &Path) -> Result<Vec<u8>> {
+    let data = tokio::fs::read(path).await?;
+    // pdf-extract panics on some broken files, which ends up as an error of the task
+    let pages =
+        tokio::task::spawn_blocking(move || pdf_extract::extract_text_from_mem_by_pages(&data))
+            .await
+            .map_err(|e| format_err!("pdf-extract crashed: {}", e))??;
+    Ok(pages.join("\x0c").into_bytes())
+}
+
+#[derive(Default, Clone)]
+pub struct PdfAdapter;
+
+impl PdfAdapter {
+    pub fn new() -> PdfAdapter {
+        PdfAdapter
+    }
+}
+impl GetMetadata for PdfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+#[async_trait]
+impl FileAdapter for PdfAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let backends = backends(&config)?;
+        let mut output: Option<ReadBox> = None;
+        for (i, backend) in backends.iter().enumerate() {
+            let last = i == backends.len() - 1;
+            let res = match &backend.extractor {
+                #[cfg(feature = "pdf-poppler")]
+                Extractor::Program { binary, help, args } if last => {
+                    // nothing to fall back to, so the output of the last backend is streamed
+                    output = Some(pipe_output(
+                        &line_prefix,
+                        command(&config.policy, binary, *args, &filepath_hint)?,
+                        Box::pin(tokio::io::empty()),
+                        binary,
+                        help,
+                    )?);
+                    break;
+                }
+                // a backend the policy forbids falls back to the next one, like a missing program
+                #[cfg(feature = "pdf-poppler")]
+                Extractor::Program { binary, help, args } => {
+                    match command(&config.policy, binary, *args, &filepath_hint) {
+                        Ok(cmd) => run_program(cmd, binary, help).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                #[cfg(feature = "pdf-native")]
+                Extractor::Native => extract_native(&filepath_hint).await,
+            };
+            match res {
+                Ok(text) => {
+                    output = Some(Box::pin(Cursor::new(text)));
+                    break;
+                }
+                Err(e) if last => {
+                    return Err(e.context(format!("pdf backend {} failed", backend.name)))
+                }
+                Err(e) => debug!(
+                    "{}: {} failed ({:#}), trying the next pdf backend",
+                    filepath_hint.display(),
+                    backend.name,
+                    e
+                ),
+            }
+        }
+        let inp = match output {
+            Some(inp) => inp,
+            None => bail!("no pdf backends configured"),
+        };
+        Ok(one_file(AdaptInfo {
+            // the page breaks are turned into #page=N prefixes by postprocpagebreaks
+            filepath_hint: PathBuf::from(format!(
+                "{}.txt.asciipagebreaks",
+                filepath_hint.to_string_lossy()
+            )),
+            inp,
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preproc::loop_adapt;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+    use tokio::fs::File;
+
+    #[cfg(feature = "pdf-poppler")]
+    #[tokio::test]
+    async fn poppler() -> Result<()> {
+        let filepath = test_data_dir().join("short.pdf");
+
+        let (mut a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+        a.config.pdf_backends = vec!["poppler".to_string()];
+        let r = loop_adapt(&PdfAdapter::new(), d, a).await?;
+        let o = adapted_to_vec(r).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:#page=1: hello world
+PREFIX:#page=1: this is just a test.
+PREFIX:#page=1: 
+PREFIX:#page=1: 1
+PREFIX:#page=1: 
+PREFIX:#page=1: 
+"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "pdf-native")]
+    #[tokio::test]
+    async fn native() -> Result<()> {
+        let filepath = test_data_dir().join("short.pdf");
+
+        let (mut a, d) = simple_adapt_info(&filepath, Box::pin(File::open(&filepath).await?));
+        a.config.pdf_backends = vec!["native".to_string()];
+        let r = loop_adapt(&PdfAdapter::new(), d, a).await?;
+        let o = String::from_utf8(adapted_to_vec(r).await?)?;
+        assert!(o.contains("PREFIX:#page=1: hello world"), "{o}");
+        Ok(())
+    }
+
+    #[cfg(feature = "pdf-poppler")]
+    #[test]
+    fn backend_order() -> Result<()> {
+        let mut config = RgaConfig::default();
+        let names = |config: &RgaConfig| -> Result<Vec<&str>> {
+            Ok(backends(config)?.iter().map(|b| b.name).collect())
+        };
+        assert_eq!(names(&config)?[..2], ["poppler", "mupdf"]);
+        config.pdf_backends = vec!["mupdf".to_string(), "poppler".to_string()];
+        assert_eq!(names(&config)?, vec!["mupdf", "poppler"]);
+        config.pdf_backends = vec!["pdfium".to_string()];
+        assert!(backends(&config).is_err());
+        Ok(())
+    }
+}
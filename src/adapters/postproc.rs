@@ -10,6 +10,7 @@ use encoding_rs::Encoding;
 use encoding_rs_io::DecodeReaderBytesBuilder;
 use tokio_util::io::SyncIoBridge;
 
+use std::borrow::Cow;
 use std::io::Cursor;
 use std::path::PathBuf;
 use std::pin::Pin;
@@ -20,8 +21,14 @@ use tokio_util::io::StreamReader;
 use crate::adapted_iter::one_file;
 use crate::adapted_iter::AdaptedFilesIterBox;
 use crate::matching::FastFileMatcher;
+use crate::vpath::page_prefix;
 
-use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata};
+use super::{AdaptInfo, AdapterMeta, FileAdapter, GetMetadata, InputKind};
+
+/// longest line in the output of adapters, longer lines are split so rg doesn't have to buffer them
+pub const MAX_LINE_LEN: usize = 1 << 16;
+/// starts the rest of a line that was split
+const CONTINUATION: &str = "[rga: line continued] ";
 
 fn add_newline(ar: impl AsyncRead + Send) -> impl AsyncRead + Send {
     ar.chain(Cursor::new(&[b'\n']))
@@ -33,13 +40,15 @@ impl GetMetadata for PostprocPrefix {
         lazy_static::lazy_static! {
             static ref METADATA: AdapterMeta = AdapterMeta {
                 name: "postprocprefix".to_owned(),
-                version: 1,
-                description: "Adds the line prefix to each line (e.g. the filename within a zip)".to_owned(),
+                version: 2,
+                description: "Adds the line prefix to each line (e.g. the filename within a zip), after making the text valid UTF-8 without NUL bytes and splitting overlong lines".to_owned(),
                 recurses: false,
                 fast_matchers: vec![],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
-                disabled_by_default: false
+                disabled_by_default: false,
+                sensitive: false,
+                input: InputKind::Stream
             };
         }
         &METADATA
@@ -54,7 +63,8 @@ impl FileAdapter for PostprocPrefix {
     ) -> Result<AdaptedFilesIterBox> {
         let read = add_newline(postproc_prefix(
             &a.line_prefix,
-            postproc_encoding(&a.line_prefix, a.inp).await?,
+            a.config.prefix_column,
+            postproc_lines(postproc_encoding(&a.line_prefix, a.inp).await?),
         ));
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
@@ -128,8 +138,143 @@ async fn postproc_encoding(
     }
 }
 
+/// Splits lines longer than `max_line_len` bytes and replaces invalid UTF-8 and NUL bytes, across
+/// the chunks of a stream
+struct Lines {
+    max_line_len: usize,
+    /// the start of a UTF-8 sequence at the end of the previous chunk
+    partial: Vec<u8>,
+    /// bytes in the current line so far
+    line_len: usize,
+}
+
+impl Lines {
+    fn new(max_line_len: usize) -> Lines {
+        Lines {
+            max_line_len,
+            partial: Vec::new(),
+            line_len: 0,
+        }
+    }
+
+    fn push(&mut self, chunk: &[u8]) -> Vec<u8> {
+        let mut data = std::mem::take(&mut self.partial);
+        data.extend_from_slice(chunk);
+        let mut out = Vec::with_capacity(data.len());
+        let mut rest = &data[..];
+        loop {
+            match std::str::from_utf8(rest) {
+                Ok(text) => {
+                    self.text(text, &mut out);
+                    return out;
+                }
+                Err(e) => {
+                    let (valid, invalid) = rest.split_at(e.valid_up_to());
+                    self.text(std::str::from_utf8(valid).expect("checked"), &mut out);
+                    match e.error_len() {
+                        Some(len) => {
+                            self.text("\u{FFFD}", &mut out);
+                            rest = &invalid[len..];
+                        }
+                        // may be completed by the next chunk
+                        None => {
+                            self.partial = invalid.to_vec();
+                            return out;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// the end of the stream
+    fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        if !self.partial.is_empty() {
+            self.partial.clear();
+            self.text("\u{FFFD}", &mut out);
+        }
+        out
+    }
+
+    fn text(&mut self, text: &str, out: &mut Vec<u8>) {
+        // rg treats everything after a NUL byte as binary
+        let text = if text.contains('\0') {
+            Cow::Owned(text.replace('\0', "\u{FFFD}"))
+        } else {
+            Cow::Borrowed(text)
+        };
+        for (i, mut line) in text.split('\n').enumerate() {
+            if i > 0 {
+                out.push(b'\n');
+                self.line_len = 0;
+            }
+            while self.line_len + line.len() > self.max_line_len {
+                let mut at = self.max_line_len.saturating_sub(self.line_len);
+                while !line.is_char_boundary(at) {
+                    at -= 1;
+                }
+                out.extend_from_slice(line[..at].as_bytes());
+                out.push(b'\n');
+                out.extend_from_slice(CONTINUATION.as_bytes());
+                self.line_len = 0;
+                line = &line[at..];
+            }
+            out.extend_from_slice(line.as_bytes());
+            self.line_len += line.len();
+        }
+    }
+}
+
+/// Makes the output of an adapter safe for rg's line oriented search: invalid UTF-8 and NUL bytes
+/// (after which rg treats the rest as binary) are replaced with U+FFFD, and lines longer than
+/// [MAX_LINE_LEN] are split, with the rest of the line starting with a marker
+pub fn postproc_lines(inp: impl AsyncRead + Send) -> impl AsyncRead + Send {
+    let inp_stream = ReaderStream::new(inp);
+    let oup_stream = stream! {
+        let mut lines = Lines::new(MAX_LINE_LEN);
+        for await chunk in inp_stream {
+            yield std::io::Result::Ok(Bytes::from(lines.push(&chunk?)));
+        }
+        yield Ok(Bytes::from(lines.finish()));
+    };
+    Box::pin(StreamReader::new(oup_stream))
+}
+
+/// The end of a line whose prefix is output as a column after the text (`--rga-prefix-column`):
+/// a tab followed by the prefix without its trailing ": ". Tabs in the prefix are replaced so the last
+/// tab of a line always starts the column.
+fn prefix_column(line_prefix: &str) -> String {
+    let column = line_prefix.trim_end_matches(|c| c == ':' || c == ' ');
+    format!("\t{}", column.replace('\t', " "))
+}
+
+/// A single line of text with the given prefix, in front of the text or as a column after it
+pub fn prefixed_line(line_prefix: &str, text: &str, column: bool) -> String {
+    if !column {
+        format!("{line_prefix}{text}\n")
+    } else if line_prefix.is_empty() {
+        format!("{text}\n")
+    } else {
+        format!("{text}{}\n", prefix_column(line_prefix))
+    }
+}
+
 /// Adds the given prefix to each line in an `AsyncRead`.
-pub fn postproc_prefix(line_prefix: &str, inp: impl AsyncRead + Send) -> impl AsyncRead + Send {
+///
+/// If `column` is true, the prefix is added after each line as a tab separated column instead
+/// (see [prefix_column]), so the lines still start with the text.
+pub fn postproc_prefix(
+    line_prefix: &str,
+    column: bool,
+    inp: impl AsyncRead + Send + 'static,
+) -> Pin<Box<dyn AsyncRead + Send>> {
+    if column {
+        if line_prefix.is_empty() {
+            return Box::pin(inp);
+        }
+        return Box::pin(postproc_suffix(prefix_column(line_prefix), inp));
+    }
     let line_prefix_n = format!("\n{line_prefix}"); // clone since we need it later
     let line_prefix_o = Bytes::copy_from_slice(line_prefix.as_bytes());
     let regex = regex::bytes::Regex::new("\n").unwrap();
@@ -152,6 +297,25 @@ pub fn postproc_prefix(line_prefix: &str, inp: impl AsyncRead + Send) -> impl As
     Box::pin(StreamReader::new(oup_stream))
 }
 
+/// Adds the given suffix to the end of each line in an `AsyncRead`, including the last one.
+fn postproc_suffix(suffix: String, inp: impl AsyncRead + Send) -> impl AsyncRead + Send {
+    let suffix_n = format!("{suffix}\n");
+    let regex = regex::bytes::Regex::new("\n").unwrap();
+    let inp_stream = ReaderStream::new(inp);
+    let oup_stream = stream! {
+        for await chunk in inp_stream {
+            let chunk = chunk?;
+            if chunk.contains(&b'\n') {
+                yield std::io::Result::Ok(Bytes::copy_from_slice(&regex.replace_all(&chunk, suffix_n.as_bytes())));
+            } else {
+                yield Ok(chunk);
+            }
+        }
+        yield Ok(Bytes::from(suffix));
+    };
+    Box::pin(StreamReader::new(oup_stream))
+}
+
 #[derive(Default)]
 pub struct PostprocPageBreaks {}
 
@@ -161,12 +325,14 @@ impl GetMetadata for PostprocPageBreaks {
             static ref METADATA: AdapterMeta = AdapterMeta {
                 name: "postprocpagebreaks".to_owned(),
                 version: 1,
-                description: "Adds the page number to each line for an input file that specifies page breaks as ascii page break character.\nMainly to be used internally by the poppler adapter.".to_owned(),
+                description: "Adds the page number to each line for an input file that specifies page breaks as ascii page break character.\nMainly to be used internally by the pdf adapter.".to_owned(),
                 recurses: false,
                 fast_matchers: vec![FastFileMatcher::FileExtension("asciipagebreaks".to_string())],
                 slow_matchers: None,
                 keep_fast_matchers_if_accurate: false,
-                disabled_by_default: false
+                disabled_by_default: false,
+                sensitive: false,
+                input: InputKind::Stream
             };
         }
         &METADATA
@@ -179,10 +345,16 @@ impl FileAdapter for PostprocPageBreaks {
         a: super::AdaptInfo,
         _detection_reason: &crate::matching::FileMatcher,
     ) -> Result<AdaptedFilesIterBox> {
-        let read = postproc_pagebreaks(postproc_encoding(&a.line_prefix, a.inp).await?);
+        let read = postproc_pagebreaks(
+            &a.line_prefix,
+            a.config.prefix_column,
+            postproc_encoding(&a.line_prefix, a.inp).await?,
+        );
         // keep adapt info (filename etc) except replace inp
         let ai = AdaptInfo {
             inp: Box::pin(read),
+            // the page prefixes already contain the line prefix
+            line_prefix: String::new(),
             postprocess: true,
             archive_recursion_depth: a.archive_recursion_depth + 1,
             filepath_hint: a
@@ -196,18 +368,28 @@ impl FileAdapter for PostprocPageBreaks {
         Ok(one_file(ai))
     }
 }
-/// Adds the prefix "Page N: " to each line,
+/// Adds the prefix "{line_prefix}#page=N: " to each line (see `vpath::page_prefix`),
 /// where N starts at one and is incremented for each ASCII Form Feed character in the input stream.
 /// ASCII form feeds are the page delimiters output by `pdftotext`.
-pub fn postproc_pagebreaks(input: impl AsyncRead + Send) -> impl AsyncRead + Send {
+///
+/// If `column` is true, the prefixes are added after the lines as a column instead (see [prefix_column]).
+pub fn postproc_pagebreaks(
+    line_prefix: &str,
+    column: bool,
+    input: impl AsyncRead + Send + 'static,
+) -> Pin<Box<dyn AsyncRead + Send>> {
+    if column {
+        return Box::pin(postproc_pagebreaks_column(line_prefix, input));
+    }
     let regex_linefeed = regex::bytes::Regex::new(r"\x0c").unwrap();
     let regex_newline = regex::bytes::Regex::new("\n").unwrap();
+    let line_prefix = line_prefix.to_string();
     let mut page_count: i32 = 1;
-    let mut page_prefix: String = format!("\nPage {page_count}: ");
+    let mut prefix: String = format!("\n{}", page_prefix(&line_prefix, page_count));
 
     let input_stream = ReaderStream::new(input);
     let output_stream = stream! {
-        yield std::io::Result::Ok(Bytes::copy_from_slice(format!("Page {page_count}: ").as_bytes()));
+        yield std::io::Result::Ok(Bytes::copy_from_slice(page_prefix(&line_prefix, page_count).as_bytes()));
         // store Page X: line prefixes in pending and only write it to the output when there is more text to be written
         // this is needed since pdftotext outputs a \x0c at the end of the last page
         let mut pending: Option<Bytes> = None;
@@ -218,17 +400,17 @@ pub fn postproc_pagebreaks(input: impl AsyncRead + Send) -> impl AsyncRead + Sen
             for (chunk_idx, page_chunk) in page_chunks.enumerate() {
                 if chunk_idx != 0 {
                     page_count += 1;
-                    page_prefix = format!("\nPage {page_count}: ");
+                    prefix = format!("\n{}", page_prefix(&line_prefix, page_count));
                     if let Some(p) = pending.take() {
                         yield Ok(p);
                     }
-                    pending = Some(Bytes::copy_from_slice(page_prefix.as_bytes()));
+                    pending = Some(Bytes::copy_from_slice(prefix.as_bytes()));
                 }
                 if !page_chunk.is_empty() {
                     if let Some(p) = pending.take() {
                         yield Ok(p);
                     }
-                    yield Ok(Bytes::copy_from_slice(&regex_newline.replace_all(page_chunk, page_prefix.as_bytes())));
+                    yield Ok(Bytes::copy_from_slice(&regex_newline.replace_all(page_chunk, prefix.as_bytes())));
                 }
 
             }
@@ -239,8 +421,54 @@ pub fn postproc_pagebreaks(input: impl AsyncRead + Send) -> impl AsyncRead + Sen
     Box::pin(StreamReader::new(output_stream))
 }
 
+/// [postproc_pagebreaks] with the page prefixes as a column after the lines. The lines are the same,
+/// so each line ends with the column of the page it started on.
+fn postproc_pagebreaks_column(
+    line_prefix: &str,
+    input: impl AsyncRead + Send,
+) -> impl AsyncRead + Send {
+    let regex_linefeed = regex::bytes::Regex::new(r"\x0c").unwrap();
+    let regex_newline = regex::bytes::Regex::new("\n").unwrap();
+    let line_prefix = line_prefix.to_string();
+    let mut page_count: i32 = 1;
+    let mut suffix = prefix_column(&page_prefix(&line_prefix, page_count));
+
+    let input_stream = ReaderStream::new(input);
+    let output_stream = stream! {
+        // the column of the line after the next page break, only ended and started when there is more text to be written
+        let mut pending: Option<String> = None;
+
+        for await read_chunk in input_stream {
+            let read_chunk = read_chunk?;
+            let page_chunks = regex_linefeed.split(&read_chunk);
+            for (chunk_idx, page_chunk) in page_chunks.enumerate() {
+                if chunk_idx != 0 {
+                    page_count += 1;
+                    if let Some(next) = pending.take() {
+                        yield std::io::Result::Ok(Bytes::from(format!("{suffix}\n")));
+                        suffix = next;
+                    }
+                    pending = Some(prefix_column(&page_prefix(&line_prefix, page_count)));
+                }
+                if !page_chunk.is_empty() {
+                    if let Some(next) = pending.take() {
+                        yield Ok(Bytes::from(format!("{suffix}\n")));
+                        suffix = next;
+                    }
+                    let suffix_n = format!("{suffix}\n");
+                    yield Ok(Bytes::copy_from_slice(&regex_newline.replace_all(page_chunk, suffix_n.as_bytes())));
+                }
+            }
+        }
+        yield Ok(Bytes::from(suffix));
+    };
+    Box::pin(StreamReader::new(output_stream))
+}
+
 #[cfg(test)]
 mod tests {
+    #[cfg(any(feature = "pdf-poppler", feature = "pdf-native"))]
+    use crate::adapters::pdf::PdfAdapter;
     use crate::preproc::loop_adapt;
     use crate::test_utils::*;
 
@@ -258,12 +486,14 @@ mod tests {
         let mock: Mock = Builder::new()
             .read(b"Hello\nWorld\x0cFoo Bar\n\x0cTest\x0c")
             .build();
-        let res = postproc_pagebreaks(mock).read_to_end(&mut output).await;
+        let res = postproc_pagebreaks("", false, mock)
+            .read_to_end(&mut output)
+            .await;
         println!("{}", String::from_utf8_lossy(&output));
         assert!(matches!(res, Ok(_)));
         assert_eq!(
             String::from_utf8_lossy(&output),
-            "Page 1: Hello\nPage 1: World\nPage 2: Foo Bar\nPage 2: \nPage 3: Test"
+            "#page=1: Hello\n#page=1: World\n#page=2: Foo Bar\n#page=2: \n#page=3: Test"
         );
     }
 
@@ -276,18 +506,21 @@ mod tests {
             .read(b"Foo Bar\n")
             .read(b"\x0cTest\x0c")
             .build();
-        let res = postproc_pagebreaks(mock).read_to_end(&mut output).await;
+        let res = postproc_pagebreaks("", false, mock)
+            .read_to_end(&mut output)
+            .await;
         println!("{}", String::from_utf8_lossy(&output));
         assert!(matches!(res, Ok(_)));
         assert_eq!(
             String::from_utf8_lossy(&output),
-            "Page 1: Hello\nPage 1: World\nPage 2: Foo Bar\nPage 2: \nPage 3: Test"
+            "#page=1: Hello\n#page=1: World\n#page=2: Foo Bar\n#page=2: \n#page=3: Test"
         );
     }
 
+    #[cfg(any(feature = "pdf-poppler", feature = "pdf-native"))]
     #[tokio::test]
     async fn test_pdf_twoblank() -> Result<()> {
-        let adapter = poppler_adapter();
+        let adapter = PdfAdapter::new();
         let fname = test_data_dir().join("twoblankpages.pdf");
         let rd = File::open(&fname).await?;
         let (a, d) = simple_adapt_info(&fname, Box::pin(rd));
@@ -297,11 +530,11 @@ mod tests {
 
         assert_eq!(
             String::from_utf8(buf)?,
-            "PREFIX:Page 1: 
-PREFIX:Page 2: 
-PREFIX:Page 3: HelloWorld
-PREFIX:Page 3: 
-PREFIX:Page 3: 
+            "PREFIX:#page=1: 
+PREFIX:#page=2: 
+PREFIX:#page=3: HelloWorld
+PREFIX:#page=3: 
+PREFIX:#page=3: 
 ",
         );
 
@@ -312,7 +545,7 @@ PREFIX:Page 3:
     async fn test_postproc_prefix() {
         let mut output: Vec<u8> = Vec::new();
         let mock: Mock = Builder::new().read(b"Hello\nWorld").build();
-        let res = postproc_prefix("prefix: ", mock)
+        let res = postproc_prefix("prefix: ", false, mock)
             .read_to_end(&mut output)
             .await;
         println!("{}", String::from_utf8_lossy(&output));
@@ -320,6 +553,51 @@ PREFIX:Page 3:
         assert_eq!(output, b"prefix: Hello\nprefix: World");
     }
 
+    #[tokio::test]
+    async fn test_prefix_column() -> Result<()> {
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new().read(b"Hello\nWo").read(b"rld").build();
+        postproc_prefix("a.zip!/b\tc.txt: ", true, mock)
+            .read_to_end(&mut output)
+            .await?;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Hello\ta.zip!/b c.txt\nWorld\ta.zip!/b c.txt"
+        );
+
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new().read(b"Hello\nWorld").build();
+        postproc_prefix("", true, mock)
+            .read_to_end(&mut output)
+            .await?;
+        assert_eq!(output, b"Hello\nWorld");
+
+        let mut output: Vec<u8> = Vec::new();
+        let mock: Mock = Builder::new()
+            .read(b"Hello\nWo")
+            .read(b"rld\x0c")
+            .read(b"Foo Bar\n")
+            .read(b"\x0cTest\x0c")
+            .build();
+        postproc_pagebreaks("doc.pdf: ", true, mock)
+            .read_to_end(&mut output)
+            .await?;
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            "Hello\tdoc.pdf#page=1\nWorld\tdoc.pdf#page=1\nFoo Bar\tdoc.pdf#page=2\n\tdoc.pdf#page=2\nTest\tdoc.pdf#page=3"
+        );
+
+        assert_eq!(
+            prefixed_line("a.zip: ", "[rga: marker]", true),
+            "[rga: marker]\ta.zip\n"
+        );
+        assert_eq!(
+            prefixed_line("a.zip: ", "[rga: marker]", false),
+            "a.zip: [rga: marker]\n"
+        );
+        Ok(())
+    }
+
     async fn test_from_strs(
         pagebreaks: bool,
         line_prefix: &str,
@@ -339,9 +617,11 @@ PREFIX:Page 3:
         let inp = Box::pin(Cursor::new(a));
         let inp = postproc_encoding("", inp).await?;
         if pagebreaks {
-            postproc_pagebreaks(inp).read_to_end(&mut oup).await?;
+            postproc_pagebreaks(line_prefix, false, inp)
+                .read_to_end(&mut oup)
+                .await?;
         } else {
-            let x = postproc_prefix(line_prefix, inp);
+            let x = postproc_prefix(line_prefix, false, inp);
             pin!(x);
             x.read_to_end(&mut oup).await?;
         }
@@ -371,17 +651,21 @@ PREFIX:Page 3:
     #[tokio::test]
     async fn post1() -> Result<()> {
         let inp = "What is this\nThis is a test\nFoo";
-        let oup = "Page 1: What is this\nPage 1: This is a test\nPage 1: Foo";
+        let oup = "#page=1: What is this\n#page=1: This is a test\n#page=1: Foo";
 
         test_from_strs(true, "", inp, oup).await?;
 
         println!("\n\n\n\n");
 
         let inp = "What is this\nThis is a test\nFoo\x0c\nHelloooo\nHow are you?\x0c\nGreat!";
-        let oup = "Page 1: What is this\nPage 1: This is a test\nPage 1: Foo\nPage 2: \nPage 2: Helloooo\nPage 2: How are you?\nPage 3: \nPage 3: Great!";
+        let oup = "#page=1: What is this\n#page=1: This is a test\n#page=1: Foo\n#page=2: \n#page=2: Helloooo\n#page=2: How are you?\n#page=3: \n#page=3: Great!";
 
         test_from_strs(true, "", inp, oup).await?;
 
+        let oup = "foo.pdf#page=1: What is this\nfoo.pdf#page=1: This is a test\nfoo.pdf#page=1: Foo\nfoo.pdf#page=2: \nfoo.pdf#page=2: Helloooo\nfoo.pdf#page=2: How are you?\nfoo.pdf#page=3: \nfoo.pdf#page=3: Great!";
+
+        test_from_strs(true, "foo.pdf: ", inp, oup).await?;
+
         let inp = "What is this\nThis is a test\nFoo\x0c\nHelloooo\nHow are you?\x0c\nGreat!";
         let oup = "foo.pdf:What is this\nfoo.pdf:This is a test\nfoo.pdf:Foo\x0c\nfoo.pdf:Helloooo\nfoo.pdf:How are you?\x0c\nfoo.pdf:Great!";
 
@@ -390,6 +674,27 @@ PREFIX:Page 3:
         Ok(())
     }
 
+    #[test]
+    fn lines() {
+        let mut lines = Lines::new(10);
+        let mut out = lines.push(b"short\n0123456789abcdefghij");
+        // \u{e9} split between chunks
+        out.extend(lines.push(b"\n\xc3"));
+        out.extend(lines.push(b"\xa9t\xe9 \0\n\xe2\x82"));
+        out.extend(lines.finish());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "short\n0123456789\n[rga: line continued] abcdefghij\n\u{e9}t\u{FFFD} \u{FFFD}\n\u{FFFD}"
+        );
+        // never splits a character
+        let mut lines = Lines::new(5);
+        let out = lines.push("ab\u{20ac}\u{20ac}".as_bytes());
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "ab\u{20ac}\n[rga: line continued] \u{20ac}"
+        );
+    }
+
     #[tokio::test]
     async fn test_binary_content() -> Result<()> {
         test_from_strs(
@@ -0,0 +1,389 @@
+use super::custom::map_exe_error;
+use super::mail::html_text;
+use super::rtf::rtf_to_text;
+use super::*;
+use crate::policy;
+use crate::vpath::{member_prefix, MEMBER_SEPARATOR};
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use regex::Regex;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+static EXTENSIONS: &[&str] = &["pst", "ost"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "pst".to_owned(),
+        version: 1,
+        description:
+            "Uses pffexport (from libpff) to export the folders and messages of Outlook PST and OST stores. The lines of a message are prefixed with its folder and subject, like Inbox/Re: budget, and its attachments are searched as members of it"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Path
+    };
+    /// the directories pffexport writes the items to, like Message00001 and Contact00001
+    static ref ITEM_DIR: Regex = Regex::new(r"^[A-Za-z]+\d{5}$").unwrap();
+}
+
+#[derive(Default, Clone)]
+pub struct PstAdapter;
+
+impl PstAdapter {
+    pub fn new() -> PstAdapter {
+        PstAdapter
+    }
+}
+impl GetMetadata for PstAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// folders and attached messages nested deeper than this are left out
+const MAX_DEPTH: usize = 20;
+/// files of an item that are not output, the useful headers are taken from OutlookHeaders.txt
+static SKIPPED_FILES: &[&str] = &[
+    "OutlookHeaders.txt",
+    "InternetHeaders.txt",
+    "ConversationIndex.txt",
+];
+
+/// a message (or another item, like a contact) exported by pffexport
+#[derive(Debug, PartialEq)]
+struct Item {
+    /// the folders and the subject, e.g. `Inbox/Re: budget`
+    name: String,
+    text: String,
+    attachments: Vec<PathBuf>,
+}
+
+/// the entries of a directory, sorted so the output is the same on every run
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn is_item(dir: &Path) -> bool {
+    dir.join("OutlookHeaders.txt").is_file() || ITEM_DIR.is_match(&file_name(dir))
+}
+
+/// the item directories below `dir`, with the folder they are in
+fn item_dirs(
+    dir: &Path,
+    folder: &str,
+    depth: usize,
+    items: &mut Vec<(PathBuf, String)>,
+) -> Result<()> {
+    if depth > MAX_DEPTH {
+        return Ok(());
+    }
+    for path in sorted_entries(dir)? {
+        if !path.is_dir() {
+            continue;
+        }
+        if is_item(&path) {
+            items.push((path, folder.to_owned()));
+            continue;
+        }
+        let name = file_name(&path);
+        // the root folder of the store is not part of the names
+        let folder = if depth == 0 && name.starts_with("Top of ") {
+            folder.to_owned()
+        } else if folder.is_empty() {
+            name
+        } else {
+            format!("{folder}/{name}")
+        };
+        item_dirs(&path, &folder, depth + 1, items)?;
+    }
+    Ok(())
+}
+
+/// the fields of OutlookHeaders.txt, lines like `Subject:<TABS>Re: budget`
+fn header_fields(text: &str) -> Vec<(&str, &str)> {
+    text.lines()
+        .filter_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            let value = value.trim();
+            if value.is_empty() {
+                None
+            } else {
+                Some((name.trim(), value))
+            }
+        })
+        .collect()
+}
+
+/// the body of an item: the plain text, else the HTML or the RTF version
+fn body_text(dir: &Path) -> String {
+    let read = |name: &str| std::fs::read(dir.join(name)).ok();
+    if let Some(text) = read("Message.txt") {
+        return String::from_utf8_lossy(&text).replace("\r\n", "\n");
+    }
+    if let Some(html) = read("Message.html") {
+        return html_text(&String::from_utf8_lossy(&html));
+    }
+    match read("Message.rtf").and_then(|rtf| rtf_to_text(&rtf).ok()) {
+        Some(text) => text
+            .lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty())
+            .map(|l| format!("{l}\n"))
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn read_item(dir: &Path, folder: &str, depth: usize) -> Result<Item> {
+    let mut text = String::new();
+    let headers = std::fs::read(dir.join("OutlookHeaders.txt")).unwrap_or_default();
+    let headers = String::from_utf8_lossy(&headers);
+    let fields = header_fields(&headers);
+    let field = |name: &str| fields.iter().find(|f| f.0 == name).map(|f| f.1);
+    let from = match (field("Sender name"), field("Sender email address")) {
+        (Some(name), Some(email)) if name != email => Some(format!("{name} <{email}>")),
+        (name, email) => name.or(email).map(String::from),
+    };
+    let headers = [
+        ("From", from),
+        ("Subject", field("Subject").map(String::from)),
+        ("Date", field("Client submit time").map(String::from)),
+    ];
+    for (name, value) in headers {
+        if let Some(value) = value {
+            text.push_str(&format!("{name}: {value}\n"));
+        }
+    }
+    let body = body_text(dir);
+    let body = body.trim();
+    if !body.is_empty() {
+        text.push_str(body);
+        text.push('\n');
+    }
+    let mut attachments = Vec::new();
+    for path in sorted_entries(dir)? {
+        let name = file_name(&path);
+        if path.is_dir() && name == "Attachments" {
+            for attachment in sorted_entries(&path)? {
+                if attachment.is_file() {
+                    attachments.push(attachment);
+                } else if attachment.is_dir() && depth < MAX_DEPTH {
+                    // an attached message
+                    let item = read_item(&attachment, "", depth + 1)?;
+                    text.push_str(&item.text);
+                    attachments.extend(item.attachments);
+                }
+            }
+        } else if path.is_file()
+            && name.ends_with(".txt")
+            && name != "Message.txt"
+            && !SKIPPED_FILES.contains(&name.as_str())
+        {
+            // Recipients.txt, Contact.txt, Appointment.txt etc
+            let data = std::fs::read(&path)?;
+            let data = String::from_utf8_lossy(&data);
+            let data = data.trim();
+            if !data.is_empty() {
+                text.push_str(data);
+                text.push('\n');
+            }
+        }
+    }
+    let subject = field("Subject")
+        .map(|s| {
+            s.replace(['\r', '\n', '\t'], " ")
+                .replace(MEMBER_SEPARATOR, "! ")
+        })
+        .unwrap_or_else(|| file_name(dir));
+    let name = if folder.is_empty() {
+        subject
+    } else {
+        format!("{folder}/{subject}")
+    };
+    Ok(Item {
+        name,
+        text,
+        attachments,
+    })
+}
+
+#[async_trait]
+impl FileAdapter for PstAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        // pffexport writes each message to a directory, the export of a large store can be large too
+        let dir = tempfile::tempdir().context("creating temporary directory")?;
+        let out = policy::command(&config.policy, "pffexport", &filepath_hint)?
+            .args(["-q", "-f", "all", "-t"])
+            .arg(dir.path().join("store"))
+            .arg(&filepath_hint)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "pffexport", "Install libpff (pff-tools)."))?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            bail!(
+                "pffexport failed ({}): {}",
+                out.status,
+                stderr.trim().lines().last().unwrap_or_default()
+            );
+        }
+        let mut items = Vec::new();
+        item_dirs(&dir.path().join("store.export"), "", 0, &mut items)?;
+        let s = stream! {
+            // the exported files are read while the members are searched
+            let _dir = dir;
+            for (item_dir, folder) in items {
+                let item = read_item(&item_dir, &folder, 0)?;
+                let prefix = member_prefix(&line_prefix, &item.name);
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(format!("{}.txt", item.name)),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(Cursor::new(item.text.into_bytes())),
+                    line_prefix: prefix.clone(),
+                    config: config.clone(),
+                    postprocess,
+                });
+                for path in item.attachments {
+                    let name = file_name(&path);
+                    yield Ok(AdaptInfo {
+                        filepath_hint: PathBuf::from(&name),
+                        is_real_file: false,
+                        archive_recursion_depth: archive_recursion_depth + 1,
+                        inp: Box::pin(tokio::fs::File::open(&path).await?),
+                        line_prefix: member_prefix(&prefix, &name),
+                        config: config.clone(),
+                        postprocess,
+                    });
+                }
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn write(dir: &Path, path: &str, data: &str) -> Result<()> {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap())?;
+        std::fs::write(path, data)?;
+        Ok(())
+    }
+
+    #[test]
+    fn export() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let root = dir.path().join("store.export");
+        let inbox = "Top of Personal Folders/Inbox";
+        write(
+            &root,
+            &format!("{inbox}/Message00001/OutlookHeaders.txt"),
+            "Client submit time:\t\tJul 03, 2001 08:20:30.000000000 UTC\nSubject:\t\t\tRe: budget\nSender name:\t\t\tCarol\nSender email address:\t\tcarol@example.com\nImportance:\t\t\tNormal\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/Message.txt"),
+            "Looks good\r\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/Recipients.txt"),
+            "Display name:\t\tDave\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/ConversationIndex.txt"),
+            "Header block:\t\t01 02\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/Attachments/notes.txt"),
+            "attached notes\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/Attachments/1_Message00001/OutlookHeaders.txt"),
+            "Subject:\t\tfwd\n",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Message00001/Attachments/1_Message00001/Message.html"),
+            "<p>forwarded</p>",
+        )?;
+        write(
+            &root,
+            &format!("{inbox}/Projects/Message00002/OutlookHeaders.txt"),
+            "Subject:\t\ta!/b\n",
+        )?;
+        write(
+            &root,
+            "Top of Personal Folders/Contacts/Contact00001/Contact.txt",
+            "Given name:\t\tErin\n",
+        )?;
+
+        let mut items = Vec::new();
+        item_dirs(&root, "", 0, &mut items)?;
+        let items = items
+            .iter()
+            .map(|(dir, folder)| read_item(dir, folder, 0))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(
+            items
+                .iter()
+                .map(|i| (i.name.as_str(), i.text.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("Contacts/Contact00001", "Given name:\t\tErin\n"),
+                (
+                    "Inbox/Re: budget",
+                    "From: Carol <carol@example.com>\nSubject: Re: budget\nDate: Jul 03, 2001 08:20:30.000000000 UTC\nLooks good\nSubject: fwd\nforwarded\nDisplay name:\t\tDave\n"
+                ),
+                ("Inbox/Projects/a! b", "Subject: a!/b\n"),
+            ]
+        );
+        assert_eq!(
+            items[1].attachments,
+            vec![root.join(format!("{inbox}/Message00001/Attachments/notes.txt"))]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,868 @@
+use super::binary::{one_line, usize_from, BinReader};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static PYC_EXTENSIONS: &[&str] = &["pyc", "pyo"];
+static PICKLE_EXTENSIONS: &[&str] = &["pkl", "pickle"];
+
+lazy_static! {
+    static ref PYC_METADATA: AdapterMeta = AdapterMeta {
+        name: "pyc".to_owned(),
+        version: 1,
+        description:
+            "Lists code object names, referenced names and string constants of compiled Python bytecode"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: PYC_EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-python-bytecode".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+    static ref PICKLE_METADATA: AdapterMeta = AdapterMeta {
+        name: "pickle".to_owned(),
+        version: 1,
+        description:
+            "Renders the structure of Python pickle streams as indented text without executing them"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: PICKLE_EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct PycAdapter;
+
+impl PycAdapter {
+    pub fn new() -> PycAdapter {
+        PycAdapter
+    }
+}
+impl GetMetadata for PycAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &PYC_METADATA
+    }
+}
+
+#[derive(Default, Clone)]
+pub struct PickleAdapter;
+
+impl PickleAdapter {
+    pub fn new() -> PickleAdapter {
+        PickleAdapter
+    }
+}
+impl GetMetadata for PickleAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &PICKLE_METADATA
+    }
+}
+
+/// nesting limit for both marshal and pickle data, so malicious files can't overflow the stack
+const MAX_DEPTH: usize = 200;
+
+/// the fields of a code object differ between python versions
+#[derive(Clone, Copy, PartialEq)]
+enum CodeLayout {
+    Py2,
+    Py3,
+    Py38,
+    Py311,
+}
+
+#[derive(Clone)]
+enum Marshal {
+    Null,
+    Str(String),
+    Seq(Vec<Marshal>),
+    Code(Box<CodeObject>),
+    Other,
+}
+
+#[derive(Clone)]
+struct CodeObject {
+    name: String,
+    filename: String,
+    first_line: i32,
+    consts: Vec<Marshal>,
+    names: Vec<String>,
+}
+
+impl Marshal {
+    fn str(&self) -> String {
+        match self {
+            Marshal::Str(s) => s.clone(),
+            _ => String::new(),
+        }
+    }
+    fn strs(&self) -> Vec<String> {
+        match self {
+            Marshal::Seq(v) => v.iter().map(Marshal::str).collect(),
+            _ => vec![],
+        }
+    }
+}
+
+struct Unmarshal<'a> {
+    r: BinReader<'a>,
+    pos: usize,
+    refs: Vec<Marshal>,
+    /// python 2 has no general back references, only to interned strings
+    interned: Vec<String>,
+    layout: CodeLayout,
+}
+
+impl<'a> Unmarshal<'a> {
+    fn u8(&mut self) -> Result<u8> {
+        self.pos += 1;
+        self.r.u8(self.pos - 1)
+    }
+    fn i32(&mut self) -> Result<i32> {
+        self.pos += 4;
+        Ok(self.r.u32(self.pos - 4)? as i32)
+    }
+    fn len(&mut self) -> Result<usize> {
+        usize_from(self.i32()? as u32)
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        self.pos += len;
+        self.r.slice(self.pos - len, len)
+    }
+    fn seq(&mut self, len: usize, depth: usize) -> Result<Marshal> {
+        // don't preallocate `len`, it comes from the file
+        let mut v = vec![];
+        for _ in 0..len {
+            v.push(self.read(depth + 1)?);
+        }
+        Ok(Marshal::Seq(v))
+    }
+    fn read(&mut self, depth: usize) -> Result<Marshal> {
+        if depth > MAX_DEPTH {
+            return Err(format_err!("marshal data nested too deeply"));
+        }
+        let code = self.u8()?;
+        let flag_ref = code & 0x80 != 0;
+        let ref_index = self.refs.len();
+        if flag_ref {
+            // reserve the slot before reading children, like CPython does
+            self.refs.push(Marshal::Other);
+        }
+        let value = match code & 0x7f {
+            b'0' => Marshal::Null,
+            b'N' | b'F' | b'T' | b'S' | b'.' => Marshal::Other,
+            b'i' => {
+                self.i32()?;
+                Marshal::Other
+            }
+            b'I' | b'g' => {
+                self.bytes(8)?;
+                Marshal::Other
+            }
+            b'y' => {
+                self.bytes(16)?;
+                Marshal::Other
+            }
+            b'f' => {
+                let len = usize::from(self.u8()?);
+                self.bytes(len)?;
+                Marshal::Other
+            }
+            b'x' => {
+                for _ in 0..2 {
+                    let len = usize::from(self.u8()?);
+                    self.bytes(len)?;
+                }
+                Marshal::Other
+            }
+            b'l' => {
+                let digits = self.i32()?.unsigned_abs() as usize;
+                self.bytes(digits * 2)?;
+                Marshal::Other
+            }
+            b's' | b't' | b'u' | b'a' | b'A' => {
+                let len = self.len()?;
+                let str = String::from_utf8_lossy(self.bytes(len)?).into_owned();
+                if code == b't' && self.layout == CodeLayout::Py2 {
+                    self.interned.push(str.clone());
+                }
+                Marshal::Str(str)
+            }
+            b'z' | b'Z' => {
+                let len = usize::from(self.u8()?);
+                Marshal::Str(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+            }
+            b'(' | b'[' | b'<' | b'>' => {
+                let len = self.len()?;
+                self.seq(len, depth)?
+            }
+            b')' => {
+                let len = usize::from(self.u8()?);
+                self.seq(len, depth)?
+            }
+            b'{' => {
+                let mut v = vec![];
+                loop {
+                    let key = self.read(depth + 1)?;
+                    if let Marshal::Null = key {
+                        break;
+                    }
+                    v.push(key);
+                    v.push(self.read(depth + 1)?);
+                }
+                Marshal::Seq(v)
+            }
+            b'r' => {
+                let idx = self.len()?;
+                self.refs
+                    .get(idx)
+                    .cloned()
+                    .ok_or_else(|| format_err!("invalid marshal back reference {}", idx))?
+            }
+            b'R' if self.layout == CodeLayout::Py2 => {
+                let idx = self.len()?;
+                Marshal::Str(
+                    self.interned
+                        .get(idx)
+                        .cloned()
+                        .ok_or_else(|| format_err!("invalid interned string reference {}", idx))?,
+                )
+            }
+            b'c' => Marshal::Code(Box::new(self.code(depth)?)),
+            other => {
+                return Err(format_err!(
+                    "unknown marshal type {:?} at offset {}",
+                    other as char,
+                    self.pos - 1
+                ))
+            }
+        };
+        if flag_ref {
+            self.refs[ref_index] = value.clone();
+        }
+        Ok(value)
+    }
+    fn code(&mut self, depth: usize) -> Result<CodeObject> {
+        let int_fields = match self.layout {
+            CodeLayout::Py2 => 4,
+            CodeLayout::Py3 | CodeLayout::Py311 => 5,
+            CodeLayout::Py38 => 6,
+        };
+        self.bytes(4 * int_fields)?;
+        let _bytecode = self.read(depth + 1)?;
+        let consts = match self.read(depth + 1)? {
+            Marshal::Seq(v) => v,
+            _ => vec![],
+        };
+        let names = self.read(depth + 1)?.strs();
+        let object_fields = if self.layout == CodeLayout::Py311 {
+            2 // localsplusnames, localspluskinds
+        } else {
+            3 // varnames, freevars, cellvars
+        };
+        for _ in 0..object_fields {
+            self.read(depth + 1)?;
+        }
+        let filename = self.read(depth + 1)?.str();
+        let mut name = self.read(depth + 1)?.str();
+        if self.layout == CodeLayout::Py311 {
+            name = self.read(depth + 1)?.str();
+        }
+        let first_line = self.i32()?;
+        // line number table, and exception table since 3.11
+        self.read(depth + 1)?;
+        if self.layout == CodeLayout::Py311 {
+            self.read(depth + 1)?;
+        }
+        Ok(CodeObject {
+            name,
+            filename,
+            first_line,
+            consts,
+            names,
+        })
+    }
+}
+
+fn write_code(code: &CodeObject, s: &mut impl Write) -> Result<()> {
+    writeln!(
+        s,
+        "code: {} ({}:{})",
+        code.name, code.filename, code.first_line
+    )?;
+    if !code.names.is_empty() {
+        writeln!(s, "names: {}", code.names.join(", "))?;
+    }
+    let mut nested = vec![];
+    let mut consts: Vec<&Marshal> = code.consts.iter().rev().collect();
+    while let Some(c) = consts.pop() {
+        match c {
+            Marshal::Str(str) => {
+                for line in str.lines().filter(|l| !l.trim().is_empty()) {
+                    writeln!(s, "string: {}", one_line(line.as_bytes()))?;
+                }
+            }
+            Marshal::Seq(v) => consts.extend(v.iter().rev()),
+            Marshal::Code(c) => nested.push(c),
+            _ => {}
+        }
+    }
+    for c in nested {
+        write_code(c, s)?;
+    }
+    Ok(())
+}
+
+fn dump_pyc(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let r = BinReader::le(data);
+    if r.slice(2, 2)? != b"\r\n" {
+        writeln!(s, "[rga: not a pyc file]")?;
+        return Ok(());
+    }
+    let magic = r.u16(0)?;
+    let (header_len, layout) = match magic {
+        3450..=3999 => (16, CodeLayout::Py311),
+        3410..=3449 => (16, CodeLayout::Py38),
+        3390..=3409 => (16, CodeLayout::Py3),
+        3190..=3389 => (12, CodeLayout::Py3),
+        3000..=3189 => (8, CodeLayout::Py3),
+        _ => (8, CodeLayout::Py2),
+    };
+    let mut u = Unmarshal {
+        r,
+        pos: header_len,
+        refs: vec![],
+        interned: vec![],
+        layout,
+    };
+    match u.read(0)? {
+        Marshal::Code(code) => write_code(&code, s),
+        _ => Err(format_err!("pyc does not contain a code object")),
+    }
+}
+
+#[derive(Clone)]
+pub enum Pickled {
+    None,
+    Bool(bool),
+    Int(String),
+    Float(f64),
+    Str(String),
+    Bytes(Vec<u8>),
+    List(Vec<Pickled>),
+    Tuple(Vec<Pickled>),
+    Set(Vec<Pickled>),
+    Dict(Vec<(Pickled, Pickled)>),
+    Global(String),
+    /// callable(args), from REDUCE / NEWOBJ / INST / OBJ
+    Call(Box<Pickled>, Vec<Pickled>),
+    /// object with state applied via BUILD
+    Build(Box<Pickled>, Box<Pickled>),
+    Persistent(Box<Pickled>),
+    Other(&'static str),
+}
+
+fn long_from_le(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "0".to_owned();
+    }
+    if bytes.len() <= 16 {
+        let fill = if bytes[bytes.len() - 1] & 0x80 != 0 {
+            0xff
+        } else {
+            0
+        };
+        let mut b = [fill; 16];
+        b[..bytes.len()].copy_from_slice(bytes);
+        i128::from_le_bytes(b).to_string()
+    } else {
+        let hex: String = bytes.iter().rev().map(|b| format!("{b:02x}")).collect();
+        format!("0x{hex}")
+    }
+}
+
+/// APPENDS / SETITEMS also apply to objects created by REDUCE (e.g. OrderedDict), so those keep the items as an extra argument
+fn add_items(target: &mut Pickled, items: Vec<Pickled>, is_dict: bool) {
+    match target {
+        Pickled::Dict(d) if is_dict => d.extend(pairs(items)),
+        Pickled::List(l) | Pickled::Set(l) if !is_dict => l.extend(items),
+        Pickled::Call(_, args) => match args.last_mut() {
+            Some(Pickled::Dict(d)) if is_dict => d.extend(pairs(items)),
+            Some(Pickled::List(l)) if !is_dict => l.extend(items),
+            _ if is_dict => args.push(Pickled::Dict(pairs(items))),
+            _ => args.push(Pickled::List(items)),
+        },
+        _ => {}
+    }
+}
+
+fn pairs(items: Vec<Pickled>) -> Vec<(Pickled, Pickled)> {
+    let mut it = items.into_iter();
+    let mut v = vec![];
+    while let (Some(k), Some(val)) = (it.next(), it.next()) {
+        v.push((k, val));
+    }
+    v
+}
+
+/// Runs the pickle opcodes on a symbolic stack, building a description of the result instead of calling anything
+pub fn unpickle(data: &[u8]) -> Result<Pickled> {
+    let r = BinReader::le(data);
+    let mut pos = 0;
+    let mut stack: Vec<Pickled> = vec![];
+    let mut marks: Vec<usize> = vec![];
+    let mut memo: HashMap<String, Pickled> = HashMap::new();
+    let take = |pos: &mut usize, len: usize| -> Result<&[u8]> {
+        *pos += len;
+        r.slice(*pos - len, len)
+    };
+    let line = |pos: &mut usize| -> Result<String> {
+        let rest = r.data.get(*pos..).unwrap_or_default();
+        let end = memchr::memchr(b'\n', rest)
+            .ok_or_else(|| format_err!("unterminated pickle line at {}", pos))?;
+        *pos += end + 1;
+        Ok(String::from_utf8_lossy(&rest[..end]).into_owned())
+    };
+    macro_rules! pop {
+        () => {
+            stack
+                .pop()
+                .ok_or_else(|| format_err!("pickle stack underflow"))?
+        };
+    }
+    macro_rules! pop_mark {
+        () => {{
+            let mark = marks
+                .pop()
+                .ok_or_else(|| format_err!("pickle mark stack underflow"))?;
+            if mark > stack.len() {
+                return Err(format_err!("pickle mark beyond stack"));
+            }
+            stack.split_off(mark)
+        }};
+    }
+    macro_rules! top {
+        () => {
+            stack
+                .last_mut()
+                .ok_or_else(|| format_err!("pickle stack underflow"))?
+        };
+    }
+    loop {
+        let op = take(&mut pos, 1)?[0];
+        match op {
+            b'.' => break,
+            b'\x80' => {
+                take(&mut pos, 1)?;
+            }
+            b'\x95' => {
+                take(&mut pos, 8)?;
+            }
+            b'(' => marks.push(stack.len()),
+            b'0' => {
+                pop!();
+            }
+            b'1' => {
+                let _ = pop_mark!();
+            }
+            b'2' => {
+                let t = top!().clone();
+                stack.push(t);
+            }
+            b'N' => stack.push(Pickled::None),
+            b'\x88' => stack.push(Pickled::Bool(true)),
+            b'\x89' => stack.push(Pickled::Bool(false)),
+            b'I' => {
+                let l = line(&mut pos)?;
+                stack.push(match l.as_str() {
+                    "00" => Pickled::Bool(false),
+                    "01" => Pickled::Bool(true),
+                    _ => Pickled::Int(l),
+                });
+            }
+            b'L' => stack.push(Pickled::Int(
+                line(&mut pos)?.trim_end_matches('L').to_owned(),
+            )),
+            b'F' => stack.push(Pickled::Float(line(&mut pos)?.parse().unwrap_or(f64::NAN))),
+            b'J' => stack.push(Pickled::Int(
+                (BinReader::le(take(&mut pos, 4)?).u32(0)? as i32).to_string(),
+            )),
+            b'K' => stack.push(Pickled::Int(take(&mut pos, 1)?[0].to_string())),
+            b'M' => stack.push(Pickled::Int(
+                BinReader::le(take(&mut pos, 2)?).u16(0)?.to_string(),
+            )),
+            b'\x8a' => {
+                let len = usize::from(take(&mut pos, 1)?[0]);
+                stack.push(Pickled::Int(long_from_le(take(&mut pos, len)?)));
+            }
+            b'\x8b' => {
+                let len = usize_from(BinReader::le(take(&mut pos, 4)?).u32(0)?)?;
+                stack.push(Pickled::Int(long_from_le(take(&mut pos, len)?)));
+            }
+            b'G' => stack.push(Pickled::Float(f64::from_bits(
+                BinReader::be(take(&mut pos, 8)?).u64(0)?,
+            ))),
+            b'S' => {
+                let l = line(&mut pos)?;
+                stack.push(Pickled::Str(
+                    l.trim_matches(|c| c == '\'' || c == '"').to_owned(),
+                ))
+            }
+            b'V' => stack.push(Pickled::Str(line(&mut pos)?)),
+            b'T' | b'X' | b'B' | b'U' | b'C' | b'\x8c' | b'\x8d' | b'\x8e' | b'\x96' => {
+                let len = match op {
+                    b'T' | b'X' | b'B' => usize_from(BinReader::le(take(&mut pos, 4)?).u32(0)?)?,
+                    b'U' | b'C' | b'\x8c' => usize::from(take(&mut pos, 1)?[0]),
+                    _ => usize_from(BinReader::le(take(&mut pos, 8)?).u64(0)?)?,
+                };
+                let bytes = take(&mut pos, len)?;
+                stack.push(match op {
+                    b'B' | b'C' | b'\x8e' | b'\x96' => Pickled::Bytes(bytes.to_vec()),
+                    _ => Pickled::Str(String::from_utf8_lossy(bytes).into_owned()),
+                });
+            }
+            b')' => stack.push(Pickled::Tuple(vec![])),
+            b']' => stack.push(Pickled::List(vec![])),
+            b'}' => stack.push(Pickled::Dict(vec![])),
+            b'\x8f' => stack.push(Pickled::Set(vec![])),
+            b't' => {
+                let items = pop_mark!();
+                stack.push(Pickled::Tuple(items))
+            }
+            b'l' => {
+                let items = pop_mark!();
+                stack.push(Pickled::List(items))
+            }
+            b'd' => {
+                let items = pop_mark!();
+                stack.push(Pickled::Dict(pairs(items)))
+            }
+            b'\x91' => {
+                let items = pop_mark!();
+                stack.push(Pickled::Set(items))
+            }
+            b'\x85' | b'\x86' | b'\x87' => {
+                let n = usize::from(op - 0x84);
+                if n > stack.len() {
+                    return Err(format_err!("pickle stack underflow"));
+                }
+                let items = stack.split_off(stack.len() - n);
+                stack.push(Pickled::Tuple(items));
+            }
+            b'a' => {
+                let item = pop!();
+                add_items(top!(), vec![item], false);
+            }
+            b'e' | b'\x90' => {
+                let items = pop_mark!();
+                add_items(top!(), items, false);
+            }
+            b's' => {
+                let value = pop!();
+                let key = pop!();
+                add_items(top!(), vec![key, value], true);
+            }
+            b'u' => {
+                let items = pop_mark!();
+                add_items(top!(), items, true);
+            }
+            b'c' => {
+                let module = line(&mut pos)?;
+                let name = line(&mut pos)?;
+                stack.push(Pickled::Global(format!("{module}.{name}")));
+            }
+            b'\x93' => {
+                let name = pop!();
+                let module = pop!();
+                let str = |p: Pickled| match p {
+                    Pickled::Str(s) => s,
+                    _ => "?".to_owned(),
+                };
+                stack.push(Pickled::Global(format!("{}.{}", str(module), str(name))));
+            }
+            b'i' => {
+                let module = line(&mut pos)?;
+                let name = line(&mut pos)?;
+                let args = pop_mark!();
+                stack.push(Pickled::Call(
+                    Box::new(Pickled::Global(format!("{module}.{name}"))),
+                    args,
+                ));
+            }
+            b'o' => {
+                let mut items = pop_mark!().into_iter();
+                let class = items
+                    .next()
+                    .ok_or_else(|| format_err!("OBJ without class"))?;
+                stack.push(Pickled::Call(Box::new(class), items.collect()));
+            }
+            b'R' | b'\x81' => {
+                let args = match pop!() {
+                    Pickled::Tuple(args) => args,
+                    other => vec![other],
+                };
+                let callable = pop!();
+                stack.push(Pickled::Call(Box::new(callable), args));
+            }
+            b'\x92' => {
+                let kwargs = pop!();
+                let mut args = match pop!() {
+                    Pickled::Tuple(args) => args,
+                    other => vec![other],
+                };
+                args.push(kwargs);
+                let class = pop!();
+                stack.push(Pickled::Call(Box::new(class), args));
+            }
+            b'b' => {
+                let state = pop!();
+                let obj = pop!();
+                stack.push(Pickled::Build(Box::new(obj), Box::new(state)));
+            }
+            b'P' => {
+                let id = line(&mut pos)?;
+                stack.push(Pickled::Persistent(Box::new(Pickled::Str(id))));
+            }
+            b'Q' => {
+                let id = pop!();
+                stack.push(Pickled::Persistent(Box::new(id)));
+            }
+            b'\x82' => {
+                take(&mut pos, 1)?;
+                stack.push(Pickled::Other("extension"));
+            }
+            b'\x83' => {
+                take(&mut pos, 2)?;
+                stack.push(Pickled::Other("extension"));
+            }
+            b'\x84' => {
+                take(&mut pos, 4)?;
+                stack.push(Pickled::Other("extension"));
+            }
+            b'\x97' => stack.push(Pickled::Other("out-of-band buffer")),
+            b'\x98' => {}
+            b'p' => {
+                let key = line(&mut pos)?;
+                memo.insert(key, top!().clone());
+            }
+            b'q' | b'r' | b'\x94' => {
+                let key = match op {
+                    b'q' => take(&mut pos, 1)?[0].to_string(),
+                    b'r' => BinReader::le(take(&mut pos, 4)?).u32(0)?.to_string(),
+                    _ => memo.len().to_string(),
+                };
+                let value = top!().clone();
+                memo.insert(key, value);
+            }
+            b'g' | b'h' | b'j' => {
+                let key = match op {
+                    b'g' => line(&mut pos)?,
+                    b'h' => take(&mut pos, 1)?[0].to_string(),
+                    _ => BinReader::le(take(&mut pos, 4)?).u32(0)?.to_string(),
+                };
+                let value = memo
+                    .get(&key)
+                    .cloned()
+                    .ok_or_else(|| format_err!("pickle memo key {} missing", key))?;
+                stack.push(value);
+            }
+            op => {
+                return Err(format_err!(
+                    "unknown pickle opcode 0x{:02x} at offset {}",
+                    op,
+                    pos - 1
+                ))
+            }
+        }
+        if stack.len() + marks.len() > 1_000_000 {
+            return Err(format_err!("pickle stack too large"));
+        }
+    }
+    stack.pop().ok_or_else(|| format_err!("empty pickle"))
+}
+
+/// pretty print an unpickled value with one scalar per line so matches have context
+fn write_pickled(p: &Pickled, indent: usize, s: &mut impl Write) -> Result<()> {
+    if indent > MAX_DEPTH {
+        writeln!(s, "{:indent$}...", "")?;
+        return Ok(());
+    }
+    let pad = " ".repeat(indent);
+    let inner = indent + 2;
+    let scalar = |p: &Pickled| -> Option<String> {
+        Some(match p {
+            Pickled::None => "None".to_owned(),
+            Pickled::Bool(b) => if *b { "True" } else { "False" }.to_owned(),
+            Pickled::Int(i) => i.clone(),
+            Pickled::Float(f) => f.to_string(),
+            Pickled::Str(str) => format!("{str:?}"),
+            Pickled::Bytes(b) => format!("b{:?}", one_line(b)),
+            Pickled::Global(g) => g.clone(),
+            Pickled::Other(o) => format!("<{o}>"),
+            _ => return None,
+        })
+    };
+    let seq = |open: &str, close: &str, items: &[Pickled], s: &mut dyn Write| -> Result<()> {
+        writeln!(s, "{pad}{open}")?;
+        for item in items {
+            write_pickled(item, inner, &mut &mut *s)?;
+        }
+        writeln!(s, "{pad}{close}")?;
+        Ok(())
+    };
+    match p {
+        Pickled::List(items) => seq("[", "]", items, s)?,
+        Pickled::Tuple(items) => seq("(", ")", items, s)?,
+        Pickled::Set(items) => seq("{", "}", items, s)?,
+        Pickled::Call(callable, args) => {
+            let name = scalar(callable).unwrap_or_else(|| "<callable>".to_owned());
+            seq(&format!("{name}("), ")", args, s)?
+        }
+        Pickled::Build(obj, state) => {
+            write_pickled(obj, indent, s)?;
+            writeln!(s, "{pad}.__setstate__(")?;
+            write_pickled(state, inner, s)?;
+            writeln!(s, "{pad})")?;
+        }
+        Pickled::Persistent(id) => {
+            writeln!(s, "{pad}persistent_load(")?;
+            write_pickled(id, inner, s)?;
+            writeln!(s, "{pad})")?;
+        }
+        Pickled::Dict(pairs) => {
+            writeln!(s, "{pad}{{")?;
+            for (k, v) in pairs {
+                match (scalar(k), scalar(v)) {
+                    (Some(k), Some(v)) => writeln!(s, "{pad}  {k}: {v}")?,
+                    (Some(k), None) => {
+                        writeln!(s, "{pad}  {k}:")?;
+                        write_pickled(v, inner + 2, s)?;
+                    }
+                    _ => {
+                        write_pickled(k, inner, s)?;
+                        write_pickled(v, inner + 2, s)?;
+                    }
+                }
+            }
+            writeln!(s, "{pad}}}")?;
+        }
+        scalar_value => writeln!(s, "{pad}{}", scalar(scalar_value).unwrap_or_default())?,
+    }
+    Ok(())
+}
+
+fn dump_pickle(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let value = unpickle(data)?;
+    write_pickled(&value, 0, s)
+}
+
+#[async_trait]
+impl WritingFileAdapter for PycAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_pyc(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for PickleAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_pickle(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn pyc_311() -> Result<()> {
+        // py_compile of `x = "needle"` with python 3.11
+        let data = b"\xa7\x0d\x0d\x0a\x00\x00\x00\x00\x95G\xd0j\x0d\x00\x00\x00\xe3\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x01\x00\x00\x00\x00\x00\x00\x00\xf3\x0a\x00\x00\x00\x97\x00d\x00Z\x00d\x01S\x00)\x02\xda\x06needleN)\x01\xda\x01x\xa9\x00\xf3\x00\x00\x00\x00\xfa\x04t.py\xfa\x08<module>r\x07\x00\x00\x00\x01\x00\x00\x00s\x0e\x00\x00\x00\xf0\x03\x01\x01\x01\xd8\x04\x0c\x80\x01\x80\x01\x80\x01r\x05\x00\x00\x00";
+        let mut out = Vec::new();
+        dump_pyc(data, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "code: <module> (t.py:1)\nnames: x\nstring: needle\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pickle_protocol2() -> Result<()> {
+        // pickle.dumps({'user': 'alice', 'ids': [1, 2**40], 'when': datetime.date(2020, 1, 2)}, protocol=2)
+        let data = b"\x80\x02}q\x00(X\x04\x00\x00\x00userq\x01X\x05\x00\x00\x00aliceq\x02X\x03\x00\x00\x00idsq\x03]q\x04(K\x01\x8a\x06\x00\x00\x00\x00\x00\x01eX\x04\x00\x00\x00whenq\x05cdatetime\ndate\nq\x06c_codecs\nencode\nq\x07X\x05\x00\x00\x00\x07\xc3\xa4\x01\x02q\x08X\x06\x00\x00\x00latin1q\t\x86q\nRq\x0b\x85q\x0cRq\ru.";
+        let mut out = Vec::new();
+        dump_pickle(data, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            r#"{
+  "user": "alice"
+  "ids":
+    [
+      1
+      1099511627776
+    ]
+  "when":
+    datetime.date(
+      _codecs.encode(
+        "\u{7}ä\u{1}\u{2}"
+        "latin1"
+      )
+    )
+}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn pickle_errors() {
+        assert!(unpickle(b"").is_err());
+        assert!(unpickle(b"\x80\x02R.").is_err());
+        assert!(unpickle(b"\x80\x02\xff.").is_err());
+    }
+
+    #[test]
+    fn long() {
+        assert_eq!(long_from_le(&[0xff]), "-1");
+        assert_eq!(long_from_le(&[0x00, 0x01]), "256");
+    }
+}
@@ -0,0 +1,192 @@
+use super::custom::map_exe_error;
+use super::sevenzip::bsdtar;
+use super::tar::TarAdapter;
+use super::*;
+use crate::policy;
+use crate::vpath::member_prefix;
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+static EXTENSIONS: &[&str] = &["rar"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rar".to_owned(),
+        version: 1,
+        description:
+            "Uses unrar to extract RAR archives (including RAR5) and recurses down into their contents. Multi-volume archives are searched with their first part. Falls back to bsdtar (from libarchive) if unrar is not installed"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.rar".to_owned()),
+            FileMatcher::MimeType("application/x-rar-compressed".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // unrar reads the later parts of multi-volume archives from next to the first one
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RarAdapter;
+
+impl RarAdapter {
+    pub fn new() -> RarAdapter {
+        RarAdapter
+    }
+}
+impl GetMetadata for RarAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the extracted files below `dir` in order, with their path in the archive. Links are skipped, so
+/// nothing outside of the extracted archive is read
+pub(crate) fn extracted_files(
+    dir: &Path,
+    member_dir: &str,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("reading {}", dir.display()))?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for path in entries {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let member = if member_dir.is_empty() {
+            name
+        } else {
+            format!("{member_dir}/{name}")
+        };
+        let file_type = std::fs::symlink_metadata(&path)?.file_type();
+        if file_type.is_dir() {
+            extracted_files(&path, &member, files)?;
+        } else if file_type.is_file() {
+            files.push((path, member));
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl FileAdapter for RarAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let dir = tempfile::tempdir().context("creating temporary directory")?;
+        // -p-: encrypted members are skipped instead of asking for the password
+        let out = policy::command(&config.policy, "unrar", &filepath_hint)?
+            .args(["x", "-idq", "-p-", "-y", "-o+"])
+            .arg(&filepath_hint)
+            // unrar takes a destination only if it ends with a separator
+            .arg(dir.path().join(""))
+            .stdin(Stdio::null())
+            .output()
+            .await;
+        let out = match out {
+            Ok(out) => out,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                debug!(
+                    "unrar not found, converting {} with bsdtar",
+                    filepath_hint.display()
+                );
+                let ai = AdaptInfo {
+                    inp: bsdtar(&config, &filepath_hint)?,
+                    filepath_hint,
+                    is_real_file: false,
+                    archive_recursion_depth,
+                    line_prefix,
+                    config,
+                    postprocess,
+                };
+                return TarAdapter::new().adapt(ai, detection_reason).await;
+            }
+            Err(e) => return Err(map_exe_error(e, "unrar", "Install unrar.")),
+        };
+        let mut files = Vec::new();
+        extracted_files(dir.path(), "", &mut files)?;
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let error = stderr.trim().lines().last().unwrap_or_default().to_owned();
+            // damaged and encrypted members are left out, the others are still searched
+            if files.is_empty() {
+                bail!("unrar failed ({}): {}", out.status, error);
+            }
+            warn!(
+                "{}: unrar failed ({}): {}",
+                filepath_hint.display(),
+                out.status,
+                error
+            );
+        }
+        let s = stream! {
+            // the extracted files are read while the members are searched
+            let _dir = dir;
+            for (path, member) in files {
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&member),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(tokio::fs::File::open(&path).await?),
+                    line_prefix: member_prefix(&line_prefix, &member),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn members() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("data/2023"))?;
+        std::fs::write(dir.path().join("readme.txt"), "")?;
+        std::fs::write(dir.path().join("data/2023/results.csv"), "")?;
+        std::fs::write(dir.path().join("data/index.html"), "")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("/etc/passwd", dir.path().join("data/link"))?;
+        let mut files = Vec::new();
+        extracted_files(dir.path(), "", &mut files)?;
+        let members = files.iter().map(|(_, m)| m.as_str()).collect::<Vec<_>>();
+        assert_eq!(
+            members,
+            ["data/2023/results.csv", "data/index.html", "readme.txt"]
+        );
+        assert_eq!(files[0].0, dir.path().join("data/2023/results.csv"));
+        Ok(())
+    }
+}
@@ -0,0 +1,475 @@
+use super::{writing::WritingFileAdapter, *};
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use encoding_rs::{Encoding, MACINTOSH, WINDOWS_1252};
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["rtf"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "rtf".to_owned(),
+        version: 1,
+        description:
+            "Extracts the text of RTF documents without external programs, decoding \\uN escapes and the codepages of the document and its fonts. Table rows are lines with the cells separated by tabs, pictures, embedded objects and document properties are left out"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/rtf".to_owned()),
+            FileMatcher::MimeType("text/rtf".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct RtfAdapter;
+
+impl RtfAdapter {
+    pub fn new() -> RtfAdapter {
+        RtfAdapter
+    }
+}
+impl GetMetadata for RtfAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// destinations (groups starting with these control words) that don't contain text of the document
+static SKIPPED_DESTINATIONS: &[&str] = &[
+    "colortbl",
+    "stylesheet",
+    "info",
+    "pict",
+    "nonshppict",
+    "shprslt",
+    "sp",
+    "objdata",
+    "objclass",
+    "fldinst",
+    "listtable",
+    "listoverridetable",
+    "revtbl",
+    "rsidtbl",
+    "filetbl",
+    "themedata",
+    "colorschememapping",
+    "datastore",
+    "latentstyles",
+    "xmlnstbl",
+    "generator",
+];
+
+/// destinations marked with `\*` (skip if unknown) that contain text of the document
+static KEPT_OPTIONAL_DESTINATIONS: &[&str] = &["shpinst"];
+
+/// the text of control words that stand for a character
+fn control_word_text(word: &str) -> Option<&'static str> {
+    Some(match word {
+        "par" | "line" | "sect" | "page" | "row" => "\n",
+        "tab" | "cell" | "nestcell" => "\t",
+        "emdash" => "\u{2014}",
+        "endash" => "\u{2013}",
+        "emspace" | "enspace" | "qmspace" => " ",
+        "bullet" => "\u{2022}",
+        "lquote" => "\u{2018}",
+        "rquote" => "\u{2019}",
+        "ldblquote" => "\u{201C}",
+        "rdblquote" => "\u{201D}",
+        _ => return None,
+    })
+}
+
+/// the encoding of a Windows codepage (`\ansicpgN`, `\cpgN`)
+pub(super) fn codepage_encoding(codepage: i64) -> Option<&'static Encoding> {
+    let label = match codepage {
+        874 | 1250..=1258 => format!("windows-{codepage}"),
+        932 => "shift_jis".to_owned(),
+        936 => "gbk".to_owned(),
+        949 => "euc-kr".to_owned(),
+        950 => "big5".to_owned(),
+        10000 => "macintosh".to_owned(),
+        20866 => "koi8-r".to_owned(),
+        65001 => "utf-8".to_owned(),
+        _ => return None,
+    };
+    Encoding::for_label(label.as_bytes())
+}
+
+/// the codepage of a font charset (`\fcharsetN`)
+fn charset_codepage(charset: i64) -> Option<i64> {
+    Some(match charset {
+        0 => 1252,
+        77 => 10000,
+        128 => 932,
+        129 => 949,
+        134 => 936,
+        136 => 950,
+        161 => 1253,
+        162 => 1254,
+        163 => 1258,
+        177 => 1255,
+        178 => 1256,
+        186 => 1257,
+        204 => 1251,
+        222 => 874,
+        238 => 1250,
+        _ => return None,
+    })
+}
+
+/// the state of a group (`{...}`), inherited by the groups in it
+#[derive(Clone)]
+struct Group {
+    /// the group doesn't contain text of the document
+    skip: bool,
+    /// the group is (in) the font table
+    fonttbl: bool,
+    /// the number of fallback characters after each `\uN` (`\ucN`)
+    uc: usize,
+    /// the current font (`\fN`), the default font if None
+    font: Option<i64>,
+}
+
+struct Rtf {
+    text: String,
+    /// text bytes that are not decoded yet, since the characters of multi-byte codepages are split
+    /// over several `\'hh` escapes
+    bytes: Vec<u8>,
+    bytes_encoding: &'static Encoding,
+    /// the first half of a character outside the BMP, given as two `\uN`
+    high_surrogate: Option<u32>,
+    /// the codepage of the document (`\ansicpgN`)
+    codepage: &'static Encoding,
+    default_font: Option<i64>,
+    fonts: HashMap<i64, &'static Encoding>,
+    /// the font of the font table being read
+    table_font: Option<i64>,
+}
+
+impl Rtf {
+    fn encoding(&self, group: &Group) -> &'static Encoding {
+        group
+            .font
+            .or(self.default_font)
+            .and_then(|f| self.fonts.get(&f).copied())
+            .unwrap_or(self.codepage)
+    }
+
+    fn flush(&mut self) {
+        if !self.bytes.is_empty() {
+            let text = self
+                .bytes_encoding
+                .decode_without_bom_handling(&self.bytes)
+                .0;
+            self.text.push_str(&text);
+            self.bytes.clear();
+        }
+        if self.high_surrogate.take().is_some() {
+            self.text.push('\u{FFFD}');
+        }
+    }
+
+    fn byte(&mut self, b: u8, encoding: &'static Encoding) {
+        if encoding != self.bytes_encoding || self.high_surrogate.is_some() {
+            self.flush();
+            self.bytes_encoding = encoding;
+        }
+        self.bytes.push(b);
+    }
+
+    fn push(&mut self, text: &str) {
+        self.flush();
+        self.text.push_str(text);
+    }
+
+    /// the character of `\uN`, where N is a signed 16 bit number
+    fn unicode(&mut self, n: i64) {
+        let u = n.rem_euclid(0x10000) as u32;
+        match (self.high_surrogate, u) {
+            (Some(high), 0xDC00..=0xDFFF) => {
+                self.high_surrogate = None;
+                let c = char::from_u32(0x10000 + ((high - 0xD800) << 10) + (u - 0xDC00));
+                self.push(&c.unwrap_or('\u{FFFD}').to_string());
+            }
+            (_, 0xD800..=0xDBFF) => {
+                self.flush();
+                self.high_surrogate = Some(u);
+            }
+            _ => self.push(&char::from_u32(u).unwrap_or('\u{FFFD}').to_string()),
+        }
+    }
+}
+
+/// the text of an RTF document, one line per paragraph
+pub(super) fn rtf_to_text(data: &[u8]) -> Result<String> {
+    if !data.starts_with(b"{\\rtf") {
+        bail!("not an RTF document");
+    }
+    let mut rtf = Rtf {
+        text: String::new(),
+        bytes: Vec::new(),
+        bytes_encoding: WINDOWS_1252,
+        high_surrogate: None,
+        codepage: WINDOWS_1252,
+        default_font: None,
+        fonts: HashMap::new(),
+        table_font: None,
+    };
+    let mut groups = vec![Group {
+        skip: false,
+        fonttbl: false,
+        uc: 1,
+        font: None,
+    }];
+    // fallback characters of the last \uN that are still to be skipped
+    let mut skip_chars = 0;
+    // the next control word is the first in its group, which makes the group a destination
+    let mut destination = false;
+    // the destination is marked with \*, only to be read if it is known
+    let mut optional = false;
+    let mut pos = 0;
+    while pos < data.len() {
+        let b = data[pos];
+        pos += 1;
+        let group = groups
+            .last_mut()
+            .expect("the document group is never closed");
+        match b {
+            b'{' => {
+                let group = group.clone();
+                groups.push(group);
+                skip_chars = 0;
+                destination = true;
+                optional = false;
+                continue;
+            }
+            b'}' => {
+                if groups.len() > 1 {
+                    groups.pop();
+                }
+                skip_chars = 0;
+            }
+            b'\r' | b'\n' => continue,
+            b'\\' => {
+                let c = match data.get(pos) {
+                    Some(&c) => c,
+                    None => break,
+                };
+                if c.is_ascii_alphabetic() {
+                    let start = pos;
+                    while data.get(pos).map_or(false, u8::is_ascii_alphabetic) {
+                        pos += 1;
+                    }
+                    let word = std::str::from_utf8(&data[start..pos]).expect("ascii");
+                    let number_start = pos;
+                    if data.get(pos) == Some(&b'-') {
+                        pos += 1;
+                    }
+                    while data.get(pos).map_or(false, u8::is_ascii_digit) {
+                        pos += 1;
+                    }
+                    let param = std::str::from_utf8(&data[number_start..pos])
+                        .expect("ascii")
+                        .parse::<i64>()
+                        .ok();
+                    if data.get(pos) == Some(&b' ') {
+                        pos += 1;
+                    }
+                    if destination {
+                        if word == "fonttbl" {
+                            group.fonttbl = true;
+                            group.skip = true;
+                        } else if SKIPPED_DESTINATIONS.contains(&word)
+                            || (optional && !KEPT_OPTIONAL_DESTINATIONS.contains(&word))
+                        {
+                            group.skip = true;
+                        }
+                    }
+                    destination = false;
+                    optional = false;
+                    if word == "bin" {
+                        // binary data, not even escaped
+                        pos = pos.saturating_add(param.unwrap_or(0).max(0) as usize);
+                        continue;
+                    }
+                    if skip_chars > 0 && word != "u" {
+                        skip_chars -= 1;
+                        continue;
+                    }
+                    match (word, param) {
+                        ("ansicpg", Some(cp)) => {
+                            rtf.codepage = codepage_encoding(cp).unwrap_or(rtf.codepage)
+                        }
+                        ("mac", _) => rtf.codepage = MACINTOSH,
+                        ("deff", Some(f)) => rtf.default_font = Some(f),
+                        ("f", Some(f)) if group.fonttbl => rtf.table_font = Some(f),
+                        ("f", Some(f)) => group.font = Some(f),
+                        ("fcharset", Some(charset)) if group.fonttbl => {
+                            if let (Some(f), Some(encoding)) = (
+                                rtf.table_font,
+                                charset_codepage(charset).and_then(codepage_encoding),
+                            ) {
+                                rtf.fonts.entry(f).or_insert(encoding);
+                            }
+                        }
+                        ("cpg", Some(cp)) if group.fonttbl => {
+                            if let (Some(f), Some(encoding)) =
+                                (rtf.table_font, codepage_encoding(cp))
+                            {
+                                rtf.fonts.insert(f, encoding);
+                            }
+                        }
+                        ("plain", _) => group.font = None,
+                        ("uc", Some(n)) => group.uc = n.max(0) as usize,
+                        ("u", Some(n)) => {
+                            if !group.skip {
+                                rtf.unicode(n);
+                            }
+                            skip_chars = group.uc;
+                        }
+                        _ => {
+                            if let Some(text) = control_word_text(word).filter(|_| !group.skip) {
+                                rtf.push(text);
+                            }
+                        }
+                    }
+                    continue;
+                }
+                pos += 1;
+                if c == b'*' {
+                    optional = true;
+                    continue;
+                }
+                destination = false;
+                if c == b'\'' {
+                    let byte = data
+                        .get(pos..pos + 2)
+                        .and_then(|hex| std::str::from_utf8(hex).ok())
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                    pos = (pos + 2).min(data.len());
+                    if skip_chars > 0 {
+                        skip_chars -= 1;
+                    } else if let Some(byte) = byte.filter(|_| !group.skip) {
+                        let encoding = rtf.encoding(group);
+                        rtf.byte(byte, encoding);
+                    }
+                    continue;
+                }
+                if skip_chars > 0 {
+                    skip_chars -= 1;
+                    continue;
+                }
+                let text = match c {
+                    b'\\' => "\\",
+                    b'{' => "{",
+                    b'}' => "}",
+                    b'~' => " ",
+                    b'_' => "-",
+                    b'\r' | b'\n' => "\n",
+                    _ => "",
+                };
+                if !group.skip {
+                    rtf.push(text);
+                }
+                continue;
+            }
+            _ => {
+                if skip_chars > 0 {
+                    skip_chars -= 1;
+                } else if !group.skip {
+                    let encoding = rtf.encoding(group);
+                    rtf.byte(b, encoding);
+                }
+            }
+        }
+        destination = false;
+        optional = false;
+    }
+    rtf.flush();
+    Ok(rtf.text)
+}
+
+fn dump_rtf(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let text = rtf_to_text(data)?;
+    for line in text.lines() {
+        let line = line.trim_end();
+        if !line.is_empty() {
+            writeln!(s, "{line}")?;
+        }
+    }
+    Ok(())
+}
+
+#[async_trait]
+impl WritingFileAdapter for RtfAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        dump_rtf(&data, &mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dump(data: &[u8]) -> Result<String> {
+        let mut out = Vec::new();
+        dump_rtf(data, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn rtf() -> Result<()> {
+        let doc = br#"{\rtf1\ansi\ansicpg1252\deff0{\fonttbl{\f0\fswiss Helvetica;}{\f1\fcharset204 Times New Roman Cyr;}}
+{\colortbl;\red255\green0\blue0;}{\*\generator Riched20 10.0;}{\info{\title Secret title}{\author Me}}
+\pard\f0 Caf\'e9 na\u239?ve \'93quoted\'94\par
+{\f1 \'cf\'f0\'e8\'e2\'e5\'f2}\par
+Line\line two\tab tabbed\par
+{\pict\pngblip 89504e470d0a}{\field{\*\fldinst HYPERLINK "http://example.com"}{\fldrslt link text}}\par
+\trowd a\cell b\cell\row
+\u-10179?\u-8704? emoji, \{braces\} and \\backslash\par
+{\*\unknowndestination hidden}{\*\bkmkstart mark}visible\bin4 ab}c after binary\par}"#;
+        assert_eq!(
+            dump(doc)?,
+            "Café naïve “quoted”\nПривет\nLine\ntwo\ttabbed\nlink text\na\tb\n😀 emoji, {braces} and \\backslash\nvisible after binary\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn codepages() -> Result<()> {
+        // 日本語 in shift_jis, the second byte of 本 is an escaped {
+        let doc = br#"{\rtf1\ansi\ansicpg932\deff0{\fonttbl{\f0\fnil\fcharset128 MS Mincho;}}
+\f0 \'93\'fa\'96\'7b\'8c\'ea\par
+{\uc0\u8364 5}\par}"#;
+        assert_eq!(dump(doc)?, "日本語\n€5\n");
+        Ok(())
+    }
+
+    #[test]
+    fn not_rtf() {
+        assert!(dump(b"plain text").is_err());
+    }
+}
@@ -0,0 +1,187 @@
+use super::custom::pipe_output;
+use super::tar::TarAdapter;
+use super::*;
+use crate::config::RgaConfig;
+use crate::policy;
+use crate::vpath::member_prefix;
+use anyhow::{Context, Result};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use sevenz_rust::{Password, SevenZReader};
+use std::ffi::OsString;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::io::DuplexStream;
+use tokio::sync::mpsc;
+use tokio_util::io::SyncIoBridge;
+
+static EXTENSIONS: &[&str] = &["7z"];
+
+/// the buffer between the decoder and the adapter of a member
+const PIPE_LEN: usize = 64 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "7z".to_owned(),
+        version: 1,
+        description:
+            "Reads 7-Zip archives and recurses down into their contents. Archives with compression methods rga can't decode (like PPMd) are converted with bsdtar (from libarchive)"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![FileMatcher::MimeType(
+            "application/x-7z-compressed".to_owned()
+        )]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // the headers are at the end of the archive
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SevenZipAdapter;
+
+impl SevenZipAdapter {
+    pub fn new() -> SevenZipAdapter {
+        SevenZipAdapter
+    }
+}
+impl GetMetadata for SevenZipAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// decode the members one after the other, each is written to a pipe that is sent to the adapter.
+/// Members are in solid blocks, so they can only be decoded in order
+fn decode_members(path: &Path, tx: mpsc::Sender<(String, DuplexStream)>) -> Result<()> {
+    let mut archive = SevenZReader::open(path, Password::empty())
+        .with_context(|| format!("opening {}", path.display()))?;
+    archive.for_each_entries(|entry, reader| {
+        if entry.is_directory() {
+            return Ok(true);
+        }
+        let (inp, out) = tokio::io::duplex(PIPE_LEN);
+        if tx.blocking_send((entry.name().to_owned(), inp)).is_err() {
+            // the search was stopped
+            return Ok(false);
+        }
+        let mut out = SyncIoBridge::new(out);
+        if let Err(e) = std::io::copy(reader, &mut out) {
+            if e.kind() != ErrorKind::BrokenPipe {
+                return Err(e.into());
+            }
+            // the member was not read to the end, the rest still has to be decoded to get to the next one
+            std::io::copy(reader, &mut std::io::sink())?;
+        }
+        Ok(true)
+    })?;
+    Ok(())
+}
+
+/// the archive as a tar stream, converted by bsdtar. libarchive reads most archive formats
+pub(crate) fn bsdtar(config: &RgaConfig, path: &Path) -> Result<ReadBox> {
+    let mut archive = OsString::from("@");
+    archive.push(path);
+    let mut cmd = policy::command(&config.policy, "bsdtar", path)?;
+    cmd.args(["-cf", "-"]).arg(archive);
+    pipe_output(
+        "",
+        cmd,
+        Box::pin(tokio::io::empty()),
+        "bsdtar",
+        "Install libarchive-tools.",
+    )
+}
+
+#[async_trait]
+impl FileAdapter for SevenZipAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let (tx, mut rx) = mpsc::channel(1);
+        let decoder = {
+            let path = filepath_hint.clone();
+            tokio::task::spawn_blocking(move || decode_members(&path, tx))
+        };
+        let detection_reason = detection_reason.clone();
+        let s = stream! {
+            let mut members = 0;
+            while let Some((name, inp)) = rx.recv().await {
+                members += 1;
+                debug!("{}|{}", filepath_hint.display(), name);
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&name),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(inp),
+                    line_prefix: member_prefix(&line_prefix, &name),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+            match decoder.await? {
+                Ok(()) => {}
+                // nothing was output yet, so the whole archive can be read with bsdtar instead
+                Err(e) if members == 0 => {
+                    debug!("{}: {:#}, converting with bsdtar", filepath_hint.display(), e);
+                    let ai = AdaptInfo {
+                        inp: bsdtar(&config, &filepath_hint)?,
+                        filepath_hint,
+                        is_real_file: false,
+                        archive_recursion_depth,
+                        line_prefix,
+                        config,
+                        postprocess,
+                    };
+                    for await member in TarAdapter::new().adapt(ai, &detection_reason).await? {
+                        yield member;
+                    }
+                }
+                Err(e) => yield Err(e),
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_simple_7z() -> Result<()> {
+        let filepath = test_data_dir().join("hello.7z");
+        let (a, d) = simple_fs_adapt_info(&filepath).await?;
+        let r = loop_adapt(&SevenZipAdapter::new(), d, a)
+            .await
+            .context("adapt")?;
+        let o = adapted_to_vec(r).await.context("adapted_to_vec")?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:hello.txt: hello world
+PREFIX:dir/nested.txt: this is a nested file
+PREFIX:dir/nested.txt: in a 7z archive
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,275 @@
+use super::binary::{usize_from, BinReader};
+use super::postproc::PostprocPrefix;
+use super::strings::StringsAdapter;
+use super::*;
+use crate::adapted_iter::one_file;
+use anyhow::*;
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+static MIMETYPES: &[&str] = &[
+    "application/x-executable",
+    "application/x-pie-executable",
+    "application/x-sharedlib",
+    "application/x-dosexec",
+    "application/x-ms-dos-executable",
+    "application/x-msdownload",
+    "application/vnd.microsoft.portable-executable",
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "sfx".to_owned(),
+        version: 1,
+        description:
+            "Finds zip, 7z and rar archives appended to PE and ELF executables (self-extracting archives) and recurses into them. Only used with --rga-accurate"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: vec![],
+        slow_matchers: Some(
+            MIMETYPES
+                .iter()
+                .map(|s| FileMatcher::MimeType(s.to_string()))
+                .collect()
+        ),
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SfxAdapter;
+
+impl SfxAdapter {
+    pub fn new() -> SfxAdapter {
+        SfxAdapter
+    }
+}
+impl GetMetadata for SfxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// archive signatures and the extension the payload is adapted as
+static PAYLOADS: &[(&[u8], &str)] = &[
+    (b"PK\x03\x04", "zip"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+];
+
+/// end of the last section of a PE file, where appended data ("overlay") starts
+fn pe_overlay(data: &[u8]) -> Result<usize> {
+    let r = BinReader::le(data);
+    let pe = usize_from(r.u32(0x3c)?)?;
+    if r.slice(pe, 4)? != b"PE\0\0" {
+        bail!("not a PE file");
+    }
+    let sections = usize::from(r.u16(pe + 6)?);
+    let table = pe + 24 + usize::from(r.u16(pe + 20)?);
+    let mut end = table + sections * 40;
+    for i in 0..sections {
+        let section = table + i * 40;
+        let size = usize_from(r.u32(section + 16)?)?;
+        let pointer = usize_from(r.u32(section + 20)?)?;
+        end = end.max(pointer.saturating_add(size));
+    }
+    Ok(end)
+}
+
+/// end of the last segment or section of an ELF file, or of the header tables
+pub(crate) fn elf_overlay(data: &[u8]) -> Result<usize> {
+    let r = match data.get(5) {
+        Some(2) => BinReader::be(data),
+        _ => BinReader::le(data),
+    };
+    let is_64 = r.u8(4)? == 2;
+    let (phoff, shoff, sizes) = if is_64 {
+        (r.u64(0x20)?, r.u64(0x28)?, 0x36)
+    } else {
+        (u64::from(r.u32(0x1c)?), u64::from(r.u32(0x20)?), 0x2a)
+    };
+    let (phoff, shoff) = (usize_from(phoff)?, usize_from(shoff)?);
+    let phentsize = usize::from(r.u16(sizes)?);
+    let phnum = usize::from(r.u16(sizes + 2)?);
+    let shentsize = usize::from(r.u16(sizes + 4)?);
+    let shnum = usize::from(r.u16(sizes + 6)?);
+    // bounds check the tables first, so the offsets below can't overflow
+    r.slice(phoff, phentsize * phnum)?;
+    r.slice(shoff, shentsize * shnum)?;
+    let mut end = (phoff + phentsize * phnum).max(shoff + shentsize * shnum);
+    for i in 0..phnum {
+        let ph = phoff + i * phentsize;
+        let (offset, size) = if is_64 {
+            (r.u64(ph + 8)?, r.u64(ph + 0x20)?)
+        } else {
+            (u64::from(r.u32(ph + 4)?), u64::from(r.u32(ph + 0x10)?))
+        };
+        end = end.max(usize_from(offset.saturating_add(size))?);
+    }
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        // SHT_NOBITS (.bss) takes no space in the file
+        if r.u32(sh + 4)? == 8 {
+            continue;
+        }
+        let (offset, size) = if is_64 {
+            (r.u64(sh + 0x18)?, r.u64(sh + 0x20)?)
+        } else {
+            (u64::from(r.u32(sh + 0x10)?), u64::from(r.u32(sh + 0x14)?))
+        };
+        end = end.max(usize_from(offset.saturating_add(size))?);
+    }
+    Ok(end)
+}
+
+/// offset and type of the first archive after the executable image, if any
+fn find_payload(data: &[u8]) -> Result<Option<(usize, &'static str)>> {
+    let overlay = if data.starts_with(b"MZ") {
+        pe_overlay(data)?
+    } else if data.starts_with(b"\x7fELF") {
+        elf_overlay(data)?
+    } else {
+        bail!("not a PE or ELF executable")
+    };
+    let rest = match data.get(overlay..) {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    // installers often put a config block in front of the archive, so search instead of
+    // only looking at the start of the overlay
+    Ok(PAYLOADS
+        .iter()
+        .filter_map(|(magic, ext)| {
+            memchr::memmem::find(rest, magic).map(|pos| (overlay + pos, *ext))
+        })
+        .min())
+}
+
+#[async_trait]
+impl FileAdapter for SfxAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            is_real_file,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            config,
+            postprocess,
+        } = ai;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let payload = find_payload(&data).unwrap_or_else(|e| {
+            debug!("{}: {:#}", filepath_hint.display(), e);
+            None
+        });
+        let (offset, ext) = if let Some(payload) = payload {
+            payload
+        } else {
+            // a plain executable: handle it as if no adapter had matched
+            let active = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+            let ai = AdaptInfo {
+                filepath_hint,
+                is_real_file,
+                inp: Box::pin(Cursor::new(data)),
+                line_prefix,
+                archive_recursion_depth,
+                config,
+                postprocess,
+            };
+            if active.iter().any(|a| a.metadata().name == "strings") {
+                return StringsAdapter::new().adapt(ai, detection_reason).await;
+            }
+            if postprocess {
+                return PostprocPrefix {}.adapt(ai, detection_reason).await;
+            }
+            return Ok(one_file(ai));
+        };
+        debug!(
+            "{}: {} archive at offset {}",
+            filepath_hint.display(),
+            ext,
+            offset
+        );
+        data.drain(..offset);
+        let name = filepath_hint
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let s = stream! {
+            yield Ok(AdaptInfo {
+                filepath_hint: PathBuf::from(format!("{name}.{ext}")),
+                is_real_file: false,
+                inp: Box::pin(Cursor::new(data)),
+                line_prefix,
+                archive_recursion_depth: archive_recursion_depth + 1,
+                postprocess,
+                config,
+            });
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// a PE image with one section at 0x200..0x300
+    fn pe_stub() -> Vec<u8> {
+        let mut data = vec![0u8; 0x300];
+        data[..2].copy_from_slice(b"MZ");
+        data[0x3c..0x40].copy_from_slice(&0x80u32.to_le_bytes());
+        data[0x80..0x84].copy_from_slice(b"PE\0\0");
+        data[0x86..0x88].copy_from_slice(&1u16.to_le_bytes());
+        let section = 0x80 + 24;
+        data[section..section + 8].copy_from_slice(b".text\0\0\0");
+        data[section + 16..section + 20].copy_from_slice(&0x100u32.to_le_bytes());
+        data[section + 20..section + 24].copy_from_slice(&0x200u32.to_le_bytes());
+        // an archive signature inside the image itself is not a payload
+        data[0x210..0x214].copy_from_slice(b"PK\x03\x04");
+        data
+    }
+
+    /// an ELF64 file with one PT_LOAD segment at 0..0x100 and no sections
+    fn elf_stub() -> Vec<u8> {
+        let mut data = vec![0u8; 0x100];
+        data[..6].copy_from_slice(b"\x7fELF\x02\x01");
+        data[0x20..0x28].copy_from_slice(&0x40u64.to_le_bytes());
+        data[0x36..0x38].copy_from_slice(&0x38u16.to_le_bytes());
+        data[0x38..0x3a].copy_from_slice(&1u16.to_le_bytes());
+        data[0x40..0x44].copy_from_slice(&1u32.to_le_bytes());
+        data[0x60..0x68].copy_from_slice(&0x100u64.to_le_bytes());
+        data[0x90..0x96].copy_from_slice(b"Rar!\x1a\x07");
+        data
+    }
+
+    #[test]
+    fn payload_offset() -> Result<()> {
+        let mut pe = pe_stub();
+        assert_eq!(find_payload(&pe)?, None);
+        pe.extend(b";!@Install@!UTF-8!\n");
+        pe.extend(b"7z\xbc\xaf\x27\x1c\x00\x04");
+        assert_eq!(find_payload(&pe)?, Some((0x313, "7z")));
+
+        let mut elf = elf_stub();
+        assert_eq!(find_payload(&elf)?, None);
+        elf.extend(b"PK\x03\x04");
+        assert_eq!(find_payload(&elf)?, Some((0x100, "zip")));
+
+        assert!(find_payload(b"PK\x03\x04").is_err());
+        Ok(())
+    }
+}
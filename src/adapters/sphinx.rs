@@ -0,0 +1,270 @@
+use super::binary::one_line;
+use super::python::{unpickle, Pickled};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["inv", "doctree"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "sphinx".to_owned(),
+        version: 1,
+        description:
+            "Lists symbol -> document mappings of Sphinx object inventories (objects.inv) and the text, symbols and references of doctree pickles"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SphinxAdapter;
+
+impl SphinxAdapter {
+    pub fn new() -> SphinxAdapter {
+        SphinxAdapter
+    }
+}
+impl GetMetadata for SphinxAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// print the entries of a version 2 inventory, given the uncompressed part after the header
+fn dump_inventory(body: &str, s: &mut impl Write) -> Result<()> {
+    for line in body.lines() {
+        // name domain:role priority uri dispname, where the name may contain spaces
+        let mut parts = line.splitn(2, ' ');
+        let mut name = parts.next().unwrap_or_default().to_owned();
+        let mut rest = parts.next().unwrap_or_default();
+        while let Some((word, after)) = rest.split_once(' ') {
+            if word.contains(':')
+                && after
+                    .split(' ')
+                    .next()
+                    .is_some_and(|p| p.parse::<i32>().is_ok())
+            {
+                break;
+            }
+            name.push(' ');
+            name.push_str(word);
+            rest = after;
+        }
+        let fields: Vec<&str> = rest.splitn(4, ' ').collect();
+        if let [role, _priority, uri, dispname] = fields.as_slice() {
+            let uri = match uri.strip_suffix('$') {
+                Some(base) => format!("{base}{name}"),
+                None => uri.to_string(),
+            };
+            if *dispname == "-" || *dispname == name {
+                writeln!(s, "{name} ({role}) -> {uri}")?;
+            } else {
+                writeln!(s, "{name} ({role}) -> {uri} \"{dispname}\"")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// node types whose text is printed on separate lines
+const BLOCK_NODES: &[&str] = &[
+    "title",
+    "subtitle",
+    "paragraph",
+    "literal_block",
+    "doctest_block",
+    "line",
+    "term",
+    "entry",
+    "caption",
+    "rubric",
+    "desc_signature",
+    "field_name",
+];
+
+struct DoctreeWriter<'a, W: Write> {
+    s: &'a mut W,
+    line: String,
+}
+
+impl<'a, W: Write> DoctreeWriter<'a, W> {
+    fn flush(&mut self) -> Result<()> {
+        let line = self.line.trim();
+        if !line.is_empty() {
+            writeln!(self.s, "{line}")?;
+        }
+        self.line.clear();
+        Ok(())
+    }
+
+    fn walk(&mut self, node: &Pickled, depth: usize) -> Result<()> {
+        if depth > 500 {
+            return Ok(());
+        }
+        let (node, state) = match node {
+            Pickled::Build(node, state) => (node, state),
+            _ => return Ok(()),
+        };
+        let class = match node.as_ref() {
+            // docutils.nodes.Text is a str subclass, pickled as Text("content")
+            Pickled::Call(class, args) => {
+                if let [Pickled::Str(text)] = args.as_slice() {
+                    self.line.push_str(&text.replace('\n', " "));
+                    return Ok(());
+                }
+                class
+            }
+            _ => return Ok(()),
+        };
+        let tag = match class.as_ref() {
+            Pickled::Global(g) => g.rsplit('.').next().unwrap_or_default(),
+            _ => "",
+        };
+        let state = match state.as_ref() {
+            Pickled::Dict(d) => d,
+            _ => return Ok(()),
+        };
+        let get = |key: &str| {
+            state.iter().find_map(|(k, v)| match k {
+                Pickled::Str(k) if k == key => Some(v),
+                _ => None,
+            })
+        };
+        let attr = |key: &str| -> Vec<String> {
+            let attributes = match get("attributes") {
+                Some(Pickled::Dict(a)) => a,
+                _ => return vec![],
+            };
+            let value = attributes.iter().find_map(|(k, v)| match k {
+                Pickled::Str(k) if k == key => Some(v),
+                _ => None,
+            });
+            match value {
+                Some(Pickled::Str(s)) => vec![s.clone()],
+                Some(Pickled::List(l)) => l
+                    .iter()
+                    .filter_map(|v| match v {
+                        Pickled::Str(s) => Some(s.clone()),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => vec![],
+            }
+        };
+        let block = BLOCK_NODES.contains(&tag);
+        if block {
+            self.flush()?;
+        }
+        match tag {
+            "document" => {
+                for source in attr("source") {
+                    writeln!(self.s, "document: {}", one_line(source.as_bytes()))?;
+                }
+            }
+            "desc_signature" => {
+                for id in attr("ids") {
+                    writeln!(self.s, "symbol: {id}")?;
+                }
+            }
+            "pending_xref" => {
+                for target in attr("reftarget") {
+                    writeln!(self.s, "ref: {target}")?;
+                }
+            }
+            _ => {}
+        }
+        if let Some(Pickled::List(children)) = get("children") {
+            for child in children {
+                self.walk(child, depth + 1)?;
+            }
+        }
+        if block {
+            self.flush()?;
+        }
+        Ok(())
+    }
+}
+
+fn dump_doctree(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let tree = unpickle(data)?;
+    let mut w = DoctreeWriter {
+        s,
+        line: String::new(),
+    };
+    w.walk(&tree, 0)?;
+    w.flush()
+}
+
+#[async_trait]
+impl WritingFileAdapter for SphinxAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        if data.starts_with(b"# Sphinx inventory version 2") {
+            // four header lines, then a zlib stream
+            let body_start = data
+                .iter()
+                .enumerate()
+                .filter(|(_, b)| **b == b'\n')
+                .nth(3)
+                .map(|(i, _)| i + 1)
+                .ok_or_else(|| format_err!("truncated inventory header"))?;
+            buf.extend_from_slice(&data[..body_start]);
+            let mut body = Vec::new();
+            async_compression::tokio::bufread::ZlibDecoder::new(&data[body_start..])
+                .read_to_end(&mut body)
+                .await?;
+            dump_inventory(&String::from_utf8_lossy(&body), &mut buf)?;
+        } else if data.starts_with(b"# Sphinx inventory version 1") {
+            // uncompressed: name type module
+            buf.extend_from_slice(&data);
+        } else {
+            dump_doctree(&data, &mut buf)?;
+        }
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn inventory() -> Result<()> {
+        let body = "os.path.join py:function 1 library/os.path.html#$ -
+Sphinx inventory std:label -1 usage.html#sphinx-inv Sphinx Inventories
+genindex std:label -1 genindex.html Index
+";
+        let mut out = Vec::new();
+        dump_inventory(body, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "os.path.join (py:function) -> library/os.path.html#os.path.join
+Sphinx inventory (std:label) -> usage.html#sphinx-inv \"Sphinx Inventories\"
+genindex (std:label) -> genindex.html \"Index\"
+"
+        );
+        Ok(())
+    }
+}
@@ -5,7 +5,7 @@ use lazy_static::lazy_static;
 use log::*;
 use rusqlite::types::ValueRef;
 use rusqlite::*;
-use std::{convert::TryInto, io::Write};
+use std::{convert::TryInto, io::Write, path::Path};
 use tokio::io::AsyncWrite;
 
 use tokio_util::io::SyncIoBridge;
@@ -15,9 +15,9 @@ static EXTENSIONS: &[&str] = &["db", "db3", "sqlite", "sqlite3"];
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
         name: "sqlite".to_owned(),
-        version: 1,
+        version: 2,
         description:
-            "Uses sqlite bindings to convert sqlite databases into a simple plain text format"
+            "Uses sqlite bindings to convert sqlite databases into a simple plain text format, one line per row prefixed with the table and the rowid. Databases locked by a running program, like the history of a browser, are read as they are on disk"
                 .to_owned(),
         recurses: false, // set to true if we decide to make sqlite blobs searchable (gz blob in db is kinda common I think)
         fast_matchers: EXTENSIONS
@@ -28,7 +28,9 @@ lazy_static! {
             "application/x-sqlite3".to_owned()
         )]),
         keep_fast_matchers_if_accurate: false,
-        disabled_by_default: false
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Path
     };
 }
 
@@ -55,61 +57,110 @@ fn format_blob(b: ValueRef) -> String {
         Text(i) => format!("'{}'", String::from_utf8_lossy(i).replace('\'', "''")),
         Blob(b) => format!(
             "[blob {}B]",
-            size_format::SizeFormatterSI::new(
-                // can't be larger than 2GB anyways
-                b.len().try_into().unwrap()
-            )
+            // can't be larger than 2GB anyways
+            size_format::SizeFormatterSI::new(b.len() as u64)
         ),
     }
 }
 
+/// open the database as a `file:` URI, percent-encoding the characters that have a meaning in URIs
+fn open_read_only(path: &Path, immutable: bool) -> rusqlite::Result<Connection> {
+    let mut uri = String::from("file:");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            '?' | '#' | '%' => uri.push_str(&format!("%{:02X}", c as u32)),
+            c => uri.push(c),
+        }
+    }
+    if immutable {
+        // no locking, so programs that keep the database locked exclusively, like browsers, don't block us
+        uri.push_str("?immutable=1");
+    }
+    let conn = Connection::open_with_flags(
+        uri,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_URI,
+    )?;
+    // a lock is usually held for as long as the program runs, don't wait for it
+    conn.busy_timeout(std::time::Duration::ZERO)?;
+    Ok(conn)
+}
+
+fn table_names(conn: &Connection) -> rusqlite::Result<Vec<String>> {
+    conn.prepare("select name from sqlite_master where type='table'")?
+        .query_map([], |r| r.get::<_, String>(0))?
+        .collect()
+}
+
+fn is_locked(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked)
+    )
+}
+
 fn synchronous_dump_sqlite(ai: AdaptInfo, mut s: impl Write) -> Result<()> {
+    // members of archives are spooled to a temporary file first (InputKind::Path)
     let AdaptInfo {
-        is_real_file,
         filepath_hint,
         line_prefix,
         ..
     } = ai;
-    if !is_real_file {
-        // db is in an archive
-        // todo: read to memory and then use that blob if size < max
-        writeln!(s, "{line_prefix}[rga: skipping sqlite in archive]",)?;
-        return Ok(());
-    }
     let inp_fname = filepath_hint;
-    let conn = Connection::open_with_flags(&inp_fname, OpenFlags::SQLITE_OPEN_READ_ONLY)
-        .with_context(|| format!("opening sqlite connection to {}", inp_fname.display()))?;
-    let tables: Vec<String> = conn
-        .prepare("select name from sqlite_master where type='table'")
-        .context("while preparing query")?
-        .query_map([], |r| r.get::<_, String>(0))
-        .context("while executing query")?
-        .filter_map(|e| e.ok())
-        .collect();
+    let open = |immutable| -> rusqlite::Result<_> {
+        let conn = open_read_only(&inp_fname, immutable)?;
+        let tables = table_names(&conn)?;
+        Ok((conn, tables))
+    };
+    let (conn, tables) = match open(false) {
+        Err(e) if is_locked(&e) => {
+            debug!("database is locked ({}), reading it without locking", e);
+            open(true)
+        }
+        r => r,
+    }
+    .with_context(|| format!("opening sqlite connection to {}", inp_fname.display()))?;
     debug!("db has {} tables", tables.len());
     for table in tables {
         // can't use query param at that position
-        let mut sel = conn.prepare(&format!(
-            "select * from {}",
-            rusqlite::vtab::escape_double_quote(&table)
-        ))?;
+        let table_sql = rusqlite::vtab::escape_double_quote(&table);
+        // tables created WITHOUT ROWID have none, their rows are numbered instead
+        let (mut sel, has_rowid) =
+            match conn.prepare(&format!("select rowid, * from \"{table_sql}\"")) {
+                Ok(sel) => (sel, true),
+                Err(_) => (
+                    conn.prepare(&format!("select * from \"{table_sql}\""))?,
+                    false,
+                ),
+            };
+        let first_col = usize::from(has_rowid);
         let col_names: Vec<String> = sel
             .column_names()
             .into_iter()
+            .skip(first_col)
             .map(|e| e.to_owned())
             .collect();
         let mut z = sel.query([])?;
-        // writeln!(oup, "{}: {}", table, cols.join(", "))?;
-
-        // kind of shitty (lossy) output. maybe output real csv or something?
+        let mut row_number = 0;
         while let Some(row) = z.next()? {
+            row_number += 1;
+            let rowid = if has_rowid {
+                format_blob(row.get_ref(0)?)
+            } else {
+                row_number.to_string()
+            };
             let row_str = col_names
                 .iter()
                 .enumerate()
-                .map(|(i, e)| Ok(format!("{}={}", e, format_blob(row.get_ref(i)?))))
+                .map(|(i, e)| {
+                    Ok(format!(
+                        "{}={}",
+                        e,
+                        format_blob(row.get_ref(first_col + i)?)
+                    ))
+                })
                 .collect::<Result<Vec<String>>>()?
-                .join(", ");
-            writeln!(s, "{line_prefix}{table}: {row_str}",)?;
+                .join(" ");
+            writeln!(s, "{line_prefix}{table}:{rowid}: {row_str}",)?;
         }
     }
     Ok(())
@@ -147,9 +198,30 @@ mod test {
 
         assert_eq!(
             String::from_utf8(buf)?,
-            "PREFIX:tbl: greeting='hello', from='sqlite database!'\nPREFIX:tbl2: x=123, y=456.789\n",
+            "PREFIX:tbl:1: greeting='hello' from='sqlite database!'\nPREFIX:tbl2:1: x=123 y=456.789\n",
         );
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn locked_and_without_rowid() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("places?.sqlite");
+        let conn = Connection::open(&fname)?;
+        conn.execute_batch(
+            "create table kv (k text primary key, v text) without rowid;
+            insert into kv values ('a', 'first'), ('b', 'second');
+            pragma locking_mode = exclusive;
+            begin exclusive;",
+        )?;
+        let (a, d) = simple_fs_adapt_info(&fname).await?;
+        let res = SqliteAdapter::default().adapt(a, &d).await?;
+        assert_eq!(
+            String::from_utf8(adapted_to_vec(res).await?)?,
+            "PREFIX:kv:1: k='a' v='first'\nPREFIX:kv:2: k='b' v='second'\n",
+        );
+        drop(conn);
+        Ok(())
+    }
 }
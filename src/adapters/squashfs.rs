@@ -0,0 +1,175 @@
+use super::custom::map_exe_error;
+use super::rar::extracted_files;
+use super::sfx::elf_overlay;
+use super::*;
+use crate::policy;
+use crate::vpath::member_prefix;
+use anyhow::{bail, Context, Result};
+use async_stream::stream;
+use lazy_static::lazy_static;
+use log::*;
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+
+static EXTENSIONS: &[&str] = &["squashfs", "sqfs", "sfs", "snap", "appimage"];
+
+/// the AppImage runtime in front of the image is about 200 KB
+const MAX_RUNTIME_LEN: u64 = 8 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "squashfs".to_owned(),
+        version: 1,
+        description:
+            "Uses unsquashfs to extract SquashFS images (firmware, snap packages and AppImages) and recurses down into their contents"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/vnd.squashfs".to_owned()),
+            FileMatcher::MimeType("application/vnd.snap".to_owned()),
+            FileMatcher::MimeType("application/vnd.appimage".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // unsquashfs reads the tables at the end of the image first
+        input: InputKind::Path
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct SquashfsAdapter;
+
+impl SquashfsAdapter {
+    pub fn new() -> SquashfsAdapter {
+        SquashfsAdapter
+    }
+}
+impl GetMetadata for SquashfsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// where the image starts: 0, or after the ELF runtime of an AppImage
+fn image_offset(head: &[u8]) -> Result<usize> {
+    if head.starts_with(b"hsqs") {
+        return Ok(0);
+    }
+    if !head.starts_with(b"\x7fELF") {
+        bail!("not a SquashFS image or AppImage");
+    }
+    let offset = elf_overlay(head)?;
+    if head.get(offset..offset + 4) != Some(&b"hsqs"[..]) {
+        bail!("no SquashFS image after the executable, not a type 2 AppImage");
+    }
+    Ok(offset)
+}
+
+#[async_trait]
+impl FileAdapter for SquashfsAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let mut head = Vec::new();
+        tokio::fs::File::open(&filepath_hint)
+            .await?
+            .take(MAX_RUNTIME_LEN)
+            .read_to_end(&mut head)
+            .await?;
+        let offset = image_offset(&head)?;
+        drop(head);
+        let dir = tempfile::tempdir().context("creating temporary directory")?;
+        // unsquashfs creates the destination itself
+        let root = dir.path().join("root");
+        let mut cmd = policy::command(&config.policy, "unsquashfs", &filepath_hint)?;
+        cmd.args(["-n", "-no-xattrs"]);
+        if offset > 0 {
+            // -o needs unsquashfs 4.4
+            cmd.arg("-o").arg(offset.to_string());
+        }
+        let out = cmd
+            .arg("-d")
+            .arg(&root)
+            .arg(&filepath_hint)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .map_err(|e| map_exe_error(e, "unsquashfs", "Install squashfs-tools."))?;
+        let mut files = Vec::new();
+        if root.exists() {
+            extracted_files(&root, "", &mut files)?;
+        }
+        if !out.status.success() {
+            let stderr = String::from_utf8_lossy(&out.stderr);
+            let error = stderr.trim().lines().last().unwrap_or_default().to_owned();
+            // device files can't be created without root, the regular files are still searched
+            if files.is_empty() {
+                bail!("unsquashfs failed ({}): {}", out.status, error);
+            }
+            warn!(
+                "{}: unsquashfs failed ({}): {}",
+                filepath_hint.display(),
+                out.status,
+                error
+            );
+        }
+        let s = stream! {
+            // the extracted files are read while the members are searched
+            let _dir = dir;
+            for (path, member) in files {
+                yield Ok(AdaptInfo {
+                    filepath_hint: PathBuf::from(&member),
+                    is_real_file: false,
+                    archive_recursion_depth: archive_recursion_depth + 1,
+                    inp: Box::pin(tokio::fs::File::open(&path).await?),
+                    line_prefix: member_prefix(&line_prefix, &member),
+                    config: config.clone(),
+                    postprocess,
+                });
+            }
+        };
+        Ok(Box::pin(s))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// a 64-bit ELF header without segments and sections, followed by `rest`
+    fn elf(rest: &[u8]) -> Vec<u8> {
+        let mut data = b"\x7fELF\x02\x01\x01".to_vec();
+        data.resize(64, 0);
+        // e_phoff and e_shoff: the tables are empty, right after the header
+        data[0x20] = 64;
+        data[0x28] = 64;
+        data.extend_from_slice(rest);
+        data
+    }
+
+    #[test]
+    fn offsets() -> Result<()> {
+        assert_eq!(image_offset(b"hsqs\x10\0\0\0")?, 0);
+        assert_eq!(image_offset(&elf(b"hsqs\x10\0\0\0"))?, 64);
+        assert!(image_offset(&elf(b"PK\x03\x04")).is_err());
+        assert!(image_offset(b"sqsh").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use super::binary::entropy;
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "strings".to_owned(),
+        version: 1,
+        description:
+            "Carves printable ASCII and UTF-16 strings with their hex offsets out of binary files no other adapter matches (like `strings -t x`). Only used with --rga-accurate"
+                .to_owned(),
+        recurses: false,
+        // never matched directly, see preproc::choose_adapter
+        fast_matchers: vec![],
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: false,
+        disabled_by_default: true,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct StringsAdapter;
+
+impl StringsAdapter {
+    pub fn new() -> StringsAdapter {
+        StringsAdapter
+    }
+}
+impl GetMetadata for StringsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// shorter runs of printable characters are mostly noise in binary data
+const MIN_LEN: usize = 6;
+/// longer strings are split so a single line can't grow without bounds
+const MAX_LEN: usize = 4096;
+/// the entropy is measured per block of this size
+const BLOCK_SIZE: usize = 64 * 1024;
+/// blocks above this entropy (in bits per byte) are likely compressed or encrypted and not scanned
+const MAX_ENTROPY: f64 = 7.5;
+
+fn printable(b: u8) -> bool {
+    b == b'\t' || (0x20..0x7f).contains(&b)
+}
+
+#[derive(Default)]
+struct Run {
+    start: u64,
+    text: Vec<u8>,
+}
+
+impl Run {
+    fn push(&mut self, offset: u64, b: u8, s: &mut impl Write) -> Result<()> {
+        if self.text.is_empty() {
+            self.start = offset;
+        }
+        self.text.push(b);
+        if self.text.len() >= MAX_LEN {
+            self.flush(s)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self, s: &mut impl Write) -> Result<()> {
+        if self.text.len() >= MIN_LEN {
+            write!(s, "{:08x} ", self.start)?;
+            s.write_all(&self.text)?;
+            s.write_all(b"\n")?;
+        }
+        self.text.clear();
+        Ok(())
+    }
+}
+
+/// Streaming string extraction. UTF-16LE strings are tracked separately for
+/// even and odd offsets, since they can start anywhere
+#[derive(Default)]
+struct Carver {
+    offset: u64,
+    prev: Option<u8>,
+    ascii: Run,
+    utf16: [Run; 2],
+    /// start offset and highest entropy of consecutive skipped blocks
+    skipped: Option<(u64, f64)>,
+}
+
+impl Carver {
+    fn feed(&mut self, data: &[u8], s: &mut impl Write) -> Result<()> {
+        for &b in data {
+            if printable(b) {
+                self.ascii.push(self.offset, b, s)?;
+            } else {
+                self.ascii.flush(s)?;
+            }
+            // the code unit that started at the previous byte
+            if let Some(prev) = self.prev {
+                let start = self.offset - 1;
+                let run = &mut self.utf16[(start % 2) as usize];
+                if b == 0 && printable(prev) {
+                    run.push(start, prev, s)?;
+                } else {
+                    run.flush(s)?;
+                }
+            }
+            self.prev = Some(b);
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    /// like feed, but skips the block if it looks like random data
+    fn feed_block(&mut self, block: &[u8], s: &mut impl Write) -> Result<()> {
+        let e = entropy(block);
+        if e > MAX_ENTROPY {
+            self.flush_runs(s)?;
+            let (start, max) = self.skipped.unwrap_or((self.offset, e));
+            self.skipped = Some((start, max.max(e)));
+            self.offset += block.len() as u64;
+            self.prev = None;
+            return Ok(());
+        }
+        self.end_skip(s)?;
+        self.feed(block, s)
+    }
+
+    fn end_skip(&mut self, s: &mut impl Write) -> Result<()> {
+        if let Some((start, e)) = self.skipped.take() {
+            writeln!(
+                s,
+                "[rga: skipped 0x{start:x}-0x{:x}, likely compressed or encrypted (entropy {e:.2} bits/byte)]",
+                self.offset
+            )?;
+        }
+        Ok(())
+    }
+
+    fn flush_runs(&mut self, s: &mut impl Write) -> Result<()> {
+        self.ascii.flush(s)?;
+        for run in &mut self.utf16 {
+            run.flush(s)?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self, s: &mut impl Write) -> Result<()> {
+        self.flush_runs(s)?;
+        self.end_skip(s)
+    }
+}
+
+#[async_trait]
+impl WritingFileAdapter for StringsAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut carver = Carver::default();
+        let mut block = Vec::with_capacity(BLOCK_SIZE);
+        let mut buf = Vec::new();
+        loop {
+            block.clear();
+            (&mut inp)
+                .take(BLOCK_SIZE as u64)
+                .read_to_end(&mut block)
+                .await?;
+            if block.is_empty() {
+                break;
+            }
+            carver.feed_block(&block, &mut buf)?;
+            oup.write_all(&buf).await?;
+            buf.clear();
+        }
+        carver.finish(&mut buf)?;
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn carve() -> Result<()> {
+        let mut data = vec![0xff, 0x00, 0x13];
+        data.extend(b"U-Boot 2019.07\0");
+        data.extend([0x90; 5]);
+        data.extend(b"short\0");
+        data.push(0x7f);
+        for c in "Windows".encode_utf16() {
+            data.extend(c.to_le_bytes());
+        }
+        data.extend([0, 0, 0xee]);
+        data.extend(b"tail-string");
+        let mut out = Vec::new();
+        let mut carver = Carver::default();
+        // split in the middle of strings to check state is kept across chunks
+        for chunk in data.chunks(5) {
+            carver.feed(chunk, &mut out)?;
+        }
+        carver.finish(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "00000003 U-Boot 2019.07
+0000001e Windows
+0000002f tail-string
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn skips_random_blocks() -> Result<()> {
+        // xorshift, as a stand-in for compressed data
+        let mut x = 0x2545f4914f6cdd1du64;
+        let random: Vec<u8> = (0..2 * BLOCK_SIZE)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x as u8
+            })
+            .collect();
+        let mut out = Vec::new();
+        let mut carver = Carver::default();
+        carver.feed_block(&random[..BLOCK_SIZE], &mut out)?;
+        carver.feed_block(&random[BLOCK_SIZE..], &mut out)?;
+        carver.feed_block(
+            b"firmware version 1.2.3\0firmware version 1.2.3\0",
+            &mut out,
+        )?;
+        carver.finish(&mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "[rga: skipped 0x0-0x20000, likely compressed or encrypted (entropy 8.00 bits/byte)]
+00020000 firmware version 1.2.3
+00020017 firmware version 1.2.3
+"
+        );
+        Ok(())
+    }
+}
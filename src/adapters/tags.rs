@@ -0,0 +1,618 @@
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::meta::{id3_frames, id3_text};
+use anyhow::{bail, Result};
+use lazy_static::lazy_static;
+use std::io::Cursor;
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+static EXTENSIONS: &[&str] = &["mp3", "flac", "ogg", "oga", "opus", "m4a"];
+
+/// larger ID3 tags, comment packets and moov atoms are skipped instead of read into memory. Cover
+/// images can make tags a few MB large
+const MAX_TAG_LEN: usize = 32 * 1024 * 1024;
+
+/// the ID3v2 frames, Vorbis comments and MP4 items with the tags, by the names they are output with
+static ID3_FRAMES: &[(&[u8], &str)] = &[
+    (b"TIT2", "title"),
+    (b"TPE1", "artist"),
+    (b"TALB", "album"),
+    (b"TPE2", "album artist"),
+    (b"TCON", "genre"),
+    (b"TDRC", "date"),
+    (b"TYER", "date"),
+    (b"USLT", "lyrics"),
+    (b"COMM", "comment"),
+];
+static VORBIS_FIELDS: &[(&str, &str)] = &[
+    ("TITLE", "title"),
+    ("ARTIST", "artist"),
+    ("ALBUM", "album"),
+    ("ALBUMARTIST", "album artist"),
+    ("GENRE", "genre"),
+    ("DATE", "date"),
+    ("LYRICS", "lyrics"),
+    ("UNSYNCEDLYRICS", "lyrics"),
+    ("COMMENT", "comment"),
+    ("DESCRIPTION", "comment"),
+];
+static MP4_ITEMS: &[(&[u8], &str)] = &[
+    (b"\xa9nam", "title"),
+    (b"\xa9ART", "artist"),
+    (b"\xa9alb", "album"),
+    (b"aART", "album artist"),
+    (b"\xa9gen", "genre"),
+    (b"\xa9day", "date"),
+    (b"\xa9lyr", "lyrics"),
+    (b"\xa9cmt", "comment"),
+];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "tags".to_owned(),
+        version: 1,
+        description:
+            "Outputs the title, artist, album, genre, date, lyrics and comment tags of music files (ID3 in mp3, Vorbis comments in flac, ogg and opus, and the metadata items of m4a) as lines like \"artist: value\", without external programs. ffmpeg is preferred for the formats it reads, since it also extracts the other metadata"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("audio/mpeg".to_owned()),
+            FileMatcher::MimeType("audio/flac".to_owned()),
+            FileMatcher::MimeType("audio/ogg".to_owned()),
+            FileMatcher::MimeType("audio/mp4".to_owned()),
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct TagsAdapter;
+
+impl TagsAdapter {
+    pub fn new() -> TagsAdapter {
+        TagsAdapter
+    }
+}
+impl GetMetadata for TagsAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// a tag name from the tables above and its value
+type Tag = (&'static str, String);
+
+fn lookup<K: PartialEq + ?Sized>(
+    table: &[(&'static K, &'static str)],
+    key: &K,
+) -> Option<&'static str> {
+    table.iter().find(|(k, _)| *k == key).map(|(_, name)| *name)
+}
+
+/// the next `len` bytes of the input, None at its end
+async fn read_vec(inp: &mut (impl AsyncRead + Unpin), len: usize) -> Result<Option<Vec<u8>>> {
+    let mut buf = Vec::with_capacity(len.min(MAX_TAG_LEN));
+    (&mut *inp).take(len as u64).read_to_end(&mut buf).await?;
+    if buf.len() < len {
+        return Ok(None);
+    }
+    Ok(Some(buf))
+}
+
+async fn skip(inp: &mut (impl AsyncRead + Unpin), len: u64) -> Result<()> {
+    tokio::io::copy(&mut (&mut *inp).take(len), &mut tokio::io::sink()).await?;
+    Ok(())
+}
+
+/// the text of a USLT (lyrics) or COMM (comment) frame: an encoding byte, a language, a description
+/// ending with NUL and the text. Returns the description and the text
+fn id3_described_text(frame: &[u8]) -> Option<(String, String)> {
+    let encoding = *frame.first()?;
+    let rest = frame.get(4..)?;
+    let (end, terminator) = if matches!(encoding, 1 | 2) {
+        (rest.chunks_exact(2).position(|c| c == [0, 0])? * 2, 2)
+    } else {
+        (rest.iter().position(|&b| b == 0)?, 1)
+    };
+    let description = id3_text(&[&[encoding][..], &rest[..end]].concat())?;
+    let text = id3_text(&[&[encoding][..], &rest[end + terminator..]].concat())?;
+    Some((description, text))
+}
+
+fn id3v2_tags(tag: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    for (id, frame) in id3_frames(tag) {
+        let name = match lookup(ID3_FRAMES, id) {
+            Some(name) => name,
+            None => continue,
+        };
+        let text = match id {
+            b"USLT" | b"COMM" => match id3_described_text(frame) {
+                // iTunes stores its loudness and gapless playback data in comments
+                Some((description, _)) if description.starts_with("iTun") => continue,
+                Some((_, text)) => text,
+                None => continue,
+            },
+            _ => match id3_text(frame) {
+                Some(text) => text,
+                None => continue,
+            },
+        };
+        tags.push((name, text));
+    }
+    tags
+}
+
+/// the ID3v1 tag in the last 128 bytes of mp3 files without an ID3v2 tag
+fn id3v1_tags(tail: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    if tail.len() < 128 || !tail[tail.len() - 128..].starts_with(b"TAG") {
+        return tags;
+    }
+    let tag = &tail[tail.len() - 128..];
+    let field = |range: std::ops::Range<usize>| -> String {
+        tag[range]
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| char::from(b))
+            .collect::<String>()
+            .trim()
+            .to_owned()
+    };
+    for (name, range) in [
+        ("title", 3..33),
+        ("artist", 33..63),
+        ("album", 63..93),
+        ("date", 93..97),
+        ("comment", 97..127),
+    ] {
+        let value = field(range);
+        if !value.is_empty() {
+            tags.push((name, value));
+        }
+    }
+    tags
+}
+
+/// a Vorbis comment header as in flac and ogg files: the vendor, then `NAME=value` comments
+fn vorbis_comments(data: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    let u32_at = |pos: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize)
+    };
+    // the strings and the number of comments are preceded by their length
+    let vendor_len = match u32_at(0) {
+        Some(len) => len,
+        None => return tags,
+    };
+    let mut pos = 4 + vendor_len;
+    let count = match u32_at(pos) {
+        Some(count) => count,
+        None => return tags,
+    };
+    pos += 4;
+    for _ in 0..count {
+        let comment = match u32_at(pos).and_then(|len| data.get(pos + 4..pos + 4 + len)) {
+            Some(comment) => comment,
+            None => break,
+        };
+        pos += 4 + comment.len();
+        let comment = String::from_utf8_lossy(comment);
+        if let Some((key, value)) = comment.split_once('=') {
+            if let Some(name) = lookup(VORBIS_FIELDS, key.to_ascii_uppercase().as_str()) {
+                tags.push((name, value.to_owned()));
+            }
+        }
+    }
+    tags
+}
+
+/// the atoms in the content of an MP4 atom, as their type and content
+pub(crate) fn mp4_atoms(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut atoms = vec![];
+    let mut pos = 0;
+    while pos + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        let (header, size) = match size {
+            0 => (8, data.len() - pos),
+            1 => match data.get(pos + 8..pos + 16) {
+                Some(size) => (16, u64::from_be_bytes(size.try_into().unwrap()) as usize),
+                None => break,
+            },
+            size => (8, size),
+        };
+        if size < header || size > data.len() - pos {
+            break;
+        }
+        atoms.push((&data[pos + 4..pos + 8], &data[pos + header..pos + size]));
+        pos += size;
+    }
+    atoms
+}
+
+/// the content of the first atom of a type
+fn mp4_atom<'a>(data: &'a [u8], ty: &[u8]) -> Option<&'a [u8]> {
+    mp4_atoms(data)
+        .into_iter()
+        .find(|(t, _)| *t == ty)
+        .map(|(_, content)| content)
+}
+
+/// the metadata items in moov/udta/meta/ilst of m4a files
+fn mp4_tags(moov: &[u8]) -> Vec<Tag> {
+    let mut tags = vec![];
+    let meta = match mp4_atom(moov, b"udta").and_then(|udta| mp4_atom(udta, b"meta")) {
+        Some(meta) => meta,
+        None => return tags,
+    };
+    // meta has a version and flags before its atoms, except in some QuickTime files
+    let meta = match meta.get(..4) {
+        Some([0, 0, 0, 0]) => &meta[4..],
+        _ => meta,
+    };
+    let ilst = match mp4_atom(meta, b"ilst") {
+        Some(ilst) => ilst,
+        None => return tags,
+    };
+    for (ty, item) in mp4_atoms(ilst) {
+        let name = match lookup(MP4_ITEMS, ty) {
+            Some(name) => name,
+            None => continue,
+        };
+        for (ty, data) in mp4_atoms(item) {
+            // the data type 1 is UTF-8 text, the value comes after the type and the locale
+            if ty == b"data" && data.len() >= 8 && data[..4] == [0, 0, 0, 1] {
+                tags.push((name, String::from_utf8_lossy(&data[8..]).into_owned()));
+            }
+        }
+    }
+    tags
+}
+
+async fn read_id3v2(inp: &mut (impl AsyncRead + Unpin), mut tag: Vec<u8>) -> Result<Vec<Tag>> {
+    let syncsafe = |b: &[u8]| b.iter().fold(0, |n, &b| n << 7 | usize::from(b & 0x7F));
+    let mut header = match read_vec(inp, 2).await? {
+        Some(rest) => rest,
+        None => return Ok(vec![]),
+    };
+    tag.append(&mut header);
+    let len = syncsafe(&tag[6..10]);
+    if len > MAX_TAG_LEN {
+        return Ok(vec![]);
+    }
+    match read_vec(inp, len).await? {
+        Some(mut frames) => {
+            tag.append(&mut frames);
+            Ok(id3v2_tags(&tag))
+        }
+        None => Ok(vec![]),
+    }
+}
+
+/// the ID3v1 tag at the end of the input
+async fn read_id3v1(inp: &mut (impl AsyncRead + Unpin), head: Vec<u8>) -> Result<Vec<Tag>> {
+    let mut tail = head;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = inp.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > 128 {
+            tail.drain(..tail.len() - 128);
+        }
+    }
+    Ok(id3v1_tags(&tail))
+}
+
+async fn read_flac(inp: &mut (impl AsyncRead + Unpin)) -> Result<Vec<Tag>> {
+    loop {
+        let header = match read_vec(inp, 4).await? {
+            Some(header) => header,
+            None => return Ok(vec![]),
+        };
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        // block type 4 is VORBIS_COMMENT
+        if header[0] & 0x7F == 4 {
+            return Ok(match read_vec(inp, len).await? {
+                Some(block) => vorbis_comments(&block),
+                None => vec![],
+            });
+        }
+        if header[0] & 0x80 != 0 {
+            // the last metadata block
+            return Ok(vec![]);
+        }
+        skip(inp, len as u64).await?;
+    }
+}
+
+/// the comment header of Vorbis and Opus streams in ogg files, the second packet of the stream
+async fn read_ogg(inp: &mut (impl AsyncRead + Unpin), mut header: Vec<u8>) -> Result<Vec<Tag>> {
+    let mut packets = 0;
+    let mut packet = vec![];
+    loop {
+        match read_vec(inp, 27 - header.len()).await? {
+            Some(mut rest) => header.append(&mut rest),
+            None => return Ok(vec![]),
+        }
+        if !header.starts_with(b"OggS") {
+            bail!("invalid ogg page");
+        }
+        let segments = match read_vec(inp, usize::from(header[26])).await? {
+            Some(segments) => segments,
+            None => return Ok(vec![]),
+        };
+        let body_len = segments.iter().map(|&s| usize::from(s)).sum();
+        let body = match read_vec(inp, body_len).await? {
+            Some(body) => body,
+            None => return Ok(vec![]),
+        };
+        let mut pos = 0;
+        for &len in &segments {
+            packet.extend_from_slice(&body[pos..pos + usize::from(len)]);
+            pos += usize::from(len);
+            if packet.len() > MAX_TAG_LEN {
+                return Ok(vec![]);
+            }
+            // a segment shorter than 255 bytes ends a packet
+            if len < 255 {
+                packets += 1;
+                if packets == 2 {
+                    for prefix in [&b"\x03vorbis"[..], b"OpusTags"] {
+                        if let Some(comments) = packet.strip_prefix(prefix) {
+                            return Ok(vorbis_comments(comments));
+                        }
+                    }
+                    return Ok(vec![]);
+                }
+                packet.clear();
+            }
+        }
+        header = vec![];
+    }
+}
+
+async fn read_mp4(inp: &mut (impl AsyncRead + Unpin), mut header: Vec<u8>) -> Result<Vec<Tag>> {
+    loop {
+        let size = u64::from(u32::from_be_bytes(header[..4].try_into().unwrap()));
+        let (header_len, size) = match size {
+            // the atom goes to the end of the file
+            0 => return Ok(vec![]),
+            1 => match read_vec(inp, 8).await? {
+                Some(size) => (16, u64::from_be_bytes(size[..].try_into().unwrap())),
+                None => return Ok(vec![]),
+            },
+            size => (8, size),
+        };
+        if size < header_len {
+            return Ok(vec![]);
+        }
+        let len = size - header_len;
+        if &header[4..8] == b"moov" {
+            if len > MAX_TAG_LEN as u64 {
+                return Ok(vec![]);
+            }
+            return Ok(match read_vec(inp, len as usize).await? {
+                Some(moov) => mp4_tags(&moov),
+                None => vec![],
+            });
+        }
+        skip(inp, len).await?;
+        header = match read_vec(inp, 8).await? {
+            Some(header) => header,
+            None => return Ok(vec![]),
+        };
+    }
+}
+
+/// the tags of a music file, recognized by its content
+async fn read_tags(inp: &mut (impl AsyncRead + Unpin)) -> Result<Vec<Tag>> {
+    let head = match read_vec(inp, 8).await? {
+        Some(head) => head,
+        None => return Ok(vec![]),
+    };
+    if head.starts_with(b"ID3") {
+        read_id3v2(inp, head).await
+    } else if head.starts_with(b"fLaC") {
+        // the first metadata block header starts in the head
+        let mut inp = Cursor::new(head[4..].to_vec()).chain(inp);
+        read_flac(&mut inp).await
+    } else if head.starts_with(b"OggS") {
+        read_ogg(inp, head).await
+    } else if &head[4..] == b"ftyp" {
+        read_mp4(inp, head).await
+    } else {
+        read_id3v1(inp, head).await
+    }
+}
+
+/// one line per tag, and per line of multi-line tags like lyrics
+fn tag_lines(tags: &[Tag]) -> String {
+    let mut out = String::new();
+    for (name, value) in tags {
+        for line in value.replace("\r\n", "\n").replace('\r', "\n").lines() {
+            let line = line.trim();
+            if !line.is_empty() {
+                out.push_str(&format!("{name}: {line}\n"));
+            }
+        }
+    }
+    out
+}
+
+#[async_trait]
+impl FileAdapter for TagsAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            mut inp,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let text = tag_lines(&read_tags(&mut inp).await?);
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn owned(tags: &[(&'static str, &str)]) -> Vec<Tag> {
+        tags.iter().map(|(n, v)| (*n, v.to_string())).collect()
+    }
+
+    fn vorbis_comment_header(comments: &[&str]) -> Vec<u8> {
+        let mut data = 3u32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"rga");
+        data.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+        for comment in comments {
+            data.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+            data.extend_from_slice(comment.as_bytes());
+        }
+        data
+    }
+
+    fn atom(ty: &[u8], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend_from_slice(ty);
+        atom.extend_from_slice(content);
+        atom
+    }
+
+    #[tokio::test]
+    async fn id3() -> Result<()> {
+        let mut frames = Vec::new();
+        for (id, text) in [
+            (b"TIT2", &b"\x03Song"[..]),
+            (b"COMM", b"\x00engiTunNORM\0 000001"),
+            (b"USLT", b"\x00eng\0First line\r\nSecond line"),
+            (b"COMM", b"\x03eng\0Live recording"),
+        ] {
+            frames.extend_from_slice(id);
+            frames.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            frames.extend_from_slice(&[0, 0]);
+            frames.extend_from_slice(text);
+        }
+        let mut data = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        data.push(frames.len() as u8);
+        data.extend_from_slice(&frames);
+        data.extend_from_slice(&[0xFF; 1000]);
+        assert_eq!(
+            tag_lines(&read_tags(&mut &data[..]).await?),
+            "title: Song\nlyrics: First line\nlyrics: Second line\ncomment: Live recording\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn id3v1() -> Result<()> {
+        let mut data = vec![0xFF; 100_000];
+        let mut tag = b"TAG".to_vec();
+        for (value, len) in [("Song", 30), ("Band", 30), ("", 30), ("1999", 4), ("", 31)] {
+            tag.extend_from_slice(value.as_bytes());
+            tag.resize(tag.len() + len - value.len(), 0);
+        }
+        data.extend_from_slice(&tag);
+        assert_eq!(
+            read_tags(&mut &data[..]).await?,
+            owned(&[("title", "Song"), ("artist", "Band"), ("date", "1999")])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn flac() -> Result<()> {
+        let mut data = b"fLaC\x00\x00\x00\x22".to_vec();
+        data.extend_from_slice(&[0; 0x22]);
+        let comments = vorbis_comment_header(&["title=Song", "ARTIST=Band", "TRACKNUMBER=3"]);
+        data.push(0x84);
+        data.extend_from_slice(&(comments.len() as u32).to_be_bytes()[1..]);
+        data.extend_from_slice(&comments);
+        assert_eq!(
+            read_tags(&mut &data[..]).await?,
+            owned(&[("title", "Song"), ("artist", "Band")])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn ogg() -> Result<()> {
+        let mut comments = b"OpusTags".to_vec();
+        comments.extend_from_slice(&vorbis_comment_header(&["ALBUM=Live", "LYRICS=la la"]));
+        let page = |packets: &[&[u8]]| {
+            let mut page = b"OggS".to_vec();
+            page.resize(26, 0);
+            let mut segments = vec![];
+            for packet in packets {
+                segments.extend(std::iter::repeat(255).take(packet.len() / 255));
+                segments.push((packet.len() % 255) as u8);
+            }
+            page.push(segments.len() as u8);
+            page.extend_from_slice(&segments);
+            page.extend_from_slice(&packets.concat());
+            page
+        };
+        let mut data = page(&[&b"OpusHead\x01\x02"[..]]);
+        data.extend_from_slice(&page(&[&comments[..]]));
+        assert_eq!(
+            read_tags(&mut &data[..]).await?,
+            owned(&[("album", "Live"), ("lyrics", "la la")])
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn m4a() -> Result<()> {
+        let item = |ty: &[u8], value: &str| {
+            let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+            data.extend_from_slice(value.as_bytes());
+            atom(ty, &atom(b"data", &data))
+        };
+        let ilst = [item(b"\xa9nam", "Song"), item(b"\xa9too", "Lavf")].concat();
+        let meta = [
+            &[0, 0, 0, 0][..],
+            &atom(b"hdlr", &[0; 25]),
+            &atom(b"ilst", &ilst),
+        ]
+        .concat();
+        let moov = atom(b"moov", &atom(b"udta", &atom(b"meta", &meta)));
+        let data = [
+            atom(b"ftyp", b"M4A \0\0\0\0"),
+            atom(b"mdat", &[0xFF; 5000]),
+            moov,
+        ]
+        .concat();
+        assert_eq!(
+            read_tags(&mut &data[..]).await?,
+            owned(&[("title", "Song")])
+        );
+        Ok(())
+    }
+}
@@ -3,6 +3,7 @@ use crate::{
     adapters::AdapterMeta,
     matching::{FastFileMatcher, FileMatcher},
     print_bytes,
+    vpath::member_prefix,
 };
 use anyhow::*;
 use async_stream::stream;
@@ -29,7 +30,9 @@ lazy_static! {
             .collect(),
         slow_matchers: None,
         keep_fast_matchers_if_accurate: true,
-        disabled_by_default: false
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
     };
 }
 #[derive(Default, Clone)]
@@ -76,7 +79,7 @@ impl FileAdapter for TarAdapter {
                         path.display(),
                         print_bytes(file.header().size().unwrap_or(0) as f64),
                     );
-                    let line_prefix = &format!("{}{}: ", line_prefix, path.display());
+                    let line_prefix = &member_prefix(&line_prefix, &path.to_string_lossy());
                     let ai2: AdaptInfo = AdaptInfo {
                         filepath_hint: path,
                         is_real_file: false,
@@ -113,18 +116,18 @@ mod tests {
         let o = adapted_to_vec(r).await.context("adapted_to_vec")?;
         assert_eq!(
             String::from_utf8(o).context("parsing utf8")?,
-            "PREFIX:dir/file-b.pdf: Page 1: hello world
-PREFIX:dir/file-b.pdf: Page 1: this is just a test.
-PREFIX:dir/file-b.pdf: Page 1: 
-PREFIX:dir/file-b.pdf: Page 1: 1
-PREFIX:dir/file-b.pdf: Page 1: 
-PREFIX:dir/file-b.pdf: Page 1: 
-PREFIX:dir/file-a.pdf: Page 1: hello world
-PREFIX:dir/file-a.pdf: Page 1: this is just a test.
-PREFIX:dir/file-a.pdf: Page 1: 
-PREFIX:dir/file-a.pdf: Page 1: 1
-PREFIX:dir/file-a.pdf: Page 1: 
-PREFIX:dir/file-a.pdf: Page 1: 
+            "PREFIX:dir/file-b.pdf#page=1: hello world
+PREFIX:dir/file-b.pdf#page=1: this is just a test.
+PREFIX:dir/file-b.pdf#page=1: 
+PREFIX:dir/file-b.pdf#page=1: 1
+PREFIX:dir/file-b.pdf#page=1: 
+PREFIX:dir/file-b.pdf#page=1: 
+PREFIX:dir/file-a.pdf#page=1: hello world
+PREFIX:dir/file-a.pdf#page=1: this is just a test.
+PREFIX:dir/file-a.pdf#page=1: 
+PREFIX:dir/file-a.pdf#page=1: 1
+PREFIX:dir/file-a.pdf#page=1: 
+PREFIX:dir/file-a.pdf#page=1: 
 "
         );
         Ok(())
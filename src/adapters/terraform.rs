@@ -0,0 +1,569 @@
+use super::binary::{proto_fields, usize_from, BinReader, Proto};
+use super::{writing::WritingFileAdapter, *};
+use anyhow::Result;
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use serde_json::Value;
+use std::io::Write;
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+static EXTENSIONS: &[&str] = &["tfstate", "tfstate.backup", "tfplan", "tfplan.json"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "terraform".to_owned(),
+        version: 1,
+        description:
+            "Flattens resources and outputs of Terraform state and plan files (binary or `terraform show -json`) into `type.name.attr = value` lines"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
+    };
+}
+
+#[derive(Default, Clone)]
+pub struct TerraformAdapter;
+
+impl TerraformAdapter {
+    pub fn new() -> TerraformAdapter {
+        TerraformAdapter
+    }
+}
+impl GetMetadata for TerraformAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+const MAX_DEPTH: usize = 200;
+
+/// Write one `path = value` line per scalar in a json value. Nulls and empty containers are skipped since they are mostly noise in state files
+pub fn flatten_json(path: &str, v: &Value, s: &mut impl Write) -> Result<()> {
+    fn inner(path: &str, v: &Value, depth: usize, s: &mut impl Write) -> Result<()> {
+        if depth > MAX_DEPTH {
+            return Ok(());
+        }
+        match v {
+            Value::Null => {}
+            Value::Object(map) => {
+                for (k, v) in map {
+                    inner(&format!("{path}.{k}"), v, depth + 1, s)?;
+                }
+            }
+            Value::Array(items) => {
+                for (i, v) in items.iter().enumerate() {
+                    inner(&format!("{path}[{i}]"), v, depth + 1, s)?;
+                }
+            }
+            // keep multi-line values like policy documents on one line
+            Value::String(str) => writeln!(s, "{path} = {}", str.replace('\n', "\\n"))?,
+            Value::Bool(b) => writeln!(s, "{path} = {b}")?,
+            Value::Number(n) => writeln!(s, "{path} = {n}")?,
+        }
+        Ok(())
+    }
+    inner(path, v, 0, s)
+}
+
+fn str_field<'a>(v: &'a Value, key: &str) -> &'a str {
+    v.get(key).and_then(Value::as_str).unwrap_or_default()
+}
+
+/// state format version 4 (terraform 0.12+)
+fn dump_state_v4(state: &Value, s: &mut impl Write) -> Result<()> {
+    for resource in state
+        .get("resources")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let mut addr = String::new();
+        if let Some(module) = resource.get("module").and_then(Value::as_str) {
+            addr.push_str(module);
+            addr.push('.');
+        }
+        if str_field(resource, "mode") == "data" {
+            addr.push_str("data.");
+        }
+        addr.push_str(str_field(resource, "type"));
+        addr.push('.');
+        addr.push_str(str_field(resource, "name"));
+        for instance in resource
+            .get("instances")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let key = match instance.get("index_key") {
+                Some(Value::String(k)) => format!("[{k:?}]"),
+                Some(Value::Number(n)) => format!("[{n}]"),
+                _ => String::new(),
+            };
+            if let Some(attrs) = instance.get("attributes") {
+                flatten_json(&format!("{addr}{key}"), attrs, s)?;
+            }
+        }
+    }
+    if let Some(outputs) = state.get("outputs").and_then(Value::as_object) {
+        for (name, output) in outputs {
+            if let Some(value) = output.get("value") {
+                flatten_json(&format!("output.{name}"), value, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// state format version 1-3 (before terraform 0.12), with pre-flattened attributes
+fn dump_state_v3(state: &Value, s: &mut impl Write) -> Result<()> {
+    for module in state
+        .get("modules")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let mut prefix = String::new();
+        for p in module
+            .get("path")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .skip(1)
+        {
+            prefix.push_str(&format!("module.{}.", p.as_str().unwrap_or_default()));
+        }
+        if let Some(resources) = module.get("resources").and_then(Value::as_object) {
+            for (name, resource) in resources {
+                if let Some(attrs) = resource
+                    .get("primary")
+                    .and_then(|p| p.get("attributes"))
+                    .and_then(Value::as_object)
+                {
+                    for (k, v) in attrs {
+                        flatten_json(&format!("{prefix}{name}.{k}"), v, s)?;
+                    }
+                }
+            }
+        }
+        if let Some(outputs) = module.get("outputs").and_then(Value::as_object) {
+            for (name, output) in outputs {
+                let value = output.get("value").unwrap_or(output);
+                flatten_json(&format!("{prefix}output.{name}"), value, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn actions_name(actions: &[&str]) -> String {
+    match actions {
+        ["delete", "create"] | ["create", "delete"] => "replace".to_owned(),
+        _ => actions.join(", "),
+    }
+}
+
+/// output of `terraform show -json planfile`
+fn dump_json_plan(plan: &Value, s: &mut impl Write) -> Result<()> {
+    for rc in plan
+        .get("resource_changes")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        let addr = str_field(rc, "address");
+        let change = rc.get("change").unwrap_or(&Value::Null);
+        let actions: Vec<&str> = change
+            .get("actions")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        writeln!(s, "{addr}: {}", actions_name(&actions))?;
+        let after = change.get("after").filter(|v| !v.is_null());
+        if let Some(values) = after.or_else(|| change.get("before")) {
+            flatten_json(addr, values, s)?;
+        }
+    }
+    if let Some(outputs) = plan.get("output_changes").and_then(Value::as_object) {
+        for (name, change) in outputs {
+            if let Some(after) = change.get("after") {
+                flatten_json(&format!("output.{name}"), after, s)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_json(data: &[u8], s: &mut impl Write) -> Result<()> {
+    let v: Value = serde_json::from_slice(data)?;
+    if v.get("resource_changes").is_some() || v.get("planned_values").is_some() {
+        dump_json_plan(&v, s)
+    } else if v.get("modules").is_some() {
+        dump_state_v3(&v, s)
+    } else if v.get("resources").is_some() || v.get("outputs").is_some() {
+        dump_state_v4(&v, s)
+    } else {
+        writeln!(s, "[rga: not a terraform state or plan]")?;
+        Ok(())
+    }
+}
+
+/// decode the msgpack serialization of cty values used in binary plans
+fn msgpack_to_json(r: BinReader, pos: &mut usize, depth: usize) -> Result<Value> {
+    if depth > MAX_DEPTH {
+        return Err(format_err!("msgpack value nested too deeply"));
+    }
+    let mut take = |len: usize| -> Result<&[u8]> {
+        *pos += len;
+        r.slice(*pos - len, len)
+    };
+    let be = |b: &[u8]| b.iter().fold(0u64, |acc, b| acc << 8 | u64::from(*b));
+    let tag = take(1)?[0];
+    let (kind, len) = match tag {
+        0x00..=0x7f => return Ok(Value::from(u64::from(tag))),
+        0xe0..=0xff => return Ok(Value::from(i64::from(tag as i8))),
+        0xc0 => return Ok(Value::Null),
+        0xc2 => return Ok(Value::Bool(false)),
+        0xc3 => return Ok(Value::Bool(true)),
+        0xcc..=0xcf => {
+            let n = be(take(1 << (tag - 0xcc))?);
+            return Ok(Value::from(n));
+        }
+        0xd0..=0xd3 => {
+            let size = 1usize << (tag - 0xd0);
+            let n = be(take(size)?);
+            let shift = 64 - 8 * size as u32;
+            return Ok(Value::from(((n << shift) as i64) >> shift));
+        }
+        0xca => return Ok(Value::from(f64::from(f32::from_bits(be(take(4)?) as u32)))),
+        0xcb => return Ok(Value::from(f64::from_bits(be(take(8)?)))),
+        0xa0..=0xbf => ('s', usize::from(tag & 0x1f)),
+        0xd9..=0xdb => ('s', usize_from(be(take(1 << (tag - 0xd9))?))?),
+        0xc4..=0xc6 => ('s', usize_from(be(take(1 << (tag - 0xc4))?))?),
+        0x90..=0x9f => ('a', usize::from(tag & 0x0f)),
+        0xdc | 0xdd => ('a', usize_from(be(take(2 << (tag - 0xdc))?))?),
+        0x80..=0x8f => ('m', usize::from(tag & 0x0f)),
+        0xde | 0xdf => ('m', usize_from(be(take(2 << (tag - 0xde))?))?),
+        // extension types are how cty marks unknown values
+        0xd4..=0xd8 => ('e', 1 + (1 << (tag - 0xd4))),
+        0xc7..=0xc9 => ('e', 1 + usize_from(be(take(1 << (tag - 0xc7))?))?),
+        0xc1 => return Err(format_err!("invalid msgpack tag 0xc1")),
+    };
+    Ok(match kind {
+        's' => Value::String(String::from_utf8_lossy(take(len)?).into_owned()),
+        'e' => {
+            take(len)?;
+            Value::String("(known after apply)".to_owned())
+        }
+        'a' => {
+            let mut items = vec![];
+            for _ in 0..len {
+                items.push(msgpack_to_json(r, pos, depth + 1)?);
+            }
+            Value::Array(items)
+        }
+        _ => {
+            let mut map = serde_json::Map::new();
+            for _ in 0..len {
+                let key = match msgpack_to_json(r, pos, depth + 1)? {
+                    Value::String(k) => k,
+                    other => other.to_string(),
+                };
+                map.insert(key, msgpack_to_json(r, pos, depth + 1)?);
+            }
+            Value::Object(map)
+        }
+    })
+}
+
+fn proto_str(v: &Proto) -> String {
+    match v {
+        Proto::Bytes(b) => String::from_utf8_lossy(b).into_owned(),
+        Proto::Varint(n) => n.to_string(),
+        Proto::Fixed => String::new(),
+    }
+}
+
+/// (action name, last of the before/after values) of a plans.Change message
+fn proto_change(data: &[u8]) -> Result<(&'static str, Option<Value>)> {
+    let mut action = 0;
+    let mut value = None;
+    for (field, v) in proto_fields(data)? {
+        match (field, v) {
+            (1, Proto::Varint(a)) => action = a,
+            (2, Proto::Bytes(dynamic_value)) => {
+                for (field, v) in proto_fields(dynamic_value)? {
+                    if let (1, Proto::Bytes(msgpack)) = (field, v) {
+                        let r = BinReader::le(msgpack);
+                        value = Some(msgpack_to_json(r, &mut 0, 0)?);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let action = match action {
+        0 => "no-op",
+        1 => "create",
+        2 => "read",
+        3 => "update",
+        5 => "delete",
+        6 | 7 => "replace",
+        8 => "forget",
+        _ => "unknown action",
+    };
+    Ok((action, value))
+}
+
+/// the `tfplan` protobuf file inside a binary plan
+fn dump_proto_plan(data: &[u8], s: &mut impl Write) -> Result<()> {
+    for (field, v) in proto_fields(data)? {
+        let msg = match v {
+            Proto::Bytes(msg) => msg,
+            _ => continue,
+        };
+        match field {
+            // variables (map entry of name and DynamicValue)
+            2 => {
+                let fields = proto_fields(msg)?;
+                let name = fields
+                    .iter()
+                    .find(|f| f.0 == 1)
+                    .map(|f| proto_str(&f.1))
+                    .unwrap_or_default();
+                for (field, v) in fields {
+                    if let (2, Proto::Bytes(dynamic_value)) = (field, v) {
+                        for (field, v) in proto_fields(dynamic_value)? {
+                            if let (1, Proto::Bytes(msgpack)) = (field, v) {
+                                let value = msgpack_to_json(BinReader::le(msgpack), &mut 0, 0)?;
+                                flatten_json(&format!("var.{name}"), &value, s)?;
+                            }
+                        }
+                    }
+                }
+            }
+            // resource changes
+            3 => {
+                let mut addr = None;
+                // address parts used before terraform 1.0 introduced the addr field
+                let mut parts: [String; 4] = Default::default();
+                let mut key = String::new();
+                let mut change = (None, None);
+                for (field, v) in proto_fields(msg)? {
+                    match field {
+                        13 => addr = Some(proto_str(&v)),
+                        1 => parts[0] = proto_str(&v),
+                        2 => parts[1] = proto_str(&v),
+                        3 => parts[2] = proto_str(&v),
+                        4 => parts[3] = proto_str(&v),
+                        5 => key = format!("[{:?}]", proto_str(&v)),
+                        6 => key = format!("[{}]", proto_str(&v)),
+                        9 => {
+                            if let Proto::Bytes(c) = v {
+                                let (action, value) = proto_change(c)?;
+                                change = (Some(action), value);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                let addr = addr.unwrap_or_else(|| {
+                    let [module, mode, typ, name] = &parts;
+                    let mut a = String::new();
+                    if !module.is_empty() {
+                        a.push_str(module);
+                        a.push('.');
+                    }
+                    if mode == "1" {
+                        a.push_str("data.");
+                    }
+                    format!("{a}{typ}.{name}{key}")
+                });
+                writeln!(s, "{addr}: {}", change.0.unwrap_or("no-op"))?;
+                if let Some(value) = change.1 {
+                    flatten_json(&addr, &value, s)?;
+                }
+            }
+            // output changes
+            4 => {
+                let mut name = String::new();
+                let mut value = None;
+                let mut sensitive = false;
+                for (field, v) in proto_fields(msg)? {
+                    match (field, v) {
+                        (1, v) => name = proto_str(&v),
+                        (2, Proto::Bytes(c)) => value = proto_change(c)?.1,
+                        (3, Proto::Varint(b)) => sensitive = b != 0,
+                        _ => {}
+                    }
+                }
+                if sensitive {
+                    writeln!(s, "output.{name} = (sensitive)")?;
+                } else if let Some(value) = value {
+                    flatten_json(&format!("output.{name}"), &value, s)?;
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// find a file in a zip archive by name, returning its compression method and raw data
+fn zip_entry<'a>(data: &'a [u8], name: &str) -> Result<Option<(u16, &'a [u8])>> {
+    let r = BinReader::le(data);
+    let search_from = data.len().saturating_sub(22 + 0xffff);
+    let eocd = (search_from..data.len().saturating_sub(21))
+        .rev()
+        .find(|&i| data[i..].starts_with(b"PK\x05\x06"))
+        .ok_or_else(|| format_err!("zip end of central directory not found"))?;
+    let count = r.u16(eocd + 10)?;
+    let mut pos = usize_from(r.u32(eocd + 16)?)?;
+    for _ in 0..count {
+        if r.slice(pos, 4)? != b"PK\x01\x02" {
+            return Err(format_err!("invalid zip central directory entry"));
+        }
+        let method = r.u16(pos + 10)?;
+        let compressed_len = usize_from(r.u32(pos + 20)?)?;
+        let name_len = usize::from(r.u16(pos + 28)?);
+        let extra_len = usize::from(r.u16(pos + 30)?);
+        let comment_len = usize::from(r.u16(pos + 32)?);
+        let local = usize_from(r.u32(pos + 42)?)?;
+        if r.slice(pos + 46, name_len)? == name.as_bytes() {
+            let data_start =
+                local + 30 + usize::from(r.u16(local + 26)?) + usize::from(r.u16(local + 28)?);
+            return Ok(Some((method, r.slice(data_start, compressed_len)?)));
+        }
+        pos += 46 + name_len + extra_len + comment_len;
+    }
+    Ok(None)
+}
+
+#[async_trait]
+impl WritingFileAdapter for TerraformAdapter {
+    async fn adapt_write(
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+        mut oup: Pin<Box<dyn AsyncWrite + Send>>,
+    ) -> Result<()> {
+        let mut inp = ai.inp;
+        let mut data = Vec::new();
+        inp.read_to_end(&mut data).await?;
+        let mut buf = Vec::new();
+        if data.starts_with(b"PK\x03\x04") {
+            // binary plan: a zip with the plan as protobuf in the `tfplan` entry
+            let plan = match zip_entry(&data, "tfplan")? {
+                Some((0, stored)) => stored.to_vec(),
+                Some((8, deflated)) => {
+                    let mut plan = Vec::new();
+                    async_compression::tokio::bufread::DeflateDecoder::new(deflated)
+                        .read_to_end(&mut plan)
+                        .await?;
+                    plan
+                }
+                Some((method, _)) => {
+                    return Err(format_err!("unsupported zip compression method {}", method))
+                }
+                None => return Err(format_err!("no tfplan entry in plan file")),
+            };
+            dump_proto_plan(&plan, &mut buf)?;
+        } else {
+            dump_json(&data, &mut buf)?;
+        }
+        oup.write_all(&buf).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn dump(f: impl Fn(&mut Vec<u8>) -> Result<()>) -> Result<String> {
+        let mut out = Vec::new();
+        f(&mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn state() -> Result<()> {
+        let state = br#"{
+            "version": 4,
+            "outputs": {"ip": {"value": "10.0.0.1", "type": "string"}},
+            "resources": [
+                {"mode": "managed", "type": "aws_instance", "name": "web", "instances": [
+                    {"index_key": 0, "attributes": {"ami": "ami-123", "tags": {"Name": "web"}, "sg": ["a", "b"], "ebs": null}}
+                ]},
+                {"module": "module.net", "mode": "data", "type": "aws_vpc", "name": "main", "instances": [
+                    {"attributes": {"cidr_block": "10.0.0.0/16"}}
+                ]}
+            ]
+        }"#;
+        assert_eq!(
+            dump(|s| dump_json(state, s))?,
+            "aws_instance.web[0].ami = ami-123
+aws_instance.web[0].sg[0] = a
+aws_instance.web[0].sg[1] = b
+aws_instance.web[0].tags.Name = web
+module.net.data.aws_vpc.main.cidr_block = 10.0.0.0/16
+output.ip = 10.0.0.1
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn json_plan() -> Result<()> {
+        let plan = br#"{"format_version": "1.2", "resource_changes": [
+            {"address": "aws_s3_bucket.logs", "change": {"actions": ["delete", "create"], "before": {"bucket": "old"}, "after": {"bucket": "new", "policy": "{\n}"}}}
+        ]}"#;
+        assert_eq!(
+            dump(|s| dump_json(plan, s))?,
+            "aws_s3_bucket.logs: replace
+aws_s3_bucket.logs.bucket = new
+aws_s3_bucket.logs.policy = {\\n}
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn proto_plan() -> Result<()> {
+        // {"id": unknown, "name": "x", "count": -2}
+        let msgpack = b"\x83\xa2id\xd4\x00\x00\xa4name\xa1x\xa5count\xfe";
+        let dynamic_value = [&[0x0a, msgpack.len() as u8][..], msgpack].concat();
+        let change = [
+            &[0x08, 0x01, 0x12, dynamic_value.len() as u8][..],
+            &dynamic_value,
+        ]
+        .concat();
+        let addr = b"null_resource.a";
+        let rc = [
+            &[0x6a, addr.len() as u8][..],
+            addr,
+            &[0x4a, change.len() as u8],
+            &change,
+        ]
+        .concat();
+        let plan = [&[0x1a, rc.len() as u8][..], &rc].concat();
+        assert_eq!(
+            dump(|s| dump_proto_plan(&plan, s))?,
+            "null_resource.a: create
+null_resource.a.count = -2
+null_resource.a.id = (known after apply)
+null_resource.a.name = x
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,692 @@
+use super::binary::{usize_from, BinReader};
+use super::fsimage::FsImageAdapter;
+use super::*;
+use crate::preproc::keep_spool;
+use anyhow::{bail, Context, Result};
+use flate2::read::{DeflateDecoder, ZlibDecoder};
+use lazy_static::lazy_static;
+use log::*;
+use regex::Regex;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+static EXTENSIONS: &[&str] = &["qcow2", "vmdk", "vhd"];
+
+/// the data is copied in pieces of this size
+const CHUNK_LEN: u64 = 1024 * 1024;
+/// qcow2 clusters are at most 2 MiB, VMDK grains are usually 64 KiB
+const MAX_CLUSTER_LEN: u64 = 2 * 1024 * 1024;
+/// tables and descriptors longer than this are damaged
+const MAX_TABLE_LEN: u64 = 64 * 1024 * 1024;
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "vmdisk".to_owned(),
+        version: 1,
+        description:
+            "Decodes the disk images of virtual machines (qcow2, VMDK and VHD) to a temporary raw image and searches the filesystems in it like the fsimage adapter. Images with a backing file or parent disk are read without it"
+                .to_owned(),
+        recurses: true,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: Some(vec![
+            FileMatcher::MimeType("application/x-qemu-disk".to_owned()),
+            FileMatcher::MimeType("application/x-vmdk-disk".to_owned()),
+            FileMatcher::MimeType("application/x-vhd-disk".to_owned())
+        ]),
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: false,
+        sensitive: false,
+        // the tables are all over the image, and VMDK descriptors point to files next to them
+        input: InputKind::Path
+    };
+    static ref EXTENT: Regex =
+        Regex::new(r#"^(?:RW|RDONLY|NOACCESS)\s+(\d+)\s+(\w+)(?:\s+"([^"]*)"(?:\s+(\d+))?)?"#)
+            .unwrap();
+}
+
+#[derive(Default, Clone)]
+pub struct VmDiskAdapter;
+
+impl VmDiskAdapter {
+    pub fn new() -> VmDiskAdapter {
+        VmDiskAdapter
+    }
+}
+impl GetMetadata for VmDiskAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+/// the format of a disk image
+#[derive(Debug, PartialEq, Eq)]
+enum Format {
+    Qcow2,
+    /// a dynamic VHD, the footer is at the end of the file
+    Vhd,
+    /// a monolithicSparse or streamOptimized VMDK
+    VmdkSparse,
+    /// the text file that lists the files of a VMDK
+    VmdkDescriptor,
+    /// a raw image with a footer, like a fixed VHD
+    Raw,
+    /// a part of a VMDK that is searched with its descriptor
+    VmdkExtent,
+}
+
+fn format(head: &[u8], footer: &[u8], is_vmdk: bool) -> Result<Format> {
+    if head.starts_with(b"QFI\xfb") {
+        return Ok(Format::Qcow2);
+    }
+    if head.starts_with(b"KDMV") {
+        // the extents of split images have no descriptor
+        return Ok(match BinReader::le(head).u64(28)? {
+            0 => Format::VmdkExtent,
+            _ => Format::VmdkSparse,
+        });
+    }
+    if head.starts_with(b"# Disk DescriptorFile") {
+        return Ok(Format::VmdkDescriptor);
+    }
+    // dynamic disks have a copy of the footer at the start
+    let footer = match (
+        footer.starts_with(b"conectix"),
+        head.starts_with(b"conectix"),
+    ) {
+        (true, _) => footer,
+        (false, true) => head,
+        (false, false) if is_vmdk => return Ok(Format::VmdkExtent),
+        (false, false) => bail!("not a qcow2, VMDK or VHD image"),
+    };
+    match BinReader::be(footer).u32(60)? {
+        2 => Ok(Format::Raw),
+        3 => Ok(Format::Vhd),
+        4 => bail!("differencing VHDs are not supported, search their parent instead"),
+        disk_type => bail!("unknown VHD disk type {}", disk_type),
+    }
+}
+
+fn read_at(f: &mut File, pos: u64, len: u64) -> Result<Vec<u8>> {
+    if len > MAX_TABLE_LEN {
+        bail!("table too long");
+    }
+    f.seek(SeekFrom::Start(pos))?;
+    let mut data = vec![0; usize_from(len)?];
+    f.read_exact(&mut data)
+        .with_context(|| format!("reading {len} bytes at {pos}"))?;
+    Ok(data)
+}
+
+/// write to the raw image, parts that are zeros are left as holes
+fn write_at(out: &mut File, pos: u64, data: &[u8]) -> Result<()> {
+    if data.iter().any(|&b| b != 0) {
+        out.seek(SeekFrom::Start(pos))?;
+        out.write_all(data)?;
+    }
+    Ok(())
+}
+
+fn copy_at(inp: &mut File, from: u64, out: &mut File, to: u64, len: u64) -> Result<()> {
+    let mut done = 0;
+    while done < len {
+        let n = (len - done).min(CHUNK_LEN);
+        write_at(out, to + done, &read_at(inp, from + done, n)?)?;
+        done += n;
+    }
+    Ok(())
+}
+
+/// at most `max` bytes of the decompressed data
+fn inflate(r: impl Read, max: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.take(max)
+        .read_to_end(&mut data)
+        .context("decompressing")?;
+    Ok(data)
+}
+
+/// decode a qcow2 image, see qcow2.txt of qemu. Returns the size of the disk
+fn qcow2(inp: &mut File, out: &mut File) -> Result<u64> {
+    const OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+    let header = read_at(inp, 0, 512)?;
+    let r = BinReader::be(&header);
+    let version = r.u32(4)?;
+    if !matches!(version, 2 | 3) {
+        bail!("qcow2 version {} is not supported", version);
+    }
+    let cluster_bits = r.u32(20)?;
+    if !(9..=21).contains(&cluster_bits) {
+        bail!("invalid qcow2 cluster size");
+    }
+    let cluster_len = 1u64 << cluster_bits;
+    let size = r.u64(24)?;
+    if r.u32(32)? != 0 {
+        bail!("encrypted qcow2 images are not supported");
+    }
+    if r.u64(8)? != 0 {
+        warn!("the qcow2 image has a backing file, the parts of the disk that are only in it read as zeros");
+    }
+    if version == 3 {
+        let incompatible = r.u64(72)?;
+        if incompatible & 0x4 != 0 {
+            bail!("qcow2 images with an external data file are not supported");
+        }
+        if incompatible & 0x8 != 0 && r.u32(100)? > 104 && r.u8(104)? != 0 {
+            bail!("zstd compressed qcow2 images are not supported");
+        }
+        if incompatible & 0x10 != 0 {
+            bail!("qcow2 images with extended L2 entries are not supported");
+        }
+    }
+    let l1 = read_at(inp, r.u64(40)?, u64::from(r.u32(36)?) * 8)?;
+    let l2_entries = cluster_len / 8;
+    // compressed clusters have the offset in the low x bits, and the number of sectors after the first one above
+    let sector_bits = cluster_bits - 8;
+    let x = 62 - sector_bits;
+    for (i, l1_entry) in l1.chunks_exact(8).enumerate() {
+        let l2_offset = BinReader::be(l1_entry).u64(0)? & OFFSET_MASK;
+        if l2_offset == 0 {
+            continue;
+        }
+        let l2 = read_at(inp, l2_offset, cluster_len)?;
+        for (j, entry) in l2.chunks_exact(8).enumerate() {
+            let entry = BinReader::be(entry).u64(0)?;
+            let pos = (i as u64 * l2_entries + j as u64) * cluster_len;
+            if pos >= size {
+                break;
+            }
+            let len = cluster_len.min(size - pos);
+            if entry & (1 << 62) != 0 {
+                let host = entry & ((1 << x) - 1);
+                let sectors = ((entry >> x) & ((1 << sector_bits) - 1)) + 1;
+                let mut compressed = Vec::new();
+                inp.seek(SeekFrom::Start(host))?;
+                // the last sector can be cut off at the end of the file
+                (&mut *inp)
+                    .take(sectors * 512 - (host & 511))
+                    .read_to_end(&mut compressed)?;
+                write_at(
+                    out,
+                    pos,
+                    &inflate(DeflateDecoder::new(&compressed[..]), len)?,
+                )?;
+            } else if entry & 1 == 0 && entry & OFFSET_MASK != 0 {
+                // bit 0 marks clusters that read as zeros
+                copy_at(inp, entry & OFFSET_MASK, out, pos, len)?;
+            }
+        }
+    }
+    Ok(size)
+}
+
+/// decode a dynamic VHD, see the Virtual Hard Disk Image Format Specification
+fn vhd(inp: &mut File, out: &mut File, footer: &[u8]) -> Result<u64> {
+    let r = BinReader::be(footer);
+    let size = r.u64(48)?;
+    let header = read_at(inp, r.u64(16)?, 1024)?;
+    let h = BinReader::be(&header);
+    if h.slice(0, 8)? != b"cxsparse" {
+        bail!("no VHD dynamic disk header");
+    }
+    let block_len = u64::from(h.u32(32)?);
+    if block_len == 0 || block_len % 512 != 0 {
+        bail!("invalid VHD block size");
+    }
+    // each block starts with a bitmap of its sectors, padded to full sectors
+    let bitmap_len = (block_len / 512).div_ceil(8).div_ceil(512) * 512;
+    let bat = read_at(inp, h.u64(16)?, u64::from(h.u32(28)?) * 4)?;
+    for (i, sector) in bat.chunks_exact(4).enumerate() {
+        let sector = BinReader::be(sector).u32(0)?;
+        let pos = i as u64 * block_len;
+        if sector == u32::MAX || pos >= size {
+            continue;
+        }
+        copy_at(
+            inp,
+            u64::from(sector) * 512 + bitmap_len,
+            out,
+            pos,
+            block_len.min(size - pos),
+        )?;
+    }
+    Ok(size)
+}
+
+/// decode a sparse VMDK extent to `base` in the raw image, see the Virtual Disk Format 5.0
+/// specification of VMware. Returns the size of the extent
+fn vmdk(inp: &mut File, out: &mut File, base: u64) -> Result<u64> {
+    const GD_AT_END: u64 = u64::MAX;
+    let mut header = read_at(inp, 0, 512)?;
+    if BinReader::le(&header).u64(56)? == GD_AT_END {
+        // streamOptimized images have the header with the grain directory in their footer, before the
+        // end of stream marker
+        let len = inp.seek(SeekFrom::End(0))?;
+        header = read_at(inp, len.checked_sub(1024).context("VMDK too short")?, 512)?;
+        if !header.starts_with(b"KDMV") {
+            bail!("no VMDK footer");
+        }
+    }
+    let r = BinReader::le(&header);
+    let flags = r.u32(8)?;
+    let capacity = r
+        .u64(12)?
+        .checked_mul(512)
+        .context("invalid VMDK capacity")?;
+    let grain_len = r.u64(20)?.saturating_mul(512);
+    let gt_entries = u64::from(r.u32(44)?);
+    if grain_len == 0 || grain_len > MAX_CLUSTER_LEN || gt_entries == 0 {
+        bail!("invalid VMDK header");
+    }
+    let is_compressed = flags & 0x10000 != 0 && r.u16(77)? == 1;
+    let tables = capacity.div_ceil(grain_len).div_ceil(gt_entries);
+    let gd_offset = r.u64(56)?.checked_mul(512).context("invalid VMDK header")?;
+    let gd = read_at(inp, gd_offset, tables * 4)?;
+    for (i, gt_sector) in gd.chunks_exact(4).enumerate() {
+        let gt_sector = BinReader::le(gt_sector).u32(0)?;
+        if gt_sector == 0 {
+            continue;
+        }
+        let gt = read_at(inp, u64::from(gt_sector) * 512, gt_entries * 4)?;
+        for (j, grain) in gt.chunks_exact(4).enumerate() {
+            let grain = BinReader::le(grain).u32(0)?;
+            let pos = (i as u64 * gt_entries + j as u64) * grain_len;
+            if pos >= capacity {
+                break;
+            }
+            // 0 is a grain that was never written, 1 one of zeros
+            if grain <= 1 {
+                continue;
+            }
+            let from = u64::from(grain) * 512;
+            let len = grain_len.min(capacity - pos);
+            if is_compressed {
+                // the grain marker: the sector of the grain and the length of the compressed data
+                let marker = read_at(inp, from, 12)?;
+                let compressed_len = u64::from(BinReader::le(&marker).u32(8)?);
+                let compressed = read_at(inp, from + 12, compressed_len)?;
+                write_at(
+                    out,
+                    base + pos,
+                    &inflate(ZlibDecoder::new(&compressed[..]), len)?,
+                )?;
+            } else {
+                copy_at(inp, from, out, base + pos, len)?;
+            }
+        }
+    }
+    Ok(capacity)
+}
+
+/// an extent of a VMDK descriptor: the number of sectors, the type, and the file with the sector it
+/// starts at for flat extents
+#[derive(Debug, PartialEq, Eq)]
+struct Extent {
+    sectors: u64,
+    kind: String,
+    file: Option<String>,
+    offset: u64,
+}
+
+fn descriptor_extents(text: &str) -> Vec<Extent> {
+    text.lines()
+        .filter_map(|line| EXTENT.captures(line.trim()))
+        .map(|c| Extent {
+            sectors: c[1].parse().unwrap_or(0),
+            kind: c[2].to_owned(),
+            file: c.get(3).map(|m| m.as_str().to_owned()),
+            offset: c.get(4).and_then(|m| m.as_str().parse().ok()).unwrap_or(0),
+        })
+        .collect()
+}
+
+/// decode the extents of a VMDK descriptor in `dir` one after the other. Returns the size of the disk
+fn vmdk_descriptor(dir: &Path, text: &str, out: &mut File) -> Result<u64> {
+    let mut pos = 0;
+    for extent in descriptor_extents(text) {
+        let len = extent.sectors * 512;
+        let file = match (&extent.file, extent.kind.as_str()) {
+            (_, "ZERO") => None,
+            // only files next to the descriptor are read
+            (Some(file), _) if !file.contains(['/', '\\']) => Some(dir.join(file)),
+            (file, kind) => bail!("unsupported VMDK extent {} {:?}", kind, file),
+        };
+        if let Some(file) = file {
+            let mut inp =
+                File::open(&file).with_context(|| format!("opening {}", file.display()))?;
+            match extent.kind.as_str() {
+                "FLAT" | "VMFS" => copy_at(&mut inp, extent.offset * 512, out, pos, len)?,
+                "SPARSE" | "VMFSSPARSE" => {
+                    vmdk(&mut inp, out, pos)?;
+                }
+                kind => bail!("unsupported VMDK extent type {}", kind),
+            }
+        }
+        pos += len;
+    }
+    if pos == 0 {
+        bail!("no extents in the VMDK descriptor");
+    }
+    Ok(pos)
+}
+
+/// what became of a disk image
+#[derive(Debug, PartialEq, Eq)]
+enum Decoded {
+    /// it was written to the raw image
+    Image,
+    /// it is a raw image itself
+    Raw,
+    /// it is searched as a part of another file
+    Skipped,
+}
+
+/// decode the disk image at `path` to the raw image `raw`
+fn decode(path: &Path, raw: &Path) -> Result<Decoded> {
+    let is_vmdk = path
+        .extension()
+        .map_or(false, |e| e.eq_ignore_ascii_case("vmdk"));
+    let mut inp = File::open(path).with_context(|| format!("opening {}", path.display()))?;
+    let len = inp.metadata()?.len();
+    let mut head = Vec::new();
+    (&mut inp).take(512).read_to_end(&mut head)?;
+    let footer = read_at(&mut inp, len.saturating_sub(512), len.min(512))?;
+    let format = format(&head, &footer, is_vmdk)?;
+    debug!("{}: {:?}", path.display(), format);
+    let mut out = File::create(raw).with_context(|| format!("creating {}", raw.display()))?;
+    let size = match format {
+        Format::Raw => return Ok(Decoded::Raw),
+        Format::VmdkExtent => return Ok(Decoded::Skipped),
+        Format::Qcow2 => qcow2(&mut inp, &mut out)?,
+        Format::Vhd => {
+            let footer = if footer.starts_with(b"conectix") {
+                footer
+            } else {
+                head
+            };
+            vhd(&mut inp, &mut out, &footer)?
+        }
+        Format::VmdkSparse => vmdk(&mut inp, &mut out, 0)?,
+        Format::VmdkDescriptor => {
+            let text = read_at(&mut inp, 0, len)?;
+            let dir = path.parent().unwrap_or_else(|| Path::new(""));
+            vmdk_descriptor(dir, &String::from_utf8_lossy(&text), &mut out)?
+        }
+    };
+    // the holes at the end
+    out.set_len(size)?;
+    Ok(Decoded::Image)
+}
+
+#[async_trait]
+impl FileAdapter for VmDiskAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let dir = tempfile::tempdir().context("creating temporary directory")?;
+        let name = ai.filepath_hint.file_stem().unwrap_or_default();
+        let raw = dir.path().join(PathBuf::from(name).with_extension("img"));
+        let decoded = {
+            let (path, raw) = (ai.filepath_hint.clone(), raw.clone());
+            tokio::task::spawn_blocking(move || decode(&path, &raw)).await??
+        };
+        match decoded {
+            Decoded::Raw => FsImageAdapter::new().adapt(ai, detection_reason).await,
+            Decoded::Skipped => {
+                debug!(
+                    "{}: a part of a VMDK, searched with its descriptor",
+                    ai.filepath_hint.display()
+                );
+                Ok(Box::pin(tokio_stream::empty()))
+            }
+            Decoded::Image => {
+                let ai = AdaptInfo {
+                    inp: Box::pin(tokio::fs::File::open(&raw).await?),
+                    filepath_hint: raw,
+                    is_real_file: true,
+                    ..ai
+                };
+                let members = FsImageAdapter::new().adapt(ai, detection_reason).await?;
+                // the raw image is read while the members are searched
+                Ok(keep_spool(members, Some(dir)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{preproc::loop_adapt, test_utils::*};
+    use flate2::write::{DeflateEncoder, ZlibEncoder};
+    use flate2::Compression;
+    use pretty_assertions::assert_eq;
+
+    const CLUSTER: usize = 65536;
+
+    fn pad(data: &mut Vec<u8>, to: usize) {
+        data.resize(data.len().next_multiple_of(to), 0);
+    }
+
+    fn put(data: &mut [u8], pos: usize, value: &[u8]) {
+        data[pos..pos + value.len()].copy_from_slice(value);
+    }
+
+    /// a qcow2 image with 64 KiB clusters: the header, the L1 table, one L2 table and the data
+    fn qcow2_image(raw: &[u8], compress: bool) -> Vec<u8> {
+        let mut img = vec![0; 3 * CLUSTER];
+        put(&mut img, 0, b"QFI\xfb\0\0\0\x02");
+        put(&mut img, 20, &16u32.to_be_bytes());
+        put(&mut img, 24, &(raw.len() as u64).to_be_bytes());
+        put(&mut img, 36, &1u32.to_be_bytes());
+        put(&mut img, 40, &(CLUSTER as u64).to_be_bytes());
+        put(&mut img, CLUSTER, &(2 * CLUSTER as u64).to_be_bytes());
+        for (i, data) in raw.chunks(CLUSTER).enumerate() {
+            if data.iter().all(|&b| b == 0) {
+                continue;
+            }
+            let host = img.len() as u64;
+            let entry = if compress {
+                let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                let compressed = encoder.finish().unwrap();
+                let sectors = (compressed.len() as u64).div_ceil(512);
+                img.extend(compressed);
+                pad(&mut img, 512);
+                1 << 62 | (sectors - 1) << 54 | host
+            } else {
+                img.extend(data);
+                host
+            };
+            put(&mut img, 2 * CLUSTER + i * 8, &entry.to_be_bytes());
+        }
+        img
+    }
+
+    /// a dynamic VHD with 64 KiB blocks
+    fn vhd_image(raw: &[u8]) -> Vec<u8> {
+        let mut footer = vec![0; 512];
+        put(&mut footer, 0, b"conectix");
+        put(&mut footer, 16, &512u64.to_be_bytes());
+        put(&mut footer, 48, &(raw.len() as u64).to_be_bytes());
+        put(&mut footer, 60, &3u32.to_be_bytes());
+        let mut img = footer.clone();
+        let blocks = raw.len() / CLUSTER;
+        img.resize(1536, 0);
+        put(&mut img, 512, b"cxsparse");
+        put(&mut img, 528, &1536u64.to_be_bytes());
+        put(&mut img, 540, &(blocks as u32).to_be_bytes());
+        put(&mut img, 544, &(CLUSTER as u32).to_be_bytes());
+        img.resize(1536 + blocks * 4, 0xff);
+        pad(&mut img, 512);
+        for (i, data) in raw.chunks(CLUSTER).enumerate() {
+            if data.iter().all(|&b| b == 0) {
+                continue;
+            }
+            let sector = (img.len() / 512) as u32;
+            put(&mut img, 1536 + i * 4, &sector.to_be_bytes());
+            // the sector bitmap
+            img.extend([0xff; 512]);
+            img.extend(data);
+        }
+        img.extend(footer);
+        img
+    }
+
+    /// a monolithicSparse VMDK with 64 KiB grains, or a streamOptimized one with compressed grains
+    /// and the grain directory at the end
+    fn vmdk_image(raw: &[u8], stream: bool) -> Vec<u8> {
+        let mut header = vec![0; 512];
+        put(&mut header, 0, b"KDMV");
+        put(&mut header, 4, &3u32.to_le_bytes());
+        let flags: u32 = if stream { 0x30003 } else { 0x3 };
+        put(&mut header, 8, &flags.to_le_bytes());
+        put(&mut header, 12, &(raw.len() as u64 / 512).to_le_bytes());
+        put(&mut header, 20, &128u64.to_le_bytes());
+        put(&mut header, 28, &1u64.to_le_bytes());
+        put(&mut header, 36, &1u64.to_le_bytes());
+        put(&mut header, 44, &512u32.to_le_bytes());
+        put(&mut header, 77, &u16::from(stream).to_le_bytes());
+        let mut img = header.clone();
+        img.extend(b"# Disk DescriptorFile\n");
+        pad(&mut img, 512);
+        let mut gt = vec![0; 2048];
+        if !stream {
+            // the grain directory and table after the descriptor
+            img.resize(3 * 512, 0);
+            put(&mut img, 2 * 512, &3u32.to_le_bytes());
+            img.resize(7 * 512, 0);
+        }
+        for (i, data) in raw.chunks(CLUSTER).enumerate() {
+            if data.iter().all(|&b| b == 0) {
+                continue;
+            }
+            put(&mut gt, i * 4, &((img.len() / 512) as u32).to_le_bytes());
+            if stream {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                let compressed = encoder.finish().unwrap();
+                img.extend((i as u64 * 128).to_le_bytes());
+                img.extend((compressed.len() as u32).to_le_bytes());
+                img.extend(compressed);
+                pad(&mut img, 512);
+            } else {
+                img.extend(data);
+            }
+        }
+        if stream {
+            let gt_sector = (img.len() / 512) as u32;
+            img.extend(&gt);
+            let gd_sector = (img.len() / 512) as u64;
+            img.extend(gt_sector.to_le_bytes());
+            pad(&mut img, 512);
+            put(&mut header, 56, &gd_sector.to_le_bytes());
+            put(&mut img, 56, &u64::MAX.to_le_bytes());
+            // the footer marker, the footer and the end of stream marker
+            img.extend([0; 512]);
+            img.extend(header);
+            img.extend([0; 512]);
+        } else {
+            put(&mut img, 56, &2u64.to_le_bytes());
+            put(&mut img, 3 * 512, &gt);
+        }
+        img
+    }
+
+    /// 256 KiB with data in the first and the last 64 KiB
+    fn raw_disk() -> Vec<u8> {
+        let mut raw = vec![0; 4 * CLUSTER];
+        for (i, b) in raw[..CLUSTER].iter_mut().enumerate() {
+            *b = (i % 251) as u8;
+        }
+        put(&mut raw, 3 * CLUSTER + 100, b"the last cluster");
+        raw
+    }
+
+    #[test]
+    fn formats() -> Result<()> {
+        let raw = raw_disk();
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("disk.img");
+        for (name, img) in [
+            ("plain.qcow2", qcow2_image(&raw, false)),
+            ("compressed.qcow2", qcow2_image(&raw, true)),
+            ("dynamic.vhd", vhd_image(&raw)),
+            ("sparse.vmdk", vmdk_image(&raw, false)),
+            ("stream.vmdk", vmdk_image(&raw, true)),
+        ] {
+            let path = dir.path().join(name);
+            std::fs::write(&path, img)?;
+            assert_eq!(decode(&path, &out)?, Decoded::Image, "{name}");
+            assert_eq!(std::fs::read(&out)?, raw, "{name}");
+        }
+        // a fixed VHD is the disk with a footer
+        let mut fixed = raw.clone();
+        fixed.extend(&vhd_image(&raw)[..512]);
+        put(&mut fixed, raw.len() + 60, &2u32.to_be_bytes());
+        let path = dir.path().join("fixed.vhd");
+        std::fs::write(&path, fixed)?;
+        assert_eq!(decode(&path, &out)?, Decoded::Raw);
+        Ok(())
+    }
+
+    #[test]
+    fn descriptor() -> Result<()> {
+        let raw = raw_disk();
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("disk.img");
+        std::fs::write(dir.path().join("disk-flat.vmdk"), &raw[..2 * CLUSTER])?;
+        std::fs::write(
+            dir.path().join("disk-s002.vmdk"),
+            vmdk_image(&raw[2 * CLUSTER..], false),
+        )?;
+        let text = r#"# Disk DescriptorFile
+version=1
+createType="custom"
+
+# Extent description
+RW 256 FLAT "disk-flat.vmdk" 0
+RW 256 SPARSE "disk-s002.vmdk"
+"#;
+        let path = dir.path().join("disk.vmdk");
+        std::fs::write(&path, text)?;
+        assert_eq!(
+            descriptor_extents(text)[0],
+            Extent {
+                sectors: 256,
+                kind: "FLAT".to_owned(),
+                file: Some("disk-flat.vmdk".to_owned()),
+                offset: 0
+            }
+        );
+        assert_eq!(decode(&path, &out)?, Decoded::Image);
+        assert_eq!(std::fs::read(&out)?, raw);
+        // the extents are searched with the descriptor
+        let extent = dir.path().join("disk-flat.vmdk");
+        assert_eq!(decode(&extent, &out)?, Decoded::Skipped);
+        std::fs::write(&path, text.replace("disk-flat", "../disk-flat"))?;
+        assert!(decode(&path, &out).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn filesystem() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let filepath = dir.path().join("hello.qcow2");
+        let raw = std::fs::read(test_data_dir().join("hello.img"))?;
+        std::fs::write(&filepath, qcow2_image(&raw, true))?;
+        let (a, d) =
+            simple_adapt_info(&filepath, Box::pin(tokio::fs::File::open(&filepath).await?));
+        let o = adapted_to_vec(loop_adapt(&VmDiskAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(o)?,
+            "PREFIX:docs/notes.txt: Hello from ext4\nPREFIX:hello.txt: hello\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,317 @@
+use super::custom::map_exe_error;
+use super::*;
+use crate::adapted_iter::one_file;
+use crate::config::RgaConfig;
+use crate::policy;
+use crate::preproc_cache::{content_hash, open_cache_db, PreprocCache};
+use anyhow::{bail, Context, Result};
+use lazy_static::lazy_static;
+use log::debug;
+use regex::Regex;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::process::{Output, Stdio};
+
+static EXTENSIONS: &[&str] = &["mp3", "wav", "m4a", "ogg"];
+
+/// the speech recognition programs, selected with --rga-whisper-backend. The first one is the default
+static BACKENDS: &[&str] = &["whisper.cpp", "whisper"];
+
+lazy_static! {
+    static ref METADATA: AdapterMeta = AdapterMeta {
+        name: "whisper".to_owned(),
+        version: 1,
+        description:
+            "Transcribes the speech in audio recordings with whisper.cpp (with the model in --rga-whisper-model), or with OpenAI's whisper (--rga-whisper-backend=whisper). Every line starts with the time it was said, like [00:01:23]. Very slow, so it is disabled by default, and the transcripts are cached by the content of the file, so moved or copied recordings are not transcribed again. Enable it with --rga-adapters=+whisper to use it instead of ffmpeg for mp3 and ogg files"
+                .to_owned(),
+        recurses: false,
+        fast_matchers: EXTENSIONS
+            .iter()
+            .map(|s| FastFileMatcher::FileExtension(s.to_string()))
+            .collect(),
+        slow_matchers: None,
+        keep_fast_matchers_if_accurate: true,
+        disabled_by_default: true,
+        sensitive: false,
+        // ffmpeg converts the audio for whisper.cpp and needs to seek
+        input: InputKind::Path
+    };
+    /// a segment in the output of both programs, like `[00:01:23.000 --> 00:01:25.500]  text`.
+    /// whisper leaves out the hours in recordings shorter than an hour
+    static ref SEGMENT: Regex =
+        Regex::new(r"^\[((?:\d+:)?\d+:\d+)(?:\.\d+)? --> [^\]]*\]\s*(.*)$").unwrap();
+}
+
+#[derive(Default, Clone)]
+pub struct WhisperAdapter;
+
+impl WhisperAdapter {
+    pub fn new() -> WhisperAdapter {
+        WhisperAdapter
+    }
+}
+impl GetMetadata for WhisperAdapter {
+    fn metadata(&self) -> &AdapterMeta {
+        &METADATA
+    }
+}
+
+fn backend(config: &RgaConfig) -> Result<&str> {
+    match config.whisper_backend.as_deref() {
+        None => Ok(BACKENDS[0]),
+        Some(name) => match BACKENDS.iter().find(|b| **b == name) {
+            Some(b) => Ok(*b),
+            None => bail!(
+                "unknown whisper backend {}, available: {}",
+                name,
+                BACKENDS.join(", ")
+            ),
+        },
+    }
+}
+
+/// the backend and options for the cache key, since they change the transcript. None for the defaults
+pub fn options_key(config: &RgaConfig) -> Option<String> {
+    let mut options = vec![];
+    if let Some(backend) = config
+        .whisper_backend
+        .as_deref()
+        .filter(|b| *b != BACKENDS[0])
+    {
+        options.push(backend.to_owned());
+    }
+    if let Some(model) = &config.whisper_model {
+        options.push("-m".to_owned());
+        options.push(model.clone());
+    }
+    if let Some(language) = &config.whisper_language {
+        options.push("-l".to_owned());
+        options.push(language.clone());
+    }
+    if options.is_empty() {
+        return None;
+    }
+    Some(format!("whisper:{}", options.join(" ")))
+}
+
+/// what the cached transcripts depend on besides the content of the recording
+fn transcript_options(config: &RgaConfig, backend: &str) -> String {
+    format!(
+        "{}.v{} {}",
+        backend,
+        METADATA.version,
+        options_key(config).unwrap_or_default()
+    )
+}
+
+/// the transcript with one segment per line, starting with the time, like `[00:01:23] text`
+fn transcript(output: &str) -> String {
+    let mut out = String::new();
+    for line in output.lines() {
+        let c = match SEGMENT.captures(line.trim()) {
+            Some(c) => c,
+            None => continue,
+        };
+        let text = c[2].trim();
+        if text.is_empty() {
+            continue;
+        }
+        let mut time = c[1]
+            .split(':')
+            .map(|n| n.parse::<u32>().unwrap_or(0))
+            .collect::<Vec<_>>();
+        while time.len() < 3 {
+            time.insert(0, 0);
+        }
+        out.push_str(&format!(
+            "[{:02}:{:02}:{:02}] {}\n",
+            time[0], time[1], time[2], text
+        ));
+    }
+    out
+}
+
+fn check_status(out: &Output, program: &str) -> Result<()> {
+    if !out.status.success() {
+        let stderr = String::from_utf8_lossy(&out.stderr);
+        bail!(
+            "{} failed ({}): {}",
+            program,
+            out.status,
+            stderr.trim().lines().last().unwrap_or_default()
+        );
+    }
+    Ok(())
+}
+
+async fn whisper_cpp(config: &RgaConfig, audio: &Path, dir: &Path) -> Result<String> {
+    let model = match &config.whisper_model {
+        Some(model) => model,
+        None => bail!(
+            "whisper.cpp needs a model, download one (e.g. ggml-base.bin) and pass its path with --rga-whisper-model"
+        ),
+    };
+    // whisper.cpp only reads 16 kHz WAV files
+    let wav = dir.join("audio.wav");
+    let out = policy::command(&config.policy, "ffmpeg", audio)?
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(audio)
+        .args(["-ar", "16000", "-ac", "1", "-c:a", "pcm_s16le"])
+        .arg(&wav)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "ffmpeg", "Make sure you have ffmpeg installed."))?;
+    check_status(&out, "ffmpeg")?;
+    let out = policy::command(&config.policy, "whisper-cli", audio)?
+        .arg("-m")
+        .arg(model)
+        .arg("-l")
+        .arg(config.whisper_language.as_deref().unwrap_or("auto"))
+        .arg("-f")
+        .arg(&wav)
+        .stdin(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| map_exe_error(e, "whisper-cli", "Install whisper.cpp."))?;
+    check_status(&out, "whisper-cli")?;
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+async fn openai_whisper(config: &RgaConfig, audio: &Path, dir: &Path) -> Result<String> {
+    let mut cmd = policy::command(&config.policy, "whisper", audio)?;
+    // the segments are printed with --verbose, the output files are not needed
+    cmd.arg(audio)
+        .args([
+            "--verbose",
+            "True",
+            "--output_format",
+            "txt",
+            "--output_dir",
+        ])
+        .arg(dir);
+    if let Some(model) = &config.whisper_model {
+        cmd.arg("--model").arg(model);
+    }
+    if let Some(language) = &config.whisper_language {
+        cmd.arg("--language").arg(language);
+    }
+    let out = cmd.stdin(Stdio::null()).output().await.map_err(|e| {
+        map_exe_error(
+            e,
+            "whisper",
+            "Install whisper (pip install openai-whisper).",
+        )
+    })?;
+    check_status(&out, "whisper")?;
+    Ok(String::from_utf8_lossy(&out.stdout).into_owned())
+}
+
+#[async_trait]
+impl FileAdapter for WhisperAdapter {
+    async fn adapt(
+        &self,
+        ai: AdaptInfo,
+        _detection_reason: &FileMatcher,
+    ) -> Result<AdaptedFilesIterBox> {
+        let AdaptInfo {
+            filepath_hint,
+            line_prefix,
+            archive_recursion_depth,
+            postprocess,
+            config,
+            ..
+        } = ai;
+        let backend = backend(&config)?;
+        // transcribing takes about as long as listening, so the transcripts are kept by content
+        // (independent of the path and mtime of the file) and for as long as the cache
+        let options = transcript_options(&config, backend);
+        let mut cache = if config.cache.disabled {
+            None
+        } else {
+            Some(open_cache_db(Path::new(&config.cache.path.0)).await?)
+        };
+        let hash = {
+            let path = filepath_hint.clone();
+            tokio::task::spawn_blocking(move || content_hash(&path)).await??
+        };
+        let cached = match &cache {
+            Some(cache) => cache.get_transcript(hash, &options).await?,
+            None => None,
+        };
+        let text = match cached {
+            Some(text) => {
+                debug!(
+                    "{}: transcript found in cache",
+                    filepath_hint.to_string_lossy()
+                );
+                text
+            }
+            None => {
+                let dir = tempfile::tempdir().context("creating temporary directory")?;
+                let output = match backend {
+                    "whisper" => openai_whisper(&config, &filepath_hint, dir.path()).await?,
+                    _ => whisper_cpp(&config, &filepath_hint, dir.path()).await?,
+                };
+                let text = transcript(&output);
+                if let Some(cache) = &mut cache {
+                    cache.set_transcript(hash, &options, &text).await?;
+                }
+                text
+            }
+        };
+        Ok(one_file(AdaptInfo {
+            filepath_hint: PathBuf::from(format!("{}.txt", filepath_hint.to_string_lossy())),
+            inp: Box::pin(Cursor::new(text.into_bytes())),
+            line_prefix,
+            is_real_file: false,
+            archive_recursion_depth: archive_recursion_depth + 1,
+            postprocess,
+            config,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn transcripts() {
+        // whisper.cpp
+        assert_eq!(
+            transcript(
+                "\n[00:00:00.000 --> 00:00:04.200]   Welcome to the show.\n[00:00:04.200 --> 00:00:05.000]  \n[01:02:03.500 --> 01:02:09.000]   Goodbye.\n"
+            ),
+            "[00:00:00] Welcome to the show.\n[01:02:03] Goodbye.\n"
+        );
+        // whisper, with the language detection before the segments
+        assert_eq!(
+            transcript("Detected language: English\n[00:05.000 --> 00:09.120]  Hello there.\n"),
+            "[00:00:05] Hello there.\n"
+        );
+    }
+
+    #[test]
+    fn backends() {
+        let mut config = RgaConfig::default();
+        assert_eq!(backend(&config).unwrap(), "whisper.cpp");
+        assert_eq!(options_key(&config), None);
+        config.whisper_model = Some("ggml-base.bin".to_owned());
+        assert_eq!(
+            options_key(&config).as_deref(),
+            Some("whisper:-m ggml-base.bin")
+        );
+        config.whisper_backend = Some("whisper".to_owned());
+        config.whisper_model = Some("small".to_owned());
+        config.whisper_language = Some("de".to_owned());
+        assert_eq!(backend(&config).unwrap(), "whisper");
+        assert_eq!(
+            transcript_options(&config, "whisper"),
+            "whisper.v1 whisper:whisper -m small -l de"
+        );
+        config.whisper_backend = Some("vosk".to_owned());
+        assert!(backend(&config).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+//! A minimal XML tokenizer for adapters that only need the elements and text of a document,
+//! not validation, DTDs or namespaces.
+
+pub enum Xml<'a> {
+    Start(&'a str, Vec<(&'a str, String)>),
+    End(&'a str),
+    Text(String),
+}
+
+pub fn xml_unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(amp) = rest.find('&') {
+        out.push_str(&rest[..amp]);
+        rest = &rest[amp..];
+        let end = match rest.find(';') {
+            Some(end) if end < 12 => end,
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        let entity = &rest[1..end];
+        let c = match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "amp" => Some('&'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => entity
+                .strip_prefix("#x")
+                .map(|hex| u32::from_str_radix(hex, 16))
+                .or_else(|| entity.strip_prefix('#').map(str::parse))
+                .and_then(|n| n.ok())
+                .and_then(char::from_u32),
+        };
+        match c {
+            Some(c) => out.push(c),
+            None => out.push_str(&rest[..=end]),
+        }
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn local_name(name: &str) -> &str {
+    name.rsplit(':').next().unwrap_or(name)
+}
+
+/// a minimal XML tokenizer, good enough for localization files and the text parts of office documents.
+/// Namespace prefixes are removed from element names
+pub fn xml_events(doc: &str) -> Vec<Xml<'_>> {
+    let mut events = vec![];
+    let mut rest = doc;
+    while let Some(lt) = rest.find('<') {
+        if lt > 0 {
+            events.push(Xml::Text(xml_unescape(&rest[..lt])));
+        }
+        rest = &rest[lt..];
+        let skip_to = |rest: &mut &str, end: &str| {
+            *rest = rest.find(end).map_or("", |i| &rest[i + end.len()..]);
+        };
+        if rest.starts_with("<!--") {
+            skip_to(&mut rest, "-->");
+        } else if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            events.push(Xml::Text(cdata[..end].to_owned()));
+            rest = cdata.get(end + 3..).unwrap_or_default();
+        } else if rest.starts_with("<?") {
+            skip_to(&mut rest, "?>");
+        } else if rest.starts_with("<!") {
+            skip_to(&mut rest, ">");
+        } else if let Some(end_tag) = rest.strip_prefix("</") {
+            let end = end_tag.find('>').unwrap_or(end_tag.len());
+            events.push(Xml::End(local_name(end_tag[..end].trim())));
+            rest = end_tag.get(end + 1..).unwrap_or_default();
+        } else {
+            // find the end of the tag, ignoring > inside quoted attribute values
+            let mut quote = None;
+            let end = rest
+                .char_indices()
+                .find(|&(_, c)| match quote {
+                    Some(q) => {
+                        if c == q {
+                            quote = None;
+                        }
+                        false
+                    }
+                    None if c == '"' || c == '\'' => {
+                        quote = Some(c);
+                        false
+                    }
+                    None => c == '>',
+                })
+                .map_or(rest.len(), |(i, _)| i);
+            let tag = &rest[1..end];
+            rest = rest.get(end + 1..).unwrap_or_default();
+            let self_closing = tag.ends_with('/');
+            let tag = tag.trim_end_matches('/');
+            let name_end = tag.find(char::is_whitespace).unwrap_or(tag.len());
+            let name = local_name(&tag[..name_end]);
+            let mut attrs = vec![];
+            let mut a = &tag[name_end..];
+            while let Some(eq) = a.find('=') {
+                let key = a[..eq].trim();
+                let value = a[eq + 1..].trim_start();
+                let q = match value.chars().next() {
+                    Some(q @ ('"' | '\'')) => q,
+                    _ => break,
+                };
+                let value_end = value[1..].find(q).map_or(value.len(), |i| i + 1);
+                attrs.push((key, xml_unescape(&value[1..value_end])));
+                a = value.get(value_end + 1..).unwrap_or_default();
+            }
+            events.push(Xml::Start(name, attrs));
+            if self_closing {
+                events.push(Xml::End(name));
+            }
+        }
+    }
+    events
+}
+
+pub fn attr<'a>(attrs: &'a [(&str, String)], name: &str) -> Option<&'a str> {
+    attrs
+        .iter()
+        .find(|(k, _)| local_name(k) == name)
+        .map(|(_, v)| v.as_str())
+}
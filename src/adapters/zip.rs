@@ -1,11 +1,12 @@
 use super::*;
 use crate::print_bytes;
+use crate::vpath::member_prefix;
 use anyhow::*;
 use async_stream::stream;
 use lazy_static::lazy_static;
 use log::*;
 
-static EXTENSIONS: &[&str] = &["zip", "jar"];
+static EXTENSIONS: &[&str] = &["zip", "jar", "war", "ear", "aar", "apk"];
 
 lazy_static! {
     static ref METADATA: AdapterMeta = AdapterMeta {
@@ -19,7 +20,9 @@ lazy_static! {
             .collect(),
         slow_matchers: Some(vec![FileMatcher::MimeType("application/zip".to_owned())]),
         keep_fast_matchers_if_accurate: false,
-        disabled_by_default: false
+        disabled_by_default: false,
+        sensitive: false,
+        input: InputKind::Stream
     };
 }
 #[derive(Default, Clone)]
@@ -73,7 +76,7 @@ impl FileAdapter for ZipAdapter {
                         print_bytes(file.uncompressed_size() as f64),
                         print_bytes(file.compressed_size() as f64)
                     );
-                    let new_line_prefix = format!("{}{}: ", line_prefix, file.filename());
+                    let new_line_prefix = member_prefix(&line_prefix, file.filename());
                     let fname = PathBuf::from(file.filename());
                     tokio::pin!(reader);
                     // SAFETY: this should be solvable without unsafe but idk how :(
@@ -118,7 +121,7 @@ impl FileAdapter for ZipAdapter {
                             print_bytes(file.uncompressed_size() as f64),
                             print_bytes(file.compressed_size() as f64)
                         );
-                        let new_line_prefix = format!("{}{}: ", line_prefix, file.filename());
+                        let new_line_prefix = member_prefix(&line_prefix, file.filename());
                         let fname = PathBuf::from(file.filename());
                         let reader = entry.reader();
                         tokio::pin!(reader);
@@ -173,7 +176,7 @@ impl<'a> AdaptedFilesIter for ZipAdaptIter<'a> {
                     print_bytes(file.size() as f64),
                     print_bytes(file.compressed_size() as f64)
                 );
-                let line_prefix = format!("{}{}: ", line_prefix, file.name());
+                let line_prefix = member_prefix(&line_prefix, file.name());
                 Some(AdaptInfo {
                     filepath_hint: PathBuf::from(file.name()),
                     is_real_file: false,
@@ -192,8 +195,14 @@ mod test {
     use async_zip::{write::ZipFileWriter, Compression, ZipEntryBuilder};
 
     use super::*;
-    use crate::{preproc::loop_adapt, test_utils::*};
+    use crate::config::{
+        CacheMinTime, CacheMinTimePerMb, CachePath, MaxArchiveMembers, MaxExtractedBytes, RgaConfig,
+    };
+    use crate::preproc::{loop_adapt, open_virtual_path, rga_preproc};
+    use crate::test_utils::*;
+    use crate::vpath::VirtualPath;
     use pretty_assertions::assert_eq;
+    use tokio::io::AsyncReadExt;
 
     #[async_recursion::async_recursion]
     async fn create_zip(fname: &str, content: &str, add_inner: bool) -> Result<Vec<u8>> {
@@ -247,9 +256,114 @@ mod test {
 
         assert_eq!(
             String::from_utf8(buf)?,
-            "PREFIX:outer.txt: outer text file\nPREFIX:inner.zip: inner.txt: inner text file\n",
+            "PREFIX:outer.txt: outer text file\nPREFIX:inner.zip!/inner.txt: inner text file\n",
+        );
+
+        Ok(())
+    }
+    #[tokio::test]
+    async fn recursion_budget() -> Result<()> {
+        let zipfile = create_zip("outer.txt", "outer text file", true).await?;
+        let (mut a, d) = simple_adapt_info(
+            &PathBuf::from("outer.zip"),
+            Box::pin(std::io::Cursor::new(zipfile.clone())),
         );
+        a.config.max_archive_members = MaxArchiveMembers(2);
+        let buf = adapted_to_vec(loop_adapt(&ZipAdapter::new(), d.clone(), a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:outer.txt: outer text file\nPREFIX:inner.zip!/inner.txt: [rga: max archive members reached (2), skipping the rest of the archive]\n",
+        );
+
+        let (mut a, _) = simple_adapt_info(
+            &PathBuf::from("outer.zip"),
+            Box::pin(std::io::Cursor::new(zipfile)),
+        );
+        a.config.max_extracted_bytes = MaxExtractedBytes(10);
+        let buf = adapted_to_vec(loop_adapt(&ZipAdapter::new(), d, a).await?).await?;
+        let out = String::from_utf8(buf)?;
+        assert!(out.starts_with("PREFIX:outer.txt: outer text file\nPREFIX:inner.zip: [rga: max extracted bytes reached"));
+        assert!(!out.contains("inner text file"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn cached_budget() -> Result<()> {
+        // an archive cut short by the budget is not cached, so higher limits see all of it
+        let dir = tempfile::tempdir()?;
+        let fname = dir.path().join("outer.zip");
+        std::fs::write(
+            &fname,
+            create_zip("outer.txt", "outer text file", true).await?,
+        )?;
+        let run = |max_members: u64| {
+            let fname = fname.clone();
+            let cache_path = dir.path().join("cache");
+            async move {
+                let (mut a, _) = simple_fs_adapt_info(&fname).await?;
+                a.config.cache.path = CachePath(cache_path.to_string_lossy().into_owned());
+                a.config.cache.min_time = CacheMinTime(0);
+                a.config.cache.min_time_per_mb = CacheMinTimePerMb(0);
+                a.config.max_archive_members = MaxArchiveMembers(max_members);
+                let mut out = String::new();
+                rga_preproc(a).await?.read_to_string(&mut out).await?;
+                Ok::<_, anyhow::Error>(out)
+            }
+        };
+        let out = run(2).await?;
+        assert!(out.contains("max archive members reached (2)"));
+        assert!(!out.contains("inner text file"));
+        let out = run(100).await?;
+        assert!(out.contains("inner text file"));
+        assert!(!out.contains("max archive members reached"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sqlite_member() -> Result<()> {
+        // the sqlite adapter needs a path, so the member is spooled to a temporary file
+        let dir = tempfile::tempdir()?;
+        let db = dir.path().join("test.sqlite3");
+        rusqlite::Connection::open(&db)?
+            .execute_batch("create table items(name text); insert into items values ('hello');")?;
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let mut zip = ZipFileWriter::new(&mut cursor);
+        let options = ZipEntryBuilder::new("test.sqlite3".to_string(), Compression::Stored);
+        zip.write_entry_whole(options, &std::fs::read(&db)?).await?;
+        zip.close().await?;
+        let zipfile = cursor.into_inner();
+
+        let (a, d) = simple_adapt_info(
+            &PathBuf::from("outer.zip"),
+            Box::pin(std::io::Cursor::new(zipfile)),
+        );
+        let buf = adapted_to_vec(loop_adapt(&ZipAdapter::new(), d, a).await?).await?;
+        assert_eq!(
+            String::from_utf8(buf)?,
+            "PREFIX:test.sqlite3: items: name='hello'\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn open_nested_member() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let outer = dir.path().join("outer.zip");
+        std::fs::write(
+            &outer,
+            create_zip("outer.txt", "outer text file", true).await?,
+        )?;
+        let vpath = VirtualPath::parse(&format!("{}!/inner.zip!/inner.txt", outer.display()));
+        let mut ai = open_virtual_path(&vpath, RgaConfig::default()).await?;
+        assert_eq!(ai.filepath_hint, PathBuf::from("inner.txt"));
+        let mut s = String::new();
+        ai.inp.read_to_string(&mut s).await?;
+        assert_eq!(s, "inner text file");
 
+        let vpath = VirtualPath::parse(&format!("{}!/missing.txt", outer.display()));
+        assert!(open_virtual_path(&vpath, RgaConfig::default())
+            .await
+            .is_err());
         Ok(())
     }
 }
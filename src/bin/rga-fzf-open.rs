@@ -1,4 +1,5 @@
 use anyhow::Context;
+use ripgrep_all::vpath::VirtualPath;
 
 use std::process::Command;
 
@@ -11,20 +12,25 @@ fn main() -> anyhow::Result<()> {
     let fname = args.next().context("no filename")?;
     // let instance_id = std::env::var("RGA_FZF_INSTANCE").unwrap_or("unk".to_string());
 
-    if fname.ends_with(".pdf") {
+    // accept virtual paths like outer.zip!/doc.pdf#page=3. Other programs can't open members
+    // of archives, so open the file on disk that contains them
+    let vpath = VirtualPath::parse(&fname);
+    let fname = vpath.file;
+
+    if vpath.members.is_empty() && fname.extension().map_or(false, |e| e == "pdf") {
         use std::io::ErrorKind::*;
-        let worked = Command::new("evince")
-            .arg("--find")
-            .arg(&query)
-            .arg(&fname)
-            .spawn()
-            .map_or_else(
-                |err| match err.kind() {
-                    NotFound => Ok(false),
-                    _ => Err(err),
-                },
-                |_| Ok(true),
-            )?;
+        let mut cmd = Command::new("evince");
+        cmd.arg("--find").arg(&query);
+        if let Some(page) = vpath.page {
+            cmd.arg(format!("--page-index={page}"));
+        }
+        let worked = cmd.arg(&fname).spawn().map_or_else(
+            |err| match err.kind() {
+                NotFound => Ok(false),
+                _ => Err(err),
+            },
+            |_| Ok(true),
+        )?;
         if worked {
             return Ok(());
         }
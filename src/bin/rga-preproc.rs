@@ -1,40 +1,204 @@
 use rga::adapters::*;
 use rga::preproc::*;
+use rga::preproc_cache::record_outcome;
 use rga::print_dur;
+use rga::stats;
+use rga::vpath::VirtualPath;
 use ripgrep_all as rga;
 
-use anyhow::Context;
-use log::debug;
-use std::time::Instant;
+use anyhow::{bail, Context};
+use async_stream::stream;
+use bytes::Bytes;
+use log::{debug, warn};
+use std::ffi::OsString;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use tokio::fs::File;
+use tokio_util::io::StreamReader;
+
+const FILENAME_HINT: &str = "--filename-hint";
+
+/// removes `--filename-hint NAME` or `--filename-hint=NAME` from the arguments
+fn take_filename_hint(args: &mut Vec<OsString>) -> anyhow::Result<Option<PathBuf>> {
+    let pos = args.iter().position(|a| {
+        a == FILENAME_HINT
+            || a.to_str()
+                .map_or(false, |a| a.starts_with(&format!("{FILENAME_HINT}=")))
+    });
+    let pos = match pos {
+        Some(pos) => pos,
+        None => return Ok(None),
+    };
+    let arg = args.remove(pos);
+    if arg == FILENAME_HINT {
+        if pos >= args.len() {
+            bail!("{} needs a file name", FILENAME_HINT);
+        }
+        return Ok(Some(PathBuf::from(args.remove(pos))));
+    }
+    let arg = arg.to_str().expect("checked above");
+    Ok(Some(PathBuf::from(&arg[FILENAME_HINT.len() + 1..])))
+}
+
+const FIFO: &str = "named pipe";
+
+/// the kind of special file, if this is one. Reading FIFOs, sockets and devices can block forever
+#[cfg(unix)]
+fn special_file_kind(path: &Path) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    let file_type = std::fs::metadata(path).ok()?.file_type();
+    if file_type.is_fifo() {
+        Some(FIFO)
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_char_device() {
+        Some("character device")
+    } else if file_type.is_block_device() {
+        Some("block device")
+    } else {
+        None
+    }
+}
+#[cfg(not(unix))]
+fn special_file_kind(_path: &Path) -> Option<&'static str> {
+    None
+}
+
+/// read a FIFO, stopping with a marker if no data arrives for `timeout`.
+/// Opening and reading happen in a separate thread, since both can block forever. The thread is left behind in that case
+fn read_fifo(path: PathBuf, timeout: Duration) -> ReadBox {
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<std::io::Result<Vec<u8>>>(4);
+    std::thread::spawn(move || {
+        let mut file = match std::fs::File::open(&path) {
+            Ok(file) => file,
+            Err(e) => {
+                let _ = tx.blocking_send(Err(e));
+                return;
+            }
+        };
+        let mut buf = vec![0u8; 1 << 16];
+        loop {
+            let res = match file.read(&mut buf) {
+                Ok(0) => return,
+                Ok(n) => Ok(buf[..n].to_vec()),
+                Err(e) => Err(e),
+            };
+            let failed = res.is_err();
+            if tx.blocking_send(res).is_err() || failed {
+                return;
+            }
+        }
+    });
+    let s = stream! {
+        loop {
+            match tokio::time::timeout(timeout, rx.recv()).await {
+                Ok(Some(chunk)) => yield chunk.map(Bytes::from),
+                Ok(None) => break,
+                Err(_) => {
+                    let marker = format!("\n[rga: no data from {} for {}s, stopped reading]\n", FIFO, timeout.as_secs());
+                    yield Ok(Bytes::from(marker));
+                    break;
+                }
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     env_logger::init();
-    let mut arg_arr: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let mut arg_arr: Vec<OsString> = std::env::args_os().collect();
     let last = arg_arr.pop().expect("No filename specified");
+    let filename_hint = take_filename_hint(&mut arg_arr)?;
     let config = rga::config::parse_args(arg_arr, true)?;
     //clap::App::new("rga-preproc").arg(Arg::from_usage())
+    if last == "-" {
+        // the file name is only used to choose the adapter. Not cached since there is no file to check for changes
+        let ai = AdaptInfo {
+            inp: Box::pin(tokio::io::stdin()),
+            filepath_hint: filename_hint.unwrap_or_else(|| PathBuf::from("-")),
+            is_real_file: false,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: true,
+            config,
+        };
+        return preproc_to_stdout(ai).await;
+    }
+    if filename_hint.is_some() {
+        bail!(
+            "{} can only be used when reading from stdin (-)",
+            FILENAME_HINT
+        );
+    }
     let path = {
         let filepath = last;
         std::env::current_dir()?.join(filepath)
     };
+    // a virtual path like archive.zip!/dir/doc.docx (see vpath.rs) only extracts and adapts that member
+    let vpath = VirtualPath::parse(&path.to_string_lossy());
 
-    let i = File::open(&path)
-        .await
-        .context("Specified input file not found")?;
-    let mut o = tokio::io::stdout();
-    let ai = AdaptInfo {
-        inp: Box::pin(i),
-        filepath_hint: path,
-        is_real_file: true,
-        line_prefix: "".to_string(),
-        archive_recursion_depth: 0,
-        postprocess: true,
-        config,
+    let ai = if path.exists() || vpath.members.is_empty() {
+        // FIFOs are not cached, the content is different every time
+        let (inp, is_real_file): (ReadBox, bool) =
+            match (special_file_kind(&path), config.fifo_timeout) {
+                (None, _) => {
+                    let i = File::open(&path)
+                        .await
+                        .context("Specified input file not found")?;
+                    (Box::pin(i), true)
+                }
+                (Some(FIFO), Some(timeout)) => {
+                    (read_fifo(path.clone(), Duration::from_secs(timeout)), false)
+                }
+                (Some(kind), _) => {
+                    let hint = if kind == FIFO {
+                        ", use --rga-fifo-timeout to read it"
+                    } else {
+                        ""
+                    };
+                    println!("[rga: skipping {kind}{hint}]");
+                    return Ok(());
+                }
+            };
+        AdaptInfo {
+            inp,
+            filepath_hint: path,
+            is_real_file,
+            line_prefix: "".to_string(),
+            archive_recursion_depth: 0,
+            postprocess: true,
+            config,
+        }
+    } else {
+        open_virtual_path(&vpath, config).await?
     };
+    preproc_to_stdout(ai).await
+}
 
+async fn preproc_to_stdout(ai: AdaptInfo) -> anyhow::Result<()> {
+    let path = ai.filepath_hint.clone();
+    let is_real_file = ai.is_real_file;
+    let config = ai.config.clone();
     let start = Instant::now();
+    let res = copy_to_stdout(ai, start).await;
+    let stats = stats::file_stats(&path, start.elapsed(), res.as_ref().err());
+    if let Err(e) = stats::finish(&stats) {
+        warn!("could not write stats: {:#}", e);
+    }
+    if is_real_file {
+        if let Err(e) = record_outcome(&config, &stats).await {
+            warn!("could not record failure in cache: {:#}", e);
+        }
+    }
+    res
+}
+
+async fn copy_to_stdout(ai: AdaptInfo, start: Instant) -> anyhow::Result<()> {
+    let mut o = tokio::io::stdout();
+
     let mut oup = rga_preproc(ai).await.context("during preprocessing")?;
     debug!("finding and starting adapter took {}", print_dur(start));
     let res = tokio::io::copy(&mut oup, &mut o).await;
@@ -1,14 +1,26 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use path_clean::PathClean;
 use rga::adapters::custom::map_exe_error;
 use rga::adapters::*;
 use rga::config::{split_args, RgaConfig};
+use rga::dates::DateFilter;
 use rga::matching::*;
+use rga::preproc_cache::{open_cache_db, open_cache_db_readonly, PreprocCache};
 use rga::print_dur;
+use rga::queries::{read_queries, Queries};
+use rga::report::{Report, ReportFormat};
+use rga::stats;
+use rga::strategy::Strategy;
+use rga::throttle;
+use rga::vpath::VirtualPath;
 use ripgrep_all as rga;
 use structopt::StructOpt;
 
 use schemars::schema_for;
-use std::process::Command;
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
 use std::time::Instant;
 
 fn list_adapters(args: RgaConfig) -> Result<()> {
@@ -22,6 +34,7 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
             .iter()
             .map(|m| match m {
                 FastFileMatcher::FileExtension(ext) => format!(".{ext}"),
+                FastFileMatcher::Directory(dir) => format!("{dir}/"),
             })
             .collect::<Vec<_>>()
             .join(", ");
@@ -59,6 +72,33 @@ fn list_adapters(args: RgaConfig) -> Result<()> {
     }
     Ok(())
 }
+/// the first pass of --rga-strategy=auto: searches only the metadata and member lists and returns the
+/// files that matched
+fn shallow_pass(mut cmd: Command, passthrough_args: &[OsString]) -> Result<Vec<PathBuf>> {
+    let mut config: serde_json::Value = serde_json::from_str(
+        &std::env::var(rga::config::RGA_CONFIG).context("RGA_CONFIG not set")?,
+    )?;
+    config["meta_only"] = true.into();
+    cmd.args(passthrough_args)
+        .arg("--files-with-matches")
+        .env(rga::config::RGA_CONFIG, config.to_string())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit());
+    log::debug!("shallow pass to run: {:?}", cmd);
+    let out = cmd
+        .output()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    // rg exits with 1 if nothing matched
+    if !out.status.success() && out.status.code() != Some(1) {
+        anyhow::bail!("shallow pass failed: {}", out.status);
+    }
+    let cwd = std::env::current_dir()?;
+    Ok(String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(|l| cwd.join(l).clean())
+        .collect())
+}
+
 fn main() -> anyhow::Result<()> {
     // set debugging as early as possible
     if std::env::args().any(|e| e == "--debug") {
@@ -76,36 +116,171 @@ fn main() -> anyhow::Result<()> {
     if config.list_adapters {
         return list_adapters(config);
     }
+    if let Some(key) = &config.config_get {
+        match rga::config_edit::get(&config, key)? {
+            Some(serde_json::Value::String(value)) => println!("{value}"),
+            Some(value) => println!("{value}"),
+            // not set, like git config
+            None => std::process::exit(1),
+        }
+        return Ok(());
+    }
+    if let Some(assignment) = &config.config_set {
+        return rga::config_edit::set(&config, assignment);
+    }
+    if config.config_edit {
+        return rga::config_edit::edit(&config);
+    }
+    if let Some(source) = &config.adapter_add {
+        return tokio::runtime::Runtime::new()?.block_on(rga::adapter_store::add(&config, source));
+    }
+    if config.adapter_list {
+        return rga::adapter_store::list();
+    }
+    if let Some(path) = &config.config_validate {
+        let problems = rga::config_edit::validate(Path::new(path))?;
+        for problem in &problems {
+            eprintln!("{problem}");
+        }
+        if !problems.is_empty() {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if config.self_test {
+        let ok = tokio::runtime::Runtime::new()?.block_on(rga::testing::self_test(&config))?;
+        if !ok {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    if config.install_deps || config.install_deps_run {
+        return tokio::runtime::Runtime::new()?
+            .block_on(rga::install::install_deps(&config, config.install_deps_run));
+    }
+    if config.self_update {
+        return tokio::runtime::Runtime::new()?.block_on(rga::install::self_update(&config));
+    }
+    if config.estimate {
+        return estimate(&config, &passthrough_args);
+    }
+    if config.quarantine_add.is_some()
+        || config.quarantine_remove.is_some()
+        || config.quarantine_list
+    {
+        return tokio::runtime::Runtime::new()?.block_on(quarantine(&config));
+    }
     if let Some(path) = config.fzf_path {
         if path == "_" {
             // fzf found no result, ignore everything and return
             println!("[no file found]");
             return Ok(());
         }
-        passthrough_args.push(std::ffi::OsString::from(&path[1..]));
+        // the preview searches the file on disk that contains a virtual path
+        passthrough_args.push(VirtualPath::parse(&path[1..]).file.into_os_string());
     }
 
-    if passthrough_args.is_empty() {
+    let mut queries = match &config.queries {
+        Some(path) => Some(Queries::new(
+            read_queries(Path::new(path))?,
+            &mut passthrough_args,
+        )?),
+        None => None,
+    };
+    let report_format = ReportFormat::new(&config)?;
+    if config.report_file.is_some() && report_format.is_none() {
+        anyhow::bail!("--rga-report-file needs --rga-report");
+    }
+    let context = report_format.map_or(false, ReportFormat::context);
+    let mut report = report_format.map(|format| Report::new(format.snippets()));
+    if queries.is_some() && report.is_some() {
+        anyhow::bail!("--rga-report can't be used with --rga-queries");
+    }
+    let strategy = Strategy::new(&config)?;
+    if strategy == Strategy::Auto && config.meta_only {
+        anyhow::bail!("--rga-strategy=auto can't be used with --rga-meta-only");
+    }
+
+    if passthrough_args.is_empty() && queries.is_none() {
         // rg would show help. Show own help instead.
         RgaConfig::clap().print_help()?;
         println!();
         return Ok(());
     }
 
+    let job_file = match &config.job {
+        Some(job) => {
+            // absolute, so it is the same file for rga-preproc
+            let job = std::env::current_dir()?.join(job);
+            let state = rga::job::read(&job)?;
+            if !state.done.is_empty() || !state.failed.is_empty() {
+                eprintln!(
+                    "[rga: job {}: {} files done, {} failed]",
+                    job.display(),
+                    state.done.len(),
+                    state.failed.len()
+                );
+            }
+            if config.retry_failed {
+                if state.failed.is_empty() {
+                    return Ok(());
+                }
+                passthrough_args.extend(state.failed.into_keys().map(OsString::from));
+            }
+            Some(job)
+        }
+        None if config.retry_failed => {
+            let failures = tokio::runtime::Runtime::new()?.block_on(cached_failures(&config))?;
+            if failures.is_empty() {
+                return Ok(());
+            }
+            eprintln!("[rga: retrying {} failed files]", failures.len());
+            passthrough_args.extend(failures.into_iter().map(OsString::from));
+            None
+        }
+        None => None,
+    };
+    if config.retry_with.is_some() && !config.retry_failed {
+        anyhow::bail!("--rga-retry-with needs --rga-retry-failed");
+    }
+    if !config.cache.disabled && config.retry_with.is_none() {
+        let quarantined = tokio::runtime::Runtime::new()?.block_on(async {
+            let cache = open_cache_db(Path::new(&config.cache.path.0)).await?;
+            cache.quarantine_list().await
+        })?;
+        if !quarantined.is_empty() {
+            eprintln!(
+                "[rga: skipping {} quarantined files, see --rga-quarantine-list]",
+                quarantined.len()
+            );
+        }
+    }
+
     let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
 
-    let pre_glob = if !config.accurate {
-        let extensions = adapters
-            .iter()
-            .flat_map(|a| &a.metadata().fast_matchers)
-            .flat_map(|m| match m {
-                FastFileMatcher::FileExtension(ext) => vec![ext.clone(), ext.to_ascii_uppercase()],
-            })
-            .collect::<Vec<_>>()
-            .join(",");
-        format!("*.{{{extensions}}}")
+    // rga-preproc checks the dates of all files, not only of the ones with an adapter
+    let date_filter = DateFilter::new(&config)?;
+    let pre_globs = if !config.accurate && date_filter.is_none() {
+        let mut extensions = vec![];
+        let mut directories = vec![];
+        for matcher in adapters.iter().flat_map(|a| &a.metadata().fast_matchers) {
+            match matcher {
+                FastFileMatcher::FileExtension(ext) => {
+                    extensions.push(ext.clone());
+                    extensions.push(ext.to_ascii_uppercase());
+                }
+                FastFileMatcher::Directory(dir) => directories.push(dir.clone()),
+            }
+        }
+        // first parts of split archives, see volumes.rs
+        extensions.extend(["001", "z01", "Z01"].map(String::from));
+        let mut globs = vec![format!("*.{{{}}}", extensions.join(","))];
+        if !directories.is_empty() {
+            globs.push(format!("**/{{{}}}/*", directories.join(",")));
+        }
+        globs
     } else {
-        "*".to_owned()
+        vec!["*".to_owned()]
     };
 
     add_exe_to_path()?;
@@ -120,22 +295,223 @@ fn main() -> anyhow::Result<()> {
     let exe = std::env::current_exe().expect("Could not get executable location");
     let preproc_exe = exe.with_file_name("rga-preproc");
 
+    // rga-preproc writes the stats of each file here, they are summed up at the end
+    let stats_dir = if config.stats_json.is_some() || config.stats_prometheus.is_some() {
+        Some(tempfile::tempdir()?)
+    } else {
+        None
+    };
+    // rga-preproc writes a hint for each missing program here, they are printed once at the end
+    let hints_dir = tempfile::tempdir()?;
+    // the files of the --rga-throttle-jobs slots
+    let slots_dir = match config.throttle.jobs {
+        Some(_) => Some(tempfile::tempdir()?),
+        None => None,
+    };
+
+    // the patterns of --rga-queries, for rg -f
+    let patterns_file = match &queries {
+        Some(queries) => {
+            let mut file = tempfile::NamedTempFile::new()?;
+            file.write_all(queries.patterns().as_bytes())?;
+            Some(file)
+        }
+        None => None,
+    };
+
     let before = Instant::now();
-    let mut cmd = Command::new("rg");
-    cmd.args(rg_args)
-        .arg("--pre")
-        .arg(preproc_exe)
-        .arg("--pre-glob")
-        .arg(pre_glob)
-        .args(passthrough_args);
+    let wrapper = throttle::priority_wrapper(&config.throttle);
+    let rg_command = || {
+        let mut cmd = match wrapper.split_first() {
+            Some((program, args)) => {
+                let mut cmd = Command::new(program);
+                cmd.args(args).arg("rg");
+                cmd
+            }
+            None => Command::new("rg"),
+        };
+        cmd.args(&rg_args).arg("--pre").arg(&preproc_exe);
+        for glob in &pre_globs {
+            cmd.arg("--pre-glob").arg(glob);
+        }
+        if let Some(file) = &patterns_file {
+            // the patterns already say whether they are case sensitive, this overrides --smart-case
+            cmd.args(["--case-sensitive", "-f"]).arg(file.path());
+        }
+        if let Some(dir) = &slots_dir {
+            cmd.env(throttle::SLOTS_DIR, dir.path());
+        }
+        cmd.env(rga::install::HINTS_DIR, hints_dir.path());
+        cmd
+    };
+
+    // the files next to the matches of the shallow pass, for the second pass of --rga-strategy=auto
+    let deep_files = if strategy == Strategy::Auto {
+        let matched = shallow_pass(rg_command(), &passthrough_args)?;
+        log::debug!("shallow pass matched {} files", matched.len());
+        if matched.is_empty() {
+            rga::install::print_hints(hints_dir.path())?;
+            return Ok(());
+        }
+        let mut file = tempfile::NamedTempFile::new()?;
+        for path in &matched {
+            writeln!(file, "{}", path.display())?;
+        }
+        Some(file)
+    } else {
+        None
+    };
+
+    let mut cmd = rg_command();
+    if patterns_file.is_some() {
+        cmd.arg("--json").stdout(Stdio::piped());
+    }
+    if report.is_some() {
+        // plain files have no prefix column, their matches are located by line number
+        cmd.args(["--json", "--line-number"]).stdout(Stdio::piped());
+    }
+    if context {
+        // before the arguments of rg, so -C changes it
+        cmd.arg("--context=2");
+    }
+    cmd.args(passthrough_args);
+    if let Some(dir) = &stats_dir {
+        cmd.env(stats::STATS_DIR, dir.path());
+    }
+    if let Some(job) = &job_file {
+        cmd.env(rga::job::JOB_FILE, job);
+    }
+    if let Some(file) = &deep_files {
+        cmd.env(rga::strategy::DEEP_FILES, file.path());
+    }
     log::debug!("rg command to run: {:?}", cmd);
-    let mut child = cmd
-        .spawn()
-        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    let mut child = cmd.spawn().map_err(|e| match wrapper.first() {
+        Some(program) => map_exe_error(
+            e,
+            program,
+            "--rga-throttle-nice and --rga-throttle-idle-io need nice and ionice.",
+        ),
+        None => map_exe_error(e, "rg", "Please make sure you have ripgrep installed."),
+    })?;
+
+    if let Some(queries) = &mut queries {
+        let rg_output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        let mut stdout = std::io::stdout().lock();
+        for message in rg_output.lines() {
+            stdout.write_all(queries.handle_message(&message?)?.as_bytes())?;
+        }
+    }
+    if let Some(report) = &mut report {
+        let rg_output = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        for message in rg_output.lines() {
+            report.handle_message(&message?)?;
+        }
+    }
 
     child.wait()?;
+    if let Some(queries) = &queries {
+        eprintln!("{}", queries.summary());
+    }
+    if let (Some(report), Some(format)) = (&report, report_format) {
+        let out = report.render(format, &std::env::current_dir()?)?;
+        match &config.report_file {
+            Some(path) => std::fs::write(path, out).with_context(|| format!("writing {path}"))?,
+            None => print!("{out}"),
+        }
+    }
 
     log::debug!("running rg took {}", print_dur(before));
+    rga::install::print_hints(hints_dir.path())?;
+    if let Some(dir) = stats_dir {
+        let run = stats::summarize(dir.path(), before.elapsed())?;
+        if let Some(path) = &config.stats_json {
+            std::fs::write(path, serde_json::to_string_pretty(&run)?)
+                .with_context(|| format!("writing {path}"))?;
+        }
+        if let Some(path) = &config.stats_prometheus {
+            // the textfile collector must not see a partially written file
+            let tmp = format!("{path}.tmp");
+            std::fs::write(&tmp, run.to_prometheus()).with_context(|| format!("writing {tmp}"))?;
+            std::fs::rename(&tmp, path).with_context(|| format!("writing {path}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// the files on disk whose adapter failed the last time, as recorded in the cache
+async fn cached_failures(config: &RgaConfig) -> Result<Vec<String>> {
+    let cache = open_cache_db(Path::new(&config.cache.path.0)).await?;
+    Ok(cache
+        .failures()
+        .await?
+        .into_iter()
+        .map(|(path, _adapter, _error)| path)
+        .filter(|path| Path::new(path).exists())
+        .collect())
+}
+
+/// --rga-quarantine-add, --rga-quarantine-remove and --rga-quarantine-list
+async fn quarantine(config: &RgaConfig) -> Result<()> {
+    // the path as rga-preproc stores it in the cache
+    let cache_path = |path: &str| -> Result<String> {
+        Ok(std::env::current_dir()?
+            .join(path)
+            .clean()
+            .to_string_lossy()
+            .into_owned())
+    };
+    if config.quarantine_add.is_some() || config.quarantine_remove.is_some() {
+        let mut cache = open_cache_db(Path::new(&config.cache.path.0)).await?;
+        if let Some(path) = &config.quarantine_add {
+            cache
+                .quarantine(&cache_path(path)?, "added with --rga-quarantine-add")
+                .await?;
+        }
+        if let Some(path) = &config.quarantine_remove {
+            cache.clear_failure(&cache_path(path)?).await?;
+        }
+    }
+    if config.quarantine_list {
+        // nothing is quarantined without a cache, listing doesn't create one
+        if let Some(cache) = open_cache_db_readonly(Path::new(&config.cache.path.0)).await? {
+            for (path, reason) in cache.quarantine_list().await? {
+                println!("{path}: {reason}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// print which adapters would run on the files a search with the given rg arguments would search
+fn estimate(config: &RgaConfig, rg_args: &[OsString]) -> Result<()> {
+    let out = Command::new("rg")
+        .arg("--files")
+        .args(rga::estimate::files_args(rg_args))
+        .output()
+        .map_err(|e| map_exe_error(e, "rg", "Please make sure you have ripgrep installed."))?;
+    if !out.status.success() {
+        anyhow::bail!(
+            "rg --files failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    let files = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .map(PathBuf::from)
+        .filter_map(|path| {
+            let len = std::fs::metadata(&path).ok()?.len();
+            Some((path, len))
+        })
+        .collect::<Vec<_>>();
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let threads = config
+        .throttle
+        .jobs
+        .map_or(threads, |jobs| threads.min(jobs as usize));
+    print!(
+        "{}",
+        rga::estimate::estimate(config, files)?.report(threads)
+    );
     Ok(())
 }
 
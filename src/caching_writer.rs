@@ -1,4 +1,4 @@
-use std::{future::Future, pin::Pin};
+use std::{future::Future, path::PathBuf, pin::Pin};
 
 use anyhow::{Context, Result};
 use async_compression::tokio::write::ZstdEncoder;
@@ -6,21 +6,38 @@ use async_stream::stream;
 
 use crate::to_io_err;
 use log::*;
+use tempfile::TempPath;
 use tokio::io::{AsyncRead, AsyncWriteExt};
 use tokio_stream::StreamExt;
 use tokio_util::io::{ReaderStream, StreamReader};
 
-type FinishHandler =
-    dyn FnOnce((u64, Option<Vec<u8>>)) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> + Send;
+/// the compressed output
+pub enum Compressed {
+    Blob(Vec<u8>),
+    /// output longer than max_cache_size, written to a file of [Spill]
+    File(TempPath),
+}
+
+/// where to write compressed outputs that are too long for a blob
+pub struct Spill {
+    pub dir: PathBuf,
+    /// longest compressed output to write to a file, longer ones are not cached
+    pub max_len: u64,
+}
+
+type FinishHandler = dyn FnOnce((u64, Option<Compressed>)) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>
+    + Send;
 /**
  * wrap a AsyncRead so that it is passthrough,
  * but also the written data is compressed and written into a buffer,
- * unless more than max_cache_size bytes is written, then the cache is dropped and it is pure passthrough.
+ * unless more than max_cache_size bytes is written, then the rest is written to a file of `spill`,
+ * or without one the cache is dropped and it is pure passthrough.
  */
 pub fn async_read_and_write_to_cache<'a>(
     inp: impl AsyncRead + Send + 'a,
     max_cache_size: usize,
     compression_level: i32,
+    spill: Option<Spill>,
     on_finish: Box<FinishHandler>,
 ) -> Result<Pin<Box<dyn AsyncRead + Send + 'a>>> {
     let inp = Box::pin(inp);
@@ -29,6 +46,8 @@ pub fn async_read_and_write_to_cache<'a>(
         async_compression::Level::Precise(compression_level),
     ));
     let mut bytes_written = 0;
+    // the spill file and how much was written to it
+    let mut spilled: Option<(tokio::fs::File, TempPath, u64)> = None;
 
     let s = stream! {
         let mut stream = ReaderStream::new(inp);
@@ -40,9 +59,33 @@ pub fn async_read_and_write_to_cache<'a>(
                     let compressed_len = writer.get_ref().len();
                     trace!("wrote {} to zstd, len now {}", bytes.len(), compressed_len);
                     if compressed_len > max_cache_size {
-                        debug!("cache longer than max, dropping");
-                        //writer.finish();
+                        match (&spill, spilled.is_some()) {
+                            (Some(spill), false) => {
+                                debug!("cache longer than max, writing it to a file");
+                                std::fs::create_dir_all(&spill.dir)?;
+                                let (file, path) = tempfile::Builder::new()
+                                    .suffix(".zst")
+                                    .tempfile_in(&spill.dir)?
+                                    .into_parts();
+                                spilled = Some((tokio::fs::File::from_std(file), path, 0));
+                            }
+                            (None, _) => {
+                                debug!("cache longer than max, dropping");
+                                zstd_writer.take();
+                            }
+                            (Some(_), true) => {}
+                        }
+                    }
+                }
+                if let (Some(writer), Some((file, _, len))) = (zstd_writer.as_mut(), spilled.as_mut()) {
+                    let buf = writer.get_mut();
+                    file.write_all(buf).await?;
+                    *len += buf.len() as u64;
+                    buf.clear();
+                    if spill.as_ref().map_or(false, |s| *len > s.max_len) {
+                        debug!("cache file longer than max, dropping");
                         zstd_writer.take();
+                        spilled.take();
                     }
                 }
             }
@@ -53,10 +96,19 @@ pub fn async_read_and_write_to_cache<'a>(
             if let Some(mut writer) = zstd_writer.take() {
                 writer.shutdown().await?;
                 let res = writer.into_inner();
-                if res.len() <= max_cache_size {
-                    (bytes_written, Some(res))
-                } else {
-                    (bytes_written, None)
+                match spilled.take() {
+                    Some((mut file, path, len)) => {
+                        file.write_all(&res).await?;
+                        file.flush().await?;
+                        let len = len + res.len() as u64;
+                        if spill.as_ref().map_or(false, |s| len <= s.max_len) {
+                            (bytes_written, Some(Compressed::File(path)))
+                        } else {
+                            (bytes_written, None)
+                        }
+                    }
+                    None if res.len() <= max_cache_size => (bytes_written, Some(Compressed::Blob(res))),
+                    None => (bytes_written, None),
                 }
             } else {
                 (bytes_written, None)
@@ -1,12 +1,23 @@
-use crate::{adapters::custom::CustomAdapterConfig, project_dirs};
+use crate::{
+    adapter_store,
+    adapters::{custom::CustomAdapterConfig, FEATURES},
+    config_env, policy, power, project_dirs,
+};
 use anyhow::{Context, Result};
 use derive_more::FromStr;
+use lazy_static::lazy_static;
 use log::*;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use std::ffi::OsString;
 use std::io::Read;
-use std::{fs::File, io::Write, iter::IntoIterator, path::PathBuf, str::FromStr};
+use std::{
+    fs::File,
+    io::Write,
+    iter::IntoIterator,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 use structopt::StructOpt;
 
 #[derive(Debug, Deserialize, Serialize)]
@@ -72,27 +83,118 @@ impl Default for CacheMaxBlobLen {
     }
 }
 
+/// parse a byte count with an optional k, M or G suffix
+pub(crate) fn parse_readable_bytes_str(s: &str) -> Result<u64> {
+    let suffix = s.chars().last();
+    if let Some(suffix) = suffix {
+        match suffix {
+            'k' | 'M' | 'G' => u64::from_str(s.trim_end_matches(suffix))
+                .with_context(|| "Could not parse int".to_string())
+                .map(|e| {
+                    e * match suffix {
+                        'k' => 1000,
+                        'M' => 1_000_000,
+                        'G' => 1_000_000_000,
+                        _ => panic!("impossible"),
+                    }
+                }),
+            _ => u64::from_str(s).with_context(|| "Could not parse int".to_string()),
+        }
+    } else {
+        Err(anyhow::format_err!("empty byte input"))
+    }
+}
+
 impl FromStr for CacheMaxBlobLen {
     type Err = anyhow::Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let suffix = s.chars().last();
-        if let Some(suffix) = suffix {
-            Ok(CacheMaxBlobLen(match suffix {
-                'k' | 'M' | 'G' => usize::from_str(s.trim_end_matches(suffix))
-                    .with_context(|| "Could not parse int".to_string())
-                    .map(|e| {
-                        e * match suffix {
-                            'k' => 1000,
-                            'M' => 1_000_000,
-                            'G' => 1_000_000_000,
-                            _ => panic!("impossible"),
-                        }
-                    }),
-                _ => usize::from_str(s).with_context(|| "Could not parse int".to_string()),
-            }?))
-        } else {
-            Err(anyhow::format_err!("empty byte input"))
-        }
+        Ok(CacheMaxBlobLen(usize::try_from(parse_readable_bytes_str(
+            s,
+        )?)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct CacheMaxFileLen(pub u64);
+
+impl ToString for CacheMaxFileLen {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMaxFileLen {
+    fn default() -> Self {
+        CacheMaxFileLen(1_000_000_000)
+    }
+}
+
+impl FromStr for CacheMaxFileLen {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(CacheMaxFileLen(parse_readable_bytes_str(s)?))
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct CacheMinTime(pub u64);
+
+impl ToString for CacheMinTime {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMinTime {
+    fn default() -> Self {
+        CacheMinTime(10)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq, FromStr)]
+pub struct CacheMinTimePerMb(pub u64);
+
+impl ToString for CacheMinTimePerMb {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for CacheMinTimePerMb {
+    fn default() -> Self {
+        CacheMinTimePerMb(10)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, FromStr)]
+pub struct MaxArchiveMembers(pub u64);
+
+impl ToString for MaxArchiveMembers {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for MaxArchiveMembers {
+    fn default() -> Self {
+        MaxArchiveMembers(100_000)
+    }
+}
+
+#[derive(JsonSchema, Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
+pub struct MaxExtractedBytes(pub u64);
+
+impl ToString for MaxExtractedBytes {
+    fn to_string(&self) -> String {
+        self.0.to_string()
+    }
+}
+impl Default for MaxExtractedBytes {
+    fn default() -> Self {
+        MaxExtractedBytes(10_000_000_000)
+    }
+}
+
+impl FromStr for MaxExtractedBytes {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(MaxExtractedBytes(parse_readable_bytes_str(s)?))
     }
 }
 
@@ -122,6 +224,7 @@ pub struct RgaConfig {
     /// will try to detect the mime type of input files using the magic bytes
     /// (similar to the `file` utility), and use that to choose the adapter.
     /// Detection is only done on the first 8KiB of the file, since we can't always seek on the input (in archives).
+    /// The mime type of files on disk is remembered in the cache until they change.
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(long = "--rga-accurate")]
     pub accurate: bool,
@@ -143,6 +246,50 @@ pub struct RgaConfig {
     #[structopt(flatten)]
     pub cache: CacheConfig,
 
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub throttle: ThrottleConfig,
+
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(flatten)]
+    pub policy: PolicyConfig,
+
+    /// Adapter to use for the files of --rga-retry-failed instead of the one that failed, e.g. tika
+    ///
+    /// Disabled adapters can be used too. Only this run uses it, later searches use the usual adapter.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-retry-with",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub retry_with: Option<String>,
+
+    /// Skip files in future searches once their adapter failed or ran out of --rga-per-file-budget this many times in a row
+    ///
+    /// Quarantined files are stored in the cache. They are skipped until --rga-quarantine-remove or until an adapter
+    /// succeeds on them with --rga-retry-failed --rga-retry-with.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-quarantine-after",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub quarantine_after: Option<u64>,
+
+    /// Adapters to leave out while the computer runs on battery power, e.g. ocr,ffmpeg
+    ///
+    /// The files they would adapt are not searched, and are picked up by the next run on mains power.
+    /// Detected on Linux and macOS.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-battery-pause",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub battery_pause: Vec<String>,
+
     /// Maximum nestedness of archives to recurse into
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
@@ -153,6 +300,248 @@ pub struct RgaConfig {
     )]
     pub max_archive_recursion: MaxArchiveRecursion,
 
+    /// Maximum number of archive members to search per file
+    ///
+    /// Members of nested archives count too. The rest of the archive is skipped with a marker once the limit is reached.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-archive-members",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_archive_members: MaxArchiveMembers,
+
+    /// Maximum number of bytes to extract from archives per file
+    ///
+    /// Bytes extracted from nested archives count too. Checked before each member, so the rest of the archive is skipped with a marker once the limit is reached.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-max-extracted-bytes",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub max_extracted_bytes: MaxExtractedBytes,
+
+    /// Search the cheap members of archives first
+    ///
+    /// Members that are slow to adapt (images that may need OCR, PDFs, audio and video) are held back
+    /// until the rest of the archive has been searched, so matches in text files show up sooner.
+    /// The order only depends on the archive, so the output is still the same on every run.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-cheap-first", hidden_short_help = true)]
+    pub cheap_first: bool,
+
+    /// Put the line prefixes (archive members, pages) after the text, separated by a tab
+    ///
+    /// By default, lines of files in archives and pages of documents start with their location, e.g.
+    /// `inner.tar!/doc.pdf#page=3: text`, so patterns anchored with `^` don't match the text. With this flag
+    /// the lines are output as `text<TAB>inner.tar!/doc.pdf#page=3` instead, and `^` matches at the start
+    /// of the extracted text. Lines of files that need no prefix are output unchanged.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-prefix-column", hidden_short_help = true)]
+    pub prefix_column: bool,
+
+    /// Search only the metadata of documents and the member lists of archives
+    ///
+    /// Instead of extracting the text, rga outputs the names of the members of archives and the titles,
+    /// authors, keywords and dates of documents (PDF, office documents, e-books, emails, ID3 tags of audio
+    /// files), read from the start and the end of the files. This is a fast first pass over a large
+    /// collection, to find the documents to search fully afterwards. Nothing is cached.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-meta-only", hidden_short_help = true)]
+    pub meta_only: bool,
+
+    /// How much of the files to extract: deep, shallow or auto
+    ///
+    /// "deep" runs the adapters on all files. "shallow" only searches the metadata of documents and the
+    /// member lists of archives, like --rga-meta-only. "auto" searches shallowly first, then runs the
+    /// adapters only on the files in the directories of the files that matched, and outputs the matches
+    /// of that second pass. Default: deep
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-strategy",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub strategy: Option<String>,
+
+    /// Read named pipes (FIFOs), giving up after this many seconds without data
+    ///
+    /// By default, FIFOs are skipped with a marker since reading them blocks until another program writes to them,
+    /// possibly forever. Sockets and device files are always skipped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-fifo-timeout",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub fifo_timeout: Option<u64>,
+
+    /// Stop preprocessing a single file after this many seconds
+    ///
+    /// The output so far is searched, followed by a marker. Output cut short is not cached, but archive members
+    /// that were completed are, so the next search continues where this one stopped.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-per-file-budget",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub per_file_budget: Option<u64>,
+
+    /// Only search the documents from this date on, like 2023-01-31, 2023-01-31T12:00:00 (UTC) or 30d for 30 days ago
+    ///
+    /// The date of a document is the one inside it if rga can find it without extracting the document (the
+    /// creation date of a PDF, the Date header of an email, the EXIF timestamp of a photo), otherwise the
+    /// modification time of the file. The members of archives are not filtered. All files go through
+    /// rga-preproc, as with --rga-accurate.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-newer-than",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub newer_than: Option<String>,
+
+    /// Only search the documents from before this date, see --rga-newer-than
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-older-than",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub older_than: Option<String>,
+
+    /// Programs the pdf adapter uses to extract text, in the order they are tried
+    ///
+    /// "poppler" runs pdftotext, "mupdf" runs mutool. If one fails on a file, the next one is tried.
+    /// "native" extracts the text without external programs, but not as well. It is only available if
+    /// rga was built with the pdf-native feature, which is on by default.
+    /// Default: poppler,mupdf,native
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-pdf-backends",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub pdf_backends: Vec<String>,
+
+    /// Languages for OCR with tesseract, e.g. eng,deu
+    ///
+    /// Tesseract language codes, the language data has to be installed. Default: tesseract's default (eng)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-languages",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub ocr_languages: Vec<String>,
+
+    /// Page segmentation mode for OCR (tesseract --psm)
+    ///
+    /// 1: automatic with orientation and script detection, 3: automatic (default), 4: a single column,
+    /// 6: a single block of text, 11: sparse text. 1 and 3 split multi-column pages like newspapers and
+    /// papers into their columns, while 4 and 6 read straight across them.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-psm",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub ocr_psm: Option<u8>,
+
+    /// Program the ocr adapter uses to recognize text: tesseract or kraken
+    ///
+    /// "kraken" is slower, but can read handwriting with a matching model (--rga-ocr-model). Put it in a
+    /// separate config file (--rga-config-file) to use it only for archives of handwritten notes.
+    /// Default: tesseract
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-backend",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub ocr_backend: Option<String>,
+
+    /// Recognition model for the kraken OCR backend, e.g. a handwriting model from its model repository
+    ///
+    /// A path or the name of a model installed with `kraken get`. Default: kraken's default model (printed text)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-ocr-model",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub ocr_model: Option<String>,
+
+    /// Recognize the text of image subtitles in videos (Blu-ray and DVD) with tesseract
+    ///
+    /// Renders every second of the subtitles in which they change and reads it with tesseract, in the
+    /// languages of --rga-ocr-languages. Slow, so image subtitles are skipped by default
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-ffmpeg-ocr-subtitles", hidden_short_help = true)]
+    pub ffmpeg_ocr_subtitles: bool,
+
+    /// Only read the text columns of Parquet files
+    ///
+    /// Skips decoding the numbers, dates and nested columns, which is much faster when searching
+    /// data lakes for IDs or names
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-parquet-strings-only", hidden_short_help = true)]
+    pub parquet_strings_only: bool,
+
+    /// Program the whisper adapter uses to transcribe audio: whisper.cpp or whisper
+    ///
+    /// "whisper.cpp" runs whisper-cli and needs a model (--rga-whisper-model), "whisper" runs OpenAI's
+    /// Python implementation. Default: whisper.cpp
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-whisper-backend",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub whisper_backend: Option<String>,
+
+    /// Speech recognition model for the whisper adapter
+    ///
+    /// For whisper.cpp the path of a ggml model file, e.g. ggml-base.en.bin. For whisper the name of a
+    /// model, e.g. small or medium.en. Larger models are more accurate but much slower
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-whisper-model",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub whisper_model: Option<String>,
+
+    /// Spoken language of the recordings for the whisper adapter, e.g. en or de
+    ///
+    /// Default: detected from the first 30 seconds of each recording
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-whisper-language",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub whisper_language: Option<String>,
+
+    /// URL of an index of custom adapters, to install them by name with --rga-adapter-add
+    ///
+    /// A JSON object with an entry like {"url": "...", "sha256": "..."} for each adapter name.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-adapter-index",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub adapter_index: Option<String>,
+
     //////////////////////////////////////////
     //////////////////////////// Config file only
     //////////////////////////////////////////
@@ -166,6 +555,101 @@ pub struct RgaConfig {
     #[structopt(long = "--rga-config-file", require_equals = true)]
     pub config_file_path: Option<String>,
 
+    /// Write statistics of the run to this JSON file: per adapter the number of files, the time
+    /// spent and the cache hit ratio, and the files that failed
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-stats-json",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub stats_json: Option<String>,
+
+    /// Write statistics of the run to this file in the text format of Prometheus
+    ///
+    /// File counts, cache hits, errors and a histogram of the time per file, for each adapter. Point
+    /// it at the directory of the textfile collector of node_exporter to monitor regular runs.
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-stats-prometheus",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub stats_prometheus: Option<String>,
+
+    /// Record the outcome of every file in this job file, to resume long runs
+    ///
+    /// Running the same search with the same job file again continues where it stopped, since the
+    /// files that are done are read from the cache. The job file survives interruptions and reboots.
+    #[serde(skip)]
+    #[structopt(long = "--rga-job", require_equals = true, hidden_short_help = true)]
+    pub job: Option<String>,
+
+    /// Search for each pattern in this file (one per line) in a single pass, and output which of them match
+    ///
+    /// rg runs once with all patterns, so every file is only read and adapted once. Each matching line is
+    /// output as `query<TAB>path<TAB>line` for every query that matches it, followed by how many queries matched.
+    /// All arguments of rg are paths then. -i, -s, -S and -F apply to each query separately.
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-queries",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub queries: Option<String>,
+
+    /// Output a report of the matches instead of the matching lines: table, html, markdown, csv or sarif
+    ///
+    /// "table" ranks the documents by their number of matches, with the location of the first match
+    /// (archive member, page) or its line number for plain files. Archives count as one document.
+    /// "html" and "markdown" rank the documents the same way and link to the files on disk, with the
+    /// matching lines highlighted and two lines of context around them (-C changes the number of lines).
+    /// "csv" has one row per match, with the document, the location of the match in it, the line and
+    /// column, the match and the text of the line. "sarif" is for code scanning dashboards, the location
+    /// of a match in the document is its logical location.
+    #[serde(skip)]
+    #[structopt(long = "--rga-report", require_equals = true, hidden_short_help = true)]
+    pub report: Option<String>,
+
+    /// Write the report of --rga-report to this file instead of the standard output
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-report-file",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub report_file: Option<String>,
+
+    /// Only search the files whose adapter failed, instead of the given paths
+    ///
+    /// The failed files of the job of --rga-job, or else the ones the cache knows of.
+    #[serde(skip)]
+    #[structopt(long = "--rga-retry-failed", hidden_short_help = true)]
+    pub retry_failed: bool,
+
+    /// Skip this file in future searches, e.g. because it makes an adapter hang
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-quarantine-add",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub quarantine_add: Option<String>,
+
+    /// Search this quarantined file again
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-quarantine-remove",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub quarantine_remove: Option<String>,
+
+    /// List the quarantined files and why they were quarantined
+    #[serde(skip)]
+    #[structopt(long = "--rga-quarantine-list", hidden_short_help = true)]
+    pub quarantine_list: bool,
+
     /// same as passing path directly, except if argument is empty
     /// kinda hacky, but if no file is found, fzf calls rga with empty string as path, which causes No such file or directory from rg. So filter those cases and return specially
     #[serde(skip)]
@@ -184,6 +668,101 @@ pub struct RgaConfig {
     )]
     pub print_config_schema: bool,
 
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-estimate",
+        help = "List the adapters that would run on the files a search with the given pattern and paths would search, with the size and an estimated time, without running them"
+    )]
+    pub estimate: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-self-test",
+        help = "Run the adapters over bundled test files and compare the output"
+    )]
+    pub self_test: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-install-deps",
+        help = "Check the external programs the enabled adapters need and print how to install the missing ones"
+    )]
+    pub install_deps: bool,
+
+    /// Also run the package manager to install them
+    #[serde(skip)]
+    #[structopt(long = "--rga-install-deps-run", hidden_short_help = true)]
+    pub install_deps_run: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-config-get",
+        require_equals = true,
+        value_name = "key",
+        help = "Print the value of an option in the config files, e.g. cache.max_blob_len"
+    )]
+    pub config_get: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-config-set",
+        require_equals = true,
+        value_name = "key=value",
+        help = "Set an option in the config file, e.g. ocr_languages=eng,deu, checked against the schema"
+    )]
+    pub config_set: Option<String>,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-config-edit",
+        help = "Open the config file in $VISUAL or $EDITOR and check it afterwards"
+    )]
+    pub config_edit: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-config-validate",
+        require_equals = true,
+        value_name = "file",
+        help = "Check a config file against the schema and print the line of each problem, like unknown options and values of the wrong type"
+    )]
+    pub config_validate: Option<String>,
+
+    /// Download a custom adapter from a URL or by its name in the adapter index (--rga-adapter-index)
+    ///
+    /// The definition is a JSON object like an entry of custom_adapters. It is only installed if its
+    /// SHA-256 checksum is the one in the index or in the #sha256= fragment of the URL, e.g.
+    /// https://example.org/gpg.json#sha256=... The URL is downloaded with curl, git repositories
+    /// can't be cloned: use the URL of the raw file in them. The adapters are installed next to the
+    /// config file and used like the custom adapters of the config file, unless they were changed
+    /// since.
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-adapter-add",
+        require_equals = true,
+        value_name = "url-or-name",
+        hidden_short_help = true
+    )]
+    pub adapter_add: Option<String>,
+
+    /// List the adapters installed with --rga-adapter-add, with where they were downloaded from
+    #[serde(skip)]
+    #[structopt(long = "--rga-adapter-list", hidden_short_help = true)]
+    pub adapter_list: bool,
+
+    /// Read and change the config file of the machine with --rga-config-get, --rga-config-set and
+    /// --rga-config-edit instead of the user's
+    #[serde(skip)]
+    #[structopt(long = "--rga-config-machine", hidden_short_help = true)]
+    pub config_machine: bool,
+
+    #[serde(skip)]
+    #[structopt(
+        long = "--rga-self-update",
+        help = "Replace rga with the binaries of the latest GitHub release, if it is newer"
+    )]
+    pub self_update: bool,
+
     #[serde(skip)]
     #[structopt(long, help = "Show help for ripgrep itself")]
     pub rg_help: bool,
@@ -193,6 +772,95 @@ pub struct RgaConfig {
     pub rg_version: bool,
 }
 
+/// Limits that keep rga from slowing down the rest of the machine, e.g. when warming up the cache in the background
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct ThrottleConfig {
+    /// Read each file with at most this many MB/s
+    ///
+    /// Applies to each file on its own, so rg's threads can read several files at this speed at the
+    /// same time. Programs that read the files themselves, like pdftotext, are not limited.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-throttle-read",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub read_mb_per_sec: Option<f64>,
+
+    /// Run the adapters for at most this many files at the same time
+    ///
+    /// Files read from the cache and files without an adapter don't count.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-throttle-jobs",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub jobs: Option<u64>,
+
+    /// Run rg, rga-preproc and the programs of the adapters with this niceness (1 - 19, unix only)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-throttle-nice",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub nice: Option<u8>,
+
+    /// Only read and write the disk when no other program needs it (ionice -c 3, Linux only)
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-throttle-idle-io", hidden_short_help = true)]
+    pub idle_io: bool,
+}
+
+/// Restrictions on the programs adapters run, e.g. on workstations where only approved tools may run.
+/// Usually set in the "policy" section of the config file
+#[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
+pub struct PolicyConfig {
+    /// Programs adapters must not run, e.g. curl,ffmpeg
+    ///
+    /// The adapters that need them fail on the files they were chosen for.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-policy-forbid",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub forbid: Vec<String>,
+
+    /// Forbid adapters that use the network, like tika
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-policy-no-network", hidden_short_help = true)]
+    pub no_network: bool,
+
+    /// Absolute paths of the programs adapters run, e.g. pdftotext=/usr/bin/pdftotext,tesseract=/usr/bin/tesseract
+    ///
+    /// Programs without a path here are looked up in PATH, unless --rga-policy-pinned-only is given.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-policy-tool-paths",
+        require_equals = true,
+        require_delimiter = true,
+        hidden_short_help = true
+    )]
+    pub tool_paths: Vec<String>,
+
+    /// Only run the programs that have a path in --rga-policy-tool-paths
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-policy-pinned-only", hidden_short_help = true)]
+    pub pinned_only: bool,
+
+    /// Append a line for every program an adapter runs, with the file it runs on, to this file
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        long = "--rga-policy-audit-log",
+        require_equals = true,
+        hidden_short_help = true
+    )]
+    pub audit_log: Option<String>,
+}
+
 #[derive(StructOpt, Debug, Deserialize, Serialize, JsonSchema, Default, Clone, PartialEq)]
 pub struct CacheConfig {
     /// Disable caching of results
@@ -209,7 +877,7 @@ pub struct CacheConfig {
 
     /// Max compressed size to cache
     ///
-    /// Longest byte length (after compression) to store in cache. Longer adapter outputs will not be cached and recomputed every time.
+    /// Longest byte length (after compression) to store in the cache database. Longer adapter outputs are written to files next to the database instead, up to --rga-cache-max-file-len. The outputs of the members of archives are cached separately, so only members with a longer output are recomputed.
     ///
     /// Allowed suffixes on command line: k M G
     #[serde(default, skip_serializing_if = "is_default")]
@@ -222,6 +890,20 @@ pub struct CacheConfig {
     )]
     pub max_blob_len: CacheMaxBlobLen,
 
+    /// Max compressed size to cache in a file
+    ///
+    /// Adapter outputs longer than --rga-cache-max-blob-len are cached in files in the files directory of the cache path, if they are at most this long (after compression). Longer outputs are recomputed every time. 0 disables these files.
+    ///
+    /// Allowed suffixes on command line: k M G
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-max-file-len",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub max_file_len: CacheMaxFileLen,
+
     /// ZSTD compression level to apply to adapter outputs before storing in cache db
     ///
     ///  Ranges from 1 - 22
@@ -235,6 +917,30 @@ pub struct CacheConfig {
     )]
     pub compression_level: CacheCompressionLevel,
 
+    /// Don't cache outputs that took less than this many milliseconds
+    ///
+    /// Outputs that are computed quickly, like those of small text files or plain decompression, are cheaper to recompute than to store and read back, so the cache is kept for slow adapters like OCR. The time is measured from starting the adapter until its output is read completely. 0 caches all outputs.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-min-time",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub min_time: CacheMinTime,
+
+    /// Don't cache outputs that took less than this many milliseconds per MB (after compression)
+    ///
+    /// Reading an output back from the cache takes longer the longer it is, so long outputs are only cached if computing them took long enough too. 0 disables this check.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(
+        default_value,
+        long = "--rga-cache-min-time-per-mb",
+        hidden_short_help = true,
+        require_equals = true
+    )]
+    pub min_time_per_mb: CacheMinTimePerMb,
+
     /// Path to store cache db
     #[serde(default, skip_serializing_if = "is_default")]
     #[structopt(
@@ -244,12 +950,33 @@ pub struct CacheConfig {
         require_equals = true
     )]
     pub path: CachePath,
+
+    /// Also cache the output of adapters that decrypt files
+    ///
+    /// The output of adapters like ansiblevault and sops contains secrets,
+    /// so by default it is never written to the cache.
+    #[serde(default, skip_serializing_if = "is_default")]
+    #[structopt(long = "--rga-cache-sensitive", hidden_short_help = true)]
+    pub cache_sensitive: bool,
 }
 
-static RGA_CONFIG: &str = "RGA_CONFIG";
+pub const RGA_CONFIG: &str = "RGA_CONFIG";
+
+lazy_static! {
+    /// shown by --version, with the optional backends so distributors can check their build
+    static ref VERSION: String = format!(
+        "{} (features: {})",
+        env!("CARGO_PKG_VERSION"),
+        if FEATURES.is_empty() {
+            "none".to_owned()
+        } else {
+            FEATURES.join(", ")
+        }
+    );
+}
 
 use serde_json::Value;
-fn json_merge(a: &mut Value, b: &Value) {
+pub(crate) fn json_merge(a: &mut Value, b: &Value) {
     match (a, b) {
         (&mut Value::Object(ref mut a), Value::Object(b)) => {
             for (k, v) in b {
@@ -262,59 +989,94 @@ fn json_merge(a: &mut Value, b: &Value) {
     }
 }
 
+/// the config file of the user
+pub fn user_config_path() -> Result<PathBuf> {
+    Ok(project_dirs()?.config_dir().join("config.jsonc"))
+}
+
+/// the config file for all users of the machine. The user's config file overrides its options,
+/// except for the ones in "policy", which it can only tighten
+pub fn machine_config_path() -> PathBuf {
+    if cfg!(windows) {
+        std::env::var_os("ProgramData")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(r"C:\ProgramData"))
+            .join("ripgrep-all")
+            .join("config.jsonc")
+    } else {
+        PathBuf::from("/etc/ripgrep-all/config.jsonc")
+    }
+}
+
+/// the options in a config file, checked against RgaConfig
+pub fn read_config_json(config_filename: &Path) -> Result<Value> {
+    let config_filename_str = config_filename.to_string_lossy();
+    let config_file_contents = {
+        let raw = std::fs::read_to_string(config_filename)
+            .with_context(|| format!("Could not read config file json {config_filename_str}"))?;
+        let mut s = String::new();
+        json_comments::StripComments::new(raw.as_bytes())
+            .read_to_string(&mut s)
+            .context("strip comments")?;
+        s
+    };
+    {
+        // just for error messages, actual deserialization happens after merging with cmd args
+        serde_json::from_str::<RgaConfig>(&config_file_contents).with_context(|| {
+            format!("Error in config file {config_filename_str}: {config_file_contents}")
+        })?;
+    }
+    serde_json::from_str(&config_file_contents).context("Could not parse config json")
+}
+
+/// write the default config and its schema, for users that have no config file yet
+pub fn write_default_config(config_filename: &Path) -> Result<()> {
+    let config_dir = config_filename.parent().context("no config directory")?;
+    std::fs::create_dir_all(config_dir)?;
+    let mut schemafile = File::create(config_dir.join("config.v1.schema.json"))?;
+
+    schemafile
+        .write_all(serde_json::to_string_pretty(&schemars::schema_for!(RgaConfig))?.as_bytes())?;
+
+    let mut configfile = File::create(config_filename)?;
+    configfile.write_all(include_str!("../doc/config.default.jsonc").as_bytes())?;
+    Ok(())
+}
+
 fn read_config_file(path_override: Option<String>) -> Result<(String, Value)> {
-    let proj = project_dirs()?;
-    let config_dir = proj.config_dir();
-    let config_filename = path_override
-        .as_ref()
-        .map(PathBuf::from)
-        .unwrap_or_else(|| config_dir.join("config.jsonc"));
+    let config_filename = match &path_override {
+        Some(path) => PathBuf::from(path),
+        None => user_config_path()?,
+    };
     let config_filename_str = config_filename.to_string_lossy().into_owned();
+    // the options of the machine, overridden by the ones of the user
+    let machine_config_filename = machine_config_path();
+    let mut config_json = if machine_config_filename.exists() {
+        read_config_json(&machine_config_filename)?
+    } else {
+        serde_json::Value::Object(Default::default())
+    };
     if config_filename.exists() {
-        let config_file_contents = {
-            let raw = std::fs::read_to_string(config_filename).with_context(|| {
-                format!("Could not read config file json {config_filename_str}")
-            })?;
-            let mut s = String::new();
-            json_comments::StripComments::new(raw.as_bytes())
-                .read_to_string(&mut s)
-                .context("strip comments")?;
-            s
-        };
-        {
-            // just for error messages, actual deserialization happens after merging with cmd args
-            serde_json::from_str::<RgaConfig>(&config_file_contents).with_context(|| {
-                format!("Error in config file {config_filename_str}: {config_file_contents}")
-            })?;
-        }
-        let config_json: serde_json::Value =
-            serde_json::from_str(&config_file_contents).context("Could not parse config json")?;
-        Ok((config_filename_str, config_json))
+        json_merge(&mut config_json, &read_config_json(&config_filename)?);
     } else if let Some(p) = path_override.as_ref() {
         Err(anyhow::anyhow!("Config file not found: {}", p))?
     } else {
-        // write default config
-        std::fs::create_dir_all(config_dir)?;
-        let mut schemafile = File::create(config_dir.join("config.v1.schema.json"))?;
-
-        schemafile.write_all(
-            serde_json::to_string_pretty(&schemars::schema_for!(RgaConfig))?.as_bytes(),
-        )?;
-
-        let mut configfile = File::create(config_filename)?;
-        configfile.write_all(include_str!("../doc/config.default.jsonc").as_bytes())?;
-        Ok((
-            config_filename_str,
-            serde_json::Value::Object(Default::default()),
-        ))
+        write_default_config(&config_filename)?;
     }
+    // the adapters installed with --rga-adapter-add, unless the config files define one of the same name
+    adapter_store::merge_installed(&mut config_json)?;
+    Ok((config_filename_str, config_json))
 }
 fn read_config_env() -> Result<Value> {
     let val = std::env::var(RGA_CONFIG).ok();
     if let Some(val) = val {
         serde_json::from_str(&val).context("could not parse config from env RGA_CONFIG")
     } else {
-        serde_json::to_value(RgaConfig::default()).context("could not create default config")
+        let mut config = serde_json::to_value(RgaConfig::default())
+            .context("could not create default config")?;
+        // when rga runs rga-preproc, RGA_CONFIG already contains them
+        json_merge(&mut config, &config_env::env_config()?);
+        Ok(config)
     }
 }
 pub fn parse_args<I>(args: I, is_rga_preproc: bool) -> Result<RgaConfig>
@@ -324,11 +1086,21 @@ where
 {
     // TODO: don't read config file in rga-preproc for performance (called for every file)
 
-    let arg_matches: RgaConfig = RgaConfig::from_iter(args);
+    let arg_matches = RgaConfig::from_clap(
+        &RgaConfig::clap()
+            .version(VERSION.as_str())
+            .get_matches_from(args),
+    );
     let args_config = serde_json::to_value(&arg_matches)?;
 
-    let merged_config = {
-        if is_rga_preproc {
+    // the config commands read the config files themselves, and have to work when one is invalid
+    let is_config_command = arg_matches.config_get.is_some()
+        || arg_matches.config_set.is_some()
+        || arg_matches.config_edit
+        || arg_matches.config_validate.is_some()
+        || arg_matches.print_config_schema;
+    let mut merged_config = {
+        if is_rga_preproc || is_config_command {
             // only read from env and args
             let mut merged_config = read_config_env()?;
             json_merge(&mut merged_config, &args_config);
@@ -337,7 +1109,7 @@ where
         } else {
             // read from config file, env and args
             let (config_filename, config_file_config) =
-                read_config_file(arg_matches.config_file_path)?;
+                read_config_file(arg_matches.config_file_path.clone())?;
             let env_var_config = read_config_env()?;
             let mut merged_config = config_file_config.clone();
             json_merge(&mut merged_config, &env_var_config);
@@ -351,11 +1123,22 @@ where
                 serde_json::to_string_pretty(&args_config)?,
                 serde_json::to_string_pretty(&merged_config)?
             );
-            // pass to child processes
-            std::env::set_var(RGA_CONFIG, merged_config.to_string());
             merged_config
         }
     };
+    let machine_config_filename = machine_config_path();
+    if !is_config_command && machine_config_filename.exists() {
+        // the policy of the machine comes last, so nothing can loosen it. rga-preproc applies it
+        // again, since RGA_CONFIG can be set by hand
+        policy::enforce_machine(
+            &mut merged_config,
+            &read_config_json(&machine_config_filename)?,
+        )?;
+    }
+    if !is_rga_preproc && !is_config_command {
+        // pass to child processes
+        std::env::set_var(RGA_CONFIG, merged_config.to_string());
+    }
 
     let mut res: RgaConfig = serde_json::from_value(merged_config.clone())
         .map_err(|e| {
@@ -370,12 +1153,58 @@ where
         })?;
     {
         // readd values with [serde(skip)]
+        res.config_file_path = arg_matches.config_file_path;
         res.fzf_path = arg_matches.fzf_path;
+        res.stats_json = arg_matches.stats_json;
+        res.stats_prometheus = arg_matches.stats_prometheus;
+        res.job = arg_matches.job;
+        res.queries = arg_matches.queries;
+        res.report = arg_matches.report;
+        res.report_file = arg_matches.report_file;
+        res.retry_failed = arg_matches.retry_failed;
+        res.quarantine_add = arg_matches.quarantine_add;
+        res.quarantine_remove = arg_matches.quarantine_remove;
+        res.quarantine_list = arg_matches.quarantine_list;
         res.list_adapters = arg_matches.list_adapters;
         res.print_config_schema = arg_matches.print_config_schema;
+        res.estimate = arg_matches.estimate;
+        res.self_test = arg_matches.self_test;
+        res.install_deps = arg_matches.install_deps;
+        res.install_deps_run = arg_matches.install_deps_run;
+        res.config_get = arg_matches.config_get;
+        res.config_set = arg_matches.config_set;
+        res.config_edit = arg_matches.config_edit;
+        res.config_validate = arg_matches.config_validate;
+        res.adapter_add = arg_matches.adapter_add;
+        res.adapter_list = arg_matches.adapter_list;
+        res.config_machine = arg_matches.config_machine;
+        res.self_update = arg_matches.self_update;
         res.rg_help = arg_matches.rg_help;
         res.rg_version = arg_matches.rg_version;
     }
+    if !is_rga_preproc && res.report.is_some() && !res.prefix_column {
+        // the report reads the locations of the matches from the prefix column
+        res.prefix_column = true;
+        merged_config["prefix_column"] = true.into();
+        std::env::set_var(RGA_CONFIG, merged_config.to_string());
+    }
+    if !is_rga_preproc && res.strategy.as_deref() == Some("shallow") && !res.meta_only {
+        // rga-preproc only reads the metadata
+        res.meta_only = true;
+        merged_config["meta_only"] = true.into();
+        std::env::set_var(RGA_CONFIG, merged_config.to_string());
+    }
+    if !is_rga_preproc && !res.battery_pause.is_empty() && power::on_battery() {
+        let adapters = power::adapters_on_battery(&res)?;
+        eprintln!(
+            "[rga: on battery power, pausing adapters {}]",
+            res.battery_pause.join(",")
+        );
+        res.adapters = adapters;
+        // rga-preproc gets the adapters from the environment
+        merged_config["adapters"] = serde_json::to_value(&res.adapters)?;
+        std::env::set_var(RGA_CONFIG, merged_config.to_string());
+    }
     Ok(res)
 }
 
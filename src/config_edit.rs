@@ -0,0 +1,462 @@
+//! --rga-config-get, --rga-config-set and --rga-config-edit: read and change the config files from
+//! scripts and dotfile managers, checked against the schema of RgaConfig. Options are addressed by
+//! their path in the config file, like `ocr_languages` or `cache.max_blob_len`.
+//! --rga-config-validate checks a config file without running rga.
+
+use crate::adapters::custom::map_exe_error;
+use crate::config::{
+    json_merge, machine_config_path, read_config_json, user_config_path, write_default_config,
+    RgaConfig,
+};
+use crate::policy;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// the config file --rga-config-set and --rga-config-edit change
+fn config_path(config: &RgaConfig) -> Result<PathBuf> {
+    if config.config_machine {
+        return Ok(machine_config_path());
+    }
+    match &config.config_file_path {
+        Some(path) => Ok(PathBuf::from(path)),
+        None => user_config_path(),
+    }
+}
+
+/// the definition a `$ref` in the schema points to, or the schema itself. schemars puts references
+/// in `allOf` when the field has a description
+pub(crate) fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    let reference = schema
+        .get("$ref")
+        .or_else(|| schema.get("allOf")?.get(0)?.get("$ref"))
+        .and_then(|r| r.as_str())
+        .and_then(|r| r.strip_prefix("#/definitions/"));
+    match reference.and_then(|name| root.get("definitions")?.get(name)) {
+        Some(definition) => definition,
+        None => schema,
+    }
+}
+
+/// the schema of the option at `key`, an error for options that are not in the config file
+fn option_schema<'a>(root: &'a Value, key: &str) -> Result<&'a Value> {
+    let mut schema = root;
+    for part in key.split('.') {
+        schema = match resolve(root, schema)
+            .get("properties")
+            .and_then(|p| p.get(part))
+        {
+            Some(schema) => schema,
+            None => bail!(
+                "{} is not an option of the config file, see --rga-print-config-schema",
+                key
+            ),
+        };
+    }
+    Ok(resolve(root, schema))
+}
+
+/// `-` can be used instead of `_`, like in the command line options
+fn normalize_key(key: &str) -> String {
+    key.trim().replace('-', "_")
+}
+
+/// the value of --rga-config-set as JSON. Values that are not JSON are strings, or comma separated
+/// lists for list options, like on the command line
+fn parse_value(schema: &Value, value: &str) -> Value {
+    if let Ok(value) = serde_json::from_str(value) {
+        return value;
+    }
+    let is_list = schema.get("type").map_or(false, |t| {
+        t == "array"
+            || t.as_array()
+                .map_or(false, |t| t.iter().any(|t| t == "array"))
+    });
+    if is_list {
+        Value::Array(
+            value
+                .split(',')
+                .map(|v| Value::String(v.to_owned()))
+                .collect(),
+        )
+    } else {
+        Value::String(value.to_owned())
+    }
+}
+
+fn lookup<'a>(json: &'a Value, key: &str) -> Option<&'a Value> {
+    key.split('.').try_fold(json, |json, part| json.get(part))
+}
+
+/// set the option at `key`, or remove it if the value is null
+pub(crate) fn set_key(json: &mut Value, key: &str, value: Value) {
+    let mut object = json;
+    let mut parts = key.split('.').peekable();
+    while let Some(part) = parts.next() {
+        if !object.is_object() {
+            *object = Value::Object(Default::default());
+        }
+        let map = object.as_object_mut().expect("is an object");
+        if parts.peek().is_none() {
+            if value.is_null() {
+                map.remove(part);
+            } else {
+                map.insert(part.to_owned(), value);
+            }
+            return;
+        }
+        object = map
+            .entry(part)
+            .or_insert_with(|| Value::Object(Default::default()));
+    }
+}
+
+/// --rga-config-get: the value of the option in the config files of the machine and the user, None
+/// if it is not set in any of them
+pub fn get(config: &RgaConfig, key: &str) -> Result<Option<Value>> {
+    let key = normalize_key(key);
+    let schema = serde_json::to_value(schemars::schema_for!(RgaConfig))?;
+    option_schema(&schema, &key)?;
+    let mut paths = vec![machine_config_path()];
+    if !config.config_machine {
+        paths.push(config_path(config)?);
+    }
+    let mut merged = Value::Object(Default::default());
+    for path in paths.iter().filter(|p| p.exists()) {
+        json_merge(&mut merged, &read_config_json(path)?);
+    }
+    if paths[0].exists() {
+        policy::enforce_machine(&mut merged, &read_config_json(&paths[0])?)?;
+    }
+    Ok(lookup(&merged, &key).filter(|v| !v.is_null()).cloned())
+}
+
+/// --rga-config-set: set `key=value` in the config file. The comments of the file are not kept
+pub fn set(config: &RgaConfig, assignment: &str) -> Result<()> {
+    let (key, value) = match assignment.split_once('=') {
+        Some((key, value)) => (normalize_key(key), value),
+        None => bail!("--rga-config-set needs key=value, got {}", assignment),
+    };
+    let schema = serde_json::to_value(schemars::schema_for!(RgaConfig))?;
+    let value = parse_value(option_schema(&schema, &key)?, value);
+    let path = config_path(config)?;
+    if !path.exists() && !config.config_machine && config.config_file_path.is_none() {
+        write_default_config(&path)?;
+    }
+    let mut json = if path.exists() {
+        read_config_json(&path)?
+    } else {
+        Value::Object(Default::default())
+    };
+    set_key(&mut json, &key, value);
+    serde_json::from_value::<RgaConfig>(json.clone())
+        .with_context(|| format!("invalid value for {key}"))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, serde_json::to_string_pretty(&json)? + "\n")
+        .with_context(|| format!("writing {}", path.display()))?;
+    Ok(())
+}
+
+/// --rga-config-edit: open the config file in the editor of the user and check it when it is closed
+pub fn edit(config: &RgaConfig) -> Result<()> {
+    let path = config_path(config)?;
+    if !path.exists() {
+        if config.config_machine {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(&path, "{\n}\n")
+                .with_context(|| format!("writing {}", path.display()))?;
+        } else if config.config_file_path.is_none() {
+            write_default_config(&path)?;
+        } else {
+            bail!("Config file not found: {}", path.display());
+        }
+    }
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| if cfg!(windows) { "notepad" } else { "vi" }.to_owned());
+    // editors are often set with arguments, like `code --wait`
+    let mut words = editor.split_whitespace();
+    let program = words.next().context("$EDITOR is empty")?;
+    let status = std::process::Command::new(program)
+        .args(words)
+        .arg(&path)
+        .status()
+        .map_err(|e| map_exe_error(e, program, "Set $EDITOR to your editor."))?;
+    if !status.success() {
+        bail!("{} failed: {}", program, status);
+    }
+    let problems = validate(&path)?;
+    if !problems.is_empty() {
+        bail!(
+            "{}\n{} is invalid, fix it with --rga-config-edit",
+            problems.join("\n"),
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// the positions of the keys in a JSON document as line and column, by their path like
+/// `cache.max_blob_len`. Array elements have their index in the path, like `custom_adapters.0.name`
+fn key_positions(json: &str) -> HashMap<String, (usize, usize)> {
+    let mut positions = HashMap::new();
+    // the open objects and arrays, with the current key or index
+    let mut stack: Vec<(bool, String)> = vec![];
+    let mut expect_key = false;
+    let (mut line, mut column) = (1, 0);
+    let mut chars = json.chars();
+    while let Some(c) = chars.next() {
+        if c == '\n' {
+            line += 1;
+            column = 0;
+        } else {
+            column += 1;
+        }
+        match c {
+            '{' => {
+                stack.push((true, String::new()));
+                expect_key = true;
+            }
+            '[' => stack.push((false, "0".to_owned())),
+            '}' | ']' => {
+                stack.pop();
+                expect_key = false;
+            }
+            ',' => match stack.last_mut() {
+                Some((true, _)) => expect_key = true,
+                Some((false, index)) => {
+                    *index = (index.parse::<usize>().unwrap_or_default() + 1).to_string()
+                }
+                None => {}
+            },
+            '"' => {
+                let start = (line, column);
+                let mut string = String::new();
+                while let Some(c) = chars.next() {
+                    column += 1;
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            if let Some(c) = chars.next() {
+                                column += 1;
+                                string.push(c);
+                            }
+                        }
+                        c => string.push(c),
+                    }
+                }
+                if expect_key {
+                    if let Some((_, key)) = stack.last_mut() {
+                        *key = string;
+                    }
+                    let path = stack.iter().map(|(_, k)| k.as_str());
+                    positions.insert(path.collect::<Vec<_>>().join("."), start);
+                    expect_key = false;
+                }
+            }
+            _ => {}
+        }
+    }
+    positions
+}
+
+/// the number of characters to insert, delete or replace to turn `a` into `b`
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let next = (row[j + 1] + 1)
+                .min(row[j] + 1)
+                .min(diagonal + usize::from(ca != *cb));
+            diagonal = row[j + 1];
+            row[j + 1] = next;
+        }
+    }
+    row[b.len()]
+}
+
+/// the options in `json` that are not in the schema, by their path, with the option that was
+/// probably meant. serde ignores them, so a typo would silently have no effect
+fn unknown_keys(
+    root: &Value,
+    schema: &Value,
+    json: &Value,
+    path: &str,
+    out: &mut Vec<(String, Option<String>)>,
+) {
+    let schema = resolve(root, schema);
+    let join = |key: &str| {
+        if path.is_empty() {
+            key.to_owned()
+        } else {
+            format!("{path}.{key}")
+        }
+    };
+    match json {
+        Value::Object(object) => {
+            let properties = match schema.get("properties").and_then(|p| p.as_object()) {
+                Some(properties) => properties,
+                None => return,
+            };
+            for (key, value) in object {
+                if path.is_empty() && key == "$schema" {
+                    continue;
+                }
+                match properties.get(key) {
+                    Some(property) => unknown_keys(root, property, value, &join(key), out),
+                    None => {
+                        let normalized = normalize_key(key).to_ascii_lowercase();
+                        let meant = properties
+                            .keys()
+                            .map(|p| (edit_distance(&normalized, p), p))
+                            .filter(|(distance, _)| *distance <= 2)
+                            .min()
+                            .map(|(_, p)| join(p));
+                        out.push((join(key), meant));
+                    }
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item) = schema.get("items") {
+                for (i, value) in items.iter().enumerate() {
+                    unknown_keys(root, item, value, &join(&i.to_string()), out);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// --rga-config-validate: the problems of a config file, like
+/// `config.jsonc: unknown option cache.max_blob_size at line 4 column 5`. Empty if it is valid
+pub fn validate(path: &Path) -> Result<Vec<String>> {
+    let raw =
+        std::fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+    // comments are replaced by spaces, so the lines and columns stay the same
+    let mut text = String::new();
+    json_comments::StripComments::new(raw.as_bytes())
+        .read_to_string(&mut text)
+        .context("strip comments")?;
+    let json = match serde_json::from_str::<Value>(&text) {
+        Ok(json) => json,
+        Err(e) => return Ok(vec![format!("{}: {}", path.display(), e)]),
+    };
+    let mut problems = vec![];
+    let schema = serde_json::to_value(schemars::schema_for!(RgaConfig))?;
+    let mut unknown = vec![];
+    unknown_keys(&schema, &schema, &json, "", &mut unknown);
+    let positions = key_positions(&text);
+    for (key, meant) in unknown {
+        let mut problem = format!("{}: unknown option {}", path.display(), key);
+        if let Some((line, column)) = positions.get(&key) {
+            problem += &format!(" at line {line} column {column}");
+        }
+        if let Some(meant) = meant {
+            problem += &format!(", did you mean {meant}?");
+        }
+        problems.push(problem);
+    }
+    if let Err(e) = serde_json::from_str::<RgaConfig>(&text) {
+        problems.push(format!("{}: {}", path.display(), e));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+
+    #[test]
+    fn keys() -> Result<()> {
+        let schema = serde_json::to_value(schemars::schema_for!(RgaConfig))?;
+        assert!(option_schema(&schema, "ocr_languages").is_ok());
+        assert!(option_schema(&schema, &normalize_key("cache.max-blob-len")).is_ok());
+        assert!(option_schema(&schema, "throttle.jobs").is_ok());
+        // only on the command line
+        assert!(option_schema(&schema, "stats_json").is_err());
+        assert!(option_schema(&schema, "cache.no_such_option").is_err());
+        assert_eq!(
+            parse_value(option_schema(&schema, "ocr_languages")?, "eng,deu"),
+            json!(["eng", "deu"])
+        );
+        assert_eq!(
+            parse_value(option_schema(&schema, "ocr_backend")?, "kraken"),
+            json!("kraken")
+        );
+        assert_eq!(
+            parse_value(option_schema(&schema, "accurate")?, "true"),
+            json!(true)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn set_keys() {
+        let mut config = json!({"$schema": "./config.v1.schema.json", "accurate": true});
+        set_key(&mut config, "cache.max_blob_len", json!(1000));
+        set_key(&mut config, "accurate", Value::Null);
+        assert_eq!(
+            config,
+            json!({"$schema": "./config.v1.schema.json", "cache": {"max_blob_len": 1000}})
+        );
+        assert_eq!(lookup(&config, "cache.max_blob_len"), Some(&json!(1000)));
+        assert_eq!(lookup(&config, "cache.disabled"), None);
+    }
+
+    #[test]
+    fn positions() {
+        let positions = key_positions("{\"a\": [{\"b\\\"\": 1}, {\"c\": {}}],\n \"d\": 2}");
+        assert_eq!(positions.get("a"), Some(&(1, 2)));
+        assert_eq!(positions.get("a.0.b\""), Some(&(1, 9)));
+        assert_eq!(positions.get("a.1.c"), Some(&(1, 21)));
+        assert_eq!(positions.get("d"), Some(&(2, 2)));
+        assert_eq!(edit_distance("ocr_langauges", "ocr_languages"), 2);
+    }
+
+    #[test]
+    fn validate_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("config.jsonc");
+        std::fs::write(
+            &path,
+            r#"{
+  // the cache
+  "cache": {"max-blob-len": 1000},
+  "ocr_langauges": ["eng"],
+  "accurate": "yes"
+}"#,
+        )?;
+        let problems = validate(&path)?;
+        let file = path.display();
+        assert_eq!(problems.len(), 3);
+        assert_eq!(
+            problems[0],
+            format!("{file}: unknown option cache.max-blob-len at line 3 column 13, did you mean cache.max_blob_len?")
+        );
+        assert_eq!(
+            problems[1],
+            format!("{file}: unknown option ocr_langauges at line 4 column 3, did you mean ocr_languages?")
+        );
+        assert!(problems[2].contains("expected a boolean at line 5"));
+        std::fs::write(&path, r#"{"accurate": true,}"#)?;
+        assert!(validate(&path)?[0].contains("trailing comma at line 1"));
+        std::fs::write(
+            &path,
+            r#"{"$schema": "./config.v1.schema.json", "accurate": true}"#,
+        )?;
+        assert!(validate(&path)?.is_empty());
+        Ok(())
+    }
+}
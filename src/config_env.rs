@@ -0,0 +1,197 @@
+//! RGA_* environment variables for the options of the config file, like RGA_ACCURATE=true or
+//! RGA_CACHE_MAX_BLOB_LEN=10M, to configure rga in containers and CI without a config file. They
+//! are generated from the schema of RgaConfig, so every option has one: the path of the option in
+//! the config file in upper case, joined with _.
+
+use crate::config::{parse_readable_bytes_str, RgaConfig, RGA_CONFIG};
+use crate::config_edit::{resolve, set_key};
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+
+/// the variables of rga that are not options. An option with one of these names would be ambiguous
+static RESERVED: &[&str] = &[
+    RGA_CONFIG,
+    crate::stats::STATS_DIR,
+    crate::job::JOB_FILE,
+    crate::throttle::SLOTS_DIR,
+    crate::install::HINTS_DIR,
+    crate::strategy::DEEP_FILES,
+    "RGA_FZF_INSTANCE",
+];
+
+/// an environment variable and the option it sets, with the path and the schema of the option
+pub struct Binding {
+    pub var: String,
+    pub path: Vec<String>,
+    schema: Value,
+}
+
+fn collect(root: &Value, schema: &Value, prefix: &str, path: &[String], out: &mut Vec<Binding>) {
+    let properties = match resolve(root, schema)
+        .get("properties")
+        .and_then(|p| p.as_object())
+    {
+        Some(properties) => properties,
+        None => return,
+    };
+    for (name, property) in properties {
+        let var = format!("{}_{}", prefix, name.to_ascii_uppercase());
+        let mut path = path.to_vec();
+        path.push(name.clone());
+        let property = resolve(root, property);
+        // the options in groups like cache get a variable each, like RGA_CACHE_DISABLED
+        if property.get("properties").is_some() {
+            collect(root, property, &var, &path, out);
+        } else {
+            out.push(Binding {
+                var,
+                path,
+                schema: property.clone(),
+            });
+        }
+    }
+}
+
+/// the environment variables of all options
+pub fn bindings() -> Result<Vec<Binding>> {
+    let root = serde_json::to_value(schemars::schema_for!(RgaConfig))?;
+    let mut out = vec![];
+    collect(&root, &root, "RGA", &[], &mut out);
+    Ok(out)
+}
+
+/// the value of a variable as JSON, by the type of the option. Lists are comma separated like on
+/// the command line, or JSON like the list of custom adapters
+fn parse(schema: &Value, value: &str) -> Result<Value> {
+    let types = match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(|t| t.as_str()).collect(),
+        _ => vec![],
+    };
+    let has = |t: &str| types.contains(&t);
+    let value = value.trim();
+    if has("boolean") {
+        return match value.to_ascii_lowercase().as_str() {
+            "1" | "true" | "yes" | "on" => Ok(Value::Bool(true)),
+            "0" | "false" | "no" | "off" => Ok(Value::Bool(false)),
+            _ => bail!("expected true or false, got {}", value),
+        };
+    }
+    if has("integer") || has("number") {
+        if let Ok(number) = serde_json::from_str::<serde_json::Number>(value) {
+            return Ok(Value::Number(number));
+        }
+        // byte counts like 10M
+        return Ok(Value::from(parse_readable_bytes_str(value)?));
+    }
+    if has("array") && !value.starts_with('[') {
+        return Ok(Value::Array(
+            value
+                .split(',')
+                .filter(|v| !v.is_empty())
+                .map(|v| Value::String(v.to_owned()))
+                .collect(),
+        ));
+    }
+    if has("string") {
+        return Ok(Value::String(value.to_owned()));
+    }
+    serde_json::from_str(value).context("expected JSON")
+}
+
+/// the options set with RGA_* variables, to merge over the ones of the config files
+pub fn env_config() -> Result<Value> {
+    let mut config = Value::Object(Default::default());
+    let is_set = std::env::vars_os().any(|(var, _)| {
+        var.to_str().map_or(false, |var| {
+            var.starts_with("RGA_") && !RESERVED.contains(&var)
+        })
+    });
+    if !is_set {
+        return Ok(config);
+    }
+    for binding in bindings()? {
+        let value = match std::env::var(&binding.var) {
+            Ok(value) if !value.trim().is_empty() => value,
+            _ => continue,
+        };
+        log::debug!("{}={}", binding.var, value);
+        let value = parse(&binding.schema, &value)
+            .with_context(|| format!("invalid value in {}", binding.var))?;
+        set_key(&mut config, &binding.path.join("."), value);
+    }
+    // checked like a config file
+    serde_json::from_value::<RgaConfig>(config.clone())
+        .context("invalid options in RGA_* environment variables")?;
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use serde_json::json;
+    use std::collections::HashSet;
+
+    fn schema_of(var: &str) -> Value {
+        bindings()
+            .unwrap()
+            .into_iter()
+            .find(|b| b.var == var)
+            .unwrap_or_else(|| panic!("no binding {var}"))
+            .schema
+    }
+
+    #[test]
+    fn names() -> Result<()> {
+        let bindings = bindings()?;
+        let vars = bindings
+            .iter()
+            .map(|b| b.var.as_str())
+            .collect::<HashSet<_>>();
+        assert_eq!(vars.len(), bindings.len());
+        for var in [
+            "RGA_ACCURATE",
+            "RGA_CACHE_MAX_BLOB_LEN",
+            "RGA_THROTTLE_JOBS",
+        ] {
+            assert!(vars.contains(&var), "{var}");
+        }
+        assert!(vars.contains(&"RGA_CUSTOM_ADAPTERS"));
+        // only on the command line
+        assert!(!vars.contains(&"RGA_STATS_JSON"));
+        for var in RESERVED {
+            assert!(!vars.contains(var), "{var}");
+        }
+        let cache = bindings
+            .iter()
+            .find(|b| b.var == "RGA_CACHE_DISABLED")
+            .unwrap();
+        assert_eq!(cache.path, ["cache", "disabled"]);
+        Ok(())
+    }
+
+    #[test]
+    fn values() -> Result<()> {
+        assert_eq!(parse(&schema_of("RGA_ACCURATE"), "yes")?, json!(true));
+        assert!(parse(&schema_of("RGA_ACCURATE"), "maybe").is_err());
+        assert_eq!(
+            parse(&schema_of("RGA_CACHE_MAX_BLOB_LEN"), "10M")?,
+            json!(10_000_000)
+        );
+        assert_eq!(parse(&schema_of("RGA_OCR_PSM"), "6")?, json!(6));
+        assert_eq!(
+            parse(&schema_of("RGA_ADAPTERS"), "-ffmpeg,ocr")?,
+            json!(["-ffmpeg", "ocr"])
+        );
+        assert_eq!(parse(&schema_of("RGA_OCR_MODEL"), "123")?, json!("123"));
+        assert_eq!(
+            parse(
+                &schema_of("RGA_CUSTOM_ADAPTERS"),
+                r#"[{"name": "x", "version": 1}]"#
+            )?,
+            json!([{"name": "x", "version": 1}])
+        );
+        Ok(())
+    }
+}
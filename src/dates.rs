@@ -0,0 +1,522 @@
+//! --rga-newer-than and --rga-older-than: search only the documents from a time range. The date of
+//! a document is the one inside it if rga can find it without adapting the document (the creation
+//! date of a PDF, the Date header of an email, the EXIF timestamp of a photo), otherwise the
+//! modification time of the file. rga-preproc checks the date of a file before adapting it, the
+//! members of archives are not filtered.
+
+use crate::config::RgaConfig;
+use anyhow::{format_err, Context, Result};
+use memchr::memmem;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// how much of the start and of the end of a file is searched for its date
+pub(crate) const SCAN_LEN: u64 = 64 * 1024;
+
+static MONTHS: &[&str] = &[
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// the days since 1970-01-01 of a date, see http://howardhinnant.github.io/date_algorithms.html
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// the year, month and day of the days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+    (yoe + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// the seconds since 1970 of a date and time in UTC, if it is valid
+fn timestamp(year: i64, month: u32, day: u32, hour: u32, minute: u32, second: u32) -> Option<i64> {
+    if !(1..=12).contains(&month)
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86400 + i64::from(hour * 3600 + minute * 60 + second))
+}
+
+/// the number of ASCII digits `digits`
+fn number(digits: &[u8]) -> Option<u32> {
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// the offset from UTC in seconds of a time zone like `+01'00'`, `-0500`, `+01:00` or `Z`
+fn zone_offset(zone: &[u8]) -> i64 {
+    let sign = match zone.first() {
+        Some(b'+') => 1,
+        Some(b'-') => -1,
+        _ => return 0,
+    };
+    let digits = zone[1..]
+        .iter()
+        .copied()
+        .filter(u8::is_ascii_digit)
+        .take(4)
+        .collect::<Vec<_>>();
+    let hours = digits.get(..2).and_then(number).unwrap_or(0);
+    let minutes = digits.get(2..4).and_then(number).unwrap_or(0);
+    sign * i64::from(hours * 3600 + minutes * 60)
+}
+
+/// a date like `2023-01-31`, `2023-01-31T12:00:00.5+01:00` or `2023-01-31 12:00` (UTC without a zone)
+fn iso_date(s: &str) -> Option<i64> {
+    let s = s.trim().as_bytes();
+    let date = s.get(..10)?;
+    if date[4] != b'-' || date[7] != b'-' {
+        return None;
+    }
+    let rest = &s[10..];
+    let (mut hour, mut minute, mut second) = (0, 0, 0);
+    let mut zone: &[u8] = b"";
+    if let Some(time) = rest.strip_prefix(b"T").or_else(|| rest.strip_prefix(b" ")) {
+        if time.get(2) != Some(&b':') {
+            return None;
+        }
+        hour = number(time.get(..2)?)?;
+        minute = number(time.get(3..5)?)?;
+        let mut rest = &time[5..];
+        if rest.first() == Some(&b':') {
+            second = number(rest.get(1..3)?)?;
+            rest = &rest[3..];
+        }
+        // fractions of a second
+        let fraction = rest
+            .iter()
+            .take_while(|&&b| b == b'.' || b.is_ascii_digit())
+            .count();
+        zone = &rest[fraction..];
+    } else if !rest.is_empty() {
+        return None;
+    }
+    let time = timestamp(
+        number(&date[..4])?.into(),
+        number(&date[5..7])?,
+        number(&date[8..10])?,
+        hour,
+        minute,
+        second,
+    )?;
+    Some(time - zone_offset(zone))
+}
+
+/// a PDF date like `D:20230131120000+01'00'`, of which everything after the year is optional
+fn pdf_date(s: &[u8]) -> Option<i64> {
+    let s = s.strip_prefix(b"D:").unwrap_or(s);
+    let len = s.iter().take_while(|b| b.is_ascii_digit()).count();
+    let (digits, zone) = s.split_at(len);
+    let field = |start: usize, default: u32| match digits.get(start..start + 2) {
+        Some(field) => number(field),
+        None => Some(default),
+    };
+    let time = timestamp(
+        number(digits.get(..4)?)?.into(),
+        field(4, 1)?,
+        field(6, 1)?,
+        field(8, 0)?,
+        field(10, 0)?,
+        field(12, 0)?,
+    )?;
+    Some(time - zone_offset(zone))
+}
+
+/// the date of an email, like `Tue, 3 Jul 2001 10:20:30 +0200`
+fn mail_date(s: &str) -> Option<i64> {
+    let mut tokens = s
+        .split_whitespace()
+        // the day of the week
+        .skip_while(|t| t.chars().next().map_or(false, |c| c.is_alphabetic()));
+    let day: u32 = tokens.next()?.parse().ok()?;
+    let month = tokens.next()?.to_ascii_lowercase();
+    let month = MONTHS.iter().position(|m| month.starts_with(m))? as u32 + 1;
+    let year: i64 = tokens.next()?.parse().ok()?;
+    let year = match year {
+        0..=49 => year + 2000,
+        50..=999 => year + 1900,
+        _ => year,
+    };
+    let mut time = tokens.next()?.split(':').map(|t| t.parse::<u32>().ok());
+    let hour = time.next()??;
+    let minute = time.next()??;
+    let second = time.next().unwrap_or(Some(0))?;
+    let zone = tokens.next().unwrap_or_default();
+    Some(timestamp(year, month, day, hour, minute, second)? - zone_offset(zone.as_bytes()))
+}
+
+/// the Date header of the email starting with `head`
+fn mail_header_date(head: &[u8]) -> Option<i64> {
+    String::from_utf8_lossy(head)
+        .lines()
+        .take_while(|l| !l.trim().is_empty())
+        .find_map(|l| {
+            let (name, value) = l.split_once(':')?;
+            if name.eq_ignore_ascii_case("date") {
+                mail_date(value)
+            } else {
+                None
+            }
+        })
+}
+
+/// the /CreationDate of the document information of a PDF. The last one is the newest, since
+/// changes are appended to PDF files
+fn pdf_creation_date(data: &[u8]) -> Option<i64> {
+    const KEY: &[u8] = b"/CreationDate";
+    let value = &data[memmem::rfind(data, KEY)? + KEY.len()..];
+    let start = value.iter().take(4).position(|&b| b == b'(')? + 1;
+    let len = value[start..].iter().position(|&b| b == b')')?;
+    pdf_date(&value[start..start + len])
+}
+
+/// the xmp:CreateDate of XMP metadata, as an element or as an attribute
+fn xmp_create_date(data: &[u8]) -> Option<i64> {
+    const KEY: &[u8] = b"xmp:CreateDate";
+    let value = &data[memmem::find(data, KEY)? + KEY.len()..];
+    let value = match value.first()? {
+        b'>' => &value[1..],
+        b'=' => value.get(2..)?,
+        _ => return None,
+    };
+    let len = value
+        .iter()
+        .take(64)
+        .position(|&b| b == b'<' || b == b'"' || b == b'\'')?;
+    iso_date(std::str::from_utf8(&value[..len]).ok()?)
+}
+
+/// an EXIF date like `2023:01:31 12:00:00`, in local time which is taken as UTC
+fn exif_datetime(s: &[u8]) -> Option<i64> {
+    let field = |start: usize| number(s.get(start..start + 2)?);
+    timestamp(
+        number(s.get(..4)?)?.into(),
+        field(5)?,
+        field(8)?,
+        field(11)?,
+        field(14)?,
+        field(17)?,
+    )
+}
+
+/// the DateTimeOriginal of EXIF data, which is in the TIFF format, or else its DateTime
+fn exif_date(tiff: &[u8]) -> Option<i64> {
+    let big_endian = match tiff.get(..4)? {
+        b"II*\0" => false,
+        b"MM\0*" => true,
+        _ => return None,
+    };
+    let u16_at = |offset: usize| {
+        let b = tiff.get(offset..offset + 2)?;
+        Some(if big_endian {
+            u16::from_be_bytes([b[0], b[1]])
+        } else {
+            u16::from_le_bytes([b[0], b[1]])
+        })
+    };
+    let u32_at = |offset: usize| {
+        let b = tiff.get(offset..offset + 4)?;
+        let b = [b[0], b[1], b[2], b[3]];
+        Some(if big_endian {
+            u32::from_be_bytes(b)
+        } else {
+            u32::from_le_bytes(b)
+        })
+    };
+    // the offset of the value of `tag` in the directory at `ifd`
+    let value = |ifd: usize, tag: u16| {
+        let count = usize::from(u16_at(ifd)?);
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| u16_at(entry) == Some(tag))
+            .map(|entry| entry + 8)
+    };
+    // the values of the date tags are longer than 4 bytes, so the entries point to them
+    let date = |ifd: usize, tag: u16| {
+        let offset = u32_at(value(ifd, tag)?)? as usize;
+        exif_datetime(tiff.get(offset..offset + 19)?)
+    };
+    let ifd0 = u32_at(4)? as usize;
+    value(ifd0, 0x8769)
+        .and_then(u32_at)
+        .and_then(|exif_ifd| date(exif_ifd as usize, 0x9003))
+        .or_else(|| date(ifd0, 0x0132))
+}
+
+/// the EXIF data of a JPEG file, from its APP1 segment
+fn jpeg_exif(jpeg: &[u8]) -> Option<&[u8]> {
+    let mut pos = 2;
+    loop {
+        let segment = jpeg.get(pos..pos + 4)?;
+        // the image data starts with the start of scan segment
+        if segment[0] != 0xFF || segment[1] == 0xDA {
+            return None;
+        }
+        let len = usize::from(u16::from_be_bytes([segment[2], segment[3]]));
+        let data = jpeg.get(pos + 4..(pos + 2 + len).min(jpeg.len()))?;
+        if segment[1] == 0xE1 {
+            if let Some(tiff) = data.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        pos += 2 + len;
+    }
+}
+
+/// the first and the last SCAN_LEN bytes of a file, the end is empty for short files
+pub(crate) fn head_and_tail(path: &Path) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+    let mut head = Vec::new();
+    (&mut file).take(SCAN_LEN).read_to_end(&mut head)?;
+    let mut tail = Vec::new();
+    if len > SCAN_LEN {
+        file.seek(SeekFrom::Start(len.saturating_sub(SCAN_LEN).max(SCAN_LEN)))?;
+        file.read_to_end(&mut tail)?;
+    }
+    Ok((head, tail))
+}
+
+/// the date inside a document in seconds since 1970, if it is in the start or the end of the file
+pub fn document_date(path: &Path) -> Result<Option<i64>> {
+    let (head, tail) =
+        head_and_tail(path).with_context(|| format!("reading {}", path.display()))?;
+    let date = if head.starts_with(b"%PDF-") {
+        pdf_creation_date(&tail)
+            .or_else(|| pdf_creation_date(&head))
+            .or_else(|| xmp_create_date(&head))
+            .or_else(|| xmp_create_date(&tail))
+    } else if head.starts_with(b"\xFF\xD8") {
+        jpeg_exif(&head)
+            .and_then(exif_date)
+            .or_else(|| xmp_create_date(&head))
+    } else if head.starts_with(b"II*\0") || head.starts_with(b"MM\0*") {
+        // tiff and the raw formats of cameras
+        exif_date(&head)
+    } else if path
+        .extension()
+        .map_or(false, |e| e.eq_ignore_ascii_case("eml"))
+    {
+        mail_header_date(&head)
+    } else {
+        None
+    };
+    Ok(date)
+}
+
+/// a time of --rga-newer-than or --rga-older-than: a date, a date and time in UTC or a number of
+/// days before `now`
+fn parse_time(value: &str, now: i64) -> Result<i64> {
+    if let Some(days) = value.strip_suffix('d') {
+        if let Ok(days) = days.parse::<u32>() {
+            return Ok(now - i64::from(days) * 86400);
+        }
+    }
+    iso_date(value).ok_or_else(|| {
+        format_err!(
+            "{:?} is not a date like 2023-01-31 or 2023-01-31T12:00:00, or a number of days like 30d",
+            value
+        )
+    })
+}
+
+/// the time range of --rga-newer-than and --rga-older-than, in seconds since 1970
+#[derive(Debug, PartialEq)]
+pub struct DateFilter {
+    newer_than: Option<i64>,
+    older_than: Option<i64>,
+}
+
+impl DateFilter {
+    /// None if no time range is given
+    pub fn new(config: &RgaConfig) -> Result<Option<DateFilter>> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let parse = |value: &Option<String>, flag: &str| {
+            value
+                .as_deref()
+                .map(|v| parse_time(v, now).with_context(|| format!("--rga-{flag}")))
+                .transpose()
+        };
+        let filter = DateFilter {
+            newer_than: parse(&config.newer_than, "newer-than")?,
+            older_than: parse(&config.older_than, "older-than")?,
+        };
+        if filter.newer_than.is_none() && filter.older_than.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(filter))
+    }
+
+    fn contains(&self, time: i64) -> bool {
+        self.newer_than.map_or(true, |t| time >= t) && self.older_than.map_or(true, |t| time < t)
+    }
+
+    /// true if the date of the document at `path`, or else its modification time, is in the range
+    pub fn includes(&self, path: &Path) -> Result<bool> {
+        let time = match document_date(path)? {
+            Some(time) => time,
+            None => {
+                let modified = std::fs::metadata(path)
+                    .and_then(|m| m.modified())
+                    .with_context(|| {
+                        format!("reading the modification time of {}", path.display())
+                    })?;
+                match modified.duration_since(UNIX_EPOCH) {
+                    Ok(d) => d.as_secs() as i64,
+                    Err(e) => -(e.duration().as_secs() as i64),
+                }
+            }
+        };
+        log::debug!("date of {}: {}", path.display(), time);
+        Ok(self.contains(time))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    /// 2001-07-03 08:20:30 UTC
+    const TIME: i64 = 994148430;
+
+    /// a little endian TIFF with a DateTime and an EXIF directory with a DateTimeOriginal
+    fn tiff() -> Vec<u8> {
+        let mut t = b"II*\0".to_vec();
+        t.extend(8u32.to_le_bytes());
+        // IFD0 at 8: DateTime, pointer to the EXIF IFD
+        t.extend(2u16.to_le_bytes());
+        t.extend([0x32, 0x01, 2, 0, 20, 0, 0, 0]);
+        t.extend(52u32.to_le_bytes());
+        t.extend([0x69, 0x87, 4, 0, 1, 0, 0, 0]);
+        t.extend(38u32.to_le_bytes());
+        t.extend(0u32.to_le_bytes());
+        // the EXIF IFD at 38
+        t.extend(1u16.to_le_bytes());
+        t.extend([0x03, 0x90, 2, 0, 20, 0, 0, 0]);
+        t.extend(72u32.to_le_bytes());
+        assert_eq!(t.len(), 52);
+        t.extend(b"2005:01:01 00:00:00\0");
+        t.extend(b"2001:07:03 08:20:30\0");
+        t
+    }
+
+    #[test]
+    fn dates() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2001, 7, 3), 11506);
+        assert_eq!(civil_from_days(11506), (2001, 7, 3));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(
+            days_from_civil(2000, 3, 1),
+            days_from_civil(2000, 2, 29) + 1
+        );
+
+        assert_eq!(iso_date("2001-07-03T08:20:30Z"), Some(TIME));
+        assert_eq!(iso_date("2001-07-03T10:20:30.25+02:00"), Some(TIME));
+        assert_eq!(iso_date("2001-07-03 08:20"), Some(TIME - 30));
+        assert_eq!(iso_date("2001-07-03"), Some(TIME - 30030));
+        assert_eq!(iso_date("2001-13-03"), None);
+        assert_eq!(iso_date("2001-07-03x"), None);
+
+        assert_eq!(pdf_date(b"D:20010703102030+02'00'"), Some(TIME));
+        assert_eq!(pdf_date(b"D:20010703082030Z"), Some(TIME));
+        assert_eq!(pdf_date(b"20010703"), Some(TIME - 30030));
+        assert_eq!(pdf_date(b"D:"), None);
+
+        assert_eq!(mail_date("Tue, 3 Jul 2001 10:20:30 +0200"), Some(TIME));
+        assert_eq!(mail_date(" 3 Jul 01 08:20 GMT"), Some(TIME - 30));
+        assert_eq!(mail_date("yesterday"), None);
+        assert_eq!(
+            mail_header_date(b"From: a@example.com\r\nDATE: Tue, 3 Jul 2001 10:20:30 +0200\r\n\r\nDate: 1 Jan 2020 00:00 +0000\r\n"),
+            Some(TIME)
+        );
+        assert_eq!(
+            mail_header_date(b"From: a\n\nDate: 1 Jan 2020 00:00 +0000\n"),
+            None
+        );
+
+        assert_eq!(
+            pdf_creation_date(b"<< /CreationDate (D:20050101) >> ... << /CreationDate (D:20010703082030Z) /Producer (x) >>"),
+            Some(TIME)
+        );
+        assert_eq!(
+            xmp_create_date(b"<xmp:CreateDate>2001-07-03T10:20:30+02:00</xmp:CreateDate>"),
+            Some(TIME)
+        );
+        assert_eq!(
+            xmp_create_date(b"<rdf:Description xmp:CreateDate=\"2001-07-03T08:20:30Z\"/>"),
+            Some(TIME)
+        );
+
+        let tiff = tiff();
+        assert_eq!(exif_date(&tiff), Some(TIME));
+        // without the EXIF directory, the DateTime of IFD0 is used
+        let mut without_exif = tiff.clone();
+        without_exif[22] = 0;
+        assert_eq!(
+            exif_date(&without_exif),
+            Some(days_from_civil(2005, 1, 1) * 86400)
+        );
+        let mut jpeg = b"\xFF\xD8\xFF\xE0\0\x04ab\xFF\xE1".to_vec();
+        jpeg.extend(((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend(b"Exif\0\0");
+        jpeg.extend(&tiff);
+        jpeg.extend(b"\xFF\xDA");
+        assert_eq!(jpeg_exif(&jpeg), Some(&tiff[..]));
+        assert_eq!(jpeg_exif(b"\xFF\xD8\xFF\xDA\0\x02"), None);
+    }
+
+    #[test]
+    fn filter() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.eml");
+        std::fs::write(
+            &path,
+            "Subject: hi\nDate: Tue, 3 Jul 2001 10:20:30 +0200\n\nhello\n",
+        )?;
+        let config = |newer_than: &str, older_than: &str| RgaConfig {
+            newer_than: Some(newer_than.to_owned()).filter(|s| !s.is_empty()),
+            older_than: Some(older_than.to_owned()).filter(|s| !s.is_empty()),
+            ..Default::default()
+        };
+        assert_eq!(DateFilter::new(&config("", ""))?, None);
+        let includes = |newer_than, older_than| -> Result<bool> {
+            DateFilter::new(&config(newer_than, older_than))?
+                .context("no filter")?
+                .includes(&path)
+        };
+        assert!(includes("2001-07-03", "2001-07-04")?);
+        assert!(includes("2001-07-03T08:20:30", "")?);
+        assert!(!includes("2001-07-03T08:20:31", "")?);
+        assert!(!includes("", "2001-07-03T08:20:30")?);
+        // the date of the email, not when the file was written
+        assert!(!includes("7d", "")?);
+        std::fs::write(&path, "hello\n")?;
+        assert!(includes("7d", "")?);
+        assert!(DateFilter::new(&config("last week", "")).is_err());
+        assert_eq!(parse_time("30d", 86400 * 40)?, 86400 * 10);
+        Ok(())
+    }
+}
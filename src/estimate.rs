@@ -0,0 +1,337 @@
+//! --rga-estimate: which adapters a search would run on which files and roughly how long it would
+//! take, without running them. Files already in the cache are counted as if they were not.
+
+use crate::adapters::get_adapters_filtered;
+use crate::config::RgaConfig;
+use crate::matching::{adapter_matcher, FileMeta};
+use crate::print_bytes;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// rough speed of the slow adapters on a typical laptop: (adapter, bytes per second, seconds per file).
+/// Most of the time of the programs they run goes into starting them or into the pages, not the bytes
+static SPEEDS: &[(&str, f64, f64)] = &[
+    ("ocr", 1e6, 2.0),
+    ("pdf", 10e6, 0.05),
+    ("ffmpeg", 1e9, 0.2),
+    ("whisper", 50e3, 2.0),
+    ("pandoc", 2e6, 0.3),
+    ("calibre", 2e6, 3.0),
+    ("pst", 20e6, 0.5),
+    ("libreoffice", 2e6, 1.0),
+    ("tika", 5e6, 0.2),
+];
+/// rg options that take a value, as the next argument or after `=` (or attached to the short ones)
+static RG_VALUE_OPTIONS: &[&str] = &[
+    "-A",
+    "--after-context",
+    "-B",
+    "--before-context",
+    "-C",
+    "--context",
+    "--color",
+    "--colors",
+    "--context-separator",
+    "-d",
+    "--max-depth",
+    "--dfa-size-limit",
+    "-E",
+    "--encoding",
+    "--engine",
+    "-e",
+    "--regexp",
+    "-f",
+    "--file",
+    "--field-context-separator",
+    "--field-match-separator",
+    "-g",
+    "--glob",
+    "--hyperlink-format",
+    "--iglob",
+    "--ignore-file",
+    "-j",
+    "--threads",
+    "-M",
+    "--max-columns",
+    "-m",
+    "--max-count",
+    "--max-filesize",
+    "--path-separator",
+    "--pre",
+    "--pre-glob",
+    "-r",
+    "--replace",
+    "--regex-size-limit",
+    "--sort",
+    "--sortr",
+    "-t",
+    "--type",
+    "-T",
+    "--type-not",
+    "--type-add",
+    "--type-clear",
+];
+/// rg options that select the files to search, which `rg --files` takes too
+static RG_FILE_OPTIONS: &[&str] = &[
+    "-d",
+    "--max-depth",
+    "-g",
+    "--glob",
+    "--iglob",
+    "--ignore-file",
+    "--max-filesize",
+    "-t",
+    "--type",
+    "-T",
+    "--type-not",
+    "--type-add",
+    "--type-clear",
+    "--hidden",
+    "--no-hidden",
+    "-.",
+    "-L",
+    "--follow",
+    "--no-follow",
+    "--one-file-system",
+    "--ignore-file-case-insensitive",
+    "--sort",
+    "--sortr",
+];
+
+/// the arguments for `rg --files` from the rg arguments of a search: the paths and the options that
+/// select files. The pattern (the first path, unless -e or -f give it) and the other options are left out
+pub fn files_args(rg_args: &[OsString]) -> Vec<OsString> {
+    let mut options = Vec::new();
+    let mut positional = Vec::new();
+    let mut pattern_option = false;
+    let mut args = rg_args.iter();
+    while let Some(arg) = args.next() {
+        let s = arg.to_string_lossy();
+        if s == "--" {
+            positional.extend(args.cloned());
+            break;
+        }
+        if !s.starts_with('-') || s == "-" {
+            positional.push(arg.clone());
+            continue;
+        }
+        // --glob=*.pdf, -g*.pdf
+        let (name, attached) = match s.split_once('=') {
+            Some((name, _)) if s.starts_with("--") => (name, true),
+            _ => match s.get(..2) {
+                Some(short)
+                    if s.len() > 2 && !s.starts_with("--") && RG_VALUE_OPTIONS.contains(&short) =>
+                {
+                    (short, true)
+                }
+                _ => (s.as_ref(), false),
+            },
+        };
+        let value = if !attached && RG_VALUE_OPTIONS.contains(&name) {
+            args.next().cloned()
+        } else {
+            None
+        };
+        if matches!(name, "-e" | "--regexp" | "-f" | "--file") {
+            pattern_option = true;
+        }
+        // -u, -uu and -uuu
+        let unrestricted = name.len() > 1 && name[1..].chars().all(|c| c == 'u');
+        if RG_FILE_OPTIONS.contains(&name) || name.starts_with("--no-ignore") || unrestricted {
+            options.push(arg.clone());
+            options.extend(value);
+        }
+    }
+    if !pattern_option && !positional.is_empty() {
+        positional.remove(0);
+    }
+    if !positional.is_empty() {
+        options.push("--".into());
+        options.extend(positional);
+    }
+    options
+}
+
+/// the other adapters mostly decompress or parse in rga itself
+const DEFAULT_SPEED: (f64, f64) = (50e6, 0.01);
+/// rg searches the files without an adapter itself
+const RG_SPEED: f64 = 500e6;
+
+#[derive(Default, Debug, PartialEq)]
+pub struct AdapterEstimate {
+    pub files: u64,
+    pub bytes: u64,
+    pub seconds: f64,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub struct Estimate {
+    pub adapters: BTreeMap<String, AdapterEstimate>,
+    /// files rg searches without an adapter
+    pub plain_files: u64,
+    pub plain_bytes: u64,
+}
+
+/// choose the adapters for `files` (path and size) by their extension, as a search would without --rga-accurate.
+/// Archives are estimated by their size, since their members are only known when they are read
+pub fn estimate(
+    config: &RgaConfig,
+    files: impl IntoIterator<Item = (PathBuf, u64)>,
+) -> Result<Estimate> {
+    let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+    let matcher = adapter_matcher(&adapters, false)?;
+    let mut estimate = Estimate::default();
+    for (path, len) in files {
+        let lossy_filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let lossy_dirname = path
+            .parent()
+            .and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let adapter = matcher(FileMeta {
+            lossy_filename,
+            lossy_dirname,
+            mimetype: None,
+        });
+        match adapter {
+            Some((adapter, _)) => {
+                let name = &adapter.metadata().name;
+                let (bytes_per_sec, secs_per_file) = SPEEDS
+                    .iter()
+                    .find(|s| s.0 == *name)
+                    .map(|s| (s.1, s.2))
+                    .unwrap_or(DEFAULT_SPEED);
+                let e = estimate.adapters.entry(name.clone()).or_default();
+                e.files += 1;
+                e.bytes += len;
+                e.seconds += len as f64 / bytes_per_sec + secs_per_file;
+            }
+            None => {
+                estimate.plain_files += 1;
+                estimate.plain_bytes += len;
+            }
+        }
+    }
+    Ok(estimate)
+}
+
+fn print_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h {}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m {}s", secs / 60, secs % 60)
+    } else {
+        format!("{}s", secs.max(1))
+    }
+}
+
+impl Estimate {
+    /// the time of the whole run, with `threads` files adapted at the same time
+    pub fn duration(&self, threads: usize) -> Duration {
+        let seconds = self.adapters.values().map(|a| a.seconds).sum::<f64>()
+            + self.plain_bytes as f64 / RG_SPEED;
+        Duration::from_secs_f64(seconds / threads.max(1) as f64)
+    }
+
+    pub fn report(&self, threads: usize) -> String {
+        let mut out = format!(
+            "{:<20} {:>8} {:>12} {:>10}\n",
+            "adapter", "files", "size", "time"
+        );
+        for (name, a) in &self.adapters {
+            let _ = writeln!(
+                out,
+                "{:<20} {:>8} {:>12} {:>10}",
+                name,
+                a.files,
+                print_bytes(a.bytes as f64),
+                print_duration(Duration::from_secs_f64(a.seconds))
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{:<20} {:>8} {:>12}",
+            "(searched by rg)",
+            self.plain_files,
+            print_bytes(self.plain_bytes as f64)
+        );
+        if let Some(ocr) = self.adapters.get("ocr") {
+            let _ = writeln!(out, "\n{} files need OCR", ocr.files);
+        }
+        let _ = writeln!(
+            out,
+            "\nestimated time with {} threads: {} (less for files already in the cache)",
+            threads,
+            print_duration(self.duration(threads))
+        );
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rg_files_args() {
+        let args = |args: &[&str]| args.iter().map(OsString::from).collect::<Vec<_>>();
+        assert_eq!(
+            files_args(&args(&[
+                "-i",
+                "-A",
+                "3",
+                "foo",
+                "-g",
+                "*.pdf",
+                "docs",
+                "--max-depth=2",
+                "-tpdf",
+                "-uu"
+            ])),
+            args(&["-g", "*.pdf", "--max-depth=2", "-tpdf", "-uu", "--", "docs"])
+        );
+        assert_eq!(
+            files_args(&args(&["-e", "foo", "--hidden", "a", "--", "-b"])),
+            args(&["--hidden", "--", "a", "-b"])
+        );
+        // only a pattern: rg searches the current directory
+        assert_eq!(files_args(&args(&["foo"])), args(&[]));
+    }
+
+    #[test]
+    fn adapters() -> Result<()> {
+        let config = RgaConfig {
+            adapters: vec!["+ocr".to_owned()],
+            ..Default::default()
+        };
+        let files = [
+            ("a/scan.png", 1_000_000),
+            ("a/paper.pdf", 10_000_000),
+            ("b/notes.txt", 500),
+            ("b/scan2.JPG", 1_000_000),
+        ];
+        let e = estimate(&config, files.map(|(p, len)| (PathBuf::from(p), len)))?;
+        assert_eq!(
+            e.adapters["ocr"],
+            AdapterEstimate {
+                files: 2,
+                bytes: 2_000_000,
+                seconds: 6.0
+            }
+        );
+        assert_eq!(e.adapters["pdf"].files, 1);
+        assert_eq!(e.plain_files, 1);
+        assert_eq!(e.duration(2).as_secs(), 3);
+        assert_eq!(print_duration(Duration::from_secs(3725)), "1h 2m");
+        Ok(())
+    }
+}
@@ -0,0 +1,134 @@
+//! Entry points for fuzzing the parsers of the native adapters (the ones that don't run other programs),
+//! only built with the `fuzzing` feature. A cargo-fuzz target looks like
+//!
+//! ```ignore
+//! fuzz_target!(|data: &[u8]| {
+//!     let _ = ripgrep_all::fuzzing::zip::adapt_bytes(data);
+//! });
+//! ```
+//!
+//! Each `adapt_bytes` runs one adapter on the input as if it was a file in an archive, without recursing
+//! into members, cache or config file, so the result only depends on the input.
+//! Malformed input has to return an error, a panic is a bug.
+use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapters::{AdaptInfo, FileAdapter};
+use crate::config::RgaConfig;
+use crate::matching::{FastFileMatcher, FileMatcher};
+use crate::preproc::{keep_spool, provide_input};
+use crate::recurse::concat_read_streams;
+use anyhow::Result;
+use std::io::Cursor;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+async fn adapt(adapter: &dyn FileAdapter, name: &str, data: Vec<u8>) -> Result<String> {
+    let path = Path::new(name);
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ai = AdaptInfo {
+        filepath_hint: path.to_owned(),
+        is_real_file: false,
+        archive_recursion_depth: 0,
+        inp: Box::pin(Cursor::new(data)),
+        line_prefix: String::new(),
+        config: RgaConfig::default(),
+        postprocess: false,
+    };
+    let detection_reason = FileMatcher::Fast(FastFileMatcher::FileExtension(ext));
+    let (ai, spool) = provide_input(adapter, ai).await?;
+    let members: AdaptedFilesIterBox = adapter.adapt(ai, &detection_reason).await?;
+    let mut oup = concat_read_streams(keep_spool(members, spool));
+    let mut buf = Vec::new();
+    oup.read_to_end(&mut buf).await?;
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+/// run `adapter` on `data` as the file `name`. The output of archive adapters is the concatenated raw members
+pub fn adapt_bytes(adapter: &dyn FileAdapter, name: &str, data: &[u8]) -> Result<String> {
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    rt.block_on(adapt(adapter, name, data.to_vec()))
+}
+
+macro_rules! entry_points {
+    ($($module:ident: $adapter:ident::$ty:ident, $name:literal;)*) => {
+        $(
+            pub mod $module {
+                #[doc = concat!("run the ", stringify!($adapter), " adapter on `data` as the file `", $name, "`")]
+                pub fn adapt_bytes(data: &[u8]) -> anyhow::Result<String> {
+                    super::adapt_bytes(&crate::adapters::$adapter::$ty::new(), $name, data)
+                }
+            }
+        )*
+    };
+}
+
+entry_points! {
+    zip: zip::ZipAdapter, "fuzz.zip";
+    tar: tar::TarAdapter, "fuzz.tar";
+    mbox: mail::MboxAdapter, "fuzz.mbox";
+    eml: mail::EmlAdapter, "fuzz.eml";
+    msg: msg::MsgAdapter, "fuzz.msg";
+    gz: decompress::DecompressAdapter, "fuzz.gz";
+    bz2: decompress::DecompressAdapter, "fuzz.bz2";
+    xz: decompress::DecompressAdapter, "fuzz.xz";
+    zst: decompress::DecompressAdapter, "fuzz.zst";
+    lz4: decompress::DecompressAdapter, "fuzz.lz4";
+    br: decompress::DecompressAdapter, "fuzz.br";
+    sqlite: sqlite::SqliteAdapter, "fuzz.sqlite3";
+    minidump: crashdump::CrashdumpAdapter, "fuzz.dmp";
+    elf_core: crashdump::CrashdumpAdapter, "fuzz.core";
+    class: java::JavaAdapter, "Fuzz.class";
+    dex: java::JavaAdapter, "fuzz.dex";
+    dotnet: dotnet::DotnetAdapter, "fuzz.dll";
+    pyc: python::PycAdapter, "fuzz.pyc";
+    pickle: python::PickleAdapter, "fuzz.pkl";
+    tfstate: terraform::TerraformAdapter, "terraform.tfstate";
+    tfplan: terraform::TerraformAdapter, "fuzz.tfplan";
+    cloudformation: cloudformation::CloudformationAdapter, "fuzz.template.json";
+    etcd: etcd::EtcdAdapter, "fuzz.etcd";
+    inv: sphinx::SphinxAdapter, "objects.inv";
+    doctree: sphinx::SphinxAdapter, "fuzz.doctree";
+    mo: localization::LocalizationAdapter, "fuzz.mo";
+    qm: localization::LocalizationAdapter, "fuzz.qm";
+    resx: localization::LocalizationAdapter, "fuzz.resx";
+    xliff: localization::LocalizationAdapter, "fuzz.xliff";
+    ttf: fonts::FontsAdapter, "fuzz.ttf";
+    woff2: fonts::FontsAdapter, "fuzz.woff2";
+    step: cad::CadAdapter, "fuzz.step";
+    stl: cad::CadAdapter, "fuzz.stl";
+    gltf: cad::CadAdapter, "fuzz.gltf";
+    glb: cad::CadAdapter, "fuzz.glb";
+    pptx: ooxml::OoxmlAdapter, "fuzz.pptx";
+    odt: opendocument::OpenDocumentAdapter, "fuzz.odt";
+    mobi: ebook::EbookAdapter, "fuzz.mobi";
+    fb2: ebook::EbookAdapter, "fuzz.fb2";
+    rtf: rtf::RtfAdapter, "fuzz.rtf";
+    pak: gamearchive::GameArchiveAdapter, "fuzz.pak";
+    vpk: gamearchive::GameArchiveAdapter, "fuzz.vpk";
+    unity3d: gamearchive::GameArchiveAdapter, "fuzz.unity3d";
+    sfx: sfx::SfxAdapter, "fuzz.exe";
+    strings: strings::StringsAdapter, "fuzz.bin";
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malformed_input() {
+        let inputs: &[&[u8]] = &[b"", b"PK\x03\x04\xff\xff", &[0xff; 300]];
+        for data in inputs {
+            // errors are fine, panics are not
+            let _ = zip::adapt_bytes(data);
+            let _ = tar::adapt_bytes(data);
+            let _ = class::adapt_bytes(data);
+            let _ = ttf::adapt_bytes(data);
+            let _ = pak::adapt_bytes(data);
+        }
+        assert!(sqlite::adapt_bytes(&[0xff; 300]).is_err());
+    }
+}
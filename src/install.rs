@@ -0,0 +1,725 @@
+//! --rga-install-deps and --rga-self-update. The external programs adapters run are installed with
+//! the package manager of the system, since rga can't know how to build them for every platform.
+//! rga itself is updated from the binaries on the GitHub releases page, which are only built for a
+//! few platforms. All programs are run through [policy::command], so the policy also applies here.
+//!
+//! [TOOLS] is also used for the hints rga prints when an adapter's program is missing while searching.
+
+use crate::adapters::get_adapters_filtered;
+use crate::config::RgaConfig;
+use crate::policy;
+use anyhow::{bail, format_err, Context, Result};
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// an external program some adapters need
+pub struct Tool {
+    pub program: &'static str,
+    /// the adapters that run it
+    pub adapters: &'static [&'static str],
+    version_args: &'static [&'static str],
+    /// the lowest version that works with the arguments the adapters pass
+    min_version: Option<&'static [u32]>,
+    /// the package that contains it, for each package manager
+    packages: &'static [(&'static str, &'static str)],
+}
+
+pub static TOOLS: &[Tool] = &[
+    Tool {
+        program: "pdftotext",
+        adapters: &["pdf"],
+        version_args: &["-v"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "poppler-utils"),
+            ("dnf", "poppler-utils"),
+            ("pacman", "poppler"),
+            ("zypper", "poppler-tools"),
+            ("apk", "poppler-utils"),
+            ("brew", "poppler"),
+            ("scoop", "poppler"),
+        ],
+    },
+    Tool {
+        program: "mutool",
+        adapters: &["pdf"],
+        version_args: &["-v"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "mupdf-tools"),
+            ("dnf", "mupdf"),
+            ("pacman", "mupdf-tools"),
+            ("zypper", "mupdf"),
+            ("apk", "mupdf-tools"),
+            ("brew", "mupdf-tools"),
+            ("scoop", "mupdf"),
+        ],
+    },
+    Tool {
+        program: "tesseract",
+        adapters: &["ocr"],
+        version_args: &["--version"],
+        // the LSTM engine, older versions are much worse
+        min_version: Some(&[4]),
+        packages: &[
+            ("apt-get", "tesseract-ocr"),
+            ("dnf", "tesseract"),
+            ("pacman", "tesseract"),
+            ("zypper", "tesseract-ocr"),
+            ("apk", "tesseract-ocr"),
+            ("brew", "tesseract"),
+            ("choco", "tesseract"),
+            ("scoop", "tesseract"),
+        ],
+    },
+    Tool {
+        program: "ffmpeg",
+        adapters: &["ffmpeg", "whisper"],
+        version_args: &["-version"],
+        min_version: None,
+        packages: FFMPEG_PACKAGES,
+    },
+    Tool {
+        program: "whisper-cli",
+        adapters: &["whisper"],
+        version_args: &["--help"],
+        min_version: None,
+        packages: &[("brew", "whisper-cpp")],
+    },
+    Tool {
+        program: "ffprobe",
+        adapters: &["ffmpeg"],
+        version_args: &["-version"],
+        min_version: None,
+        packages: FFMPEG_PACKAGES,
+    },
+    Tool {
+        program: "pandoc",
+        adapters: &["pandoc", "ooxml", "opendocument", "epub"],
+        version_args: &["--version"],
+        // --markdown-headings
+        min_version: Some(&[2, 11, 2]),
+        packages: &[
+            ("apt-get", "pandoc"),
+            ("dnf", "pandoc"),
+            ("pacman", "pandoc"),
+            ("zypper", "pandoc"),
+            ("apk", "pandoc"),
+            ("brew", "pandoc"),
+            ("choco", "pandoc"),
+            ("scoop", "pandoc"),
+        ],
+    },
+    Tool {
+        program: "bsdtar",
+        adapters: &["comic", "7z", "rar"],
+        version_args: &["--version"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "libarchive-tools"),
+            ("dnf", "bsdtar"),
+            ("pacman", "libarchive"),
+            ("zypper", "bsdtar"),
+            ("apk", "libarchive-tools"),
+            ("brew", "libarchive"),
+        ],
+    },
+    Tool {
+        program: "ebook-convert",
+        adapters: &["calibre"],
+        version_args: &["--version"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "calibre"),
+            ("dnf", "calibre"),
+            ("pacman", "calibre"),
+            ("zypper", "calibre"),
+            ("choco", "calibre"),
+        ],
+    },
+    Tool {
+        program: "unrar",
+        adapters: &["rar"],
+        // the usage starts with the version
+        version_args: &[],
+        min_version: None,
+        packages: &[
+            ("apt-get", "unrar"),
+            ("dnf", "unrar"),
+            ("pacman", "unrar"),
+            ("zypper", "unrar"),
+            ("apk", "unrar"),
+            ("choco", "unrar"),
+        ],
+    },
+    Tool {
+        program: "unsquashfs",
+        adapters: &["squashfs"],
+        version_args: &["-version"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "squashfs-tools"),
+            ("dnf", "squashfs-tools"),
+            ("pacman", "squashfs-tools"),
+            ("zypper", "squashfs"),
+            ("apk", "squashfs-tools"),
+            ("brew", "squashfs"),
+        ],
+    },
+    Tool {
+        program: "pffexport",
+        adapters: &["pst"],
+        version_args: &["-V"],
+        min_version: None,
+        packages: &[
+            ("apt-get", "pff-tools"),
+            ("dnf", "libpff-tools"),
+            ("brew", "libpff"),
+        ],
+    },
+];
+
+static FFMPEG_PACKAGES: &[(&str, &str)] = &[
+    ("apt-get", "ffmpeg"),
+    ("dnf", "ffmpeg-free"),
+    ("pacman", "ffmpeg"),
+    ("zypper", "ffmpeg"),
+    ("apk", "ffmpeg"),
+    ("brew", "ffmpeg"),
+    ("choco", "ffmpeg"),
+    ("scoop", "ffmpeg"),
+];
+
+/// the package managers rga knows, in order of preference, with the command that installs packages
+static PACKAGE_MANAGERS: &[(&str, &[&str])] = &[
+    ("apt-get", &["sudo", "apt-get", "install", "-y"]),
+    ("dnf", &["sudo", "dnf", "install", "-y"]),
+    ("pacman", &["sudo", "pacman", "-S", "--needed"]),
+    ("zypper", &["sudo", "zypper", "install"]),
+    ("apk", &["sudo", "apk", "add"]),
+    ("brew", &["brew", "install"]),
+    ("scoop", &["scoop", "install"]),
+    ("choco", &["choco", "install", "-y"]),
+];
+
+lazy_static! {
+    static ref VERSION: Regex = Regex::new(r"\d+(\.\d+)+").unwrap();
+}
+
+/// the first version number in the output of e.g. `tesseract --version`
+fn parse_version(output: &str) -> Option<Vec<u32>> {
+    let m = VERSION.find(output)?;
+    m.as_str().split('.').map(|n| n.parse().ok()).collect()
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    let path = std::env::var_os("PATH")?;
+    std::env::split_paths(&path).find_map(|dir| {
+        ["", ".exe", ".cmd"]
+            .iter()
+            .map(|ext| dir.join(format!("{program}{ext}")))
+            .find(|p| p.is_file())
+    })
+}
+
+#[derive(Debug, PartialEq)]
+enum Status {
+    Ok(Option<Vec<u32>>),
+    TooOld(Vec<u32>),
+    Missing,
+    /// the policy doesn't allow running it
+    Forbidden(String),
+}
+
+async fn check(config: &RgaConfig, tool: &Tool) -> Status {
+    let mut cmd = match policy::command(&config.policy, tool.program, Path::new("")) {
+        Ok(cmd) => cmd,
+        Err(e) => return Status::Forbidden(format!("{e:#}")),
+    };
+    let out = match cmd.args(tool.version_args).output().await {
+        Ok(out) => out,
+        Err(_) => return Status::Missing,
+    };
+    // some print the version to stderr
+    let output = String::from_utf8_lossy(&out.stdout) + String::from_utf8_lossy(&out.stderr);
+    let version = parse_version(&output);
+    match (version, tool.min_version) {
+        (Some(version), Some(min)) if version.as_slice() < min => Status::TooOld(version),
+        (version, _) => Status::Ok(version),
+    }
+}
+
+fn print_version(version: &[u32]) -> String {
+    version
+        .iter()
+        .map(u32::to_string)
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// the command that installs the packages of `tools` with `manager`
+fn install_command(manager: &str, tools: &[&Tool]) -> Option<Vec<String>> {
+    let (_, command) = PACKAGE_MANAGERS.iter().find(|(m, _)| *m == manager)?;
+    let mut packages: Vec<&str> = vec![];
+    for tool in tools {
+        if let Some((_, package)) = tool.packages.iter().find(|(m, _)| *m == manager) {
+            if !packages.contains(package) {
+                packages.push(package);
+            }
+        }
+    }
+    if packages.is_empty() {
+        return None;
+    }
+    // no sudo in containers running as root
+    let command = command
+        .iter()
+        .skip_while(|c| **c == "sudo" && find_in_path("sudo").is_none());
+    Some(
+        command
+            .chain(packages.iter())
+            .map(|s| s.to_string())
+            .collect(),
+    )
+}
+
+/// the package managers that might be used on this OS
+fn for_this_os(manager: &str) -> bool {
+    match std::env::consts::OS {
+        "windows" => matches!(manager, "scoop" | "choco"),
+        "macos" => manager == "brew",
+        _ => !matches!(manager, "scoop" | "choco"),
+    }
+}
+
+/// environment variable with the directory rga-preproc writes the hints about missing programs to,
+/// so rga can print each of them once at the end
+pub const HINTS_DIR: &str = "RGA_HINTS_DIR";
+
+/// the error of an adapter whose program is not installed, see [crate::adapters::custom::map_exe_error]
+#[derive(Debug)]
+pub struct MissingProgram {
+    pub program: String,
+    pub help: String,
+}
+
+impl std::fmt::Display for MissingProgram {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Could not find executable \"{}\". {}",
+            self.program, self.help
+        )
+    }
+}
+
+impl std::error::Error for MissingProgram {}
+
+pub fn missing_program(e: &anyhow::Error) -> Option<&MissingProgram> {
+    e.chain().find_map(|e| e.downcast_ref())
+}
+
+/// how to get a missing program
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Hint {
+    pub program: String,
+    pub adapter: String,
+    pub help: String,
+    /// the commands that install it, for the package managers found or else all of this OS
+    pub install: Vec<String>,
+}
+
+pub fn hint(missing: &MissingProgram, adapter: &str) -> Hint {
+    let tool = TOOLS.iter().find(|t| t.program == missing.program);
+    let commands = |found: bool| {
+        PACKAGE_MANAGERS
+            .iter()
+            .map(|(m, _)| *m)
+            .filter(|m| for_this_os(m) && (!found || find_in_path(m).is_some()))
+            .filter_map(|m| install_command(m, &[tool?]))
+            .map(|c| c.join(" "))
+            .collect::<Vec<_>>()
+    };
+    let install = match commands(true) {
+        found if found.is_empty() => commands(false),
+        found => found,
+    };
+    Hint {
+        program: missing.program.clone(),
+        adapter: adapter.to_owned(),
+        help: missing.help.clone(),
+        install,
+    }
+}
+
+impl std::fmt::Display for Hint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "[rga: {} not found, the {} adapter needs it. Files that need it were skipped]",
+            self.program, self.adapter
+        )?;
+        match self.install.as_slice() {
+            [] => writeln!(f, "  {}", self.help),
+            install => {
+                for command in install {
+                    writeln!(f, "  install with: {command}")?;
+                }
+                writeln!(f, "  or see rga --rga-install-deps")
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref MISSING: AtomicBool = AtomicBool::new(false);
+}
+
+/// note that a program was missing: the output is incomplete and must not be cached. The hint is
+/// written for rga, or printed if rga-preproc runs on its own
+pub fn note_missing(missing: &MissingProgram, adapter: &str) -> Result<()> {
+    if MISSING.swap(true, Ordering::Relaxed) {
+        return Ok(());
+    }
+    let hint = hint(missing, adapter);
+    let dir = match std::env::var_os(HINTS_DIR) {
+        Some(dir) => PathBuf::from(dir),
+        None => {
+            eprint!("{hint}");
+            return Ok(());
+        }
+    };
+    // one file per program, so parallel rga-preproc processes write each hint once
+    let file = dir.join(format!("{}.json", hint.program.replace(['/', '\\'], "_")));
+    match std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&file)
+    {
+        Ok(mut f) => f.write_all(serde_json::to_string(&hint)?.as_bytes())?,
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e).with_context(|| format!("writing {}", file.display())),
+    }
+    Ok(())
+}
+
+/// whether a program was missing in this process
+pub fn any_missing() -> bool {
+    MISSING.load(Ordering::Relaxed)
+}
+
+/// print the hints rga-preproc wrote to `dir`
+pub fn print_hints(dir: &Path) -> Result<()> {
+    let mut files = std::fs::read_dir(dir)?
+        .map(|e| Ok(e?.path()))
+        .collect::<Result<Vec<_>>>()?;
+    files.sort();
+    for file in files {
+        // a file that is still empty belongs to a process that was killed while writing it
+        let hint: Hint = match serde_json::from_slice(&std::fs::read(&file)?) {
+            Ok(hint) => hint,
+            Err(_) => continue,
+        };
+        eprint!("{hint}");
+    }
+    Ok(())
+}
+
+/// check the external programs of the enabled adapters and print how to install the missing ones.
+/// With `run`, also run the package manager
+pub async fn install_deps(config: &RgaConfig, run: bool) -> Result<()> {
+    let adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?
+        .iter()
+        .map(|a| a.metadata().name.clone())
+        .collect::<Vec<_>>();
+    let mut missing = vec![];
+    for tool in TOOLS {
+        let used_by = tool
+            .adapters
+            .iter()
+            .filter(|a| adapters.iter().any(|name| name == *a))
+            .copied()
+            .collect::<Vec<_>>();
+        if used_by.is_empty() {
+            println!(
+                "-    {:<14} not needed, adapter {} disabled",
+                tool.program,
+                tool.adapters.join(",")
+            );
+            continue;
+        }
+        let used_by = used_by.join(",");
+        match check(config, tool).await {
+            Status::Ok(version) => println!(
+                "ok   {:<14} {} ({})",
+                tool.program,
+                version.map_or("unknown version".to_owned(), |v| print_version(&v)),
+                used_by
+            ),
+            Status::TooOld(version) => {
+                println!(
+                    "old  {:<14} {}, at least {} needed ({})",
+                    tool.program,
+                    print_version(&version),
+                    print_version(tool.min_version.unwrap_or_default()),
+                    used_by
+                );
+                missing.push(tool);
+            }
+            Status::Missing => {
+                println!("MISS {:<14} not found ({})", tool.program, used_by);
+                missing.push(tool);
+            }
+            Status::Forbidden(e) => println!("-    {:<14} {}", tool.program, e),
+        }
+    }
+    if missing.is_empty() {
+        println!("\nall programs the enabled adapters need are installed");
+        return Ok(());
+    }
+    let manager = PACKAGE_MANAGERS
+        .iter()
+        .map(|(m, _)| *m)
+        .find(|m| find_in_path(m).is_some());
+    let command = match manager.and_then(|m| install_command(m, &missing)) {
+        Some(command) => command,
+        None => {
+            println!(
+                "\nno supported package manager found, install {} yourself",
+                missing
+                    .iter()
+                    .map(|t| t.program)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            return Ok(());
+        }
+    };
+    println!(
+        "\nto install the missing programs, run:\n  {}",
+        command.join(" ")
+    );
+    if !run {
+        return Ok(());
+    }
+    let status = policy::command(&config.policy, &command[0], Path::new(""))?
+        .args(&command[1..])
+        .status()
+        .await
+        .with_context(|| format!("running {}", command[0]))?;
+    if !status.success() {
+        bail!("{} failed with {}", command.join(" "), status);
+    }
+    Ok(())
+}
+
+const RELEASES_API: &str = "https://api.github.com/repos/phiresky/ripgrep-all/releases/latest";
+
+/// the release build for this platform, see .github/workflows/release.yml
+fn release_target() -> Option<&'static str> {
+    match (std::env::consts::ARCH, std::env::consts::OS) {
+        ("x86_64", "linux") => Some("x86_64-unknown-linux-musl"),
+        ("arm", "linux") => Some("arm-unknown-linux-gnueabihf"),
+        // there are no arm builds for macOS, Rosetta runs the x86_64 one
+        ("x86_64" | "aarch64", "macos") => Some("x86_64-apple-darwin"),
+        ("x86_64", "windows") => Some("x86_64-pc-windows-msvc"),
+        _ => None,
+    }
+}
+
+/// the version and download URL of the asset for `target` in the JSON of a GitHub release
+fn release_asset(release: &serde_json::Value, target: &str) -> Result<(String, String, String)> {
+    let tag = release["tag_name"]
+        .as_str()
+        .ok_or_else(|| format_err!("release without tag_name"))?;
+    let ext = if target.contains("windows") {
+        "zip"
+    } else {
+        "tar.gz"
+    };
+    let name = format!("ripgrep_all-{tag}-{target}.{ext}");
+    let url = release["assets"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|a| a["name"] == name.as_str())
+        .and_then(|a| a["browser_download_url"].as_str())
+        .ok_or_else(|| format_err!("release {} has no {}", tag, name))?;
+    Ok((tag.to_owned(), name, url.to_owned()))
+}
+
+/// run a program as allowed by the policy, and return its output
+pub(crate) async fn run(config: &RgaConfig, program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let out = policy::command(&config.policy, program, Path::new(args[args.len() - 1]))?
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("running {program}"))?;
+    if !out.status.success() {
+        bail!(
+            "{} {} failed: {}",
+            program,
+            args.join(" "),
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(out.stdout)
+}
+
+/// replace rga and rga-preproc with the binaries of the latest release, if it is newer
+pub async fn self_update(config: &RgaConfig) -> Result<()> {
+    if config.policy.no_network {
+        bail!("--rga-self-update downloads from GitHub, which --rga-policy-no-network forbids");
+    }
+    let target = release_target().ok_or_else(|| {
+        format_err!(
+            "there are no release builds for {}-{}, update with cargo install instead",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        )
+    })?;
+    let release = run(
+        config,
+        "curl",
+        &[
+            "-sSfL",
+            "-H",
+            "Accept: application/vnd.github+json",
+            RELEASES_API,
+        ],
+    )
+    .await?;
+    let release: serde_json::Value =
+        serde_json::from_slice(&release).context("reading the latest release")?;
+    let (tag, name, url) = release_asset(&release, target)?;
+    let current = env!("CARGO_PKG_VERSION");
+    if parse_version(&tag) <= parse_version(current) {
+        println!("rga {current} is up to date");
+        return Ok(());
+    }
+    println!("updating rga {current} to {tag}");
+    let dir = tempfile::tempdir()?;
+    let archive = dir.path().join(&name);
+    let archive = archive.to_string_lossy();
+    let dest = dir.path().to_string_lossy();
+    run(config, "curl", &["-sSfL", "-o", &archive, &url]).await?;
+    // tar also extracts zip files on Windows
+    run(config, "tar", &["-xf", &archive, "-C", &dest]).await?;
+    let unpacked = dir.path().join(format!("ripgrep_all-{tag}-{target}"));
+    let exe_dir = std::env::current_exe()?
+        .parent()
+        .expect("the executable is in a directory")
+        .to_owned();
+    // check that the new binary runs here before replacing the old one
+    let rga = unpacked.join(format!("rga{}", std::env::consts::EXE_SUFFIX));
+    let out = run(config, &rga.to_string_lossy(), &["--version"]).await?;
+    let version = print_version(&parse_version(&tag).unwrap_or_default());
+    if !String::from_utf8_lossy(&out).contains(&version) {
+        bail!("{} --version does not print {}", rga.display(), version);
+    }
+    for exe in ["rga", "rga-preproc"] {
+        let exe = format!("{exe}{}", std::env::consts::EXE_SUFFIX);
+        let path = exe_dir.join(&exe);
+        // a running executable can't be overwritten on Windows, but it can be renamed
+        let old = exe_dir.join(format!("{exe}.old"));
+        if path.exists() {
+            std::fs::rename(&path, &old).with_context(|| {
+                format!(
+                    "replacing {}, update it with the package manager that installed it",
+                    path.display()
+                )
+            })?;
+        }
+        std::fs::copy(unpacked.join(&exe), &path)
+            .with_context(|| format!("copying to {}", path.display()))?;
+        // fails on Windows while rga is running, the next update removes it
+        let _ = std::fs::remove_file(&old);
+    }
+    println!("updated rga to {tag} in {}", exe_dir.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn versions() {
+        assert_eq!(
+            parse_version("pdftotext version 22.02.0\nCopyright 2005-2022"),
+            Some(vec![22, 2, 0])
+        );
+        assert_eq!(
+            parse_version("ebook-convert.exe (calibre 6.13)"),
+            Some(vec![6, 13])
+        );
+        assert_eq!(parse_version("ffmpeg version N-111123-g1234"), None);
+        assert!(parse_version("v0.10.6") > parse_version("0.9.6"));
+        assert!(vec![2, 11] < vec![2, 11, 2]);
+    }
+
+    #[test]
+    fn install_commands() {
+        let tools = TOOLS
+            .iter()
+            .filter(|t| t.adapters.contains(&"ffmpeg") || t.program == "bsdtar")
+            .collect::<Vec<_>>();
+        let command = install_command("brew", &tools).unwrap();
+        assert_eq!(command, vec!["brew", "install", "ffmpeg", "libarchive"]);
+        let command = install_command("scoop", &tools[2..]);
+        assert_eq!(command, None);
+        assert_eq!(install_command("nix-env", &tools), None);
+    }
+
+    #[test]
+    fn hints() -> Result<()> {
+        let missing = MissingProgram {
+            program: "pdftotext".to_owned(),
+            help: "Install poppler.".to_owned(),
+        };
+        let e = anyhow::Error::new(missing).context("adapting a.pdf via pdf failed");
+        let missing = missing_program(&e).expect("found in the chain");
+        let hint = hint(missing, "pdf");
+        assert_eq!(hint.program, "pdftotext");
+        assert!(hint
+            .install
+            .iter()
+            .all(|c| c.contains("poppler") || c.contains("mupdf")));
+        let other = MissingProgram {
+            program: "sops".to_owned(),
+            help: "Install sops.".to_owned(),
+        };
+        assert_eq!(super::hint(&other, "sops").install, Vec::<String>::new());
+        assert!(missing_program(&format_err!("other")).is_none());
+
+        let dir = tempfile::tempdir()?;
+        std::fs::write(
+            dir.path().join("sops.json"),
+            serde_json::to_string(&super::hint(&other, "sops"))?,
+        )?;
+        std::fs::write(dir.path().join("broken.json"), "")?;
+        print_hints(dir.path())?;
+        Ok(())
+    }
+
+    #[test]
+    fn assets() -> Result<()> {
+        let release = serde_json::json!({
+            "tag_name": "v0.10.6",
+            "assets": [
+                {"name": "ripgrep_all-v0.10.6-x86_64-apple-darwin.tar.gz", "browser_download_url": "https://example.com/mac"},
+                {"name": "ripgrep_all-v0.10.6-x86_64-pc-windows-msvc.zip", "browser_download_url": "https://example.com/win"},
+            ]
+        });
+        let (tag, _, url) = release_asset(&release, "x86_64-pc-windows-msvc")?;
+        assert_eq!(tag, "v0.10.6");
+        assert_eq!(url, "https://example.com/win");
+        assert!(release_asset(&release, "x86_64-unknown-linux-musl").is_err());
+        Ok(())
+    }
+}
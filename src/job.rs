@@ -0,0 +1,98 @@
+//! --rga-job: a file that records the outcome of every file of a long run. Running the same search
+//! again resumes it, since the files that are done are in the cache, and --rga-retry-failed
+//! searches only the files that failed. rga-preproc appends a line to it for each file
+//! (see [FileStats]), so the progress survives interruptions and reboots.
+
+use crate::stats::FileStats;
+use anyhow::{Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::Path;
+
+/// environment variable with the path of the job file, for rga-preproc
+pub const JOB_FILE: &str = "RGA_JOB_FILE";
+
+/// append the outcome of a file to the job file
+pub fn record(job: &Path, stats: &FileStats) -> Result<()> {
+    let mut line = serde_json::to_string(stats)?;
+    line.push('\n');
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(job)
+        .with_context(|| format!("opening {}", job.display()))?;
+    // a single write in append mode, so lines of parallel processes don't get mixed up
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("writing {}", job.display()))?;
+    Ok(())
+}
+
+/// the files of a job by their last outcome
+#[derive(Default, Debug, PartialEq)]
+pub struct JobState {
+    pub done: BTreeSet<String>,
+    /// path and error
+    pub failed: BTreeMap<String, String>,
+}
+
+pub fn read(job: &Path) -> Result<JobState> {
+    let mut state = JobState::default();
+    if !job.exists() {
+        return Ok(state);
+    }
+    let content =
+        std::fs::read_to_string(job).with_context(|| format!("reading {}", job.display()))?;
+    for line in content.lines() {
+        let file: FileStats = match serde_json::from_str(line) {
+            Ok(file) => file,
+            // the last line can be cut short by a crash
+            Err(_) => continue,
+        };
+        match file.error {
+            Some(error) => {
+                state.done.remove(&file.path);
+                state.failed.insert(file.path, error);
+            }
+            None => {
+                state.failed.remove(&file.path);
+                state.done.insert(file.path);
+            }
+        }
+    }
+    Ok(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn resume() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let job = dir.path().join("job.jsonl");
+        let file = |path: &str, error: Option<&str>| FileStats {
+            path: path.to_owned(),
+            error: error.map(String::from),
+            ..Default::default()
+        };
+        record(&job, &file("/a.pdf", None))?;
+        record(&job, &file("/b.pdf", Some("pdftotext failed")))?;
+        record(&job, &file("/c.zip", Some("invalid zip")))?;
+        // retried successfully
+        record(&job, &file("/c.zip", None))?;
+        let mut f = std::fs::OpenOptions::new().append(true).open(&job)?;
+        f.write_all(b"{\"path\":\"/d.pd")?;
+        let state = read(&job)?;
+        assert_eq!(
+            state.done,
+            BTreeSet::from(["/a.pdf".to_owned(), "/c.zip".to_owned()])
+        );
+        assert_eq!(
+            state.failed,
+            BTreeMap::from([("/b.pdf".to_owned(), "pdftotext failed".to_owned())])
+        );
+        assert_eq!(read(&dir.path().join("missing"))?, JobState::default());
+        Ok(())
+    }
+}
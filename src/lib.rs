@@ -1,16 +1,37 @@
 #![warn(clippy::all)]
 
 pub mod adapted_iter;
+pub mod adapter_store;
 pub mod adapters;
 mod caching_writer;
 pub mod config;
+pub mod config_edit;
+pub mod config_env;
+pub mod dates;
+pub mod estimate;
 pub mod expand;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+pub mod install;
+pub mod job;
 pub mod matching;
+pub mod meta;
+pub mod policy;
+pub mod power;
 pub mod preproc;
 pub mod preproc_cache;
+pub mod queries;
 pub mod recurse;
+pub mod report;
+pub mod rgaignore;
+pub mod stats;
+pub mod strategy;
 #[cfg(test)]
 pub mod test_utils;
+pub mod testing;
+pub mod throttle;
+pub mod volumes;
+pub mod vpath;
 use anyhow::Context;
 use anyhow::Result;
 use async_stream::stream;
@@ -20,8 +20,10 @@ pub enum FastFileMatcher {
      *
      */
     FileExtension(String),
+    /// the name of the directory the file is in, e.g. "cur" for the messages of a Maildir. Only
+    /// used for files whose extension no adapter matches
+    Directory(String),
     // todo: maybe add others, e.g. regex on whole filename or even paths
-    // todo: maybe allow matching a directory (e.g. /var/lib/postgres)
 }
 
 #[derive(Clone, Debug)]
@@ -40,12 +42,14 @@ impl From<FastFileMatcher> for FileMatcher {
     }
 }
 
-pub struct FileMeta {
+pub struct FileMeta<'a> {
     // filename is not actually a utf8 string, but since we can't do regex on OsStr and can't get a &[u8] from OsStr either,
     // and since we probably only want to do only matching on ascii stuff anyways, this is the filename as a string with non-valid bytes removed
     pub lossy_filename: String,
+    /// the name of the directory the file is in, empty if there is none
+    pub lossy_dirname: String,
     // only given when slow matching is enabled
-    pub mimetype: Option<&'static str>,
+    pub mimetype: Option<&'a str>,
 }
 
 pub fn extension_to_regex(extension: &str) -> Regex {
@@ -53,6 +57,10 @@ pub fn extension_to_regex(extension: &str) -> Regex {
         .expect("we know this regex compiles")
 }
 
+pub fn directory_to_regex(directory: &str) -> Regex {
+    Regex::new(&format!("^{}$", &regex::escape(directory))).expect("we know this regex compiles")
+}
+
 pub fn adapter_matcher(
     adapters: &[Arc<dyn FileAdapter>],
     slow: bool,
@@ -60,6 +68,7 @@ pub fn adapter_matcher(
     // need order later
     let adapter_names: Vec<String> = adapters.iter().map(|e| e.metadata().name.clone()).collect();
     let mut fname_regexes = vec![];
+    let mut dir_regexes = vec![];
     let mut mime_regexes = vec![];
     for adapter in adapters.iter() {
         let metadata = adapter.metadata();
@@ -74,16 +83,33 @@ pub fn adapter_matcher(
                     adapter.clone(),
                     Fast(FastFileMatcher::FileExtension(re.clone())),
                 )),
+                Fast(FastFileMatcher::Directory(dir)) => dir_regexes.push((
+                    directory_to_regex(dir),
+                    adapter.clone(),
+                    Fast(FastFileMatcher::Directory(dir.clone())),
+                )),
             };
         }
     }
     let fname_regex_set = RegexSet::new(fname_regexes.iter().map(|p| p.0.as_str()))?;
+    let dir_regex_set = RegexSet::new(dir_regexes.iter().map(|p| p.0.as_str()))?;
     let mime_regex_set = RegexSet::new(mime_regexes.iter().map(|p| p.0.as_str()))?;
     Ok(move |meta: FileMeta| {
         let fname_matches: Vec<_> = fname_regex_set
             .matches(&meta.lossy_filename)
             .into_iter()
             .collect();
+        if fname_matches.is_empty() {
+            // before the mime types, which are less specific (a Maildir message is message/rfc822)
+            if let Some(m) = dir_regex_set
+                .matches(&meta.lossy_dirname)
+                .into_iter()
+                .next()
+            {
+                let (_, adapter, matcher) = &dir_regexes[m];
+                return Some((adapter.clone(), matcher.clone()));
+            }
+        }
         let mime_matches: Vec<_> = if slow {
             mime_regex_set
                 .matches(meta.mimetype.expect("No mimetype?"))
@@ -132,3 +158,34 @@ pub fn adapter_matcher(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn directory() -> Result<()> {
+        let adapters: Vec<Arc<dyn FileAdapter>> = vec![
+            Arc::new(mail::MaildirAdapter::new()),
+            Arc::new(zip::ZipAdapter::new()),
+        ];
+        let matcher = adapter_matcher(&adapters, false)?;
+        let adapter = |filename: &str, dirname: &str| {
+            matcher(FileMeta {
+                lossy_filename: filename.to_owned(),
+                lossy_dirname: dirname.to_owned(),
+                mimetype: None,
+            })
+            .map(|(adapter, _)| adapter.metadata().name.clone())
+        };
+        assert_eq!(
+            adapter("1700000000.M1P2.host:2,S", "cur").as_deref(),
+            Some("maildir")
+        );
+        // the extension comes first
+        assert_eq!(adapter("mail.zip", "new").as_deref(), Some("zip"));
+        assert_eq!(adapter("1700000000.M1P2.host", "Maildir"), None);
+        Ok(())
+    }
+}
@@ -0,0 +1,507 @@
+//! The metadata of documents for --rga-meta-only: titles, authors, tags and dates that are in the
+//! start or the end of a file or in a small part of a zip package, so they are found without
+//! extracting the text of the document.
+
+use crate::adapters::mail::decode_header;
+use crate::adapters::xml::{attr, xml_events, Xml};
+use crate::dates::{civil_from_days, document_date, head_and_tail, SCAN_LEN};
+use anyhow::{Context, Result};
+use log::debug;
+use memchr::memmem;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// a name and a value, like ("Title", "Annual report")
+pub type Field = (&'static str, String);
+
+/// the keys of the document information dictionary of PDFs
+static PDF_KEYS: &[(&[u8], &str)] = &[
+    (b"/Title", "Title"),
+    (b"/Author", "Author"),
+    (b"/Subject", "Subject"),
+    (b"/Keywords", "Keywords"),
+];
+
+static MAIL_HEADERS: &[(&str, &str)] = &[
+    ("From", "From"),
+    ("To", "To"),
+    ("Subject", "Subject"),
+    ("Date", "Date"),
+];
+
+/// the properties of Office Open XML (docProps/core.xml), OpenDocument (meta.xml) and EPUB (the
+/// package document) files, by their element names without namespace
+static PACKAGE_PROPERTIES: &[(&str, &str)] = &[
+    ("title", "Title"),
+    ("creator", "Author"),
+    ("initial-creator", "Author"),
+    ("subject", "Subject"),
+    ("keywords", "Keywords"),
+    ("keyword", "Keywords"),
+    ("description", "Description"),
+];
+
+/// add a field, with its whitespace collapsed. Repeated fields like the authors of a book are joined
+pub(crate) fn push(fields: &mut Vec<Field>, name: &'static str, value: &str) {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    if value.is_empty() {
+        return;
+    }
+    match fields.iter_mut().find(|(n, _)| *n == name) {
+        Some((_, v)) => {
+            if !v.split(", ").any(|old| old == value) {
+                v.push_str(", ");
+                v.push_str(&value);
+            }
+        }
+        None => fields.push((name, value)),
+    }
+}
+
+/// the value of the PDF string at the start of `data`, `(literal)` or `<hex>`. Strings starting with a
+/// byte order mark are UTF-16, the others are taken as Latin-1, which is close to PDFDocEncoding
+fn pdf_string(data: &[u8]) -> Option<String> {
+    let bytes = match data.first()? {
+        b'(' => {
+            let (mut out, mut depth, mut i) = (vec![], 0, 1);
+            loop {
+                let b = *data.get(i)?;
+                i += 1;
+                match b {
+                    b'\\' => {
+                        let e = *data.get(i)?;
+                        i += 1;
+                        match e {
+                            b'n' => out.push(b'\n'),
+                            b'r' => out.push(b'\r'),
+                            b't' => out.push(b'\t'),
+                            b'b' => out.push(8),
+                            b'f' => out.push(12),
+                            b'0'..=b'7' => {
+                                let mut n = u32::from(e - b'0');
+                                for _ in 0..2 {
+                                    match data.get(i) {
+                                        Some(d @ b'0'..=b'7') => {
+                                            n = n * 8 + u32::from(d - b'0');
+                                            i += 1;
+                                        }
+                                        _ => break,
+                                    }
+                                }
+                                out.push(n as u8);
+                            }
+                            // a line continuation
+                            b'\r' | b'\n' => {}
+                            e => out.push(e),
+                        }
+                    }
+                    b'(' => {
+                        depth += 1;
+                        out.push(b);
+                    }
+                    b')' if depth == 0 => break,
+                    b')' => {
+                        depth -= 1;
+                        out.push(b);
+                    }
+                    b => out.push(b),
+                }
+            }
+            out
+        }
+        b'<' => {
+            let end = data.iter().position(|&b| b == b'>')?;
+            let digits = data[1..end]
+                .iter()
+                .filter(|b| !b.is_ascii_whitespace())
+                .map(|&b| char::from(b).to_digit(16).map(|d| d as u8))
+                .collect::<Option<Vec<_>>>()?;
+            digits
+                .chunks(2)
+                .map(|d| d[0] << 4 | d.get(1).copied().unwrap_or(0))
+                .collect()
+        }
+        _ => return None,
+    };
+    Some(match bytes.strip_prefix(b"\xFE\xFF") {
+        Some(utf16) => String::from_utf16_lossy(
+            &utf16
+                .chunks_exact(2)
+                .map(|c| u16::from_be_bytes([c[0], c[1]]))
+                .collect::<Vec<_>>(),
+        ),
+        None => bytes.iter().map(|&b| char::from(b)).collect(),
+    })
+}
+
+/// the object header (`12 0 obj`) of the document information dictionary the last /Info in `data` points to
+fn pdf_info_ref(data: &[u8]) -> Option<String> {
+    let after = &data[memmem::rfind(data, b"/Info")? + 5..];
+    let reference = String::from_utf8_lossy(&after[..after.len().min(32)]);
+    let mut tokens = reference.split_ascii_whitespace();
+    let (number, generation) = (tokens.next()?, tokens.next()?);
+    if !tokens.next()?.starts_with('R')
+        || number.parse::<u32>().is_err()
+        || generation.parse::<u32>().is_err()
+    {
+        return None;
+    }
+    Some(format!("{number} {generation} obj"))
+}
+
+/// the content of the last object with the given header in `data`
+fn pdf_object<'a>(data: &'a [u8], header: &str) -> Option<&'a [u8]> {
+    // 12 0 obj is not 112 0 obj
+    let start = memmem::rfind_iter(data, header.as_bytes())
+        .find(|&i| i == 0 || !data[i - 1].is_ascii_digit())?
+        + header.len();
+    let len = memmem::find(&data[start..], b"endobj")?;
+    Some(&data[start..start + len])
+}
+
+/// the title, author, subject and keywords of a PDF, from its document information dictionary.
+/// Dictionaries in compressed object streams are not found
+fn pdf_info(head: &[u8], tail: &[u8]) -> Vec<Field> {
+    let mut fields = vec![];
+    let dict = [tail, head]
+        .iter()
+        .find_map(|data| pdf_info_ref(data))
+        .and_then(|header| {
+            [tail, head]
+                .iter()
+                .find_map(|data| pdf_object(data, &header))
+        });
+    let dict = match dict {
+        Some(dict) => dict,
+        None => return fields,
+    };
+    for &(key, name) in PDF_KEYS {
+        let value = memmem::find(dict, key).and_then(|i| {
+            let value = &dict[i + key.len()..];
+            let start = value.iter().position(|b| !b.is_ascii_whitespace())?;
+            pdf_string(&value[start..])
+        });
+        if let Some(value) = value {
+            push(&mut fields, name, &value);
+        }
+    }
+    fields
+}
+
+/// the sender, recipients, subject and date of the email starting with `head`. Nothing if it does
+/// not start with headers including From
+fn mail_headers(head: &[u8]) -> Vec<Field> {
+    let text = String::from_utf8_lossy(head);
+    let mut headers: Vec<(&str, String)> = vec![];
+    for line in text.lines().take_while(|l| !l.trim().is_empty()) {
+        if line.starts_with(|c: char| c == ' ' || c == '\t') {
+            // a continuation of the previous header
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        match line.split_once(':') {
+            Some((name, value)) if !name.is_empty() && !name.contains(char::is_whitespace) => {
+                headers.push((name, value.trim().to_owned()))
+            }
+            _ => return vec![],
+        }
+    }
+    if !headers.iter().any(|(n, _)| n.eq_ignore_ascii_case("from")) {
+        return vec![];
+    }
+    let mut fields = vec![];
+    for &(header, name) in MAIL_HEADERS {
+        if let Some((_, value)) = headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(header)) {
+            push(&mut fields, name, &decode_header(value));
+        }
+    }
+    fields
+}
+
+/// a text frame of an ID3 tag: an encoding byte, then ISO-8859-1, UTF-16 with a byte order mark,
+/// UTF-16BE or UTF-8 text. Multiple values are separated by NUL
+pub(crate) fn id3_text(data: &[u8]) -> Option<String> {
+    let (encoding, text) = data.split_first()?;
+    let utf16 = |text: &[u8], big_endian: bool| {
+        String::from_utf16_lossy(
+            &text
+                .chunks_exact(2)
+                .map(|c| {
+                    if big_endian {
+                        u16::from_be_bytes([c[0], c[1]])
+                    } else {
+                        u16::from_le_bytes([c[0], c[1]])
+                    }
+                })
+                .collect::<Vec<_>>(),
+        )
+    };
+    let text = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 => match text {
+            [0xFE, 0xFF, rest @ ..] => utf16(rest, true),
+            [0xFF, 0xFE, rest @ ..] => utf16(rest, false),
+            _ => utf16(text, false),
+        },
+        2 => utf16(text, true),
+        3 => String::from_utf8_lossy(text).into_owned(),
+        _ => return None,
+    };
+    Some(
+        text.split('\0')
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>()
+            .join(", "),
+    )
+}
+
+/// the frames of the ID3v2.3 or ID3v2.4 tag at the start of an audio file, as their id and content
+pub(crate) fn id3_frames(head: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut frames = vec![];
+    // older versions have shorter frame headers, files with an extended header are rare
+    if head.len() < 10
+        || !head.starts_with(b"ID3")
+        || !matches!(head[3], 3 | 4)
+        || head[5] & 0x40 != 0
+    {
+        return frames;
+    }
+    let syncsafe = |b: &[u8]| b.iter().fold(0, |n, &b| n << 7 | usize::from(b & 0x7F));
+    let end = (10 + syncsafe(&head[6..10])).min(head.len());
+    let mut pos = 10;
+    while pos + 10 <= end && head[pos] != 0 {
+        let size = &head[pos + 4..pos + 8];
+        let size = if head[3] == 4 {
+            syncsafe(size)
+        } else {
+            u32::from_be_bytes([size[0], size[1], size[2], size[3]]) as usize
+        };
+        let frame = match head.get(pos + 10..pos + 10 + size) {
+            Some(frame) => frame,
+            None => break,
+        };
+        frames.push((&head[pos..pos + 4], frame));
+        pos += 10 + size;
+    }
+    frames
+}
+
+/// the title, artist, album and genre of the ID3 tag at the start of an audio file
+fn id3_tags(head: &[u8]) -> Vec<Field> {
+    let mut fields = vec![];
+    for (id, frame) in id3_frames(head) {
+        let name = match id {
+            b"TIT2" => Some("Title"),
+            b"TPE1" => Some("Artist"),
+            b"TALB" => Some("Album"),
+            b"TCON" => Some("Genre"),
+            _ => None,
+        };
+        if let Some((name, text)) = name.zip(id3_text(frame)) {
+            push(&mut fields, name, &text);
+        }
+    }
+    fields
+}
+
+/// the metadata in the start and the end of a file, for files that are not on disk
+pub fn embedded_meta(head: &[u8], tail: &[u8]) -> Vec<Field> {
+    if head.starts_with(b"%PDF-") {
+        pdf_info(head, tail)
+    } else if head.starts_with(b"ID3") {
+        id3_tags(head)
+    } else {
+        mail_headers(head)
+    }
+}
+
+/// the member of a zip file with the given name, as text
+async fn read_entry(
+    zip: &async_zip::read::fs::ZipFileReader,
+    name: &str,
+) -> Result<Option<String>> {
+    for i in 0..zip.file().entries().len() {
+        if zip.get_entry(i)?.filename() != name {
+            continue;
+        }
+        let mut data = Vec::new();
+        let reader = zip.entry(i).await?;
+        tokio::pin!(reader);
+        reader.read_to_end(&mut data).await?;
+        return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+    }
+    Ok(None)
+}
+
+/// the properties of an office document or e-book, which are in their own part of the zip package
+async fn package_properties(path: &Path) -> Result<Vec<Field>> {
+    use async_zip::read::fs::ZipFileReader;
+    let zip = ZipFileReader::new(path).await?;
+    let mut xml = read_entry(&zip, "docProps/core.xml").await?;
+    if xml.is_none() {
+        xml = read_entry(&zip, "meta.xml").await?;
+    }
+    if xml.is_none() {
+        // the container of an EPUB points to its package document
+        let container = read_entry(&zip, "META-INF/container.xml").await?;
+        let package = container.as_deref().and_then(|container| {
+            xml_events(container).into_iter().find_map(|e| match e {
+                Xml::Start("rootfile", attrs) => attr(&attrs, "full-path").map(str::to_owned),
+                _ => None,
+            })
+        });
+        if let Some(package) = package {
+            xml = read_entry(&zip, &package).await?;
+        }
+    }
+    let mut fields = vec![];
+    let xml = match xml {
+        Some(xml) => xml,
+        None => return Ok(fields),
+    };
+    let mut current = None;
+    for event in xml_events(&xml) {
+        match event {
+            Xml::Start(element, _) => {
+                current = PACKAGE_PROPERTIES
+                    .iter()
+                    .find(|(e, _)| *e == element)
+                    .map(|(_, name)| *name)
+            }
+            Xml::Text(text) => {
+                if let Some(name) = current {
+                    push(&mut fields, name, &text);
+                }
+            }
+            Xml::End(_) => current = None,
+        }
+    }
+    Ok(fields)
+}
+
+/// like the start and end from [head_and_tail], for a stream, which is read to its end
+pub async fn read_head_and_tail(
+    inp: &mut (impl AsyncRead + Unpin),
+) -> std::io::Result<(Vec<u8>, Vec<u8>)> {
+    let scan_len = SCAN_LEN as usize;
+    let mut head = Vec::new();
+    (&mut *inp).take(SCAN_LEN).read_to_end(&mut head).await?;
+    let mut tail = Vec::new();
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = inp.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        tail.extend_from_slice(&buf[..n]);
+        if tail.len() > 2 * scan_len {
+            tail.drain(..tail.len() - scan_len);
+        }
+    }
+    if tail.len() > scan_len {
+        tail.drain(..tail.len() - scan_len);
+    }
+    Ok((head, tail))
+}
+
+/// the metadata of a file on disk, and the date inside it if there is one
+pub async fn document_meta(path: &Path) -> Result<Vec<Field>> {
+    let (head, tail) =
+        head_and_tail(path).with_context(|| format!("reading {}", path.display()))?;
+    let mut fields = if head.starts_with(b"PK\x03\x04") {
+        package_properties(path).await.unwrap_or_else(|e| {
+            debug!("{}: no package properties: {:#}", path.display(), e);
+            vec![]
+        })
+    } else {
+        embedded_meta(&head, &tail)
+    };
+    if !fields.iter().any(|(name, _)| *name == "Date") {
+        if let Some(date) = document_date(path)? {
+            let (year, month, day) = civil_from_days(date.div_euclid(86400));
+            push(
+                &mut fields,
+                "Date",
+                &format!("{year:04}-{month:02}-{day:02}"),
+            );
+        }
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn owned(fields: &[(&'static str, &str)]) -> Vec<Field> {
+        fields.iter().map(|(n, v)| (*n, v.to_string())).collect()
+    }
+
+    #[test]
+    fn pdf() {
+        let tail = b"5 0 obj\n<< /Title (Annual \\(draft\\)\\n report) /Author <FEFF00C40062> /Producer (x) >>\nendobj\n15 0 obj\n<< /Title (Chapter 1) >>\nendobj\ntrailer\n<< /Size 16 /Root 1 0 R /Info 5 0 R >>\n%%EOF";
+        assert_eq!(
+            pdf_info(b"%PDF-1.4\n", tail),
+            owned(&[("Title", "Annual (draft) report"), ("Author", "Äb")])
+        );
+        assert_eq!(pdf_string(b"(caf\\351)").as_deref(), Some("café"));
+        assert_eq!(pdf_info(b"%PDF-1.4\n", b"trailer\n<< /Size 1 >>"), vec![]);
+    }
+
+    #[test]
+    fn mail() {
+        let head = b"From: Ann <ann@example.com>\nTo: bob@example.com,\n  carl@example.com\nSubject: =?utf-8?q?Gr=C3=BC=C3=9Fe?=\nDate: Tue, 3 Jul 2001 10:20:30 +0200\n\nbody: text\n";
+        assert_eq!(
+            embedded_meta(head, b""),
+            owned(&[
+                ("From", "Ann <ann@example.com>"),
+                ("To", "bob@example.com, carl@example.com"),
+                ("Subject", "Grüße"),
+                ("Date", "Tue, 3 Jul 2001 10:20:30 +0200"),
+            ])
+        );
+        assert_eq!(
+            embedded_meta(b"title: notes\ndate: 2023-01-01\n", b""),
+            vec![]
+        );
+        assert_eq!(
+            embedded_meta(b"From ann@example.com Tue Jul  3 10:20:30 2001\n", b""),
+            vec![]
+        );
+    }
+
+    #[test]
+    fn id3() {
+        let mut frames = Vec::new();
+        for (id, text) in [
+            (b"TIT2", &b"\x03Episode 12"[..]),
+            (b"TPE1", b"\x00Ann\0Bob"),
+        ] {
+            frames.extend_from_slice(id);
+            frames.extend_from_slice(&(text.len() as u32).to_be_bytes());
+            frames.extend_from_slice(&[0, 0]);
+            frames.extend_from_slice(text);
+        }
+        let mut head = b"ID3\x03\x00\x00\x00\x00\x00".to_vec();
+        head.push(frames.len() as u8);
+        head.extend_from_slice(&frames);
+        head.extend_from_slice(&[0; 16]);
+        assert_eq!(
+            embedded_meta(&head, b""),
+            owned(&[("Title", "Episode 12"), ("Artist", "Ann, Bob")])
+        );
+    }
+
+    #[tokio::test]
+    async fn stream_tail() -> Result<()> {
+        let data = (0..200_000u32).map(|i| i as u8).collect::<Vec<_>>();
+        let (head, tail) = read_head_and_tail(&mut &data[..]).await?;
+        assert_eq!(head, &data[..SCAN_LEN as usize]);
+        assert_eq!(tail, &data[data.len() - SCAN_LEN as usize..]);
+        Ok(())
+    }
+}
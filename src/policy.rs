@@ -0,0 +1,248 @@
+//! --rga-policy-*: restrictions on the programs adapters run, for machines where only approved
+//! tools may run. Every adapter that runs a program creates it with [command]. This is not a
+//! sandbox: the programs themselves are not restricted, and adapters that don't run programs, like
+//! zip or sqlite, are not affected.
+
+use crate::config::PolicyConfig;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+
+/// the path --rga-policy-tool-paths pins `name` to
+fn pinned_path<'a>(config: &'a PolicyConfig, name: &str) -> Result<Option<&'a str>> {
+    for entry in &config.tool_paths {
+        let (tool, path) = match entry.split_once('=') {
+            Some(tool_path) => tool_path,
+            None => bail!("--rga-policy-tool-paths: {} is not name=path", entry),
+        };
+        if !Path::new(path).is_absolute() {
+            bail!("--rga-policy-tool-paths: {} is not an absolute path", path);
+        }
+        if tool == name {
+            return Ok(Some(path));
+        }
+    }
+    Ok(None)
+}
+
+/// the file name of a program given as a name or a path
+fn program_name(program: &str) -> String {
+    Path::new(program)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| program.to_owned())
+}
+
+/// the program to run for `program`, if the policy allows it
+fn resolve(config: &PolicyConfig, program: &str) -> Result<String> {
+    // custom adapters can give a path instead of a name
+    let name = program_name(program);
+    if config.forbid.contains(&name) {
+        bail!("running {} is forbidden by --rga-policy-forbid", name);
+    }
+    let resolved = match pinned_path(config, &name)? {
+        Some(path) => path.to_owned(),
+        None if config.pinned_only => bail!(
+            "{} has no path in --rga-policy-tool-paths, which --rga-policy-pinned-only requires",
+            name
+        ),
+        None => program.to_owned(),
+    };
+    // a pin can point another name at a forbidden program
+    let resolved_name = program_name(&resolved);
+    if config.forbid.contains(&resolved_name) {
+        bail!(
+            "running {} (as {}) is forbidden by --rga-policy-forbid",
+            resolved_name,
+            name
+        );
+    }
+    Ok(resolved)
+}
+
+/// create the command to run `program` on `file`, as allowed by the policy, and write it to the audit log
+pub fn command(config: &PolicyConfig, program: &str, file: &Path) -> Result<Command> {
+    let resolved = resolve(config, program)?;
+    if let Some(log) = &config.audit_log {
+        let line = serde_json::json!({
+            "time_unix_ms": SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64,
+            "program": &resolved,
+            "file": file.to_string_lossy(),
+        });
+        // a single write, so lines of parallel rga-preproc processes don't get mixed up
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(log)
+            .and_then(|mut f| f.write_all(format!("{line}\n").as_bytes()))
+            .with_context(|| format!("writing audit log {log}"))?;
+    }
+    Ok(Command::new(resolved))
+}
+
+/// the policy of the machine config file, tightened by `lower` (the user config, environment and
+/// arguments). Lower layers can forbid more programs and pin more paths, but not allow more
+fn tighten(machine: &PolicyConfig, lower: &PolicyConfig) -> PolicyConfig {
+    let mut forbid = machine.forbid.clone();
+    forbid.extend(
+        lower
+            .forbid
+            .iter()
+            .filter(|p| !machine.forbid.contains(*p))
+            .cloned(),
+    );
+    let machine_pins = |entry: &String| {
+        let tool = entry.split_once('=').map_or(entry.as_str(), |e| e.0);
+        machine
+            .tool_paths
+            .iter()
+            .any(|m| m.split_once('=').map_or(m.as_str(), |m| m.0) == tool)
+    };
+    let mut tool_paths = machine.tool_paths.clone();
+    if !machine.pinned_only {
+        // with pinned_only, another pin would allow one more program
+        tool_paths.extend(
+            lower
+                .tool_paths
+                .iter()
+                .filter(|e| !machine_pins(e))
+                .cloned(),
+        );
+    }
+    PolicyConfig {
+        forbid,
+        no_network: machine.no_network || lower.no_network,
+        tool_paths,
+        pinned_only: machine.pinned_only || lower.pinned_only,
+        audit_log: machine
+            .audit_log
+            .clone()
+            .or_else(|| lower.audit_log.clone()),
+    }
+}
+
+/// apply the "policy" section of the machine config file to the merged config, last, so users can't
+/// loosen it with their own config file, RGA_* variables, RGA_CONFIG or arguments
+pub(crate) fn enforce_machine(merged: &mut Value, machine_config: &Value) -> Result<()> {
+    let machine = match machine_config.get("policy") {
+        Some(policy) => serde_json::from_value::<PolicyConfig>(policy.clone())
+            .context("invalid policy in the machine config")?,
+        None => return Ok(()),
+    };
+    let lower = match merged.get("policy") {
+        Some(policy) if !policy.is_null() => serde_json::from_value(policy.clone())?,
+        _ => PolicyConfig::default(),
+    };
+    merged["policy"] = serde_json::to_value(tighten(&machine, &lower))?;
+    Ok(())
+}
+
+/// fail if the policy forbids adapters that use the network
+pub fn check_network(config: &PolicyConfig, adapter: &str) -> Result<()> {
+    if config.no_network {
+        bail!(
+            "the {} adapter uses the network, which --rga-policy-no-network forbids",
+            adapter
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rules() -> Result<()> {
+        let mut config = PolicyConfig {
+            forbid: vec!["curl".to_owned()],
+            tool_paths: vec!["pdftotext=/opt/poppler/bin/pdftotext".to_owned()],
+            ..Default::default()
+        };
+        assert!(resolve(&config, "curl").is_err());
+        assert!(resolve(&config, "/usr/bin/curl").is_err());
+        assert_eq!(resolve(&config, "pdftotext")?, "/opt/poppler/bin/pdftotext");
+        assert_eq!(resolve(&config, "tesseract")?, "tesseract");
+        config.tool_paths.push("foo=/usr/bin/curl".to_owned());
+        assert!(resolve(&config, "foo").is_err());
+        config.pinned_only = true;
+        assert!(resolve(&config, "tesseract").is_err());
+        config.tool_paths = vec!["tesseract=tesseract".to_owned()];
+        assert!(resolve(&config, "tesseract").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn machine_policy() -> Result<()> {
+        let machine = serde_json::json!({"policy": {
+            "forbid": ["curl"],
+            "no_network": true,
+            "tool_paths": ["pdftotext=/usr/bin/pdftotext"],
+            "pinned_only": true,
+            "audit_log": "/var/log/rga.jsonl",
+        }});
+        // the user config tries to unset all of it, and the arguments allow another program
+        let mut merged = machine.clone();
+        crate::config::json_merge(
+            &mut merged,
+            &serde_json::json!({"policy": {
+                "forbid": ["ffmpeg"],
+                "no_network": false,
+                "tool_paths": ["pdftotext=/tmp/pdftotext", "sh=/bin/sh"],
+                "pinned_only": false,
+                "audit_log": null,
+            }}),
+        );
+        crate::config::json_merge(&mut merged, &serde_json::json!({"policy": {"forbid": []}}));
+        enforce_machine(&mut merged, &machine)?;
+        let policy: PolicyConfig = serde_json::from_value(merged["policy"].clone())?;
+        assert_eq!(
+            policy,
+            PolicyConfig {
+                forbid: vec!["curl".to_owned()],
+                no_network: true,
+                tool_paths: vec!["pdftotext=/usr/bin/pdftotext".to_owned()],
+                pinned_only: true,
+                audit_log: Some("/var/log/rga.jsonl".to_owned()),
+            }
+        );
+
+        // lower layers can still tighten what the machine leaves open
+        let machine = serde_json::json!({"policy": {"forbid": ["curl"]}});
+        let mut merged = serde_json::json!({"policy": {
+            "forbid": ["ffmpeg"],
+            "pinned_only": true,
+            "tool_paths": ["tesseract=/usr/bin/tesseract"],
+        }});
+        enforce_machine(&mut merged, &machine)?;
+        let policy: PolicyConfig = serde_json::from_value(merged["policy"].clone())?;
+        assert_eq!(policy.forbid, vec!["curl", "ffmpeg"]);
+        assert!(policy.pinned_only);
+        assert_eq!(policy.tool_paths, vec!["tesseract=/usr/bin/tesseract"]);
+        Ok(())
+    }
+
+    #[test]
+    fn audit_log() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let log = dir.path().join("audit.jsonl");
+        let config = PolicyConfig {
+            audit_log: Some(log.to_string_lossy().into_owned()),
+            ..Default::default()
+        };
+        command(&config, "pdftotext", Path::new("/docs/a.pdf"))?;
+        command(&config, "tesseract", Path::new("/docs/b.png"))?;
+        let lines = std::fs::read_to_string(&log)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<Result<Vec<serde_json::Value>, _>>()?;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["program"], "tesseract");
+        assert_eq!(lines[1]["file"], "/docs/b.png");
+        Ok(())
+    }
+}
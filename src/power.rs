@@ -0,0 +1,96 @@
+//! --rga-battery-pause: adapters that are too expensive to run on battery power. They are left out
+//! of the run, so the files they would adapt are picked up by the next run on mains power. The
+//! cache key of archives contains the active adapters, so their output is not reused then.
+
+use crate::adapters::adapters_without;
+use crate::config::RgaConfig;
+use anyhow::Result;
+use std::path::Path;
+
+/// whether the computer runs on battery power. False if that can't be determined
+pub fn on_battery() -> bool {
+    if cfg!(target_os = "macos") {
+        match std::process::Command::new("pmset")
+            .args(["-g", "batt"])
+            .output()
+        {
+            Ok(out) => String::from_utf8_lossy(&out.stdout).contains("'Battery Power'"),
+            Err(_) => false,
+        }
+    } else {
+        on_battery_sysfs(Path::new("/sys/class/power_supply"))
+    }
+}
+
+/// on Linux, a battery of the system (not of a wireless mouse etc.) is discharging
+fn on_battery_sysfs(dir: &Path) -> bool {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return false,
+    };
+    entries.flatten().any(|entry| {
+        let read = |name: &str| {
+            std::fs::read_to_string(entry.path().join(name))
+                .unwrap_or_default()
+                .trim()
+                .to_owned()
+        };
+        read("type") == "Battery" && read("scope") != "Device" && read("status") == "Discharging"
+    })
+}
+
+/// the adapters to use on battery power: the chosen ones without the paused ones
+pub fn adapters_on_battery(config: &RgaConfig) -> Result<Vec<String>> {
+    adapters_without(config, &config.battery_pause)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn supply(dir: &Path, name: &str, files: &[(&str, &str)]) -> Result<()> {
+        let dir = dir.join(name);
+        std::fs::create_dir(&dir)?;
+        for (file, content) in files {
+            std::fs::write(dir.join(file), format!("{content}\n"))?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn sysfs() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        supply(dir.path(), "AC", &[("type", "Mains"), ("online", "1")])?;
+        supply(
+            dir.path(),
+            "hidpp_battery_0",
+            &[
+                ("type", "Battery"),
+                ("scope", "Device"),
+                ("status", "Discharging"),
+            ],
+        )?;
+        supply(
+            dir.path(),
+            "BAT0",
+            &[("type", "Battery"), ("status", "Charging")],
+        )?;
+        assert!(!on_battery_sysfs(dir.path()));
+        std::fs::write(dir.path().join("BAT0/status"), "Discharging\n")?;
+        assert!(on_battery_sysfs(dir.path()));
+        assert!(!on_battery_sysfs(&dir.path().join("missing")));
+        Ok(())
+    }
+
+    #[test]
+    fn paused_adapters() -> Result<()> {
+        let config = RgaConfig {
+            adapters: vec!["pdf".to_owned(), "ocr".to_owned(), "zip".to_owned()],
+            battery_pause: vec!["ocr".to_owned()],
+            ..Default::default()
+        };
+        assert_eq!(adapters_on_battery(&config)?, vec!["pdf", "zip"]);
+        Ok(())
+    }
+}
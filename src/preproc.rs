@@ -1,38 +1,153 @@
-use crate::adapted_iter::AdaptedFilesIterBox;
+use crate::adapted_iter::{one_file, AdaptedFilesIterBox};
 use crate::adapters::*;
-use crate::caching_writer::async_read_and_write_to_cache;
-use crate::config::RgaConfig;
+use crate::caching_writer::{async_read_and_write_to_cache, Compressed, Spill};
+use crate::config::{CacheConfig, RgaConfig};
 use crate::matching::*;
-use crate::preproc_cache::CacheKey;
-use crate::recurse::concat_read_streams;
+use crate::preproc_cache::{file_identity, files_dir, CacheKey};
+use crate::recurse::{cheap_first, concat_read_streams};
+use crate::volumes::{detect_volume, open_volumes, Volume};
+use crate::vpath::VirtualPath;
 use crate::{
-    preproc_cache::{open_cache_db, PreprocCache},
-    print_bytes,
+    dates::DateFilter,
+    install, meta,
+    preproc_cache::{open_cache_db, PreprocCache, SqliteCache},
+    print_bytes, print_dur, rgaignore, stats,
+    strategy::DeepFiles,
+    throttle,
 };
 use anyhow::*;
 use async_compression::tokio::bufread::ZstdDecoder;
 use async_stream::stream;
+use bytes::Bytes;
 // use futures::future::{BoxFuture, FutureExt};
 use log::*;
-use postproc::PostprocPrefix;
+use postproc::{prefixed_line, PostprocPrefix};
 use std::future::Future;
 use std::io::Cursor;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+use tokio::fs::File;
 use tokio::io::AsyncBufRead;
 use tokio::io::AsyncBufReadExt;
+use tokio::io::AsyncReadExt;
+use tokio::io::AsyncWriteExt;
 use tokio::io::BufReader;
+use tokio_stream::StreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
 
 pub type ActiveAdapters = Vec<Arc<dyn FileAdapter>>;
 
+/// How much of the archives in one top-level file has been unpacked so far, shared by all levels of recursion.
+/// The limits are --rga-max-archive-members and --rga-max-extracted-bytes
+#[derive(Default)]
+struct Budget {
+    members: AtomicU64,
+    bytes: AtomicU64,
+    /// the marker is only output once, not once per level of nesting
+    reported: AtomicBool,
+}
+
+impl Budget {
+    /// why no more members should be unpacked, if the budget is used up
+    fn exhausted(&self, config: &RgaConfig) -> Option<String> {
+        let members = self.members.load(Ordering::Relaxed);
+        if members >= config.max_archive_members.0 {
+            return Some(format!("max archive members reached ({})", members));
+        }
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        if bytes >= config.max_extracted_bytes.0 {
+            return Some(format!(
+                "max extracted bytes reached ({})",
+                print_bytes(bytes as f64)
+            ));
+        }
+        None
+    }
+}
+
+/// count the bytes read from a member against the budget
+fn count_bytes(inp: ReadBox, budget: Arc<Budget>) -> ReadBox {
+    Box::pin(StreamReader::new(ReaderStream::new(inp).map(
+        move |bytes| {
+            if let Ok(bytes) = &bytes {
+                budget
+                    .bytes
+                    .fetch_add(bytes.len() as u64, Ordering::Relaxed);
+            }
+            bytes
+        },
+    )))
+}
+
+/// the mimetype of the file, for --rga-accurate. It is remembered in the cache for files on disk, so
+/// warm searches don't sniff unchanged files again. The cache is opened into `cache`, so
+/// adapt_caching can use it too
+async fn sniff_mimetype(
+    config: &RgaConfig,
+    filepath_hint: &Path,
+    is_real_file: bool,
+    inp: &mut (impl AsyncBufRead + Unpin),
+    cache: &mut Option<SqliteCache>,
+) -> Result<String> {
+    let remember = is_real_file && !config.cache.disabled;
+    let identity = if remember {
+        let (file_path, version) = file_identity(filepath_hint)?;
+        let cache = match cache {
+            Some(cache) => cache.clone(),
+            None => cache
+                .insert(open_cache_db(Path::new(&config.cache.path.0)).await?)
+                .clone(),
+        };
+        if let Some(mimetype) = cache.get_mimetype(&file_path, version).await? {
+            debug!("mimetype (cached): {:?}", mimetype);
+            return Ok(mimetype);
+        }
+        Some((cache, file_path, version))
+    } else {
+        None
+    };
+    let buf = inp.fill_buf().await?; // fill but do not consume!
+    let mimetype = tree_magic::from_u8(buf);
+    debug!("mimetype: {:?}", mimetype);
+    if let Some((mut cache, file_path, version)) = identity {
+        cache.set_mimetype(&file_path, version, mimetype).await?;
+    }
+    Ok(mimetype.to_owned())
+}
+
 async fn choose_adapter(
     config: &RgaConfig,
     filepath_hint: &Path,
     archive_recursion_depth: i32,
+    is_real_file: bool,
     inp: &mut (impl AsyncBufRead + Unpin),
+    cache: &mut Option<SqliteCache>,
 ) -> Result<Option<(Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters)>> {
     let active_adapters = get_adapters_filtered(config.custom_adapters.clone(), &config.adapters)?;
+    if let Some(name) = config
+        .retry_with
+        .as_ref()
+        .filter(|_| archive_recursion_depth == 0)
+    {
+        // --rga-retry-with can also use adapters that are disabled
+        let (enabled, disabled) = get_all_adapters(config.custom_adapters.clone());
+        let adapter = enabled
+            .into_iter()
+            .chain(disabled)
+            .find(|a| &a.metadata().name == name)
+            .ok_or_else(|| format_err!("Unknown adapter: \"{}\"", name))?;
+        // some adapters decide what to do by the extension they were chosen for
+        let extension = filepath_hint
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        let matcher = FileMatcher::Fast(FastFileMatcher::FileExtension(extension));
+        return Ok(Some((adapter, matcher, active_adapters)));
+    }
     let adapters = adapter_matcher(&active_adapters, config.accurate)?;
     let filename = filepath_hint
         .file_name()
@@ -40,17 +155,32 @@ async fn choose_adapter(
     debug!("Archive recursion depth: {}", archive_recursion_depth);
 
     let mimetype = if config.accurate {
-        let buf = inp.fill_buf().await?; // fill but do not consume!
-        let mimetype = tree_magic::from_u8(buf);
-        debug!("mimetype: {:?}", mimetype);
-        Some(mimetype)
+        Some(sniff_mimetype(config, filepath_hint, is_real_file, inp, cache).await?)
     } else {
         None
     };
     let adapter = adapters(FileMeta {
-        mimetype,
+        mimetype: mimetype.as_deref(),
         lossy_filename: filename.to_string_lossy().to_string(),
+        lossy_dirname: filepath_hint
+            .parent()
+            .and_then(Path::file_name)
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default(),
     });
+    if adapter.is_none() && config.accurate {
+        // carve strings out of binary data nothing else understands, if enabled
+        if let Some(strings) = active_adapters
+            .iter()
+            .find(|a| a.metadata().name == "strings")
+        {
+            let buf = inp.fill_buf().await?;
+            if memchr::memchr(0, buf).is_some() {
+                let matcher = FileMatcher::MimeType(mimetype.unwrap_or_default());
+                return Ok(Some((strings.clone(), matcher, active_adapters)));
+            }
+        }
+    }
     Ok(adapter.map(|e| (e.0, e.1, active_adapters)))
 }
 
@@ -58,13 +188,16 @@ enum Ret {
     Recurse(AdaptInfo, Arc<dyn FileAdapter>, FileMatcher, ActiveAdapters),
     Passthrough(AdaptInfo),
 }
-async fn buf_choose_adapter(ai: AdaptInfo) -> Result<Ret> {
+/// `cache` is the cache database if it was opened to look up the mimetype, or to open it into
+async fn buf_choose_adapter(ai: AdaptInfo, cache: &mut Option<SqliteCache>) -> Result<Ret> {
     let mut inp = BufReader::with_capacity(1 << 16, ai.inp);
     let adapter = choose_adapter(
         &ai.config,
         &ai.filepath_hint,
         ai.archive_recursion_depth,
+        ai.is_real_file,
         &mut inp,
+        cache,
     )
     .await?;
     let ai = AdaptInfo {
@@ -107,27 +240,317 @@ async fn buf_choose_adapter(ai: AdaptInfo) -> Result<Ret> {
  *
  */
 pub async fn rga_preproc(ai: AdaptInfo) -> Result<ReadBox> {
+    let budget = ai.config.per_file_budget.map(Duration::from_secs);
+    let fph = ai.filepath_hint.clone();
+    let start = Instant::now();
+    let inp = match budget {
+        Some(budget) => match tokio::time::timeout(budget, rga_preproc_unlimited(ai)).await {
+            Ok(inp) => inp?,
+            Err(_) => Box::pin(tokio::io::empty()),
+        },
+        None => rga_preproc_unlimited(ai).await?,
+    };
+    Ok(limit_time(inp, fph, start, budget))
+}
+
+/// interval of the debug messages about files that take long to preprocess
+const HEARTBEAT: Duration = Duration::from_secs(10);
+
+/// cut the output short with a marker once `budget` (--rga-per-file-budget) has passed since `start`.
+/// Output cut short is not cached, since the caching writer only stores complete output
+fn limit_time(
+    inp: ReadBox,
+    filepath_hint: PathBuf,
+    start: Instant,
+    budget: Option<Duration>,
+) -> ReadBox {
+    let s = stream! {
+        let mut stream = ReaderStream::new(inp);
+        let mut len = 0;
+        loop {
+            let wait = match budget {
+                Some(budget) => match budget.checked_sub(start.elapsed()) {
+                    Some(left) if !left.is_zero() => left.min(HEARTBEAT),
+                    _ => {
+                        let s = format!("\n[rga: per-file budget of {}s used up, output truncated]\n", budget.as_secs());
+                        stats::note_error(format!("per-file budget of {}s used up", budget.as_secs()));
+                        yield Ok(Bytes::from(s));
+                        break;
+                    }
+                },
+                None => HEARTBEAT,
+            };
+            match tokio::time::timeout(wait, stream.next()).await {
+                Ok(Some(bytes)) => {
+                    if let Ok(bytes) = &bytes {
+                        len += bytes.len();
+                    }
+                    yield bytes;
+                }
+                Ok(None) => break,
+                Err(_) => debug!(
+                    "{}: still running after {}, {} of output so far",
+                    filepath_hint.to_string_lossy(),
+                    print_dur(start),
+                    print_bytes(len as f64)
+                ),
+            }
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+async fn rga_preproc_unlimited(ai: AdaptInfo) -> Result<ReadBox> {
     debug!("path (hint) to preprocess: {:?}", ai.filepath_hint);
 
-    // todo: figure out when using a bufreader is a good idea and when it is not
-    // seems to be good for File::open() reads, but not sure about within archives (tar, zip)
-    let (ai, adapter, detection_reason, active_adapters) = match buf_choose_adapter(ai).await? {
-        Ret::Recurse(ai, a, b, c) => (ai, a, b, c),
-        Ret::Passthrough(ai) => {
-            return Ok(ai.inp);
+    let ai = if ai.is_real_file {
+        match rgaignore::adapters_for(&ai.config, &ai.filepath_hint)? {
+            Some(adapters) => AdaptInfo {
+                config: RgaConfig {
+                    adapters,
+                    ..ai.config
+                },
+                ..ai
+            },
+            None => {
+                debug!("skipped by {}", rgaignore::FILE_NAME);
+                return Ok(Box::pin(tokio::io::empty()));
+            }
         }
+    } else {
+        ai
     };
+    if ai.is_real_file {
+        if let Some(filter) = DateFilter::new(&ai.config)? {
+            if !filter.includes(&ai.filepath_hint)? {
+                debug!("skipped by --rga-newer-than or --rga-older-than");
+                return Ok(Box::pin(tokio::io::empty()));
+            }
+        }
+        if let Some(deep) = DeepFiles::from_env()? {
+            if !deep.includes(&ai.filepath_hint) {
+                debug!("skipped, nothing near it matched in the first pass of --rga-strategy=auto");
+                return Ok(Box::pin(tokio::io::empty()));
+            }
+        }
+    }
+    let ai = if ai.is_real_file {
+        match detect_volume(&ai.filepath_hint) {
+            Some(Volume::First { joined, parts }) => {
+                debug!("joining {} archive parts as {:?}", parts.len(), joined);
+                // not a real file anymore, so this is not cached
+                AdaptInfo {
+                    inp: open_volumes(&parts).await?,
+                    filepath_hint: joined,
+                    is_real_file: false,
+                    ..ai
+                }
+            }
+            Some(Volume::Later { first }) => {
+                let s = format!(
+                    "{}[rga: part of a split archive, searched with {}]\n",
+                    ai.line_prefix,
+                    first.display()
+                );
+                return Ok(Box::pin(Cursor::new(s.into_bytes())));
+            }
+            None => ai,
+        }
+    } else {
+        ai
+    };
+    let ai = match ai.config.throttle.read_mb_per_sec {
+        Some(mb) => AdaptInfo {
+            inp: throttle::limit_rate(ai.inp, mb * 1_000_000.0),
+            ..ai
+        },
+        None => ai,
+    };
+
+    // todo: figure out when using a bufreader is a good idea and when it is not
+    // seems to be good for File::open() reads, but not sure about within archives (tar, zip)
+    let mut cache = None;
+    let (ai, adapter, detection_reason, active_adapters) =
+        match buf_choose_adapter(ai, &mut cache).await? {
+            Ret::Recurse(ai, a, b, c) => (ai, a, b, c),
+            Ret::Passthrough(ai) => {
+                return Ok(ai.inp);
+            }
+        };
     let path_hint_copy = ai.filepath_hint.clone();
-    adapt_caching(ai, adapter, detection_reason, active_adapters)
+    if ai.config.meta_only {
+        return Ok(concat_read_streams(
+            meta_only(ai, adapter, detection_reason)
+                .await
+                .with_context(|| format!("reading metadata of {}", path_hint_copy.display()))?,
+        ));
+    }
+    adapt_caching(ai, adapter, detection_reason, active_adapters, cache)
         .await
         .with_context(|| format!("run_adapter({})", &path_hint_copy.to_string_lossy()))
 }
 
+/// the member of an archive a virtual path like `archive.zip!/dir/doc.docx` points to (see vpath.rs),
+/// ready to be preprocessed. The page of the virtual path is ignored
+pub async fn open_virtual_path(vpath: &VirtualPath, config: RgaConfig) -> Result<AdaptInfo> {
+    let mut ai = AdaptInfo {
+        inp: Box::pin(
+            File::open(&vpath.file)
+                .await
+                .with_context(|| format!("opening {}", vpath.file.display()))?,
+        ),
+        filepath_hint: vpath.file.clone(),
+        is_real_file: true,
+        line_prefix: String::new(),
+        archive_recursion_depth: 0,
+        postprocess: true,
+        config,
+    };
+    for member in &vpath.members {
+        let fph = ai.filepath_hint.clone();
+        ai = match buf_choose_adapter(ai, &mut None).await? {
+            Ret::Recurse(ai, adapter, detection_reason, _) if adapter.metadata().recurses => {
+                find_member(adapter.as_ref(), detection_reason, ai, member)
+                    .await
+                    .with_context(|| format!("opening {}", vpath))?
+            }
+            _ => bail!("{} is not an archive", fph.display()),
+        };
+    }
+    Ok(ai)
+}
+
+async fn find_member(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    ai: AdaptInfo,
+    name: &str,
+) -> Result<AdaptInfo> {
+    let fph = ai.filepath_hint.clone();
+    // archive files on disk are read by seeking (e.g. via the central directory of zips), so
+    // members that are not needed are skipped without reading them
+    let seeking = ai.is_real_file;
+    let mut members = adapter.adapt(ai, &detection_reason).await?;
+    while let Some(member) = members.next().await {
+        let mut member = member?;
+        if member.filepath_hint != Path::new(name) {
+            if !seeking {
+                // streaming archive readers need each member to be read fully before the next one
+                tokio::io::copy(&mut member.inp, &mut tokio::io::sink()).await?;
+            }
+            continue;
+        }
+        // the member can borrow from the archive reader, so it can't outlive the stream
+        let mut data = Vec::new();
+        member.inp.read_to_end(&mut data).await?;
+        return Ok(AdaptInfo {
+            inp: Box::pin(Cursor::new(data)),
+            line_prefix: String::new(),
+            archive_recursion_depth: 0,
+            ..member
+        });
+    }
+    bail!("{} not found in {}", name, fph.display())
+}
+
+/// a file with the given text in place of `ai`
+fn text_file(ai: &AdaptInfo, text: String) -> AdaptInfo {
+    AdaptInfo {
+        inp: Box::pin(Cursor::new(text.into_bytes())),
+        filepath_hint: ai.filepath_hint.clone(),
+        is_real_file: false,
+        line_prefix: ai.line_prefix.clone(),
+        archive_recursion_depth: ai.archive_recursion_depth,
+        postprocess: ai.postprocess,
+        config: ai.config.clone(),
+    }
+}
+
+/// --rga-meta-only: the metadata of the file (see meta.rs) instead of the output of its adapter, and the
+/// names of the members of archives, recursively
+fn meta_only(
+    ai: AdaptInfo,
+    adapter: Arc<dyn FileAdapter>,
+    detection_reason: FileMatcher,
+) -> Pin<Box<dyn Future<Output = Result<AdaptedFilesIterBox>> + Send>> {
+    Box::pin(async move {
+        let column = ai.config.prefix_column;
+        let recurses = adapter.metadata().recurses;
+        let mut inp = BufReader::with_capacity(1 << 16, ai.inp);
+        let fields = if ai.is_real_file {
+            meta::document_meta(&ai.filepath_hint).await?
+        } else if recurses {
+            // the rest of the stream is still needed for the member list
+            meta::embedded_meta(inp.fill_buf().await?, &[])
+        } else {
+            let (head, tail) = meta::read_head_and_tail(&mut inp).await?;
+            meta::embedded_meta(&head, &tail)
+        };
+        let ai = AdaptInfo {
+            inp: Box::pin(inp),
+            ..ai
+        };
+        let text = fields
+            .iter()
+            .map(|(name, value)| {
+                prefixed_line(&ai.line_prefix, &format!("{name}: {value}"), column)
+            })
+            .collect::<String>();
+        let fields = text_file(&ai, text);
+        if !recurses {
+            return Ok(one_file(fields));
+        }
+        let line_prefix = ai.line_prefix.clone();
+        let (ai, spool) = provide_input(adapter.as_ref(), ai).await?;
+        let members = keep_spool(adapter.adapt(ai, &detection_reason).await?, spool);
+        let s = stream! {
+            yield Ok(fields);
+            for await member in members {
+                let member = member?;
+                let name = prefixed_line(&line_prefix, &member.filepath_hint.to_string_lossy(), column);
+                yield Ok(text_file(&member, name));
+                match buf_choose_adapter(member, &mut None).await? {
+                    Ret::Recurse(ai, adapter, detection_reason, _)
+                        if !adapter.metadata().recurses
+                            || ai.archive_recursion_depth < ai.config.max_archive_recursion.0 =>
+                    {
+                        for await file in meta_only(ai, adapter, detection_reason).await? {
+                            yield file;
+                        }
+                    }
+                    Ret::Recurse(mut ai, ..) | Ret::Passthrough(mut ai) => {
+                        // streaming archive readers need each member to be read fully before the next one
+                        tokio::io::copy(&mut ai.inp, &mut tokio::io::sink()).await?;
+                    }
+                }
+            }
+        };
+        Ok(Box::pin(s) as AdaptedFilesIterBox)
+    })
+}
+
+/// whether an output is worth caching: outputs that were computed faster than they can be read back
+/// from the cache are recomputed instead, see --rga-cache-min-time and --rga-cache-min-time-per-mb
+fn worth_caching(cache: &CacheConfig, elapsed: Duration, compressed_len: u64) -> bool {
+    let min_time = Duration::from_millis(cache.min_time.0)
+        .max(Duration::from_millis(cache.min_time_per_mb.0).mul_f64(compressed_len as f64 / 1e6));
+    if elapsed < min_time {
+        debug!(
+            "not caching output of {} computed in {}ms, cheaper to recompute",
+            print_bytes(compressed_len as f64),
+            elapsed.as_millis()
+        );
+        return false;
+    }
+    true
+}
+
 async fn adapt_caching(
     ai: AdaptInfo,
     adapter: Arc<dyn FileAdapter>,
     detection_reason: FileMatcher,
     active_adapters: ActiveAdapters,
+    cache: Option<SqliteCache>,
 ) -> Result<ReadBox> {
     let meta = adapter.metadata();
     debug!(
@@ -142,63 +565,231 @@ async fn adapt_caching(
     let cache_compression_level = ai.config.cache.compression_level;
     let cache_max_blob_len = ai.config.cache.max_blob_len;
 
-    let cache = if ai.is_real_file && !ai.config.cache.disabled {
-        Some(open_cache_db(Path::new(&ai.config.cache.path.0)).await?)
-    } else {
-        None
+    let use_cache = ai.is_real_file
+        && !ai.config.cache.disabled
+        && (!meta.sensitive || ai.config.cache.cache_sensitive);
+    if !use_cache {
+        stats::adapter_used(&meta.name, ai.archive_recursion_depth, false);
+        let slot = throttle::acquire_slot(&ai.config.throttle).await?;
+        let inp = loop_adapt(adapter.as_ref(), detection_reason, ai).await?;
+        return Ok(throttle::keep_slot(concat_read_streams(inp), slot));
+    }
+    // already open if it was used to look up the mimetype
+    let mut cache = match cache {
+        Some(cache) => cache,
+        None => open_cache_db(Path::new(&ai.config.cache.path.0)).await?,
     };
-
-    let mut cache = cache.context("No cache?")?;
-    let cache_key = CacheKey::new(&ai.filepath_hint, adapter.as_ref(), &active_adapters)?;
+    let cache_key = CacheKey::new(
+        &ai.filepath_hint,
+        adapter.as_ref(),
+        &active_adapters,
+        &ai.config,
+    )?;
+    // rga prints a single note about the quarantined files, see --rga-quarantine-after
+    if ai.archive_recursion_depth == 0 && ai.config.retry_with.is_none() {
+        if let Some(reason) = cache.quarantined(&cache_key.file_path).await? {
+            debug!("skipping quarantined file: {}", reason);
+            return Ok(Box::pin(tokio::io::empty()));
+        }
+    }
     // let dbg_ctx = format!("adapter {}", &adapter.metadata().name);
     let cached = cache.get(&cache_key).await.context("cache.get")?;
+    stats::adapter_used(&meta.name, ai.archive_recursion_depth, cached.is_some());
     match cached {
-        Some(cached) => Ok(Box::pin(ZstdDecoder::new(Cursor::new(cached)))),
+        Some(cached) => Ok(Box::pin(ZstdDecoder::new(BufReader::new(cached)))),
         None => {
             debug!("cache MISS, running adapter with caching...");
-            let inp = loop_adapt(adapter.as_ref(), detection_reason, ai).await?;
+            let slot = throttle::acquire_slot(&ai.config.throttle).await?;
+            let start = Instant::now();
+            let cache_config = ai.config.cache.clone();
+            let recurses = meta.recurses;
+            let max_file_len = ai.config.cache.max_file_len.0;
+            let spill = (max_file_len > 0).then(|| Spill {
+                dir: files_dir(Path::new(&ai.config.cache.path.0)),
+                max_len: max_file_len,
+            });
+            let budget = Arc::new(Budget::default());
+            let inp = if recurses {
+                loop_adapt_resumable(
+                    adapter.as_ref(),
+                    detection_reason,
+                    ai,
+                    cache.clone(),
+                    cache_key.clone(),
+                    budget.clone(),
+                )
+                .await?
+            } else {
+                loop_adapt(adapter.as_ref(), detection_reason, ai).await?
+            };
             let inp = concat_read_streams(inp);
             let inp = async_read_and_write_to_cache(
                 inp,
                 cache_max_blob_len.0,
                 cache_compression_level.0,
+                spill,
                 Box::new(move |(uncompressed_size, compressed)| {
                     Box::pin(async move {
                         debug!(
                             "uncompressed output: {}",
                             print_bytes(uncompressed_size as f64)
                         );
-                        if let Some(cached) = compressed {
-                            debug!("compressed output: {}", print_bytes(cached.len() as f64));
+                        // the files of a missing program were left out, and the rest of an archive that
+                        // used up --rga-max-archive-members or --rga-max-extracted-bytes
+                        if install::any_missing() || budget.reported.load(Ordering::Relaxed) {
+                            return Ok(());
+                        }
+                        let compressed = match compressed {
+                            Some(compressed) => compressed,
+                            None => return Ok(()),
+                        };
+                        let compressed_len = match &compressed {
+                            Compressed::Blob(cached) => cached.len() as u64,
+                            Compressed::File(cached) => std::fs::metadata(cached)?.len(),
+                        };
+                        if !worth_caching(&cache_config, start.elapsed(), compressed_len) {
+                            return Ok(());
+                        }
+                        let stored = match compressed {
+                            Compressed::Blob(cached) => {
+                                debug!("compressed output: {}", print_bytes(cached.len() as f64));
+                                cache.set(&cache_key, cached).await
+                            }
+                            Compressed::File(cached) => {
+                                debug!("compressed output cached in {}", cached.display());
+                                cache.set_file(&cache_key, cached).await
+                            }
+                        };
+                        stored.context("writing to cache")?;
+                        if recurses {
                             cache
-                                .set(&cache_key, cached)
+                                .clear_members(&cache_key)
                                 .await
-                                .context("writing to cache")?
+                                .context("clearing member cache")?;
                         }
                         Ok(())
                     })
                 }),
             )?;
 
-            Ok(Box::pin(inp))
+            Ok(throttle::keep_slot(Box::pin(inp), slot))
         }
     }
 }
 
+/// like loop_adapt, but the output of each member of the archive is also cached on its own, so a run
+/// that is interrupted in the middle of a huge archive resumes after the last completed member
+async fn loop_adapt_resumable(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    ai: AdaptInfo,
+    cache: impl PreprocCache + Clone + Send + Sync + 'static,
+    cache_key: CacheKey,
+    budget: Arc<Budget>,
+) -> Result<AdaptedFilesIterBox> {
+    let fph = ai.filepath_hint.clone();
+    let reorder = ai.config.cheap_first && adapter.metadata().recurses;
+    let max_blob_len = ai.config.cache.max_blob_len.0;
+    let compression_level = ai.config.cache.compression_level.0;
+    let (ai, spool) = provide_input(adapter, ai).await?;
+    let inp = skip_missing_program(adapter, adapter.adapt(ai, &detection_reason).await)
+        .with_context(|| {
+            format!(
+                "adapting {} via {} failed",
+                fph.to_string_lossy(),
+                adapter.metadata().name
+            )
+        })?;
+    let inp = keep_spool(if reorder { cheap_first(inp) } else { inp }, spool);
+    let s = stream! {
+        let mut index = 0;
+        for await file in inp {
+            let mut file = file?;
+            if budget.reported.load(Ordering::Relaxed) {
+                break;
+            }
+            let i = index;
+            index += 1;
+            if let Some(cached) = cache.get_member(&cache_key, i).await? {
+                debug!("{}: already done in a previous run", file.filepath_hint.to_string_lossy());
+                // streaming archive readers need each member to be read fully before the next one
+                tokio::io::copy(&mut file.inp, &mut tokio::io::sink()).await?;
+                yield Ok(AdaptInfo {
+                    inp: Box::pin(ZstdDecoder::new(Cursor::new(cached))),
+                    ..file
+                });
+                continue;
+            }
+            let info = AdaptInfo {
+                inp: Box::pin(tokio::io::empty()),
+                filepath_hint: file.filepath_hint.clone(),
+                is_real_file: file.is_real_file,
+                line_prefix: file.line_prefix.clone(),
+                archive_recursion_depth: file.archive_recursion_depth,
+                config: file.config.clone(),
+                postprocess: file.postprocess,
+            };
+            let mut cache = cache.clone();
+            let cache_key = cache_key.clone();
+            let member_budget = budget.clone();
+            let cache_config = file.config.cache.clone();
+            let start = Instant::now();
+            let out = async_read_and_write_to_cache(
+                concat_read_streams(recurse_members(one_file(file), budget.clone(), true)),
+                max_blob_len,
+                compression_level,
+                // members too long for a blob are only cached as part of the whole archive
+                None,
+                Box::new(move |(_, compressed)| {
+                    Box::pin(async move {
+                        // the output of a member cut short by the budget is not complete
+                        if member_budget.reported.load(Ordering::Relaxed) || install::any_missing() {
+                            return Ok(());
+                        }
+                        if let Some(Compressed::Blob(cached)) = compressed {
+                            if !worth_caching(&cache_config, start.elapsed(), cached.len() as u64) {
+                                return Ok(());
+                            }
+                            cache
+                                .set_member(&cache_key, i, cached)
+                                .await
+                                .context("writing member to cache")?;
+                        }
+                        Ok(())
+                    })
+                }),
+            )?;
+            yield Ok(AdaptInfo { inp: out, ..info });
+        }
+    };
+    Ok(Box::pin(s))
+}
+
 pub fn loop_adapt(
     adapter: &dyn FileAdapter,
     detection_reason: FileMatcher,
     ai: AdaptInfo,
 ) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
-    Box::pin(async move { loop_adapt_inner(adapter, detection_reason, ai).await })
+    loop_adapt_budget(adapter, detection_reason, ai, Arc::default())
 }
-pub async fn loop_adapt_inner(
+fn loop_adapt_budget(
     adapter: &dyn FileAdapter,
     detection_reason: FileMatcher,
     ai: AdaptInfo,
+    budget: Arc<Budget>,
+) -> Pin<Box<dyn Future<Output = anyhow::Result<AdaptedFilesIterBox>> + Send + '_>> {
+    Box::pin(async move { loop_adapt_inner(adapter, detection_reason, ai, budget).await })
+}
+async fn loop_adapt_inner(
+    adapter: &dyn FileAdapter,
+    detection_reason: FileMatcher,
+    ai: AdaptInfo,
+    budget: Arc<Budget>,
 ) -> anyhow::Result<AdaptedFilesIterBox> {
     let fph = ai.filepath_hint.clone();
-    let inp = adapter.adapt(ai, &detection_reason).await;
+    let reorder = ai.config.cheap_first && adapter.metadata().recurses;
+    let (ai, spool) = provide_input(adapter, ai).await?;
+    let inp = skip_missing_program(adapter, adapter.adapt(ai, &detection_reason).await);
     let inp = if adapter.metadata().name == "postprocprefix" {
         // don't add confusing error context
         inp?
@@ -211,12 +802,112 @@ pub async fn loop_adapt_inner(
             )
         })?
     };
+    let inp = keep_spool(if reorder { cheap_first(inp) } else { inp }, spool);
+    Ok(recurse_members(inp, budget, adapter.metadata().recurses))
+}
+
+/// a file whose adapter needs a program that is not installed is left out instead of failing, rga
+/// prints a hint about the program once at the end
+fn skip_missing_program(
+    adapter: &dyn FileAdapter,
+    res: Result<AdaptedFilesIterBox>,
+) -> Result<AdaptedFilesIterBox> {
+    match res {
+        Err(e) => match install::missing_program(&e) {
+            Some(missing) => {
+                debug!("{:#}", e);
+                install::note_missing(missing, &adapter.metadata().name)?;
+                Ok(Box::pin(tokio_stream::empty()))
+            }
+            None => Err(e),
+        },
+        res => res,
+    }
+}
+
+/// give the adapter its input in the form it needs (see [InputKind]). Files on disk already are
+/// seekable and have a path, members of archives are spooled to a temporary file with the same file name
+pub(crate) async fn provide_input(
+    adapter: &dyn FileAdapter,
+    ai: AdaptInfo,
+) -> Result<(AdaptInfo, Option<TempDir>)> {
+    if ai.is_real_file || adapter.metadata().input == InputKind::Stream {
+        return Ok((ai, None));
+    }
+    let dir = tempfile::tempdir().context("creating temporary directory")?;
+    // programs like ffprobe look at the extension
+    let name = ai
+        .filepath_hint
+        .file_name()
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| "input".into());
+    let path = dir.path().join(name);
+    let mut inp = ai.inp;
+    let mut file = File::create(&path)
+        .await
+        .with_context(|| format!("creating {}", path.display()))?;
+    let len = tokio::io::copy(&mut inp, &mut file).await?;
+    file.flush().await?;
+    debug!(
+        "{}: spooled {} to {} for {}",
+        ai.filepath_hint.to_string_lossy(),
+        print_bytes(len as f64),
+        path.display(),
+        adapter.metadata().name
+    );
+    let file = File::open(&path).await?;
+    Ok((
+        AdaptInfo {
+            inp: Box::pin(file),
+            filepath_hint: path,
+            is_real_file: true,
+            ..ai
+        },
+        Some(dir),
+    ))
+}
+
+/// delete the temporary file from provide_input only once the adapter output has been read
+pub(crate) fn keep_spool(inp: AdaptedFilesIterBox, spool: Option<TempDir>) -> AdaptedFilesIterBox {
+    match spool {
+        Some(dir) => Box::pin(stream! {
+            let _dir = dir;
+            for await file in inp {
+                yield file;
+            }
+        }),
+        None => inp,
+    }
+}
+
+/// run the matching adapter on each of the given files, recursively.
+/// `members` is true if the files are members of an archive, which count against the budget
+fn recurse_members(
+    inp: AdaptedFilesIterBox,
+    budget: Arc<Budget>,
+    members: bool,
+) -> AdaptedFilesIterBox {
     let s = stream! {
         for await file in inp {
-            match buf_choose_adapter(file?).await? {
+            let mut file = file?;
+            if members {
+                if let Some(reason) = budget.exhausted(&file.config) {
+                    if !budget.reported.swap(true, Ordering::Relaxed) {
+                        let s = prefixed_line(&file.line_prefix, &format!("[rga: {reason}, skipping the rest of the archive]"), file.config.prefix_column).into_bytes();
+                        yield Ok(AdaptInfo {
+                            inp: Box::pin(Cursor::new(s)),
+                            ..file
+                        });
+                    }
+                    break;
+                }
+                budget.members.fetch_add(1, Ordering::Relaxed);
+                file.inp = count_bytes(file.inp, budget.clone());
+            }
+            match buf_choose_adapter(file, &mut None).await? {
                 Ret::Recurse(ai, adapter, detection_reason, _active_adapters) => {
                     if ai.archive_recursion_depth >= ai.config.max_archive_recursion.0 {
-                        let s = format!("{}[rga: max archive recursion reached ({})]\n", ai.line_prefix, ai.archive_recursion_depth).into_bytes();
+                        let s = prefixed_line(&ai.line_prefix, &format!("[rga: max archive recursion reached ({})]", ai.archive_recursion_depth), ai.config.prefix_column).into_bytes();
                         yield Ok(AdaptInfo {
                             inp: Box::pin(Cursor::new(s)),
                             ..ai
@@ -232,7 +923,8 @@ pub async fn loop_adapt_inner(
                         ai.filepath_hint.to_string_lossy(),
                         &adapter.metadata().name
                     );
-                    for await ifile in loop_adapt(adapter.as_ref(), detection_reason, ai).await? {
+                    stats::adapter_used(&adapter.metadata().name, ai.archive_recursion_depth, false);
+                    for await ifile in loop_adapt_budget(adapter.as_ref(), detection_reason, ai, budget.clone()).await? {
                         yield ifile;
                     }
                 }
@@ -243,5 +935,53 @@ pub async fn loop_adapt_inner(
             }
         }
     };
-    Ok(Box::pin(s))
+    Box::pin(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{CacheMinTime, CacheMinTimePerMb};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn cache_admission() {
+        let cache = CacheConfig::default();
+        let ms = Duration::from_millis;
+        // a small text file
+        assert!(!worth_caching(&cache, ms(1), 200));
+        // OCR of a page
+        assert!(worth_caching(&cache, ms(2000), 2_000));
+        // decompressing a large log file
+        assert!(!worth_caching(&cache, ms(300), 50_000_000));
+        assert!(worth_caching(&cache, ms(600), 50_000_000));
+        let cache_all = CacheConfig {
+            min_time: CacheMinTime(0),
+            min_time_per_mb: CacheMinTimePerMb(0),
+            ..Default::default()
+        };
+        assert!(worth_caching(&cache_all, ms(0), 50_000_000));
+    }
+
+    #[tokio::test]
+    async fn per_file_budget() -> Result<()> {
+        let (mut w, r) = tokio::io::duplex(64);
+        w.write_all(b"some output").await?;
+        // w stays open without writing more, like a hanging adapter
+        let mut inp = limit_time(
+            Box::pin(r),
+            PathBuf::from("slow.pdf"),
+            Instant::now(),
+            // the budget is whole seconds, see --rga-per-file-budget
+            Some(Duration::from_secs(1)),
+        );
+        let mut out = String::new();
+        inp.read_to_string(&mut out).await?;
+        assert_eq!(
+            out,
+            "some output\n[rga: per-file budget of 1s used up, output truncated]\n"
+        );
+        drop(w);
+        Ok(())
+    }
 }
@@ -1,16 +1,48 @@
-use crate::{adapters::FileAdapter, preproc::ActiveAdapters};
+#[cfg(feature = "media")]
+use crate::adapters::ffmpeg;
+#[cfg(feature = "ocr")]
+use crate::adapters::ocr;
+#[cfg(feature = "parquet")]
+use crate::adapters::parquet;
+#[cfg(feature = "media")]
+use crate::adapters::whisper;
+use crate::{
+    adapters::{FileAdapter, ReadBox},
+    config::RgaConfig,
+    preproc::ActiveAdapters,
+    stats::FileStats,
+    to_io_err,
+};
 use anyhow::{Context, Result};
+use async_stream::stream;
+use bytes::Bytes;
+use log::debug;
 use path_clean::PathClean;
-use rusqlite::{named_params, OptionalExtension};
-use std::{path::Path, time::UNIX_EPOCH};
+use rusqlite::{named_params, DatabaseName, OpenFlags, OptionalExtension};
+use std::io::Read;
+use std::{
+    fs::Metadata,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tempfile::TempPath;
 use tokio_rusqlite::Connection;
+use tokio_util::io::StreamReader;
+
+/// how much of a cached output is read from the database at once
+const BLOB_CHUNK: usize = 1 << 20;
+
+/// where the outputs longer than --rga-cache-max-blob-len are cached
+pub fn files_dir(cache_path: &Path) -> PathBuf {
+    cache_path.join("files")
+}
 
 #[derive(Clone)]
 pub struct CacheKey {
     adapter: String,
     adapter_version: i32,
     active_adapters: String,
-    file_path: String,
+    pub(crate) file_path: String,
     file_mtime_unix_ms: i64,
 }
 impl CacheKey {
@@ -18,35 +50,213 @@ impl CacheKey {
         filepath_hint: &Path,
         adapter: &dyn FileAdapter,
         active_adapters: &ActiveAdapters,
+        config: &RgaConfig,
     ) -> Result<CacheKey> {
-        let meta = std::fs::metadata(filepath_hint)
-            .with_context(|| format!("reading metadata for {}", filepath_hint.to_string_lossy()))?;
-        let modified = meta.modified().expect("weird OS that can't into mtime");
-        let file_mtime_unix_ms = modified.duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let (file_path, file_mtime_unix_ms) = file_identity(filepath_hint)?;
+        // the OCR backend and options change the text of images, the whisper ones the transcripts, and
+        // ffmpeg recognizes image subtitles with them. parquet can skip the columns that aren't text
+        #[cfg(feature = "ocr")]
+        let ocr_options = ocr::options_key(config);
+        #[cfg(not(feature = "ocr"))]
+        let ocr_options: Option<String> = None;
+        #[cfg(feature = "media")]
+        let whisper_options = whisper::options_key(config);
+        #[cfg(not(feature = "media"))]
+        let whisper_options: Option<String> = None;
+        #[cfg(feature = "media")]
+        let ffmpeg_options = ffmpeg::options_key(config);
+        #[cfg(not(feature = "media"))]
+        let ffmpeg_options: Option<String> = None;
+        #[cfg(feature = "parquet")]
+        let parquet_options = parquet::options_key(config);
+        #[cfg(not(feature = "parquet"))]
+        let parquet_options: Option<String> = None;
+        let adapter_options = [
+            ("ocr", ocr_options),
+            ("whisper", whisper_options),
+            ("ffmpeg", ffmpeg_options),
+            ("parquet", parquet_options),
+        ];
         let active_adapters = if adapter.metadata().recurses {
-            serde_json::to_string(
-                &active_adapters
-                    .iter()
-                    .map(|a| format!("{}.v{}", a.metadata().name, a.metadata().version))
-                    .collect::<Vec<_>>(),
-            )?
+            let mut active_adapters = active_adapters
+                .iter()
+                .map(|a| format!("{}.v{}", a.metadata().name, a.metadata().version))
+                .collect::<Vec<_>>();
+            if config.cheap_first {
+                // the members are output in a different order
+                active_adapters.push("cheap-first".to_string());
+            }
+            // the limits decide which members are unpacked, and which get the marker instead. Each
+            // member is cached under this key too, so members of a run with other limits aren't mixed in
+            active_adapters.push(format!(
+                "limits:{}/{}/{}",
+                config.max_archive_recursion.0,
+                config.max_archive_members.0,
+                config.max_extracted_bytes.0
+            ));
+            for (name, options) in &adapter_options {
+                let prefix = format!("{name}.v");
+                let active = active_adapters.iter().any(|a| a.starts_with(&prefix));
+                if let Some(options) = options.clone().filter(|_| active) {
+                    active_adapters.push(options);
+                }
+            }
+            serde_json::to_string(&active_adapters)?
+        } else if let Some((_, Some(options))) = adapter_options
+            .iter()
+            .find(|(name, _)| *name == adapter.metadata().name)
+        {
+            serde_json::to_string(options)?
         } else {
             "null".to_string()
         };
+        let active_adapters = if config.prefix_column {
+            // the line prefixes are in a different place in the output
+            format!("{active_adapters}+prefix-column")
+        } else {
+            active_adapters
+        };
+        // the output of custom adapters (and of archives with files they convert) is keyed by their
+        // definitions, so an edited definition is used in the next search, e.g. the next reload of
+        // rga-fzf, without changing its version
+        let custom_adapters = config.custom_adapters.as_deref().unwrap_or_default();
+        let is_custom = custom_adapters
+            .iter()
+            .any(|c| c.name == adapter.metadata().name);
+        let active_adapters =
+            if !custom_adapters.is_empty() && (is_custom || adapter.metadata().recurses) {
+                let definitions = serde_json::to_vec(custom_adapters)?;
+                format!(
+                    "{active_adapters}+custom:{:016x}",
+                    fnv1a(FNV_OFFSET, &definitions)
+                )
+            } else {
+                active_adapters
+            };
         Ok(CacheKey {
             adapter: adapter.metadata().name.clone(),
             adapter_version: adapter.metadata().version,
-            file_path: filepath_hint.clean().to_string_lossy().to_string(),
+            file_path,
             file_mtime_unix_ms,
             active_adapters,
         })
     }
 }
 
+/// the path and version of a file on disk, which identify its entries in the cache
+pub fn file_identity(path: &Path) -> Result<(String, i64)> {
+    let meta = std::fs::metadata(path)
+        .with_context(|| format!("reading metadata for {}", path.to_string_lossy()))?;
+    let version = file_version(path, &meta)?;
+    Ok((path.clean().to_string_lossy().to_string(), version))
+}
+
+/// 64 bit FNV-1a, a hash that stays the same across Rust versions (unlike DefaultHasher)
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for b in bytes {
+        hash ^= u64::from(*b);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+const FNV_OFFSET: u64 = 0xcbf29ce484222325;
+
+/// the value used as the mtime in the cache key. Some file systems (FUSE mounts, MTP devices) have
+/// no mtime or always report 0, for those the ctime and size are used, or a hash of the content
+fn file_version(path: &Path, meta: &Metadata) -> Result<i64> {
+    match meta.modified().map(|m| m.duration_since(UNIX_EPOCH)) {
+        Ok(Ok(mtime)) if !mtime.is_zero() => return Ok(mtime.as_millis() as i64),
+        Ok(_) => debug!("{}: mtime not set", path.to_string_lossy()),
+        Err(e) => debug!("{}: no mtime: {}", path.to_string_lossy(), e),
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if meta.ctime() > 0 {
+            debug!(
+                "{}: using ctime and size for the cache key",
+                path.to_string_lossy()
+            );
+            let mut hash = fnv1a(FNV_OFFSET, &meta.ctime().to_le_bytes());
+            hash = fnv1a(hash, &meta.ctime_nsec().to_le_bytes());
+            hash = fnv1a(hash, &meta.len().to_le_bytes());
+            return Ok(hash as i64);
+        }
+    }
+    debug!(
+        "{}: hashing the content for the cache key",
+        path.to_string_lossy()
+    );
+    content_hash(path)
+}
+
+/// a hash of the content of a file, which stays the same when it is moved, copied or touched
+pub fn content_hash(path: &Path) -> Result<i64> {
+    let mut file = std::fs::File::open(path)
+        .with_context(|| format!("opening {} to hash it", path.to_string_lossy()))?;
+    let mut hash = FNV_OFFSET;
+    let mut buf = vec![0u8; 1 << 16];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hash = fnv1a(hash, &buf[..n]);
+    }
+    Ok(hash as i64)
+}
+
 #[async_trait::async_trait]
 pub trait PreprocCache {
-    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>>;
+    /// the compressed output. It is read from the database in chunks while it is consumed, so rg
+    /// gets the start of a large output right away and it never has to fit in memory
+    async fn get(&self, key: &CacheKey) -> Result<Option<ReadBox>>;
     async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()>;
+    /// cache an output too long for the database. The file must be in [files_dir], it is kept
+    /// until the output is replaced
+    async fn set_file(&mut self, key: &CacheKey, file: TempPath) -> Result<()>;
+    /// output of a single member of an archive, stored while the archive is processed so an
+    /// interrupted run can resume after the last completed member
+    async fn get_member(&self, key: &CacheKey, index: i64) -> Result<Option<Vec<u8>>>;
+    async fn set_member(&mut self, key: &CacheKey, index: i64, value: Vec<u8>) -> Result<()>;
+    /// forget the member outputs once the output of the whole archive is cached
+    async fn clear_members(&mut self, key: &CacheKey) -> Result<()>;
+    /// remember that the adapter failed on a file, for --rga-retry-failed. Returns how often it
+    /// failed in a row
+    async fn set_failure(&mut self, file_path: &str, adapter: &str, error: &str) -> Result<i64>;
+    /// the mimetype sniffed from the file the last time, for --rga-accurate, if the file didn't change
+    async fn get_mimetype(
+        &self,
+        file_path: &str,
+        file_mtime_unix_ms: i64,
+    ) -> Result<Option<String>>;
+    async fn set_mimetype(
+        &mut self,
+        file_path: &str,
+        file_mtime_unix_ms: i64,
+        mimetype: &str,
+    ) -> Result<()>;
+    /// forget the failures of a file and take it out of quarantine
+    async fn clear_failure(&mut self, file_path: &str) -> Result<()>;
+    /// the files whose adapter failed the last time: path, adapter and error
+    async fn failures(&self) -> Result<Vec<(String, String, String)>>;
+    /// whether the adapter failed on a file the last time
+    async fn failed(&self, file_path: &str) -> Result<bool>;
+    /// skip a file in future searches, for --rga-quarantine-after and --rga-quarantine-add
+    async fn quarantine(&mut self, file_path: &str, reason: &str) -> Result<()>;
+    /// why a file is quarantined, if it is
+    async fn quarantined(&self, file_path: &str) -> Result<Option<String>>;
+    /// the quarantined files and why
+    async fn quarantine_list(&self) -> Result<Vec<(String, String)>>;
+    /// a transcript of a recording by its [content_hash], so it is not transcribed again when the
+    /// file is moved, copied or touched. The options are what else the transcript depends on
+    async fn get_transcript(&self, content_hash: i64, options: &str) -> Result<Option<String>>;
+    async fn set_transcript(
+        &mut self,
+        content_hash: i64,
+        options: &str,
+        transcript: &str,
+    ) -> Result<()>;
 }
 
 async fn connect_pragmas(db: &Connection) -> Result<()> {
@@ -74,6 +284,58 @@ async fn connect_pragmas(db: &Connection) -> Result<()> {
     ) strict;
     
     create unique index if not exists preproc_cache_idx on preproc_cache (adapter, adapter_version, file_path, active_adapters);
+
+    create table if not exists preproc_cache_files (
+        adapter text not null,
+        adapter_version integer not null,
+        created_unix_ms integer not null default (unixepoch() * 1000),
+        active_adapters text not null,
+        file_path text not null,
+        file_mtime_unix_ms integer not null,
+        blob_file text not null -- name of the file in the files dir
+    ) strict;
+
+    create unique index if not exists preproc_cache_files_idx on preproc_cache_files (adapter, adapter_version, file_path, active_adapters);
+
+    create table if not exists preproc_cache_members (
+        adapter text not null,
+        adapter_version integer not null,
+        active_adapters text not null,
+        file_path text not null,
+        file_mtime_unix_ms integer not null,
+        member_index integer not null,
+        text_content_zstd blob not null
+    ) strict;
+
+    create unique index if not exists preproc_cache_members_idx on preproc_cache_members (adapter, adapter_version, file_path, active_adapters, member_index);
+
+    create table if not exists preproc_mimetypes (
+        file_path text primary key,
+        file_mtime_unix_ms integer not null,
+        mimetype text not null
+    ) strict;
+
+    create table if not exists preproc_failures (
+        file_path text primary key,
+        adapter text not null,
+        error text not null,
+        count integer not null default 1,
+        failed_unix_ms integer not null default (unixepoch() * 1000)
+    ) strict;
+
+    create table if not exists preproc_quarantine (
+        file_path text primary key,
+        reason text not null,
+        added_unix_ms integer not null default (unixepoch() * 1000)
+    ) strict;
+
+    create table if not exists preproc_transcripts (
+        content_hash integer not null,
+        options text not null,
+        created_unix_ms integer not null default (unixepoch() * 1000),
+        transcript text not null,
+        primary key (content_hash, options)
+    ) strict;
     ",
         )
     })
@@ -100,27 +362,110 @@ async fn create_pragmas(db: &Connection) -> Result<()> {
     .await?;
     Ok(())
 }
-struct SqliteCache {
+/// the cache database. Cloning it shares the connection
+#[derive(Clone)]
+pub struct SqliteCache {
     db: Connection,
+    dir: PathBuf,
 }
 impl SqliteCache {
     async fn new(path: &Path) -> Result<SqliteCache> {
         let db = Connection::open(path.join("cache.sqlite3")).await?;
         connect_pragmas(&db).await?;
 
-        Ok(SqliteCache { db })
+        Ok(SqliteCache {
+            db,
+            dir: files_dir(path),
+        })
+    }
+
+    /// forget the output file cached for the key and delete it
+    async fn remove_file(&self, key: &CacheKey) -> Result<()> {
+        let key = key.clone();
+        let old: Option<String> = self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "delete from preproc_cache_files where
+                            adapter = :adapter
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path
+                    returning blob_file",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path
+                    },
+                    |r| r.get(0),
+                )
+                .optional()
+            })
+            .await?;
+        if let Some(old) = old {
+            match tokio::fs::remove_file(self.dir.join(&old)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    debug!("could not delete cache file {}: {}", old, e)
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// the output cached in a file, if there is one
+    async fn get_file(&self, key: CacheKey) -> Result<Option<ReadBox>> {
+        let found: Option<String> = self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select blob_file from preproc_cache_files where
+                            adapter = :adapter
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path
+                        and file_mtime_unix_ms = :file_mtime_unix_ms
+                ",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms
+                    },
+                    |r| r.get(0),
+                )
+                .optional()
+            })
+            .await
+            .context("reading from cache")?;
+        let found = match found {
+            Some(found) => found,
+            None => return Ok(None),
+        };
+        match tokio::fs::File::open(self.dir.join(&found)).await {
+            Ok(file) => Ok(Some(Box::pin(file))),
+            // deleted with the files directory, recompute it
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!("cache file {} is gone", found);
+                Ok(None)
+            }
+            Err(e) => Err(e).with_context(|| format!("opening cache file {}", found)),
+        }
     }
 }
 
 #[async_trait::async_trait]
 impl PreprocCache for SqliteCache {
-    async fn get(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+    async fn get(&self, key: &CacheKey) -> Result<Option<ReadBox>> {
+        let file_key = key.clone();
         let key = (*key).clone(); // todo: without cloning
-        Ok(self
+        let found = self
             .db
             .call(move |db| {
                 db.query_row(
-                    "select text_content_zstd from preproc_cache where
+                    "select rowid, length(text_content_zstd) from preproc_cache where
                             adapter = :adapter
                         and adapter_version = :adapter_version
                         and active_adapters = :active_adapters
@@ -134,15 +479,46 @@ impl PreprocCache for SqliteCache {
                         ":file_path": &key.file_path,
                         ":file_mtime_unix_ms": &key.file_mtime_unix_ms
                     },
-                    |r| r.get::<_, Vec<u8>>(0),
+                    |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)? as usize)),
                 )
                 .optional()
             })
             .await
-            .context("reading from cache")?)
+            .context("reading from cache")?;
+        let (rowid, len) = match found {
+            Some(found) => found,
+            None => return self.get_file(file_key).await,
+        };
+        let db = self.db.clone();
+        let s = stream! {
+            let mut offset = 0;
+            while offset < len {
+                let n = (len - offset).min(BLOB_CHUNK);
+                let chunk = db
+                    .call(move |db| {
+                        let blob = db.blob_open(
+                            DatabaseName::Main,
+                            "preproc_cache",
+                            "text_content_zstd",
+                            rowid,
+                            true,
+                        )?;
+                        let mut buf = vec![0u8; n];
+                        blob.read_at_exact(&mut buf, offset)?;
+                        Ok::<_, rusqlite::Error>(buf)
+                    })
+                    .await
+                    .context("reading from cache")
+                    .map_err(to_io_err)?;
+                offset += n;
+                yield std::io::Result::Ok(Bytes::from(chunk));
+            }
+        };
+        Ok(Some(Box::pin(StreamReader::new(s))))
     }
 
     async fn set(&mut self, key: &CacheKey, value: Vec<u8>) -> Result<()> {
+        self.remove_file(key).await?;
         let key = (*key).clone(); // todo: without cloning
         Ok(self
             .db
@@ -166,13 +542,408 @@ impl PreprocCache for SqliteCache {
             })
             .await?)
     }
+
+    async fn set_file(&mut self, key: &CacheKey, file: TempPath) -> Result<()> {
+        self.remove_file(key).await?;
+        let name = file
+            .file_name()
+            .context("cache file without name")?
+            .to_string_lossy()
+            .to_string();
+        // from now on it is deleted with its row
+        file.keep()?;
+        let key = (*key).clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "delete from preproc_cache where
+                            adapter = :adapter
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path
+                    },
+                )?;
+                db.execute(
+                    "insert into preproc_cache_files (adapter, adapter_version, active_adapters, file_path, file_mtime_unix_ms, blob_file) values
+                        (:adapter, :adapter_version, :active_adapters, :file_path, :file_mtime_unix_ms, :blob_file)",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                        ":blob_file": &name
+                    },
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn get_member(&self, key: &CacheKey, index: i64) -> Result<Option<Vec<u8>>> {
+        let key = (*key).clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select text_content_zstd from preproc_cache_members where
+                            adapter = :adapter
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path
+                        and file_mtime_unix_ms = :file_mtime_unix_ms
+                        and member_index = :member_index
+                ",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                        ":member_index": index
+                    },
+                    |r| r.get::<_, Vec<u8>>(0),
+                )
+                .optional()
+            })
+            .await
+            .context("reading member from cache")?)
+    }
+
+    async fn set_member(&mut self, key: &CacheKey, index: i64, value: Vec<u8>) -> Result<()> {
+        let key = (*key).clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_cache_members (adapter, adapter_version, active_adapters, file_path, file_mtime_unix_ms, member_index, text_content_zstd) values
+                        (:adapter, :adapter_version, :active_adapters, :file_path, :file_mtime_unix_ms, :member_index, :text_content_zstd)
+                    on conflict (adapter, adapter_version, active_adapters, file_path, member_index) do update set
+                        file_mtime_unix_ms = :file_mtime_unix_ms,
+                        text_content_zstd = :text_content_zstd",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path,
+                        ":file_mtime_unix_ms": &key.file_mtime_unix_ms,
+                        ":member_index": index,
+                        ":text_content_zstd": value
+                    }
+                ).map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn clear_members(&mut self, key: &CacheKey) -> Result<()> {
+        let key = (*key).clone();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "delete from preproc_cache_members where
+                            adapter = :adapter
+                        and adapter_version = :adapter_version
+                        and active_adapters = :active_adapters
+                        and file_path = :file_path",
+                    named_params! {
+                        ":adapter": &key.adapter,
+                        ":adapter_version": &key.adapter_version,
+                        ":active_adapters": &key.active_adapters,
+                        ":file_path": &key.file_path
+                    },
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn set_failure(&mut self, file_path: &str, adapter: &str, error: &str) -> Result<i64> {
+        let (file_path, adapter, error) =
+            (file_path.to_owned(), adapter.to_owned(), error.to_owned());
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "insert into preproc_failures (file_path, adapter, error) values (:file_path, :adapter, :error)
+                    on conflict (file_path) do update set
+                        adapter = :adapter,
+                        error = :error,
+                        count = count + 1,
+                        failed_unix_ms = unixepoch() * 1000
+                    returning count",
+                    named_params! {
+                        ":file_path": &file_path,
+                        ":adapter": &adapter,
+                        ":error": &error
+                    },
+                    |r| r.get(0),
+                )
+            })
+            .await?)
+    }
+
+    async fn get_mimetype(
+        &self,
+        file_path: &str,
+        file_mtime_unix_ms: i64,
+    ) -> Result<Option<String>> {
+        let file_path = file_path.to_owned();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select mimetype from preproc_mimetypes where file_path = :file_path and file_mtime_unix_ms = :file_mtime_unix_ms",
+                    named_params! {
+                        ":file_path": &file_path,
+                        ":file_mtime_unix_ms": file_mtime_unix_ms
+                    },
+                    |r| r.get(0),
+                )
+                .optional()
+            })
+            .await
+            .context("reading mimetype from cache")?)
+    }
+
+    async fn set_mimetype(
+        &mut self,
+        file_path: &str,
+        file_mtime_unix_ms: i64,
+        mimetype: &str,
+    ) -> Result<()> {
+        let (file_path, mimetype) = (file_path.to_owned(), mimetype.to_owned());
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_mimetypes (file_path, file_mtime_unix_ms, mimetype) values (:file_path, :file_mtime_unix_ms, :mimetype)
+                    on conflict (file_path) do update set
+                        file_mtime_unix_ms = :file_mtime_unix_ms,
+                        mimetype = :mimetype",
+                    named_params! {
+                        ":file_path": &file_path,
+                        ":file_mtime_unix_ms": file_mtime_unix_ms,
+                        ":mimetype": &mimetype
+                    },
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn clear_failure(&mut self, file_path: &str) -> Result<()> {
+        let file_path = file_path.to_owned();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "delete from preproc_failures where file_path = :file_path",
+                    named_params! {":file_path": &file_path},
+                )?;
+                db.execute(
+                    "delete from preproc_quarantine where file_path = :file_path",
+                    named_params! {":file_path": &file_path},
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn failures(&self) -> Result<Vec<(String, String, String)>> {
+        Ok(self
+            .db
+            .call(|db| {
+                let mut stmt = db.prepare(
+                    "select file_path, adapter, error from preproc_failures order by file_path",
+                )?;
+                let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await
+            .context("reading failures from cache")?)
+    }
+
+    async fn failed(&self, file_path: &str) -> Result<bool> {
+        let file_path = file_path.to_owned();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select exists(select 1 from preproc_failures where file_path = :file_path)",
+                    named_params! {":file_path": &file_path},
+                    |r| r.get(0),
+                )
+            })
+            .await
+            .context("reading failures from cache")?)
+    }
+
+    async fn quarantine(&mut self, file_path: &str, reason: &str) -> Result<()> {
+        let (file_path, reason) = (file_path.to_owned(), reason.to_owned());
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_quarantine (file_path, reason) values (:file_path, :reason)
+                    on conflict (file_path) do update set reason = :reason",
+                    named_params! {
+                        ":file_path": &file_path,
+                        ":reason": &reason
+                    },
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+
+    async fn quarantined(&self, file_path: &str) -> Result<Option<String>> {
+        let file_path = file_path.to_owned();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select reason from preproc_quarantine where file_path = :file_path",
+                    named_params! {":file_path": &file_path},
+                    |r| r.get(0),
+                )
+                .optional()
+            })
+            .await
+            .context("reading quarantine from cache")?)
+    }
+
+    async fn quarantine_list(&self) -> Result<Vec<(String, String)>> {
+        Ok(self
+            .db
+            .call(|db| {
+                let mut stmt = db.prepare(
+                    "select file_path, reason from preproc_quarantine order by file_path",
+                )?;
+                let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+                rows.collect::<rusqlite::Result<Vec<_>>>()
+            })
+            .await
+            .context("reading quarantine from cache")?)
+    }
+
+    async fn get_transcript(&self, content_hash: i64, options: &str) -> Result<Option<String>> {
+        let options = options.to_owned();
+        Ok(self
+            .db
+            .call(move |db| {
+                db.query_row(
+                    "select transcript from preproc_transcripts where content_hash = :content_hash and options = :options",
+                    named_params! {
+                        ":content_hash": content_hash,
+                        ":options": &options
+                    },
+                    |r| r.get(0),
+                )
+                .optional()
+            })
+            .await
+            .context("reading transcript from cache")?)
+    }
+
+    async fn set_transcript(
+        &mut self,
+        content_hash: i64,
+        options: &str,
+        transcript: &str,
+    ) -> Result<()> {
+        let (options, transcript) = (options.to_owned(), transcript.to_owned());
+        Ok(self
+            .db
+            .call(move |db| {
+                db.execute(
+                    "insert into preproc_transcripts (content_hash, options, transcript) values (:content_hash, :options, :transcript)
+                    on conflict (content_hash, options) do update set
+                        transcript = :transcript,
+                        created_unix_ms = unixepoch() * 1000",
+                    named_params! {
+                        ":content_hash": content_hash,
+                        ":options": &options,
+                        ":transcript": &transcript
+                    },
+                )
+                .map(|_| ())
+            })
+            .await?)
+    }
+}
+
+/// remember the files whose adapter failed, and quarantine them once they failed
+/// --rga-quarantine-after times in a row. They are forgotten once an adapter succeeds on them.
+/// Files read from the cache didn't fail
+pub async fn record_outcome(config: &RgaConfig, stats: &FileStats) -> Result<()> {
+    if stats.cache_hit || config.cache.disabled {
+        return Ok(());
+    }
+    let path = Path::new(&stats.path).clean().to_string_lossy().to_string();
+    match (&stats.error, &stats.adapter) {
+        (Some(error), adapter) => {
+            let mut cache = open_cache_db(Path::new(&config.cache.path.0)).await?;
+            let count = cache
+                .set_failure(&path, adapter.as_deref().unwrap_or_default(), error)
+                .await?;
+            match config.quarantine_after {
+                Some(after) if count >= after as i64 => {
+                    let reason = format!("failed {count} times, last: {error}");
+                    cache.quarantine(&path, &reason).await
+                }
+                _ => Ok(()),
+            }
+        }
+        (None, Some(_)) => {
+            // most files never failed, so this only reads, and parallel rga-preproc processes don't
+            // wait for each other to write
+            let failed = match open_cache_db_readonly(Path::new(&config.cache.path.0)).await? {
+                Some(cache) => cache.failed(&path).await?,
+                None => false,
+            };
+            if !failed {
+                return Ok(());
+            }
+            let mut cache = open_cache_db(Path::new(&config.cache.path.0)).await?;
+            cache.clear_failure(&path).await
+        }
+        // passed through without an adapter
+        (None, None) => Ok(()),
+    }
 }
+
 /// opens a default cache
-pub async fn open_cache_db(path: &Path) -> Result<impl PreprocCache> {
+pub async fn open_cache_db(path: &Path) -> Result<SqliteCache> {
     std::fs::create_dir_all(path)?;
     SqliteCache::new(path).await
 }
 
+/// opens the cache for reading, None if there is no cache yet. Unlike open_cache_db, this doesn't
+/// create the cache
+pub async fn open_cache_db_readonly(path: &Path) -> Result<Option<SqliteCache>> {
+    let file = path.join("cache.sqlite3");
+    if !file.exists() {
+        return Ok(None);
+    }
+    let db = Connection::open_with_flags(
+        &file,
+        OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+    )
+    .await
+    .with_context(|| format!("opening {}", file.display()))?;
+    Ok(Some(SqliteCache {
+        db,
+        dir: files_dir(path),
+    }))
+}
+
 #[cfg(test)]
 mod test {
 
@@ -185,4 +956,222 @@ mod test {
         // db.set();
         Ok(())
     }
+
+    #[tokio::test]
+    async fn read_in_chunks() -> anyhow::Result<()> {
+        use tokio::io::AsyncReadExt;
+        let path = tempfile::tempdir()?;
+        let file = path.path().join("big.pdf");
+        std::fs::write(&file, "")?;
+        let adapter = crate::adapters::zip::ZipAdapter::new();
+        let key = CacheKey::new(&file, &adapter, &Vec::new(), &RgaConfig::default())?;
+        let mut db = open_cache_db(&path.path().join("cache")).await?;
+        assert!(db.get(&key).await?.is_none());
+        let value = (0..BLOB_CHUNK * 5 / 2)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        db.set(&key, value.clone()).await?;
+        let mut read = Vec::new();
+        db.get(&key)
+            .await?
+            .expect("just set")
+            .read_to_end(&mut read)
+            .await?;
+        assert!(read == value);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn files() -> anyhow::Result<()> {
+        use tokio::io::AsyncReadExt;
+        let path = tempfile::tempdir()?;
+        let file = path.path().join("big.pdf");
+        std::fs::write(&file, "")?;
+        let adapter = crate::adapters::zip::ZipAdapter::new();
+        let key = CacheKey::new(&file, &adapter, &Vec::new(), &RgaConfig::default())?;
+        let cache_path = path.path().join("cache");
+        let mut db = open_cache_db(&cache_path).await?;
+        std::fs::create_dir_all(files_dir(&cache_path))?;
+        let spilled = |content: &[u8]| -> anyhow::Result<TempPath> {
+            let mut f = tempfile::NamedTempFile::new_in(files_dir(&cache_path))?;
+            std::io::Write::write_all(&mut f, content)?;
+            Ok(f.into_temp_path())
+        };
+        db.set_file(&key, spilled(b"first")?).await?;
+        db.set_file(&key, spilled(b"second")?).await?;
+        let mut read = Vec::new();
+        db.get(&key)
+            .await?
+            .expect("just set")
+            .read_to_end(&mut read)
+            .await?;
+        assert_eq!(read, b"second");
+        // the replaced file is deleted
+        assert_eq!(std::fs::read_dir(files_dir(&cache_path))?.count(), 1);
+        db.set(&key, b"short".to_vec()).await?;
+        assert_eq!(std::fs::read_dir(files_dir(&cache_path))?.count(), 0);
+        db.set_file(&key, spilled(b"third")?).await?;
+        std::fs::remove_dir_all(files_dir(&cache_path))?;
+        assert!(db.get(&key).await?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn custom_adapter_key() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let file = path.path().join("a.foo");
+        std::fs::write(&file, "")?;
+        let definition = crate::adapters::custom::CustomAdapterConfig {
+            name: "foo".to_owned(),
+            version: 1,
+            extensions: vec!["foo".to_owned()],
+            binary: "cat".to_owned(),
+            ..Default::default()
+        };
+        let key = |definition: &crate::adapters::custom::CustomAdapterConfig| {
+            let config = RgaConfig {
+                custom_adapters: Some(vec![definition.clone()]),
+                ..Default::default()
+            };
+            Ok::<_, anyhow::Error>(
+                CacheKey::new(&file, &definition.to_adapter(), &Vec::new(), &config)?
+                    .active_adapters,
+            )
+        };
+        let before = key(&definition)?;
+        assert_eq!(key(&definition)?, before);
+        let edited = crate::adapters::custom::CustomAdapterConfig {
+            args: vec!["-v".to_owned()],
+            ..definition
+        };
+        // same version, but the output of the old args isn't used
+        assert_ne!(key(&edited)?, before);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_members() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let file = path.path().join("archive.zip");
+        std::fs::write(&file, "")?;
+        let adapter = crate::adapters::zip::ZipAdapter::new();
+        let key = CacheKey::new(&file, &adapter, &Vec::new(), &RgaConfig::default())?;
+        let mut db = open_cache_db(&path.path().join("cache")).await?;
+        db.set_member(&key, 0, b"first".to_vec()).await?;
+        db.set_member(&key, 1, b"second".to_vec()).await?;
+        assert_eq!(db.get_member(&key, 1).await?, Some(b"second".to_vec()));
+        assert_eq!(db.get_member(&key, 2).await?, None);
+        // not used with other limits
+        let config = RgaConfig {
+            max_archive_recursion: crate::config::MaxArchiveRecursion(1),
+            ..Default::default()
+        };
+        let other_key = CacheKey::new(&file, &adapter, &Vec::new(), &config)?;
+        assert_eq!(db.get_member(&other_key, 1).await?, None);
+        db.clear_members(&key).await?;
+        assert_eq!(db.get_member(&key, 0).await?, None);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn failures() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut db = open_cache_db(path.path()).await?;
+        db.set_failure("/a.pdf", "pdf", "pdftotext failed").await?;
+        db.set_failure("/b.pdf", "pdf", "pdftotext failed").await?;
+        db.set_failure("/a.pdf", "tika", "connection refused")
+            .await?;
+        db.clear_failure("/b.pdf").await?;
+        assert!(db.failed("/a.pdf").await?);
+        assert!(!db.failed("/b.pdf").await?);
+        assert_eq!(
+            db.failures().await?,
+            vec![(
+                "/a.pdf".to_owned(),
+                "tika".to_owned(),
+                "connection refused".to_owned()
+            )]
+        );
+        assert_eq!(db.set_failure("/a.pdf", "tika", "timed out").await?, 3);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn mimetypes() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut db = open_cache_db(path.path()).await?;
+        db.set_mimetype("/a.bin", 1, "application/pdf").await?;
+        assert_eq!(
+            db.get_mimetype("/a.bin", 1).await?.as_deref(),
+            Some("application/pdf")
+        );
+        // changed since
+        assert_eq!(db.get_mimetype("/a.bin", 2).await?, None);
+        db.set_mimetype("/a.bin", 2, "application/zip").await?;
+        assert_eq!(
+            db.get_mimetype("/a.bin", 2).await?.as_deref(),
+            Some("application/zip")
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn quarantine() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let mut db = open_cache_db(path.path()).await?;
+        db.quarantine("/hangs.pdf", "added by hand").await?;
+        assert_eq!(
+            db.quarantined("/hangs.pdf").await?.as_deref(),
+            Some("added by hand")
+        );
+        assert_eq!(db.quarantined("/ok.pdf").await?, None);
+        assert_eq!(db.quarantine_list().await?.len(), 1);
+        db.clear_failure("/hangs.pdf").await?;
+        assert_eq!(db.quarantined("/hangs.pdf").await?, None);
+
+        let readonly = open_cache_db_readonly(path.path()).await?.expect("exists");
+        assert_eq!(readonly.quarantine_list().await?.len(), 0);
+        let missing = path.path().join("missing");
+        assert!(open_cache_db_readonly(&missing).await?.is_none());
+        assert!(!missing.exists());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn transcripts() -> anyhow::Result<()> {
+        let path = tempfile::tempdir()?;
+        let a = path.path().join("a.mp3");
+        std::fs::write(&a, "one")?;
+        let b = path.path().join("b.mp3");
+        std::fs::copy(&a, &b)?;
+        assert_eq!(content_hash(&a)?, content_hash(&b)?);
+        let mut db = open_cache_db(&path.path().join("cache")).await?;
+        db.set_transcript(content_hash(&a)?, "whisper.cpp", "[00:00:00] one")
+            .await?;
+        assert_eq!(
+            db.get_transcript(content_hash(&b)?, "whisper.cpp")
+                .await?
+                .as_deref(),
+            Some("[00:00:00] one")
+        );
+        assert_eq!(db.get_transcript(content_hash(&b)?, "whisper").await?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn no_mtime() -> anyhow::Result<()> {
+        let dir = tempfile::tempdir()?;
+        let file = dir.path().join("a.txt");
+        std::fs::write(&file, "one")?;
+        // like files on MTP devices
+        std::fs::File::options()
+            .write(true)
+            .open(&file)?
+            .set_modified(UNIX_EPOCH)?;
+        let meta = std::fs::metadata(&file)?;
+        let version = file_version(&file, &meta)?;
+        assert_ne!(version, 0);
+        assert_eq!(file_version(&file, &meta)?, version);
+        Ok(())
+    }
 }
@@ -0,0 +1,227 @@
+//! --rga-queries: search for a list of patterns at once, e.g. the keywords of an analyst. rg runs
+//! once with all of them, so every file is only read and adapted once, and rga reads the matching
+//! lines from rg's JSON output and tells which of the queries match each of them.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::Value;
+use std::ffi::OsString;
+use std::path::Path;
+
+/// the queries of a file, one per line. Empty lines are left out since they would match every line
+pub fn read_queries(path: &Path) -> Result<Vec<String>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("reading queries from {}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(|l| l.trim_end_matches('\r'))
+        .filter(|l| !l.is_empty())
+        .map(String::from)
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Case {
+    Smart,
+    Sensitive,
+    Insensitive,
+}
+
+/// true if smart case makes `query` case sensitive: it contains an upper case letter that is not
+/// part of an escape like `\W`
+fn has_uppercase(query: &str, fixed_strings: bool) -> bool {
+    let mut escaped = false;
+    for c in query.chars() {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' && !fixed_strings {
+            escaped = true;
+        } else if c.is_uppercase() {
+            return true;
+        }
+    }
+    false
+}
+
+struct Query {
+    query: String,
+    /// the pattern given to rg
+    pattern: String,
+    /// tells if a line rg found matches this query
+    regex: Regex,
+    /// the number of matching lines
+    hits: u64,
+}
+
+pub struct Queries {
+    queries: Vec<Query>,
+}
+
+impl Queries {
+    /// Builds the patterns of the queries. The rg options that change how patterns are read (case and
+    /// fixed strings) are taken out of `rg_args` and applied to each query separately, since rg would
+    /// apply smart case to all of them together
+    pub fn new(queries: Vec<String>, rg_args: &mut Vec<OsString>) -> Result<Queries> {
+        let mut case = Case::Smart;
+        let mut fixed_strings = false;
+        let mut word = false;
+        let mut line = false;
+        rg_args.retain(|arg| {
+            match arg.to_str().unwrap_or_default() {
+                "-S" | "--smart-case" => case = Case::Smart,
+                "-s" | "--case-sensitive" => case = Case::Sensitive,
+                "-i" | "--ignore-case" => case = Case::Insensitive,
+                "-F" | "--fixed-strings" => fixed_strings = true,
+                "--no-fixed-strings" => fixed_strings = false,
+                // rg applies these to every pattern itself
+                "-w" | "--word-regexp" => {
+                    word = true;
+                    return true;
+                }
+                "-x" | "--line-regexp" => {
+                    line = true;
+                    return true;
+                }
+                _ => return true,
+            }
+            false
+        });
+        let queries = queries
+            .into_iter()
+            .map(|query| {
+                let insensitive = match case {
+                    Case::Smart => !has_uppercase(&query, fixed_strings),
+                    Case::Sensitive => false,
+                    Case::Insensitive => true,
+                };
+                let pattern = if fixed_strings {
+                    regex::escape(&query)
+                } else {
+                    query.clone()
+                };
+                let pattern = if insensitive {
+                    format!("(?i:{pattern})")
+                } else {
+                    format!("(?:{pattern})")
+                };
+                let full = if line {
+                    format!("^{pattern}$")
+                } else if word {
+                    format!(r"(?:^|\W){pattern}(?:\W|$)")
+                } else {
+                    pattern.clone()
+                };
+                let regex =
+                    Regex::new(&full).with_context(|| format!("invalid query {query:?}"))?;
+                Ok(Query {
+                    query,
+                    pattern,
+                    regex,
+                    hits: 0,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Queries { queries })
+    }
+
+    /// the patterns for rg, one per line (for `rg -f`)
+    pub fn patterns(&self) -> String {
+        self.queries
+            .iter()
+            .map(|q| format!("{}\n", q.pattern))
+            .collect()
+    }
+
+    /// reads a message of `rg --json` and returns the output for it: a line `query<TAB>path<TAB>line`
+    /// for each query that matches a matching line
+    pub fn handle_message(&mut self, message: &str) -> Result<String> {
+        let message: Value = serde_json::from_str(message).context("parsing rg --json output")?;
+        if message["type"] != "match" {
+            return Ok(String::new());
+        }
+        let data = &message["data"];
+        let path = data["path"]["text"]
+            .as_str()
+            .unwrap_or("[rga: path is not valid UTF-8]");
+        // the output of rga-preproc is valid UTF-8, but files without an adapter may not be
+        let text = match data["lines"]["text"].as_str() {
+            Some(text) => text,
+            None => return Ok(String::new()),
+        };
+        let mut out = String::new();
+        for line in text.lines() {
+            for q in &mut self.queries {
+                if q.regex.is_match(line) {
+                    q.hits += 1;
+                    out.push_str(&format!("{}\t{path}\t{line}\n", q.query));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// how many of the queries matched
+    pub fn summary(&self) -> String {
+        let matched = self.queries.iter().filter(|q| q.hits > 0).count();
+        format!(
+            "[rga: {} of {} queries matched]",
+            matched,
+            self.queries.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn message(path: &str, line: &str) -> String {
+        serde_json::json!({
+            "type": "match",
+            "data": {
+                "path": {"text": path},
+                "lines": {"text": line},
+                "line_number": null,
+                "absolute_offset": 0,
+                "submatches": []
+            }
+        })
+        .to_string()
+    }
+
+    #[test]
+    fn queries() -> Result<()> {
+        let mut args = vec![OsString::from("-F"), OsString::from("docs/")];
+        let mut queries = Queries::new(
+            vec!["invoice".to_owned(), "ACME".to_owned(), "a.b".to_owned()],
+            &mut args,
+        )?;
+        assert_eq!(args, vec![OsString::from("docs/")]);
+        assert_eq!(queries.patterns(), "(?i:invoice)\n(?:ACME)\n(?i:a\\.b)\n");
+        assert_eq!(
+            queries.handle_message(&message("a.pdf", "Page 1: Invoice for Acme\n"))?,
+            "invoice\ta.pdf\tPage 1: Invoice for Acme\n"
+        );
+        assert_eq!(
+            queries.handle_message(&message("b.zip", "x.txt: ACME invoice a.b\n"))?,
+            "invoice\tb.zip\tx.txt: ACME invoice a.b\nACME\tb.zip\tx.txt: ACME invoice a.b\na.b\tb.zip\tx.txt: ACME invoice a.b\n"
+        );
+        assert_eq!(
+            queries.handle_message(r#"{"type":"begin","data":{"path":{"text":"a.pdf"}}}"#)?,
+            ""
+        );
+        assert_eq!(queries.summary(), "[rga: 3 of 3 queries matched]");
+
+        let mut args = vec![OsString::from("-w"), OsString::from("-i")];
+        let mut queries = Queries::new(vec!["cat".to_owned(), "d\\w+".to_owned()], &mut args)?;
+        assert_eq!(args, vec![OsString::from("-w")]);
+        assert_eq!(
+            queries.handle_message(&message("c.txt", "concatenate DOGS\n"))?,
+            "d\\w+\tc.txt\tconcatenate DOGS\n"
+        );
+        assert_eq!(queries.summary(), "[rga: 1 of 2 queries matched]");
+        assert!(Queries::new(vec!["(".to_owned()], &mut Vec::new()).is_err());
+        Ok(())
+    }
+}
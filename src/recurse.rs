@@ -2,6 +2,9 @@ use tokio_util::io::{ReaderStream, StreamReader};
 
 use crate::{adapted_iter::AdaptedFilesIterBox, adapters::*, to_io_err};
 use async_stream::stream;
+use std::io::Cursor;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
 
 pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
     let s = stream! {
@@ -15,3 +18,100 @@ pub fn concat_read_streams(input: AdaptedFilesIterBox) -> ReadBox {
     };
     Box::pin(StreamReader::new(s))
 }
+
+/// extensions of members that are slow to adapt: images (for OCR adapters), PDFs, audio and video
+static EXPENSIVE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "bmp", "tif", "tiff", "webp", "pdf", "mkv", "mp4", "mov", "avi",
+    "webm", "mp3", "flac", "ogg", "opus", "wav", "m4a",
+];
+/// at most this many bytes of expensive members are held back in memory, members that don't fit
+/// are adapted in place
+const MAX_DEFERRED: usize = 64 * 1024 * 1024;
+
+fn is_expensive(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .map(|e| EXPENSIVE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false)
+}
+
+/// yield the members of an archive that are cheap to adapt first, holding back the expensive
+/// ones until the end. The order only depends on the archive, so the complete output is the
+/// same on every run
+pub fn cheap_first(input: AdaptedFilesIterBox) -> AdaptedFilesIterBox {
+    defer_expensive(input, MAX_DEFERRED)
+}
+
+fn defer_expensive(input: AdaptedFilesIterBox, max_deferred: usize) -> AdaptedFilesIterBox {
+    let s = stream! {
+        let mut deferred = Vec::new();
+        let mut deferred_len = 0;
+        for await file in input {
+            let mut file = file?;
+            if is_expensive(&file.filepath_hint) && deferred_len < max_deferred {
+                let room = max_deferred - deferred_len;
+                let mut data = Vec::new();
+                (&mut file.inp).take(room as u64 + 1).read_to_end(&mut data).await?;
+                if data.len() > room {
+                    // doesn't fit, adapted in place with the part that was already read
+                    file.inp = Box::pin(Cursor::new(data).chain(file.inp));
+                    yield Ok(file);
+                    continue;
+                }
+                deferred_len += data.len();
+                deferred.push(AdaptInfo {
+                    inp: Box::pin(Cursor::new(data)),
+                    ..file
+                });
+                continue;
+            }
+            yield Ok(file);
+        }
+        for file in deferred {
+            yield Ok(file);
+        }
+    };
+    Box::pin(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn cheap_members_first() -> anyhow::Result<()> {
+        let members = ["scan.png", "notes.txt", "paper.PDF", "readme.md"].map(|name| {
+            let inp = Box::pin(Cursor::new(format!("{name}\n").into_bytes()));
+            Ok(simple_adapt_info(Path::new(name), inp).0)
+        });
+        let out = adapted_to_vec(cheap_first(Box::pin(tokio_stream::iter(members)))).await?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "notes.txt\nreadme.md\nscan.png\npaper.PDF\n"
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn deferred_limit() -> anyhow::Result<()> {
+        let members = [
+            ("scan.png", "small\n"),
+            ("huge.pdf", "longer than the limit\n"),
+            ("notes.txt", "text\n"),
+            ("photo.jpg", "fits\n"),
+        ]
+        .map(|(name, content)| {
+            let inp = Box::pin(Cursor::new(content.as_bytes().to_vec()));
+            Ok(simple_adapt_info(Path::new(name), inp).0)
+        });
+        let out =
+            adapted_to_vec(defer_expensive(Box::pin(tokio_stream::iter(members)), 16)).await?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "longer than the limit\ntext\nsmall\nfits\n"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,664 @@
+//! --rga-report: how often the pattern matches in each document, ranked, or the matches with their
+//! locations instead of the matching lines. rga-preproc puts the line prefixes in a column after the text (as
+//! with --rga-prefix-column), so the location of a match (archive member, page) can be told apart
+//! from the text of the line.
+
+use crate::config::RgaConfig;
+use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+
+static FORMATS: &[&str] = &["table", "html", "markdown", "csv", "sarif"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Table,
+    Html,
+    Markdown,
+    Csv,
+    Sarif,
+}
+
+impl ReportFormat {
+    /// the format of --rga-report, None without it
+    pub fn new(config: &RgaConfig) -> Result<Option<ReportFormat>> {
+        match config.report.as_deref() {
+            None => Ok(None),
+            Some("table") => Ok(Some(ReportFormat::Table)),
+            Some("html") => Ok(Some(ReportFormat::Html)),
+            Some("markdown") => Ok(Some(ReportFormat::Markdown)),
+            Some("csv") => Ok(Some(ReportFormat::Csv)),
+            Some("sarif") => Ok(Some(ReportFormat::Sarif)),
+            Some(name) => bail!(
+                "unknown report format {}, available: {}",
+                name,
+                FORMATS.join(", ")
+            ),
+        }
+    }
+
+    /// whether the report shows the matching lines
+    pub fn snippets(self) -> bool {
+        self != ReportFormat::Table
+    }
+
+    /// whether it shows lines of context around them too
+    pub fn context(self) -> bool {
+        matches!(self, ReportFormat::Html | ReportFormat::Markdown)
+    }
+}
+
+/// a matching line or a line of context around it, for the reports other than the table
+#[derive(Debug, PartialEq)]
+struct Line {
+    text: String,
+    locator: String,
+    line_number: Option<u64>,
+    context: bool,
+    /// the byte ranges of the matches in `text`. Matches in the prefix column are left out
+    matches: Vec<(usize, usize)>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Document {
+    matches: u64,
+    /// where the first match is, e.g. `inner.zip!/a.pdf#page=3`
+    first_locator: Option<String>,
+    lines: Vec<Line>,
+}
+
+#[derive(Default)]
+pub struct Report {
+    documents: BTreeMap<String, Document>,
+    /// keep the lines for the reports other than the table
+    snippets: bool,
+}
+
+/// the text of a line and its location: its prefix column, or its line number for files rga-preproc
+/// outputs without prefixes
+fn split_locator(line: &str, line_number: Option<u64>) -> (&str, String) {
+    let line = line.trim_end_matches(['\n', '\r']);
+    match line.rsplit_once('\t') {
+        Some((text, prefix)) if !prefix.is_empty() => (text, prefix.to_owned()),
+        _ => (
+            line,
+            line_number.map(|n| format!("line {n}")).unwrap_or_default(),
+        ),
+    }
+}
+
+fn html_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// escapes the characters that have a meaning in Markdown text and tables
+fn markdown_escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "\\`*_[]<>|#".contains(c) {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// `path` as the path of a URL, with `/` as separator
+fn url_path(path: &str) -> String {
+    let mut url = String::new();
+    for b in path.replace('\\', "/").bytes() {
+        match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                url.push(b as char)
+            }
+            b => url.push_str(&format!("%{b:02X}")),
+        }
+    }
+    url
+}
+
+/// a `file://` URL of `path`, which is relative to `cwd` unless it is absolute
+fn file_url(cwd: &Path, path: &str) -> String {
+    let path = url_path(&cwd.join(path).to_string_lossy());
+    if path.starts_with('/') {
+        format!("file://{path}")
+    } else {
+        // windows drive letter
+        format!("file:///{path}")
+    }
+}
+
+/// `text` with the matches wrapped in `open` and `close`, each part escaped with `escape`
+fn highlight(line: &Line, escape: fn(&str) -> String, open: &str, close: &str) -> String {
+    let mut out = String::new();
+    let mut pos = 0;
+    for &(start, end) in &line.matches {
+        out.push_str(&escape(&line.text[pos..start]));
+        out.push_str(open);
+        out.push_str(&escape(&line.text[start..end]));
+        out.push_str(close);
+        pos = end;
+    }
+    out.push_str(&escape(&line.text[pos..]));
+    out
+}
+
+/// escapes a field of a CSV file if it contains a separator, a quote or a line break
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// the column of the byte offset `offset` of `text`, counted in characters from 1
+fn column(text: &str, offset: usize) -> usize {
+    text[..offset].chars().count() + 1
+}
+
+/// true if lines were left out between `a` and `b`
+fn is_gap(a: &Line, b: &Line) -> bool {
+    match (a.line_number, b.line_number) {
+        (Some(a), Some(b)) => b > a + 1,
+        _ => false,
+    }
+}
+
+impl Report {
+    /// with `snippets`, the matching lines and the lines of context around them are kept for the
+    /// reports other than the table
+    pub fn new(snippets: bool) -> Report {
+        Report {
+            documents: BTreeMap::new(),
+            snippets,
+        }
+    }
+
+    /// the report in `format`
+    pub fn render(&self, format: ReportFormat, cwd: &Path) -> Result<String> {
+        Ok(match format {
+            ReportFormat::Table => self.table(),
+            ReportFormat::Html => self.html(cwd),
+            ReportFormat::Markdown => self.markdown(cwd),
+            ReportFormat::Csv => self.csv(),
+            ReportFormat::Sarif => serde_json::to_string_pretty(&self.sarif(cwd))?,
+        })
+    }
+
+    /// reads a message of `rg --json`
+    pub fn handle_message(&mut self, message: &str) -> Result<()> {
+        let message: Value = serde_json::from_str(message).context("parsing rg --json output")?;
+        let is_match = message["type"] == "match";
+        if !is_match && !(self.snippets && message["type"] == "context") {
+            return Ok(());
+        }
+        let data = &message["data"];
+        let path = data["path"]["text"]
+            .as_str()
+            .unwrap_or("[rga: path is not valid UTF-8]");
+        let line_number = data["line_number"].as_u64();
+        // files without an adapter may not be valid UTF-8
+        let (text, locator) = split_locator(
+            data["lines"]["text"].as_str().unwrap_or_default(),
+            line_number,
+        );
+        let submatches = data["submatches"].as_array().cloned().unwrap_or_default();
+        let document = self.documents.entry(path.to_owned()).or_default();
+        if is_match {
+            document.matches += submatches.len().max(1) as u64;
+            if document.first_locator.is_none() {
+                document.first_locator = Some(locator.clone());
+            }
+        }
+        if self.snippets {
+            let mut matches = Vec::new();
+            for m in &submatches {
+                let start = m["start"].as_u64().unwrap_or(0) as usize;
+                let end = m["end"].as_u64().unwrap_or(0) as usize;
+                // matches in the prefix column are not highlighted
+                let end = end.min(text.len());
+                if start < end && text.is_char_boundary(start) && text.is_char_boundary(end) {
+                    matches.push((start, end));
+                }
+            }
+            document.lines.push(Line {
+                text: text.to_owned(),
+                locator,
+                line_number,
+                context: !is_match,
+                matches,
+            });
+        }
+        Ok(())
+    }
+
+    /// the documents with the most matches first
+    fn ranked(&self) -> Vec<(&String, &Document)> {
+        let mut documents = self.documents.iter().collect::<Vec<_>>();
+        documents.sort_by(|a, b| b.1.matches.cmp(&a.1.matches).then(a.0.cmp(b.0)));
+        documents
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "{} matches in {} documents",
+            self.documents.values().map(|d| d.matches).sum::<u64>(),
+            self.documents.len()
+        )
+    }
+
+    pub fn table(&self) -> String {
+        let documents = self.ranked();
+        let width = documents
+            .iter()
+            .map(|(path, _)| path.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("document".len());
+        let mut out = format!(
+            "{:<width$} {:>8}  {}\n",
+            "document", "matches", "first match"
+        );
+        for (path, d) in &documents {
+            let _ = writeln!(
+                out,
+                "{:<width$} {:>8}  {}",
+                path,
+                d.matches,
+                d.first_locator.as_deref().unwrap_or_default()
+            );
+        }
+        let _ = writeln!(out, "\n{}", self.summary());
+        out
+    }
+
+    /// a standalone HTML page with the snippets of each document, linking to the files on disk
+    /// (relative paths are relative to `cwd`)
+    pub fn html(&self, cwd: &Path) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>rga report</title>
+<style>
+body { font-family: sans-serif; }
+td { padding: 0.1em 0.5em; vertical-align: top; }
+td.locator { color: #666; white-space: nowrap; }
+td.text { font-family: monospace; white-space: pre-wrap; }
+tr.context td.text { color: #666; }
+tr.gap td { color: #999; }
+mark { background: #ff6; }
+</style>
+</head>
+<body>
+",
+        );
+        let _ = writeln!(out, "<h1>rga report</h1>\n<p>{}</p>", self.summary());
+        for (path, d) in self.ranked() {
+            let _ = writeln!(
+                out,
+                "<h2><a href=\"{}\">{}</a></h2>\n<p>{} matches</p>\n<table>",
+                html_escape(&file_url(cwd, path)),
+                html_escape(path),
+                d.matches
+            );
+            for (i, line) in d.lines.iter().enumerate() {
+                if i > 0 && is_gap(&d.lines[i - 1], line) {
+                    out.push_str("<tr class=\"gap\"><td></td><td>…</td></tr>\n");
+                }
+                let _ = writeln!(
+                    out,
+                    "<tr class=\"{}\"><td class=\"locator\">{}</td><td class=\"text\">{}</td></tr>",
+                    if line.context { "context" } else { "match" },
+                    html_escape(&line.locator),
+                    highlight(line, html_escape, "<mark>", "</mark>")
+                );
+            }
+            out.push_str("</table>\n");
+        }
+        out.push_str("</body>\n</html>\n");
+        out
+    }
+
+    /// the snippets of each document as Markdown tables, linking to the files on disk (relative
+    /// paths are relative to `cwd`)
+    pub fn markdown(&self, cwd: &Path) -> String {
+        let mut out = format!("# rga report\n\n{}\n", self.summary());
+        for (path, d) in self.ranked() {
+            let _ = write!(
+                out,
+                "\n## [{}](<{}>)\n\n{} matches\n\n| location | text |\n| --- | --- |\n",
+                markdown_escape(path),
+                file_url(cwd, path),
+                d.matches
+            );
+            for (i, line) in d.lines.iter().enumerate() {
+                if i > 0 && is_gap(&d.lines[i - 1], line) {
+                    out.push_str("| | … |\n");
+                }
+                let _ = writeln!(
+                    out,
+                    "| {} | {} |",
+                    markdown_escape(&line.locator),
+                    highlight(line, markdown_escape, "**", "**")
+                );
+            }
+        }
+        out
+    }
+
+    /// the matches of the documents in the order of their paths, with the byte range of each match
+    /// in its line. A line that only matches in its prefix column has a single row without a range
+    fn match_rows(&self) -> impl Iterator<Item = (&str, &Line, Option<(usize, usize)>)> {
+        self.documents.iter().flat_map(|(path, d)| {
+            d.lines.iter().filter(|l| !l.context).flat_map(move |line| {
+                let ranges = if line.matches.is_empty() {
+                    vec![None]
+                } else {
+                    line.matches.iter().copied().map(Some).collect()
+                };
+                ranges.into_iter().map(move |m| (path.as_str(), line, m))
+            })
+        })
+    }
+
+    /// one row per match with the document, the location in it, the line and column, the match and
+    /// the text of the line
+    pub fn csv(&self) -> String {
+        let mut out = String::from("document,locator,line,column,match,text\n");
+        for (path, line, m) in self.match_rows() {
+            let (col, matched) = match m {
+                Some((start, end)) => (
+                    column(&line.text, start).to_string(),
+                    &line.text[start..end],
+                ),
+                None => (String::new(), ""),
+            };
+            let _ = writeln!(
+                out,
+                "{},{},{},{},{},{}",
+                csv_field(path),
+                csv_field(&line.locator),
+                line.line_number.map(|n| n.to_string()).unwrap_or_default(),
+                col,
+                csv_field(matched),
+                csv_field(&line.text)
+            );
+        }
+        out
+    }
+
+    /// the matches as a SARIF log, for the dashboards of code scanners. Relative paths are relative
+    /// to `cwd`, the location in the document is its logical location
+    pub fn sarif(&self, cwd: &Path) -> Value {
+        let results = self
+            .match_rows()
+            .map(|(path, line, m)| {
+                let path = path.strip_prefix("./").unwrap_or(path);
+                let artifact = if Path::new(path).is_absolute() {
+                    json!({ "uri": file_url(cwd, path) })
+                } else {
+                    json!({ "uri": url_path(path), "uriBaseId": "SRCROOT" })
+                };
+                let mut region = json!({ "snippet": { "text": line.text } });
+                if let Some(n) = line.line_number {
+                    region["startLine"] = n.into();
+                    if let Some((start, end)) = m {
+                        region["startColumn"] = column(&line.text, start).into();
+                        region["endColumn"] = column(&line.text, end).into();
+                    }
+                }
+                let mut location = json!({
+                    "physicalLocation": { "artifactLocation": artifact, "region": region }
+                });
+                if !line.locator.is_empty() {
+                    location["logicalLocations"] = json!([{ "fullyQualifiedName": line.locator }]);
+                }
+                json!({
+                    "ruleId": "match",
+                    "level": "note",
+                    "message": { "text": line.text },
+                    "locations": [location]
+                })
+            })
+            .collect::<Vec<_>>();
+        json!({
+            "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+            "version": "2.1.0",
+            "runs": [{
+                "tool": {
+                    "driver": {
+                        "name": "rga",
+                        "version": env!("CARGO_PKG_VERSION"),
+                        "informationUri": "https://github.com/phiresky/ripgrep-all",
+                        "rules": [{
+                            "id": "match",
+                            "shortDescription": { "text": "A match of the search pattern" }
+                        }]
+                    }
+                },
+                "originalUriBaseIds": { "SRCROOT": { "uri": file_url(cwd, "") } },
+                "columnKind": "unicodeCodePoints",
+                "results": results
+            }]
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn message(
+        kind: &str,
+        path: &str,
+        line: &str,
+        line_number: u64,
+        submatches: &[(usize, usize)],
+    ) -> String {
+        serde_json::json!({
+            "type": kind,
+            "data": {
+                "path": {"text": path},
+                "lines": {"text": line},
+                "line_number": line_number,
+                "absolute_offset": 0,
+                "submatches": submatches
+                    .iter()
+                    .map(|&(start, end)| serde_json::json!({"match": {"text": &line[start..end]}, "start": start, "end": end}))
+                    .collect::<Vec<_>>()
+            }
+        })
+        .to_string()
+    }
+
+    fn messages(report: &mut Report) -> Result<()> {
+        report.handle_message(r#"{"type":"begin","data":{"path":{"text":"a.pdf"}}}"#)?;
+        report.handle_message(&message("context", "a.pdf", "total\tPage 3\n", 6, &[]))?;
+        report.handle_message(&message(
+            "match",
+            "a.pdf",
+            "invoice\tPage 3\n",
+            7,
+            &[(0, 7)],
+        ))?;
+        report.handle_message(&message(
+            "match",
+            "notes.txt",
+            "invoice, invoice\n",
+            12,
+            &[(0, 7), (9, 16)],
+        ))?;
+        report.handle_message(&message(
+            "match",
+            "mail.zip",
+            "the <invoice>\tb.eml!/invoice.pdf#page=1\n",
+            1,
+            &[(5, 12), (21, 28)],
+        ))?;
+        report.handle_message(&message(
+            "match",
+            "a.pdf",
+            "invoice & invoice\tPage 4\n",
+            9,
+            &[(0, 7), (10, 17)],
+        ))?;
+        Ok(())
+    }
+
+    #[test]
+    fn formats() {
+        let mut config = RgaConfig::default();
+        assert_eq!(ReportFormat::new(&config).unwrap(), None);
+        config.report = Some("markdown".to_owned());
+        assert_eq!(
+            ReportFormat::new(&config).unwrap(),
+            Some(ReportFormat::Markdown)
+        );
+        config.report = Some("sarif".to_owned());
+        assert_eq!(
+            ReportFormat::new(&config).unwrap(),
+            Some(ReportFormat::Sarif)
+        );
+        config.report = Some("pdf".to_owned());
+        assert!(ReportFormat::new(&config).is_err());
+    }
+
+    #[test]
+    fn table() -> Result<()> {
+        let mut report = Report::new(false);
+        messages(&mut report)?;
+        assert!(report.documents["a.pdf"].lines.is_empty());
+        assert_eq!(
+            report.table(),
+            "document   matches  first match
+a.pdf            3  Page 3
+notes.txt        2  line 12
+mail.zip         1  b.eml!/invoice.pdf#page=1
+
+6 matches in 3 documents
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn snippets() -> Result<()> {
+        let mut report = Report::new(true);
+        messages(&mut report)?;
+        let cwd = Path::new("/home/me/docs");
+        let html = report.html(cwd);
+        assert!(html.contains(
+            "<h2><a href=\"file:///home/me/docs/mail.zip\">mail.zip</a></h2>
+<p>1 matches</p>
+<table>
+<tr class=\"match\"><td class=\"locator\">b.eml!/invoice.pdf#page=1</td><td class=\"text\">the &lt;<mark>invoice</mark>&gt;</td></tr>
+</table>"
+        ));
+        assert!(html.contains(
+            "<tr class=\"gap\"><td></td><td>…</td></tr>
+<tr class=\"match\"><td class=\"locator\">Page 4</td><td class=\"text\"><mark>invoice</mark> &amp; <mark>invoice</mark></td></tr>"
+        ));
+        assert_eq!(
+            report.markdown(cwd),
+            "# rga report
+
+6 matches in 3 documents
+
+## [a.pdf](<file:///home/me/docs/a.pdf>)
+
+3 matches
+
+| location | text |
+| --- | --- |
+| Page 3 | total |
+| Page 3 | **invoice** |
+| | … |
+| Page 4 | **invoice** & **invoice** |
+
+## [notes.txt](<file:///home/me/docs/notes.txt>)
+
+2 matches
+
+| location | text |
+| --- | --- |
+| line 12 | **invoice**, **invoice** |
+
+## [mail.zip](<file:///home/me/docs/mail.zip>)
+
+1 matches
+
+| location | text |
+| --- | --- |
+| b.eml!/invoice.pdf\\#page=1 | the \\<**invoice**\\> |
+"
+        );
+        assert_eq!(
+            file_url(cwd, "../my files/ä#1.pdf"),
+            "file:///home/me/docs/../my%20files/%C3%A4%231.pdf"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn exports() -> Result<()> {
+        let mut report = Report::new(true);
+        messages(&mut report)?;
+        report.handle_message(&message("match", "./ü/c.txt", "Invoice \"A\"\n", 2, &[]))?;
+        assert_eq!(
+            report.csv(),
+            "document,locator,line,column,match,text
+./ü/c.txt,line 2,2,,,\"Invoice \"\"A\"\"\"
+a.pdf,Page 3,7,1,invoice,invoice
+a.pdf,Page 4,9,1,invoice,invoice & invoice
+a.pdf,Page 4,9,11,invoice,invoice & invoice
+mail.zip,b.eml!/invoice.pdf#page=1,1,6,invoice,the <invoice>
+notes.txt,line 12,12,1,invoice,\"invoice, invoice\"
+notes.txt,line 12,12,10,invoice,\"invoice, invoice\"
+"
+        );
+        let sarif = report.sarif(Path::new("/home/me/docs"));
+        let run = &sarif["runs"][0];
+        assert_eq!(
+            run["originalUriBaseIds"]["SRCROOT"]["uri"],
+            "file:///home/me/docs/"
+        );
+        let results = run["results"].as_array().unwrap();
+        assert_eq!(results.len(), 7);
+        assert_eq!(
+            results[0]["locations"][0]["physicalLocation"]["artifactLocation"],
+            serde_json::json!({"uri": "%C3%BC/c.txt", "uriBaseId": "SRCROOT"})
+        );
+        assert_eq!(
+            results[4]["locations"][0],
+            serde_json::json!({
+                "physicalLocation": {
+                    "artifactLocation": {"uri": "mail.zip", "uriBaseId": "SRCROOT"},
+                    "region": {
+                        "startLine": 1,
+                        "startColumn": 6,
+                        "endColumn": 13,
+                        "snippet": {"text": "the <invoice>"}
+                    }
+                },
+                "logicalLocations": [{"fullyQualifiedName": "b.eml!/invoice.pdf#page=1"}]
+            })
+        );
+        Ok(())
+    }
+}
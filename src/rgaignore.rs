@@ -0,0 +1,194 @@
+//! .rgaignore files: rules about adapters that rga-preproc reads itself, since rg's own ignore
+//! files can only include or exclude whole files. Each line is a glob followed by what to do with
+//! the files it matches:
+//!
+//! ```text
+//! # don't OCR the scanned pdfs, but still search their text
+//! *.pdf !ocr
+//! # don't look inside the zip archives in backups/
+//! backups/** !zip
+//! old/** skip
+//! ```
+//!
+//! `skip` leaves the file out of the search, `!name` leaves out the adapter `name`, also for the
+//! members of archives. Like in .gitignore, a glob without a slash matches the file name in any
+//! directory, one with a slash matches the path relative to the directory of the .rgaignore file.
+//! The .rgaignore files of all directories above a file apply. Files rg searches without rga-preproc,
+//! like text files, are not affected.
+
+use crate::adapters::adapters_without;
+use crate::config::RgaConfig;
+use anyhow::{format_err, Context, Result};
+use glob::{MatchOptions, Pattern};
+use path_clean::PathClean;
+use std::path::Path;
+
+pub const FILE_NAME: &str = ".rgaignore";
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Skip,
+    NoAdapter(String),
+}
+
+#[derive(Debug)]
+struct Rule {
+    pattern: Pattern,
+    /// matched against the path relative to the .rgaignore file instead of the file name
+    anchored: bool,
+    actions: Vec<Action>,
+}
+
+fn parse(content: &str) -> Result<Vec<Rule>> {
+    let mut rules = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.split_whitespace();
+        let glob = words.next().expect("line is not empty");
+        let actions = words
+            .map(|word| match word.strip_prefix('!') {
+                Some(adapter) if !adapter.is_empty() => Ok(Action::NoAdapter(adapter.to_owned())),
+                _ if word == "skip" => Ok(Action::Skip),
+                _ => Err(format_err!(
+                    "line {}: unknown action \"{}\", expected skip or !adapter",
+                    i + 1,
+                    word
+                )),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if actions.is_empty() {
+            return Err(format_err!("line {}: {} needs an action", i + 1, glob));
+        }
+        let anchored = glob.contains('/');
+        let pattern = Pattern::new(glob.trim_start_matches('/'))
+            .with_context(|| format!("line {}: invalid glob {}", i + 1, glob))?;
+        rules.push(Rule {
+            pattern,
+            anchored,
+            actions,
+        });
+    }
+    Ok(rules)
+}
+
+/// what the .rgaignore files say about a file
+#[derive(Debug, Default, PartialEq)]
+pub struct Decision {
+    pub skip: bool,
+    pub disabled_adapters: Vec<String>,
+}
+
+impl Decision {
+    fn apply(&mut self, rules: &[Rule], relative: &Path) {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let file_name = relative.file_name().unwrap_or_default();
+        for rule in rules {
+            let target = if rule.anchored {
+                relative.as_os_str()
+            } else {
+                file_name
+            };
+            if !rule
+                .pattern
+                .matches_with(&target.to_string_lossy(), options)
+            {
+                continue;
+            }
+            for action in &rule.actions {
+                match action {
+                    Action::Skip => self.skip = true,
+                    Action::NoAdapter(name) => {
+                        if !self.disabled_adapters.contains(name) {
+                            self.disabled_adapters.push(name.clone())
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// read the .rgaignore files in the directories above `path`
+pub fn decide(path: &Path) -> Result<Decision> {
+    let path = path.clean();
+    let mut decision = Decision::default();
+    for dir in path.ancestors().skip(1) {
+        let file = dir.join(FILE_NAME);
+        let content = match std::fs::read_to_string(&file) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("reading {}", file.display())),
+        };
+        let rules = parse(&content).with_context(|| format!("in {}", file.display()))?;
+        let relative = path.strip_prefix(dir).expect("dir is an ancestor");
+        decision.apply(&rules, relative);
+    }
+    Ok(decision)
+}
+
+/// the adapters to use for the file, or None to skip it
+pub fn adapters_for(config: &RgaConfig, path: &Path) -> Result<Option<Vec<String>>> {
+    let decision = decide(path)?;
+    if decision.skip {
+        return Ok(None);
+    }
+    if decision.disabled_adapters.is_empty() {
+        return Ok(Some(config.adapters.clone()));
+    }
+    Ok(Some(adapters_without(config, &decision.disabled_adapters)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn rules() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::create_dir_all(dir.path().join("docs/backups/2020"))?;
+        std::fs::write(
+            dir.path().join(FILE_NAME),
+            "# comment\n*.pdf !ocr\n\nbackups/** skip\n",
+        )?;
+        std::fs::write(
+            dir.path().join("docs").join(FILE_NAME),
+            "backups/** !zip !ocr\n/*.zip skip\n",
+        )?;
+        let decide = |path: &str| decide(&dir.path().join(path));
+        assert_eq!(
+            decide("scan.pdf")?,
+            Decision {
+                skip: false,
+                disabled_adapters: vec!["ocr".to_owned()]
+            }
+        );
+        assert_eq!(
+            decide("docs/backups/2020/a.pdf")?,
+            Decision {
+                skip: false,
+                disabled_adapters: vec!["zip".to_owned(), "ocr".to_owned()]
+            }
+        );
+        // anchored to the directory of the .rgaignore file
+        assert!(decide("docs/a.zip")?.skip);
+        assert!(!decide("docs/backups/a.zip")?.skip);
+        assert!(decide("backups/a.txt")?.skip);
+        assert_eq!(decide("notes.txt")?, Decision::default());
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(parse("*.pdf").is_err());
+        assert!(parse("*.pdf ocr").is_err());
+        assert!(parse("*.pdf !").is_err());
+        assert!(parse("[.pdf skip").is_err());
+    }
+}
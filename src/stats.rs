@@ -0,0 +1,351 @@
+//! statistics of a run for --rga-stats-json and --rga-stats-prometheus. Every file is preprocessed by its own rga-preproc
+//! process, which appends a line to a file in the directory given by [STATS_DIR]. rga sums those up
+//! once rg is done.
+
+use crate::job;
+use anyhow::{Context, Result};
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// environment variable with the directory rga-preproc writes the statistics of each file to
+pub const STATS_DIR: &str = "RGA_STATS_DIR";
+
+lazy_static! {
+    static ref ENABLED: bool =
+        std::env::var_os(STATS_DIR).is_some() || std::env::var_os(job::JOB_FILE).is_some();
+    static ref ADAPTERS: Mutex<Vec<AdapterUse>> = Mutex::new(Vec::new());
+    static ref NOTED_ERROR: Mutex<Option<String>> = Mutex::new(None);
+}
+
+struct AdapterUse {
+    name: String,
+    archive_recursion_depth: i32,
+    cache_hit: bool,
+}
+
+/// note that an adapter was chosen for a file or an archive member
+pub fn adapter_used(name: &str, archive_recursion_depth: i32, cache_hit: bool) {
+    // the adapter of the file itself is also needed to record failures in the cache
+    if archive_recursion_depth > 0 && !*ENABLED {
+        return;
+    }
+    ADAPTERS.lock().unwrap().push(AdapterUse {
+        name: name.to_owned(),
+        archive_recursion_depth,
+        cache_hit,
+    });
+}
+
+/// note that the file failed even though its output could be read to the end, e.g. because it
+/// was cut short by --rga-per-file-budget
+pub fn note_error(error: String) {
+    *NOTED_ERROR.lock().unwrap() = Some(error);
+}
+
+/// what happened to one file given to rga-preproc
+#[derive(Serialize, Deserialize, Debug, PartialEq, Default)]
+pub struct FileStats {
+    pub path: String,
+    /// the adapter of the file itself, None if it was passed through unchanged
+    pub adapter: Option<String>,
+    pub cache_hit: bool,
+    /// the adapters of the archive members, including nested ones
+    pub member_adapters: Vec<String>,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+/// what happened to the file this process preprocessed
+pub fn file_stats(path: &Path, duration: Duration, error: Option<&anyhow::Error>) -> FileStats {
+    let mut stats = FileStats {
+        path: path.to_string_lossy().into_owned(),
+        duration_ms: duration.as_millis() as u64,
+        error: error
+            .map(|e| format!("{e:#}"))
+            .or_else(|| NOTED_ERROR.lock().unwrap().take()),
+        ..Default::default()
+    };
+    for used in ADAPTERS.lock().unwrap().drain(..) {
+        if used.archive_recursion_depth == 0 && stats.adapter.is_none() {
+            stats.adapter = Some(used.name);
+            stats.cache_hit = used.cache_hit;
+        } else {
+            stats.member_adapters.push(used.name);
+        }
+    }
+    stats
+}
+
+/// write the statistics of a file, if rga asked for them, and record it in the job file of --rga-job
+pub fn finish(stats: &FileStats) -> Result<()> {
+    let dir = std::env::var_os(STATS_DIR);
+    let job = std::env::var_os(job::JOB_FILE);
+    if let Some(job) = job {
+        job::record(Path::new(&job), stats)?;
+    }
+    if let Some(dir) = dir {
+        // one file per process, so lines of parallel processes don't get mixed up
+        let file = Path::new(&dir).join(format!("{}.jsonl", std::process::id()));
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&file)
+            .with_context(|| format!("opening {}", file.display()))?;
+        writeln!(file, "{}", serde_json::to_string(stats)?)?;
+    }
+    Ok(())
+}
+
+/// upper bounds of the buckets of the duration histogram for --rga-stats-prometheus, in seconds
+const BUCKETS: [f64; 8] = [0.01, 0.05, 0.25, 1.0, 5.0, 30.0, 120.0, 600.0];
+
+#[derive(Serialize, Debug, PartialEq, Default)]
+pub struct AdapterStats {
+    /// files this adapter was chosen for
+    pub files: u64,
+    /// archive members this adapter was chosen for
+    pub members: u64,
+    pub cache_hits: u64,
+    pub cache_hit_ratio: f64,
+    /// time spent on the files of this adapter, including their members
+    pub duration_ms: u64,
+    pub errors: u64,
+    /// number of files that took at most as long as the bucket's bound, for each of [BUCKETS]
+    #[serde(skip)]
+    pub duration_buckets: [u64; BUCKETS.len()],
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct FileError {
+    pub path: String,
+    pub adapter: Option<String>,
+    pub error: String,
+}
+
+/// the content of the --rga-stats-json file
+#[derive(Serialize, Debug, PartialEq, Default)]
+pub struct RunStats {
+    pub files: u64,
+    pub duration_ms: u64,
+    pub adapters: BTreeMap<String, AdapterStats>,
+    pub errors: Vec<FileError>,
+}
+
+/// sum up the statistics the rga-preproc processes wrote to `dir`
+pub fn summarize(dir: &Path, duration: Duration) -> Result<RunStats> {
+    let mut run = RunStats {
+        duration_ms: duration.as_millis() as u64,
+        ..Default::default()
+    };
+    for entry in std::fs::read_dir(dir).with_context(|| format!("reading {}", dir.display()))? {
+        let content = std::fs::read_to_string(entry?.path())?;
+        for line in content.lines() {
+            let file: FileStats = serde_json::from_str(line).context("reading file stats")?;
+            run.files += 1;
+            if let Some(adapter) = &file.adapter {
+                let stats = run.adapters.entry(adapter.clone()).or_default();
+                stats.files += 1;
+                stats.duration_ms += file.duration_ms;
+                for (count, bound) in stats.duration_buckets.iter_mut().zip(BUCKETS) {
+                    if file.duration_ms as f64 / 1000.0 <= bound {
+                        *count += 1;
+                    }
+                }
+                if file.cache_hit {
+                    stats.cache_hits += 1;
+                }
+                if file.error.is_some() {
+                    stats.errors += 1;
+                }
+            }
+            for member in file.member_adapters {
+                run.adapters.entry(member).or_default().members += 1;
+            }
+            if let Some(error) = file.error {
+                run.errors.push(FileError {
+                    path: file.path,
+                    adapter: file.adapter,
+                    error,
+                });
+            }
+        }
+    }
+    for stats in run.adapters.values_mut() {
+        if stats.files > 0 {
+            stats.cache_hit_ratio = stats.cache_hits as f64 / stats.files as f64;
+        }
+    }
+    // read_dir has no defined order
+    run.errors.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(run)
+}
+
+impl RunStats {
+    /// the statistics in the text format of Prometheus, e.g. for the textfile collector of node_exporter
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let mut metric =
+            |name: &str, kind: &str, help: &str, value: &dyn Fn(&AdapterStats) -> u64| {
+                out += &format!("# HELP {name} {help}\n# TYPE {name} {kind}\n");
+                for (adapter, stats) in &self.adapters {
+                    out += &format!(
+                        "{name}{{adapter=\"{}\"}} {}\n",
+                        label(adapter),
+                        value(stats)
+                    );
+                }
+            };
+        metric(
+            "rga_files_total",
+            "counter",
+            "Files preprocessed, by the adapter chosen for them",
+            &|s| s.files,
+        );
+        metric(
+            "rga_members_total",
+            "counter",
+            "Archive members preprocessed, by the adapter chosen for them",
+            &|s| s.members,
+        );
+        metric(
+            "rga_cache_hits_total",
+            "counter",
+            "Files whose output was read from the cache",
+            &|s| s.cache_hits,
+        );
+        metric("rga_errors_total", "counter", "Files that failed", &|s| {
+            s.errors
+        });
+        let name = "rga_file_duration_seconds";
+        out += &format!("# HELP {name} Time spent preprocessing a file, including its members\n# TYPE {name} histogram\n");
+        for (adapter, stats) in &self.adapters {
+            let adapter = label(adapter);
+            for (count, bound) in stats.duration_buckets.iter().zip(BUCKETS) {
+                out += &format!("{name}_bucket{{adapter=\"{adapter}\",le=\"{bound}\"}} {count}\n");
+            }
+            out += &format!(
+                "{name}_bucket{{adapter=\"{adapter}\",le=\"+Inf\"}} {}\n",
+                stats.files
+            );
+            out += &format!(
+                "{name}_sum{{adapter=\"{adapter}\"}} {}\n",
+                stats.duration_ms as f64 / 1000.0
+            );
+            out += &format!("{name}_count{{adapter=\"{adapter}\"}} {}\n", stats.files);
+        }
+        let name = "rga_run_duration_seconds";
+        out += &format!("# HELP {name} Duration of the whole run\n# TYPE {name} gauge\n");
+        out += &format!("{name} {}\n", self.duration_ms as f64 / 1000.0);
+        out
+    }
+}
+
+/// escape a label value, custom adapters can have any name
+fn label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn summarize_files() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let files = [
+            FileStats {
+                path: "a.zip".to_owned(),
+                adapter: Some("zip".to_owned()),
+                cache_hit: false,
+                member_adapters: vec!["pdf".to_owned(), "postprocprefix".to_owned()],
+                duration_ms: 30,
+                error: None,
+            },
+            FileStats {
+                path: "b.zip".to_owned(),
+                adapter: Some("zip".to_owned()),
+                cache_hit: true,
+                member_adapters: vec![],
+                duration_ms: 10,
+                error: None,
+            },
+            FileStats {
+                path: "c.pdf".to_owned(),
+                adapter: Some("pdf".to_owned()),
+                cache_hit: false,
+                member_adapters: vec![],
+                duration_ms: 5,
+                error: Some("pdftotext failed".to_owned()),
+            },
+        ];
+        for (i, file) in files.iter().enumerate() {
+            std::fs::write(
+                dir.path().join(format!("{i}.jsonl")),
+                serde_json::to_string(file)? + "\n",
+            )?;
+        }
+        let run = summarize(dir.path(), Duration::from_millis(50))?;
+        assert_eq!(run.files, 3);
+        assert_eq!(
+            run.adapters["zip"],
+            AdapterStats {
+                files: 2,
+                members: 0,
+                cache_hits: 1,
+                cache_hit_ratio: 0.5,
+                duration_ms: 40,
+                errors: 0,
+                duration_buckets: [1, 2, 2, 2, 2, 2, 2, 2],
+            }
+        );
+        assert_eq!(run.adapters["pdf"].files, 1);
+        assert_eq!(run.adapters["pdf"].members, 1);
+        assert_eq!(run.adapters["pdf"].errors, 1);
+        assert_eq!(
+            run.errors,
+            vec![FileError {
+                path: "c.pdf".to_owned(),
+                adapter: Some("pdf".to_owned()),
+                error: "pdftotext failed".to_owned(),
+            }]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn prometheus() {
+        let mut run = RunStats {
+            files: 1,
+            duration_ms: 2500,
+            ..Default::default()
+        };
+        run.adapters.insert(
+            "pdf".to_owned(),
+            AdapterStats {
+                files: 1,
+                duration_ms: 2000,
+                duration_buckets: [0, 0, 0, 0, 1, 1, 1, 1],
+                ..Default::default()
+            },
+        );
+        let out = run.to_prometheus();
+        assert!(
+            out.contains("# TYPE rga_files_total counter\nrga_files_total{adapter=\"pdf\"} 1\n")
+        );
+        assert!(out.contains("rga_file_duration_seconds_bucket{adapter=\"pdf\",le=\"1\"} 0\n"));
+        assert!(out.contains("rga_file_duration_seconds_bucket{adapter=\"pdf\",le=\"5\"} 1\n"));
+        assert!(out.contains("rga_file_duration_seconds_bucket{adapter=\"pdf\",le=\"+Inf\"} 1\n"));
+        assert!(out.contains("rga_file_duration_seconds_sum{adapter=\"pdf\"} 2\n"));
+        assert!(out.ends_with("rga_run_duration_seconds 2.5\n"));
+        assert_eq!(label("a\"b"), "a\\\"b");
+    }
+}
@@ -0,0 +1,99 @@
+//! --rga-strategy: how much of the files is extracted. `deep` runs the adapters on all files,
+//! `shallow` only reads their metadata (like --rga-meta-only), and `auto` does a shallow pass first,
+//! then runs the adapters only on the files that matched and on their neighbors.
+
+use crate::config::RgaConfig;
+use anyhow::{bail, Context, Result};
+use path_clean::PathClean;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// the environment variable with the path of the list of files that matched in the first pass of
+/// `auto`, one per line. rga-preproc only adapts the files next to them
+pub const DEEP_FILES: &str = "RGA_DEEP_FILES";
+
+static STRATEGIES: &[&str] = &["deep", "shallow", "auto"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    Deep,
+    Shallow,
+    Auto,
+}
+
+impl Strategy {
+    pub fn new(config: &RgaConfig) -> Result<Strategy> {
+        match config.strategy.as_deref() {
+            None | Some("deep") => Ok(Strategy::Deep),
+            Some("shallow") => Ok(Strategy::Shallow),
+            Some("auto") => Ok(Strategy::Auto),
+            Some(name) => bail!(
+                "unknown strategy {}, available: {}",
+                name,
+                STRATEGIES.join(", ")
+            ),
+        }
+    }
+}
+
+/// the files the second pass of `auto` adapts: the ones in the directories of the files that matched
+/// in the first pass, since documents next to a match are likely to be relevant too
+#[derive(Debug, Default)]
+pub struct DeepFiles {
+    dirs: HashSet<PathBuf>,
+}
+
+impl DeepFiles {
+    pub fn new(matched: impl IntoIterator<Item = PathBuf>) -> DeepFiles {
+        let dirs = matched
+            .into_iter()
+            .filter_map(|file| Some(file.clean().parent()?.to_owned()))
+            .collect();
+        DeepFiles { dirs }
+    }
+
+    /// the files of the second pass, if this is one
+    pub fn from_env() -> Result<Option<DeepFiles>> {
+        let list = match std::env::var_os(DEEP_FILES) {
+            Some(list) => PathBuf::from(list),
+            None => return Ok(None),
+        };
+        let matched = std::fs::read_to_string(&list)
+            .with_context(|| format!("reading {}", list.display()))?;
+        Ok(Some(DeepFiles::new(matched.lines().map(PathBuf::from))))
+    }
+
+    pub fn includes(&self, path: &Path) -> bool {
+        path.clean()
+            .parent()
+            .map_or(false, |dir| self.dirs.contains(dir))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strategies() {
+        let mut config = RgaConfig::default();
+        assert_eq!(Strategy::new(&config).unwrap(), Strategy::Deep);
+        config.strategy = Some("auto".to_owned());
+        assert_eq!(Strategy::new(&config).unwrap(), Strategy::Auto);
+        config.strategy = Some("fast".to_owned());
+        assert!(Strategy::new(&config).is_err());
+    }
+
+    #[test]
+    fn neighbors() {
+        let deep = DeepFiles::new([
+            PathBuf::from("/docs/2023/./notes.txt"),
+            PathBuf::from("/docs/report.pdf"),
+        ]);
+        assert!(deep.includes(Path::new("/docs/2023/scan.pdf")));
+        assert!(deep.includes(Path::new("/docs/report.pdf")));
+        assert!(deep.includes(Path::new("/docs/2023/../slides.pptx")));
+        assert!(!deep.includes(Path::new("/docs/2022/report.pdf")));
+        assert!(!deep.includes(Path::new("/docs/2023/old/report.pdf")));
+    }
+}
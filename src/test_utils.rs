@@ -1,9 +1,6 @@
 use crate::{
     adapted_iter::AdaptedFilesIterBox,
-    adapters::{
-        custom::{CustomSpawningFileAdapter, BUILTIN_SPAWNING_ADAPTERS},
-        AdaptInfo, ReadBox,
-    },
+    adapters::{AdaptInfo, ReadBox},
     config::RgaConfig,
     matching::{FastFileMatcher, FileMatcher},
     recurse::concat_read_streams,
@@ -59,12 +56,3 @@ pub async fn adapted_to_vec(adapted: AdaptedFilesIterBox) -> Result<Vec<u8>> {
     res.read_to_end(&mut buf).await?;
     Ok(buf)
 }
-
-pub fn poppler_adapter() -> CustomSpawningFileAdapter {
-    let adapter = BUILTIN_SPAWNING_ADAPTERS
-        .iter()
-        .find(|e| e.name == "poppler")
-        .expect("no poppler adapter");
-
-    adapter.to_adapter()
-}
@@ -0,0 +1,197 @@
+//! Golden file tests for adapters. `assert_adapter_output` checks the output of an adapter for one
+//! input, `self_test` (`rga --rga-self-test`) runs the adapters over the fixtures bundled with rga,
+//! so packagers can check that the external programs the adapters need work on their system.
+use crate::adapters::{get_all_adapters, AdaptInfo, FileAdapter, ReadBox};
+use crate::config::RgaConfig;
+use crate::matching::{FastFileMatcher, FileMatcher};
+use crate::preproc::loop_adapt;
+use crate::print_dur;
+use crate::recurse::concat_read_streams;
+use anyhow::{bail, Context, Result};
+use std::io::Cursor;
+use std::path::Path;
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::AsyncReadExt;
+
+pub struct Fixture {
+    /// name of the adapter that is run on the input
+    pub adapter: &'static str,
+    /// file name of the input, also used to choose the matcher
+    pub name: &'static str,
+    pub input: &'static [u8],
+    /// the expected output (golden file), with an empty line prefix
+    pub expected: &'static str,
+}
+
+macro_rules! fixture {
+    ($adapter:literal, $name:literal) => {
+        Fixture {
+            adapter: $adapter,
+            name: $name,
+            input: include_bytes!(concat!("../exampledir/test/", $name)),
+            expected: include_str!(concat!("../exampledir/test/golden/", $name, ".txt")),
+        }
+    };
+}
+
+/// inputs and golden files in exampledir/test. Update the golden file when changing the output of an adapter
+pub static FIXTURES: &[Fixture] = &[
+    fixture!("pdf", "short.pdf"),
+    fixture!("decompress", "hello.gz"),
+    // needs the pdf adapter for the decompressed file
+    #[cfg(any(feature = "pdf-poppler", feature = "pdf-native"))]
+    fixture!("decompress", "short.pdf.gz"),
+    fixture!("tar", "hello.tar"),
+    fixture!("sqlite", "hello.sqlite3"),
+];
+
+/// run the adapter (and the adapters of nested files) on the input, like rga-preproc without cache
+pub async fn adapter_output(
+    adapter: &dyn FileAdapter,
+    filepath_hint: &Path,
+    inp: ReadBox,
+    is_real_file: bool,
+    config: RgaConfig,
+) -> Result<String> {
+    let ext = filepath_hint
+        .extension()
+        .map(|e| e.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ai = AdaptInfo {
+        filepath_hint: filepath_hint.to_owned(),
+        is_real_file,
+        archive_recursion_depth: 0,
+        inp,
+        line_prefix: String::new(),
+        config,
+        postprocess: true,
+    };
+    let detection_reason = FileMatcher::Fast(FastFileMatcher::FileExtension(ext));
+    let mut oup = concat_read_streams(loop_adapt(adapter, detection_reason, ai).await?);
+    let mut buf = Vec::new();
+    oup.read_to_end(&mut buf).await?;
+    String::from_utf8(buf).context("adapter output is not utf8")
+}
+
+/// fails with the first line that differs from the golden file
+fn compare(actual: &str, expected: &str) -> Result<()> {
+    if actual == expected {
+        return Ok(());
+    }
+    let mut actual_lines = actual.split_inclusive('\n');
+    let mut expected_lines = expected.split_inclusive('\n');
+    for line in 1.. {
+        match (actual_lines.next(), expected_lines.next()) {
+            (Some(a), Some(e)) if a == e => continue,
+            (a, e) => bail!(
+                "output differs in line {}:\n  expected: {:?}\n  actual:   {:?}",
+                line,
+                e.unwrap_or("<end of output>"),
+                a.unwrap_or("<end of output>")
+            ),
+        }
+    }
+    unreachable!()
+}
+
+/// check that the adapter turns the file `input_fixture` into `expected_text`. Nested files are
+/// adapted with the default adapters, lines are not prefixed with the file name
+pub async fn assert_adapter_output(
+    adapter: &dyn FileAdapter,
+    input_fixture: &Path,
+    expected_text: &str,
+) -> Result<()> {
+    let inp = File::open(input_fixture)
+        .await
+        .with_context(|| format!("opening {}", input_fixture.display()))?;
+    let actual = adapter_output(
+        adapter,
+        input_fixture,
+        Box::pin(inp),
+        true,
+        RgaConfig::default(),
+    )
+    .await?;
+    compare(&actual, expected_text).with_context(|| {
+        format!(
+            "{} via {}",
+            input_fixture.display(),
+            adapter.metadata().name
+        )
+    })
+}
+
+/// run the adapters over the bundled fixtures and print the results. Returns false if any failed
+pub async fn self_test(config: &RgaConfig) -> Result<bool> {
+    let (enabled, disabled) = get_all_adapters(config.custom_adapters.clone());
+    let mut ok = true;
+    for fixture in FIXTURES {
+        let adapter = match enabled
+            .iter()
+            .chain(disabled.iter())
+            .find(|a| a.metadata().name == fixture.adapter)
+        {
+            Some(adapter) => adapter,
+            None => {
+                println!(
+                    "SKIP {} ({}): no such adapter",
+                    fixture.name, fixture.adapter
+                );
+                continue;
+            }
+        };
+        let start = Instant::now();
+        let res = adapter_output(
+            adapter.as_ref(),
+            Path::new(fixture.name),
+            Box::pin(Cursor::new(fixture.input)),
+            false,
+            config.clone(),
+        )
+        .await
+        .and_then(|actual| compare(&actual, fixture.expected));
+        match res {
+            Ok(()) => println!(
+                "ok   {} ({}, {})",
+                fixture.name,
+                fixture.adapter,
+                print_dur(start)
+            ),
+            Err(e) => {
+                ok = false;
+                println!("FAIL {} ({}): {:#}", fixture.name, fixture.adapter, e);
+            }
+        }
+    }
+    Ok(ok)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adapters::sqlite::SqliteAdapter;
+    use crate::test_utils::test_data_dir;
+
+    #[tokio::test]
+    async fn golden_sqlite() -> Result<()> {
+        let fixture = test_data_dir().join("hello.sqlite3");
+        let expected = include_str!("../exampledir/test/golden/hello.sqlite3.txt");
+        assert_adapter_output(&SqliteAdapter::default(), &fixture, expected).await?;
+        assert!(
+            assert_adapter_output(&SqliteAdapter::default(), &fixture, "tbl: \n")
+                .await
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn first_difference() {
+        assert!(compare("a\nb\n", "a\nb\n").is_ok());
+        let e = compare("a\nc\n", "a\nb\n").unwrap_err().to_string();
+        assert!(e.contains("line 2"), "{e}");
+        let e = compare("a\n", "a\nb\n").unwrap_err().to_string();
+        assert!(e.contains("<end of output>"), "{e}");
+    }
+}
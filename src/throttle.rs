@@ -0,0 +1,134 @@
+//! --rga-throttle-*: limits for running rga in the background without slowing down the rest of the
+//! machine. The number of jobs is limited across the rga-preproc processes with slot files in a
+//! directory rga creates for each run, given by [SLOTS_DIR].
+
+use crate::adapters::ReadBox;
+use crate::config::ThrottleConfig;
+use anyhow::{Context, Result};
+use async_stream::stream;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::StreamExt;
+use tokio_util::io::{ReaderStream, StreamReader};
+
+/// environment variable with the directory of the slot files for --rga-throttle-jobs
+pub const SLOTS_DIR: &str = "RGA_SLOTS_DIR";
+
+/// the program and arguments to run rg with to lower its priority, which rga-preproc and the
+/// programs of the adapters inherit. Empty if no priority is set
+pub fn priority_wrapper(config: &ThrottleConfig) -> Vec<String> {
+    let mut wrapper = vec![];
+    if config.idle_io {
+        wrapper.extend(["ionice", "-c", "3"].map(String::from));
+    }
+    if let Some(nice) = config.nice {
+        wrapper.extend(["nice".to_owned(), "-n".to_owned(), nice.to_string()]);
+    }
+    wrapper
+}
+
+/// read at most `bytes_per_sec` on average (--rga-throttle-read)
+pub fn limit_rate(inp: ReadBox, bytes_per_sec: f64) -> ReadBox {
+    let s = stream! {
+        let start = Instant::now();
+        let mut total = 0;
+        let mut stream = ReaderStream::new(inp);
+        while let Some(bytes) = stream.next().await {
+            if let Ok(bytes) = &bytes {
+                total += bytes.len();
+            }
+            let due = start + Duration::from_secs_f64(total as f64 / bytes_per_sec);
+            tokio::time::sleep_until(due).await;
+            yield bytes;
+        }
+    };
+    Box::pin(StreamReader::new(s))
+}
+
+/// one of the --rga-throttle-jobs slots, free again when dropped
+pub struct Slot(PathBuf);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+/// wait until fewer than --rga-throttle-jobs files are adapted. None if the number is not limited
+pub async fn acquire_slot(config: &ThrottleConfig) -> Result<Option<Slot>> {
+    match (config.jobs, std::env::var_os(SLOTS_DIR)) {
+        (Some(jobs), Some(dir)) => Ok(Some(wait_for_slot(Path::new(&dir), jobs).await?)),
+        _ => Ok(None),
+    }
+}
+
+async fn wait_for_slot(dir: &Path, jobs: u64) -> Result<Slot> {
+    loop {
+        for i in 0..jobs.max(1) {
+            let path = dir.join(format!("slot-{i}"));
+            // creating a file that must not exist yet is atomic, so only one process gets each slot
+            match std::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Slot(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e).with_context(|| format!("creating {}", path.display())),
+            }
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// free the slot only once the output has been read
+pub fn keep_slot(inp: ReadBox, slot: Option<Slot>) -> ReadBox {
+    match slot {
+        Some(slot) => {
+            let s = stream! {
+                let _slot = slot;
+                let mut stream = ReaderStream::new(inp);
+                while let Some(bytes) = stream.next().await {
+                    yield bytes;
+                }
+            };
+            Box::pin(StreamReader::new(s))
+        }
+        None => inp,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::io::Cursor;
+    use tokio::io::AsyncReadExt;
+
+    #[tokio::test]
+    async fn rate() -> Result<()> {
+        let start = Instant::now();
+        let mut inp = limit_rate(Box::pin(Cursor::new(vec![0u8; 2000])), 20000.0);
+        let mut out = Vec::new();
+        inp.read_to_end(&mut out).await?;
+        assert_eq!(out.len(), 2000);
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn slots() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let first = wait_for_slot(dir.path(), 1).await?;
+        let start = Instant::now();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            drop(first);
+        });
+        let _second = wait_for_slot(dir.path(), 1).await?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
+}
@@ -0,0 +1,159 @@
+//! Split ("multi-volume") archives on disk: generic splits like `name.zip.001`, `name.zip.002`, ...
+//! (also used for 7z and tar) and spanned zips (`name.z01`, `name.z02`, ..., `name.zip`).
+//!
+//! The first part stands for the whole archive: the parts are joined into one stream that
+//! is adapted under the name of the joined archive. Later parts are not searched by themselves.
+//! Multi-volume RAR archives (`name.part1.rar`, `name.part2.rar`, ...) are not joined, unrar reads
+//! the later parts itself when it is given the first one.
+use crate::adapters::ReadBox;
+use anyhow::{Context, Result};
+use std::io::SeekFrom;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+pub enum Volume {
+    /// the first part: the file name of the joined archive and all parts in order
+    First {
+        joined: PathBuf,
+        parts: Vec<PathBuf>,
+    },
+    /// a later part, searched as part of the first one
+    Later { first: PathBuf },
+}
+
+/// `stem.{prefix}NN` with `digits` digits
+fn numbered(ext: &str, prefix: &str, digits: usize) -> Option<u32> {
+    let number = ext.strip_prefix(prefix)?;
+    if number.len() != digits || !number.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    number.parse().ok()
+}
+
+pub fn detect_volume(path: &Path) -> Option<Volume> {
+    let name = path.file_name()?.to_str()?;
+    let (stem, ext) = name.rsplit_once('.')?;
+    let sibling = |ext: String| path.with_file_name(format!("{stem}.{ext}"));
+    if ext.eq_ignore_ascii_case("rar") {
+        let (base, number) = stem.rsplit_once(".part")?;
+        if number.is_empty() || !number.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        if number.parse::<u32>().ok()? == 1 {
+            return None;
+        }
+        let first = path.with_file_name(format!(
+            "{base}.part{:0width$}.{ext}",
+            1,
+            width = number.len()
+        ));
+        return first.exists().then_some(Volume::Later { first });
+    }
+    let volume = |n: u32, part: &dyn Fn(u32) -> PathBuf, last: Option<PathBuf>, joined| {
+        if n != 1 {
+            let first = part(1);
+            return first.exists().then_some(Volume::Later { first });
+        }
+        let mut parts: Vec<PathBuf> = (1..).map(part).take_while(|p| p.exists()).collect();
+        parts.extend(last.filter(|l| l.exists()));
+        Some(Volume::First { joined, parts })
+    };
+    if let Some(n) = numbered(ext, "", 3) {
+        return volume(
+            n,
+            &|i| sibling(format!("{i:03}")),
+            None,
+            path.with_file_name(stem),
+        );
+    }
+    // keep the case of the extension for the other parts
+    for (z, zip) in [("z", "zip"), ("Z", "ZIP")] {
+        if let Some(n) = numbered(ext, z, 2) {
+            // the central directory is in the last part, name.zip
+            let last = sibling(zip.to_owned());
+            return volume(
+                n,
+                &|i| sibling(format!("{z}{i:02}")),
+                Some(last.clone()),
+                last,
+            );
+        }
+        if ext == zip {
+            let first = sibling(format!("{z}01"));
+            return first.exists().then_some(Volume::Later { first });
+        }
+    }
+    None
+}
+
+/// the parts as one stream
+pub async fn open_volumes(parts: &[PathBuf]) -> Result<ReadBox> {
+    let mut joined: ReadBox = Box::pin(tokio::io::empty());
+    for (i, part) in parts.iter().enumerate() {
+        let mut file = File::open(part)
+            .await
+            .with_context(|| format!("opening archive part {}", part.display()))?;
+        if i == 0 {
+            // split zips start with a marker that is not part of the first entry
+            let mut magic = [0u8; 4];
+            let n = file.read(&mut magic).await?;
+            if n < 4 || !matches!(&magic, b"PK\x07\x08" | b"PK00") {
+                file.seek(SeekFrom::Start(0)).await?;
+            }
+        }
+        joined = Box::pin(joined.chain(file));
+    }
+    Ok(joined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[tokio::test]
+    async fn split_and_spanned() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = |name: &str| dir.path().join(name);
+        std::fs::write(path("notes.txt.001"), "hello ")?;
+        std::fs::write(path("notes.txt.002"), "world\n")?;
+        std::fs::write(path("data.z01"), b"PK\x07\x08first ")?;
+        std::fs::write(path("data.zip"), "last\n")?;
+
+        let (joined, parts) = match detect_volume(&path("notes.txt.001")) {
+            Some(Volume::First { joined, parts }) => (joined, parts),
+            _ => panic!("first volume not detected"),
+        };
+        assert_eq!(joined, path("notes.txt"));
+        let mut out = String::new();
+        open_volumes(&parts).await?.read_to_string(&mut out).await?;
+        assert_eq!(out, "hello world\n");
+        assert!(matches!(
+            detect_volume(&path("notes.txt.002")),
+            Some(Volume::Later { first }) if first == path("notes.txt.001")
+        ));
+
+        let parts = match detect_volume(&path("data.z01")) {
+            Some(Volume::First { joined, parts }) if joined == path("data.zip") => parts,
+            _ => panic!("spanned zip not detected"),
+        };
+        let mut out = String::new();
+        open_volumes(&parts).await?.read_to_string(&mut out).await?;
+        assert_eq!(out, "first last\n");
+        assert!(matches!(
+            detect_volume(&path("data.zip")),
+            Some(Volume::Later { .. })
+        ));
+        assert!(detect_volume(&path("other.zip")).is_none());
+
+        std::fs::write(path("photos.part01.rar"), "")?;
+        assert!(matches!(
+            detect_volume(&path("photos.part02.rar")),
+            Some(Volume::Later { first }) if first == path("photos.part01.rar")
+        ));
+        assert!(detect_volume(&path("photos.part01.rar")).is_none());
+        assert!(detect_volume(&path("photos.rar")).is_none());
+        Ok(())
+    }
+}
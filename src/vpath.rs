@@ -0,0 +1,105 @@
+//! Virtual paths address content nested in other files, mount-point style:
+//! `outer.zip!/inner.tar!/doc.pdf#page=3`. Every `!/` goes into a member of the archive before it,
+//! `#page=N` points at a page of a document.
+//!
+//! The line prefixes rga outputs are the virtual path of the line within the searched file, so
+//! `outer.zip:inner.tar!/doc.pdf#page=3: text` in the output of rg is at `outer.zip!/inner.tar!/doc.pdf#page=3`.
+use std::fmt;
+use std::path::PathBuf;
+
+pub const MEMBER_SEPARATOR: &str = "!/";
+pub const PAGE_FRAGMENT: &str = "#page=";
+
+/// line prefixes that are virtual paths end with this, other prefixes (like in tests) are kept as they are
+const PREFIX_END: &str = ": ";
+
+/// the line prefix for the lines of `member`, a member of the archive with the line prefix `line_prefix`
+pub fn member_prefix(line_prefix: &str, member: &str) -> String {
+    match line_prefix.strip_suffix(PREFIX_END) {
+        Some(parent) => format!("{parent}{MEMBER_SEPARATOR}{member}{PREFIX_END}"),
+        None => format!("{line_prefix}{member}{PREFIX_END}"),
+    }
+}
+
+/// the line prefix for the lines on page `page` of the document with the line prefix `line_prefix`
+pub fn page_prefix(line_prefix: &str, page: i32) -> String {
+    let parent = line_prefix.strip_suffix(PREFIX_END).unwrap_or(line_prefix);
+    format!("{parent}{PAGE_FRAGMENT}{page}{PREFIX_END}")
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct VirtualPath {
+    /// the file on disk
+    pub file: PathBuf,
+    /// the members to go into, outermost first
+    pub members: Vec<String>,
+    pub page: Option<i32>,
+}
+
+impl VirtualPath {
+    /// parse a virtual path. A plain path is a virtual path without members
+    pub fn parse(s: &str) -> VirtualPath {
+        let (rest, page) = match s.rsplit_once(PAGE_FRAGMENT) {
+            Some((rest, page)) => match page.parse() {
+                Ok(page) => (rest, Some(page)),
+                Err(_) => (s, None),
+            },
+            None => (s, None),
+        };
+        let mut parts = rest.split(MEMBER_SEPARATOR);
+        VirtualPath {
+            file: PathBuf::from(parts.next().unwrap_or_default()),
+            members: parts.map(str::to_string).collect(),
+            page,
+        }
+    }
+}
+
+impl fmt::Display for VirtualPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.file.display())?;
+        for member in &self.members {
+            write!(f, "{MEMBER_SEPARATOR}{member}")?;
+        }
+        if let Some(page) = self.page {
+            write!(f, "{PAGE_FRAGMENT}{page}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn prefixes() {
+        let inner = member_prefix("", "inner.tar");
+        assert_eq!(inner, "inner.tar: ");
+        let doc = member_prefix(&inner, "dir/doc.pdf");
+        assert_eq!(doc, "inner.tar!/dir/doc.pdf: ");
+        assert_eq!(page_prefix(&doc, 3), "inner.tar!/dir/doc.pdf#page=3: ");
+        assert_eq!(page_prefix("", 1), "#page=1: ");
+        assert_eq!(member_prefix("PREFIX:", "a.txt"), "PREFIX:a.txt: ");
+    }
+
+    #[test]
+    fn parse() {
+        let path = "outer.zip!/inner.tar!/doc.pdf#page=3";
+        let v = VirtualPath::parse(path);
+        assert_eq!(
+            v,
+            VirtualPath {
+                file: PathBuf::from("outer.zip"),
+                members: vec!["inner.tar".to_string(), "doc.pdf".to_string()],
+                page: Some(3)
+            }
+        );
+        assert_eq!(v.to_string(), path);
+        let v = VirtualPath::parse("notes#page=two.txt");
+        assert_eq!(v.file, PathBuf::from("notes#page=two.txt"));
+        assert_eq!(v.page, None);
+        assert!(v.members.is_empty());
+    }
+}